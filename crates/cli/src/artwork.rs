@@ -0,0 +1,370 @@
+//! `artwork localize`: copies of remote covers in the local artwork store.
+//!
+//! The store is the `artwork/` directory next to the database, where `bundle import` puts
+//! the covers of a bundle too. Each cover is saved as `artwork/{track_id}.{ext}`.
+//!
+//! `artwork report --fetch` looks for covers of tracks without one at the providers
+//! configured in the `artwork` section, in their order:
+//!
+//! ```toml
+//! [artwork]
+//! providers = ["folder", "itunes"]
+//! ```
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use localdeck_storage::{
+    bundle::ARTWORK_DIR,
+    operations::{MetadataUpdate, Storage},
+    store::LibraryStore,
+    track::{ArtworkRef, Track, TrackId},
+};
+use serde::Deserialize;
+use ureq::Agent;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// covers are small, a larger body is not an image worth keeping
+const MAX_SIZE: u64 = 20 * 1024 * 1024;
+const ITUNES_SEARCH_URL: &str = "https://itunes.apple.com/search";
+/// Names of cover images in album folders, the first one found wins
+const COVER_NAMES: &[&str] = &["cover", "folder", "front", "albumart", "album"];
+const COVER_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// Where covers of tracks without one are looked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtworkProvider {
+    /// an image like `cover.jpg` in the folder of the track's file
+    Folder,
+    /// the iTunes Search API, by artist and title. The cover stays a link until `artwork localize`
+    Itunes,
+}
+
+impl fmt::Display for ArtworkProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArtworkProvider::Folder => "folder",
+            ArtworkProvider::Itunes => "itunes",
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ArtworkConfig {
+    /// tried in this order, only covers in album folders unless configured
+    #[serde(default = "default_providers")]
+    pub providers: Vec<ArtworkProvider>,
+}
+
+fn default_providers() -> Vec<ArtworkProvider> {
+    vec![ArtworkProvider::Folder]
+}
+
+impl Default for ArtworkConfig {
+    fn default() -> Self {
+        Self {
+            providers: default_providers(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LocalizeReport {
+    pub localized: usize,
+    /// tracks whose cover could not be downloaded, with the reason
+    pub failed: Vec<(TrackId, String)>,
+}
+
+/// Whether the artwork reference points at an HTTP location
+pub fn is_remote(artwork: &ArtworkRef) -> bool {
+    let url = &artwork.0;
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Downloads every remote cover into the artwork store and points the tracks at the copies.
+/// A failed download leaves the reference as it was
+pub fn localize(storage: &mut Storage) -> anyhow::Result<LocalizeReport> {
+    let dir = storage
+        .database_path()
+        .and_then(|db| Some(db.parent()?.join(ARTWORK_DIR)))
+        .context("artwork can only be stored next to a database file")?;
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    let mut report = LocalizeReport::default();
+    for track in storage.scan_metadata()? {
+        let Some(artwork) = track.metadata.artwork.filter(is_remote) else {
+            continue;
+        };
+        match download(&agent, &artwork.0, &dir, track.id) {
+            Ok(path) => {
+                storage.update_track_metadata(
+                    track.id,
+                    MetadataUpdate {
+                        artist: None,
+                        title: None,
+                        year: None,
+                        label: None,
+                        artwork: Some(ArtworkRef(path.to_string_lossy().into_owned())),
+                    },
+                    true,
+                )?;
+                report.localized += 1;
+            }
+            Err(e) => report.failed.push((track.id, format!("{e:#}"))),
+        }
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Default)]
+pub struct FetchReport {
+    /// tracks given a cover, with the provider it came from
+    pub found: Vec<(TrackId, ArtworkProvider)>,
+    /// tracks none of the providers has a cover for
+    pub not_found: Vec<TrackId>,
+    /// tracks whose providers failed, with the reasons
+    pub failed: Vec<(TrackId, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItunesResults {
+    results: Vec<ItunesTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItunesTrack {
+    artist_name: String,
+    artwork_url100: Option<String>,
+}
+
+/// Looks for covers of the tracks at the providers and sets the first one found
+pub fn fetch_missing(
+    storage: &mut dyn LibraryStore,
+    tracks: &[Track],
+    providers: &[ArtworkProvider],
+) -> anyhow::Result<FetchReport> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let mut report = FetchReport::default();
+    for track in tracks {
+        let mut errors = vec![];
+        let mut found = None;
+        for provider in providers {
+            let cover = match provider {
+                ArtworkProvider::Folder => storage
+                    .find_track_file_with_meta(track.id)
+                    .map_err(anyhow::Error::from)
+                    .map(|(path, _, _)| {
+                        path.parent()
+                            .and_then(folder_cover)
+                            .map(|cover| cover.to_string_lossy().into_owned())
+                    }),
+                ArtworkProvider::Itunes => {
+                    itunes_cover(&agent, &track.metadata.artist, &track.metadata.title)
+                }
+            };
+            match cover {
+                Ok(Some(cover)) => {
+                    found = Some((*provider, cover));
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(format!("{provider}: {e:#}")),
+            }
+        }
+        match found {
+            Some((provider, cover)) => {
+                storage.update_track_metadata(
+                    track.id,
+                    MetadataUpdate {
+                        artist: None,
+                        title: None,
+                        year: None,
+                        label: None,
+                        artwork: Some(ArtworkRef(cover)),
+                    },
+                    false,
+                )?;
+                report.found.push((track.id, provider));
+            }
+            None if errors.is_empty() => report.not_found.push(track.id),
+            None => report.failed.push((track.id, errors.join("; "))),
+        }
+    }
+    Ok(report)
+}
+
+/// Cover image in an album folder, e.g. `cover.jpg` or `Folder.PNG`
+fn folder_cover(dir: &Path) -> Option<PathBuf> {
+    let mut images: Vec<(usize, PathBuf)> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let stem = path.file_stem()?.to_str()?.to_lowercase();
+            let ext = path.extension()?.to_str()?.to_lowercase();
+            let rank = COVER_NAMES.iter().position(|name| *name == stem)?;
+            COVER_EXTENSIONS
+                .contains(&ext.as_str())
+                .then_some((rank, path))
+        })
+        .collect();
+    images.sort();
+    images.into_iter().next().map(|(_, path)| path)
+}
+
+fn itunes_cover(agent: &Agent, artist: &str, title: &str) -> anyhow::Result<Option<String>> {
+    let results: ItunesResults = agent
+        .get(ITUNES_SEARCH_URL)
+        .query("term", format!("{artist} {title}"))
+        .query("media", "music")
+        .query("entity", "song")
+        .query("limit", "10")
+        .call()?
+        .body_mut()
+        .read_json()?;
+    Ok(pick_itunes_cover(results.results, artist))
+}
+
+/// Cover of the first result by the artist, in a larger size than the listed thumbnail
+fn pick_itunes_cover(results: Vec<ItunesTrack>, artist: &str) -> Option<String> {
+    results
+        .into_iter()
+        .filter(|track| track.artist_name.eq_ignore_ascii_case(artist))
+        .find_map(|track| track.artwork_url100)
+        .map(|url| url.replace("/100x100bb.", "/600x600bb."))
+}
+
+/// Saves the image at `url` as `{track_id}.{ext}` in `dir`, returns its path
+fn download(agent: &Agent, url: &str, dir: &Path, track_id: TrackId) -> anyhow::Result<PathBuf> {
+    let mut response = agent.get(url).call()?;
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    if let Some(mime) = &content_type
+        && !mime.starts_with("image/")
+    {
+        bail!("{url} is not an image but {mime}");
+    }
+    let image = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_SIZE)
+        .read_to_vec()?;
+
+    let path = dir.join(file_name(track_id, content_type.as_deref(), url));
+    fs::create_dir_all(dir)?;
+    fs::write(&path, image).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Name of the stored cover, the extension follows the content type, or the URL without one
+fn file_name(track_id: TrackId, content_type: Option<&str>, url: &str) -> String {
+    let from_type = content_type.and_then(|mime| {
+        let subtype = mime.strip_prefix("image/")?.split(';').next()?.trim();
+        Some(match subtype {
+            "jpeg" | "pjpeg" => "jpg",
+            "svg+xml" => "svg",
+            "x-icon" | "vnd.microsoft.icon" => "ico",
+            other => other,
+        })
+    });
+    let from_url = || {
+        let path = url.split(['?', '#']).next()?;
+        let (_, last) = path.rsplit_once('/')?;
+        let (_, ext) = last.rsplit_once('.')?;
+        (!ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric())).then_some(ext)
+    };
+    match from_type.or_else(from_url) {
+        Some(ext) => format!("{track_id}.{}", ext.to_ascii_lowercase()),
+        None => track_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        ArtworkConfig, ArtworkProvider, ItunesTrack, file_name, folder_cover, pick_itunes_cover,
+    };
+
+    #[test]
+    fn providers_are_parsed() {
+        let config: ArtworkConfig = toml::from_str(r#"providers = ["folder", "itunes"]"#).unwrap();
+        assert_eq!(
+            config.providers,
+            vec![ArtworkProvider::Folder, ArtworkProvider::Itunes]
+        );
+        let default: ArtworkConfig = toml::from_str("").unwrap();
+        assert_eq!(default, ArtworkConfig::default());
+    }
+
+    #[test]
+    fn names_follow_the_content_type() {
+        let id = 12;
+        assert_eq!(
+            file_name(id, Some("image/jpeg"), "https://a.b/cover.png"),
+            "12.jpg"
+        );
+        assert_eq!(
+            file_name(id, Some("image/webp; charset=binary"), "https://a.b/c"),
+            "12.webp"
+        );
+        assert_eq!(
+            file_name(id, None, "https://a.b/art/Cover.PNG?size=600"),
+            "12.png"
+        );
+        assert_eq!(file_name(id, None, "https://a.b/art/cover"), "12");
+    }
+
+    #[test]
+    fn covers_are_found_in_album_folders() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(folder_cover(dir.path()), None);
+        for name in ["01 Intro.mp3", "booklet.jpg", "Folder.PNG", "cover.txt"] {
+            fs::write(dir.path().join(name), name)?;
+        }
+        assert_eq!(
+            folder_cover(dir.path()),
+            Some(dir.path().join("Folder.PNG"))
+        );
+        fs::write(dir.path().join("cover.jpg"), "cover")?;
+        assert_eq!(folder_cover(dir.path()), Some(dir.path().join("cover.jpg")));
+        Ok(())
+    }
+
+    #[test]
+    fn itunes_covers_are_of_the_artist() {
+        let results = || {
+            vec![
+                ItunesTrack {
+                    artist_name: "Glee Cast".to_string(),
+                    artwork_url100: Some("https://is1.mzstatic.com/a/100x100bb.jpg".to_string()),
+                },
+                ItunesTrack {
+                    artist_name: "Journey".to_string(),
+                    artwork_url100: Some("https://is1.mzstatic.com/b/100x100bb.jpg".to_string()),
+                },
+            ]
+        };
+        assert_eq!(
+            pick_itunes_cover(results(), "journey").as_deref(),
+            Some("https://is1.mzstatic.com/b/600x600bb.jpg")
+        );
+        assert_eq!(pick_itunes_cover(results(), "Toto"), None);
+    }
+}
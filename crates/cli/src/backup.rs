@@ -0,0 +1,472 @@
+//! Off-site backups of the library made by `serve` on a schedule.
+//!
+//! A backup is a bundle, as written by `localdeck bundle export`, encrypted with age to the
+//! configured recipients, so the destination never holds the library in the clear and the
+//! server only needs public keys. To restore one:
+//!
+//! ```text
+//! age -d -i key.txt localdeck-backup-20260101T030000Z.tar.gz.age > library.tar.gz
+//! localdeck bundle import library.tar.gz
+//! ```
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use std::{fs, path::PathBuf, str::FromStr, sync::Mutex, thread, time::Duration};
+use ureq::{Agent, http};
+
+use localdeck_http::server::SharedStore;
+use localdeck_storage::{
+    location::{Location, S3Credentials},
+    s3::S3Root,
+    store::LibraryStore,
+};
+
+use crate::bundle::{WorkDir, write_archive};
+
+const BACKUP_PREFIX: &str = "localdeck-backup-";
+const BACKUP_SUFFIX: &str = ".tar.gz.age";
+/// UTC time in backup names, sorts like the time itself
+const NAME_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Wait after a failed backup, unless backups are more frequent
+const RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackupConfig {
+    /// hours between backups
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+    /// how many backups are kept at the destination, older ones are deleted
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// age public keys (`age1...`) backups are encrypted to, any of their identities decrypts them
+    pub recipients: Vec<String>,
+    pub destination: BackupDestination,
+}
+
+fn default_interval_hours() -> u64 {
+    24
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+/// Where backups are stored
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum BackupDestination {
+    /// Local directory, e.g. on another drive or in a folder synced elsewhere
+    Directory { path: PathBuf },
+    /// Key prefix `path` in a bucket of an S3-compatible object storage
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default)]
+        region: Option<String>,
+        #[serde(default)]
+        credentials: Option<S3Credentials>,
+        #[serde(default)]
+        path: PathBuf,
+    },
+    /// Collection on a WebDAV server, e.g. `https://cloud.example.com/remote.php/dav/files/me/backups`
+    WebDav {
+        url: String,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+impl BackupDestination {
+    /// Names of all files at the destination
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        match self {
+            BackupDestination::Directory { path } => {
+                if !path.exists() {
+                    return Ok(vec![]);
+                }
+                let mut names = vec![];
+                for entry in fs::read_dir(path)? {
+                    names.push(entry?.file_name().to_string_lossy().to_string());
+                }
+                Ok(names)
+            }
+            BackupDestination::S3 { .. } => Ok(self
+                .bucket()?
+                .list()?
+                .into_iter()
+                .map(|(key, _)| key.to_string_lossy().to_string())
+                .collect()),
+            BackupDestination::WebDav { url, .. } => {
+                let request = http::Request::builder()
+                    .method(http::Method::from_bytes(b"PROPFIND")?)
+                    .uri(format!("{}/", url.trim_end_matches('/')))
+                    .header("Depth", "1");
+                let request = match self.authorization() {
+                    Some(auth) => request.header("Authorization", auth),
+                    None => request,
+                };
+                let listing = agent()
+                    .run(request.body(())?)
+                    .with_context(|| format!("failed to list {url}"))?
+                    .body_mut()
+                    .read_to_string()?;
+                Ok(webdav_names(&listing))
+            }
+        }
+    }
+
+    fn put(&self, name: &str, body: &[u8]) -> anyhow::Result<()> {
+        match self {
+            BackupDestination::Directory { path } => {
+                fs::create_dir_all(path)?;
+                // a half written file must not look like a backup
+                let partial = path.join(format!("{name}.part"));
+                fs::write(&partial, body)?;
+                fs::rename(&partial, path.join(name))?;
+            }
+            BackupDestination::S3 { .. } => self.bucket()?.put(name, body)?,
+            BackupDestination::WebDav { url, .. } => {
+                let url = format!("{}/{name}", url.trim_end_matches('/'));
+                let request = agent().put(&url);
+                let request = match self.authorization() {
+                    Some(auth) => request.header("Authorization", auth),
+                    None => request,
+                };
+                request
+                    .send(body)
+                    .with_context(|| format!("failed to upload {url}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<()> {
+        match self {
+            BackupDestination::Directory { path } => fs::remove_file(path.join(name))?,
+            BackupDestination::S3 { .. } => self.bucket()?.delete(name)?,
+            BackupDestination::WebDav { url, .. } => {
+                let url = format!("{}/{name}", url.trim_end_matches('/'));
+                let request = agent().delete(&url);
+                let request = match self.authorization() {
+                    Some(auth) => request.header("Authorization", auth),
+                    None => request,
+                };
+                request
+                    .call()
+                    .with_context(|| format!("failed to delete {url}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn bucket(&self) -> anyhow::Result<S3Root> {
+        let BackupDestination::S3 {
+            endpoint,
+            bucket,
+            region,
+            credentials,
+            path,
+        } = self
+        else {
+            bail!("{self} is not a bucket");
+        };
+        S3Root::new(&Location::S3 {
+            endpoint: endpoint.clone(),
+            bucket: bucket.clone(),
+            region: region.clone(),
+            credentials: credentials.clone(),
+            path: path.clone(),
+        })
+        .ok_or(anyhow!("{self} is not a bucket"))
+    }
+
+    /// Basic auth of a WebDAV destination
+    fn authorization(&self) -> Option<String> {
+        let BackupDestination::WebDav {
+            user: Some(user),
+            password,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        let credentials = format!("{user}:{}", password.as_deref().unwrap_or_default());
+        Some(format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+    }
+}
+
+impl std::fmt::Display for BackupDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupDestination::Directory { path } => write!(f, "{}", path.to_string_lossy()),
+            BackupDestination::S3 { bucket, path, .. } => {
+                write!(f, "S3({bucket})/{}", path.to_string_lossy())
+            }
+            BackupDestination::WebDav { url, .. } => write!(f, "{url}"),
+        }
+    }
+}
+
+fn agent() -> Agent {
+    Agent::config_builder()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .build()
+        .into()
+}
+
+/// File names of the members of a WebDAV `PROPFIND` answer
+fn webdav_names(multistatus: &str) -> Vec<String> {
+    // `<D:href>`, `<d:href>` and `<href>` are all used, the text before the next tag is the link
+    multistatus
+        .split("href>")
+        .skip(1)
+        .filter_map(|rest| rest.split('<').next())
+        .map(|href| href.trim().trim_end_matches('/'))
+        .filter(|href| !href.is_empty())
+        .filter_map(|href| href.rsplit('/').next())
+        .map(str::to_string)
+        .collect()
+}
+
+fn backup_name(time: DateTime<Utc>) -> String {
+    format!(
+        "{BACKUP_PREFIX}{}{BACKUP_SUFFIX}",
+        time.format(NAME_TIME_FORMAT)
+    )
+}
+
+/// Time of the backup with this name, `None` for other files
+fn backup_time(name: &str) -> Option<DateTime<Utc>> {
+    let time = name
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(BACKUP_SUFFIX)?;
+    Some(
+        NaiveDateTime::parse_from_str(time, NAME_TIME_FORMAT)
+            .ok()?
+            .and_utc(),
+    )
+}
+
+/// Backups among `names` beyond the newest `keep` ones. The newest backup is always kept
+fn expired(names: &[String], keep: usize) -> Vec<String> {
+    let mut backups: Vec<(DateTime<Utc>, &String)> = names
+        .iter()
+        .filter_map(|name| Some((backup_time(name)?, name)))
+        .collect();
+    backups.sort_by(|a, b| b.cmp(a));
+    backups
+        .into_iter()
+        .skip(keep.max(1))
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+fn parse_recipients(recipients: &[String]) -> anyhow::Result<Vec<age::x25519::Recipient>> {
+    if recipients.is_empty() {
+        bail!("backups need at least one recipient to encrypt to");
+    }
+    recipients
+        .iter()
+        .map(|recipient| {
+            age::x25519::Recipient::from_str(recipient)
+                .map_err(|e| anyhow!("invalid backup recipient '{recipient}': {e}"))
+        })
+        .collect()
+}
+
+/// Encrypts a bundle of the library to the recipients, stores it at the destination and
+/// deletes expired backups. Returns the name of the new backup
+fn run_backup(
+    storage: &Mutex<dyn LibraryStore>,
+    config: &BackupConfig,
+    recipients: &[age::x25519::Recipient],
+    now: DateTime<Utc>,
+) -> anyhow::Result<String> {
+    let work = WorkDir::new("backup")?;
+    let db = work.0.join("library.db");
+    // the server only waits for the database copy, not for packing and uploading
+    let manifest = storage
+        .lock()
+        .map_err(|e| anyhow!("could not lock the library: {e}"))?
+        .export_bundle(&db)?;
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+    let archive = write_archive(&manifest, &db, encryptor.wrap_output(vec![])?)?.finish()?;
+
+    let name = backup_name(now);
+    config.destination.put(&name, &archive)?;
+    for old in expired(&config.destination.list()?, config.keep) {
+        config.destination.delete(&old)?;
+    }
+    Ok(name)
+}
+
+/// Starts a thread backing up the library every `interval_hours`.
+///
+/// The first backup is made once the newest backup at the destination is due for renewal,
+/// so restarting the server does not skip or repeat backups
+pub fn spawn_scheduler(storage: SharedStore, config: BackupConfig) -> anyhow::Result<()> {
+    let recipients = parse_recipients(&config.recipients)?;
+    let interval = TimeDelta::hours(config.interval_hours.max(1) as i64);
+    let retry = TimeDelta::from_std(RETRY_DELAY)?.min(interval);
+
+    let latest = match config.destination.list() {
+        Ok(names) => names.iter().filter_map(|name| backup_time(name)).max(),
+        Err(e) => {
+            log::warn!("could not list backups at {}: {e:#}", config.destination);
+            None
+        }
+    };
+    let mut next = latest.map_or(Utc::now(), |latest| latest + interval);
+    log::info!(
+        "backing up to {} every {}h, next backup at {next}",
+        config.destination,
+        interval.num_hours()
+    );
+
+    thread::Builder::new()
+        .name("backup".to_string())
+        .spawn(move || {
+            loop {
+                if let Ok(wait) = (next - Utc::now()).to_std() {
+                    thread::sleep(wait);
+                }
+                next = match run_backup(&storage, &config, &recipients, Utc::now()) {
+                    Ok(name) => {
+                        log::info!("stored backup {name} at {}", config.destination);
+                        Utc::now() + interval
+                    }
+                    Err(e) => {
+                        log::error!("backup to {} failed: {e:#}", config.destination);
+                        Utc::now() + retry
+                    }
+                };
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        iter,
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use flate2::read::GzDecoder;
+    use localdeck_http::server::SharedStore;
+    use localdeck_storage::{
+        config::{Config, Database, LibrarySource},
+        operations::Storage,
+    };
+
+    use super::{BackupConfig, BackupDestination, backup_name, expired, run_backup, webdav_names};
+
+    #[test]
+    fn backups_are_encrypted_and_expire() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage: SharedStore = Arc::new(Mutex::new(Storage::new(Config {
+            database: Database::InMemory,
+            library_source: LibrarySource::default(),
+        })?));
+        let identity = age::x25519::Identity::generate();
+        let config = BackupConfig {
+            interval_hours: 24,
+            keep: 2,
+            recipients: vec![identity.to_public().to_string()],
+            destination: BackupDestination::Directory {
+                path: dir.path().join("backups"),
+            },
+        };
+        let recipients = super::parse_recipients(&config.recipients)?;
+
+        let start = Utc.with_ymd_and_hms(2026, 1, 1, 3, 0, 0).unwrap();
+        let mut names = vec![];
+        for day in 0..3 {
+            let now = start + TimeDelta::days(day);
+            names.push(run_backup(&storage, &config, &recipients, now)?);
+        }
+        let mut stored = config.destination.list()?;
+        stored.sort();
+        assert_eq!(stored, names[1..]);
+
+        let encrypted = std::fs::File::open(dir.path().join("backups").join(&names[2]))?;
+        let decrypted =
+            age::Decryptor::new(encrypted)?.decrypt(iter::once(&identity as &dyn age::Identity))?;
+        let mut archive = tar::Archive::new(GzDecoder::new(decrypted));
+        let mut entries = vec![];
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            entries.push(entry.path()?.to_string_lossy().to_string());
+            entry.read_to_end(&mut vec![])?;
+        }
+        assert_eq!(entries, vec!["manifest.json", "library.db"]);
+
+        assert!(super::parse_recipients(&["age1nope".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_backups_beyond_keep_expire() {
+        let time = |day| Utc.with_ymd_and_hms(2026, 1, day, 3, 0, 0).unwrap();
+        let names = vec![
+            backup_name(time(2)),
+            "notes.txt".to_string(),
+            backup_name(time(3)),
+            backup_name(time(1)),
+        ];
+        assert_eq!(expired(&names, 2), vec![backup_name(time(1))]);
+        // the backup just made survives a zero keep
+        assert_eq!(
+            expired(&names, 0),
+            vec![backup_name(time(2)), backup_name(time(1))]
+        );
+    }
+
+    #[test]
+    fn webdav_listing_names() {
+        let multistatus = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response><d:href>/dav/backups/</d:href><d:propstat/></d:response>
+  <d:response><d:href>/dav/backups/localdeck-backup-20260101T030000Z.tar.gz.age</d:href></d:response>
+  <D:response><D:href>https://cloud/dav/backups/notes.txt</D:href></D:response>
+</d:multistatus>"#;
+        assert_eq!(
+            webdav_names(multistatus),
+            vec![
+                "backups",
+                "localdeck-backup-20260101T030000Z.tar.gz.age",
+                "notes.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn config_is_parsed() {
+        let config: BackupConfig = toml::from_str(
+            r#"
+recipients = ["age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p"]
+destination = {type = "WebDav", url = "https://cloud.example.com/dav/backups", user = "sasha", password = "secret"}
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.interval_hours, 24);
+        assert_eq!(config.keep, 7);
+        assert_eq!(
+            config.destination,
+            BackupDestination::WebDav {
+                url: "https://cloud.example.com/dav/backups".to_string(),
+                user: Some("sasha".to_string()),
+                password: Some("secret".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! `localdeck bundle`: the whole library in one `.tar.gz` archive, for moving to another machine.
+//!
+//! The archive holds `manifest.json`, the database as `library.db` and local artwork under
+//! `artwork/`. Music files are not included, they are expected to be copied separately
+//! and are found again below the library roots of the new machine.
+
+use anyhow::{Context, bail};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use localdeck_storage::{
+    bundle::{ARTWORK_DIR, BUNDLE_FORMAT, BundleImportReport, BundleManifest},
+    config::LibraryRoot,
+    location::Location,
+    operations::Storage,
+};
+
+const MANIFEST: &str = "manifest.json";
+const DATABASE: &str = "library.db";
+
+/// Scratch directory removed when dropped
+pub(crate) struct WorkDir(pub(crate) PathBuf);
+
+impl WorkDir {
+    /// `purpose` keeps directories of different tasks of one process apart
+    pub(crate) fn new(purpose: &str) -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("localdeck-{purpose}-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.to_string_lossy()))?;
+        Ok(WorkDir(dir))
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes the library to the archive `out`
+pub fn export(storage: &mut Storage, out: &Path) -> anyhow::Result<BundleManifest> {
+    let work = WorkDir::new("bundle")?;
+    let db = work.0.join(DATABASE);
+    let manifest = storage.export_bundle(&db)?;
+
+    let file =
+        File::create(out).with_context(|| format!("failed to create {}", out.to_string_lossy()))?;
+    write_archive(&manifest, &db, file)?;
+    Ok(manifest)
+}
+
+/// Packs the database copy `db` made for `manifest` and the artwork it lists into `out`
+pub(crate) fn write_archive<W: Write>(
+    manifest: &BundleManifest,
+    db: &Path,
+    out: W,
+) -> anyhow::Result<W> {
+    let mut archive = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_at as u64);
+    archive.append_data(&mut header, MANIFEST, json.as_slice())?;
+    archive.append_path_with_name(db, DATABASE)?;
+    for artwork in &manifest.artwork {
+        archive
+            .append_path_with_name(&artwork.source, &artwork.path)
+            .with_context(|| format!("failed to add {}", artwork.source.to_string_lossy()))?;
+    }
+    Ok(archive.into_inner()?.finish()?)
+}
+
+/// Fills the empty library of `storage` from the archive.
+///
+/// Artwork is extracted to `artwork/` next to the database
+pub fn import(
+    storage: &mut Storage,
+    library_roots: &[LibraryRoot],
+    archive: &Path,
+    root_maps: &[(String, PathBuf)],
+) -> anyhow::Result<BundleImportReport> {
+    let artwork_dir = storage
+        .database_path()
+        .and_then(|db| Some(db.parent()?.join(ARTWORK_DIR)))
+        .context("bundles can only be imported into a database file")?;
+
+    let work = WorkDir::new("bundle")?;
+    let file = File::open(archive)
+        .with_context(|| format!("failed to open {}", archive.to_string_lossy()))?;
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(&work.0)
+        .with_context(|| format!("{} is not a bundle archive", archive.to_string_lossy()))?;
+    let manifest: BundleManifest = serde_json::from_slice(
+        &fs::read(work.0.join(MANIFEST)).context("bundle has no manifest")?,
+    )?;
+    if manifest.format > BUNDLE_FORMAT {
+        bail!(
+            "bundle format {} is newer than this localdeck supports ({BUNDLE_FORMAT}), update localdeck first",
+            manifest.format
+        );
+    }
+
+    let roots = new_roots(&manifest.roots, library_roots, root_maps)?;
+    let report = storage.import_bundle(&work.0.join(DATABASE), &manifest, &roots, &artwork_dir)?;
+
+    let extracted = work.0.join(ARTWORK_DIR);
+    if extracted.is_dir() {
+        fs::create_dir_all(&artwork_dir)?;
+        for entry in fs::read_dir(&extracted)? {
+            let entry = entry?;
+            fs::copy(entry.path(), artwork_dir.join(entry.file_name()))?;
+        }
+    }
+    Ok(report)
+}
+
+/// Parses `OLD=NEW` of `--root`
+pub fn parse_root_map(arg: &str) -> Result<(String, PathBuf), String> {
+    let (old, new) = arg
+        .split_once('=')
+        .ok_or(format!("expected OLD=NEW, got '{arg}'"))?;
+    Ok((
+        old.trim_end_matches(['/', '\\']).to_string(),
+        PathBuf::from(new),
+    ))
+}
+
+/// Where each root of the bundle is on this machine: the one given with `--root`,
+/// otherwise the configured root at the same position, otherwise the same path
+fn new_roots(
+    old_roots: &[String],
+    library_roots: &[LibraryRoot],
+    root_maps: &[(String, PathBuf)],
+) -> anyhow::Result<Vec<PathBuf>> {
+    for (old, _) in root_maps {
+        if !old_roots.contains(old) {
+            bail!(
+                "'{old}' is not a root of the bundle, its roots are: {}",
+                old_roots.join(", ")
+            );
+        }
+    }
+    let configured: Vec<PathBuf> = library_roots
+        .iter()
+        .filter_map(|root| match &root.location {
+            Location::File { path } => Some(path.clone()),
+            Location::Usb { .. }
+            | Location::NetworkShare { .. }
+            | Location::S3 { .. }
+            | Location::Remote { .. } => None,
+        })
+        .collect();
+    let by_position = configured.len() == old_roots.len();
+    Ok(old_roots
+        .iter()
+        .enumerate()
+        .map(|(i, old)| {
+            root_maps
+                .iter()
+                .find(|(from, _)| from == old)
+                .map(|(_, to)| to.clone())
+                .or_else(|| by_position.then(|| configured[i].clone()))
+                .unwrap_or_else(|| PathBuf::from(old))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use localdeck_storage::{config::LibraryRoot, location::Location};
+
+    use super::{new_roots, parse_root_map};
+
+    #[test]
+    fn bundle_roots_are_mapped_to_this_machine() {
+        let old = vec!["/home/old/Music".to_string(), "/mnt/disk".to_string()];
+        let library: Vec<LibraryRoot> = vec![
+            Location::from_path("/srv/music").into(),
+            Location::from_path("/srv/disk").into(),
+        ];
+        let maps = vec![parse_root_map("/mnt/disk/=/media/disk").unwrap()];
+        assert_eq!(
+            new_roots(&old, &library, &maps).unwrap(),
+            vec![PathBuf::from("/srv/music"), PathBuf::from("/media/disk")]
+        );
+
+        // roots without a configured counterpart stay where they were
+        let library = vec![];
+        assert_eq!(
+            new_roots(&old, &library, &[]).unwrap(),
+            vec![PathBuf::from("/home/old/Music"), PathBuf::from("/mnt/disk")]
+        );
+
+        assert!(new_roots(&old, &library, &[parse_root_map("/x=/y").unwrap()]).is_err());
+        assert!(parse_root_map("/x").is_err());
+    }
+}
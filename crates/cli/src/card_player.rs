@@ -5,7 +5,7 @@ use crate::{
     music_player::{AudioPlayerError, MusicPlayer, Output, start_music_player},
     qr_scanner::{QrScanner, start_qr_scanner},
 };
-use localdeck_storage::operations::Storage;
+use localdeck_storage::store::LibraryStore;
 
 const STOP_LOCALDECK: &'static str = "FINISH";
 const STOP_MUSIC: &'static str = "STOP_MUSIC";
@@ -22,7 +22,7 @@ fn shutdown(player: MusicPlayer, scanner: QrScanner) {
 ///
 /// Then continuously:
 /// QR scan -> extract card id -> resolve path -> play
-pub fn run_card_player(storage: &mut Storage, output: Output) -> anyhow::Result<()> {
+pub fn run_card_player(storage: &mut dyn LibraryStore, output: Output) -> anyhow::Result<()> {
     let (qr_events, scanner) = start_qr_scanner();
 
     let (audio_errors, player) = match start_music_player(output) {
@@ -98,6 +98,12 @@ pub fn run_card_player(storage: &mut Storage, output: Output) -> anyhow::Result<
                         let track_id = storage.resolve_track(card_id.clone())?;
 
                         let (path, metadata) = match storage.find_track_file_with_meta(track_id) {
+                            Ok((_, loc, _)) if !loc.is_local() => {
+                                eprintln!(
+                                    "track {card_id} is not stored on this machine ({loc}), it can only be streamed over http"
+                                );
+                                continue;
+                            }
                             Ok((path, _, metadata)) => (path, metadata),
                             Err(e) => {
                                 eprintln!("could not resolve track {}: {}", card_id, e);
@@ -1,13 +1,47 @@
 use anyhow::{Context, bail};
-use clap::{Parser, Subcommand};
-use log::info;
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{LevelFilter, info};
+use std::collections::HashSet;
 use std::env;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::music_player::Output;
-use crate::{card_player, config};
-use localdeck_storage::operations::{MetadataUpdate, Storage};
+use crate::completion::{self, Shell};
+use crate::config::{ConfigSource, LogLevel};
+use crate::jukebox::open_jukebox_output;
+use crate::lrclib::Lrclib;
+use crate::music_player::{Output, audio_duration};
+use crate::progress::TerminalProgress;
+use crate::public_endpoint::PublicEndpoint;
+use crate::sync::{SyncClient, SyncOptions};
+use crate::{
+    artwork, backup, bundle, card_player, config, ddns, mdns, public_endpoint, rescan, selftest,
+    spotify, systemd,
+};
+use chrono::{Local, NaiveDate};
+use localdeck_http::{HttpConfig, server::ReloadedConfig};
+use localdeck_storage::artists::{ArtistCredit, ArtistRole};
+use localdeck_storage::card_scans;
+use localdeck_storage::glob::PathGlob;
+use localdeck_storage::location::Location;
+use localdeck_storage::operations::{
+    ListedFile, MetadataUpdate, Storage, TrackFilter, TrackListEntry,
+};
+use localdeck_storage::playlist::{Playlist, new_share_token};
+use localdeck_storage::provenance::{MetadataOrigin, MetadataPolicy, TagConflict, diff_tags};
+use localdeck_storage::snapshot::LibraryState;
+use localdeck_storage::sort_names::sort_by_name;
+use localdeck_storage::store::{LibraryStore, open_store};
+use localdeck_storage::tags::read_tags;
 use localdeck_storage::track::{ArtworkRef, TrackId, TrackMetadata};
+use localdeck_storage::youtube;
+use qrcode::QrCode;
+use qrcode::render::{svg, unicode::Dense1x2};
+use url::Url;
+
+/// Smallest side of QR codes written as SVG, in pixels
+const QR_SVG_SIZE: u32 = 512;
 
 #[derive(Parser)]
 #[command(name = "localdeck")]
@@ -16,10 +50,15 @@ use localdeck_storage::track::{ArtworkRef, TrackId, TrackMetadata};
 #[command(about = "Local music library manager")]
 pub struct Cli {
     /// Path to the config TOML file
-    /// If not provided, reads it from LOCALDECK_CONFIG env var
+    /// If not provided, reads it from LOCALDECK_CONFIG env var.
+    /// LOCALDECK_CONFIG=env reads the whole config from LOCALDECK_* variables instead
     #[arg(short, long)]
     pub config: Option<PathBuf>,
 
+    /// Do not show progress bars, useful for scripts
+    #[arg(long, global = true)]
+    pub no_progress: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -33,6 +72,15 @@ pub enum Commands {
     },
     /// Automatically update library by scanning configured directories
     Update,
+    /// Hash whole library files, reporting files whose content changed.
+    ///
+    /// Files hashed differently than `hash_kind` in the config (e.g. quickly) are rehashed,
+    /// keeping their track ids. Changed and unreadable files are quarantined
+    Verify {
+        /// Only hash files whose hash kind differs from the configured one
+        #[arg(long)]
+        only_outdated: bool,
+    },
     /// Link a specific music file to an existing track ID
     /// (Useful for adding high-quality, fixed, or alternative versions)
     Add {
@@ -54,33 +102,177 @@ pub enum Commands {
         #[arg(long)]
         ignore_slave_meta: bool,
     },
-    /// Run http server hosting library
-    Serve,
+    /// Run http server hosting library.
+    ///
+    /// Changed `basic_auth`, `tokens`, `ip_rules`, `log_level` and `ignored_dirs` of the config
+    /// are applied without a restart by `POST /admin/reload` with admin credentials, or by
+    /// SIGHUP, e.g. `systemctl reload localdeck`. `public_endpoint` is only read at start:
+    /// its check and port mapping run once per server, commands printing cards read it anew
+    Serve {
+        /// Write a systemd unit running this server with the current config, instead of starting it
+        #[arg(long)]
+        install_systemd: bool,
+
+        /// Install a system-wide unit instead of a user unit (requires root)
+        #[arg(long, requires = "install_systemd")]
+        system: bool,
+
+        /// Account the system-wide unit runs as, instead of the one that ran sudo.
+        /// Only `--user root` lets it run as root
+        #[arg(long, value_name = "NAME", requires = "system")]
+        user: Option<String>,
+
+        /// Play the party queue on this machine's audio output, e.g. a deck plugged into an amplifier.
+        /// Implies `party_queue`
+        #[arg(long, conflicts_with = "install_systemd")]
+        jukebox: bool,
+
+        /// Device name to play the jukebox from, the default output if not set
+        #[arg(short, long, requires = "jukebox")]
+        device: Option<String>,
+
+        /// Seconds of overlap between jukebox tracks, 0 plays them back to back without a gap
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 0.0,
+            requires = "jukebox"
+        )]
+        crossfade: f32,
+
+        /// Listen on this port instead of the configured one
+        #[arg(long, conflicts_with = "install_systemd")]
+        port: Option<u16>,
+
+        /// Listen on this address instead of the configured one, e.g. `127.0.0.1`
+        #[arg(long, value_name = "ADDR", conflicts_with = "install_systemd")]
+        bind: Option<String>,
+
+        /// Address guests reach this run at, in place of `base_url` of `public_endpoint`
+        #[arg(long, value_name = "URL", conflicts_with = "install_systemd")]
+        public_url: Option<String>,
+    },
     /// Find a track
     Find {
-        /// Artist, Track Name, Track Id or part of the filename to search for
+        /// Words of the artist, title, track id or filename to search for. Fields narrow
+        /// the search, e.g. `artist:"daft punk" year:>2000 playlist:party -format:wav`.
+        /// Other fields are `title`, `label`, `notes`, `path` and `card`
+        #[arg(allow_hyphen_values = true)]
         track: String,
         /// Find tracks only without metadata
         #[arg(long)]
         no_meta: bool,
     },
+    /// List all tracks with their recorded files
+    List {
+        /// Scan the library and mark files which are not found on disk,
+        /// with the time they were seen last
+        #[arg(long)]
+        show_unavailable: bool,
+        /// List only tracks marked with `favourite`
+        #[arg(long)]
+        favourites: bool,
+        /// Order of the tracks, `added` lists the most recently added first, `name` by
+        /// artist and title sort names
+        #[arg(long, value_enum, default_value_t = ListOrder::Id)]
+        sort: ListOrder,
+        /// List only tracks crediting this artist, featured or remixing too, ignoring case
+        #[arg(long)]
+        artist: Option<String>,
+        /// List only tracks released in this year or later
+        #[arg(long)]
+        year_from: Option<u32>,
+        /// List only tracks released in this year or earlier
+        #[arg(long)]
+        year_to: Option<u32>,
+        /// List only tracks with a file of this format, e.g. `flac`
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Mark a track as favourite, or unmark it if it is one already
+    Favourite { track_id: TrackId },
     /// Remove specified path from the database.
     ///
-    /// Useful to stop tracking moved or deleted files
+    /// Useful to stop tracking moved or deleted files. A path with `*` or `?` is a pattern,
+    /// e.g. `forget 'usb-backup/**/*.wav'` removes every WAV file below `usb-backup`
     Forget {
-        /// Directory or file to remove from database
+        /// Directory or file to remove from database, or a glob matching files
         path: PathBuf,
+        /// Take `*` and `?` in the path as part of the name
+        #[arg(long)]
+        literal: bool,
+    },
+    /// Generate url for a track to be printed on qr code or nfc chip.
+    ///
+    /// Includes the YouTube video stored for the track, if any. With `--playlist`
+    /// the card plays the whole playlist instead
+    Url {
+        #[arg(required_unless_present = "playlist")]
+        track_id: Option<TrackId>,
+        /// Id of the track's YouTube video, e.g. `dQw4w9WgXcQ`, stored for later urls of the track
+        #[arg(long)]
+        yt: Option<String>,
+        /// Play this playlist (name or id) from its first track, with its share token if it has one
+        #[arg(long, conflicts_with_all = ["track_id", "yt"])]
+        playlist: Option<String>,
+        /// Use this of the `other_base_urls` of `public_endpoint`, e.g. `lan`,
+        /// instead of its `base_url`
+        #[arg(long, conflicts_with = "all")]
+        endpoint: Option<String>,
+        /// Print the url for every configured base url, each after its name
+        #[arg(long)]
+        all: bool,
+        /// Print a short link `/s/<code>` instead, for a QR code with fewer, larger dots
+        #[arg(long)]
+        short: bool,
+        /// Also draw the url as a QR code in the terminal, e.g. to scan it with a phone
+        #[arg(long)]
+        qr: bool,
+    },
+    /// Print the link to the library page served by `serve`, with a QR code to print as a "library card".
+    ///
+    /// Scoped to a playlist the card opens the shared playlist page instead
+    LibraryCard {
+        /// Open this playlist (name or id) instead of the whole library
+        #[arg(long, conflicts_with = "search")]
+        playlist: Option<String>,
+        /// Start the library page with this search, e.g. an artist
+        #[arg(long)]
+        search: Option<String>,
+        /// Address guests reach the server at. Defaults to `base_url` of `public_endpoint`,
+        /// else this machine's hostname and the configured port
+        #[arg(long)]
+        base_url: Option<String>,
+        /// Write the QR code to this SVG file instead of the terminal
+        #[arg(long)]
+        svg: Option<PathBuf>,
     },
-    /// Generate url for a track to be printed on qr code or nfc chip
-    /// Currently does not include youtube link
-    Url { track_id: TrackId },
 
     /// get or edit metadata
+    #[command(alias = "metadata")]
     Meta {
         #[command(subcommand)]
         action: MetaAction,
     },
 
+    /// Fetch or show lyrics of tracks
+    Lyrics {
+        #[command(subcommand)]
+        action: LyricsAction,
+    },
+
+    /// Manage cover images of tracks
+    Artwork {
+        #[command(subcommand)]
+        action: ArtworkAction,
+    },
+
+    /// Create, fill and share playlists
+    Playlist {
+        #[command(subcommand)]
+        action: PlaylistAction,
+    },
+
     /// Clean dangling tracks (no files + no metadata)
     Clean,
 
@@ -90,6 +282,98 @@ pub enum Commands {
         #[arg(short, long)]
         device: Option<String>,
     },
+
+    /// Revert the most recent `update`, `forget` or file move found by `update`
+    Undo,
+
+    /// Files held back from playback because they changed or could not be read
+    Quarantine {
+        #[command(subcommand)]
+        action: QuarantineAction,
+    },
+
+    /// Save and compare point-in-time copies of the library
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+
+    /// Move the whole library to another machine: database, playlists and artwork in one archive
+    Bundle {
+        #[command(subcommand)]
+        action: BundleAction,
+    },
+
+    /// Write parts of the library for use elsewhere
+    Export {
+        #[command(subcommand)]
+        action: ExportAction,
+    },
+
+    /// Take metadata from other music managers
+    Import {
+        #[command(subcommand)]
+        action: ImportAction,
+    },
+
+    /// Pull metadata and playlists from another localdeck server, matching tracks by file content.
+    ///
+    /// Differing metadata and playlists here are kept and reported as conflicts
+    Sync {
+        /// Base URL of the other server, e.g. `http://other-deck:8080`
+        #[arg(long)]
+        from: String,
+        /// Also download tracks missing here into this directory, which must be inside a library root
+        #[arg(long, value_name = "DIR")]
+        download: Option<PathBuf>,
+        /// Replace differing metadata and playlists with the remote ones
+        #[arg(long)]
+        prefer_remote: bool,
+    },
+
+    /// Review past library changes. Without a subcommand shows the log of mutations
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+
+        /// Show only changes affecting this track
+        #[arg(long)]
+        track: Option<TrackId>,
+
+        /// Number of most recent changes to show
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Report how printed cards are used, from the requests `serve` received
+    Stats {
+        #[command(subcommand)]
+        action: StatsAction,
+    },
+
+    /// Find tracks needing work
+    Report {
+        #[command(subcommand)]
+        action: ReportAction,
+    },
+
+    /// Smoke-test a running server: health, track listing, playback and ranged streaming
+    Selftest {
+        /// Base URL of the server. Defaults to the address from the config
+        #[arg(long)]
+        server: Option<String>,
+    },
+
+    /// Print the script completing commands, track ids and playlist names in the shell,
+    /// e.g. `source <(localdeck completion bash)` in `~/.bashrc`
+    Completion { shell: Shell },
+
+    /// Candidates for the last of the words, called by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -104,6 +388,207 @@ pub enum CheckAction {
     Stale,
 }
 
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Store the current list of library files
+    Create {
+        /// Optional name to recognize the snapshot later
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List stored snapshots
+    List,
+    /// Show files added and removed between two snapshots.
+    ///
+    /// Each side is a snapshot id, a date (YYYY-MM-DD, the latest snapshot made until the end of that day)
+    /// or `current` for the library as it is now
+    Diff {
+        from: String,
+        /// Defaults to `current`
+        to: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryAction {
+    /// Show results of past `update` runs
+    Scans {
+        /// Number of most recent runs to show
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StatsAction {
+    /// Show how often each card was scanned and how its track was served.
+    ///
+    /// The same report is served as JSON at `/stats/scans`
+    Scans {
+        /// Only count scans of the last days
+        #[arg(long)]
+        days: Option<u32>,
+        /// List single scans with the scanning browser instead of totals per card
+        #[arg(long)]
+        log: bool,
+        /// Number of most recent scans listed with `--log`
+        #[arg(short, long, default_value_t = 20, requires = "log")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ReportAction {
+    /// List tracks without metadata, with an unknown artist or title, or without artwork,
+    /// the most recently added first
+    MissingMetadata,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ListOrder {
+    Id,
+    Added,
+    Name,
+}
+
+#[derive(Subcommand)]
+pub enum LyricsAction {
+    /// Look up lyrics on LRCLIB by artist, title and duration of the track
+    Fetch {
+        /// Track to fetch lyrics for. Without it, all tracks with metadata but without lyrics are fetched
+        track_id: Option<TrackId>,
+        /// Replace lyrics stored before
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Print stored lyrics
+    Get {
+        track_id: TrackId,
+        /// Print synced lyrics with timestamps instead of plain ones
+        #[arg(long)]
+        synced: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum QuarantineAction {
+    /// Show quarantined files with the reason they were held back
+    List,
+    /// Release quarantined files, e.g. after replacing them with good copies
+    Clear {
+        /// Only release files at or under this path
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ArtworkAction {
+    /// Download covers linked from the web to `artwork/` next to the database and point the
+    /// tracks at the copies, so they show offline and after the original host is gone
+    Localize,
+    /// List tracks with metadata but without artwork
+    Report {
+        /// Look for their covers at the providers of the `artwork` section of the config
+        #[arg(long)]
+        fetch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BundleAction {
+    /// Write the library to a `.tar.gz` archive. Music files are not included
+    Export { out: PathBuf },
+    /// Fill an empty library from an archive made by `bundle export`.
+    ///
+    /// Files below the library roots of the old machine are moved below the configured roots,
+    /// matched by position, unless mapped with `--root`
+    Import {
+        archive: PathBuf,
+        /// Where a root of the old machine is now, e.g. `--root /home/old/Music=/srv/music`
+        #[arg(long = "root", value_name = "OLD=NEW", value_parser = bundle::parse_root_map)]
+        roots: Vec<(String, PathBuf)>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportAction {
+    /// Write the tracks on a USB stick to `localdeck.db` in its root, replacing an earlier export.
+    ///
+    /// The stick then carries a library of its own: configure its database and a root with the
+    /// same label on another machine, and the tracks play from the stick there
+    UsbDb {
+        /// Label of the stick, as in the configured USB roots
+        #[arg(long)]
+        label: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ImportAction {
+    /// Take artist, title, year, label and album art of the items of a beets library.
+    ///
+    /// Items are matched to tracks by path, or by file content if beets has them elsewhere.
+    /// Tracks keep the fields they have unless `--overwrite` is given
+    Beets {
+        /// The beets database, e.g. `~/.config/beets/library.db`
+        library: PathBuf,
+
+        /// Replace values the tracks have already
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PlaylistAction {
+    /// Create an empty playlist
+    Create { name: String },
+    /// Delete a playlist, its tracks stay in the library
+    Delete { playlist: String },
+    /// List playlists with their number of tracks
+    List,
+    /// Print the tracks of a playlist in order
+    Show { playlist: String },
+    /// Append tracks to a playlist
+    Add {
+        playlist: String,
+        #[arg(required = true)]
+        track_ids: Vec<TrackId>,
+    },
+    /// Remove every occurrence of a track from a playlist
+    Remove { playlist: String, track_id: TrackId },
+    /// Rebuild a Spotify playlist from the tracks of the library, matched by artist and title.
+    ///
+    /// Takes a playlist saved from the Web API, `Playlist1.json` of a Spotify data export,
+    /// or a playlist link, which needs `--token`. Songs not in the library are listed
+    ImportSpotify {
+        /// JSON file or `https://open.spotify.com/playlist/...` link
+        source: String,
+        /// Access token of the Spotify Web API, for links
+        #[arg(long)]
+        token: Option<String>,
+        /// Name of the new playlist, defaults to the one on Spotify.
+        /// Also picks the playlist of a data export
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Print the link to the playlist page served by `serve`.
+    ///
+    /// The link stays the same until the token changes
+    Share {
+        playlist: String,
+        /// Require a new random token in the link, links shared before stop working
+        #[arg(long, conflicts_with = "public")]
+        token: bool,
+        /// Remove the token, anyone knowing the playlist id can open it
+        #[arg(long)]
+        public: bool,
+        /// Address guests reach the server at. Defaults to this machine's hostname and the configured port
+        #[arg(long)]
+        base_url: Option<String>,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum MetaAction {
     /// Get track metadata
@@ -139,10 +624,82 @@ pub enum MetaAction {
         #[arg(long)]
         artwork: Option<String>,
 
+        /// Free-form notes, e.g. "vinyl rip, crackly intro". An empty string removes them
+        #[arg(long)]
+        notes: Option<String>,
+
+        /// Name to order the artist by, e.g. "Beatles, The". An empty string makes it
+        /// from the artist again
+        #[arg(long)]
+        artist_sort: Option<String>,
+
+        /// Name to order the title by. An empty string makes it from the title again
+        #[arg(long)]
+        title_sort: Option<String>,
+
         /// Allow overwriting existing metadata
         #[arg(long)]
         overwrite: bool,
     },
+    /// Credit artists on a track, for those "A feat. B" does not name right.
+    ///
+    /// The artist becomes one made from the credits, like "A & B feat. C". Without
+    /// options the credits are read from the artist and title again
+    Artists {
+        track_id: TrackId,
+
+        /// Main artist, repeated for several
+        #[arg(long)]
+        main: Vec<String>,
+
+        /// Featured artist, repeated for several
+        #[arg(long)]
+        featuring: Vec<String>,
+
+        /// Remixing artist, repeated for several
+        #[arg(long)]
+        remixer: Vec<String>,
+    },
+    /// Set fields on every track with a file under a directory, e.g. the label of an album.
+    ///
+    /// Titles differ between tracks, so tracks without metadata yet are skipped
+    Apply {
+        /// Directory or file, as recorded in the database
+        #[arg(long)]
+        path: PathBuf,
+
+        /// Artist name
+        #[arg(short, long)]
+        artist: Option<String>,
+
+        /// Release year
+        #[arg(short, long)]
+        year: Option<u32>,
+
+        /// Label / publisher
+        #[arg(short, long)]
+        label: Option<String>,
+
+        /// Artwork URL
+        #[arg(long)]
+        artwork: Option<String>,
+
+        /// Replace values the tracks have already
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Read metadata from the tags of files again, as `update` does with `tag_policy` set.
+    ///
+    /// Fields edited by hand are treated as `tag_policy` says, `db-wins` if it is not set
+    ImportTags {
+        /// Only this track, instead of the whole library
+        track_id: Option<TrackId>,
+    },
+    /// Compare stored metadata with the tags embedded in the files, field by field
+    Diff {
+        /// Only this track, instead of the whole library
+        track_id: Option<TrackId>,
+    },
     /// retrieve all metadata
     All,
 }
@@ -165,27 +722,219 @@ impl Commands {
     }
 }
 
+/// Looks up lyrics of the track and stores them, returns whether any were found
+fn fetch_lyrics(
+    storage: &mut dyn LibraryStore,
+    lrclib: &Lrclib,
+    track_id: TrackId,
+    meta: &TrackMetadata,
+) -> anyhow::Result<bool> {
+    // the duration tells apart live versions, remixes and edits
+    let duration = storage
+        .find_track_file_with_meta(track_id)
+        .ok()
+        .and_then(|(path, _, _)| audio_duration(&path));
+    match lrclib.find(&meta.artist, &meta.title, duration)? {
+        Some(lyrics) => {
+            storage.set_lyrics(track_id, &lyrics)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Playlist with this name, or with this id if no name matches
+fn find_playlist(storage: &mut dyn LibraryStore, arg: &str) -> anyhow::Result<Playlist> {
+    let playlists = storage.list_playlists()?;
+    let by_id = || playlists.iter().find(|p| arg.parse() == Ok(p.id));
+    playlists
+        .iter()
+        .find(|p| p.name == arg)
+        .or_else(by_id)
+        .cloned()
+        .with_context(|| format!("no playlist named '{arg}'"))
+}
+
+/// Link to a shared playlist page, with the share token if the playlist has one
+fn playlist_url(base_url: &str, playlist: &Playlist) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    match &playlist.share_token {
+        Some(token) => format!("{base_url}/playlists/{}?t={token}", playlist.id),
+        None => format!("{base_url}/playlists/{}", playlist.id),
+    }
+}
+
+/// Link to the library page, opened with `search` filled in
+fn library_url(base_url: &str, search: Option<&str>) -> anyhow::Result<String> {
+    let mut url = Url::parse(&format!("{}/library", base_url.trim_end_matches('/')))
+        .with_context(|| format!("invalid base url '{base_url}'"))?;
+    if let Some(search) = search {
+        url.query_pairs_mut().append_pair("q", search);
+    }
+    Ok(url.to_string())
+}
+
+/// `Artist - Title` of listed tracks, the name of the first file for tracks without metadata
+fn track_name(track: &TrackListEntry) -> String {
+    match &track.meta {
+        Some(meta) => format!("{} - {}", meta.artist, meta.title),
+        None => track
+            .files
+            .first()
+            .and_then(|file| file.loc.file_name())
+            .unwrap_or_default(),
+    }
+}
+
+/// Path a card plays the track with, see `/play` of `serve`, with the YouTube video to fall back to
+fn play_path(track: TrackId, yt: Option<&str>) -> String {
+    match yt {
+        Some(yt) => format!("/play?h={track}&y={yt}"),
+        None => format!("/play?h={track}"),
+    }
+}
+
+/// Path a card plays a whole playlist with, see `/play-list` of `serve`
+fn play_list_path(playlist: &Playlist) -> String {
+    match &playlist.share_token {
+        Some(token) => format!("/play-list?p={}&t={token}", playlist.id),
+        None => format!("/play-list?p={}", playlist.id),
+    }
+}
+
+/// Draws `url` as a QR code with half-height blocks, two rows of modules per line
+fn print_qr(url: &str) -> anyhow::Result<()> {
+    let code = QrCode::new(url)?;
+    println!("{}", code.render::<Dense1x2>().build());
+    Ok(())
+}
+
+/// Every address `serve` is reached at by name, the one of [`public_base_url`] first
+fn public_base_urls(http: &HttpConfig, endpoint: Option<&PublicEndpoint>) -> Vec<(String, String)> {
+    match endpoint {
+        Some(endpoint) => endpoint
+            .base_urls()
+            .map(|(name, url)| (name.to_string(), url.trim_end_matches('/').to_string()))
+            .collect(),
+        None => vec![("default".to_string(), public_base_url(http, None))],
+    }
+}
+
+/// Address of `serve` as guests reach it, the configured public endpoint if any,
+/// else as other devices on the network do
+fn public_base_url(http: &HttpConfig, endpoint: Option<&PublicEndpoint>) -> String {
+    if let Some(endpoint) = endpoint {
+        return endpoint.base_url.trim_end_matches('/').to_string();
+    }
+    let host = match http.bind_addr.as_str() {
+        "0.0.0.0" | "::" => std::fs::read_to_string("/etc/hostname")
+            .ok()
+            .or_else(|| env::var("COMPUTERNAME").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string()),
+        addr => addr.to_string(),
+    };
+    format!("http://{host}:{}", http.port)
+}
+
+fn describe_last_seen(storage: &mut Storage, loc: &Location) -> anyhow::Result<String> {
+    Ok(match storage.last_seen(loc)? {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => "never".to_string(),
+    })
+}
+
+/// Format, size and modification time of a listed file, e.g. `flac, 31.2 MB, modified 2026-03-01 18:20`
+fn describe_file(file: &ListedFile) -> String {
+    let mut parts = vec![];
+    if let Some(format) = &file.format {
+        parts.push(format.clone());
+    }
+    parts.push(format!("{:.1} MB", file.size as f64 / (1024. * 1024.)));
+    if let Some(modified) = file.modified {
+        parts.push(format!("modified {}", modified.format("%Y-%m-%d %H:%M")));
+    }
+    parts.join(", ")
+}
+
+/// Parses a snapshot id, a date or `current`, see [`SnapshotAction::Diff`]
+fn parse_library_state(storage: &mut Storage, arg: &str) -> anyhow::Result<LibraryState> {
+    if arg == "current" {
+        return Ok(LibraryState::Current);
+    }
+    if let Ok(id) = arg.parse() {
+        return Ok(LibraryState::Snapshot(id));
+    }
+    let date = NaiveDate::parse_from_str(arg, "%Y-%m-%d")
+        .with_context(|| format!("'{arg}' is neither a snapshot id, a date nor `current`"))?;
+    let end_of_day = date
+        .and_hms_opt(23, 59, 59)
+        .and_then(|time| time.and_local_timezone(Local).latest())
+        .with_context(|| format!("failed to get local time of {date}"))?;
+    match storage.snapshot_at(end_of_day)? {
+        Some(id) => Ok(LibraryState::Snapshot(id)),
+        None => bail!("no snapshot was made until {date}"),
+    }
+}
+
 /// Entrypoint for CLI
 pub fn run() -> anyhow::Result<()> {
-    env_logger::builder()
+    // the level of `RUST_LOG` is kept in `log::max_level` rather than the logger,
+    // so `serve` can change it with the config. Its per-module filters still apply
+    let level = env_logger::Builder::from_default_env().build().filter();
+    env_logger::Builder::new()
         .target(env_logger::Target::Stdout)
+        .parse_default_env()
+        .filter_level(LevelFilter::Trace)
         .init();
+    log::set_max_level(level);
     info!("Initialized logging to stdout");
 
     let cli = Cli::parse();
 
-    let cfg_path = if let Some(path) = cli.config {
-        path
-    } else {
+    let config_source = || -> anyhow::Result<ConfigSource> {
+        if let Some(path) = &cli.config {
+            return Ok(ConfigSource::File(path.clone()));
+        }
         let path = env::var("LOCALDECK_CONFIG")
             .context("Failed to get path to config. Provide it via flag or environment variable LOCALDECK_CONFIG")?;
-        PathBuf::from(path)
+        Ok(if path == config::ENV_CONFIG {
+            ConfigSource::Env
+        } else {
+            ConfigSource::File(PathBuf::from(path))
+        })
     };
-    let cfg = config::Config::load(&cfg_path)?;
+    // completing subcommands works without a config
+    match &cli.command {
+        Commands::Completion { shell } => {
+            print!("{}", shell.script());
+            return Ok(());
+        }
+        Commands::Complete { words } => {
+            let open = || {
+                Ok(open_store(
+                    config::Config::load_from(&config_source()?)?.storage,
+                )?)
+            };
+            for candidate in completion::candidates(words, open)? {
+                println!("{candidate}");
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let cfg_source = config_source()?;
+    let cfg = config::Config::load_from(&cfg_source)?;
+    let show_progress = !cli.no_progress;
 
     match cli.command {
         Commands::Check { action } => {
             let mut storage = Storage::new(cfg.storage)?;
+            if show_progress {
+                storage.set_progress(Box::new(TerminalProgress::default()));
+            }
             if let Some(action) = action {
                 match action {
                     CheckAction::New => {
@@ -208,9 +957,10 @@ pub fn run() -> anyhow::Result<()> {
                                     println!("Unavailable locations:");
                                     for file in old_locs {
                                         println!(
-                                            "  - {}\n      size: {:.2} MB",
+                                            "  - {}\n      size: {:.2} MB\n      last seen: {}",
                                             file.loc,
-                                            file.size_mb()
+                                            file.size_mb(),
+                                            describe_last_seen(&mut storage, &file.loc)?
                                         );
                                     }
                                 }
@@ -256,11 +1006,30 @@ pub fn run() -> anyhow::Result<()> {
             } else {
                 let time = storage.updated_at()?;
                 println!("Data base was updated {}", time);
+                let unavailable = storage.unavailable_roots();
+                if !unavailable.is_empty() {
+                    println!("Unavailable library roots:");
+                    for root in &unavailable {
+                        println!("  - {}: {}", root.location, root.reason);
+                    }
+                }
+                if unavailable
+                    .iter()
+                    .any(|root| matches!(root.location, Location::NetworkShare { .. }))
+                {
+                    println!(
+                        "Files on unmounted network shares are kept, they are served again once the share is back"
+                    );
+                }
             }
         }
 
         Commands::Update {} => {
-            let mut storage = Storage::new(cfg.storage)?;
+            let tag_policy = cfg.storage.library_source.tag_policy;
+            let mut storage = open_store(cfg.storage)?;
+            if show_progress {
+                storage.set_progress(Box::new(TerminalProgress::default()));
+            }
             let files = storage.update_db_with_new_files()?;
             println!("Database updated, new files ({}):", files.len());
             for (track, files) in &files {
@@ -269,14 +1038,190 @@ pub fn run() -> anyhow::Result<()> {
                     println!("    - {}", file.file.loc);
                 }
             }
+            if let Some(policy) = tag_policy {
+                let tracks: Vec<_> = files.keys().copied().collect();
+                import_tags(storage.as_mut(), &tracks, policy)?;
+            }
         }
 
-        Commands::Serve {} => {
+        Commands::Verify { only_outdated } => {
+            let mut storage = Storage::new(cfg.storage)?;
+            if show_progress {
+                storage.set_progress(Box::new(TerminalProgress::default()));
+            }
+            let report = storage.verify(only_outdated)?;
+            println!(
+                "Verified {} file(s), rehashed {}",
+                report.verified, report.rehashed
+            );
+            if !report.changed.is_empty() {
+                println!("Files whose content changed since they were added:");
+                for loc in &report.changed {
+                    println!("  - {loc}");
+                }
+                println!("Forget and update them to record the new content");
+            }
+            if !report.unreadable.is_empty() {
+                println!("Files that are there but could not be read:");
+                for loc in &report.unreadable {
+                    println!("  - {loc}");
+                }
+            }
+            if report.quarantined > 0 {
+                println!(
+                    "Quarantined {} file(s), they are not played until released with `localdeck quarantine clear`",
+                    report.quarantined
+                );
+            }
+            if !report.unavailable.is_empty() {
+                println!("Files that could not be found:");
+                for loc in &report.unavailable {
+                    println!("  - {loc}");
+                }
+            }
+            if !report.duplicates.is_empty() {
+                println!("Tracks with identical files:");
+                for (master, slave) in &report.duplicates {
+                    println!(
+                        "  - {master} and {slave}, join with: localdeck merge {slave} --into {master}"
+                    );
+                }
+            }
+        }
+
+        Commands::Serve {
+            install_systemd,
+            system,
+            user,
+            jukebox,
+            device,
+            crossfade,
+            port,
+            bind,
+            public_url,
+        } => {
+            if install_systemd {
+                let ConfigSource::File(cfg_path) = &cfg_source else {
+                    bail!(
+                        "--install-systemd needs a config file, environment-only config is not supported"
+                    );
+                };
+                let path = systemd::install_unit(cfg_path, system, user.as_deref())?;
+                println!("Wrote systemd unit to {}", path.to_string_lossy());
+                let scope = if system { "" } else { " --user" };
+                println!("Enable it with:");
+                println!("systemctl{scope} daemon-reload");
+                println!("systemctl{scope} enable --now localdeck.service");
+                return Ok(());
+            }
+
             println!("Starting HTTP server...");
 
-            let storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
+            let mut cfg = cfg;
+            if let Some(port) = port {
+                cfg.http.port = port;
+            }
+            if let Some(bind) = bind {
+                cfg.http.bind_addr = bind;
+            }
+            if let Some(base_url) = public_url {
+                match &mut cfg.public_endpoint {
+                    Some(endpoint) => endpoint.base_url = base_url,
+                    None => {
+                        cfg.public_endpoint = Some(PublicEndpoint {
+                            base_url,
+                            port_mapping: false,
+                            external_port: None,
+                            other_base_urls: Default::default(),
+                        })
+                    }
+                }
+            }
+
+            let rust_log_level = log::max_level();
+            if let Some(LogLevel(level)) = cfg.log_level {
+                log::set_max_level(level);
+            }
 
-            let http_server = localdeck_http::server::HttpServer::new(storage, cfg.http);
+            let tag_policy = cfg.storage.library_source.tag_policy;
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
+            storage.enable_track_index();
+            let unavailable = storage.unavailable_roots();
+            if !unavailable.is_empty() {
+                println!("Running in degraded mode, some library roots are unavailable:");
+                for root in unavailable {
+                    println!("  {}: {}", root.location, root.reason);
+                }
+            }
+
+            let reload_source = cfg_source.clone();
+            let mut http_server = localdeck_http::server::HttpServer::new(storage, cfg.http)
+                .with_reload(move || {
+                    let cfg = config::Config::load_from(&reload_source)?;
+                    Ok(ReloadedConfig {
+                        log_level: cfg.log_level.map_or(rust_log_level, |level| level.0),
+                        ignored_dirs: cfg.storage.library_source.ignored_dirs,
+                        http: cfg.http,
+                    })
+                });
+            if jukebox {
+                let seconds = |secs: f32| {
+                    Duration::try_from_secs_f32(secs)
+                        .context("crossfade must be a positive number of seconds")
+                };
+                let crossfade = seconds(crossfade)?;
+                let zones = cfg.jukebox.zones;
+                if zones.is_empty() {
+                    let output = match device {
+                        Some(d) => Output::Device(d),
+                        None => Output::Default,
+                    };
+                    let output = open_jukebox_output(output, crossfade)
+                        .context("Failed to open the audio output for the jukebox")?;
+                    println!("Jukebox mode: playing the party queue on this machine");
+                    http_server = http_server.with_jukebox(output);
+                } else if device.is_some() {
+                    bail!("--device does not apply to the zones of the config, set their `device`");
+                }
+
+                let mut names = HashSet::new();
+                for zone in zones {
+                    if !names.insert(zone.name.clone()) {
+                        bail!("jukebox zone {} is configured twice", zone.name);
+                    }
+                    let output = match zone.device {
+                        Some(d) => Output::Device(d),
+                        None => Output::Default,
+                    };
+                    let crossfade = zone.crossfade_secs.map_or(Ok(crossfade), seconds)?;
+                    let output = open_jukebox_output(output, crossfade).with_context(|| {
+                        format!("Failed to open the audio output of zone {}", zone.name)
+                    })?;
+                    println!("Jukebox zone {} playing its party queue", zone.name);
+                    http_server = http_server.with_zone(&zone.name, output);
+                }
+            }
+            if let Some(rescan) = cfg.rescan {
+                println!("Scanning the library for new files on a schedule");
+                rescan::spawn_scheduler(http_server.storage(), rescan, tag_policy)?;
+            }
+            if let Some(backup) = cfg.backup {
+                println!("Backing up the library to {}", backup.destination);
+                backup::spawn_scheduler(http_server.storage(), backup)?;
+            }
+            if let Some(ddns) = cfg.ddns {
+                println!("Keeping {} pointed at this deck", ddns.provider);
+                ddns::spawn_updater(ddns)?;
+            }
+            if let Some(endpoint) = cfg.public_endpoint {
+                println!("Checking that {} reaches this server", endpoint.base_url);
+                public_endpoint::spawn(endpoint, &http_server)?;
+            }
+            // a missing responder only costs discoverability, serve anyway
+            let _mdns = mdns::advertise(&cfg.mdns, &http_server.config).unwrap_or_else(|e| {
+                log::warn!("not advertising over mDNS: {e:#}");
+                None
+            });
 
             println!(
                 "HTTP server running at http://{}:{}",
@@ -289,7 +1234,7 @@ pub fn run() -> anyhow::Result<()> {
             track: name,
             no_meta,
         } => {
-            let mut storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
             let tracks = storage.find_files(&name, no_meta)?;
             if !tracks.is_empty() {
                 for (trackid, paths) in tracks {
@@ -302,9 +1247,14 @@ pub fn run() -> anyhow::Result<()> {
                 println!("No tracks found :(");
             }
         }
-        Commands::Forget { path } => {
-            let mut storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
-            let report = storage.forget_path(&path)?;
+        Commands::Forget { path, literal } => {
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
+            let pattern = path.to_string_lossy();
+            let report = if !literal && PathGlob::is_pattern(&pattern) {
+                storage.forget_matching(&PathGlob::new(&pattern))?
+            } else {
+                storage.forget_path(&path)?
+            };
             if report.affected_tracks == 0 {
                 println!("No tracks located under {} found", path.to_string_lossy());
             } else {
@@ -314,28 +1264,150 @@ pub fn run() -> anyhow::Result<()> {
                 );
             }
         }
-        Commands::Url { track_id } => {
-            let mut storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
-            let _ = storage.get_track_metadata(track_id).unwrap();
-            println!("{track_id}");
+        Commands::Url {
+            track_id,
+            yt,
+            playlist,
+            endpoint,
+            all,
+            short,
+            qr,
+        } => {
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
+            let play_path = match (track_id, playlist) {
+                (Some(track_id), _) => {
+                    let _ = storage.get_track_metadata(track_id).unwrap();
+                    let yt = match yt {
+                        Some(yt) => {
+                            if !youtube::is_video_id(&yt) {
+                                bail!("'{yt}' is not a YouTube video id, e.g. dQw4w9WgXcQ");
+                            }
+                            storage.set_youtube_id(track_id, &yt)?;
+                            Some(yt)
+                        }
+                        None => storage.youtube_id(track_id)?,
+                    };
+                    play_path(track_id, yt.as_deref())
+                }
+                (None, Some(playlist)) => play_list_path(&find_playlist(&mut storage, &playlist)?),
+                (None, None) => unreachable!("clap requires a track or a playlist"),
+            };
+            let path = if short {
+                format!("/s/{}", storage.short_link(&play_path)?)
+            } else {
+                play_path
+            };
+            let base_urls = public_base_urls(&cfg.http, cfg.public_endpoint.as_ref());
+            if all {
+                for (name, base_url) in &base_urls {
+                    println!("{name}: {base_url}{path}");
+                    if qr {
+                        print_qr(&format!("{base_url}{path}"))?;
+                    }
+                }
+            } else {
+                let name = endpoint.as_deref().unwrap_or("default");
+                let Some((_, base_url)) = base_urls.iter().find(|(n, _)| n == name) else {
+                    let names: Vec<_> = base_urls.iter().map(|(n, _)| n.as_str()).collect();
+                    bail!(
+                        "No base url named '{name}', configured are: {}",
+                        names.join(", ")
+                    );
+                };
+                println!("{base_url}{path}");
+                if qr {
+                    print_qr(&format!("{base_url}{path}"))?;
+                }
+            }
+        }
+
+        Commands::LibraryCard {
+            playlist,
+            search,
+            base_url,
+            svg: svg_path,
+        } => {
+            let base_url = base_url
+                .unwrap_or_else(|| public_base_url(&cfg.http, cfg.public_endpoint.as_ref()));
+            let url = match playlist {
+                Some(playlist) => {
+                    let mut storage = open_store(cfg.storage)?;
+                    playlist_url(&base_url, &find_playlist(&mut storage, &playlist)?)
+                }
+                None => library_url(&base_url, search.as_deref())?,
+            };
+            println!("{url}");
+            let code = QrCode::new(&url)?;
+            match svg_path {
+                Some(path) => {
+                    let image = code
+                        .render::<svg::Color>()
+                        .min_dimensions(QR_SVG_SIZE, QR_SVG_SIZE)
+                        .build();
+                    std::fs::write(&path, image)?;
+                    println!("Wrote the QR code to {}", path.to_string_lossy());
+                }
+                None => println!("{}", code.render::<Dense1x2>().build()),
+            }
         }
 
         Commands::Meta { action } => {
-            let mut storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
+            let tag_policy = cfg.storage.library_source.tag_policy;
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
             match action {
                 MetaAction::Get { track_id, json } => {
                     let meta = storage.get_track_metadata(track_id)?;
-                    if let Some(meta) = meta {
-                        let str = if json {
-                            serde_json::to_string(&meta)
-                                .expect("failed to serialize metadata to json")
-                        } else {
-                            pretty_metadata(meta)
-                        };
-                        println!("{str}");
-                    } else {
+                    let notes = storage.track_notes(track_id)?;
+                    if meta.is_none() && notes.is_none() {
                         bail!("No metadata for this track found :(");
                     }
+                    if json {
+                        let mut value = serde_json::to_value(&meta)
+                            .expect("failed to serialize metadata to json");
+                        value["notes"] = notes.into();
+                        println!("{value}");
+                    } else {
+                        // only worth showing when it orders the track elsewhere
+                        let sort = storage.sort_names(track_id)?.filter(|sort| {
+                            meta.as_ref().is_some_and(|meta| {
+                                sort.artist != meta.artist || sort.title != meta.title
+                            })
+                        });
+                        if let Some(meta) = meta {
+                            println!("{}", pretty_metadata(meta));
+                        }
+                        if let Some(sort) = sort {
+                            println!("Sorted: {} - {}", sort.artist, sort.title);
+                        }
+                        let credits = storage.artist_credits(track_id)?;
+                        // a single main artist is the artist shown already
+                        if credits.iter().any(|credit| credit.role != ArtistRole::Main)
+                            || credits.len() > 1
+                        {
+                            println!("Credits:");
+                            for credit in credits {
+                                println!("  {:<9}: {}", credit.role, credit.name);
+                            }
+                        }
+                        if let Some(notes) = notes {
+                            println!("Notes : {notes}");
+                        }
+                        let sources = storage.metadata_sources(track_id)?;
+                        if !sources.is_empty() {
+                            println!("Set from:");
+                            for source in sources {
+                                let origin = match source.origin {
+                                    MetadataOrigin::Tags => "file tags",
+                                    MetadataOrigin::Manual => "edit",
+                                };
+                                println!(
+                                    "  {:<6}: {origin}, {}",
+                                    source.field,
+                                    source.changed_at.format("%Y-%m-%d %H:%M")
+                                );
+                            }
+                        }
+                    }
                 }
                 MetaAction::Add {
                     track_id,
@@ -344,13 +1416,158 @@ pub fn run() -> anyhow::Result<()> {
                     year,
                     label,
                     artwork,
+                    notes,
+                    artist_sort,
+                    title_sort,
                     overwrite,
                 } => {
-                    let update = Commands::to_metadata_update(title, artist, year, label, artwork);
-
-                    storage.update_track_metadata(track_id, update, overwrite)?;
+                    // notes or sort names alone leave the metadata as it is, tracks need
+                    // none to have notes
+                    let fields_given = title.is_some()
+                        || artist.is_some()
+                        || year.is_some()
+                        || label.is_some()
+                        || artwork.is_some();
+                    let others_given =
+                        notes.is_some() || artist_sort.is_some() || title_sort.is_some();
+                    if fields_given || !others_given {
+                        let update =
+                            Commands::to_metadata_update(title, artist, year, label, artwork);
+                        storage.update_track_metadata(track_id, update, overwrite)?;
+                    }
+                    if let Some(notes) = notes {
+                        storage.set_track_notes(track_id, Some(&notes))?;
+                    }
+                    if artist_sort.is_some() || title_sort.is_some() {
+                        storage.set_sort_names(
+                            track_id,
+                            artist_sort.as_deref(),
+                            title_sort.as_deref(),
+                        )?;
+                    }
                     println!("Metadata updated for {}", track_id);
                 }
+                MetaAction::Artists {
+                    track_id,
+                    main,
+                    featuring,
+                    remixer,
+                } => {
+                    let credits: Vec<_> = [
+                        (main, ArtistRole::Main),
+                        (featuring, ArtistRole::Featuring),
+                        (remixer, ArtistRole::Remixer),
+                    ]
+                    .into_iter()
+                    .flat_map(|(names, role)| {
+                        names
+                            .into_iter()
+                            .map(move |name| ArtistCredit::new(name.trim(), role))
+                    })
+                    .collect();
+                    storage.set_artist_credits(track_id, &credits)?;
+                    for credit in storage.artist_credits(track_id)? {
+                        println!("{:<9}: {}", credit.role, credit.name);
+                    }
+                }
+                MetaAction::Apply {
+                    path,
+                    artist,
+                    year,
+                    label,
+                    artwork,
+                    overwrite,
+                } => {
+                    let update = Commands::to_metadata_update(None, artist, year, label, artwork);
+                    let report = storage.apply_metadata_under(&path, update, overwrite)?;
+                    if report.matched_tracks == 0 {
+                        bail!("No tracks located under {} found", path.to_string_lossy());
+                    }
+                    let unchanged = report.matched_tracks
+                        - report.updated.len()
+                        - report.kept.len()
+                        - report.missing_required.len();
+                    println!(
+                        "Tracks under {}: {}\n  Updated: {}\n  Unchanged: {}",
+                        path.to_string_lossy(),
+                        report.matched_tracks,
+                        report.updated.len(),
+                        unchanged
+                    );
+                    if !report.kept.is_empty() {
+                        println!(
+                            "  Kept, they have other values already (use --overwrite): {}",
+                            join_ids(&report.kept)
+                        );
+                    }
+                    if !report.missing_required.is_empty() {
+                        println!(
+                            "  Skipped, they have no metadata yet (add title and artist with `meta add`): {}",
+                            join_ids(&report.missing_required)
+                        );
+                    }
+                }
+                MetaAction::ImportTags { track_id } => {
+                    let tracks = match track_id {
+                        Some(track_id) => vec![track_id],
+                        None => storage.list_tracks()?.iter().map(|t| t.id).collect(),
+                    };
+                    import_tags(storage.as_mut(), &tracks, tag_policy.unwrap_or_default())?;
+                }
+                MetaAction::Diff { track_id } => {
+                    let tracks = match track_id {
+                        Some(track_id) => vec![track_id],
+                        None => storage.list_tracks()?.iter().map(|t| t.id).collect(),
+                    };
+                    let mut differing = 0;
+                    let mut unreadable = Vec::new();
+                    for &track in &tracks {
+                        let (path, location, meta) = match storage.find_track_file_with_meta(track)
+                        {
+                            Ok(file) if file.1.is_local() => file,
+                            _ => {
+                                unreadable.push(track);
+                                continue;
+                            }
+                        };
+                        let tags = match read_tags(&path) {
+                            Ok(tags) => tags,
+                            Err(e) => {
+                                info!("could not read tags of {}: {e}", path.display());
+                                unreadable.push(track);
+                                continue;
+                            }
+                        };
+                        let diffs = diff_tags(meta.as_ref(), &tags);
+                        if diffs.is_empty() {
+                            continue;
+                        }
+                        differing += 1;
+                        println!("- track {track} ({location}):");
+                        for diff in diffs {
+                            let show = |value: Option<String>| match value {
+                                Some(value) => format!("'{value}'"),
+                                None => "-".to_string(),
+                            };
+                            println!(
+                                "    {:<6}: database {}, tags {}",
+                                diff.field,
+                                show(diff.stored),
+                                show(diff.tagged)
+                            );
+                        }
+                    }
+                    println!(
+                        "{differing} of {} track(s) differ from their tags",
+                        tracks.len()
+                    );
+                    if !unreadable.is_empty() {
+                        println!(
+                            "Tracks without a readable local file: {}",
+                            join_ids(&unreadable)
+                        );
+                    }
+                }
                 MetaAction::All => {
                     let meta = storage.scan_metadata()?;
                     println!("Database contains metadata for {} tracks", meta.len());
@@ -361,8 +1578,253 @@ pub fn run() -> anyhow::Result<()> {
                 }
             }
         }
+        Commands::Lyrics { action } => {
+            let mut storage = open_store(cfg.storage)?;
+            match action {
+                LyricsAction::Fetch {
+                    track_id: Some(track_id),
+                    overwrite,
+                } => {
+                    if !overwrite && storage.get_lyrics(track_id)?.is_some() {
+                        bail!(
+                            "Track {track_id} already has lyrics, use --overwrite to replace them"
+                        );
+                    }
+                    let Some(meta) = storage.get_track_metadata(track_id)? else {
+                        bail!(
+                            "Track {track_id} has no metadata, artist and title are needed to find lyrics"
+                        );
+                    };
+                    if fetch_lyrics(&mut storage, &Lrclib::new(), track_id, &meta)? {
+                        println!("Stored lyrics of {track_id}");
+                    } else {
+                        println!("No lyrics found for {} - {}", meta.artist, meta.title);
+                    }
+                }
+                LyricsAction::Fetch {
+                    track_id: None,
+                    overwrite,
+                } => {
+                    let lrclib = Lrclib::new();
+                    let (mut found, mut not_found) = (0, 0);
+                    for track in storage.scan_metadata()? {
+                        if !overwrite && storage.get_lyrics(track.id)?.is_some() {
+                            continue;
+                        }
+                        let meta = &track.metadata;
+                        match fetch_lyrics(&mut storage, &lrclib, track.id, meta) {
+                            Ok(true) => {
+                                found += 1;
+                                println!("{}: {} - {}", track.id, meta.artist, meta.title);
+                            }
+                            Ok(false) => not_found += 1,
+                            Err(e) => {
+                                not_found += 1;
+                                eprintln!("{}: failed to fetch lyrics: {e}", track.id);
+                            }
+                        }
+                    }
+                    println!("Stored lyrics of {found} track(s), not found for {not_found}");
+                }
+                LyricsAction::Get { track_id, synced } => {
+                    let Some(lyrics) = storage.get_lyrics(track_id)? else {
+                        bail!(
+                            "No lyrics stored for track {track_id}, fetch them with: localdeck lyrics fetch {track_id}"
+                        );
+                    };
+                    let text = if synced { lyrics.synced } else { lyrics.plain };
+                    match text {
+                        Some(text) => println!("{text}"),
+                        None if synced => {
+                            bail!("Only plain lyrics are stored for track {track_id}")
+                        }
+                        None => bail!(
+                            "Only synced lyrics are stored for track {track_id}, show them with --synced"
+                        ),
+                    }
+                }
+            }
+        }
+        Commands::Artwork { action } => match action {
+            ArtworkAction::Localize => {
+                let mut storage = Storage::new(cfg.storage)?;
+                let report = artwork::localize(&mut storage)?;
+                for (track_id, reason) in &report.failed {
+                    eprintln!("{track_id}: {reason}");
+                }
+                println!(
+                    "Stored {} cover(s) locally, {} failed",
+                    report.localized,
+                    report.failed.len()
+                );
+            }
+            ArtworkAction::Report { fetch } => {
+                let providers = cfg.artwork.providers;
+                let mut storage = open_store(cfg.storage)?;
+                let tracks: Vec<_> = storage
+                    .scan_metadata()?
+                    .into_iter()
+                    .filter(|track| track.metadata.artwork.is_none())
+                    .collect();
+                for track in &tracks {
+                    println!(
+                        "{} {} - {}",
+                        track.id, track.metadata.artist, track.metadata.title
+                    );
+                }
+                println!("{} track(s) without artwork", tracks.len());
+                if fetch && !tracks.is_empty() {
+                    let report = artwork::fetch_missing(storage.as_mut(), &tracks, &providers)?;
+                    for (track_id, reason) in &report.failed {
+                        eprintln!("{track_id}: {reason}");
+                    }
+                    for provider in &providers {
+                        let count = report
+                            .found
+                            .iter()
+                            .filter(|(_, found)| found == provider)
+                            .count();
+                        println!("  {provider}: {count} cover(s)");
+                    }
+                    println!(
+                        "Found {} cover(s), none for {} track(s), {} failed",
+                        report.found.len(),
+                        report.not_found.len(),
+                        report.failed.len()
+                    );
+                }
+            }
+        },
+        Commands::Playlist { action } => {
+            let mut storage = open_store(cfg.storage)?;
+            match action {
+                PlaylistAction::Create { name } => {
+                    let playlist = storage.create_playlist(&name)?;
+                    println!("Created playlist {} '{}'", playlist.id, playlist.name);
+                }
+                PlaylistAction::Delete { playlist } => {
+                    let playlist = find_playlist(&mut storage, &playlist)?;
+                    storage.delete_playlist(playlist.id)?;
+                    println!("Deleted playlist '{}'", playlist.name);
+                }
+                PlaylistAction::List => {
+                    let playlists = storage.list_playlists()?;
+                    if playlists.is_empty() {
+                        println!("No playlists yet, create one with `localdeck playlist create`");
+                    }
+                    for playlist in playlists {
+                        println!(
+                            "{} {} ({} tracks)",
+                            playlist.id,
+                            playlist.name,
+                            playlist.tracks.len()
+                        );
+                    }
+                }
+                PlaylistAction::Show { playlist } => {
+                    let playlist = find_playlist(&mut storage, &playlist)?;
+                    for (i, track) in playlist.tracks.iter().enumerate() {
+                        match storage.get_track_metadata(*track)? {
+                            Some(meta) => {
+                                println!("{}. {track} {} - {}", i + 1, meta.artist, meta.title)
+                            }
+                            None => println!("{}. {track}", i + 1),
+                        }
+                    }
+                }
+                PlaylistAction::Add {
+                    playlist,
+                    track_ids,
+                } => {
+                    let mut playlist = find_playlist(&mut storage, &playlist)?;
+                    playlist.tracks.extend(&track_ids);
+                    storage.set_playlist_tracks(playlist.id, &playlist.tracks)?;
+                    println!("Added {} track(s) to '{}'", track_ids.len(), playlist.name);
+                }
+                PlaylistAction::Remove { playlist, track_id } => {
+                    let mut playlist = find_playlist(&mut storage, &playlist)?;
+                    let before = playlist.tracks.len();
+                    playlist.tracks.retain(|t| *t != track_id);
+                    if playlist.tracks.len() == before {
+                        bail!("Track {track_id} is not in '{}'", playlist.name);
+                    }
+                    storage.set_playlist_tracks(playlist.id, &playlist.tracks)?;
+                    println!("Removed {track_id} from '{}'", playlist.name);
+                }
+                PlaylistAction::ImportSpotify {
+                    source,
+                    token,
+                    name,
+                } => {
+                    let spotify = match spotify::playlist_id(&source) {
+                        Some(id) => {
+                            let token = token.context(
+                                "links are read through the Spotify Web API, pass an access token with --token",
+                            )?;
+                            spotify::fetch(id, &token)?
+                        }
+                        None => {
+                            let json = std::fs::read_to_string(&source)
+                                .with_context(|| format!("failed to read {source}"))?;
+                            spotify::read_export(&json, name.as_deref())?
+                        }
+                    };
+                    let tracks = storage.list_tracks()?;
+                    let mut matched = vec![];
+                    let mut missing = vec![];
+                    for song in &spotify.songs {
+                        let found = spotify::find_match(song, &tracks, |track| {
+                            storage
+                                .find_track_file_with_meta(track)
+                                .ok()
+                                .and_then(|(path, _, _)| audio_duration(&path))
+                        });
+                        match found {
+                            Some(track) => matched.push(track),
+                            None => missing.push(song),
+                        }
+                    }
+                    let playlist =
+                        storage.create_playlist(name.as_deref().unwrap_or(&spotify.name))?;
+                    storage.set_playlist_tracks(playlist.id, &matched)?;
+                    println!(
+                        "Created playlist {} '{}' with {} of {} songs",
+                        playlist.id,
+                        playlist.name,
+                        matched.len(),
+                        spotify.songs.len()
+                    );
+                    if !missing.is_empty() {
+                        println!("Not in the library:");
+                        for song in missing {
+                            println!("  - {song}");
+                        }
+                    }
+                }
+                PlaylistAction::Share {
+                    playlist,
+                    token,
+                    public,
+                    base_url,
+                } => {
+                    let mut playlist = find_playlist(&mut storage, &playlist)?;
+                    if token {
+                        playlist.share_token = Some(new_share_token());
+                    } else if public {
+                        playlist.share_token = None;
+                    }
+                    if token || public {
+                        storage.set_playlist_token(playlist.id, playlist.share_token.as_deref())?;
+                    }
+                    let base_url = base_url.unwrap_or_else(|| {
+                        public_base_url(&cfg.http, cfg.public_endpoint.as_ref())
+                    });
+                    println!("{}", playlist_url(&base_url, &playlist));
+                }
+            }
+        }
         Commands::Clean => {
-            let mut storage = Storage::new(cfg.storage).expect("Failed to initialize storage");
+            let mut storage = open_store(cfg.storage).expect("Failed to initialize storage");
             let report = storage.clean_dangling()?;
 
             if report.removed_tracks > 0 {
@@ -372,15 +1834,462 @@ pub fn run() -> anyhow::Result<()> {
             }
         }
         Commands::Scan { device } => {
-            let mut storage = Storage::new(cfg.storage)?;
+            let mut storage = open_store(cfg.storage)?;
+            storage.enable_track_index();
             let output = match device {
                 Some(d) => Output::Device(d),
                 None => Output::Default,
             };
             card_player::run_card_player(&mut storage, output).unwrap();
         }
-        Commands::Add { track_id, path } => {
+        Commands::Undo => {
+            let mut storage = Storage::new(cfg.storage)?;
+            match storage.undo_last()? {
+                Some(report) => {
+                    println!(
+                        "Undid #{} {} from {}",
+                        report.undone.id,
+                        report.undone.operation,
+                        report.undone.logged_at.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    println!(
+                        "Files removed: {}, files restored: {}, tracks removed: {}",
+                        report.removed_files, report.restored_files, report.removed_tracks
+                    );
+                }
+                None => println!("Nothing to undo :)"),
+            }
+        }
+
+        Commands::Quarantine { action } => {
+            let mut storage = open_store(cfg.storage)?;
+            match action {
+                QuarantineAction::List => {
+                    let files = storage.list_quarantined()?;
+                    if files.is_empty() {
+                        println!("No quarantined files :)");
+                    }
+                    for file in files {
+                        println!(
+                            "{} (track {})\n    since: {}\n    reason: {}",
+                            file.loc,
+                            file.track,
+                            file.since.format("%Y-%m-%d %H:%M:%S"),
+                            file.reason
+                        );
+                    }
+                }
+                QuarantineAction::Clear { path } => {
+                    let released = storage.clear_quarantine(path.as_deref())?;
+                    println!("Released {released} file(s) from quarantine");
+                }
+            }
+        }
+
+        Commands::List {
+            show_unavailable,
+            favourites,
+            sort,
+            artist,
+            year_from,
+            year_to,
+            format,
+        } => {
             let mut storage = Storage::new(cfg.storage)?;
+            if show_progress {
+                storage.set_progress(Box::new(TerminalProgress::default()));
+            }
+            let missing: HashSet<Location> = if show_unavailable {
+                storage
+                    .check_missing()?
+                    .into_values()
+                    .flatten()
+                    .map(|file| file.loc)
+                    .collect()
+            } else {
+                HashSet::new()
+            };
+            let favourites: Option<HashSet<TrackId>> = if favourites {
+                Some(storage.list_favourites()?.into_iter().collect())
+            } else {
+                None
+            };
+            let mut tracks = storage.list_tracks_matching(&TrackFilter {
+                artist,
+                year_from,
+                year_to,
+                format,
+            })?;
+            match sort {
+                ListOrder::Id => {}
+                // tracks of unknown age last
+                ListOrder::Added => tracks.sort_by_key(|track| std::cmp::Reverse(track.added)),
+                ListOrder::Name => sort_by_name(&mut tracks),
+            }
+            for track in tracks {
+                if favourites
+                    .as_ref()
+                    .is_some_and(|favourites| !favourites.contains(&track.id))
+                {
+                    continue;
+                }
+                let name = track_name(&track);
+                match track.added {
+                    Some(added) => {
+                        println!("{} {name} (added {})", track.id, added.format("%Y-%m-%d"))
+                    }
+                    None => println!("{} {name}", track.id),
+                }
+                for file in track.files {
+                    let loc = &file.loc;
+                    if missing.contains(loc) {
+                        let last_seen = describe_last_seen(&mut storage, loc)?;
+                        println!("  - {loc} (unavailable, last seen: {last_seen})");
+                    } else {
+                        println!("  - {loc} ({})", describe_file(&file));
+                    }
+                }
+            }
+        }
+
+        Commands::Favourite { track_id } => {
+            let mut storage = open_store(cfg.storage)?;
+            let favourite = !storage.is_favourite(track_id)?;
+            storage.set_favourite(track_id, favourite)?;
+            if favourite {
+                println!("Track {track_id} is now a favourite");
+            } else {
+                println!("Track {track_id} is no longer a favourite");
+            }
+        }
+
+        Commands::Snapshot { action } => {
+            let mut storage = Storage::new(cfg.storage)?;
+            match action {
+                SnapshotAction::Create { name } => {
+                    let snapshot = storage.create_snapshot(name.as_deref())?;
+                    println!(
+                        "Created snapshot {} with {} files",
+                        snapshot.id, snapshot.files
+                    );
+                }
+                SnapshotAction::List => {
+                    let snapshots = storage.list_snapshots()?;
+                    if snapshots.is_empty() {
+                        println!("No snapshots yet, create one with `localdeck snapshot create`");
+                    }
+                    for snapshot in snapshots {
+                        println!(
+                            "{} {} {} files {}",
+                            snapshot.id,
+                            snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+                            snapshot.files,
+                            snapshot.name.unwrap_or_default()
+                        );
+                    }
+                }
+                SnapshotAction::Diff { from, to } => {
+                    let from = parse_library_state(&mut storage, &from)?;
+                    let to = parse_library_state(&mut storage, to.as_deref().unwrap_or("current"))?;
+                    let diff = storage.diff_snapshots(from, to)?;
+                    if diff.added.is_empty() && diff.removed.is_empty() {
+                        println!("No differences :)");
+                    }
+                    for file in diff.added {
+                        println!("+ {} (track {})", file.location, file.track_id);
+                    }
+                    for file in diff.removed {
+                        println!("- {} (track {})", file.location, file.track_id);
+                    }
+                }
+            }
+        }
+
+        Commands::Bundle { action } => {
+            let library_roots = cfg.storage.library_source.roots.clone();
+            let mut storage = Storage::new(cfg.storage)?;
+            match action {
+                BundleAction::Export { out } => {
+                    let manifest = bundle::export(&mut storage, &out)?;
+                    println!(
+                        "Wrote {} files, {} artwork images and {} playlists to {}",
+                        manifest.files.len(),
+                        manifest.artwork.len(),
+                        manifest.playlists.len(),
+                        out.to_string_lossy()
+                    );
+                }
+                BundleAction::Import { archive, roots } => {
+                    let report = bundle::import(&mut storage, &library_roots, &archive, &roots)?;
+                    println!(
+                        "Imported {} tracks, moved {} files to the new roots",
+                        report.tracks, report.relocated_files
+                    );
+                    if !report.missing_files.is_empty() {
+                        println!(
+                            "{} files are not there yet, copy the music and check with `localdeck check missing`:",
+                            report.missing_files.len()
+                        );
+                        for loc in report.missing_files {
+                            println!("  - {loc}");
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Export { action } => {
+            let mut storage = Storage::new(cfg.storage)?;
+            match action {
+                ExportAction::UsbDb { label } => {
+                    let report = storage.export_usb_database(&label)?;
+                    println!(
+                        "Wrote {} tracks, {} files and {} playlists to {}",
+                        report.tracks,
+                        report.files,
+                        report.playlists,
+                        report.path.to_string_lossy()
+                    );
+                }
+            }
+        }
+
+        Commands::Import { action } => {
+            let mut storage = Storage::new(cfg.storage)?;
+            match action {
+                ImportAction::Beets { library, overwrite } => {
+                    let report = storage.import_beets(&library, overwrite)?;
+                    println!(
+                        "Beets items: {}\n  Updated tracks: {}\n  Not in the library: {}",
+                        report.items,
+                        report.updated.len(),
+                        report.unmatched.len()
+                    );
+                    if !report.missing_required.is_empty() {
+                        println!(
+                            "  Skipped, beets has no title or artist for them: {}",
+                            join_ids(&report.missing_required)
+                        );
+                    }
+                }
+            }
+        }
+
+        Commands::Sync {
+            from,
+            download,
+            prefer_remote,
+        } => {
+            if let Some(dir) = &download {
+                let dir = Location::from_path(std::path::absolute(dir)?);
+                let in_root = cfg
+                    .storage
+                    .library_source
+                    .roots
+                    .iter()
+                    .any(|root| root.enabled && dir.starts_with(&root.location));
+                if !in_root {
+                    bail!(
+                        "{dir} is not inside an enabled library root, downloads would not be added"
+                    );
+                }
+            }
+            let mut storage = open_store(cfg.storage)?;
+            if show_progress {
+                storage.set_progress(Box::new(TerminalProgress::default()));
+            }
+            let options = SyncOptions {
+                download_dir: download,
+                prefer_remote,
+            };
+            let report = SyncClient::new(&from).sync(&mut storage, &options)?;
+            println!(
+                "Matched {} tracks, {} missing here, downloaded {}",
+                report.matched_tracks, report.missing_tracks, report.downloaded
+            );
+            println!(
+                "Metadata: {} added, {} replaced. Playlists: {} created, {} replaced",
+                report.metadata_added,
+                report.metadata_replaced,
+                report.playlists_created,
+                report.playlists_replaced
+            );
+            if !report.conflicts.is_empty() {
+                println!("{} conflicts:", report.conflicts.len());
+                for conflict in report.conflicts {
+                    println!("  - {conflict}");
+                }
+            }
+        }
+
+        Commands::History {
+            action,
+            track,
+            limit,
+        } => {
+            let mut storage = Storage::new(cfg.storage)?;
+            match action {
+                None => {
+                    let log = storage.audit_log(track, limit)?;
+                    if log.is_empty() {
+                        println!("No changes recorded yet");
+                    }
+                    for entry in log {
+                        let tracks = entry
+                            .tracks
+                            .iter()
+                            .map(|t| t.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        println!(
+                            "#{} {} {} [tracks: {}] {}",
+                            entry.id,
+                            entry.logged_at.format("%Y-%m-%d %H:%M:%S"),
+                            entry.operation,
+                            tracks,
+                            entry.details
+                        );
+                    }
+                }
+                Some(HistoryAction::Scans { limit }) => {
+                    let scans = storage.list_scans(limit)?;
+                    if scans.is_empty() {
+                        println!("No scans recorded yet, run `localdeck update` first");
+                    }
+                    for scan in scans {
+                        println!(
+                            "#{} {} ({:.1}s): {} files seen, {} added, {} removed",
+                            scan.id,
+                            scan.started_at.format("%Y-%m-%d %H:%M:%S"),
+                            scan.duration.as_secs_f32(),
+                            scan.files_seen,
+                            scan.files_added,
+                            scan.files_removed
+                        );
+                        for root in scan.roots {
+                            if root.available {
+                                println!(
+                                    "  - {}: {} seen, {} added",
+                                    root.root, root.files_seen, root.files_added
+                                );
+                            } else {
+                                println!("  - {}: unavailable", root.root);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Report {
+            action: ReportAction::MissingMetadata,
+        } => {
+            let mut storage = open_store(cfg.storage)?;
+            let mut tracks = storage.list_tracks()?;
+            // tracks of unknown age last
+            tracks.sort_by_key(|track| std::cmp::Reverse(track.added));
+            let mut incomplete = 0;
+            for track in &tracks {
+                let missing = match storage.get_track_metadata(track.id)? {
+                    Some(meta) => meta.missing_fields().join(", "),
+                    None => "metadata".to_string(),
+                };
+                if missing.is_empty() {
+                    continue;
+                }
+                incomplete += 1;
+                let added = track.added.map_or("unknown".to_string(), |added| {
+                    added.format("%Y-%m-%d").to_string()
+                });
+                println!(
+                    "{} {} (added {added}): no {missing}",
+                    track.id,
+                    track_name(track)
+                );
+            }
+            if incomplete == 0 {
+                println!("All {} tracks have metadata and artwork", tracks.len());
+            } else {
+                println!("{incomplete} of {} tracks need metadata", tracks.len());
+            }
+        }
+
+        Commands::Stats {
+            action: StatsAction::Scans { days, log, limit },
+        } => {
+            let mut storage = open_store(cfg.storage)?;
+            let since = days.map(|days| Local::now() - chrono::Duration::days(days.into()));
+            let scans = storage.list_card_scans(since)?;
+            if scans.is_empty() {
+                println!(
+                    "No scans recorded yet, cards are logged when `localdeck serve` plays them"
+                );
+            }
+            if log {
+                for scan in scans.into_iter().take(limit) {
+                    let track = scan
+                        .track_id
+                        .map_or("-".to_string(), |track| track.to_string());
+                    println!(
+                        "{} card {} track {} {} [{}]",
+                        scan.scanned_at.format("%Y-%m-%d %H:%M:%S"),
+                        scan.card_id,
+                        track,
+                        scan.outcome,
+                        scan.user_agent.as_deref().unwrap_or("unknown browser")
+                    );
+                }
+            } else {
+                for stats in card_scans::summarize(&scans) {
+                    let track = match stats.track_id {
+                        Some(track) => match storage.get_track_metadata(track)? {
+                            Some(meta) => format!("{track} {} - {}", meta.artist, meta.title),
+                            None => track.to_string(),
+                        },
+                        None => "not in the library".to_string(),
+                    };
+                    println!(
+                        "card {} ({track}): {} scans, last {}. {} local, {} remote, {} not found, {} failed",
+                        stats.card_id,
+                        stats.scans,
+                        stats.last_scan.format("%Y-%m-%d %H:%M"),
+                        stats.local,
+                        stats.remote,
+                        stats.not_found,
+                        stats.failed
+                    );
+                }
+            }
+        }
+
+        Commands::Selftest { server } => {
+            let server = server.unwrap_or_else(|| {
+                let host = match cfg.http.bind_addr.as_str() {
+                    "0.0.0.0" => "127.0.0.1",
+                    addr => addr,
+                };
+                format!("http://{host}:{}", cfg.http.port)
+            });
+            println!("Testing server at {server}");
+
+            let results = selftest::run_selftest(&server);
+            let failed = results.iter().filter(|r| r.outcome.is_err()).count();
+            for result in results {
+                match result.outcome {
+                    Ok(msg) => println!("  PASS {}: {msg}", result.name),
+                    Err(msg) => println!("  FAIL {}: {msg}", result.name),
+                }
+            }
+            if failed > 0 {
+                bail!("{failed} check(s) failed");
+            }
+            println!("All checks passed :)");
+        }
+        Commands::Completion { .. } | Commands::Complete { .. } => {
+            unreachable!("completion is handled before loading the config")
+        }
+        Commands::Add { track_id, path } => {
+            let mut storage = open_store(cfg.storage)?;
             storage.add_file_to_track(track_id, &path)?;
             println!("Linked {} to track {}", path.to_string_lossy(), track_id);
         }
@@ -389,7 +2298,7 @@ pub fn run() -> anyhow::Result<()> {
             into,
             ignore_slave_meta,
         } => {
-            let mut storage = Storage::new(cfg.storage)?;
+            let mut storage = open_store(cfg.storage)?;
             storage.merge_tracks(into, slave_id, ignore_slave_meta)?;
             println!("Track {} successfully merged into {}", slave_id, into);
         }
@@ -397,6 +2306,66 @@ pub fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Merges tags of the tracks' files into their metadata and prints what changed.
+/// With the `ask` policy, conflicting edits are offered to be replaced track by track
+fn import_tags(
+    storage: &mut dyn LibraryStore,
+    tracks: &[TrackId],
+    policy: MetadataPolicy,
+) -> anyhow::Result<()> {
+    let report = storage.import_tags(tracks, policy)?;
+    println!(
+        "Metadata from tags: {} track(s) updated, {} without usable tags",
+        report.updated.len(),
+        report.skipped.len()
+    );
+    if report.conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let mut by_track: Vec<(TrackId, Vec<&TagConflict>)> = Vec::new();
+    for conflict in &report.conflicts {
+        match by_track.last_mut() {
+            Some((track, conflicts)) if *track == conflict.track_id => conflicts.push(conflict),
+            _ => by_track.push((conflict.track_id, vec![conflict])),
+        }
+    }
+    let interactive = std::io::stdin().is_terminal();
+    println!("Tags differing from edited metadata:");
+    for (track_id, conflicts) in by_track {
+        println!("  * track {track_id}:");
+        for conflict in conflicts {
+            println!(
+                "    - {}: '{}', tags say '{}'",
+                conflict.field, conflict.stored, conflict.tagged
+            );
+        }
+        if !interactive {
+            continue;
+        }
+        print!("    Take the values of the tags? [y/N] ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim().eq_ignore_ascii_case("y") {
+            storage.import_tags(&[track_id], MetadataPolicy::TagsWin)?;
+        }
+    }
+    if !interactive {
+        println!(
+            "Run `localdeck meta import-tags <TRACK_ID>` with `tag_policy = \"tags-win\"` to take the tags"
+        );
+    }
+    Ok(())
+}
+
+fn join_ids(ids: &[TrackId]) -> String {
+    ids.iter()
+        .map(TrackId::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub fn pretty_metadata(m: TrackMetadata) -> String {
     let mut lines = Vec::new();
 
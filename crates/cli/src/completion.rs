@@ -0,0 +1,217 @@
+//! Shell completion looking into the library, so `meta get <TAB>` offers track ids with
+//! their titles and `playlist add <TAB>` the names of playlists.
+//!
+//! The scripts of `localdeck completion <shell>` pass the words typed so far to the hidden
+//! `localdeck __complete`, which follows the clap definition of the command line to tell
+//! what the last word is. Candidates are printed one per line, with a hint after a tab.
+
+use clap::{Arg, Command, CommandFactory, ValueEnum};
+use localdeck_storage::store::LibraryStore;
+
+use crate::cli::Cli;
+
+/// Track ids offered at most, typing more digits narrows them down
+const MAX_TRACKS: usize = 100;
+
+/// Arguments taking track ids
+const TRACK_ARGS: &[&str] = &["track_id", "track_ids", "slave_id", "MASTER_ID", "track"];
+/// Arguments taking a playlist name or id
+const PLAYLIST_ARGS: &[&str] = &["playlist"];
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Fish,
+}
+
+impl Shell {
+    /// Script registering the completion, e.g. sourced from `~/.bashrc`
+    pub fn script(self) -> &'static str {
+        match self {
+            Shell::Bash => BASH_SCRIPT,
+            Shell::Fish => FISH_SCRIPT,
+        }
+    }
+}
+
+// bash shows no hints, they are cut off
+const BASH_SCRIPT: &str = r#"_localdeck() {
+    local IFS=$'\n'
+    COMPREPLY=($(localdeck __complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null | cut -f1))
+}
+complete -o default -F _localdeck localdeck
+"#;
+
+const FISH_SCRIPT: &str = r#"complete -c localdeck -a '(localdeck __complete (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#;
+
+/// What the last word typed is
+#[derive(Debug, PartialEq, Eq)]
+enum Completing {
+    /// a subcommand or flag, one of these
+    Names(Vec<String>),
+    Tracks,
+    Playlists,
+    /// anything else, left to the shell
+    Other,
+}
+
+/// `words` are those after `localdeck`, the last one being completed
+fn completing(root: &Command, words: &[String]) -> Completing {
+    let Some((current, typed)) = words.split_last() else {
+        return Completing::Other;
+    };
+    let mut cmd = root;
+    let mut positional = 0;
+    let mut value_of: Option<&Arg> = None;
+    for word in typed {
+        if value_of.take().is_some() {
+            continue;
+        }
+        if let Some(sub) = cmd.find_subcommand(word) {
+            cmd = sub;
+            positional = 0;
+        } else if let Some(long) = word.strip_prefix("--") {
+            // `--name=value` carries its value
+            value_of = cmd
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long))
+                .filter(|arg| arg.get_action().takes_values());
+        } else if let Some(short) = word.strip_prefix('-').filter(|short| !short.is_empty()) {
+            let mut chars = short.chars();
+            if let (Some(short), None) = (chars.next(), chars.next()) {
+                value_of = cmd
+                    .get_arguments()
+                    .find(|arg| arg.get_short() == Some(short))
+                    .filter(|arg| arg.get_action().takes_values());
+            }
+        } else {
+            positional += 1;
+        }
+    }
+
+    let arg = match value_of {
+        Some(arg) => arg,
+        None if current.starts_with('-') => {
+            return Completing::Names(
+                cmd.get_arguments()
+                    .filter(|arg| !arg.is_hide_set())
+                    .filter_map(|arg| arg.get_long())
+                    .map(|long| format!("--{long}"))
+                    .collect(),
+            );
+        }
+        None if cmd.has_subcommands() => {
+            return Completing::Names(
+                cmd.get_subcommands()
+                    .filter(|sub| !sub.is_hide_set())
+                    .map(|sub| sub.get_name().to_string())
+                    .collect(),
+            );
+        }
+        None => {
+            let positionals: Vec<&Arg> = cmd.get_positionals().collect();
+            // the last positional may take many values, like the track ids of `playlist add`
+            let repeated = positionals.last().copied().filter(|arg| {
+                arg.get_num_args()
+                    .is_some_and(|range| range.max_values() > 1)
+            });
+            match positionals.get(positional).copied().or(repeated) {
+                Some(arg) => arg,
+                None => return Completing::Other,
+            }
+        }
+    };
+    let id = arg.get_id().as_str();
+    if TRACK_ARGS.contains(&id) {
+        Completing::Tracks
+    } else if PLAYLIST_ARGS.contains(&id) {
+        Completing::Playlists
+    } else {
+        Completing::Other
+    }
+}
+
+/// Candidates for the last of `words`, each as `value` or `value<TAB>hint`.
+/// The library is only opened for track ids and playlist names
+pub fn candidates(
+    words: &[String],
+    open: impl FnOnce() -> anyhow::Result<Box<dyn LibraryStore>>,
+) -> anyhow::Result<Vec<String>> {
+    let current = words.last().map(String::as_str).unwrap_or_default();
+    let mut root = Cli::command();
+    // propagates global flags like `--config` to the subcommands
+    root.build();
+    Ok(match completing(&root, words) {
+        Completing::Names(names) => names
+            .into_iter()
+            .filter(|name| name.starts_with(current))
+            .collect(),
+        Completing::Tracks => {
+            let tracks = open()?.list_tracks()?;
+            tracks
+                .iter()
+                .filter(|track| track.id.to_string().starts_with(current))
+                .take(MAX_TRACKS)
+                .map(|track| match &track.meta {
+                    Some(meta) => format!("{}\t{} - {}", track.id, meta.artist, meta.title),
+                    None => track.id.to_string(),
+                })
+                .collect()
+        }
+        Completing::Playlists => {
+            let mut storage = open()?;
+            storage
+                .list_playlists()?
+                .into_iter()
+                .map(|playlist| playlist.name)
+                .filter(|name| name.starts_with(current))
+                .collect()
+        }
+        Completing::Other => vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completing_words(words: &[&str]) -> Completing {
+        let mut root = Cli::command();
+        root.build();
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        completing(&root, &words)
+    }
+
+    #[test]
+    fn last_word_is_told_from_the_command_line() {
+        let Completing::Names(names) = completing_words(&["pl"]) else {
+            panic!("subcommands are completed");
+        };
+        assert!(names.contains(&"playlist".to_string()));
+        assert!(!names.contains(&"__complete".to_string()));
+
+        assert_eq!(completing_words(&["meta", "get", ""]), Completing::Tracks);
+        assert_eq!(
+            completing_words(&["playlist", "add", "Mix"]),
+            Completing::Playlists
+        );
+        assert_eq!(
+            completing_words(&["playlist", "add", "Mix", "12", "4"]),
+            Completing::Tracks
+        );
+        assert_eq!(
+            completing_words(&["merge", "3", "-i", ""]),
+            Completing::Tracks
+        );
+        assert_eq!(
+            completing_words(&["-c", "deck.toml", "url", "--playlist", ""]),
+            Completing::Playlists
+        );
+        assert_eq!(completing_words(&["add", "3", ""]), Completing::Other);
+        let Completing::Names(flags) = completing_words(&["url", "--"]) else {
+            panic!("flags are completed");
+        };
+        assert!(flags.contains(&"--short".to_string()));
+    }
+}
@@ -1,21 +1,356 @@
-use anyhow::Context;
+use anyhow::{Context, anyhow, bail};
+use log::LevelFilter;
 use serde::Deserialize;
-use std::path::Path;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
-use localdeck_http::HttpConfig;
-use localdeck_storage::config::Config as DBConfig;
+use localdeck_http::{HttpConfig, auth::BasicAuth, stream_limit::StreamLimits, theme::ThemeConfig};
+use localdeck_storage::{
+    config::{Config as DBConfig, Database, LibrarySource},
+    file_hash::HashKind,
+    location::Location,
+};
+
+use crate::{
+    artwork::ArtworkConfig, backup::BackupConfig, ddns::DdnsConfig, jukebox::JukeboxConfig,
+    mdns::MdnsConfig, public_endpoint::PublicEndpoint, rescan::RescanConfig,
+};
+
+/// Value of LOCALDECK_CONFIG that makes localdeck read the whole config from environment variables
+pub const ENV_CONFIG: &str = "env";
+
+/// Where the config is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// TOML file
+    File(PathBuf),
+    /// `LOCALDECK_*` environment variables, see [`Config::from_env`]
+    Env,
+}
+
+/// DB path value selecting an in-memory database
+const IN_MEMORY_DB: &str = ":memory:";
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 8080;
+
+/// Layout of config files this build reads, the `version` at the top of the file.
+/// Files without it are taken for version 1, which was the layout before it was checked
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Upgrade of a config table by one version
+type Migration = fn(&mut toml::Table) -> anyhow::Result<()>;
+
+/// The migration at index `i` turns version `i + 1` into `i + 2`.
+/// A new layout bumps [`CONFIG_VERSION`] and adds its upgrade here
+const MIGRATIONS: &[Migration] = &[];
+
+#[derive(Deserialize)]
+struct Versioned {
+    version: Option<i64>,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub storage: DBConfig,
     pub http: HttpConfig,
+    /// scheduled backups while serving, off unless configured
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// scheduled `update` while serving, off unless configured
+    #[serde(default)]
+    pub rescan: Option<RescanConfig>,
+    /// advertising the server on the LAN, on unless disabled
+    #[serde(default)]
+    pub mdns: MdnsConfig,
+    /// the address printed on cards, checked when serving. A running `serve` keeps the one it
+    /// started with, reloads do not take it over
+    #[serde(default)]
+    pub public_endpoint: Option<PublicEndpoint>,
+    /// keeping a hostname pointed at the public address while serving
+    #[serde(default)]
+    pub ddns: Option<DdnsConfig>,
+    /// outputs of `serve --jukebox`
+    #[serde(default)]
+    pub jukebox: JukeboxConfig,
+    /// where `artwork report --fetch` looks for covers
+    #[serde(default)]
+    pub artwork: ArtworkConfig,
+    /// level `serve` logs at instead of the one of `RUST_LOG`, taken over by reloads
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+}
+
+/// `off`, `error`, `warn`, `info`, `debug` or `trace`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct LogLevel(pub LevelFilter);
+
+impl TryFrom<String> for LogLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+            .map(LogLevel)
+            .map_err(|_| anyhow!("unknown log level '{s}'"))
+    }
 }
 
 impl Config {
     /// load the config file. first tries the env var LOCALDECK_CONFIG, then the provided path
     pub fn load(path: &Path) -> anyhow::Result<Config> {
         let contents = std::fs::read_to_string(path).expect("Failed to read user config");
-        toml::from_str(&contents).with_context(|| "Failed to parse config TOML")
+        Self::parse(&contents)
+    }
+
+    /// Parses a config file, upgrading it first if it has an older `version`
+    fn parse(contents: &str) -> anyhow::Result<Config> {
+        let Versioned { version } =
+            toml::from_str(contents).with_context(|| "Failed to parse config TOML")?;
+        let version = version.unwrap_or(1);
+        if version == CONFIG_VERSION {
+            // straight from the text, so errors point at their line
+            return toml::from_str(contents).with_context(|| "Failed to parse config TOML");
+        }
+        if version > CONFIG_VERSION {
+            bail!(
+                "config version {version} is newer than this localdeck reads ({CONFIG_VERSION}), \
+                 update localdeck"
+            );
+        }
+        if version < 1 {
+            bail!("unknown config version {version}, versions start at 1");
+        }
+        let mut table: toml::Table =
+            toml::from_str(contents).with_context(|| "Failed to parse config TOML")?;
+        for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+            migrate(&mut table)
+                .with_context(|| format!("Failed to upgrade config from version {}", from + 1))?;
+        }
+        table.insert("version".to_string(), CONFIG_VERSION.into());
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Failed to parse config upgraded from version {version}"))
+    }
+
+    pub fn load_from(source: &ConfigSource) -> anyhow::Result<Config> {
+        match source {
+            ConfigSource::File(path) => Self::load(path),
+            ConfigSource::Env => Self::from_env(),
+        }
+    }
+
+    /// Builds the config from environment variables, so containers need no mounted config file.
+    ///
+    /// - `LOCALDECK_DB_PATH` (required): database file, or `:memory:` for an in-memory database,
+    ///   or a `postgres://` URL if built with the `postgres` feature
+    /// - `LOCALDECK_DB_ENCRYPTION_KEY`: passphrase of an encrypted database file, needs the `sqlcipher` feature
+    /// - `LOCALDECK_ROOTS` (required): library directories, separated like `PATH`
+    /// - `LOCALDECK_IGNORED_DIRS`: directories to skip, separated like `PATH`
+    /// - `LOCALDECK_FOLLOW_SYMLINKS`: `true`/`false`, defaults to `false`
+    /// - `LOCALDECK_SKIP_HIDDEN`: `true`/`false`, defaults to `false`
+    /// - `LOCALDECK_EXTENSIONS`: comma separated music file extensions, e.g. `mp3,flac,opus`
+    /// - `LOCALDECK_MAX_FILE_SIZE`: files above this many bytes are not indexed
+    /// - `LOCALDECK_HASH_KIND`: `full`, `quick` or `audio`, how new files are hashed, defaults to `full`
+    /// - `LOCALDECK_TAG_POLICY`: `db-wins`, `tags-win`, `newest-wins` or `ask`, reads metadata
+    ///   from the tags of new files, see `tag_policy` of the library source
+    /// - `LOCALDECK_TRANSLITERATE_SEARCH`: `true`/`false`, whether searches match Cyrillic text
+    ///   by its Latin transliteration and back, defaults to `false`
+    /// - `LOCALDECK_RESCAN_MINUTES`: minutes between scans for new files while serving, off if unset
+    /// - `LOCALDECK_LOG_LEVEL`: `off`, `error`, `warn`, `info`, `debug` or `trace`, the level `serve`
+    ///   logs at, defaults to the one of `RUST_LOG`
+    /// - `LOCALDECK_BIND_ADDR`: defaults to `0.0.0.0`
+    /// - `LOCALDECK_PORT`: defaults to `8080`
+    /// - `LOCALDECK_LANGUAGE`: language of guest pages if the browser accepts none of the supported ones, e.g. `ru`
+    /// - `LOCALDECK_AUTH_USER`, `LOCALDECK_AUTH_PASSWORD`: require these Basic auth credentials,
+    ///   except on `/play`, `/play-list`, `/random` and `/s/...`
+    /// - `LOCALDECK_AUTH_PROTECT_PLAY`: `true`/`false`, whether card routes need credentials too, defaults to `false`
+    /// - `LOCALDECK_PARTY_QUEUE`: `true`/`false`, whether guests may queue tracks for `/queue/player`, defaults to `false`
+    /// - `LOCALDECK_MAX_STREAMS`, `LOCALDECK_MAX_STREAMS_PER_IP`: tracks streamed at once in total
+    ///   and to one address, unlimited if unset
+    /// - `LOCALDECK_STREAM_BYTES_PER_SEC`: rate each stream is sent at at most, unlimited if unset
+    /// - `LOCALDECK_STATIC_DIR`: directory served under `/static/`
+    /// - `LOCALDECK_MDNS`: `true`/`false`, whether to advertise the server over mDNS, defaults to `true`
+    /// - `LOCALDECK_MDNS_NAME`: name advertised over mDNS, defaults to the title
+    /// - `LOCALDECK_PUBLIC_URL`: address guests reach the server at, checked when serving
+    /// - `LOCALDECK_PORT_MAPPING`: `true`/`false`, whether to ask the router to forward the port, defaults to `false`
+    /// - `LOCALDECK_TITLE`, `LOCALDECK_LOGO`, `LOCALDECK_ACCENT_COLOR`, `LOCALDECK_THEME_DIR`: branding of guest pages
+    pub fn from_env() -> anyhow::Result<Config> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Config> {
+        let required = |name: &str| var(name).ok_or(anyhow!("{name} is not set"));
+        let paths = |name: &str| -> Vec<PathBuf> {
+            var(name)
+                .map(|v| {
+                    env::split_paths(&v)
+                        .filter(|p| !p.as_os_str().is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let db_path = required("LOCALDECK_DB_PATH")?;
+        let database = match db_path.as_str() {
+            IN_MEMORY_DB => Database::InMemory,
+            #[cfg(feature = "postgres")]
+            url if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+                Database::Postgres {
+                    url: url.to_string(),
+                }
+            }
+            _ => Database::OnDisk {
+                location: Location::from_path(db_path),
+                encryption_key: var("LOCALDECK_DB_ENCRYPTION_KEY"),
+            },
+        };
+
+        required("LOCALDECK_ROOTS")?;
+        let roots = paths("LOCALDECK_ROOTS");
+        if roots.is_empty() {
+            bail!("LOCALDECK_ROOTS does not contain any directory");
+        }
+
+        let flag = |name: &str| -> anyhow::Result<bool> {
+            match var(name).as_deref() {
+                None | Some("") | Some("0") | Some("false") | Some("no") => Ok(false),
+                Some("1") | Some("true") | Some("yes") => Ok(true),
+                Some(other) => bail!("{name} must be true or false, got '{other}'"),
+            }
+        };
+        let follow_symlinks = flag("LOCALDECK_FOLLOW_SYMLINKS")?;
+        let skip_hidden = flag("LOCALDECK_SKIP_HIDDEN")?;
+        let extensions = match var("LOCALDECK_EXTENSIONS") {
+            Some(list) => list
+                .split(',')
+                .map(|ext| ext.trim().to_string())
+                .filter(|ext| !ext.is_empty())
+                .collect(),
+            None => LibrarySource::default().extensions,
+        };
+
+        let max_file_size = match var("LOCALDECK_MAX_FILE_SIZE") {
+            Some(size) => Some(size.parse().with_context(|| {
+                format!("LOCALDECK_MAX_FILE_SIZE is not a number of bytes: '{size}'")
+            })?),
+            None => None,
+        };
+
+        let hash_kind = match var("LOCALDECK_HASH_KIND") {
+            Some(kind) => kind
+                .parse()
+                .map_err(|e| anyhow!("LOCALDECK_HASH_KIND: {e}"))?,
+            None => HashKind::default(),
+        };
+        let tag_policy = var("LOCALDECK_TAG_POLICY")
+            .map(|policy| policy.parse())
+            .transpose()
+            .map_err(|e| anyhow!("LOCALDECK_TAG_POLICY: {e}"))?;
+
+        let basic_auth = match (var("LOCALDECK_AUTH_USER"), var("LOCALDECK_AUTH_PASSWORD")) {
+            (Some(user), Some(password)) => Some(BasicAuth { user, password }),
+            (None, None) => None,
+            _ => bail!("LOCALDECK_AUTH_USER and LOCALDECK_AUTH_PASSWORD must be set together"),
+        };
+
+        let count = |name: &str| -> anyhow::Result<Option<usize>> {
+            var(name)
+                .map(|count| {
+                    count
+                        .parse()
+                        .with_context(|| format!("{name} is not a number: '{count}'"))
+                })
+                .transpose()
+        };
+        let streams = StreamLimits {
+            max: count("LOCALDECK_MAX_STREAMS")?,
+            max_per_ip: count("LOCALDECK_MAX_STREAMS_PER_IP")?,
+            max_bytes_per_sec: var("LOCALDECK_STREAM_BYTES_PER_SEC")
+                .map(|rate| {
+                    rate.parse().with_context(|| {
+                        format!("LOCALDECK_STREAM_BYTES_PER_SEC is not a number of bytes: '{rate}'")
+                    })
+                })
+                .transpose()?,
+        };
+
+        let port = match var("LOCALDECK_PORT") {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("LOCALDECK_PORT is not a valid port: '{port}'"))?,
+            None => DEFAULT_PORT,
+        };
+
+        Ok(Config {
+            storage: DBConfig {
+                database,
+                library_source: LibrarySource {
+                    roots: roots
+                        .into_iter()
+                        .map(|root| Location::from_path(root).into())
+                        .collect(),
+                    follow_symlinks,
+                    ignored_dirs: paths("LOCALDECK_IGNORED_DIRS"),
+                    skip_hidden,
+                    extensions,
+                    max_file_size,
+                    hash_kind,
+                    tag_policy,
+                    transliterate_search: flag("LOCALDECK_TRANSLITERATE_SEARCH")?,
+                },
+            },
+            http: HttpConfig {
+                bind_addr: var("LOCALDECK_BIND_ADDR").unwrap_or(DEFAULT_BIND_ADDR.to_string()),
+                port,
+                language: var("LOCALDECK_LANGUAGE"),
+                theme: ThemeConfig {
+                    title: var("LOCALDECK_TITLE"),
+                    logo: var("LOCALDECK_LOGO").map(PathBuf::from),
+                    accent_color: var("LOCALDECK_ACCENT_COLOR"),
+                    dir: var("LOCALDECK_THEME_DIR").map(PathBuf::from),
+                },
+                basic_auth,
+                tokens: Vec::new(),
+                public_card_routes: !flag("LOCALDECK_AUTH_PROTECT_PLAY")?,
+                ip_rules: Vec::new(),
+                party_queue: flag("LOCALDECK_PARTY_QUEUE")?,
+                schedule: Vec::new(),
+                static_dir: var("LOCALDECK_STATIC_DIR").map(PathBuf::from),
+                streams,
+            },
+            backup: None,
+            rescan: match var("LOCALDECK_RESCAN_MINUTES") {
+                Some(minutes) => Some(RescanConfig {
+                    interval_minutes: Some(minutes.parse().with_context(|| {
+                        format!("LOCALDECK_RESCAN_MINUTES is not a number: '{minutes}'")
+                    })?),
+                    cron: None,
+                }),
+                None => None,
+            },
+            mdns: MdnsConfig {
+                enabled: var("LOCALDECK_MDNS").is_none() || flag("LOCALDECK_MDNS")?,
+                name: var("LOCALDECK_MDNS_NAME"),
+            },
+            public_endpoint: match var("LOCALDECK_PUBLIC_URL") {
+                Some(base_url) => Some(PublicEndpoint {
+                    base_url,
+                    port_mapping: flag("LOCALDECK_PORT_MAPPING")?,
+                    external_port: None,
+                    other_base_urls: Default::default(),
+                }),
+                None => None,
+            },
+            ddns: None,
+            jukebox: JukeboxConfig::default(),
+            artwork: ArtworkConfig::default(),
+            log_level: var("LOCALDECK_LOG_LEVEL")
+                .map(LogLevel::try_from)
+                .transpose()
+                .map_err(|e| anyhow!("LOCALDECK_LOG_LEVEL: {e}"))?,
+        })
     }
 }
 
@@ -53,4 +388,116 @@ port = 8080
         assert_eq!(cfg.http.port, 8080);
         Ok(())
     }
+
+    #[test]
+    fn test_config_version() {
+        assert_eq!(MIGRATIONS.len() as i64, CONFIG_VERSION - 1);
+
+        let config = |version: &str| {
+            Config::parse(&format!(
+                r#"{version}
+[storage.database]
+type = "InMemory"
+
+[storage.library_source]
+roots = []
+follow_symlinks = false
+
+[http]
+bind_addr = "127.0.0.1"
+port = 8080
+"#
+            ))
+        };
+        assert!(config("").is_ok());
+        assert!(config("version = 1").is_ok());
+        let newer = config("version = 2").unwrap_err().to_string();
+        assert!(newer.contains("newer than this localdeck"), "{newer}");
+        assert!(config("version = 0").is_err());
+    }
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| {
+            pairs
+                .iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_config_from_env_vars() -> anyhow::Result<()> {
+        let roots = env::join_paths(["/music", "/more-music"])?;
+        let roots = roots.to_string_lossy();
+        let cfg = Config::from_vars(vars(&[
+            ("LOCALDECK_DB_PATH", "/data/localdeck.db"),
+            ("LOCALDECK_ROOTS", &roots),
+            ("LOCALDECK_FOLLOW_SYMLINKS", "true"),
+            ("LOCALDECK_PORT", "9000"),
+            ("LOCALDECK_LANGUAGE", "ru"),
+            ("LOCALDECK_TITLE", "Sasha's Deck"),
+            ("LOCALDECK_PARTY_QUEUE", "true"),
+        ]))?;
+
+        assert_eq!(
+            cfg.storage.database,
+            Database::OnDisk {
+                location: Location::from_path("/data/localdeck.db"),
+                encryption_key: None,
+            }
+        );
+        assert_eq!(
+            cfg.storage.library_source.roots,
+            vec![
+                Location::from_path("/music").into(),
+                Location::from_path("/more-music").into()
+            ]
+        );
+        assert!(cfg.storage.library_source.follow_symlinks);
+        assert_eq!(
+            cfg.storage.library_source.extensions,
+            LibrarySource::default().extensions
+        );
+        assert_eq!(cfg.http.bind_addr, "0.0.0.0");
+        assert_eq!(cfg.http.port, 9000);
+        assert_eq!(cfg.http.language.as_deref(), Some("ru"));
+        assert_eq!(cfg.http.theme.title.as_deref(), Some("Sasha's Deck"));
+        assert_eq!(cfg.http.theme.logo, None);
+        assert!(cfg.http.basic_auth.is_none());
+        assert!(cfg.http.party_queue);
+        assert!(cfg.mdns.enabled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_env_vars_basic_auth() -> anyhow::Result<()> {
+        let base = [
+            ("LOCALDECK_DB_PATH", ":memory:"),
+            ("LOCALDECK_ROOTS", "/music"),
+        ];
+        let with = |extra: &[(&'static str, &'static str)]| {
+            let pairs = [&base[..], extra].concat();
+            Config::from_vars(vars(&pairs))
+        };
+
+        let cfg = with(&[
+            ("LOCALDECK_AUTH_USER", "sasha"),
+            ("LOCALDECK_AUTH_PASSWORD", "secret"),
+        ])?;
+        let auth = cfg.http.basic_auth.expect("credentials are configured");
+        assert_eq!(
+            (auth.user.as_str(), auth.password.as_str()),
+            ("sasha", "secret")
+        );
+        assert!(cfg.http.public_card_routes);
+
+        assert!(with(&[("LOCALDECK_AUTH_USER", "sasha")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_env_vars_requires_roots() {
+        let cfg = Config::from_vars(vars(&[("LOCALDECK_DB_PATH", ":memory:")]));
+        assert!(cfg.is_err());
+    }
 }
@@ -0,0 +1,338 @@
+//! Dynamic DNS, keeping a hostname pointed at the deck's public address while serving.
+//!
+//! Home connections change their address every now and then. QR codes printed with a
+//! domain keep working if the domain follows, so `serve` periodically looks up its public
+//! address and updates the record at DuckDNS or Cloudflare whenever it changed.
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use serde_json::json;
+use std::{fmt, net::IpAddr, thread, time::Duration};
+use ureq::Agent;
+
+const DUCKDNS_URL: &str = "https://www.duckdns.org";
+const DUCKDNS_SUFFIX: &str = ".duckdns.org";
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DdnsConfig {
+    /// minutes between checks of the public address
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+    /// service answering with the caller's address as plain text
+    #[serde(default = "default_ip_lookup_url")]
+    pub ip_lookup_url: String,
+    pub provider: DdnsProvider,
+}
+
+fn default_interval_minutes() -> u64 {
+    5
+}
+
+fn default_ip_lookup_url() -> String {
+    "https://api.ipify.org".to_string()
+}
+
+/// Where the hostname is registered
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum DdnsProvider {
+    /// `domain` is the subdomain of duckdns.org, e.g. `mydeck` for mydeck.duckdns.org
+    DuckDns { domain: String, token: String },
+    /// A or AAAA record `record`, e.g. `deck.example.com`, in a zone managed by Cloudflare.
+    /// The API token needs the `Zone.DNS` edit permission.
+    Cloudflare {
+        zone_id: String,
+        record: String,
+        api_token: String,
+    },
+}
+
+impl DdnsProvider {
+    fn update(&self, agent: &Agent, ip: IpAddr) -> anyhow::Result<()> {
+        match self {
+            DdnsProvider::DuckDns { domain, token } => {
+                update_duckdns(agent, DUCKDNS_URL, domain, token, ip)
+            }
+            DdnsProvider::Cloudflare {
+                zone_id,
+                record,
+                api_token,
+            } => update_cloudflare(agent, CLOUDFLARE_API, zone_id, record, api_token, ip),
+        }
+    }
+}
+
+impl fmt::Display for DdnsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DdnsProvider::DuckDns { domain, .. } => {
+                write!(f, "{}{DUCKDNS_SUFFIX}", duckdns_subdomain(domain))
+            }
+            DdnsProvider::Cloudflare { record, .. } => write!(f, "{record} at Cloudflare"),
+        }
+    }
+}
+
+/// Starts a thread pointing the configured hostname at the current public address
+/// every `interval_minutes`. Failures are logged and retried at the next check.
+pub fn spawn_updater(config: DdnsConfig) -> anyhow::Result<()> {
+    let interval = Duration::from_secs(config.interval_minutes.max(1) * 60);
+    let agent = agent();
+    thread::Builder::new()
+        .name("ddns".to_string())
+        .spawn(move || {
+            let mut registered = None;
+            loop {
+                match public_ip(&agent, &config.ip_lookup_url) {
+                    Ok(ip) if registered != Some(ip) => match config.provider.update(&agent, ip) {
+                        Ok(()) => {
+                            log::info!("pointed {} at {ip}", config.provider);
+                            registered = Some(ip);
+                        }
+                        Err(e) => log::error!("updating {} failed: {e:#}", config.provider),
+                    },
+                    Ok(_) => {}
+                    Err(e) => log::warn!("could not look up the public address: {e:#}"),
+                }
+                thread::sleep(interval);
+            }
+        })?;
+    Ok(())
+}
+
+fn agent() -> Agent {
+    Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into()
+}
+
+fn public_ip(agent: &Agent, lookup_url: &str) -> anyhow::Result<IpAddr> {
+    let answer = agent
+        .get(lookup_url)
+        .call()
+        .with_context(|| format!("failed to ask {lookup_url}"))?
+        .body_mut()
+        .read_to_string()?;
+    answer
+        .trim()
+        .parse()
+        .with_context(|| format!("{lookup_url} answered '{}', not an address", answer.trim()))
+}
+
+fn duckdns_subdomain(domain: &str) -> &str {
+    domain.strip_suffix(DUCKDNS_SUFFIX).unwrap_or(domain)
+}
+
+fn update_duckdns(
+    agent: &Agent,
+    base_url: &str,
+    domain: &str,
+    token: &str,
+    ip: IpAddr,
+) -> anyhow::Result<()> {
+    let ip_param = if ip.is_ipv4() { "ip" } else { "ipv6" };
+    let answer = agent
+        .get(format!("{base_url}/update"))
+        .query("domains", duckdns_subdomain(domain))
+        .query("token", token)
+        .query(ip_param, ip.to_string())
+        .call()?
+        .body_mut()
+        .read_to_string()?;
+    // DuckDNS says no more than OK or KO
+    match answer.trim() {
+        "OK" => Ok(()),
+        _ => bail!("DuckDNS refused the update, check the domain and token"),
+    }
+}
+
+#[derive(Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<CloudflareError>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct DnsRecord {
+    id: String,
+    content: String,
+}
+
+impl<T> CloudflareResponse<T> {
+    fn into_result(self) -> anyhow::Result<Option<T>> {
+        if !self.success {
+            let messages: Vec<_> = self.errors.into_iter().map(|e| e.message).collect();
+            bail!("Cloudflare API error: {}", messages.join("; "));
+        }
+        Ok(self.result)
+    }
+}
+
+fn update_cloudflare(
+    agent: &Agent,
+    api: &str,
+    zone_id: &str,
+    record: &str,
+    api_token: &str,
+    ip: IpAddr,
+) -> anyhow::Result<()> {
+    let kind = if ip.is_ipv4() { "A" } else { "AAAA" };
+    let records_url = format!("{api}/zones/{zone_id}/dns_records");
+    let authorization = format!("Bearer {api_token}");
+
+    let existing = agent
+        .get(&records_url)
+        .header("Authorization", &authorization)
+        .query("type", kind)
+        .query("name", record)
+        .call()?
+        .body_mut()
+        .read_json::<CloudflareResponse<Vec<DnsRecord>>>()?
+        .into_result()
+        .with_context(|| format!("failed to look up {record}"))?
+        .unwrap_or_default();
+
+    let response = match existing.first() {
+        Some(existing) if existing.content == ip.to_string() => return Ok(()),
+        Some(existing) => agent
+            .patch(format!("{records_url}/{}", existing.id))
+            .header("Authorization", &authorization)
+            .send_json(json!({ "content": ip.to_string() }))?,
+        None => agent
+            .post(&records_url)
+            .header("Authorization", &authorization)
+            .send_json(json!({
+                "type": kind,
+                "name": record,
+                "content": ip.to_string(),
+                // automatic, and served directly rather than through Cloudflare's proxy
+                "ttl": 1,
+                "proxied": false,
+            }))?,
+    };
+    response
+        .into_body()
+        .read_json::<CloudflareResponse<serde_json::Value>>()?
+        .into_result()
+        .with_context(|| format!("failed to update {record}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    /// Serves `answers` in order, returning the request lines and bodies it received
+    fn serve(answers: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&requests);
+        thread::spawn(move || {
+            for (stream, answer) in listener.incoming().zip(answers) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                seen.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{answer}",
+                    answer.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[test]
+    fn duckdns_update() -> anyhow::Result<()> {
+        let (base_url, requests) = serve(vec!["OK", "KO"]);
+        let ip = "203.0.113.7".parse()?;
+
+        update_duckdns(&agent(), &base_url, "mydeck.duckdns.org", "t0k3n", ip)?;
+        assert!(update_duckdns(&agent(), &base_url, "mydeck", "wrong", ip).is_err());
+
+        let requests = requests.lock().unwrap();
+        assert!(
+            requests[0].starts_with("GET /update?domains=mydeck&token=t0k3n&ip=203.0.113.7 "),
+            "{}",
+            requests[0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cloudflare_record_is_changed_or_created() -> anyhow::Result<()> {
+        let (api, requests) = serve(vec![
+            r#"{"success":true,"errors":[],"result":[{"id":"rec1","content":"198.51.100.1"}]}"#,
+            r#"{"success":true,"errors":[],"result":{"id":"rec1"}}"#,
+            r#"{"success":true,"errors":[],"result":[]}"#,
+            r#"{"success":true,"errors":[],"result":{"id":"rec2"}}"#,
+            r#"{"success":false,"errors":[{"code":10000,"message":"Authentication error"}],"result":null}"#,
+        ]);
+        let update = |record: &str, ip: &str| {
+            let ip = ip.parse().unwrap();
+            update_cloudflare(&agent(), &api, "zone", record, "cf-token", ip)
+        };
+
+        update("deck.example.com", "203.0.113.7")?;
+        update("deck6.example.com", "2001:db8::7")?;
+        let denied = update("deck.example.com", "203.0.113.7").unwrap_err();
+        assert!(format!("{denied:#}").contains("Authentication error"));
+
+        let requests = requests.lock().unwrap();
+        assert!(
+            requests[0].starts_with("GET /zones/zone/dns_records?type=A&name=deck.example.com ")
+        );
+        assert!(requests[1].starts_with("PATCH /zones/zone/dns_records/rec1 "));
+        assert!(requests[1].contains(r#""content": "203.0.113.7""#));
+        assert!(
+            requests[2]
+                .starts_with("GET /zones/zone/dns_records?type=AAAA&name=deck6.example.com ")
+        );
+        assert!(requests[3].starts_with("POST /zones/zone/dns_records "));
+        assert!(requests[3].contains(r#""type": "AAAA""#));
+        Ok(())
+    }
+
+    #[test]
+    fn config_is_parsed() {
+        let config: DdnsConfig = toml::from_str(
+            r#"provider = {type = "DuckDns", domain = "sashas-deck", token = "0000-1111"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.interval_minutes, 5);
+        assert_eq!(config.provider.to_string(), "sashas-deck.duckdns.org");
+    }
+}
@@ -0,0 +1,359 @@
+//! Playback engine of `serve --jukebox`, playing the party queue on this machine's output.
+//!
+//! The file following the playing one is decoded ahead of time. Without a crossfade it is
+//! appended to the same player shortly before the end, so it starts without a gap. With a
+//! crossfade it starts on a second player while the volumes of both are ramped.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use crossbeam::channel::{RecvTimeoutError, Sender, bounded};
+use localdeck_http::jukebox::Jukebox;
+use rodio::{Decoder, DeviceSinkBuilder, Player, Source};
+use serde::Deserialize;
+
+use crate::music_player::{AudioPlayerError, Output, find_audio_device};
+
+/// How often transitions are checked and crossfade volumes updated
+const TICK: Duration = Duration::from_millis(20);
+/// Without a crossfade, the next file is appended this long before the playing one ends
+const GAPLESS_LEAD: Duration = Duration::from_millis(500);
+
+/// `[jukebox]` section of the config, read by `serve --jukebox`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct JukeboxConfig {
+    /// outputs with a party queue and volume each. Without zones the jukebox
+    /// plays on the `--device` of `serve`
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+}
+
+/// A jukebox output, e.g. the kitchen speakers
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ZoneConfig {
+    /// picks the zone as `?zone=` on the `/queue` and `/player` routes,
+    /// the first zone is used without it
+    pub name: String,
+    /// keyword of the device name, the default output if not set. A snapcast stream is played
+    /// through the device its server reads from, e.g. an ALSA loopback device
+    #[serde(default)]
+    pub device: Option<String>,
+    /// seconds of overlap between tracks, `--crossfade` if not set
+    #[serde(default)]
+    pub crossfade_secs: Option<f32>,
+}
+
+/// A decoded file, ready to be handed to a player
+struct Prepared {
+    source: Decoder<BufReader<File>>,
+    duration: Option<Duration>,
+}
+
+impl Prepared {
+    fn open(path: &Path) -> Result<Self, AudioPlayerError> {
+        let file = File::open(path).map_err(|e| AudioPlayerError::FileOpen(e.to_string()))?;
+        let source =
+            Decoder::try_from(file).map_err(|e| AudioPlayerError::Decode(e.to_string()))?;
+        Ok(Prepared {
+            duration: source.total_duration(),
+            source,
+        })
+    }
+}
+
+/// Two players on one output, taking turns at crossfades
+struct Decks {
+    players: [Player; 2],
+    /// index of the player with the playing file
+    current: usize,
+    /// of the playing file
+    duration: Option<Duration>,
+    /// file to follow, not handed to a player yet
+    next: Option<Prepared>,
+    /// duration of the file appended after the playing one on the same player
+    appended: Option<Option<Duration>>,
+    /// `set_next` replaced the appended file, it is cut as soon as it starts
+    drop_appended: bool,
+    fading_since: Option<Instant>,
+    /// the next file took over since the driver last asked
+    advanced: bool,
+    volume: f32,
+    crossfade: Duration,
+}
+
+impl Decks {
+    fn start(&mut self, deck: usize, prepared: Prepared) {
+        let player = &self.players[deck];
+        player.clear();
+        player.append(prepared.source);
+        player.play();
+        self.current = deck;
+        self.duration = prepared.duration;
+    }
+
+    fn clear(&mut self) {
+        for player in &self.players {
+            player.clear();
+            player.set_volume(self.volume);
+        }
+        self.duration = None;
+        self.next = None;
+        self.appended = None;
+        self.drop_appended = false;
+        self.fading_since = None;
+        self.advanced = false;
+    }
+
+    /// Silences the outgoing player right away
+    fn finish_fade(&mut self) {
+        if self.fading_since.take().is_some() {
+            self.players[1 - self.current].clear();
+            self.players[self.current].set_volume(self.volume);
+        }
+    }
+
+    /// Hands over to the next file when the playing one ends, and ramps the volumes of a crossfade
+    fn tick(&mut self) {
+        if let Some(since) = self.fading_since {
+            let progress = (since.elapsed().as_secs_f32() / self.crossfade.as_secs_f32()).min(1.0);
+            self.players[1 - self.current].set_volume(self.volume * (1.0 - progress));
+            self.players[self.current].set_volume(self.volume * progress);
+            if progress >= 1.0 {
+                self.finish_fade();
+            }
+            return;
+        }
+        let player = &self.players[self.current];
+        if player.is_paused() {
+            return;
+        }
+
+        if let Some(duration) = self.appended {
+            // the appended file started once the playing one left the player's queue
+            if player.len() <= 1 {
+                self.appended = None;
+                if std::mem::take(&mut self.drop_appended) {
+                    player.clear();
+                    if let Some(next) = self.next.take() {
+                        self.start(self.current, next);
+                        self.advanced = true;
+                    }
+                } else {
+                    self.duration = duration;
+                    self.advanced = true;
+                }
+            }
+            return;
+        }
+
+        let Some(next) = self.next.take() else {
+            return;
+        };
+        let remaining = self
+            .duration
+            .map(|duration| duration.saturating_sub(player.get_pos()));
+        let ending = remaining.is_some_and(|left| left <= self.crossfade.max(GAPLESS_LEAD));
+        if player.empty() {
+            // the length was unknown or wrong, start the next file as soon as possible
+            self.start(self.current, next);
+            self.advanced = true;
+        } else if ending && !self.crossfade.is_zero() {
+            let incoming = 1 - self.current;
+            self.players[incoming].set_volume(0.0);
+            self.start(incoming, next);
+            self.fading_since = Some(Instant::now());
+            self.advanced = true;
+        } else if ending {
+            player.append(next.source);
+            self.appended = Some(next.duration);
+        } else {
+            self.next = Some(next);
+        }
+    }
+}
+
+/// Server side output of `serve --jukebox`, fed by the party queue of the HTTP server
+pub struct JukeboxOutput {
+    decks: Arc<Mutex<Decks>>,
+    /// the output stream is closed once this is dropped
+    _keep_open: Sender<()>,
+}
+
+impl JukeboxOutput {
+    fn decks(&self) -> MutexGuard<'_, Decks> {
+        self.decks.lock().unwrap()
+    }
+}
+
+impl Jukebox for JukeboxOutput {
+    fn play(&mut self, path: &Path) -> anyhow::Result<()> {
+        let prepared = Prepared::open(path)?;
+        let mut decks = self.decks();
+        decks.clear();
+        let current = decks.current;
+        decks.start(current, prepared);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.decks().clear();
+    }
+
+    fn is_finished(&self) -> bool {
+        let decks = self.decks();
+        decks.players[decks.current].empty() && decks.next.is_none()
+    }
+
+    fn pause(&mut self) {
+        let mut decks = self.decks();
+        decks.finish_fade();
+        decks.players[decks.current].pause();
+    }
+
+    fn resume(&mut self) {
+        let decks = self.decks();
+        decks.players[decks.current].play();
+    }
+
+    fn is_paused(&self) -> bool {
+        let decks = self.decks();
+        decks.players[decks.current].is_paused()
+    }
+
+    fn seek(&mut self, position: Duration) -> anyhow::Result<()> {
+        let decks = self.decks();
+        decks.players[decks.current]
+            .try_seek(position)
+            .map_err(|e| anyhow!("could not seek to {position:?}: {e}"))
+    }
+
+    fn position(&self) -> Duration {
+        let decks = self.decks();
+        decks.players[decks.current].get_pos()
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.decks().duration
+    }
+
+    fn volume(&self) -> f32 {
+        self.decks().volume
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        let mut decks = self.decks();
+        decks.volume = volume;
+        // a running crossfade picks it up on the next tick
+        if decks.fading_since.is_none() {
+            decks.players[decks.current].set_volume(volume);
+        }
+    }
+
+    fn set_next(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+        // decoding starts outside the lock, the playing file is not held up
+        let prepared = path.map(Prepared::open).transpose();
+        let mut decks = self.decks();
+        if decks.appended.is_some() {
+            decks.drop_appended = true;
+        }
+        decks.next = None;
+        decks.next = prepared?;
+        Ok(())
+    }
+
+    fn take_advanced(&mut self) -> bool {
+        std::mem::take(&mut self.decks().advanced)
+    }
+}
+
+/// Opens the output for the jukebox. The stream stays open, silent while nothing is queued.
+///
+/// With a zero `crossfade` tracks follow each other without a gap
+pub fn open_jukebox_output(
+    output: Output,
+    crossfade: Duration,
+) -> Result<JukeboxOutput, AudioPlayerError> {
+    let device = find_audio_device(output)?;
+    let (opened_tx, opened_rx) = bounded(1);
+    let (keep_open, closed) = bounded::<()>(0);
+
+    // the stream is not Send on every platform, so it is owned by a thread of its own
+    thread::spawn(move || {
+        let sink = (|| -> Result<_, _> { DeviceSinkBuilder::from_device(device)?.open_stream() })();
+        let sink = match sink {
+            Ok(sink) => sink,
+            Err(e) => {
+                let _ = opened_tx.send(Err(AudioPlayerError::from(e)));
+                return;
+            }
+        };
+        let decks = Arc::new(Mutex::new(Decks {
+            players: [
+                Player::connect_new(sink.mixer()),
+                Player::connect_new(sink.mixer()),
+            ],
+            current: 0,
+            duration: None,
+            next: None,
+            appended: None,
+            drop_appended: false,
+            fading_since: None,
+            advanced: false,
+            volume: 1.0,
+            crossfade,
+        }));
+        let _ = opened_tx.send(Ok(Arc::clone(&decks)));
+
+        // runs until the JukeboxOutput is dropped
+        while let Err(RecvTimeoutError::Timeout) = closed.recv_timeout(TICK) {
+            decks.lock().unwrap().tick();
+        }
+        log::info!("jukebox output closed");
+    });
+
+    let decks = opened_rx
+        .recv()
+        .map_err(|_| AudioPlayerError::OutputThread)??;
+    Ok(JukeboxOutput {
+        decks,
+        _keep_open: keep_open,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones_are_parsed() {
+        let config: JukeboxConfig = toml::from_str(
+            r#"
+[[zones]]
+name = "living-room"
+device = "USB Audio"
+
+[[zones]]
+name = "kitchen"
+device = "Loopback"
+crossfade_secs = 3.0
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.zones.len(), 2);
+        assert_eq!(
+            config.zones[1],
+            ZoneConfig {
+                name: "kitchen".to_string(),
+                device: Some("Loopback".to_string()),
+                crossfade_secs: Some(3.0),
+            }
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Client of the LRCLIB lyrics database, https://lrclib.net
+
+use std::time::Duration;
+
+use localdeck_storage::lyrics::TrackLyrics;
+use serde::Deserialize;
+use ureq::Agent;
+
+const API_URL: &str = "https://lrclib.net/api";
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// LRCLIB asks clients to identify themselves
+const USER_AGENT: &str = concat!(
+    "localdeck/",
+    env!("CARGO_PKG_VERSION"),
+    " (https://github.com/sancho20021/localdeck)"
+);
+/// Results with a length differing more than this from the local file are other recordings
+const MAX_DURATION_DIFF: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibTrack {
+    /// seconds
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+pub struct Lrclib {
+    agent: Agent,
+}
+
+impl Lrclib {
+    pub fn new() -> Self {
+        Self {
+            agent: Agent::config_builder()
+                .timeout_global(Some(TIMEOUT))
+                .build()
+                .into(),
+        }
+    }
+
+    /// Searches lyrics by artist and title. When the duration is known,
+    /// only recordings of about the same length are accepted
+    pub fn find(
+        &self,
+        artist: &str,
+        title: &str,
+        duration: Option<Duration>,
+    ) -> anyhow::Result<Option<TrackLyrics>> {
+        let results: Vec<LrclibTrack> = self
+            .agent
+            .get(format!("{API_URL}/search"))
+            .query("artist_name", artist)
+            .query("track_name", title)
+            .header("User-Agent", USER_AGENT)
+            .call()?
+            .body_mut()
+            .read_json()?;
+        Ok(pick_best(results, duration).map(|track| TrackLyrics {
+            plain: track.plain_lyrics,
+            synced: track.synced_lyrics,
+        }))
+    }
+}
+
+/// Result closest in length having lyrics, synced lyrics are preferred
+fn pick_best(results: Vec<LrclibTrack>, duration: Option<Duration>) -> Option<LrclibTrack> {
+    let diff = |track: &LrclibTrack| match (duration, track.duration) {
+        (Some(expected), Some(actual)) => {
+            Some(expected.abs_diff(Duration::try_from_secs_f64(actual).ok()?))
+        }
+        (Some(_), None) => None,
+        (None, _) => Some(Duration::ZERO),
+    };
+    results
+        .into_iter()
+        .filter(|track| track.plain_lyrics.is_some() || track.synced_lyrics.is_some())
+        .filter_map(|track| Some((diff(&track)?, track)))
+        .filter(|(diff, _)| *diff <= MAX_DURATION_DIFF)
+        .min_by_key(|(diff, track)| (track.synced_lyrics.is_none(), *diff))
+        .map(|(_, track)| track)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LrclibTrack, pick_best};
+
+    fn track(duration: f64, synced: bool) -> LrclibTrack {
+        LrclibTrack {
+            duration: Some(duration),
+            plain_lyrics: Some(format!("lyrics of {duration}")),
+            synced_lyrics: synced.then(|| format!("[00:01.00] lyrics of {duration}")),
+        }
+    }
+
+    #[test]
+    fn picks_synced_lyrics_of_similar_length() {
+        let results = vec![track(180.0, false), track(181.5, true), track(240.0, true)];
+        let best = pick_best(results, Some(Duration::from_secs(180))).unwrap();
+        assert_eq!(best.duration, Some(181.5));
+    }
+
+    #[test]
+    fn rejects_other_recordings() {
+        let results = vec![track(240.0, true)];
+        assert!(pick_best(results, Some(Duration::from_secs(180))).is_none());
+
+        let results = vec![track(240.0, true)];
+        assert!(pick_best(results, None).is_some());
+    }
+}
@@ -1,10 +1,25 @@
 use crate::cli::run;
 
+mod artwork;
+mod backup;
+mod bundle;
 mod card_player;
 pub mod cli;
+mod completion;
 mod config;
+mod ddns;
+mod jukebox;
+mod lrclib;
+mod mdns;
 mod music_player;
+mod progress;
+mod public_endpoint;
 mod qr_scanner;
+mod rescan;
+mod selftest;
+mod spotify;
+mod sync;
+mod systemd;
 
 fn main() {
     run().unwrap();
@@ -0,0 +1,168 @@
+//! Advertising `serve` on the LAN over mDNS (zeroconf / Bonjour).
+//!
+//! The server is registered as `_localdeck._tcp` for companion apps and other
+//! localdeck instances, and as `_http._tcp` so ordinary service browsers list it too.
+
+use anyhow::Context;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use localdeck_http::HttpConfig;
+
+const LOCALDECK_SERVICE: &str = "_localdeck._tcp.local.";
+const HTTP_SERVICE: &str = "_http._tcp.local.";
+const DEFAULT_NAME: &str = "localdeck";
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct MdnsConfig {
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// instance name shown when browsing the LAN, defaults to the theme title
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        MdnsConfig {
+            enabled: true,
+            name: None,
+        }
+    }
+}
+
+/// Registers the server's services. The returned daemon answers queries on its
+/// own thread and has to be kept alive while serving.
+pub fn advertise(config: &MdnsConfig, http: &HttpConfig) -> anyhow::Result<Option<ServiceDaemon>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let bind_addr: Option<IpAddr> = http.bind_addr.parse().ok();
+    if bind_addr.is_some_and(|addr| addr.is_loopback()) {
+        log::info!(
+            "not advertising over mDNS, the server only listens on {}",
+            http.bind_addr
+        );
+        return Ok(None);
+    }
+
+    let daemon = ServiceDaemon::new().context("failed to start the mDNS responder")?;
+    for service in services(config, http, bind_addr)? {
+        let fullname = service.get_fullname().to_string();
+        daemon
+            .register(service)
+            .with_context(|| format!("failed to register {fullname}"))?;
+        log::info!("advertising {fullname} over mDNS");
+    }
+    Ok(Some(daemon))
+}
+
+fn services(
+    config: &MdnsConfig,
+    http: &HttpConfig,
+    bind_addr: Option<IpAddr>,
+) -> anyhow::Result<Vec<ServiceInfo>> {
+    let name = config
+        .name
+        .as_deref()
+        .or(http.theme.title.as_deref())
+        .unwrap_or(DEFAULT_NAME);
+    let host_name = format!("{}.local.", host_label(name));
+    let properties = [("version", env!("CARGO_PKG_VERSION")), ("path", "/")];
+
+    [LOCALDECK_SERVICE, HTTP_SERVICE]
+        .into_iter()
+        .map(|service_type| {
+            let info = match bind_addr.filter(|addr| !addr.is_unspecified()) {
+                // listening on one address only, advertise just that one
+                Some(addr) => ServiceInfo::new(
+                    service_type,
+                    name,
+                    &host_name,
+                    addr,
+                    http.port,
+                    &properties[..],
+                ),
+                None => ServiceInfo::new(
+                    service_type,
+                    name,
+                    &host_name,
+                    (),
+                    http.port,
+                    &properties[..],
+                )
+                .map(ServiceInfo::enable_addr_auto),
+            };
+            info.with_context(|| format!("invalid mDNS service for instance '{name}'"))
+        })
+        .collect()
+}
+
+/// A DNS label for the instance, e.g. `sashas-deck` for "Sasha's Deck"
+fn host_label(name: &str) -> String {
+    let label = name
+        .replace('\'', "")
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_ascii_lowercase();
+    if label.is_empty() {
+        DEFAULT_NAME.to_string()
+    } else {
+        label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_config(bind_addr: &str) -> HttpConfig {
+        toml::from_str(&format!("bind_addr = \"{bind_addr}\"\nport = 9000")).unwrap()
+    }
+
+    #[test]
+    fn both_services_are_advertised_on_the_port() -> anyhow::Result<()> {
+        let mut http = http_config("0.0.0.0");
+        http.theme.title = Some("Sasha's Deck".to_string());
+
+        let services = services(&MdnsConfig::default(), &http, None)?;
+
+        let names: Vec<_> = services.iter().map(|s| s.get_fullname()).collect();
+        assert_eq!(services[0].get_property_val_str("path"), Some("/"));
+        assert_eq!(
+            names,
+            [
+                "Sasha's Deck._localdeck._tcp.local.",
+                "Sasha's Deck._http._tcp.local."
+            ]
+        );
+        for service in &services {
+            assert_eq!(service.get_port(), 9000);
+            assert_eq!(service.get_hostname(), "sashas-deck.local.");
+            assert!(service.is_addr_auto());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn a_specific_bind_address_is_advertised_alone() -> anyhow::Result<()> {
+        let addr: IpAddr = "192.168.1.20".parse()?;
+        let config = MdnsConfig {
+            enabled: true,
+            name: Some("kitchen".to_string()),
+        };
+
+        let services = services(&config, &http_config("192.168.1.20"), Some(addr))?;
+
+        assert!(services.iter().all(|s| !s.is_addr_auto()));
+        assert!(services[0].get_addresses().contains(&addr));
+        Ok(())
+    }
+}
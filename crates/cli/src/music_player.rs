@@ -1,12 +1,13 @@
 use crossbeam::channel::{Receiver, Sender, unbounded};
 use rodio::{
-    Decoder, DeviceSinkBuilder, DeviceSinkError, DeviceTrait, MixerDeviceSink,
+    Decoder, DeviceSinkBuilder, DeviceSinkError, DeviceTrait, MixerDeviceSink, Source,
     cpal::traits::HostTrait,
 };
 use std::{
     fs::File,
     path::{Path, PathBuf},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -38,6 +39,9 @@ pub enum AudioPlayerError {
 
     #[error("failed to decode audio: {0}")]
     Decode(String),
+
+    #[error("audio output thread stopped unexpectedly")]
+    OutputThread,
 }
 
 enum PlayerCommand {
@@ -70,6 +74,12 @@ impl MusicPlayer {
     }
 }
 
+/// Length of the music file, `None` if it can not be decoded or the format does not tell
+pub fn audio_duration(path: &Path) -> Option<Duration> {
+    let file = File::open(path).ok()?;
+    Decoder::try_from(file).ok()?.total_duration()
+}
+
 pub fn start_music_player(
     output: Output,
 ) -> Result<(Receiver<AudioPlayerError>, MusicPlayer), AudioPlayerError> {
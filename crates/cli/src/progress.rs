@@ -0,0 +1,71 @@
+//! Terminal progress bars for scans and updates
+
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use localdeck_storage::{location::Location, progress::Progress};
+
+const SCAN_TEMPLATE: &str = "{spinner} Scanning: {pos} music files found";
+const HASH_TEMPLATE: &str =
+    "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta}) {msg}";
+
+/// Shows a spinner while walking the library and a bar with hashed bytes and ETA while hashing.
+///
+/// indicatif hides the bars by itself when stderr is not a terminal.
+#[derive(Debug, Default)]
+pub struct TerminalProgress {
+    bar: Option<ProgressBar>,
+    files_total: usize,
+    files_hashed: usize,
+}
+
+impl TerminalProgress {
+    fn start(&mut self, bar: ProgressBar, template: &str) {
+        bar.set_style(ProgressStyle::with_template(template).expect("valid progress template"));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        self.bar = Some(bar);
+    }
+
+    fn finish(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn file_found(&mut self, _loc: &Location) {
+        if self.bar.is_none() {
+            self.start(ProgressBar::new_spinner(), SCAN_TEMPLATE);
+        }
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+
+    fn scan_finished(&mut self, _files: usize) {
+        self.finish();
+    }
+
+    fn hashing_started(&mut self, files: usize, bytes: u64) {
+        self.finish();
+        if files == 0 {
+            return;
+        }
+        self.files_total = files;
+        self.files_hashed = 0;
+        self.start(ProgressBar::new(bytes), HASH_TEMPLATE);
+    }
+
+    fn file_hashed(&mut self, _loc: &Location, bytes: u64) {
+        self.files_hashed += 1;
+        if let Some(bar) = &self.bar {
+            bar.inc(bytes);
+            bar.set_message(format!("{}/{} files", self.files_hashed, self.files_total));
+        }
+    }
+
+    fn hashing_finished(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,448 @@
+//! Making `serve` reachable at its public address, and checking that it is.
+//!
+//! With `port_mapping` on, the router is asked to forward the external port to the
+//! server, over UPnP IGD or, failing that, NAT-PMP. Either way the `base_url` printed
+//! on QR codes is then requested and its `/healthz` compared with this server's
+//! instance id, so a stale DNS record or missing forward shows up in the log
+//! instead of as dead cards.
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use igd_next::{PortMappingProtocol, SearchOptions};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    thread,
+    time::Duration,
+};
+use ureq::Agent;
+use url::Url;
+
+use localdeck_http::{HttpConfig, server::HttpServer};
+
+const MAPPING_DESCRIPTION: &str = "localdeck";
+/// Mappings are leased and renewed at half the lease, so they lapse soon after the server stops
+const LEASE: Duration = Duration::from_secs(60 * 60);
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+const NAT_PMP_PORT: u16 = 5351;
+const NAT_PMP_ATTEMPTS: u32 = 3;
+/// Time the server gets to start listening before its public address is requested
+const STARTUP_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct PublicEndpoint {
+    /// address guests reach the deck at, as used in printed QR codes, e.g. `https://deck.example.com`
+    pub base_url: String,
+    /// ask the router to forward `external_port` to the server, over UPnP or NAT-PMP
+    #[serde(default)]
+    pub port_mapping: bool,
+    /// port opened on the router, defaults to the server's port
+    #[serde(default)]
+    pub external_port: Option<u16>,
+    /// further addresses of the same server by name, e.g. `lan = "http://deck.local:8080"`
+    /// for cards only used at home. Only `base_url` is checked
+    #[serde(default)]
+    pub other_base_urls: BTreeMap<String, String>,
+}
+
+impl PublicEndpoint {
+    /// `base_url` and the other base URLs with their names, `base_url` first and named `default`
+    pub fn base_urls(&self) -> impl Iterator<Item = (&str, &str)> {
+        std::iter::once(("default", self.base_url.as_str())).chain(
+            self.other_base_urls
+                .iter()
+                .map(|(name, url)| (name.as_str(), url.as_str())),
+        )
+    }
+}
+
+/// A port forward set up on the router
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mapping {
+    protocol: &'static str,
+    external_port: u16,
+    /// address of the router on the internet, if it told
+    external_ip: Option<IpAddr>,
+}
+
+/// Starts a thread mapping the port if configured, then reporting whether `base_url`
+/// reaches the server that is about to run.
+pub fn spawn(endpoint: PublicEndpoint, server: &HttpServer) -> anyhow::Result<()> {
+    let port = server.config.port;
+    let instance = server.instance_id().to_string();
+    let authorization = authorization(&server.config);
+    thread::Builder::new()
+        .name("public-endpoint".to_string())
+        .spawn(move || {
+            let external_port = endpoint.external_port.unwrap_or(port);
+            let mapping = if endpoint.port_mapping {
+                match map_port(port, external_port) {
+                    Ok(mapping) => {
+                        log::info!(
+                            "{} forwards external port {} to this server, external address {}",
+                            mapping.protocol,
+                            mapping.external_port,
+                            mapping
+                                .external_ip
+                                .map_or("unknown".to_string(), |ip| ip.to_string())
+                        );
+                        Some(mapping)
+                    }
+                    Err(e) => {
+                        log::warn!("could not map port {external_port} on the router: {e:#}");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            thread::sleep(STARTUP_DELAY);
+            let external_ip = mapping.as_ref().and_then(|mapping| mapping.external_ip);
+            match verify(
+                &endpoint.base_url,
+                &instance,
+                external_ip,
+                authorization.as_deref(),
+            ) {
+                Ok(report) => log::info!("{report}"),
+                Err(problem) => log::warn!("{problem}"),
+            }
+
+            while mapping.is_some() {
+                thread::sleep(LEASE / 2);
+                if let Err(e) = map_port(port, external_port) {
+                    log::warn!("could not renew the mapping of port {external_port}: {e:#}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Credentials the server itself demands, so its `/healthz` can be read
+fn authorization(config: &HttpConfig) -> Option<String> {
+    if let Some(token) = config.tokens.first() {
+        return Some(format!("Bearer {}", token.token));
+    }
+    config.basic_auth.as_ref().map(|auth| {
+        let credentials = BASE64_STANDARD.encode(format!("{}:{}", auth.user, auth.password));
+        format!("Basic {credentials}")
+    })
+}
+
+fn map_port(internal_port: u16, external_port: u16) -> anyhow::Result<Mapping> {
+    let upnp = match map_with_upnp(internal_port, external_port) {
+        Ok(mapping) => return Ok(mapping),
+        Err(e) => e,
+    };
+    map_with_nat_pmp(internal_port, external_port)
+        .map_err(|nat_pmp| anyhow!("UPnP: {upnp:#}; NAT-PMP: {nat_pmp:#}"))
+}
+
+fn map_with_upnp(internal_port: u16, external_port: u16) -> anyhow::Result<Mapping> {
+    let gateway = igd_next::search_gateway(SearchOptions {
+        timeout: Some(UPNP_SEARCH_TIMEOUT),
+        ..Default::default()
+    })
+    .context("no UPnP gateway found")?;
+    let local_ip = local_ip_towards(gateway.addr)?;
+    gateway
+        .add_port(
+            PortMappingProtocol::TCP,
+            external_port,
+            SocketAddr::new(local_ip, internal_port),
+            LEASE.as_secs() as u32,
+            MAPPING_DESCRIPTION,
+        )
+        .with_context(|| format!("{} refused the mapping", gateway.addr))?;
+    Ok(Mapping {
+        protocol: "UPnP",
+        external_port,
+        external_ip: gateway.get_external_ip().ok(),
+    })
+}
+
+/// The address of this machine on the network leading to `peer`
+fn local_ip_towards(peer: SocketAddr) -> anyhow::Result<IpAddr> {
+    let unspecified: IpAddr = if peer.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        std::net::Ipv6Addr::UNSPECIFIED.into()
+    };
+    // connecting a UDP socket sends nothing, it only picks the route
+    let socket = UdpSocket::bind((unspecified, 0))?;
+    socket.connect(peer)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn map_with_nat_pmp(internal_port: u16, external_port: u16) -> anyhow::Result<Mapping> {
+    let gateway = default_gateway().context("default gateway unknown")?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((gateway, NAT_PMP_PORT))?;
+
+    let response = nat_pmp_exchange(&socket, &[0, 0])?;
+    let external_ip = parse_external_address(&response).ok();
+
+    let request = map_request(internal_port, external_port, LEASE.as_secs() as u32);
+    let response = nat_pmp_exchange(&socket, &request)?;
+    let mapped_port = parse_mapping(&response)?;
+    Ok(Mapping {
+        protocol: "NAT-PMP",
+        external_port: mapped_port,
+        external_ip: external_ip.map(IpAddr::V4),
+    })
+}
+
+/// Sends a request, resending it with doubling timeouts as RFC 6886 asks
+fn nat_pmp_exchange(socket: &UdpSocket, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut timeout = Duration::from_millis(250);
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request)?;
+        socket.set_read_timeout(Some(timeout))?;
+        let mut buf = [0; 16];
+        match socket.recv(&mut buf) {
+            Ok(len) => return Ok(buf[..len].to_vec()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                timeout *= 2;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    bail!("the gateway does not answer NAT-PMP requests")
+}
+
+/// Request to map a TCP port, opcode 2
+fn map_request(internal_port: u16, external_port: u16, lifetime_secs: u32) -> [u8; 12] {
+    let mut request = [0; 12];
+    request[1] = 2;
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime_secs.to_be_bytes());
+    request
+}
+
+/// Checks the header of a response to `opcode` and returns its remaining bytes
+fn nat_pmp_payload(response: &[u8], opcode: u8, len: usize) -> anyhow::Result<&[u8]> {
+    if response.len() < len || response[0] != 0 || response[1] != 128 + opcode {
+        bail!("malformed NAT-PMP response");
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(&response[8..len]),
+        2 => bail!("NAT-PMP is disabled on the gateway"),
+        3 => bail!("the gateway has no external address"),
+        code => bail!("the gateway refused with NAT-PMP result code {code}"),
+    }
+}
+
+fn parse_external_address(response: &[u8]) -> anyhow::Result<Ipv4Addr> {
+    let payload = nat_pmp_payload(response, 0, 12)?;
+    Ok(Ipv4Addr::new(
+        payload[0], payload[1], payload[2], payload[3],
+    ))
+}
+
+/// The external port the gateway mapped, which may differ from the one asked for
+fn parse_mapping(response: &[u8]) -> anyhow::Result<u16> {
+    let payload = nat_pmp_payload(response, 2, 16)?;
+    Ok(u16::from_be_bytes([payload[2], payload[3]]))
+}
+
+fn default_gateway() -> Option<Ipv4Addr> {
+    // NAT-PMP has no discovery, the gateway is taken from the routing table
+    fs::read_to_string("/proc/net/route")
+        .ok()
+        .and_then(|table| parse_route_table(&table))
+}
+
+/// Gateway of the default route in the format of `/proc/net/route`
+fn parse_route_table(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        // the kernel prints the address in host byte order
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes())).filter(|gateway| !gateway.is_unspecified())
+    })
+}
+
+#[derive(Deserialize)]
+struct Health {
+    instance: Option<String>,
+}
+
+/// Describes where `base_url` leads, an error if it is not to `instance`
+fn verify(
+    base_url: &str,
+    instance: &str,
+    external_ip: Option<IpAddr>,
+    authorization: Option<&str>,
+) -> Result<String, String> {
+    let base_url = base_url.trim_end_matches('/');
+    let url =
+        Url::parse(base_url).map_err(|e| format!("public base_url {base_url} is invalid: {e}"))?;
+    let host = url
+        .host_str()
+        .ok_or(format!("public base_url {base_url} has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let resolved: Vec<IpAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("public base_url {base_url} does not resolve: {e}"))?
+        .map(|addr| addr.ip())
+        .collect();
+    let dns_mismatch = external_ip
+        .filter(|ip| !resolved.contains(ip))
+        .map(|ip| {
+            format!("; {host} resolves to {resolved:?}, but the router's external address is {ip}")
+        })
+        .unwrap_or_default();
+
+    let agent: Agent = Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .build()
+        .into();
+    let mut request = agent.get(format!("{base_url}/healthz"));
+    if let Some(authorization) = authorization {
+        request = request.header("Authorization", authorization);
+    }
+    let mut response = request.call().map_err(|e| {
+        format!(
+            "public base_url {base_url} is unreachable from here: {e}{dns_mismatch} \
+             (routers without NAT loopback cannot reach their own address, check from outside)"
+        )
+    })?;
+    let status = response.status().as_u16();
+    let health: Option<Health> = response.body_mut().read_json().ok();
+    match health.and_then(|health| health.instance) {
+        Some(other) if other == instance => {
+            Ok(format!("public base_url {base_url} reaches this server"))
+        }
+        Some(_) => Err(format!(
+            "public base_url {base_url} reaches another localdeck server{dns_mismatch}"
+        )),
+        None => Err(format!(
+            "public base_url {base_url} is answered by something other than localdeck (status {status}){dns_mismatch}"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn endpoint_is_parsed() {
+        let endpoint: PublicEndpoint = toml::from_str(
+            r#"
+base_url = "https://deck.example.com"
+port_mapping = true
+other_base_urls = {lan = "http://deck.local:8080"}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            endpoint,
+            PublicEndpoint {
+                base_url: "https://deck.example.com".to_string(),
+                port_mapping: true,
+                external_port: None,
+                other_base_urls: [("lan".to_string(), "http://deck.local:8080".to_string())].into(),
+            }
+        );
+    }
+
+    #[test]
+    fn nat_pmp_messages() -> anyhow::Result<()> {
+        assert_eq!(
+            map_request(8080, 80, 3600),
+            [0, 2, 0, 0, 0x1f, 0x90, 0, 80, 0, 0, 0x0e, 0x10]
+        );
+
+        let address = [0, 128, 0, 0, 0, 0, 1, 0, 203, 0, 113, 7];
+        assert_eq!(
+            parse_external_address(&address)?,
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+
+        let mapped = [
+            0, 130, 0, 0, 0, 0, 1, 0, 0x1f, 0x90, 0x1f, 0x91, 0, 0, 0x0e, 0x10,
+        ];
+        assert_eq!(parse_mapping(&mapped)?, 8081);
+
+        let refused = [0, 130, 0, 2, 0, 0, 1, 0, 0x1f, 0x90, 0, 0, 0, 0, 0, 0];
+        assert!(parse_mapping(&refused).is_err());
+        assert!(parse_mapping(&address).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn default_gateway_from_route_table() {
+        let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+        let expected = if cfg!(target_endian = "little") {
+            Ipv4Addr::new(192, 168, 1, 1)
+        } else {
+            Ipv4Addr::new(1, 1, 168, 192)
+        };
+        assert_eq!(parse_route_table(table), Some(expected));
+        assert_eq!(parse_route_table("Iface\tDestination\tGateway\n"), None);
+    }
+
+    /// Answers every request with `/healthz` of a server with the given instance id
+    fn serve_health(instance: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let body = format!(r#"{{"status":"ok","instance":"{instance}"}}"#);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        base_url
+    }
+
+    #[test]
+    fn base_url_must_reach_this_instance() {
+        let base_url = serve_health("c0ffee");
+
+        assert!(verify(&base_url, "c0ffee", None, None).is_ok());
+
+        let elsewhere = verify(&base_url, "decade", None, None).unwrap_err();
+        assert!(
+            elsewhere.contains("another localdeck server"),
+            "{elsewhere}"
+        );
+
+        let external_ip = Some("203.0.113.7".parse().unwrap());
+        let stale_dns = verify(&base_url, "decade", external_ip, None).unwrap_err();
+        assert!(
+            stale_dns.contains("external address is 203.0.113.7"),
+            "{stale_dns}"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! `update` run by `serve` on a schedule, for servers nobody runs it on by hand.
+//!
+//! ```toml
+//! [rescan]
+//! interval_minutes = 60
+//! # or at fixed times, in local time
+//! # cron = "0 4 * * *"
+//! ```
+//!
+//! New files are recorded as `update` does, and with `tag_policy` set their tags are read.
+//! The roots are walked and files hashed without holding the library, it is only locked
+//! for each batch of database changes, so requests and the watchdog are served during scans.
+
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use chrono::{Local, Timelike};
+use localdeck_http::{schedule::CronSchedule, server::SharedStore};
+use localdeck_storage::{
+    progress::{NoProgress, Progress},
+    provenance::MetadataPolicy,
+    store::LibraryStore,
+    update::update_shared,
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct RescanConfig {
+    /// minutes between scans, the first one right after the server starts
+    #[serde(default)]
+    pub interval_minutes: Option<u64>,
+    /// when to scan, instead of every `interval_minutes`
+    #[serde(default)]
+    pub cron: Option<CronSchedule>,
+}
+
+/// When scans run
+enum Schedule {
+    Every(Duration),
+    At(CronSchedule),
+}
+
+impl RescanConfig {
+    fn schedule(self) -> anyhow::Result<Schedule> {
+        match (self.interval_minutes, self.cron) {
+            (Some(_), Some(_)) => bail!("rescan takes either interval_minutes or cron, not both"),
+            (None, Some(cron)) => Ok(Schedule::At(cron)),
+            (Some(minutes), None) => Ok(Schedule::Every(Duration::from_secs(minutes.max(1) * 60))),
+            (None, None) => bail!("rescan needs interval_minutes or cron"),
+        }
+    }
+}
+
+/// Tracks whose tags are read while the library is locked once
+const TAG_IMPORT_BATCH: usize = 100;
+
+/// Records new files of the library and reads their tags if `tag_policy` is set.
+/// Returns the number of new files
+fn run_rescan(
+    storage: &Mutex<dyn LibraryStore>,
+    tag_policy: Option<MetadataPolicy>,
+    progress: Box<dyn Progress>,
+) -> anyhow::Result<usize> {
+    let files = update_shared(storage, progress)?;
+    if let Some(policy) = tag_policy {
+        let tracks: Vec<_> = files.keys().copied().collect();
+        let mut conflicts = 0;
+        for batch in tracks.chunks(TAG_IMPORT_BATCH) {
+            let report = storage
+                .lock()
+                .map_err(|e| anyhow!("could not lock the library: {e}"))?
+                .import_tags(batch, policy)?;
+            conflicts += report.conflicts.len();
+        }
+        if conflicts > 0 {
+            log::warn!(
+                "tags of {conflicts} new file(s) differ from edited metadata, see `localdeck meta import-tags`"
+            );
+        }
+    }
+    Ok(files.values().map(|files| files.len()).sum())
+}
+
+/// Starts a thread scanning the library as configured, for as long as the server runs.
+/// Failed scans are logged and tried again at the next time
+pub fn spawn_scheduler(
+    storage: SharedStore,
+    config: RescanConfig,
+    tag_policy: Option<MetadataPolicy>,
+) -> anyhow::Result<()> {
+    let schedule = config.schedule()?;
+    let rescan = move || {
+        let started = Instant::now();
+        match run_rescan(&storage, tag_policy, Box::new(NoProgress)) {
+            Ok(files) => log::info!(
+                "rescan recorded {files} new file(s) in {:.1}s",
+                started.elapsed().as_secs_f32()
+            ),
+            Err(e) => log::error!("rescan failed: {e:#}"),
+        }
+    };
+    thread::Builder::new()
+        .name("rescan".to_string())
+        .spawn(move || match schedule {
+            Schedule::Every(interval) => loop {
+                rescan();
+                thread::sleep(interval);
+            },
+            Schedule::At(cron) => {
+                let mut last_minute = None;
+                loop {
+                    let now = Local::now();
+                    let minute = now.timestamp() / 60;
+                    if last_minute != Some(minute) {
+                        last_minute = Some(minute);
+                        if cron.matches(&now) {
+                            rescan();
+                        }
+                    }
+                    thread::sleep(Duration::from_secs(60 - u64::from(now.second()).min(59)));
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
+
+    use localdeck_http::server::SharedStore;
+    use localdeck_storage::{
+        config::{Config, Database, LibrarySource},
+        location::Location,
+        operations::Storage,
+        progress::{NoProgress, Progress},
+    };
+
+    use super::{RescanConfig, run_rescan};
+
+    fn shared_storage(root: &std::path::Path) -> anyhow::Result<SharedStore> {
+        Ok(Arc::new(Mutex::new(Storage::new(Config {
+            database: Database::InMemory,
+            library_source: LibrarySource {
+                roots: vec![Location::from_path(root).into()],
+                ..Default::default()
+            },
+        })?)))
+    }
+
+    #[test]
+    fn rescans_record_new_files() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let storage = shared_storage(root.path())?;
+        let rescan = || run_rescan(&storage, None, Box::new(NoProgress));
+
+        fs::write(root.path().join("a.mp3"), b"a")?;
+        assert_eq!(rescan()?, 1);
+        assert_eq!(rescan()?, 0);
+        fs::write(root.path().join("b.mp3"), b"b")?;
+        assert_eq!(rescan()?, 1);
+        assert_eq!(storage.lock().unwrap().list_tracks()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn library_is_not_locked_while_scanning_and_hashing() -> anyhow::Result<()> {
+        /// Tries to lock the library on every file found and hashed
+        struct LockProbe {
+            storage: SharedStore,
+            locked: Arc<Mutex<Vec<bool>>>,
+        }
+
+        impl std::fmt::Debug for LockProbe {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("LockProbe")
+            }
+        }
+
+        impl LockProbe {
+            fn probe(&self) {
+                let free = self.storage.try_lock().is_ok();
+                self.locked.lock().unwrap().push(free);
+            }
+        }
+
+        impl Progress for LockProbe {
+            fn file_found(&mut self, _loc: &Location) {
+                self.probe();
+            }
+
+            fn file_hashed(&mut self, _loc: &Location, _bytes: u64) {
+                self.probe();
+            }
+        }
+
+        let root = tempfile::tempdir()?;
+        let storage = shared_storage(root.path())?;
+        fs::write(root.path().join("a.mp3"), b"a")?;
+        fs::write(root.path().join("b.mp3"), b"b")?;
+
+        let locked = Arc::new(Mutex::new(vec![]));
+        let probe = LockProbe {
+            storage: storage.clone(),
+            locked: locked.clone(),
+        };
+        assert_eq!(run_rescan(&storage, None, Box::new(probe))?, 2);
+
+        let locked = locked.lock().unwrap();
+        // two files found and two hashed
+        assert_eq!(locked.len(), 4);
+        assert!(locked.iter().all(|&free| free));
+        Ok(())
+    }
+
+    #[test]
+    fn schedules_are_one_of_interval_and_cron() {
+        let config = |toml: &str| toml::from_str::<RescanConfig>(toml).unwrap().schedule();
+        assert!(config("interval_minutes = 30").is_ok());
+        assert!(config("cron = \"0 4 * * *\"").is_ok());
+        assert!(config("").is_err());
+        assert!(config("interval_minutes = 30\ncron = \"0 4 * * *\"").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Smoke test of a running localdeck server over its HTTP API
+
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use ureq::Agent;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a single check: description of what worked, or why it failed
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: Result<String, String>,
+}
+
+#[derive(Deserialize)]
+struct TrackListItem {
+    track_id: i64,
+}
+
+/// Runs all checks against the server at `base_url`, e.g. `http://main-deck:8080`
+pub fn run_selftest(base_url: &str) -> Vec<CheckResult> {
+    let base_url = base_url.trim_end_matches('/');
+    let agent: Agent = Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    let mut results = vec![CheckResult {
+        name: "healthz",
+        outcome: check_healthz(&agent, base_url),
+    }];
+
+    let tracks = list_tracks(&agent, base_url);
+    results.push(CheckResult {
+        name: "tracks",
+        outcome: tracks
+            .as_ref()
+            .map(|t| format!("{} tracks listed", t.len()))
+            .map_err(Clone::clone),
+    });
+
+    let track = match tracks {
+        Ok(tracks) if !tracks.is_empty() => Ok(pick_random(&tracks)),
+        Ok(_) => Err("library is empty, nothing to play".to_string()),
+        Err(_) => Err("skipped, track listing failed".to_string()),
+    };
+
+    results.push(CheckResult {
+        name: "play",
+        outcome: track
+            .clone()
+            .and_then(|id| check_play(&agent, base_url, id)),
+    });
+    results.push(CheckResult {
+        name: "ranged stream",
+        outcome: track.and_then(|id| check_ranged_stream(&agent, base_url, id)),
+    });
+
+    results
+}
+
+fn pick_random(tracks: &[i64]) -> i64 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    tracks[nanos as usize % tracks.len()]
+}
+
+fn get(
+    agent: &Agent,
+    url: &str,
+    range: Option<&str>,
+) -> Result<ureq::http::Response<ureq::Body>, String> {
+    let mut request = agent.get(url);
+    if let Some(range) = range {
+        request = request.header("Range", range);
+    }
+    request.call().map_err(|e| format!("GET {url} failed: {e}"))
+}
+
+fn header<'a>(response: &'a ureq::http::Response<ureq::Body>, name: &str) -> Option<&'a str> {
+    response.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn check_healthz(agent: &Agent, base_url: &str) -> Result<String, String> {
+    let response = get(agent, &format!("{base_url}/healthz"), None)?;
+    match response.status().as_u16() {
+        200 => Ok("server is healthy".to_string()),
+        status => Err(format!("unexpected status {status}")),
+    }
+}
+
+fn list_tracks(agent: &Agent, base_url: &str) -> Result<Vec<i64>, String> {
+    let mut response = get(agent, &format!("{base_url}/tracks"), None)?;
+    let status = response.status().as_u16();
+    if status != 200 {
+        return Err(format!("unexpected status {status}"));
+    }
+    let tracks: Vec<TrackListItem> = response
+        .body_mut()
+        .read_json()
+        .map_err(|e| format!("invalid track listing: {e}"))?;
+    Ok(tracks.into_iter().map(|t| t.track_id).collect())
+}
+
+fn check_play(agent: &Agent, base_url: &str, track: i64) -> Result<String, String> {
+    let response = get(agent, &format!("{base_url}/play?h={track}"), None)?;
+    let status = response.status().as_u16();
+    if status != 200 {
+        return Err(format!("track {track}: unexpected status {status}"));
+    }
+    match header(&response, "Content-Type") {
+        Some(mime) if mime.starts_with("audio/") => Ok(format!("track {track} plays as {mime}")),
+        other => Err(format!("track {track}: unexpected content type {other:?}")),
+    }
+}
+
+fn check_ranged_stream(agent: &Agent, base_url: &str, track: i64) -> Result<String, String> {
+    let mut response = get(
+        agent,
+        &format!("{base_url}/tracks/{track}/stream"),
+        Some("bytes=0-1"),
+    )?;
+    let status = response.status().as_u16();
+    if status != 206 {
+        return Err(format!("track {track}: expected 206, got {status}"));
+    }
+    let content_range = header(&response, "Content-Range")
+        .map(str::to_string)
+        .ok_or(format!("track {track}: missing Content-Range header"))?;
+    if !content_range.starts_with("bytes 0-1/") {
+        return Err(format!(
+            "track {track}: unexpected Content-Range '{content_range}'"
+        ));
+    }
+    let body = response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|e| format!("track {track}: failed to read body: {e}"))?;
+    if body.len() != 2 {
+        return Err(format!(
+            "track {track}: expected 2 bytes, got {}",
+            body.len()
+        ));
+    }
+    Ok(format!("track {track} served {content_range}"))
+}
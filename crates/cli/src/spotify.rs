@@ -0,0 +1,473 @@
+//! Playlists from Spotify, rebuilt from the tracks of the library with
+//! `localdeck playlist import-spotify`.
+//!
+//! Songs are matched by artist and title, compared loosely: case, punctuation and
+//! suffixes like `(feat. X)` or `- Remastered 2011` don't count. Where both lengths are
+//! known, tracks of another length are other recordings and don't match.
+
+use std::{fmt, time::Duration};
+
+use anyhow::{Context, bail};
+use localdeck_storage::{operations::TrackListEntry, track::TrackId};
+use serde::Deserialize;
+use ureq::Agent;
+
+const API_URL: &str = "https://api.spotify.com/v1";
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Files and Spotify differ by a bit of silence, more is a live version or an edit
+const MAX_DURATION_DIFF: Duration = Duration::from_secs(5);
+/// Lowest similarity of titles, and of artists, of a match
+const MIN_SIMILARITY: f64 = 0.8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotifySong {
+    pub artists: Vec<String>,
+    pub title: String,
+    pub duration: Option<Duration>,
+}
+
+impl fmt::Display for SpotifySong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.artists.join(", "), self.title)
+    }
+}
+
+#[derive(Debug)]
+pub struct SpotifyPlaylist {
+    pub name: String,
+    pub songs: Vec<SpotifySong>,
+}
+
+/// Playlist object of the Web API, e.g. saved from `GET /v1/playlists/{id}`
+#[derive(Debug, Deserialize)]
+struct ApiPlaylist {
+    name: String,
+    tracks: ApiPage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiPage {
+    items: Vec<ApiItem>,
+    /// link to the following page
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiItem {
+    /// `null` for songs no longer available
+    track: Option<ApiTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiTrack {
+    name: String,
+    duration_ms: Option<u64>,
+    #[serde(default)]
+    artists: Vec<ApiArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiArtist {
+    name: String,
+}
+
+/// `Playlist1.json` of the account data requested in the privacy settings
+#[derive(Debug, Deserialize)]
+struct DataExport {
+    playlists: Vec<ExportedPlaylist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedPlaylist {
+    name: String,
+    items: Vec<ExportedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedItem {
+    /// podcast episodes have none
+    track: Option<ExportedTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedTrack {
+    track_name: String,
+    artist_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Api(ApiPlaylist),
+    Data(DataExport),
+    Playlist(ExportedPlaylist),
+}
+
+impl ApiPage {
+    fn songs(self) -> impl Iterator<Item = SpotifySong> {
+        self.items
+            .into_iter()
+            .filter_map(|item| item.track)
+            .map(|track| SpotifySong {
+                artists: track
+                    .artists
+                    .into_iter()
+                    .map(|artist| artist.name)
+                    .collect(),
+                title: track.name,
+                duration: track.duration_ms.map(Duration::from_millis),
+            })
+    }
+}
+
+impl From<ExportedPlaylist> for SpotifyPlaylist {
+    fn from(playlist: ExportedPlaylist) -> Self {
+        Self {
+            name: playlist.name,
+            songs: playlist
+                .items
+                .into_iter()
+                .filter_map(|item| item.track)
+                .map(|track| SpotifySong {
+                    artists: vec![track.artist_name],
+                    title: track.track_name,
+                    duration: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reads a playlist saved from the Web API or a data export. A data export holds all
+/// playlists of the account, `name` picks one of them
+pub fn read_export(json: &str, name: Option<&str>) -> anyhow::Result<SpotifyPlaylist> {
+    let export: Export = serde_json::from_str(json)
+        .context("neither a playlist of the Spotify Web API nor a Spotify data export")?;
+    Ok(match export {
+        Export::Api(playlist) => SpotifyPlaylist {
+            name: playlist.name,
+            songs: playlist.tracks.songs().collect(),
+        },
+        Export::Playlist(playlist) => playlist.into(),
+        Export::Data(data) => {
+            let mut playlists = data.playlists;
+            match name {
+                Some(name) => playlists
+                    .into_iter()
+                    .find(|playlist| playlist.name == name)
+                    .with_context(|| format!("the export has no playlist named '{name}'"))?
+                    .into(),
+                None if playlists.len() == 1 => playlists.remove(0).into(),
+                None => {
+                    let names: Vec<_> = playlists.iter().map(|p| p.name.as_str()).collect();
+                    bail!(
+                        "the export has {} playlists, pick one with --name: {}",
+                        names.len(),
+                        names.join(", ")
+                    );
+                }
+            }
+        }
+    })
+}
+
+/// Id of the playlist of a link like `https://open.spotify.com/playlist/37i9dQZF1DX0XUsuxWHRQd`
+/// or `spotify:playlist:37i9dQZF1DX0XUsuxWHRQd`
+pub fn playlist_id(link: &str) -> Option<&str> {
+    let id = match link.strip_prefix("spotify:playlist:") {
+        Some(id) => id,
+        None => {
+            let path = link
+                .strip_prefix("https://open.spotify.com/")
+                .or_else(|| link.strip_prefix("http://open.spotify.com/"))?;
+            let path = path.split(['?', '#']).next()?;
+            // links may carry the language, `/intl-de/playlist/...`
+            path.split('/')
+                .skip_while(|part| *part != "playlist")
+                .nth(1)?
+        }
+    };
+    Some(id).filter(|id| !id.is_empty() && id.bytes().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Downloads a playlist from the Web API, `token` is an access token of a Spotify app
+pub fn fetch(id: &str, token: &str) -> anyhow::Result<SpotifyPlaylist> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let auth = format!("Bearer {token}");
+    let playlist: ApiPlaylist = agent
+        .get(format!("{API_URL}/playlists/{id}"))
+        .header("Authorization", &auth)
+        .call()
+        .with_context(|| format!("failed to get playlist {id} from Spotify"))?
+        .body_mut()
+        .read_json()?;
+    let mut next = playlist.tracks.next.clone();
+    let mut songs: Vec<SpotifySong> = playlist.tracks.songs().collect();
+    while let Some(url) = next {
+        let page: ApiPage = agent
+            .get(&url)
+            .header("Authorization", &auth)
+            .call()?
+            .body_mut()
+            .read_json()?;
+        next = page.next.clone();
+        songs.extend(page.songs());
+    }
+    Ok(SpotifyPlaylist {
+        name: playlist.name,
+        songs,
+    })
+}
+
+/// Lowercase words of a title or name, without what is in brackets or after ` - `
+fn normalize(s: &str) -> String {
+    let s = s.split(" - ").next().unwrap_or_default().to_lowercase();
+    let mut words = String::new();
+    let mut depth = 0usize;
+    for c in s.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() => words.push(c),
+            _ => {
+                if !words.ends_with(' ') {
+                    words.push(' ');
+                }
+            }
+        }
+    }
+    words.trim().to_string()
+}
+
+/// Dice coefficient of the letter pairs, 1 for equal strings
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let pairs = |s: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = s.chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect()
+    };
+    let a = pairs(a);
+    let mut b = pairs(b);
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut common = 0;
+    for pair in a {
+        if let Some(i) = b.iter().position(|other| *other == pair) {
+            b.swap_remove(i);
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / total as f64
+}
+
+/// How well one of the song's artists fits the artist of a track, which may name
+/// several, e.g. `Daft Punk & Pharrell Williams`
+fn artist_similarity(song: &SpotifySong, artist: &str) -> f64 {
+    let artist = normalize(artist);
+    song.artists
+        .iter()
+        .map(|name| normalize(name))
+        .map(|name| {
+            let named = artist
+                .split(' ')
+                .collect::<Vec<_>>()
+                .windows(name.split(' ').count())
+                .any(|words| words.join(" ") == name);
+            if named {
+                1.0
+            } else {
+                similarity(&name, &artist)
+            }
+        })
+        .fold(0.0, f64::max)
+}
+
+/// The track of the library that is the song, `None` if there is none.
+///
+/// `duration` gives the length of a track, it is only asked for tracks whose artist and
+/// title fit
+pub fn find_match(
+    song: &SpotifySong,
+    tracks: &[TrackListEntry],
+    mut duration: impl FnMut(TrackId) -> Option<Duration>,
+) -> Option<TrackId> {
+    let title = normalize(&song.title);
+    let mut candidates: Vec<(f64, TrackId)> = tracks
+        .iter()
+        .filter_map(|track| {
+            let meta = track.meta.as_ref()?;
+            let title_score = similarity(&title, &normalize(&meta.title));
+            let artist_score = artist_similarity(song, &meta.artist);
+            (title_score >= MIN_SIMILARITY && artist_score >= MIN_SIMILARITY)
+                .then_some((title_score + artist_score, track.id))
+        })
+        .collect();
+    candidates.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    let Some(expected) = song.duration else {
+        return candidates.first().map(|(_, track)| *track);
+    };
+    candidates
+        .into_iter()
+        .filter_map(|(score, track)| {
+            let diff = match duration(track) {
+                Some(actual) => expected.abs_diff(actual),
+                // an unreadable file is not another recording
+                None => MAX_DURATION_DIFF,
+            };
+            (diff <= MAX_DURATION_DIFF).then_some((score, diff, track))
+        })
+        .min_by(|(a_score, a_diff, _), (b_score, b_diff, _)| {
+            b_score.total_cmp(a_score).then(a_diff.cmp(b_diff))
+        })
+        .map(|(_, _, track)| track)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use localdeck_storage::operations::{ListedMetadata, TrackListEntry};
+
+    use super::*;
+
+    fn track(id: TrackId, artist: &str, title: &str) -> TrackListEntry {
+        TrackListEntry {
+            id,
+            added: None,
+            files: vec![],
+            meta: Some(ListedMetadata {
+                artist: artist.to_string(),
+                title: title.to_string(),
+                year: None,
+                artist_sort: artist.to_string(),
+                title_sort: title.to_string(),
+            }),
+        }
+    }
+
+    fn song(artist: &str, title: &str, secs: Option<u64>) -> SpotifySong {
+        SpotifySong {
+            artists: vec![artist.to_string()],
+            title: title.to_string(),
+            duration: secs.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn songs_match_loosely() {
+        let tracks = vec![
+            track(1, "The Beatles", "Let It Be"),
+            track(2, "Daft Punk & Pharrell Williams", "Get Lucky"),
+            track(3, "Beyoncé", "Halo"),
+        ];
+        let no_duration = |_| None;
+        assert_eq!(
+            find_match(
+                &song("The Beatles", "Let It Be - Remastered 2009", None),
+                &tracks,
+                no_duration
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            find_match(
+                &song("Daft Punk", "Get Lucky (feat. Pharrell Williams)", None),
+                &tracks,
+                no_duration
+            ),
+            Some(2)
+        );
+        assert_eq!(
+            find_match(&song("beyonce", "HALO", None), &tracks, no_duration),
+            Some(3)
+        );
+        assert_eq!(
+            find_match(
+                &song("The Beatles", "Yesterday", None),
+                &tracks,
+                no_duration
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn durations_tell_recordings_apart() {
+        let tracks = vec![
+            track(1, "Nirvana", "Lithium"),
+            track(2, "Nirvana", "Lithium (Live)"),
+        ];
+        let durations = |track| match track {
+            1 => Some(Duration::from_secs(257)),
+            _ => Some(Duration::from_secs(301)),
+        };
+        assert_eq!(
+            find_match(&song("Nirvana", "Lithium", Some(300)), &tracks, durations),
+            Some(2)
+        );
+        assert_eq!(
+            find_match(&song("Nirvana", "Lithium", Some(400)), &tracks, durations),
+            None
+        );
+    }
+
+    #[test]
+    fn exports_are_read() -> anyhow::Result<()> {
+        let api = r#"{"name": "Road trip", "tracks": {"next": null, "items": [
+            {"track": {"name": "Get Lucky", "duration_ms": 248000,
+                "artists": [{"name": "Daft Punk"}, {"name": "Pharrell Williams"}]}},
+            {"track": null}
+        ]}}"#;
+        let playlist = read_export(api, None)?;
+        assert_eq!(playlist.name, "Road trip");
+        assert_eq!(
+            playlist.songs,
+            vec![SpotifySong {
+                artists: vec!["Daft Punk".to_string(), "Pharrell Williams".to_string()],
+                title: "Get Lucky".to_string(),
+                duration: Some(Duration::from_secs(248)),
+            }]
+        );
+
+        let data = r#"{"playlists": [
+            {"name": "Road trip", "items": [
+                {"track": {"trackName": "Halo", "artistName": "Beyoncé"}},
+                {"track": null, "episode": {"episodeName": "News"}}
+            ]},
+            {"name": "Focus", "items": []}
+        ]}"#;
+        assert!(read_export(data, None).is_err());
+        let playlist = read_export(data, Some("Road trip"))?;
+        assert_eq!(playlist.songs, vec![song("Beyoncé", "Halo", None)]);
+        assert!(read_export(data, Some("Party")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn playlist_links_are_recognized() {
+        assert_eq!(
+            playlist_id("https://open.spotify.com/playlist/37i9dQZF1DX0XUsuxWHRQd?si=ab12"),
+            Some("37i9dQZF1DX0XUsuxWHRQd")
+        );
+        assert_eq!(
+            playlist_id("https://open.spotify.com/intl-de/playlist/37i9dQZF1DX0XUsuxWHRQd"),
+            Some("37i9dQZF1DX0XUsuxWHRQd")
+        );
+        assert_eq!(
+            playlist_id("spotify:playlist:37i9dQZF1DX0XUsuxWHRQd"),
+            Some("37i9dQZF1DX0XUsuxWHRQd")
+        );
+        assert_eq!(playlist_id("road-trip.json"), None);
+        assert_eq!(playlist_id("https://open.spotify.com/album/1"), None);
+    }
+}
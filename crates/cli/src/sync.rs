@@ -0,0 +1,326 @@
+//! `localdeck sync`: pulls metadata, playlists and optionally audio files from another
+//! localdeck instance through its `/sync` endpoint.
+//!
+//! Tracks are matched by the content hashes of their files, track ids of the two
+//! libraries are unrelated. Metadata and playlists already present here and differing
+//! from the other instance are conflicts: they are kept and reported, unless remote
+//! data is preferred.
+
+use anyhow::Context;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use ureq::Agent;
+
+use localdeck_storage::{
+    file_hash::{FileHash, HashKind},
+    operations::{HashedFile, MetadataUpdate},
+    remote::{SyncLibrary, SyncTrack},
+    store::LibraryStore,
+    track::{TrackId, TrackMetadata},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct SyncOptions {
+    /// directory inside a library root to save tracks missing here to
+    pub download_dir: Option<PathBuf>,
+    /// overwrite differing metadata and playlists instead of reporting conflicts
+    pub prefer_remote: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub matched_tracks: usize,
+    pub metadata_added: usize,
+    pub metadata_replaced: usize,
+    pub downloaded: usize,
+    /// tracks of the other instance without a file here
+    pub missing_tracks: usize,
+    pub playlists_created: usize,
+    pub playlists_replaced: usize,
+    /// what was left as it is, one line per conflict
+    pub conflicts: Vec<String>,
+}
+
+/// Remote track ids mapped to track ids of this library
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TrackMatches {
+    matched: HashMap<TrackId, TrackId>,
+    /// remote tracks without any file here
+    missing: Vec<TrackId>,
+    /// remote tracks whose files belong to several tracks here
+    ambiguous: Vec<(TrackId, Vec<TrackId>)>,
+}
+
+/// Matches tracks by content hash. Hashes of different kinds are never equal
+fn match_tracks(remote: &[SyncTrack], local: &[(TrackId, HashedFile)]) -> TrackMatches {
+    let mut by_hash: HashMap<(HashKind, String), BTreeSet<TrackId>> = HashMap::new();
+    for (track_id, file) in local {
+        by_hash
+            .entry((file.kind, file.hash.to_hex()))
+            .or_default()
+            .insert(*track_id);
+    }
+    let mut matches = TrackMatches::default();
+    for track in remote {
+        let candidates: BTreeSet<TrackId> = track
+            .files
+            .iter()
+            .filter_map(|file| by_hash.get(&(file.kind, file.hash.clone())))
+            .flatten()
+            .copied()
+            .collect();
+        let candidates: Vec<TrackId> = candidates.into_iter().collect();
+        match candidates.as_slice() {
+            [] => matches.missing.push(track.track_id),
+            [local] => {
+                matches.matched.insert(track.track_id, *local);
+            }
+            _ => matches.ambiguous.push((track.track_id, candidates)),
+        }
+    }
+    matches
+}
+
+pub struct SyncClient {
+    agent: Agent,
+    base_url: String,
+}
+
+impl SyncClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            agent: Agent::config_builder()
+                .timeout_connect(Some(CONNECT_TIMEOUT))
+                .build()
+                .into(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn library(&self) -> anyhow::Result<SyncLibrary> {
+        let url = format!("{}/sync", self.base_url);
+        self.agent
+            .get(&url)
+            .call()
+            .with_context(|| format!("failed to reach {url}"))?
+            .body_mut()
+            .with_config()
+            .limit(u64::MAX)
+            .read_json()
+            .with_context(|| format!("{url} did not return a library, is it localdeck?"))
+    }
+
+    /// Saves the stream of a remote track, returns `false` if the received file
+    /// has none of the hashes the other instance recorded
+    fn download(&self, track: &SyncTrack, dest: &Path) -> anyhow::Result<bool> {
+        let url = format!("{}/tracks/{}/stream", self.base_url, track.track_id);
+        let mut response = self
+            .agent
+            .get(&url)
+            .call()
+            .with_context(|| format!("failed to download {url}"))?;
+        let mut file = File::create(dest)
+            .with_context(|| format!("failed to create {}", dest.to_string_lossy()))?;
+        io::copy(&mut response.body_mut().as_reader(), &mut file)?;
+        drop(file);
+
+        for file in &track.files {
+            if FileHash::from_file_with(dest, file.kind)?.to_hex() == file.hash {
+                return Ok(true);
+            }
+        }
+        fs::remove_file(dest)?;
+        Ok(false)
+    }
+
+    pub fn sync(
+        &self,
+        storage: &mut dyn LibraryStore,
+        options: &SyncOptions,
+    ) -> anyhow::Result<SyncReport> {
+        let remote = self.library()?;
+        let mut report = SyncReport::default();
+
+        let mut matches = match_tracks(&remote.tracks, &storage.list_hashed_files()?);
+        if let Some(dir) = &options.download_dir
+            && !matches.missing.is_empty()
+        {
+            fs::create_dir_all(dir)?;
+            for track in remote
+                .tracks
+                .iter()
+                .filter(|t| matches.missing.contains(&t.track_id))
+            {
+                let dest = download_path(dir, track);
+                if self.download(track, &dest)? {
+                    report.downloaded += 1;
+                } else {
+                    report.conflicts.push(format!(
+                        "remote track {} sent a file with unexpected content, discarded it",
+                        track.track_id
+                    ));
+                }
+            }
+            // downloaded files get their track ids like any new file
+            storage.update_db_with_new_files()?;
+            matches = match_tracks(&remote.tracks, &storage.list_hashed_files()?);
+        }
+        report.matched_tracks = matches.matched.len();
+        report.missing_tracks = matches.missing.len();
+        for (remote_id, local) in &matches.ambiguous {
+            report.conflicts.push(format!(
+                "remote track {remote_id} has files of tracks {local:?} here, merge them first"
+            ));
+        }
+
+        for track in &remote.tracks {
+            let (Some(local_id), Some(theirs)) =
+                (matches.matched.get(&track.track_id), &track.metadata)
+            else {
+                continue;
+            };
+            match storage.get_track_metadata(*local_id)? {
+                None => {
+                    storage.update_track_metadata(*local_id, to_update(theirs), false)?;
+                    report.metadata_added += 1;
+                }
+                Some(ours) if same_metadata(&ours, theirs) => {}
+                Some(_) if options.prefer_remote => {
+                    storage.update_track_metadata(*local_id, to_update(theirs), true)?;
+                    report.metadata_replaced += 1;
+                }
+                Some(ours) => report.conflicts.push(format!(
+                    "track {local_id}: kept '{} - {}', remote has '{} - {}'",
+                    ours.artist, ours.title, theirs.artist, theirs.title
+                )),
+            }
+        }
+
+        let local_playlists = storage.list_playlists()?;
+        for playlist in &remote.playlists {
+            let tracks: Vec<TrackId> = playlist
+                .tracks
+                .iter()
+                .filter_map(|id| matches.matched.get(id).copied())
+                .collect();
+            match local_playlists.iter().find(|p| p.name == playlist.name) {
+                None => {
+                    let created = storage.create_playlist(&playlist.name)?;
+                    storage.set_playlist_tracks(created.id, &tracks)?;
+                    report.playlists_created += 1;
+                }
+                Some(ours) if ours.tracks == tracks => {}
+                Some(ours) if options.prefer_remote => {
+                    storage.set_playlist_tracks(ours.id, &tracks)?;
+                    report.playlists_replaced += 1;
+                }
+                Some(_) => report.conflicts.push(format!(
+                    "playlist '{}' differs from the remote one, kept it",
+                    playlist.name
+                )),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Remote artwork links only replace local ones when remote data is preferred
+fn same_metadata(ours: &TrackMetadata, theirs: &TrackMetadata) -> bool {
+    ours.artist == theirs.artist
+        && ours.title == theirs.title
+        && ours.year == theirs.year
+        && ours.label == theirs.label
+        && (theirs.artwork.is_none() || ours.artwork == theirs.artwork)
+}
+
+fn to_update(metadata: &TrackMetadata) -> MetadataUpdate {
+    MetadataUpdate {
+        artist: Some(metadata.artist.clone()),
+        title: Some(metadata.title.clone()),
+        year: metadata.year,
+        label: metadata.label.clone(),
+        artwork: metadata.artwork.clone(),
+    }
+}
+
+/// Remote file name, prefixed with the remote track id if the name is taken
+fn download_path(dir: &Path, track: &SyncTrack) -> PathBuf {
+    let name = track
+        .files
+        .iter()
+        .find_map(|f| f.name.clone())
+        .unwrap_or_else(|| format!("track-{}", track.track_id));
+    let path = dir.join(&name);
+    if path.exists() {
+        dir.join(format!("{}-{name}", track.track_id))
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use localdeck_storage::{
+        file_hash::{FileHash, HashKind},
+        location::Location,
+        operations::{FileWithMeta, HashedFile},
+        remote::{SyncFile, SyncTrack},
+    };
+
+    use super::match_tracks;
+
+    fn local_file(x: u8, kind: HashKind) -> HashedFile {
+        HashedFile::with_kind(
+            FileHash::from_bytes(&[x]),
+            kind,
+            FileWithMeta {
+                loc: Location::from_path(format!("/music/{x}.mp3")),
+                file_size: 1,
+            },
+        )
+    }
+
+    fn remote_track(track_id: i64, hashes: &[u8], kind: HashKind) -> SyncTrack {
+        SyncTrack {
+            track_id,
+            files: hashes
+                .iter()
+                .map(|x| SyncFile {
+                    hash: FileHash::from_bytes(&[*x]).to_hex(),
+                    kind,
+                    file_size: 1,
+                    name: None,
+                })
+                .collect(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn tracks_are_matched_by_content_hash() {
+        let local = vec![
+            (1, local_file(1, HashKind::Full)),
+            (2, local_file(2, HashKind::Full)),
+            (3, local_file(3, HashKind::Full)),
+            (4, local_file(4, HashKind::Quick)),
+        ];
+        let remote = vec![
+            remote_track(10, &[1], HashKind::Full),
+            remote_track(20, &[2, 3], HashKind::Full),
+            remote_track(30, &[5], HashKind::Full),
+            // same bytes, hashed another way
+            remote_track(40, &[4], HashKind::Full),
+        ];
+        let matches = match_tracks(&remote, &local);
+        assert_eq!(matches.matched.len(), 1);
+        assert_eq!(matches.matched[&10], 1);
+        assert_eq!(matches.ambiguous, vec![(20, vec![2, 3])]);
+        assert_eq!(matches.missing, vec![30, 40]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Generation of systemd unit files for `localdeck serve`
+
+use anyhow::{Context, bail};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+const UNIT_NAME: &str = "localdeck.service";
+
+/// Renders a `Type=notify` unit that runs `serve` with the given config file
+pub fn unit_file(exe: &Path, config: &Path, user: Option<&str>) -> String {
+    let mut service = vec![
+        "Type=notify".to_string(),
+        "WatchdogSec=30".to_string(),
+        "Restart=on-failure".to_string(),
+        "ExecReload=/bin/kill -HUP $MAINPID".to_string(),
+        "Environment=RUST_LOG=info".to_string(),
+        format!(
+            "ExecStart=\"{}\" -c \"{}\" serve",
+            exe.to_string_lossy(),
+            config.to_string_lossy()
+        ),
+    ];
+    if let Some(user) = user {
+        service.push(format!("User={user}"));
+    }
+    let wanted_by = if user.is_some() {
+        "multi-user.target"
+    } else {
+        "default.target"
+    };
+
+    format!(
+        "[Unit]
+Description=LOCALDECK music library server
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+{}
+
+[Install]
+WantedBy={wanted_by}
+",
+        service.join("\n")
+    )
+}
+
+/// Where the unit file is written: the user's systemd directory, or /etc/systemd/system for a system unit
+fn unit_path(system: bool) -> anyhow::Result<PathBuf> {
+    if system {
+        return Ok(PathBuf::from("/etc/systemd/system").join(UNIT_NAME));
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME").context("HOME is not set")?).join(".config"),
+    };
+    Ok(config_home.join("systemd").join("user").join(UNIT_NAME))
+}
+
+/// Account a system unit runs as: `explicit` if given, else the user who ran sudo, else
+/// the current one. Root only when asked for explicitly, a music server needs no more
+/// than the rights of its owner
+fn service_user(
+    explicit: Option<&str>,
+    sudo_user: Option<&str>,
+    user: Option<&str>,
+) -> anyhow::Result<String> {
+    if let Some(explicit) = explicit {
+        return Ok(explicit.to_string());
+    }
+    match sudo_user.or(user) {
+        Some("root") => bail!(
+            "the system unit would run as root, pass --user <NAME> to pick the account, --user root to keep root"
+        ),
+        Some(user) => Ok(user.to_string()),
+        None => bail!("USER is not set, pass --user <NAME> to pick the account"),
+    }
+}
+
+/// Writes a unit file for the current executable and config, returning its path.
+/// A system unit runs as `user`, see [`service_user`]
+pub fn install_unit(config: &Path, system: bool, user: Option<&str>) -> anyhow::Result<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        bail!("systemd units can only be installed on linux");
+    }
+    let exe = env::current_exe().context("Failed to locate the localdeck executable")?;
+    let config = config
+        .canonicalize()
+        .with_context(|| format!("Failed to locate config file {}", config.to_string_lossy()))?;
+
+    // a system unit would otherwise run as root
+    let user = if system {
+        let var = |name: &str| env::var(name).ok();
+        Some(service_user(
+            user,
+            var("SUDO_USER").as_deref(),
+            var("USER").as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    let path = unit_path(system)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.to_string_lossy()))?;
+    }
+    std::fs::write(&path, unit_file(&exe, &config, user.as_deref()))
+        .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_unit_file() {
+        let unit = unit_file(
+            Path::new("/opt/localdeck/localdeck"),
+            Path::new("/home/me/config.toml"),
+            None,
+        );
+
+        assert!(unit.contains("Type=notify"));
+        assert!(unit.contains("ExecReload=/bin/kill -HUP $MAINPID"));
+        assert!(
+            unit.contains(
+                "ExecStart=\"/opt/localdeck/localdeck\" -c \"/home/me/config.toml\" serve"
+            )
+        );
+        assert!(unit.contains("WantedBy=default.target"));
+        assert!(!unit.contains("User="));
+    }
+
+    #[test]
+    fn test_system_unit_file_sets_user() {
+        let unit = unit_file(
+            Path::new("/opt/localdeck/localdeck"),
+            Path::new("/home/me/config.toml"),
+            Some("me"),
+        );
+
+        assert!(unit.contains("User=me"));
+        assert!(unit.contains("WantedBy=multi-user.target"));
+    }
+
+    #[test]
+    fn test_system_unit_under_sudo_runs_as_the_sudo_user() {
+        assert_eq!(service_user(None, Some("me"), Some("root")).unwrap(), "me");
+        assert_eq!(service_user(None, None, Some("me")).unwrap(), "me");
+        assert!(service_user(None, None, Some("root")).is_err());
+        assert!(service_user(None, Some("root"), Some("root")).is_err());
+        assert!(service_user(None, None, None).is_err());
+        assert_eq!(
+            service_user(Some("root"), Some("me"), Some("root")).unwrap(),
+            "root"
+        );
+        assert_eq!(
+            service_user(Some("music"), Some("me"), Some("root")).unwrap(),
+            "music"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+// Service worker of the scanner page.
+//
+// Keeps the page shell cached, so the installed app opens instantly and survives
+// a flaky party Wi-Fi. The page is fetched from the network first, to pick up
+// theme and language changes. Tracks, lyrics and other API responses are never cached.
+
+const CACHE = "localdeck-shell-v1";
+const SHELL = [
+    "/scan_qr",
+    "/manifest.webmanifest",
+    "/icon.svg",
+    "https://unpkg.com/jsqr/dist/jsQR.js",
+];
+
+self.addEventListener("install", event => {
+    event.waitUntil(
+        caches.open(CACHE)
+            .then(cache => cache.addAll(SHELL))
+            .then(() => self.skipWaiting())
+    );
+});
+
+self.addEventListener("activate", event => {
+    // drop caches of older versions
+    event.waitUntil(
+        caches.keys()
+            .then(keys => Promise.all(keys.filter(key => key !== CACHE).map(key => caches.delete(key))))
+            .then(() => self.clients.claim())
+    );
+});
+
+self.addEventListener("fetch", event => {
+    const request = event.request;
+    if (request.method !== "GET") return;
+
+    const url = new URL(request.url);
+    const isShell = SHELL.includes(url.origin === self.location.origin ? url.pathname : url.href)
+        || url.pathname.startsWith("/theme/");
+    if (!isShell) return;
+
+    event.respondWith(
+        fetch(request)
+            .then(response => {
+                if (response.ok) {
+                    const copy = response.clone();
+                    caches.open(CACHE).then(cache => cache.put(request, copy));
+                }
+                return response;
+            })
+            .catch(() => caches.match(request))
+    );
+});
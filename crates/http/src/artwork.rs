@@ -0,0 +1,100 @@
+//! Covers served by `GET /tracks/{id}/artwork`.
+//!
+//! A cover in the artwork store may have smaller copies in other formats next to it,
+//! e.g. `12.avif` and `12.webp` beside `12.jpg`. Clients naming those formats in `Accept`
+//! get a copy, all others the original. Covers on other hosts are redirected to.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use rouille::Response;
+
+use crate::error::ApiError;
+
+/// Extension and type of the copies a cover can have, smallest first
+const VARIANTS: [(&str, &str); 2] = [("avif", "image/avif"), ("webp", "image/webp")];
+/// Covers rarely change, and a changed one is picked up within the hour
+const CACHE_CONTROL: &str = "private, max-age=3600";
+
+/// Formats of [`VARIANTS`] the client accepts, the preferred one first.
+///
+/// Only formats named in the header count: browsers send `image/*` without
+/// being able to decode AVIF
+fn accepted_variants(accept: &str) -> Vec<(&'static str, &'static str)> {
+    let mut accepted: Vec<_> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let mime = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            let rank = VARIANTS
+                .iter()
+                .position(|(_, m)| mime.eq_ignore_ascii_case(m))?;
+            (quality > 0.0).then_some((quality, rank))
+        })
+        .collect();
+    // highest quality first, smaller formats win ties
+    accepted.sort_by(|(q1, r1), (q2, r2)| q2.total_cmp(q1).then(r1.cmp(r2)));
+    accepted
+        .into_iter()
+        .map(|(_, rank)| VARIANTS[rank])
+        .collect()
+}
+
+/// File to send for the cover at `original` and its content type
+fn pick(original: &Path, accept: Option<&str>) -> (PathBuf, String) {
+    let variant = accept
+        .map(accepted_variants)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(ext, mime)| (original.with_extension(ext), mime))
+        .find(|(path, _)| path != original && path.is_file());
+    match variant {
+        Some((path, mime)) => (path, mime.to_string()),
+        None => (
+            original.to_path_buf(),
+            mime_guess::from_path(original)
+                .first_or_octet_stream()
+                .to_string(),
+        ),
+    }
+}
+
+/// Response for the artwork reference of a track
+pub(crate) fn respond(artwork: &str, accept: Option<&str>) -> Result<Response, ApiError> {
+    if artwork.starts_with("http://") || artwork.starts_with("https://") {
+        return Ok(Response::redirect_302(artwork.to_string()));
+    }
+    let (path, mime) = pick(Path::new(artwork), accept);
+    let file = File::open(&path)
+        .map_err(|e| ApiError::NotFound(format!("cover {}: {e}", path.display())))?;
+    Ok(Response::from_file(mime, file)
+        .with_additional_header("Vary", "Accept")
+        .with_additional_header("Cache-Control", CACHE_CONTROL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accepted_variants;
+
+    #[test]
+    fn named_formats_are_accepted() {
+        let exts = |accept| {
+            accepted_variants(accept)
+                .into_iter()
+                .map(|(ext, _)| ext)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            exts("image/avif,image/webp,image/apng,image/*,*/*;q=0.8"),
+            vec!["avif", "webp"]
+        );
+        assert_eq!(exts("image/webp,image/avif;q=0.5"), vec!["webp", "avif"]);
+        assert_eq!(exts("image/avif;q=0, image/webp"), vec!["webp"]);
+        assert!(exts("image/*,*/*").is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use log::debug;
+use rouille::{Request, Response};
+use serde::Deserialize;
+
+use crate::{HttpConfig, error::ApiError};
+
+const REALM: &str = "localdeck";
+
+/// Username and password every request has to carry, for servers behind
+/// reverse proxies that do no authentication themselves.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BasicAuth {
+    pub user: String,
+    pub password: String,
+}
+
+impl BasicAuth {
+    fn accepts(&self, request: &Request) -> bool {
+        rouille::input::basic_http_auth(request).is_some_and(|credentials| {
+            // evaluate both so the response time does not reveal which one was wrong
+            let user = constant_time_eq(&credentials.login, &self.user);
+            let password = constant_time_eq(&credentials.password, &self.password);
+            user & password
+        })
+    }
+}
+
+/// What the holder of an [`ApiToken`] may do. Roles are ordered, an admin can
+/// do everything a listener can.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// browse the library and stream tracks
+    Listener,
+    /// additionally change metadata and playlists
+    Admin,
+}
+
+impl Role {
+    /// The role a route needs: everything that only reads is open to
+    /// listeners, every other method changes the library.
+    fn required_for(request: &Request) -> Role {
+        match (request.method(), request.url().as_str()) {
+            ("GET" | "HEAD", _) => Role::Listener,
+            // guests add the cards they scan, reordering is up to the host
+            ("POST", "/queue") => Role::Listener,
+            // only reads, but the ids do not fit into a query string
+            ("POST", "/tracks/batch") => Role::Listener,
+            _ => Role::Admin,
+        }
+    }
+}
+
+/// A named secret sent as `Authorization: Bearer <token>`, or as the
+/// `access_token` query parameter where a header cannot be set, e.g. by `<audio>`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiToken {
+    /// shown in the logs instead of the token itself
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+/// Checks the request against the configured [`BasicAuth`] and [`ApiToken`]s.
+/// Either one admits a request; Basic auth credentials act as an admin.
+/// Card routes skip the check if [`HttpConfig::public_card_routes`] is set.
+pub(crate) fn authorize(config: &HttpConfig, request: &Request) -> Result<(), Response> {
+    let basic_auth = config.basic_auth.as_ref();
+    if basic_auth.is_none() && config.tokens.is_empty() {
+        return Ok(());
+    }
+    let url = request.url();
+    let card_route =
+        url == "/play" || url == "/play-list" || url == "/random" || url.starts_with("/s/");
+    if card_route && config.public_card_routes {
+        return Ok(());
+    }
+
+    if let Some(presented) = presented_token(request) {
+        let Some(token) = config
+            .tokens
+            .iter()
+            .find(|token| constant_time_eq(&token.token, &presented))
+        else {
+            return Err(unauthorized("unknown API token"));
+        };
+        let required = Role::required_for(request);
+        if token.role < required {
+            return Err(ApiError::Forbidden(format!(
+                "token '{}' is not allowed to {} {}",
+                token.name,
+                request.method(),
+                request.url()
+            ))
+            .into_response());
+        }
+        debug!("authorized with token '{}'", token.name);
+        return Ok(());
+    }
+
+    match basic_auth {
+        Some(auth) if auth.accepts(request) => Ok(()),
+        Some(_) => Err(Response::basic_http_auth_login_required(REALM)),
+        None => Err(unauthorized("an API token is required")),
+    }
+}
+
+/// Whether the request carries the configured Basic auth credentials or an admin token.
+/// Always false if neither is configured
+pub(crate) fn is_admin(config: &HttpConfig, request: &Request) -> bool {
+    let token = presented_token(request).is_some_and(|presented| {
+        config
+            .tokens
+            .iter()
+            .any(|token| token.role == Role::Admin && constant_time_eq(&token.token, &presented))
+    });
+    token
+        || config
+            .basic_auth
+            .as_ref()
+            .is_some_and(|auth| auth.accepts(request))
+}
+
+fn presented_token(request: &Request) -> Option<String> {
+    let bearer = request.header("Authorization").and_then(|value| {
+        let (scheme, token) = value.split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim().to_string())
+    });
+    bearer.or_else(|| request.get_param("access_token"))
+}
+
+fn unauthorized(reason: &str) -> Response {
+    ApiError::Unauthorized(reason.to_string())
+        .into_response()
+        .with_additional_header("WWW-Authenticate", format!("Bearer realm=\"{REALM}\""))
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_config(sections: &str) -> HttpConfig {
+        toml::from_str(&format!(
+            "bind_addr = \"127.0.0.1\"\nport = 8080\n{sections}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn credentials_are_parsed() {
+        let config = http_config(
+            r#"
+public_card_routes = false
+
+[basic_auth]
+user = "sasha"
+password = "hunter2"
+
+[[tokens]]
+name = "family"
+token = "family-secret"
+role = "listener"
+"#,
+        );
+        let auth = config.basic_auth.expect("basic auth section is parsed");
+        assert_eq!(
+            (auth.user.as_str(), auth.password.as_str()),
+            ("sasha", "hunter2")
+        );
+        assert_eq!(config.tokens[0].name, "family");
+        assert_eq!(config.tokens[0].role, Role::Listener);
+        assert!(!config.public_card_routes);
+
+        let open = http_config("");
+        assert!(open.basic_auth.is_none());
+        assert!(open.tokens.is_empty());
+        assert!(open.public_card_routes);
+    }
+}
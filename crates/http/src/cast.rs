@@ -0,0 +1,109 @@
+//! Casting tracks to Chromecast and Google speakers.
+//!
+//! The scanner page asks `/tracks/<id>/cast` for a description of the track
+//! and hands it to the default media receiver, which then streams the track from this server.
+//! The receiver fetches the stream cross-origin, hence the CORS headers.
+//! Browsers only offer casting on pages served over HTTPS, e.g. behind a reverse proxy.
+
+use rouille::{Request, Response};
+use serde::{Deserialize, Serialize};
+
+use localdeck_storage::track::{TrackId, TrackMetadata};
+
+/// `MUSIC_TRACK` metadata type of the Cast SDK
+const MUSIC_TRACK: u8 = 3;
+
+/// Adds the CORS headers cast receivers need to stream tracks and read their metadata
+pub(crate) fn with_cors(response: Response) -> Response {
+    response
+        .with_unique_header("Access-Control-Allow-Origin", "*")
+        .with_unique_header(
+            "Access-Control-Expose-Headers",
+            "Accept-Ranges, Content-Length, Content-Range, X-Track-Artist, X-Track-Title",
+        )
+}
+
+/// Answer to a CORS preflight, receivers send one before requesting byte ranges
+pub(crate) fn preflight() -> Response {
+    with_cors(Response::empty_204())
+        .with_unique_header("Access-Control-Allow-Methods", "GET, HEAD, OPTIONS")
+        .with_unique_header("Access-Control-Allow-Headers", "Range, Content-Type")
+        .with_unique_header("Access-Control-Max-Age", "86400")
+}
+
+/// `MediaInfo` of the Cast SDK
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CastMedia {
+    pub content_id: String,
+    pub content_type: String,
+    pub stream_type: String,
+    pub metadata: CastMetadata,
+}
+
+/// `MusicTrackMediaMetadata` of the Cast SDK
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CastMetadata {
+    pub metadata_type: u8,
+    pub title: String,
+    pub artist: Option<String>,
+    pub release_date: Option<String>,
+    pub images: Vec<CastImage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CastImage {
+    pub url: String,
+}
+
+impl CastMedia {
+    pub(crate) fn new(
+        request: &Request,
+        track: TrackId,
+        mime: String,
+        metadata: Option<TrackMetadata>,
+    ) -> Self {
+        let metadata = match metadata {
+            Some(meta) => CastMetadata {
+                metadata_type: MUSIC_TRACK,
+                title: meta.title,
+                artist: Some(meta.artist),
+                release_date: meta.year.map(|year| year.to_string()),
+                // receivers can only show artwork they can download
+                images: meta
+                    .artwork
+                    .map(|artwork| artwork.0)
+                    .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+                    .map(|url| CastImage { url })
+                    .into_iter()
+                    .collect(),
+            },
+            None => CastMetadata {
+                metadata_type: MUSIC_TRACK,
+                title: format!("Track {track}"),
+                artist: None,
+                release_date: None,
+                images: vec![],
+            },
+        };
+        CastMedia {
+            content_id: format!("{}/tracks/{track}/stream", base_url(request)),
+            content_type: mime,
+            stream_type: "BUFFERED".to_string(),
+            metadata,
+        }
+    }
+}
+
+/// URL of this server as the client reached it, the receiver must reach it the same way
+fn base_url(request: &Request) -> String {
+    let scheme = request
+        .header("X-Forwarded-Proto")
+        .unwrap_or(if request.is_secure() { "https" } else { "http" });
+    let host = request
+        .header("X-Forwarded-Host")
+        .or(request.header("Host"))
+        .unwrap_or("localhost");
+    format!("{scheme}://{host}")
+}
@@ -2,13 +2,24 @@ use rouille::Response;
 
 use localdeck_storage::error::StorageError;
 
+/// A track usually ends within a few seconds somewhere at a party
+const BUSY_RETRY_AFTER_SECS: u32 = 5;
+
 #[derive(Debug)]
 pub enum ApiError {
     NotFound(String),
     BadRequest(String),
     Internal(String),
-    /// invalid byte range requested
-    InvalidRange,
+    /// another localdeck instance holding the track did not answer
+    BadGateway(String),
+    /// invalid byte range requested of a file with the given size
+    InvalidRange(u64),
+    /// no API token, or one that is not configured
+    Unauthorized(String),
+    /// the API token's role does not allow the request
+    Forbidden(String),
+    /// too many tracks are streaming, the client should retry shortly
+    Busy(String),
 }
 
 impl ApiError {
@@ -17,7 +28,11 @@ impl ApiError {
             ApiError::NotFound(_) => 404,
             ApiError::BadRequest(_) => 400,
             ApiError::Internal(_) => 500,
-            ApiError::InvalidRange => 416,
+            ApiError::BadGateway(_) => 502,
+            ApiError::InvalidRange(_) => 416,
+            ApiError::Unauthorized(_) => 401,
+            ApiError::Forbidden(_) => 403,
+            ApiError::Busy(_) => 503,
         }
     }
 }
@@ -42,6 +57,10 @@ impl From<StorageError> for ApiError {
             StorageError::RequiredMetaMissing(_) => ApiError::BadRequest(err.to_string()),
             StorageError::SlaveTrackHasMetadata(_) => ApiError::BadRequest(err.to_string()),
             StorageError::PathOutsideLibrary(_) => ApiError::BadRequest(err.to_string()),
+            StorageError::SnapshotNotFound(_) => ApiError::NotFound(err.to_string()),
+            StorageError::PlaylistNotFound(_) => ApiError::NotFound(err.to_string()),
+            StorageError::PlaylistExists(_) => ApiError::BadRequest(err.to_string()),
+            StorageError::InvalidQuery(_) => ApiError::BadRequest(err.to_string()),
         }
     }
 }
@@ -49,10 +68,16 @@ impl From<StorageError> for ApiError {
 impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ApiError::NotFound(msg) | ApiError::BadRequest(msg) | ApiError::Internal(msg) => {
+            ApiError::NotFound(msg)
+            | ApiError::BadRequest(msg)
+            | ApiError::Internal(msg)
+            | ApiError::BadGateway(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::Busy(msg) => {
                 write!(f, "{}", msg)
             }
-            ApiError::InvalidRange => {
+            ApiError::InvalidRange(_) => {
                 write!(f, "invalid byte range")
             }
         }
@@ -61,6 +86,16 @@ impl std::fmt::Display for ApiError {
 
 impl ApiError {
     pub fn into_response(self) -> Response {
-        Response::text(format!("{self}")).with_status_code(self.status_code())
+        let response = Response::text(format!("{self}")).with_status_code(self.status_code());
+        match self {
+            // tells the client how much there is to ask for
+            ApiError::InvalidRange(size) => {
+                response.with_additional_header("Content-Range", format!("bytes */{size}"))
+            }
+            ApiError::Busy(_) => {
+                response.with_additional_header("Retry-After", BUSY_RETRY_AFTER_SECS.to_string())
+            }
+            _ => response,
+        }
     }
 }
@@ -0,0 +1,216 @@
+//! Translations of the texts guests see after scanning a card or opening a shared link:
+//! the scanner, library, playlist and party queue pages and the errors of /play. The language comes from
+//! the `Accept-Language` header, then from the `language` of [`HttpConfig`](crate::HttpConfig),
+//! then English.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Ru,
+    De,
+}
+
+/// Texts of one language, the scanner page receives them as JSON
+#[derive(Debug, Serialize)]
+pub struct Texts {
+    pub lang: &'static str,
+    pub scanner_title: &'static str,
+    pub waiting: &'static str,
+    pub valid_qr: &'static str,
+    pub playing_track: &'static str,
+    pub playback_error: &'static str,
+    pub could_not_load: &'static str,
+    pub playback_failed: &'static str,
+    pub invalid_qr: &'static str,
+    pub no_hash: &'static str,
+    pub read: &'static str,
+    pub reason: &'static str,
+    pub camera_error: &'static str,
+    pub casting_to: &'static str,
+    pub cast_failed: &'static str,
+    pub missing_hash: &'static str,
+    pub track_not_found: &'static str,
+    pub playlist_not_found: &'static str,
+    pub playlist_empty: &'static str,
+    pub library_title: &'static str,
+    pub search: &'static str,
+    pub no_matches: &'static str,
+    pub track: &'static str,
+    pub queue_title: &'static str,
+    pub add_to_queue: &'static str,
+    pub added_to_queue: &'static str,
+    pub queue_failed: &'static str,
+    pub queue_empty: &'static str,
+    pub skip: &'static str,
+}
+
+const EN: Texts = Texts {
+    lang: "en",
+    scanner_title: "QR Scanner",
+    waiting: "Waiting for QR...",
+    valid_qr: "VALID QR",
+    playing_track: "Playing track:",
+    playback_error: "PLAYBACK ERROR ❌",
+    could_not_load: "Could not load or decode audio",
+    playback_failed: "Playback failed (blocked, invalid, or unsupported)",
+    invalid_qr: "INVALID QR",
+    no_hash: "Could not extract track hash",
+    read: "Read:",
+    reason: "Reason:",
+    camera_error: "Camera error:",
+    casting_to: "Playing on",
+    cast_failed: "Could not cast the track:",
+    missing_hash: "Error: missing media hash",
+    track_not_found: "This card's track is not in the library",
+    playlist_not_found: "This playlist does not exist or the link is incomplete",
+    playlist_empty: "This playlist is empty",
+    library_title: "Library",
+    search: "Search artist or title",
+    no_matches: "No tracks match",
+    track: "Track",
+    queue_title: "Party queue",
+    add_to_queue: "Add scanned cards to the party queue",
+    added_to_queue: "Added to the party queue at position",
+    queue_failed: "Could not add the track to the queue:",
+    queue_empty: "The queue is empty, scan a card to add a track",
+    skip: "Skip",
+};
+
+const RU: Texts = Texts {
+    lang: "ru",
+    scanner_title: "Сканер QR",
+    waiting: "Ожидание QR-кода...",
+    valid_qr: "QR-КОД РАСПОЗНАН",
+    playing_track: "Играет трек:",
+    playback_error: "ОШИБКА ВОСПРОИЗВЕДЕНИЯ ❌",
+    could_not_load: "Не удалось загрузить или декодировать аудио",
+    playback_failed: "Воспроизведение не удалось (заблокировано, повреждено или не поддерживается)",
+    invalid_qr: "НЕВЕРНЫЙ QR-КОД",
+    no_hash: "Не удалось найти в коде идентификатор трека",
+    read: "Прочитано:",
+    reason: "Причина:",
+    camera_error: "Ошибка камеры:",
+    casting_to: "Играет на",
+    cast_failed: "Не удалось передать трек на устройство:",
+    missing_hash: "Ошибка: не указан идентификатор трека",
+    track_not_found: "Трека с этой карточки нет в библиотеке",
+    playlist_not_found: "Плейлист не найден или ссылка неполная",
+    playlist_empty: "Плейлист пуст",
+    library_title: "Библиотека",
+    search: "Поиск по исполнителю или названию",
+    no_matches: "Подходящих треков нет",
+    track: "Трек",
+    queue_title: "Очередь вечеринки",
+    add_to_queue: "Добавлять отсканированные карточки в очередь",
+    added_to_queue: "Трек добавлен в очередь, место",
+    queue_failed: "Не удалось добавить трек в очередь:",
+    queue_empty: "Очередь пуста, отсканируйте карточку, чтобы добавить трек",
+    skip: "Пропустить",
+};
+
+const DE: Texts = Texts {
+    lang: "de",
+    scanner_title: "QR-Scanner",
+    waiting: "Warte auf QR-Code...",
+    valid_qr: "QR-CODE ERKANNT",
+    playing_track: "Spiele Titel:",
+    playback_error: "WIEDERGABEFEHLER ❌",
+    could_not_load: "Audio konnte nicht geladen oder dekodiert werden",
+    playback_failed: "Wiedergabe fehlgeschlagen (blockiert, ungültig oder nicht unterstützt)",
+    invalid_qr: "UNGÜLTIGER QR-CODE",
+    no_hash: "Keine Titelkennung im Code gefunden",
+    read: "Gelesen:",
+    reason: "Grund:",
+    camera_error: "Kamerafehler:",
+    casting_to: "Wiedergabe auf",
+    cast_failed: "Titel konnte nicht gestreamt werden:",
+    missing_hash: "Fehler: Titelkennung fehlt",
+    track_not_found: "Der Titel dieser Karte ist nicht in der Bibliothek",
+    playlist_not_found: "Diese Playlist existiert nicht oder der Link ist unvollständig",
+    playlist_empty: "Diese Playlist ist leer",
+    library_title: "Bibliothek",
+    search: "Nach Interpret oder Titel suchen",
+    no_matches: "Keine passenden Titel",
+    track: "Titel",
+    queue_title: "Party-Warteschlange",
+    add_to_queue: "Gescannte Karten zur Warteschlange hinzufügen",
+    added_to_queue: "Zur Warteschlange hinzugefügt, Position",
+    queue_failed: "Titel konnte nicht zur Warteschlange hinzugefügt werden:",
+    queue_empty: "Die Warteschlange ist leer, scanne eine Karte, um einen Titel hinzuzufügen",
+    skip: "Überspringen",
+};
+
+impl Lang {
+    /// Language of a tag like `ru`, `ru-RU` or `RU`, `None` for unsupported languages
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Lang::En),
+            "ru" => Some(Lang::Ru),
+            "de" => Some(Lang::De),
+            _ => None,
+        }
+    }
+
+    /// Supported language the browser prefers most, e.g. `ru` for `fr;q=0.9, ru;q=0.8, en;q=0.5`
+    pub fn from_accept_language(header: &str) -> Option<Lang> {
+        header
+            .split(',')
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let mut parts = item.split(';');
+                let lang = Self::from_tag(parts.next()?)?;
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (quality > 0.0).then_some((quality, i, lang))
+            })
+            // highest quality first, earlier entries win ties
+            .min_by(|(q1, i1, _), (q2, i2, _)| q2.total_cmp(q1).then(i1.cmp(i2)))
+            .map(|(_, _, lang)| lang)
+    }
+
+    /// Language for a request, `default` is used if the browser accepts no supported language
+    pub fn select(accept_language: Option<&str>, default: Option<Lang>) -> Lang {
+        accept_language
+            .and_then(Self::from_accept_language)
+            .or(default)
+            .unwrap_or(Lang::En)
+    }
+
+    pub fn texts(self) -> &'static Texts {
+        match self {
+            Lang::En => &EN,
+            Lang::Ru => &RU,
+            Lang::De => &DE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lang;
+
+    #[test]
+    fn preferred_supported_language_is_selected() {
+        assert_eq!(
+            Lang::from_accept_language("fr-CH, fr;q=0.9, ru;q=0.8, en;q=0.5"),
+            Some(Lang::Ru)
+        );
+        assert_eq!(
+            Lang::from_accept_language("en;q=0.5, de-DE;q=0.7"),
+            Some(Lang::De)
+        );
+        assert_eq!(Lang::from_accept_language("ru;q=0, fr"), None);
+        assert_eq!(Lang::from_accept_language("*"), None);
+    }
+
+    #[test]
+    fn configured_language_is_the_fallback() {
+        assert_eq!(Lang::select(Some("fr"), Some(Lang::Ru)), Lang::Ru);
+        assert_eq!(Lang::select(None, None), Lang::En);
+        assert_eq!(Lang::select(Some("de"), Some(Lang::Ru)), Lang::De);
+    }
+}
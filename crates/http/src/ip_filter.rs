@@ -0,0 +1,185 @@
+//! Per-route IP allow and deny lists.
+//!
+//! Rules are checked in order and the first one matching the request's path
+//! and method decides, like a firewall. Requests no rule matches are let through.
+//! Behind a reverse proxy every request comes from the proxy's address.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use rouille::{Request, Response};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IpRule {
+    /// routes the rule covers, `/tracks` also covers `/tracks/<id>/stream`
+    #[serde(default = "every_route")]
+    pub path: String,
+    /// e.g. `["POST", "PUT", "DELETE"]`, all methods if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// only these subnets may use the routes, or everyone if empty
+    #[serde(default)]
+    pub allow: Vec<Subnet>,
+    /// subnets refused even when they are allowed
+    #[serde(default)]
+    pub deny: Vec<Subnet>,
+}
+
+fn every_route() -> String {
+    "/".to_string()
+}
+
+impl IpRule {
+    fn matches(&self, request: &Request) -> bool {
+        let prefix = self.path.trim_end_matches('/');
+        let path_matches = request
+            .url()
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        let method_matches = self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(request.method()));
+        path_matches && method_matches
+    }
+
+    fn admits(&self, ip: IpAddr) -> bool {
+        let listed = |subnets: &[Subnet]| subnets.iter().any(|subnet| subnet.contains(ip));
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+}
+
+/// Refuses the request if the first rule matching it does not admit its address.
+pub(crate) fn check(rules: &[IpRule], request: &Request) -> Result<(), Response> {
+    let Some(rule) = rules.iter().find(|rule| rule.matches(request)) else {
+        return Ok(());
+    };
+    let ip = request.remote_addr().ip().to_canonical();
+    if rule.admits(ip) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(format!(
+            "{ip} may not {} {}",
+            request.method(),
+            request.url()
+        ))
+        .into_response())
+    }
+}
+
+/// An address range in CIDR notation like `192.168.1.0/24`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Subnet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (network.to_bits() as u128, ip.to_bits() as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (network.to_bits(), ip.to_bits(), 128),
+            _ => return false,
+        };
+        let host_bits = bits - u32::from(self.prefix_len);
+        // a /0 shifts all 128 bits of an IPv6 address out
+        network.checked_shr(host_bits).unwrap_or(0) == ip.checked_shr(host_bits).unwrap_or(0)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid address in subnet '{s}': {e}"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .trim()
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in subnet '{s}'"))?,
+            None => max_len,
+        };
+        Ok(Subnet {
+            addr: addr.to_canonical(),
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnets_contain_their_addresses() {
+        let lan: Subnet = "192.168.1.0/24".parse().unwrap();
+        assert!(lan.contains("192.168.1.77".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains("fe80::1".parse().unwrap()));
+
+        let anywhere: Subnet = "0.0.0.0/0".parse().unwrap();
+        assert!(anywhere.contains("8.8.8.8".parse().unwrap()));
+        let anywhere_v6: Subnet = "::/0".parse().unwrap();
+        assert!(anywhere_v6.contains("2001:db8::1".parse().unwrap()));
+
+        let host: Subnet = "::1".parse().unwrap();
+        assert_eq!(host.to_string(), "::1/128");
+        assert!(host.contains("::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("lan".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let config: crate::HttpConfig = toml::from_str(
+            r#"
+bind_addr = "127.0.0.1"
+port = 8080
+
+[[ip_rules]]
+path = "/play"
+
+[[ip_rules]]
+methods = ["POST", "PUT", "DELETE"]
+allow = ["192.168.0.0/16", "fd00::/8"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.ip_rules.len(), 2);
+        assert_eq!(config.ip_rules[0].path, "/play");
+        assert_eq!(config.ip_rules[1].path, "/");
+        assert_eq!(config.ip_rules[1].methods, ["POST", "PUT", "DELETE"]);
+        assert_eq!(config.ip_rules[1].allow[1].to_string(), "fd00::/8");
+    }
+}
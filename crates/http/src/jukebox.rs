@@ -0,0 +1,187 @@
+//! Jukebox mode: the server plays the party queue on its own audio output, e.g. a deck
+//! plugged into the living-room amplifier, instead of a browser at `/queue/player`.
+//!
+//! The audio engine lives in the binary and is handed to the server as a [`Jukebox`].
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::bail;
+use localdeck_storage::track::TrackId;
+
+use crate::{
+    queue::{EntryId, PartyQueue},
+    server::SharedStore,
+};
+
+/// How often the queue and the output are compared
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Audio output of the machine running the server
+pub trait Jukebox: Send {
+    /// Starts playing the file, replacing whatever plays or was prepared
+    fn play(&mut self, path: &Path) -> anyhow::Result<()>;
+
+    /// Silences the output
+    fn stop(&mut self);
+
+    /// Whether the last started file played to its end, also true if none was started
+    fn is_finished(&self) -> bool;
+
+    /// Holds the playing file at its position
+    fn pause(&mut self);
+
+    /// Continues after [`Jukebox::pause`]
+    fn resume(&mut self);
+
+    fn is_paused(&self) -> bool;
+
+    /// Jumps to `position` in the playing file
+    fn seek(&mut self, position: Duration) -> anyhow::Result<()>;
+
+    /// How far the playing file got
+    fn position(&self) -> Duration;
+
+    /// Length of the playing file, `None` if its format does not tell
+    fn duration(&self) -> Option<Duration>;
+
+    /// 1.0 plays files at their own loudness
+    fn volume(&self) -> f32;
+
+    fn set_volume(&mut self, volume: f32);
+
+    /// Prepares the file following the playing one, so it starts without a gap or
+    /// crossfades in. `None` drops the prepared file, the output falls silent after the playing one
+    fn set_next(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Whether the prepared file took over since the last call
+    fn take_advanced(&mut self) -> bool {
+        false
+    }
+}
+
+pub(crate) type SharedJukebox = Arc<Mutex<dyn Jukebox>>;
+
+/// Name of the zone a server starts with
+pub(crate) const DEFAULT_ZONE: &str = "default";
+
+/// A party queue and the output playing it. A server has one zone, or one per jukebox
+/// output added with [`crate::server::HttpServer::with_zone`], e.g. kitchen and living room
+#[derive(Clone)]
+pub(crate) struct Zone {
+    pub name: String,
+    pub queue: Arc<Mutex<PartyQueue>>,
+    /// `None` if a browser at `/queue/player` plays the queue
+    pub jukebox: Option<SharedJukebox>,
+}
+
+impl Zone {
+    pub fn new(name: &str, jukebox: Option<SharedJukebox>) -> Self {
+        Self {
+            name: name.to_string(),
+            queue: Arc::default(),
+            jukebox,
+        }
+    }
+}
+
+/// Keeps the jukebox playing the first entry of the queue, with the second one prepared
+pub(crate) struct JukeboxDriver {
+    jukebox: SharedJukebox,
+    /// entry started last, it is removed from the queue once it played
+    playing: Option<EntryId>,
+    /// entry handed to [`Jukebox::set_next`]
+    next: Option<EntryId>,
+}
+
+impl JukeboxDriver {
+    pub fn new(jukebox: SharedJukebox) -> Self {
+        Self {
+            jukebox,
+            playing: None,
+            next: None,
+        }
+    }
+
+    /// Starts a thread following the queue for as long as the server runs
+    pub fn spawn(mut self, queue: Arc<Mutex<PartyQueue>>, storage: SharedStore) {
+        thread::Builder::new()
+            .name("jukebox".to_string())
+            .spawn(move || {
+                loop {
+                    self.step(&queue, &storage);
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })
+            .expect("failed to start the jukebox thread");
+    }
+
+    /// Starts the first entry if it changed, removes it once it finished or the prepared
+    /// second one took over, and stops the output when the queue ran empty
+    pub fn step(&mut self, queue: &Mutex<PartyQueue>, storage: &SharedStore) {
+        let (first, second) = {
+            let queue = queue.lock().unwrap();
+            let entries = queue.entries();
+            (entries.first().copied(), entries.get(1).copied())
+        };
+        let mut jukebox = self.jukebox.lock().unwrap();
+        match first {
+            None => {
+                if self.playing.take().is_some() {
+                    self.next = None;
+                    jukebox.stop();
+                }
+            }
+            Some(entry) if self.playing == Some(entry.id) => {
+                if jukebox.take_advanced() {
+                    queue.lock().unwrap().skip(Some(entry.id));
+                    self.playing = self.next.take();
+                } else if jukebox.is_finished() {
+                    queue.lock().unwrap().skip(Some(entry.id));
+                } else if self.next != second.map(|next| next.id) {
+                    self.next = second.map(|next| next.id);
+                    let path = second.and_then(|next| {
+                        Self::local_file(next.track_id, storage)
+                            .inspect_err(|e| {
+                                log::warn!("jukebox cannot prepare track {}: {e:#}", next.track_id)
+                            })
+                            .ok()
+                    });
+                    if let Err(e) = jukebox.set_next(path.as_deref()) {
+                        log::warn!("jukebox could not prepare the next track: {e:#}");
+                    }
+                }
+            }
+            Some(entry) => {
+                self.playing = Some(entry.id);
+                self.next = None;
+                log::info!("jukebox playing track {}", entry.track_id);
+                let started =
+                    Self::local_file(entry.track_id, storage).and_then(|path| jukebox.play(&path));
+                if let Err(e) = started {
+                    // a broken file must not stop the party
+                    log::warn!("jukebox could not play track {}: {e:#}", entry.track_id);
+                    queue.lock().unwrap().skip(Some(entry.id));
+                }
+            }
+        }
+    }
+
+    fn local_file(track_id: TrackId, storage: &SharedStore) -> anyhow::Result<PathBuf> {
+        let (path, location, _) = storage
+            .lock()
+            .unwrap()
+            .find_track_file_with_meta(track_id)?;
+        if !location.is_local() {
+            bail!("only files of this machine can be played, the track is at {location}");
+        }
+        Ok(path)
+    }
+}
@@ -1,10 +1,70 @@
+use std::path::PathBuf;
+
 use serde::Deserialize;
 
+use crate::{
+    auth::{ApiToken, BasicAuth},
+    ip_filter::IpRule,
+    schedule::ScheduledPlay,
+    stream_limit::StreamLimits,
+    theme::ThemeConfig,
+};
+
 pub mod server;
 pub mod error;
+pub mod systemd;
+pub mod i18n;
+pub mod theme;
+pub mod sync;
+pub mod auth;
+pub mod ip_filter;
+pub mod jukebox;
+pub mod schedule;
+pub mod stream_limit;
+mod pwa;
+mod cast;
+mod proxy;
+mod queue;
+mod artwork;
+mod template;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct HttpConfig {
     pub bind_addr: String,
     pub port: u16,
+    /// language of guest pages for browsers accepting none of the supported ones, e.g. `ru`
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    /// credentials required on every route, an alternative to putting the server behind an authenticating proxy
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuth>,
+    /// named API tokens, each limited to what its role allows.
+    /// Card routes stay open to everyone unless `public_card_routes` is turned off
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// keeps `/play`, `/play-list`, `/random` and short links `/s/...` open when `basic_auth`
+    /// or `tokens` are set, so the links on printed cards work without logging in
+    #[serde(default = "default_public_card_routes")]
+    pub public_card_routes: bool,
+    /// which addresses may use which routes, the first matching rule applies
+    #[serde(default)]
+    pub ip_rules: Vec<IpRule>,
+    /// lets guests queue scanned cards for the host's player at `/queue/player`
+    #[serde(default)]
+    pub party_queue: bool,
+    /// tracks and playlists started on their own, in jukebox mode only
+    #[serde(default)]
+    pub schedule: Vec<ScheduledPlay>,
+    /// files served under `/static/`, e.g. scripts, fonts and artwork placeholders of custom pages
+    #[serde(default)]
+    pub static_dir: Option<PathBuf>,
+    /// caps on tracks streamed at once, so a party does not overwhelm a small host
+    #[serde(default)]
+    pub streams: StreamLimits,
+}
+
+fn default_public_card_routes() -> bool {
+    true
 }
@@ -0,0 +1,67 @@
+//! Streaming tracks which live on another localdeck instance or in object storage,
+//! see `Location::Remote` and `Location::S3` of `localdeck_storage::location`.
+//!
+//! The request goes on to the other instance or the presigned object URL with its byte range,
+//! and the answer is passed back while it arrives, so seeking works like for local files.
+
+use std::time::Duration;
+
+use rouille::{Request, Response, ResponseBody};
+use ureq::Agent;
+
+use crate::error::ApiError;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers of the upstream answer passed on as they are
+const FORWARDED_HEADERS: [&str; 3] = ["Content-Type", "Content-Range", "ETag"];
+
+/// Forwards a stream request to the URL of the track, with the token of the instance
+/// holding it if there is one
+pub(crate) fn stream(
+    url: &str,
+    token: Option<&str>,
+    request: &Request,
+) -> Result<Response, ApiError> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let mut forwarded = agent.get(url);
+    for name in ["Range", "If-Range"] {
+        if let Some(value) = request.header(name) {
+            forwarded = forwarded.header(name, value);
+        }
+    }
+    if let Some(token) = token {
+        forwarded = forwarded.header("Authorization", format!("Bearer {token}"));
+    }
+    let answer = forwarded
+        .call()
+        .map_err(|e| ApiError::BadGateway(format!("{url} is not reachable: {e}")))?;
+
+    let header = |name: &str| {
+        answer
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let mut response = Response::empty_204().with_status_code(answer.status().as_u16());
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = header(name) {
+            response = response.with_additional_header(name, value);
+        }
+    }
+    // decompressed bodies differ in length from what was announced
+    let length = header("Content-Length")
+        .filter(|_| header("Content-Encoding").is_none())
+        .and_then(|length| length.parse().ok());
+    let body = answer.into_body().into_reader();
+    response.data = match length {
+        Some(length) => ResponseBody::from_reader_and_size(body, length),
+        None => ResponseBody::from_reader(body),
+    };
+    Ok(response)
+}
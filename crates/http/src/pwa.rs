@@ -0,0 +1,53 @@
+//! Web app manifest and service worker, so phones can install the scanner page
+//! and open it full screen from the home screen
+
+use serde::Serialize;
+
+use crate::theme::ThemeConfig;
+
+pub(crate) const SERVICE_WORKER: &str = include_str!("../html/sw.js");
+pub(crate) const ICON: &str = include_str!("../html/icon.svg");
+const DEFAULT_NAME: &str = "localdeck";
+const DEFAULT_COLOR: &str = "#222222";
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Manifest<'a> {
+    name: &'a str,
+    short_name: &'a str,
+    start_url: &'static str,
+    scope: &'static str,
+    display: &'static str,
+    background_color: &'static str,
+    theme_color: &'a str,
+    icons: Vec<Icon>,
+}
+
+#[derive(Debug, Serialize)]
+struct Icon {
+    src: &'static str,
+    sizes: &'static str,
+    #[serde(rename = "type")]
+    mime: &'static str,
+}
+
+impl<'a> Manifest<'a> {
+    /// Manifest named and colored after the theme. The built-in icon is used,
+    /// since the size of the logo is unknown
+    pub(crate) fn new(theme: &'a ThemeConfig) -> Self {
+        let name = theme.title.as_deref().unwrap_or(DEFAULT_NAME);
+        Manifest {
+            name,
+            short_name: name,
+            start_url: "/scan_qr",
+            scope: "/",
+            display: "standalone",
+            background_color: "#ffffff",
+            theme_color: theme.accent_color.as_deref().unwrap_or(DEFAULT_COLOR),
+            icons: vec![Icon {
+                src: "/icon.svg",
+                sizes: "any",
+                mime: "image/svg+xml",
+            }],
+        }
+    }
+}
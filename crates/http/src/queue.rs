@@ -0,0 +1,103 @@
+//! Party queue: guests add the cards they scan, and one host page at `/queue/player`
+//! plays the queued tracks in order on the speakers.
+//!
+//! The first entry is the one playing. The queue lives in memory only, restarting
+//! the server empties it.
+
+use localdeck_storage::track::TrackId;
+
+pub(crate) type EntryId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QueueEntry {
+    /// stays the same while the entry moves, unlike its position
+    pub id: EntryId,
+    pub track_id: TrackId,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PartyQueue {
+    entries: Vec<QueueEntry>,
+    next_id: EntryId,
+}
+
+impl PartyQueue {
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries
+    }
+
+    /// Appends the track, returning its entry and position
+    pub fn push(&mut self, track_id: TrackId) -> (QueueEntry, usize) {
+        self.next_id += 1;
+        let entry = QueueEntry {
+            id: self.next_id,
+            track_id,
+        };
+        self.entries.push(entry);
+        (entry, self.entries.len() - 1)
+    }
+
+    /// Removes the entry, or the playing one if `id` is `None`.
+    /// Returns false if there is no such entry, e.g. it was skipped already.
+    pub fn skip(&mut self, id: Option<EntryId>) -> bool {
+        let position = match id {
+            Some(id) => self.position(id),
+            None => (!self.entries.is_empty()).then_some(0),
+        };
+        position
+            .map(|position| self.entries.remove(position))
+            .is_some()
+    }
+
+    /// Moves the entry to `position`, or to the end if the queue is shorter.
+    /// Returns false if there is no such entry.
+    pub fn move_to(&mut self, id: EntryId, position: usize) -> bool {
+        let Some(from) = self.position(id) else {
+            return false;
+        };
+        let entry = self.entries.remove(from);
+        self.entries.insert(position.min(self.entries.len()), entry);
+        true
+    }
+
+    /// Empties the queue, entry ids keep counting up
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn position(&self, id: EntryId) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracks(queue: &PartyQueue) -> Vec<TrackId> {
+        queue.entries().iter().map(|entry| entry.track_id).collect()
+    }
+
+    #[test]
+    fn entries_are_reordered_and_skipped() {
+        let mut queue = PartyQueue::default();
+        let (first, _) = queue.push(10);
+        let (second, _) = queue.push(20);
+        let (third, position) = queue.push(30);
+        assert_eq!(position, 2);
+
+        assert!(queue.move_to(third.id, 1));
+        assert_eq!(tracks(&queue), vec![10, 30, 20]);
+        assert!(queue.move_to(first.id, 99));
+        assert_eq!(tracks(&queue), vec![30, 20, 10]);
+
+        assert!(queue.skip(None));
+        assert!(queue.skip(Some(first.id)));
+        assert!(!queue.skip(Some(first.id)));
+        assert_eq!(tracks(&queue), vec![20]);
+        assert!(!queue.move_to(third.id, 0));
+
+        assert!(queue.skip(Some(second.id)));
+        assert!(!queue.skip(None));
+    }
+}
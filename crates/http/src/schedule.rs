@@ -0,0 +1,298 @@
+//! Scheduled playback in jukebox mode: wake-up alarms, music at shop opening.
+//!
+//! Each entry of `schedule` names a track or playlist and a cron-like time. When the
+//! time comes, the party queue is replaced with it and the jukebox starts playing.
+//!
+//! ```toml
+//! [[http.schedule]]
+//! cron = "30 7 * * 1-5"
+//! playlist = "Wake up"
+//! zone = "bedroom"
+//! ```
+
+use std::{str::FromStr, thread, time::Duration};
+
+use anyhow::{Context, anyhow, bail};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use localdeck_storage::track::TrackId;
+use serde::Deserialize;
+
+use crate::{jukebox::Zone, server::SharedStore};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScheduledPlay {
+    /// when to start, in local time
+    pub cron: CronSchedule,
+    #[serde(flatten)]
+    pub target: PlayTarget,
+    /// jukebox zone to play in, the first one if not set
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+/// What a [`ScheduledPlay`] starts
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayTarget {
+    Track(TrackId),
+    /// playlist name, or its id if no playlist has that name
+    Playlist(String),
+}
+
+/// `minute hour day-of-month month day-of-week`, like a crontab line.
+///
+/// Fields take `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and lists of those
+/// separated by commas. Sunday is 0 or 7. As in cron, if both day fields are restricted,
+/// a day matching either one matches.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CronSchedule {
+    /// bit n is set if value n is allowed
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        let allows = |set: u64, value: u32| set & (1 << value) != 0;
+        let day = allows(self.days, time.day());
+        let weekday = allows(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        day_matches
+            && allows(self.minutes, time.minute())
+            && allows(self.hours, time.hour())
+            && allows(self.months, time.month())
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            bail!("'{s}' should have 5 fields: minute hour day-of-month month day-of-week");
+        };
+        let mut weekday_set = parse_field(weekdays, 0, 7).context("day of week")?;
+        // 7 is another name for Sunday
+        if weekday_set & (1 << 7) != 0 {
+            weekday_set = (weekday_set & !(1 << 7)) | 1;
+        }
+        Ok(CronSchedule {
+            minutes: parse_field(minutes, 0, 59).context("minute")?,
+            hours: parse_field(hours, 0, 23).context("hour")?,
+            days: parse_field(days, 1, 31).context("day of month")?,
+            months: parse_field(months, 1, 12).context("month")?,
+            weekdays: weekday_set,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Set of the values a field allows, as bits
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let number = |s: &str| -> anyhow::Result<u32> {
+        let n = s.parse().map_err(|_| anyhow!("'{s}' is not a number"))?;
+        if !(min..=max).contains(&n) {
+            bail!("{n} is not between {min} and {max}");
+        }
+        Ok(n)
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().unwrap_or(0)),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("'{part}' has no valid step");
+        }
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (number(from)?, number(to)?),
+                None => {
+                    let n = number(range)?;
+                    // `5/15` counts from 5 to the end, like `5-59/15`
+                    (n, if part.contains('/') { max } else { n })
+                }
+            },
+        };
+        if from > to {
+            bail!("'{range}' ends before it starts");
+        }
+        for n in (from..=to).step_by(step) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
+
+/// Starts a thread checking the schedule every minute, for as long as the server runs
+pub(crate) fn spawn(schedule: Vec<ScheduledPlay>, zones: Vec<Zone>, storage: SharedStore) {
+    if schedule.is_empty() {
+        return;
+    }
+    for name in schedule.iter().filter_map(|play| play.zone.as_ref()) {
+        if !zones.iter().any(|zone| &zone.name == name) {
+            log::warn!("the schedule names zone {name}, which does not exist");
+        }
+    }
+    log::info!("{} scheduled plays", schedule.len());
+    thread::Builder::new()
+        .name("schedule".to_string())
+        .spawn(move || {
+            let mut last_minute = None;
+            loop {
+                let now = Local::now();
+                let minute = now.timestamp() / 60;
+                if last_minute != Some(minute) {
+                    last_minute = Some(minute);
+                    for play in schedule.iter().filter(|play| play.cron.matches(&now)) {
+                        let zone = match &play.zone {
+                            Some(name) => zones.iter().find(|zone| &zone.name == name),
+                            None => zones.first(),
+                        };
+                        let Some(zone) = zone else {
+                            continue;
+                        };
+                        match start(&play.target, zone, &storage) {
+                            Ok(()) => log::info!("started scheduled {:?}", play.target),
+                            Err(e) => {
+                                log::error!("scheduled {:?} did not start: {e:#}", play.target)
+                            }
+                        }
+                    }
+                }
+                thread::sleep(Duration::from_secs(60 - u64::from(now.second()).min(59)));
+            }
+        })
+        .expect("failed to start the schedule thread");
+}
+
+/// Replaces the zone's queue with the target's tracks and unpauses its jukebox,
+/// which then plays them from the start
+pub(crate) fn start(target: &PlayTarget, zone: &Zone, storage: &SharedStore) -> anyhow::Result<()> {
+    let Some(jukebox) = &zone.jukebox else {
+        bail!("zone {} has no jukebox", zone.name);
+    };
+    let tracks = match target {
+        PlayTarget::Track(track_id) => vec![*track_id],
+        PlayTarget::Playlist(name) => {
+            let playlists = storage.lock().unwrap().list_playlists()?;
+            let by_id = || playlists.iter().find(|p| name.parse() == Ok(p.id));
+            playlists
+                .iter()
+                .find(|p| &p.name == name)
+                .or_else(by_id)
+                .with_context(|| format!("no playlist named '{name}'"))?
+                .tracks
+                .clone()
+        }
+    };
+    if tracks.is_empty() {
+        bail!("nothing to play, the playlist is empty");
+    }
+
+    {
+        let mut queue = zone.queue.lock().unwrap();
+        queue.clear();
+        for track_id in tracks {
+            queue.push(track_id);
+        }
+    }
+    jukebox.lock().unwrap().resume();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn cron_expressions_match() -> anyhow::Result<()> {
+        // 2026-10-16 is a Friday
+        let weekdays: CronSchedule = "30 7 * * 1-5".parse()?;
+        assert!(weekdays.matches(&at(2026, 10, 16, 7, 30)));
+        assert!(!weekdays.matches(&at(2026, 10, 17, 7, 30)));
+        assert!(!weekdays.matches(&at(2026, 10, 16, 7, 31)));
+
+        let sundays: CronSchedule = "0 10 * * 7".parse()?;
+        assert!(sundays.matches(&at(2026, 10, 18, 10, 0)));
+
+        let quarters: CronSchedule = "*/15 9-17/4 * 6,12 *".parse()?;
+        assert!(quarters.matches(&at(2026, 12, 1, 13, 45)));
+        assert!(!quarters.matches(&at(2026, 12, 1, 11, 45)));
+        assert!(!quarters.matches(&at(2026, 11, 1, 13, 45)));
+
+        // restricted in both day fields, either one is enough
+        let first_or_monday: CronSchedule = "0 8 1 * 1".parse()?;
+        assert!(first_or_monday.matches(&at(2026, 10, 1, 8, 0)));
+        assert!(first_or_monday.matches(&at(2026, 10, 19, 8, 0)));
+        assert!(!first_or_monday.matches(&at(2026, 10, 20, 8, 0)));
+
+        for wrong in [
+            "* * * *",
+            "60 * * * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(wrong.parse::<CronSchedule>().is_err(), "{wrong}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn entries_are_parsed() {
+        let config: crate::HttpConfig = toml::from_str(
+            r#"
+bind_addr = "127.0.0.1"
+port = 8080
+
+[[schedule]]
+cron = "30 7 * * 1-5"
+playlist = "Wake up"
+zone = "bedroom"
+
+[[schedule]]
+cron = "0 9 * * *"
+track = 42
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.schedule[0].target,
+            PlayTarget::Playlist("Wake up".to_string())
+        );
+        assert_eq!(config.schedule[0].zone.as_deref(), Some("bedroom"));
+        assert_eq!(config.schedule[1].target, PlayTarget::Track(42));
+        assert_eq!(config.schedule[1].zone, None);
+    }
+}
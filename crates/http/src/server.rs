@@ -1,609 +1,4488 @@
 use anyhow::anyhow;
 use log::{debug, info};
-use rouille::{Request, Response};
+use rouille::{Request, Response, ResponseBody};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
-    io::{Read, Seek, SeekFrom},
+    collections::{HashMap, HashSet},
+    fs::{File, Metadata},
+    hash::{BuildHasher, RandomState},
+    io::{self, Read, Seek, SeekFrom},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, UNIX_EPOCH},
 };
 
-use crate::{HttpConfig, error::ApiError};
+use crate::{
+    HttpConfig, artwork, auth,
+    cast::{self, CastMedia},
+    error::ApiError,
+    i18n::Lang,
+    ip_filter,
+    jukebox::{DEFAULT_ZONE, Jukebox, JukeboxDriver, Zone},
+    proxy, pwa,
+    queue::EntryId,
+    schedule,
+    stream_limit::StreamCounter,
+    sync, systemd,
+    template::{self, script_json},
+};
+use chrono::Local;
 use localdeck_storage::{
+    album::{self, Album},
+    artists::ArtistCredit,
+    card_scans::{self, CardScan, ScanOutcome},
     error::StorageError,
     location::Location,
-    operations::Storage,
+    operations::{TrackFilter, TrackListEntry},
+    search::CYRILLIC_TO_LATIN,
+    sort_names::sort_by_name,
+    store::LibraryStore,
     track::{TrackId, TrackMetadata},
 };
 
+/// Prefix of the versioned JSON routes, e.g. `/api/v1/tracks`
+const API_V1: &str = "/api/v1";
+/// Ids a single `POST /tracks/batch` may ask for
+const MAX_BATCH_TRACKS: usize = 500;
+
+/// Library shared between request handlers and background tasks of the server
+pub type SharedStore = Arc<Mutex<dyn LibraryStore>>;
+
+/// Reads the config again for `POST /admin/reload` and SIGHUP
+pub type ConfigLoader = Box<dyn Fn() -> anyhow::Result<ReloadedConfig> + Send + Sync>;
+
+/// Settings a reload takes over from the config
+pub struct ReloadedConfig {
+    /// only `basic_auth`, `tokens` and `ip_rules` are taken over
+    pub http: HttpConfig,
+    /// set with `log::set_max_level`
+    pub log_level: log::LevelFilter,
+    /// directories later scans of the library skip, e.g. the scheduled rescans of `serve`
+    pub ignored_dirs: Vec<PathBuf>,
+}
+
 pub struct HttpServer {
-    storage: Arc<Mutex<Storage>>,
+    storage: SharedStore,
+    instance_id: String,
+    /// never empty, routes without `?zone=` use the first one
+    zones: Vec<Zone>,
+    streams: StreamCounter,
     pub config: HttpConfig,
+    reload: Option<ConfigLoader>,
+    /// config read by the last reload, its access settings replace those of `config`
+    reloaded: RwLock<Option<HttpConfig>>,
 }
 
 impl HttpServer {
-    pub fn new(storage: Storage, config: HttpConfig) -> Self {
+    pub fn new(storage: impl LibraryStore + 'static, config: HttpConfig) -> Self {
+        if let Some(language) = &config.language
+            && Lang::from_tag(language).is_none()
+        {
+            log::warn!("language {language} is not supported, guest pages will be in English");
+        }
         Self {
             storage: Arc::new(Mutex::new(storage)),
+            instance_id: random_instance_id(),
+            zones: vec![Zone::new(DEFAULT_ZONE, None)],
+            streams: StreamCounter::new(config.streams.clone()),
             config,
+            reload: None,
+            reloaded: RwLock::new(None),
+        }
+    }
+
+    /// Lets admins reload parts of the config with `POST /admin/reload` or, on unix, by sending
+    /// the process SIGHUP: the access settings `basic_auth`, `tokens` and `ip_rules`, the log
+    /// level and the ignored directories of the library. Other settings need a restart.
+    /// `POST /admin/reload` is refused unless the request carries admin credentials, so servers
+    /// without any are reloaded by SIGHUP only.
+    /// Running streams go on, requests started after the reload are checked against it
+    pub fn with_reload(
+        mut self,
+        load: impl Fn() -> anyhow::Result<ReloadedConfig> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(load));
+        self
+    }
+
+    /// Plays the party queue on `jukebox` instead of a browser at `/queue/player`,
+    /// which then only shows and reorders the queue. Enables the party queue
+    pub fn with_jukebox(self, jukebox: impl Jukebox + 'static) -> Self {
+        self.with_zone(DEFAULT_ZONE, jukebox)
+    }
+
+    /// Adds a jukebox zone with a party queue and volume of its own. The `/queue` and `/player`
+    /// routes pick it with `?zone=name`; the first zone added takes the place of the default
+    /// one, used without `?zone=`
+    pub fn with_zone(mut self, name: &str, jukebox: impl Jukebox + 'static) -> Self {
+        let zone = Zone::new(name, Some(Arc::new(Mutex::new(jukebox))));
+        if self.zones[0].jukebox.is_none() {
+            self.zones[0] = zone;
+        } else {
+            self.zones.push(zone);
         }
+        self.config.party_queue = true;
+        self
+    }
+
+    /// Random id of this process, reported by `/healthz` so a client can tell
+    /// whether some address, e.g. a public one, reaches this very server
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// The library served, for tasks running next to the server
+    pub fn storage(&self) -> SharedStore {
+        Arc::clone(&self.storage)
     }
 
     pub fn run(self) {
         let addr = format!("{}:{}", self.config.bind_addr, self.config.port);
-        rouille::start_server(addr, move |request| self.handle_request(request));
+        let storage = Arc::clone(&self.storage);
+        for zone in &self.zones {
+            if let Some(jukebox) = &zone.jukebox {
+                JukeboxDriver::new(Arc::clone(jukebox))
+                    .spawn(Arc::clone(&zone.queue), Arc::clone(&storage));
+            }
+        }
+        if self.zones.iter().any(|zone| zone.jukebox.is_some()) {
+            schedule::spawn(
+                self.config.schedule.clone(),
+                self.zones.clone(),
+                Arc::clone(&storage),
+            );
+        } else if !self.config.schedule.is_empty() {
+            log::warn!("the schedule only plays in jukebox mode, ignoring it");
+        }
+
+        let this = Arc::new(self);
+        #[cfg(unix)]
+        if this.reload.is_some() {
+            Self::reload_on_sighup(Arc::clone(&this));
+        }
+        let server = match rouille::Server::new(addr, move |request| this.handle_request(request)) {
+            Ok(server) => server,
+            Err(e) => panic!("failed to start HTTP server: {e}"),
+        };
+
+        systemd::notify_ready();
+        systemd::spawn_watchdog(storage);
+
+        server.run();
+        systemd::notify_stopping();
     }
 
     /// Never change the /play route as it will be printed on qrs or nfc
     fn handle_request(&self, request: &Request) -> Response {
         Self::log_request(request);
 
+        // later versions may change response shapes, the unversioned routes stay as aliases
+        let versioned = request
+            .url()
+            .starts_with(&format!("{API_V1}/"))
+            .then(|| request.remove_prefix(API_V1))
+            .flatten();
+        let request = match &versioned {
+            Some(route) if Self::is_api_route(&route.url()) => route,
+            Some(_) => return Response::empty_404(),
+            None => request,
+        };
+
+        // cast receivers stream tracks from another origin
+        let cross_origin = request.url().starts_with("/tracks/") || request.url() == "/play";
+        {
+            let reloaded = self.reloaded.read().unwrap();
+            let access = reloaded.as_ref().unwrap_or(&self.config);
+            if let Err(denied) = ip_filter::check(&access.ip_rules, request) {
+                return denied;
+            }
+
+            if cross_origin && request.method() == "OPTIONS" {
+                return cast::preflight();
+            }
+
+            if let Err(denied) = auth::authorize(access, request) {
+                return denied;
+            }
+        }
+
+        let url = request.url();
+        let queue_route = url == "/queue" || url.starts_with("/queue/") || url == "/zones";
+        if !self.config.party_queue && queue_route {
+            return Response::empty_404();
+        }
+
+        // the router cannot match nested paths like `/static/fonts/a.woff2`
+        if let Some(dir) = &self.config.static_dir
+            && request.method() == "GET"
+            && url.starts_with("/static/")
+            && let Some(asset) = request.remove_prefix("/static")
+        {
+            return rouille::match_assets(&asset, dir);
+        }
+
         let response = rouille::router!(request,
+            (GET) (/healthz) => {
+                Self::handle_healthz(&self.storage, &self.instance_id)
+            },
+            (GET) (/tracks) => {
+                Self::handle_list_tracks(request, &self.storage)
+            },
             (GET) (/tracks/{id: String}) => {
                 Self::handle_get_track(id, &self.storage)
             },
+            (POST) (/tracks/batch) => {
+                Self::handle_get_tracks_batch(request, &self.storage)
+            },
 
             (GET) (/tracks/{id: String}/stream) => {
-                self.handle_get_track_stream(id, request)
+                self.limit_stream(request, || self.handle_get_track_stream(id, request))
+            },
+            // mobile players probe the length before asking for ranges
+            (HEAD) (/tracks/{id: String}/stream) => {
+                Self::without_body(self.handle_get_track_stream(id, request))
+            },
+            (GET) (/tracks/{id: String}/lyrics) => {
+                Self::handle_get_lyrics(id, &self.storage)
+            },
+            (GET) (/tracks/{id: String}/artwork) => {
+                Self::handle_get_artwork(id, request, &self.storage)
+            },
+            (GET) (/tracks/{id: String}/cast) => {
+                Self::handle_get_cast_media(id, request, &self.storage)
+            },
+            (GET) (/tracks/{id: String}/next) => {
+                self.handle_next_track(id, request)
+            },
+            (POST) (/tracks/{id: String}/favourite) => {
+                Self::handle_toggle_favourite(id, &self.storage)
+            },
+            (GET) (/albums) => {
+                Self::handle_list_albums(&self.storage)
+            },
+            (GET) (/albums/{id: String}) => {
+                Self::handle_get_album(id, &self.storage)
+            },
+            (GET) (/artists) => {
+                Self::handle_list_artists(&self.storage)
+            },
+            (GET) (/artists/{name: String}/tracks) => {
+                Self::handle_artist_tracks(name, request, &self.storage)
+            },
+            (GET) (/years) => {
+                Self::handle_list_years(&self.storage)
+            },
+            (GET) (/years/{year: String}/tracks) => {
+                Self::handle_year_tracks(year, request, &self.storage)
+            },
+            (GET) (/search) => {
+                Self::handle_search(request, &self.storage)
+            },
+            (GET) (/favourites) => {
+                Self::handle_list_favourites(&self.storage)
+            },
+            (GET) (/recent) => {
+                Self::handle_list_recent(request, &self.storage)
             },
             (GET) (/play) => {
-                self.handle_play(request)
+                self.limit_stream(request, || self.handle_play(request))
+            },
+            (HEAD) (/play) => {
+                Self::without_body(self.handle_play(request))
+            },
+            (GET) (/random) => {
+                self.handle_random(request)
+            },
+            (GET) (/s/{code: String}) => {
+                self.handle_short_link(code)
             },
             (GET) (/scan_qr) => {
-                Self::handle_scan_qr()
+                self.handle_scan_qr(request)
+            },
+            (GET) (/library) => {
+                self.handle_library_page(request)
+            },
+            (GET) (/playlists/{id: String}) => {
+                self.handle_playlist_page(id, request)
+            },
+            (GET) (/stats/scans) => {
+                self.handle_scan_stats(request)
+            },
+            (GET) (/queue) => {
+                self.handle_get_queue(request)
+            },
+            (POST) (/queue) => {
+                self.handle_add_to_queue(request)
+            },
+            (POST) (/queue/skip) => {
+                self.handle_skip_queue_entry(request)
+            },
+            (POST) (/queue/{entry: EntryId}/move) => {
+                self.handle_move_queue_entry(entry, request)
+            },
+            (GET) (/zones) => {
+                self.handle_list_zones()
+            },
+            (GET) (/queue/player) => {
+                self.handle_queue_player(request)
+            },
+            (POST) (/player/play) => {
+                self.handle_player_command(request, |jukebox| jukebox.resume())
+            },
+            (POST) (/player/pause) => {
+                self.handle_player_command(request, |jukebox| jukebox.pause())
+            },
+            (POST) (/player/next) => {
+                self.handle_player_next(request)
+            },
+            (POST) (/player/seek) => {
+                self.handle_player_seek(request)
+            },
+            (PUT) (/player/volume) => {
+                self.handle_player_volume(request)
+            },
+            // the router only takes identifiers as literal segments
+            (GET) (/player/{page: String}) => {
+                if page == "now-playing" {
+                    self.handle_now_playing(request)
+                } else {
+                    Response::empty_404()
+                }
+            },
+            (POST) (/admin/reload) => {
+                self.handle_reload(request)
+            },
+            (GET) (/sync) => {
+                Self::handle_sync(&self.storage)
+            },
+            (GET) (/theme/{file: String}) => {
+                self.config.theme.file_response(&file)
+            },
+            (GET) (/{file: String}) => {
+                // like `/player/now-playing`, the router can't take `/play-list` literally
+                if file == "play-list" {
+                    self.handle_play_list(request)
+                } else {
+                    self.handle_app_file(&file)
+                }
             },
             _ => Response::empty_404()
         );
+        let response = if cross_origin {
+            cast::with_cors(response)
+        } else {
+            response
+        };
 
         info!("Response: {} {}", request.method(), response.status_code);
         debug!("Response headers: {:?}", response.headers);
         response
     }
 
+    /// Routes answering with JSON or media, unlike pages and the files they load
+    fn is_api_route(url: &str) -> bool {
+        let first = url
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let api = matches!(
+            first,
+            "healthz"
+                | "tracks"
+                | "albums"
+                | "artists"
+                | "years"
+                | "favourites"
+                | "recent"
+                | "stats"
+                | "queue"
+                | "zones"
+                | "player"
+                | "sync"
+                | "search"
+        );
+        api && url != "/queue/player"
+    }
+
     fn log_request(request: &Request) {
         info!("{} {}", request.method(), request.url());
     }
 
-    fn handle_scan_qr() -> Response {
-        Response::html(include_str!("../html/scan_qr.html"))
+    /// Language of the guest pages for this request
+    fn lang(&self, request: &Request) -> Lang {
+        let default = self.config.language.as_deref().and_then(Lang::from_tag);
+        Lang::select(request.header("Accept-Language"), default)
     }
 
-    fn handle_get_track(id: String, storage: &Arc<Mutex<Storage>>) -> Response {
-        let track_id = match storage.lock().unwrap().resolve_track(id) {
-            Ok(id) => id,
-            Err(e) => return ApiError::from(e).into_response(),
-        };
+    /// Page `name` in the guest's language, with the configured theme and the `data`
+    /// of its other placeholders, see [`template`]
+    fn render_page(
+        &self,
+        request: &Request,
+        name: &str,
+        builtin: &str,
+        data: &[(&str, String)],
+    ) -> String {
+        let theme = &self.config.theme;
+        let mut values = vec![
+            ("/*TEXTS*/null", script_json(self.lang(request).texts())),
+            ("/*THEME*/null", script_json(&theme.page_theme())),
+        ];
+        values.extend_from_slice(data);
+        template::fill(&theme.page(name, builtin), &values)
+    }
 
-        let data = {
-            let mut storage = storage.lock().unwrap();
-            storage.find_track_file_with_meta(track_id)
-        };
+    fn handle_scan_qr(&self, request: &Request) -> Response {
+        let page = self.render_page(
+            request,
+            "scan_qr.html",
+            include_str!("../html/scan_qr.html"),
+            &[("/*QUEUE*/false", script_json(&self.config.party_queue))],
+        );
+        Response::html(page).with_additional_header("Vary", "Accept-Language")
+    }
 
-        match data {
-            Ok((_, loc, metadata)) => {
-                Response::json(&TrackResponse::from_domain(&track_id, loc, metadata))
-            }
+    /// Host page playing the party queue, see [`crate::queue`].
+    /// With a jukebox it only manages the queue, the server plays it
+    fn handle_queue_player(&self, request: &Request) -> Response {
+        let zone = match self.zone(request) {
+            Ok(zone) => zone,
+            Err(e) => return e.into_response(),
+        };
+        let page = self.render_page(
+            request,
+            "queue_player.html",
+            include_str!("../html/queue_player.html"),
+            &[("/*JUKEBOX*/false", script_json(&zone.jukebox.is_some()))],
+        );
+        Response::html(page).with_additional_header("Vary", "Accept-Language")
+    }
 
-            Err(e) => ApiError::from(e).into_response(),
+    /// Zone in `?zone=`, the first one without it
+    fn zone(&self, request: &Request) -> Result<&Zone, ApiError> {
+        match request.get_param("zone") {
+            None => Ok(&self.zones[0]),
+            Some(name) => self
+                .zones
+                .iter()
+                .find(|zone| zone.name == name)
+                .ok_or_else(|| ApiError::NotFound(format!("no zone named {name}"))),
         }
     }
 
-    /// streams music file, respecting byterange
-    /// returns Response with ok status, or ApiError
-    fn get_track_stream(&self, id: String, request: &Request) -> Result<Response, ApiError> {
-        let mut storage = self.storage.lock().map_err(|e| {
-            StorageError::Internal(anyhow!(
-                "Could not access localdeck storage under lock: {e}"
-            ))
-        })?;
-
-        let track_id = storage.resolve_track(id.clone())?;
-
-        let (path, _, meta) = storage.find_track_file_with_meta(track_id)?;
-        let mime = Self::mime_for_track(&path);
-
-        let mut file = File::open(&path).map_err(StorageError::Fs)?;
-        let file_size = file.metadata().map_err(StorageError::Fs)?.len();
+    fn handle_list_zones(&self) -> Response {
+        let zones: Vec<_> = self
+            .zones
+            .iter()
+            .map(|zone| ZoneResponse {
+                name: zone.name.clone(),
+                jukebox: zone.jukebox.is_some(),
+                queued: zone.queue.lock().unwrap().entries().len(),
+            })
+            .collect();
+        Response::json(&zones)
+    }
 
-        let with_extra_headers = |resp: Response| -> Response {
-            let mut resp = resp.with_additional_header("Accept-Ranges", "bytes");
+    /// Queued entries with their tracks' metadata, the playing one first
+    fn handle_get_queue(&self, request: &Request) -> Response {
+        let zone = match self.zone(request) {
+            Ok(zone) => zone,
+            Err(e) => return e.into_response(),
+        };
+        let entries = zone.queue.lock().unwrap().entries().to_vec();
+        let entries = {
+            let mut storage = self.storage.lock().unwrap();
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let meta = storage.get_track_metadata(entry.track_id)?;
+                    Ok(QueueEntryResponse {
+                        id: entry.id,
+                        track_id: entry.track_id,
+                        title: meta.as_ref().map(|m| m.title.clone()),
+                        artist: meta.map(|m| m.artist),
+                    })
+                })
+                .collect::<Result<Vec<_>, StorageError>>()
+        };
+        match entries {
+            Ok(entries) => Response::json(&QueueResponse { entries }),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-            if let Some(meta) = meta {
-                resp = resp
-                    .with_additional_header("X-Track-Artist", meta.artist)
-                    .with_additional_header("X-Track-Title", meta.title)
+    /// Appends the track of the card in `?h=`, like `/play` does for playing
+    fn handle_add_to_queue(&self, request: &Request) -> Response {
+        let texts = self.lang(request).texts();
+        let Some(card_id) = request.get_param("h") else {
+            return Response::text(texts.missing_hash).with_status_code(400);
+        };
+        let zone = match self.zone(request) {
+            Ok(zone) => zone,
+            Err(e) => return e.into_response(),
+        };
+        let track_id = match self.storage.lock().unwrap().resolve_track(card_id) {
+            Ok(track_id) => track_id,
+            Err(StorageError::TrackNotFound(_)) => {
+                return Response::text(texts.track_not_found).with_status_code(404);
             }
-            resp
+            Err(e) => return ApiError::from(e).into_response(),
         };
+        let (entry, position) = zone.queue.lock().unwrap().push(track_id);
+        info!(
+            "queued track {track_id} at position {position} in zone {}",
+            zone.name
+        );
+        Response::json(&QueuedResponse {
+            id: entry.id,
+            track_id,
+            position,
+        })
+    }
 
-        // ---------------------------------------------
-        // Parse Range header if present
-        // ---------------------------------------------
-        let range_header = request.header("Range");
-        if let Some(range) = range_header {
-            // Expect something like "bytes=123-456"
-            if let Some((start, end)) = Self::parse_http_range(range, file_size)? {
-                let chunk_size = end - start + 1;
-                let mut buffer = vec![0u8; chunk_size as usize];
-
-                file.seek(SeekFrom::Start(start))
-                    .map_err(StorageError::Fs)?;
-                file.read_exact(&mut buffer).map_err(StorageError::Fs)?;
+    /// Removes the entry in `?entry=`, or the playing one
+    fn handle_skip_queue_entry(&self, request: &Request) -> Response {
+        let entry = match request.get_param("entry").map(|entry| entry.parse()) {
+            None => None,
+            Some(Ok(entry)) => Some(entry),
+            Some(Err(_)) => {
+                return ApiError::BadRequest("entry must be a queue entry id".to_string())
+                    .into_response();
+            }
+        };
+        let zone = match self.zone(request) {
+            Ok(zone) => zone,
+            Err(e) => return e.into_response(),
+        };
+        if zone.queue.lock().unwrap().skip(entry) {
+            Response::empty_204()
+        } else {
+            ApiError::NotFound("no such queue entry".to_string()).into_response()
+        }
+    }
 
-                log::debug!(
-                    "STREAM {} -> 206 Partial Content, path: {}, MIME type: {}, bytes {}-{}",
-                    id,
-                    path.to_string_lossy(),
-                    mime,
-                    start,
-                    end
-                );
+    /// Moves the entry to `?position=`, 0 being the playing place
+    fn handle_move_queue_entry(&self, entry: EntryId, request: &Request) -> Response {
+        let Some(Ok(position)) = request.get_param("position").map(|p| p.parse()) else {
+            return ApiError::BadRequest("position must be a whole number".to_string())
+                .into_response();
+        };
+        let zone = match self.zone(request) {
+            Ok(zone) => zone,
+            Err(e) => return e.into_response(),
+        };
+        if zone.queue.lock().unwrap().move_to(entry, position) {
+            Response::empty_204()
+        } else {
+            ApiError::NotFound(format!("queue entry {entry}")).into_response()
+        }
+    }
 
-                let resp = with_extra_headers(
-                    Response::from_data(mime, buffer)
-                        .with_status_code(206)
-                        .with_additional_header(
-                            "Content-Range",
-                            format!("bytes {}-{}/{}", start, end, file_size),
-                        ),
-                );
+    /// Audio output of the zone in `?zone=`, the `/player` routes only exist in jukebox mode
+    fn jukebox(
+        &self,
+        request: &Request,
+    ) -> Result<(&Zone, MutexGuard<'_, dyn Jukebox + 'static>), ApiError> {
+        let zone = self.zone(request)?;
+        match &zone.jukebox {
+            Some(jukebox) => Ok((zone, jukebox.lock().unwrap())),
+            None => Err(ApiError::NotFound("jukebox mode is off".to_string())),
+        }
+    }
 
-                return Ok(resp);
+    fn handle_player_command(
+        &self,
+        request: &Request,
+        command: impl FnOnce(&mut dyn Jukebox),
+    ) -> Response {
+        match self.jukebox(request) {
+            Ok((_, mut jukebox)) => {
+                command(&mut *jukebox);
+                Response::empty_204()
             }
+            Err(e) => e.into_response(),
         }
+    }
 
-        // No Range header, return full file
-        log::debug!(
-            "STREAM {} -> 200 OK, path: {}, MIME type: {}",
-            id,
-            path.to_string_lossy(),
-            mime
-        );
-        Ok(with_extra_headers(Response::from_file(mime, file)))
+    /// Skips the playing entry, the jukebox moves on to the next one
+    fn handle_player_next(&self, request: &Request) -> Response {
+        let zone = match self.jukebox(request) {
+            Ok((zone, _)) => zone,
+            Err(e) => return e.into_response(),
+        };
+        if zone.queue.lock().unwrap().skip(None) {
+            Response::empty_204()
+        } else {
+            ApiError::NotFound("the queue is empty".to_string()).into_response()
+        }
     }
 
-    /// parse "bytes=start-end" header
-    /// Returns (start, end) or error
-    fn parse_http_range(range: &str, file_size: u64) -> Result<Option<(u64, u64)>, ApiError> {
-        if !range.starts_with("bytes=") {
-            return Ok(None);
+    /// Jumps to `?position=`, in seconds
+    fn handle_player_seek(&self, request: &Request) -> Response {
+        let position = match request.get_param("position").map(|p| p.parse::<f64>()) {
+            Some(Ok(secs)) if secs.is_finite() && secs >= 0.0 => Duration::from_secs_f64(secs),
+            _ => {
+                return ApiError::BadRequest("position must be a number of seconds".to_string())
+                    .into_response();
+            }
+        };
+        let result = self.jukebox(request).and_then(|(_, mut jukebox)| {
+            jukebox
+                .seek(position)
+                .map_err(|e| ApiError::BadRequest(format!("{e:#}")))
+        });
+        match result {
+            Ok(()) => Response::empty_204(),
+            Err(e) => e.into_response(),
         }
+    }
 
-        let range = &range[6..]; // strip "bytes="
-        let parts: Vec<&str> = range.split('-').collect();
-        if parts.len() != 2 {
-            return Ok(None);
+    /// Sets `?level=`, from 0 (muted) to 1 (the files' own loudness)
+    fn handle_player_volume(&self, request: &Request) -> Response {
+        let level = match request.get_param("level").map(|l| l.parse::<f32>()) {
+            Some(Ok(level)) if (0.0..=1.0).contains(&level) => level,
+            _ => {
+                return ApiError::BadRequest("level must be between 0 and 1".to_string())
+                    .into_response();
+            }
+        };
+        self.handle_player_command(request, |jukebox| jukebox.set_volume(level))
+    }
+
+    /// The playing entry of the queue with the jukebox's progress
+    fn handle_now_playing(&self, request: &Request) -> Response {
+        let now_playing = self.jukebox(request).and_then(|(zone, jukebox)| {
+            let first = zone.queue.lock().unwrap().entries().first().copied();
+            let entry = match first {
+                Some(entry) => {
+                    let meta = self
+                        .storage
+                        .lock()
+                        .unwrap()
+                        .get_track_metadata(entry.track_id)?;
+                    Some(QueueEntryResponse {
+                        id: entry.id,
+                        track_id: entry.track_id,
+                        title: meta.as_ref().map(|m| m.title.clone()),
+                        artist: meta.map(|m| m.artist),
+                    })
+                }
+                None => None,
+            };
+            let playing = entry.is_some();
+            Ok(NowPlayingResponse {
+                entry,
+                paused: jukebox.is_paused(),
+                position_secs: if playing {
+                    jukebox.position().as_secs_f64()
+                } else {
+                    0.0
+                },
+                duration_secs: jukebox
+                    .duration()
+                    .filter(|_| playing)
+                    .map(|d| d.as_secs_f64()),
+                volume: jukebox.volume(),
+            })
+        });
+        match now_playing {
+            Ok(now_playing) => Response::json(&now_playing),
+            Err(e) => e.into_response(),
         }
+    }
 
-        let start = parts[0].parse::<u64>().unwrap_or(0);
-        let end = if !parts[1].is_empty() {
-            parts[1].parse::<u64>().unwrap_or(file_size - 1)
-        } else {
-            file_size - 1
+    /// Page browsing and playing every track with files, `?q=` fills in the search
+    fn handle_library_page(&self, request: &Request) -> Response {
+        let (tracks, transliterate) = {
+            let mut storage = self.storage.lock().unwrap();
+            let transliterate = storage.transliterate_search();
+            let tracks = storage.list_tracks().and_then(|mut tracks| {
+                let mut metadata: HashMap<_, _> = storage
+                    .scan_metadata()?
+                    .into_iter()
+                    .map(|track| (track.id, track.metadata))
+                    .collect();
+                // tracks without metadata last, in the order they were added
+                sort_by_name(&mut tracks);
+                Ok(tracks
+                    .into_iter()
+                    .filter(|track| !track.files.is_empty())
+                    .map(|track| {
+                        let meta = metadata.remove(&track.id);
+                        PlaylistTrackResponse {
+                            track_id: track.id,
+                            title: meta.as_ref().map(|m| m.title.clone()),
+                            artist: meta.map(|m| m.artist),
+                        }
+                    })
+                    .collect::<Vec<_>>())
+            });
+            (tracks, transliterate)
+        };
+        let tracks = match tracks {
+            Ok(tracks) => tracks,
+            Err(e) => return ApiError::from(e).into_response(),
+        };
+        let data = LibraryPageResponse {
+            query: request.get_param("q"),
+            tracks,
+            transliteration: transliterate.then(|| {
+                CYRILLIC_TO_LATIN
+                    .iter()
+                    .map(|(letter, latin)| (*letter, latin.to_string()))
+                    .collect()
+            }),
         };
+        let page = self.render_page(
+            request,
+            "library.html",
+            include_str!("../html/library.html"),
+            &[("/*LIBRARY*/null", script_json(&data))],
+        );
+        Response::html(page).with_additional_header("Vary", "Accept-Language")
+    }
 
-        if start > end || end >= file_size {
-            return Err(ApiError::InvalidRange);
+    /// Page playing a shared playlist, `?t=` must carry the share token if the playlist has one
+    fn handle_playlist_page(&self, id: String, request: &Request) -> Response {
+        let not_found =
+            || Response::text(self.lang(request).texts().playlist_not_found).with_status_code(404);
+        let Ok(id) = id.parse() else {
+            return not_found();
+        };
+        let playlist = {
+            let mut storage = self.storage.lock().unwrap();
+            storage.get_playlist(id).and_then(|playlist| {
+                let tracks = playlist
+                    .tracks
+                    .iter()
+                    .map(|&track_id| {
+                        let meta = storage.get_track_metadata(track_id)?;
+                        Ok(PlaylistTrackResponse {
+                            track_id,
+                            title: meta.as_ref().map(|m| m.title.clone()),
+                            artist: meta.map(|m| m.artist),
+                        })
+                    })
+                    .collect::<Result<_, StorageError>>()?;
+                Ok((playlist, tracks))
+            })
+        };
+        let (playlist, tracks) = match playlist {
+            Ok(playlist) => playlist,
+            Err(StorageError::PlaylistNotFound(_)) => return not_found(),
+            Err(e) => return ApiError::from(e).into_response(),
+        };
+        // an unknown playlist and a wrong token look the same
+        if !playlist.is_shared_with(request.get_param("t").as_deref()) {
+            return not_found();
         }
+        let data = PlaylistPageResponse {
+            name: playlist.name,
+            tracks,
+        };
+        let page = self.render_page(
+            request,
+            "playlist.html",
+            include_str!("../html/playlist.html"),
+            &[("/*PLAYLIST*/null", script_json(&data))],
+        );
+        Response::html(page).with_additional_header("Vary", "Accept-Language")
+    }
 
-        Ok(Some((start, end)))
+    /// Files which make the scanner page an installable app, see [`pwa`]
+    fn handle_app_file(&self, file: &str) -> Response {
+        match file {
+            "manifest.webmanifest" => Response::json(&pwa::Manifest::new(&self.config.theme))
+                .with_unique_header("Content-Type", "application/manifest+json"),
+            // the worker controls the whole site, so it must not be cached for long
+            "sw.js" => Response::from_data("text/javascript", pwa::SERVICE_WORKER)
+                .with_additional_header("Cache-Control", "no-cache"),
+            "icon.svg" => Response::from_data("image/svg+xml", pwa::ICON),
+            _ => Response::empty_404(),
+        }
     }
 
-    fn handle_get_track_stream(&self, id: String, request: &Request) -> Response {
-        match self.get_track_stream(id, request) {
-            Ok(r) => r,
-            Err(e) => e.into_response(),
+    /// Reports whether the server can reach its database
+    fn handle_healthz(storage: &SharedStore, instance: &str) -> Response {
+        let status = match storage.lock() {
+            Ok(mut storage) => storage.updated_at().map(|_| ()).map_err(|e| e.to_string()),
+            Err(e) => Err(format!("storage lock is poisoned: {e}")),
+        };
+        match status {
+            Ok(()) => Response::json(&HealthResponse {
+                status: "ok",
+                instance,
+            }),
+            Err(e) => {
+                log::error!("health check failed: {e}");
+                Response::json(&HealthResponse {
+                    status: "unavailable",
+                    instance,
+                })
+                .with_status_code(503)
+            }
         }
     }
 
-    fn mime_for_track(path: &PathBuf) -> String {
-        let ext = path
-            .extension()
-            .map(|ext| ext.to_string_lossy())
-            .map(|s| s.to_lowercase());
-        let default = || {
-            mime_guess::from_path(path)
-                .first_or_octet_stream()
-                .to_string()
+    /// Filter of the `?artist=`, `?year_from=`, `?year_to=` and `?format=` parameters
+    fn track_filter(request: &Request) -> Result<TrackFilter, ApiError> {
+        let year = |param: &str| match request.get_param(param).map(|year| year.parse::<u32>()) {
+            None => Ok(None),
+            Some(Ok(year)) => Ok(Some(year)),
+            Some(Err(_)) => Err(ApiError::BadRequest(format!("{param} must be a year"))),
         };
-        ext.and_then(|ext| Self::mime_from_ext(ext.as_str()))
-            .unwrap_or_else(default)
+        Ok(TrackFilter {
+            artist: request.get_param("artist").filter(|a| !a.is_empty()),
+            year_from: year("year_from")?,
+            year_to: year("year_to")?,
+            format: request.get_param("format").filter(|f| !f.is_empty()),
+        })
     }
 
-    /// Map file extension (without dot) to proper MIME type for browser playback.
-    /// Returns None if the extension is not recognized.
-    pub fn mime_from_ext(ext: &str) -> Option<String> {
-        match ext {
-            "m4a" => Some("audio/x-m4a".to_string()), // Safari iOS compatible
-            "aac" => Some("audio/aac".to_string()),
-            "mp3" => Some("audio/mpeg".to_string()),
-            "wav" => Some("audio/wav".to_string()),
-            "ogg" => Some("audio/ogg".to_string()),
-            "flac" => Some("audio/flac".to_string()),
-            _ => None,
+    /// Tracks passing the filter of [`HttpServer::track_filter`], all of them by default.
+    /// `?sort=added` lists the newest first and `?sort=name` by artist and title sort names
+    fn handle_list_tracks(request: &Request, storage: &SharedStore) -> Response {
+        let filter = match Self::track_filter(request) {
+            Ok(filter) => filter,
+            Err(e) => return e.into_response(),
+        };
+        let sort = request.get_param("sort");
+        if !matches!(sort.as_deref(), None | Some("id" | "added" | "name")) {
+            return ApiError::BadRequest("sort must be one of id, added and name".to_string())
+                .into_response();
+        }
+        let tracks = storage.lock().unwrap().list_tracks_matching(&filter);
+        match tracks {
+            Ok(mut tracks) => {
+                match sort.as_deref() {
+                    Some("added") => tracks.sort_by_key(|track| std::cmp::Reverse(track.added)),
+                    Some("name") => sort_by_name(&mut tracks),
+                    _ => {}
+                }
+                Response::json(
+                    &tracks
+                        .into_iter()
+                        .map(TrackListResponse::new)
+                        .collect::<Vec<_>>(),
+                )
+            }
+            Err(e) => ApiError::from(e).into_response(),
         }
     }
 
-    /// streams just like /track/stream route
-    /// but accepts hash inside ?h= parameter.
-    fn handle_play(&self, request: &Request) -> Response {
-        let hash = if let Some(hash) = request.get_param("h") {
-            hash
-        } else {
-            return Response::text("Error: missing media hash").with_status_code(400);
+    /// Tracks added in the last `?days=` days, a week by default, like `/tracks` with the newest first
+    fn handle_list_recent(request: &Request, storage: &SharedStore) -> Response {
+        let days = match request.get_param("days").map(|days| days.parse::<u32>()) {
+            None => 7,
+            Some(Ok(days)) => days,
+            Some(Err(_)) => {
+                return ApiError::BadRequest("days must be a whole number".to_string())
+                    .into_response();
+            }
         };
-        match self.get_track_stream(hash, request) {
-            Ok(r) => r,
-            Err(e) => e.into_response(),
+        let since = Local::now() - chrono::Duration::days(days.into());
+        let tracks = storage.lock().unwrap().list_tracks();
+        match tracks {
+            Ok(tracks) => {
+                let mut recent: Vec<_> = tracks
+                    .into_iter()
+                    .filter(|track| track.added.is_some_and(|added| added >= since))
+                    .collect();
+                recent.sort_by_key(|track| std::cmp::Reverse(track.added));
+                Response::json(
+                    &recent
+                        .into_iter()
+                        .map(TrackListResponse::new)
+                        .collect::<Vec<_>>(),
+                )
+            }
+            Err(e) => ApiError::from(e).into_response(),
         }
     }
-}
 
-#[derive(Serialize, Deserialize)]
-struct TrackResponse {
-    track_id: TrackId,
-    location: Location,
-    metadata: Option<TrackMetadataResponse>,
-}
+    /// Artists of the metadata with their number of tracks
+    fn handle_list_artists(storage: &SharedStore) -> Response {
+        let artists = storage.lock().unwrap().list_artists();
+        match artists {
+            Ok(artists) => Response::json(
+                &artists
+                    .into_iter()
+                    .map(|artist| ArtistResponse {
+                        name: artist.name,
+                        track_count: artist.tracks,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-#[derive(Serialize, Deserialize)]
-struct TrackMetadataResponse {
-    pub artist: String,
-    pub title: String,
-    pub year: Option<u32>,
-    pub label: Option<String>,
-    pub artwork: Option<String>,
-}
+    /// Tracks crediting an artist, ignoring case, like `/tracks?artist=`. The other
+    /// filters of `/tracks` apply as well
+    fn handle_artist_tracks(name: String, request: &Request, storage: &SharedStore) -> Response {
+        let filter = match Self::track_filter(request) {
+            Ok(filter) => TrackFilter {
+                artist: Some(name.clone()),
+                ..filter
+            },
+            Err(e) => return e.into_response(),
+        };
+        let tracks = storage.lock().unwrap().list_tracks_matching(&filter);
+        match tracks {
+            Ok(tracks) if tracks.is_empty() => {
+                ApiError::NotFound(format!("no tracks of {name}")).into_response()
+            }
+            Ok(tracks) => Response::json(
+                &tracks
+                    .into_iter()
+                    .map(TrackListResponse::new)
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-impl TrackResponse {
-    fn from_domain(track: &TrackId, location: Location, meta: Option<TrackMetadata>) -> Self {
-        Self {
-            track_id: *track,
-            location,
-            metadata: meta.map(|metadata| TrackMetadataResponse {
-                artist: metadata.artist.clone(),
-                title: metadata.title.clone(),
-                year: metadata.year,
-                label: metadata.label.clone(),
-                artwork: metadata.artwork.clone().map(|a| a.0),
-            }),
+    /// Years of release with their number of tracks, and the same counts by decade
+    fn handle_list_years(storage: &SharedStore) -> Response {
+        let years = match storage.lock().unwrap().list_years() {
+            Ok(years) => years,
+            Err(e) => return ApiError::from(e).into_response(),
+        };
+        let mut decades: Vec<DecadeResponse> = Vec::new();
+        for year in &years {
+            let decade = year.year - year.year % 10;
+            match decades.last_mut() {
+                Some(last) if last.decade == decade => last.track_count += year.tracks,
+                _ => decades.push(DecadeResponse {
+                    decade,
+                    track_count: year.tracks,
+                }),
+            }
         }
+        Response::json(&YearsResponse {
+            years: years
+                .into_iter()
+                .map(|year| YearResponse {
+                    year: year.year,
+                    track_count: year.tracks,
+                })
+                .collect(),
+            decades,
+        })
     }
-}
 
-#[cfg(test)]
-pub fn parse_json_response<T: serde::de::DeserializeOwned>(
-    response: rouille::Response,
-) -> anyhow::Result<T> {
-    Ok(serde_json::from_reader(
-        response.data.into_reader_and_size().0,
-    )?)
-}
+    /// Tracks with the files matching `?q=`, a query like `find` takes, e.g.
+    /// `artist:"daft punk" year:>2000 -format:wav`. See [`localdeck_storage::search`]
+    fn handle_search(request: &Request, storage: &SharedStore) -> Response {
+        let query = request.get_param("q").unwrap_or_default();
+        let found = storage.lock().unwrap().find_files(&query, false);
+        match found {
+            Ok(found) => {
+                let mut results: Vec<SearchResultResponse> = found
+                    .into_iter()
+                    .map(|(track_id, locations)| {
+                        let mut locations: Vec<Location> = locations.into_iter().collect();
+                        locations.sort_by_cached_key(Location::to_string);
+                        SearchResultResponse {
+                            track_id,
+                            locations,
+                        }
+                    })
+                    .collect();
+                results.sort_by_key(|result| result.track_id);
+                Response::json(&results)
+            }
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use localdeck_storage::{
-        config::{Config, Database, LibrarySource},
-        file_hash::FileHash,
-        operations::{HashedFile, MetadataUpdate, Storage},
-        track::ArtworkRef,
-    };
+    /// Tracks released in a year, e.g. `1997`, or a decade, e.g. `1990s`. The filters
+    /// of `/tracks` apply as well
+    fn handle_year_tracks(year: String, request: &Request, storage: &SharedStore) -> Response {
+        let decade = year
+            .strip_suffix('s')
+            .and_then(|decade| decade.parse::<u32>().ok())
+            .filter(|decade| decade % 10 == 0);
+        let (from, to) = match (decade, year.parse::<u32>()) {
+            (Some(decade), _) => (decade, decade + 9),
+            (None, Ok(year)) => (year, year),
+            (None, Err(_)) => {
+                return ApiError::BadRequest(format!(
+                    "{year} is neither a year nor a decade like 1990s"
+                ))
+                .into_response();
+            }
+        };
+        let filter = match Self::track_filter(request) {
+            Ok(filter) => TrackFilter {
+                year_from: Some(from),
+                year_to: Some(to),
+                ..filter
+            },
+            Err(e) => return e.into_response(),
+        };
+        let tracks = storage.lock().unwrap().list_tracks_matching(&filter);
+        match tracks {
+            Ok(tracks) if tracks.is_empty() => {
+                ApiError::NotFound(format!("no tracks from {year}")).into_response()
+            }
+            Ok(tracks) => Response::json(
+                &tracks
+                    .into_iter()
+                    .map(TrackListResponse::new)
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-    use rouille::Request;
-    use std::{
-        collections::{HashMap, HashSet},
-        fs,
-        path::Path,
-        sync::{Arc, Mutex},
-    };
-    use tempfile::tempdir;
+    /// Albums of the library, the folders tracks are in, see [`album`]
+    fn handle_list_albums(storage: &SharedStore) -> Response {
+        let albums = {
+            let mut storage = storage.lock().unwrap();
+            Self::albums_with_metadata(&mut *storage)
+        };
+        match albums {
+            Ok((albums, metadata)) => Response::json(
+                &albums
+                    .iter()
+                    .map(|album| AlbumInfoResponse::new(album, &metadata))
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
 
-    pub fn parse_text_response(response: rouille::Response) -> String {
-        let mut buf = String::new();
-        let mut reader = response.data.into_reader_and_size().0;
-        reader.read_to_string(&mut buf).unwrap();
-        buf
+    /// Tracks of an album in playing order, with what their metadata tells of the release
+    fn handle_get_album(id: String, storage: &SharedStore) -> Response {
+        let albums = {
+            let mut storage = storage.lock().unwrap();
+            Self::albums_with_metadata(&mut *storage)
+        };
+        let (albums, mut metadata) = match albums {
+            Ok(albums) => albums,
+            Err(e) => return ApiError::from(e).into_response(),
+        };
+        let Some(album) = albums.into_iter().find(|album| id.parse() == Ok(album.id)) else {
+            return ApiError::NotFound(format!("album {id} not found")).into_response();
+        };
+        Response::json(&AlbumResponse {
+            info: AlbumInfoResponse::new(&album, &metadata),
+            tracks: album
+                .tracks
+                .into_iter()
+                .map(|track_id| {
+                    let meta = metadata.remove(&track_id);
+                    PlaylistTrackResponse {
+                        track_id,
+                        title: meta.as_ref().map(|m| m.title.clone()),
+                        artist: meta.map(|m| m.artist),
+                    }
+                })
+                .collect(),
+        })
     }
 
-    fn create_server(db: &Arc<Mutex<Storage>>) -> HttpServer {
-        HttpServer {
-            storage: Arc::clone(db),
-            config: HttpConfig {
-                bind_addr: "0.0.0.0".to_string(),
-                port: 8080,
-            },
+    fn albums_with_metadata(
+        storage: &mut dyn LibraryStore,
+    ) -> Result<(Vec<Album>, HashMap<TrackId, TrackMetadata>), StorageError> {
+        let albums = album::group_albums(&storage.list_tracks()?);
+        let metadata = storage
+            .scan_metadata()?
+            .into_iter()
+            .map(|track| (track.id, track.metadata))
+            .collect();
+        Ok((albums, metadata))
+    }
+
+    /// Tracks marked as favourite like `/tracks`, the most recently marked first
+    fn handle_list_favourites(storage: &SharedStore) -> Response {
+        let tracks = {
+            let mut storage = storage.lock().unwrap();
+            storage.list_favourites().and_then(|favourites| {
+                let mut tracks: HashMap<_, _> = storage
+                    .list_tracks()?
+                    .into_iter()
+                    .map(|track| (track.id, track))
+                    .collect();
+                Ok(favourites
+                    .into_iter()
+                    .filter_map(|track_id| tracks.remove(&track_id))
+                    .map(TrackListResponse::new)
+                    .collect::<Vec<_>>())
+            })
+        };
+        match tracks {
+            Ok(tracks) => Response::json(&tracks),
+            Err(e) => ApiError::from(e).into_response(),
         }
     }
 
-    fn create_server_with_tracks<S: AsRef<Path>>(
-        lib_root: S,
-    ) -> (HttpServer, HashMap<TrackId, HashSet<HashedFile>>) {
-        let storage = setup_storage(Some(Location::from_path(lib_root))).unwrap();
-        let files = {
-            let mut locked = storage.lock().unwrap();
-            locked.update_db_with_new_files().unwrap()
+    /// Marks the track as favourite, or unmarks it if it is one already
+    fn handle_toggle_favourite(id: String, storage: &SharedStore) -> Response {
+        let toggled = {
+            let mut storage = storage.lock().unwrap();
+            storage.resolve_track(id).and_then(|track_id| {
+                let favourite = !storage.is_favourite(track_id)?;
+                storage.set_favourite(track_id, favourite)?;
+                Ok(FavouriteResponse {
+                    track_id,
+                    favourite,
+                })
+            })
         };
-        (create_server(&storage), files)
+        match toggled {
+            Ok(toggled) => Response::json(&toggled),
+            Err(e) => ApiError::from(e).into_response(),
+        }
     }
 
-    fn create_empty_server() -> HttpServer {
-        let storage = setup_storage(None).unwrap();
-        create_server(&storage)
+    fn handle_get_track(id: String, storage: &SharedStore) -> Response {
+        let track_id = match storage.lock().unwrap().resolve_track(id) {
+            Ok(id) => id,
+            Err(e) => return ApiError::from(e).into_response(),
+        };
+
+        let data = {
+            let mut storage = storage.lock().unwrap();
+            storage
+                .find_track_file_with_meta(track_id)
+                .and_then(|(_, loc, metadata)| {
+                    let notes = storage.track_notes(track_id)?;
+                    let credits = storage.artist_credits(track_id)?;
+                    Ok((loc, metadata, notes, credits))
+                })
+        };
+
+        match data {
+            Ok((loc, metadata, notes, credits)) => Response::json(&TrackResponse::from_domain(
+                &track_id, loc, metadata, notes, credits,
+            )),
+
+            Err(e) => ApiError::from(e).into_response(),
+        }
     }
 
-    fn setup_storage(root: Option<Location>) -> anyhow::Result<Arc<Mutex<Storage>>> {
-        Ok(Arc::new(Mutex::new(Storage::new(Config {
-            database: Database::InMemory,
-            library_source: root
-                .map(|root| LibrarySource {
-                    roots: vec![root],
-                    follow_symlinks: false,
-                    ignored_dirs: vec![],
+    /// Metadata and availability of the tracks of a JSON array of ids, so the playlist
+    /// and queue pages need a single request
+    fn handle_get_tracks_batch(request: &Request, storage: &SharedStore) -> Response {
+        let ids: Vec<String> = match rouille::input::json_input(request) {
+            Ok(ids) => ids,
+            Err(e) => {
+                return ApiError::BadRequest(format!("expected a JSON array of track ids: {e}"))
+                    .into_response();
+            }
+        };
+        if ids.len() > MAX_BATCH_TRACKS {
+            return ApiError::BadRequest(format!(
+                "at most {MAX_BATCH_TRACKS} tracks can be asked for at once"
+            ))
+            .into_response();
+        }
+
+        let mut storage = storage.lock().unwrap();
+        let tracks: Result<Vec<_>, StorageError> = ids
+            .into_iter()
+            .map(|id| {
+                let Ok(track_id) = storage.resolve_track(id.clone()) else {
+                    return Ok(BatchTrackResponse {
+                        id,
+                        track_id: None,
+                        available: false,
+                        location: None,
+                        metadata: None,
+                        notes: None,
+                    });
+                };
+                let (location, metadata) = match storage.find_track_file_with_meta(track_id) {
+                    Ok((_, loc, meta)) => (Some(loc), meta),
+                    Err(StorageError::TrackNotFound(_) | StorageError::InvalidTrackFile { .. }) => {
+                        (None, storage.get_track_metadata(track_id)?)
+                    }
+                    Err(e) => return Err(e),
+                };
+                Ok(BatchTrackResponse {
+                    id,
+                    track_id: Some(track_id),
+                    available: location.is_some(),
+                    location,
+                    metadata: metadata.map(TrackMetadataResponse::from_domain),
+                    notes: storage.track_notes(track_id)?,
                 })
-                .unwrap_or_default(),
-        })?)))
+            })
+            .collect();
+        match tracks {
+            Ok(tracks) => Response::json(&tracks),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    /// Library description for `localdeck sync`, see [`crate::sync`]
+    fn handle_sync(storage: &SharedStore) -> Response {
+        let library = sync::load_library(&mut *storage.lock().unwrap());
+        match library {
+            Ok(library) => Response::json(&library),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    /// Stored lyrics, with synced ones split into timed lines for highlighting during playback
+    fn handle_get_lyrics(id: String, storage: &SharedStore) -> Response {
+        let lyrics = {
+            let mut storage = storage.lock().unwrap();
+            storage
+                .resolve_track(id)
+                .and_then(|track_id| Ok((track_id, storage.get_lyrics(track_id)?)))
+        };
+        match lyrics {
+            Ok((track_id, Some(lyrics))) => Response::json(&LyricsResponse {
+                track_id,
+                lines: lyrics
+                    .synced_lines()
+                    .into_iter()
+                    .map(|line| LyricLineResponse {
+                        time: line.time.as_secs_f64(),
+                        text: line.text,
+                    })
+                    .collect(),
+                plain: lyrics.plain,
+                synced: lyrics.synced,
+            }),
+            Ok((track_id, None)) => {
+                ApiError::NotFound(format!("no lyrics stored for track {track_id}")).into_response()
+            }
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    /// Cover of the track, in a smaller format if the client accepts one, see [`artwork`]
+    fn handle_get_artwork(id: String, request: &Request, storage: &SharedStore) -> Response {
+        let cover = {
+            let mut storage = storage.lock().unwrap();
+            storage.resolve_track(id).and_then(|track_id| {
+                let metadata = storage.get_track_metadata(track_id)?;
+                Ok((track_id, metadata.and_then(|meta| meta.artwork)))
+            })
+        };
+        let response = match cover {
+            Ok((_, Some(cover))) => artwork::respond(&cover.0, request.header("Accept")),
+            Ok((track_id, None)) => Err(ApiError::NotFound(format!(
+                "track {track_id} has no artwork"
+            ))),
+            Err(e) => Err(ApiError::from(e)),
+        };
+        response.unwrap_or_else(ApiError::into_response)
+    }
+
+    /// Description of the track for Chromecast receivers, see [`cast`]
+    fn handle_get_cast_media(id: String, request: &Request, storage: &SharedStore) -> Response {
+        let data = {
+            let mut storage = storage.lock().unwrap();
+            storage.resolve_track(id).and_then(|track_id| {
+                let (path, _, metadata) = storage.find_track_file_with_meta(track_id)?;
+                Ok((track_id, path, metadata))
+            })
+        };
+        match data {
+            Ok((track_id, path, metadata)) => Response::json(&CastMedia::new(
+                request,
+                track_id,
+                Self::mime_for_track(&path),
+                metadata,
+            )),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    /// streams music file, respecting byterange
+    /// returns Response with ok status, or ApiError
+    fn get_track_stream(&self, id: String, request: &Request) -> Result<TrackStream, ApiError> {
+        let mut storage = self.storage.lock().map_err(|e| {
+            StorageError::Internal(anyhow!(
+                "Could not access localdeck storage under lock: {e}"
+            ))
+        })?;
+
+        let track_id = storage.resolve_track(id.clone())?;
+
+        let (path, loc, meta) = storage.find_track_file_with_meta(track_id)?;
+
+        let with_extra_headers = |resp: Response| -> Response {
+            let mut resp = resp.with_additional_header("Accept-Ranges", "bytes");
+
+            if let Some(meta) = meta {
+                resp = resp
+                    .with_additional_header("X-Track-Artist", meta.artist)
+                    .with_additional_header("X-Track-Title", meta.title)
+            }
+            resp
+        };
+
+        if !loc.is_local() {
+            // other requests should not wait for the other instance or the bucket
+            drop(storage);
+            log::debug!("STREAM {id} -> proxied to {loc}");
+            let token = match &loc {
+                Location::Remote { token, .. } => token.as_deref(),
+                _ => None,
+            };
+            let url = path.to_string_lossy();
+            return Ok(TrackStream {
+                track_id,
+                local: false,
+                response: with_extra_headers(proxy::stream(&url, token, request)?),
+            });
+        }
+
+        let mime = Self::mime_for_track(&path);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                // the next request plays another copy of the track, if it has one. Other
+                // errors, e.g. permissions, may pass and are no reason to hold the file back
+                if e.kind() == io::ErrorKind::NotFound
+                    && storage.quarantine(&loc, &e.to_string())?
+                {
+                    log::warn!("STREAM {id} -> quarantined {loc}: {e}");
+                }
+                return Err(StorageError::Fs(e).into());
+            }
+        };
+        let file_meta = file.metadata().map_err(StorageError::Fs)?;
+        let file_size = file_meta.len();
+        let etag = Self::file_etag(&file_meta);
+        let with_extra_headers =
+            |resp: Response| with_extra_headers(resp).with_additional_header("ETag", etag.clone());
+
+        // ---------------------------------------------
+        // Parse Range header if present
+        // ---------------------------------------------
+        // a range of a file changed since the client's copy would corrupt it, send it whole
+        let range_header = request.header("Range").filter(|_| {
+            request
+                .header("If-Range")
+                .is_none_or(|tag| tag.trim() == etag)
+        });
+        if let Some(range) = range_header {
+            // Expect something like "bytes=123-456"
+            if let Some((start, end)) = Self::parse_http_range(range, file_size)? {
+                let chunk_size = end - start + 1;
+                file.seek(SeekFrom::Start(start))
+                    .map_err(StorageError::Fs)?;
+
+                log::debug!(
+                    "STREAM {} -> 206 Partial Content, path: {}, MIME type: {}, bytes {}-{}",
+                    id,
+                    path.to_string_lossy(),
+                    mime,
+                    start,
+                    end
+                );
+
+                let mut resp = Response::from_data(mime, Vec::new())
+                    .with_status_code(206)
+                    .with_additional_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, file_size),
+                    );
+                // the range is read while it is sent, so a HEAD request reads none of it
+                resp.data =
+                    ResponseBody::from_reader_and_size(file.take(chunk_size), chunk_size as usize);
+                let resp = with_extra_headers(resp);
+
+                return Ok(TrackStream {
+                    track_id,
+                    local: true,
+                    response: resp,
+                });
+            }
+        }
+
+        // No Range header, return full file
+        log::debug!(
+            "STREAM {} -> 200 OK, path: {}, MIME type: {}",
+            id,
+            path.to_string_lossy(),
+            mime
+        );
+        Ok(TrackStream {
+            track_id,
+            local: true,
+            response: with_extra_headers(Response::from_file(mime, file)),
+        })
+    }
+
+    /// Strong validator of a local file, changes whenever it is rewritten
+    fn file_etag(meta: &Metadata) -> String {
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        format!("\"{:x}-{:x}\"", meta.len(), modified.as_nanos())
+    }
+
+    /// parse a "bytes=start-end", "bytes=start-" or "bytes=-suffix" header.
+    /// Returns (start, end) clamped to the file, None for ranges in other units or
+    /// several ranges, which are answered with the whole file, or an error for
+    /// malformed and unsatisfiable ranges
+    fn parse_http_range(range: &str, file_size: u64) -> Result<Option<(u64, u64)>, ApiError> {
+        let Some(range) = range.trim().strip_prefix("bytes=") else {
+            return Ok(None);
+        };
+        if range.contains(',') {
+            return Ok(None);
+        }
+        let invalid = || ApiError::InvalidRange(file_size);
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let number = |s: &str| s.trim().parse::<u64>().map_err(|_| invalid());
+
+        let (start, end) = match (start.trim().is_empty(), end.trim().is_empty()) {
+            // the last `end` bytes
+            (true, false) => {
+                let suffix = number(end)?;
+                if suffix == 0 {
+                    return Err(invalid());
+                }
+                (
+                    file_size.saturating_sub(suffix),
+                    file_size.saturating_sub(1),
+                )
+            }
+            (false, true) => (number(start)?, file_size.saturating_sub(1)),
+            (false, false) => {
+                let (start, end) = (number(start)?, number(end)?);
+                if start > end {
+                    return Err(invalid());
+                }
+                (start, end.min(file_size.saturating_sub(1)))
+            }
+            (true, true) => return Err(invalid()),
+        };
+
+        if start >= file_size {
+            return Err(invalid());
+        }
+
+        Ok(Some((start, end)))
+    }
+
+    fn handle_get_track_stream(&self, id: String, request: &Request) -> Response {
+        match self.get_track_stream(id, request) {
+            Ok(stream) => stream.response,
+            Err(e) => e.into_response(),
+        }
+    }
+
+    /// Sends the stream `respond` builds if the caps of `streams` in the config allow
+    /// another one to the client, counting it until it is sent at the configured rate
+    fn limit_stream(&self, request: &Request, respond: impl FnOnce() -> Response) -> Response {
+        let ip = request.remote_addr().ip().to_canonical();
+        match self.streams.acquire(ip) {
+            Ok(slot) => slot.hold_during(respond()),
+            Err(busy) => {
+                log::warn!("refused to stream {}: {busy}", request.url());
+                busy.into_response()
+            }
+        }
+    }
+
+    /// Answer to a HEAD request: the headers of `response` and the length of its
+    /// body, if known, without sending the body
+    fn without_body(mut response: Response) -> Response {
+        let (_, size) = response.data.into_reader_and_size();
+        response.data = match size {
+            Some(size) => ResponseBody::from_reader_and_size(io::empty(), size),
+            None => ResponseBody::empty(),
+        };
+        response
+    }
+
+    fn mime_for_track(path: &PathBuf) -> String {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .map(|s| s.to_lowercase());
+        let default = || {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string()
+        };
+        ext.and_then(|ext| Self::mime_from_ext(ext.as_str()))
+            .unwrap_or_else(default)
+    }
+
+    /// Map file extension (without dot) to proper MIME type for browser playback.
+    /// Returns None if the extension is not recognized.
+    pub fn mime_from_ext(ext: &str) -> Option<String> {
+        match ext {
+            "m4a" => Some("audio/x-m4a".to_string()), // Safari iOS compatible
+            "aac" => Some("audio/aac".to_string()),
+            "mp3" => Some("audio/mpeg".to_string()),
+            "wav" => Some("audio/wav".to_string()),
+            "ogg" => Some("audio/ogg".to_string()),
+            "flac" => Some("audio/flac".to_string()),
+            _ => None,
+        }
+    }
+
+    /// streams just like /track/stream route
+    /// but accepts hash inside ?h= parameter.
+    /// Errors guests may see are translated, see [`Lang`].
+    fn handle_play(&self, request: &Request) -> Response {
+        let texts = self.lang(request).texts();
+        let hash = if let Some(hash) = request.get_param("h") {
+            hash
+        } else {
+            return Response::text(texts.missing_hash).with_status_code(400);
+        };
+        let result = self.get_track_stream(hash.clone(), request);
+        self.record_scan(hash, &result, request);
+        match result {
+            Ok(stream) => stream.response,
+            Err(ApiError::NotFound(_)) => {
+                Response::text(texts.track_not_found).with_status_code(404)
+            }
+            Err(e) => e.into_response(),
+        }
+    }
+
+    /// Card link of a whole playlist, `?p=<id>` (with `?t=` for playlists shared by link).
+    /// Redirects to the playlist page, which then starts with its first track
+    fn handle_play_list(&self, request: &Request) -> Response {
+        let not_found =
+            || Response::text(self.lang(request).texts().playlist_not_found).with_status_code(404);
+        let Some(id) = request.get_param("p").and_then(|id| id.parse().ok()) else {
+            return not_found();
+        };
+        let token = request.get_param("t");
+        let playlist = self.storage.lock().unwrap().get_playlist(id);
+        match playlist {
+            Ok(playlist) if playlist.is_shared_with(token.as_deref()) => {
+                let mut target = format!("/playlists/{id}?play=1");
+                if let Some(token) = &playlist.share_token {
+                    target.push_str(&format!("&t={token}"));
+                }
+                Response::redirect_302(target)
+            }
+            Ok(_) | Err(StorageError::PlaylistNotFound(_)) => not_found(),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    /// Redirects to `/play` of a random playable track, for a "surprise me" card.
+    /// `?playlist=` (with `?t=` for playlists shared by link) and the filters of
+    /// `/tracks` narrow the choice
+    fn handle_random(&self, request: &Request) -> Response {
+        let texts = self.lang(request).texts();
+        let not_found = || Response::text(texts.track_not_found).with_status_code(404);
+        let filter = match Self::track_filter(request) {
+            Ok(filter) => filter,
+            Err(e) => return e.into_response(),
+        };
+        let mut storage = self.storage.lock().unwrap();
+        let candidates = storage
+            .list_tracks_matching(&filter)
+            .map(|tracks| tracks.into_iter().map(|track| track.id).collect::<Vec<_>>());
+        let candidates = match (candidates, request.get_param("playlist")) {
+            (Ok(tracks), None) => tracks,
+            (Ok(tracks), Some(id)) => {
+                let Ok(id) = id.parse() else {
+                    return not_found();
+                };
+                match storage.get_playlist(id) {
+                    Ok(playlist) if playlist.is_shared_with(request.get_param("t").as_deref()) => {
+                        let matching: HashSet<TrackId> = tracks.into_iter().collect();
+                        playlist
+                            .tracks
+                            .into_iter()
+                            .filter(|track| matching.contains(track))
+                            .collect()
+                    }
+                    Ok(_) | Err(StorageError::PlaylistNotFound(_)) => return not_found(),
+                    Err(e) => return ApiError::from(e).into_response(),
+                }
+            }
+            (Err(e), _) => return ApiError::from(e).into_response(),
+        };
+        if candidates.is_empty() {
+            return not_found();
+        }
+
+        // the first playable track from a random position on
+        let start = RandomState::new().hash_one(candidates.len()) as usize % candidates.len();
+        let playable = candidates
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(candidates.len())
+            .find(|&&track| storage.find_track_file_with_meta(track).is_ok());
+        match playable {
+            Some(track) => Response::redirect_302(format!("/play?h={track}")),
+            None => not_found(),
+        }
+    }
+
+    /// Track to play after `id`, so a player can go on after one song. `?order=album`,
+    /// the default, continues with the next file of the same folder, `shuffle` with any
+    /// other track and `playlist:<id>` (with `?t=` for playlists shared by link) with the
+    /// next entry of that playlist. Tracks without a playable file are skipped
+    fn handle_next_track(&self, id: String, request: &Request) -> Response {
+        match self.next_track(id, request) {
+            Ok(track_id) => Response::json(&NextTrackResponse {
+                track_id,
+                stream_url: format!("{API_V1}/tracks/{track_id}/stream"),
+            }),
+            Err(e) => e.into_response(),
+        }
+    }
+
+    fn next_track(&self, id: String, request: &Request) -> Result<TrackId, ApiError> {
+        let mut storage = self.storage.lock().unwrap();
+        let current = storage.resolve_track(id)?;
+        let order = request
+            .get_param("order")
+            .unwrap_or_else(|| "album".to_string());
+        let candidates: Vec<TrackId> = match order.split_once(':') {
+            None if order == "album" => {
+                let tracks = storage.list_tracks()?;
+                album::album_of(&tracks, current)
+                    .map(|album| album.tracks)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .skip_while(|&track| track != current)
+                    .skip(1)
+                    .collect()
+            }
+            None if order == "shuffle" => {
+                let others: Vec<TrackId> = storage
+                    .list_tracks()?
+                    .into_iter()
+                    .filter(|track| track.id != current && !track.files.is_empty())
+                    .map(|track| track.id)
+                    .collect();
+                let start = RandomState::new().hash_one(current) as usize % others.len().max(1);
+                others[start..]
+                    .iter()
+                    .chain(&others[..start])
+                    .copied()
+                    .collect()
+            }
+            Some(("playlist", playlist)) => {
+                let not_found = || ApiError::NotFound(format!("playlist {playlist} not found"));
+                let id = playlist.parse().map_err(|_| not_found())?;
+                match storage.get_playlist(id) {
+                    // an unknown playlist and a wrong token look the same
+                    Ok(playlist) if playlist.is_shared_with(request.get_param("t").as_deref()) => {
+                        playlist
+                            .tracks
+                            .into_iter()
+                            .skip_while(|&track| track != current)
+                            .skip(1)
+                            .collect()
+                    }
+                    Ok(_) | Err(StorageError::PlaylistNotFound(_)) => return Err(not_found()),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            _ => {
+                return Err(ApiError::BadRequest(format!(
+                    "unknown order {order}, expected album, shuffle or playlist:<id>"
+                )));
+            }
+        };
+        candidates
+            .into_iter()
+            .find(|&track| storage.find_track_file_with_meta(track).is_ok())
+            .ok_or_else(|| ApiError::NotFound(format!("no track follows track {current}")))
+    }
+
+    /// Redirects a short link printed on a card to its path, usually `/play`
+    fn handle_short_link(&self, code: String) -> Response {
+        let target = self.storage.lock().unwrap().resolve_short_link(&code);
+        match target {
+            // only paths of this server are shortened, never another site
+            Ok(Some(target)) if target.starts_with('/') && !target.starts_with("//") => {
+                Response::redirect_302(target)
+            }
+            Ok(_) => ApiError::NotFound(format!("short link {code} not found")).into_response(),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+
+    fn handle_reload(&self, request: &Request) -> Response {
+        if self.reload.is_none() {
+            return ApiError::NotFound("reloading the config is not enabled".to_string())
+                .into_response();
+        }
+        {
+            let reloaded = self.reloaded.read().unwrap();
+            let access = reloaded.as_ref().unwrap_or(&self.config);
+            if !auth::is_admin(access, request) {
+                return ApiError::Forbidden(
+                    "reloading the config needs basic_auth or an admin token".to_string(),
+                )
+                .into_response();
+            }
+        }
+        match self.reload_config() {
+            Ok(response) => Response::json(&response),
+            Err(e) => ApiError::BadRequest(format!("{e:#}")).into_response(),
+        }
+    }
+
+    /// Reads the config with the loader of [`HttpServer::with_reload`] and takes it over,
+    /// for `POST /admin/reload` and SIGHUP
+    fn reload_config(&self) -> anyhow::Result<ReloadResponse> {
+        let load = self
+            .reload
+            .as_ref()
+            .ok_or_else(|| anyhow!("reloading the config is not enabled"))?;
+        let ReloadedConfig {
+            http: config,
+            log_level,
+            ignored_dirs,
+        } = load().map_err(|e| {
+            log::warn!("config not reloaded: {e:#}");
+            e.context("config not reloaded")
+        })?;
+        let response = ReloadResponse {
+            basic_auth: config.basic_auth.is_some(),
+            tokens: config.tokens.len(),
+            ip_rules: config.ip_rules.len(),
+            log_level: log_level.to_string(),
+            ignored_dirs: ignored_dirs.len(),
+        };
+        self.storage
+            .lock()
+            .map_err(|e| anyhow!("could not lock the library: {e}"))?
+            .set_ignored_dirs(ignored_dirs);
+        log::set_max_level(log_level);
+        info!(
+            "reloaded config: basic auth {}, {} tokens, {} ip rules, log level {}, {} ignored dirs",
+            if response.basic_auth { "on" } else { "off" },
+            response.tokens,
+            response.ip_rules,
+            response.log_level,
+            response.ignored_dirs
+        );
+        *self.reloaded.write().unwrap() = Some(config);
+        Ok(response)
+    }
+
+    /// Reloads the config on every SIGHUP, e.g. sent by `systemctl reload`. Unlike
+    /// `POST /admin/reload` this needs no credentials, only the right to signal the process
+    #[cfg(unix)]
+    fn reload_on_sighup(server: Arc<Self>) {
+        let mut signals = match signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP]) {
+            Ok(signals) => signals,
+            Err(e) => {
+                log::warn!("not reloading the config on SIGHUP: {e}");
+                return;
+            }
+        };
+        let spawned = std::thread::Builder::new()
+            .name("sighup".to_string())
+            .spawn(move || {
+                for _ in signals.forever() {
+                    // failures are logged by reload_config
+                    let _ = server.reload_config();
+                }
+            });
+        if let Err(e) = spawned {
+            log::warn!("not reloading the config on SIGHUP: {e}");
+        }
+    }
+
+    /// Logs a `/play` hit for `stats scans`. Players fetch long tracks in several
+    /// ranged requests, only the one starting at the first byte counts as a scan,
+    /// and a HEAD probe plays nothing.
+    fn record_scan(
+        &self,
+        card_id: String,
+        result: &Result<TrackStream, ApiError>,
+        request: &Request,
+    ) {
+        let continued = request
+            .header("Range")
+            .is_some_and(|range| !range.trim().starts_with("bytes=0-"));
+        if continued || request.method() == "HEAD" {
+            return;
+        }
+        let mut storage = self.storage.lock().unwrap();
+        let (track_id, outcome) = match result {
+            Ok(stream) if stream.local => (Some(stream.track_id), ScanOutcome::Local),
+            Ok(stream) => (Some(stream.track_id), ScanOutcome::Remote),
+            // the card may resolve to a track without available files
+            Err(ApiError::NotFound(_)) => (
+                storage.resolve_track(card_id.clone()).ok(),
+                ScanOutcome::NotFound,
+            ),
+            Err(_) => (
+                storage.resolve_track(card_id.clone()).ok(),
+                ScanOutcome::Failed,
+            ),
+        };
+        let scan = CardScan {
+            scanned_at: Local::now(),
+            card_id,
+            track_id,
+            outcome,
+            user_agent: request.header("User-Agent").map(str::to_string),
+        };
+        if let Err(e) = storage.record_card_scan(&scan) {
+            log::warn!("failed to log the scan of card {}: {e}", scan.card_id);
+        }
+    }
+
+    /// Scans per card as JSON, `?days=` limits them to the last days
+    fn handle_scan_stats(&self, request: &Request) -> Response {
+        let since = match request.get_param("days").map(|days| days.parse::<u32>()) {
+            None => None,
+            Some(Ok(days)) => Some(Local::now() - chrono::Duration::days(days.into())),
+            Some(Err(_)) => {
+                return ApiError::BadRequest("days must be a whole number".to_string())
+                    .into_response();
+            }
+        };
+        let stats = {
+            let mut storage = self.storage.lock().unwrap();
+            storage.list_card_scans(since).and_then(|scans| {
+                let mut metadata: HashMap<_, _> = storage
+                    .scan_metadata()?
+                    .into_iter()
+                    .map(|track| (track.id, track.metadata))
+                    .collect();
+                Ok(card_scans::summarize(&scans)
+                    .into_iter()
+                    .map(|stats| {
+                        let meta = stats.track_id.and_then(|id| metadata.remove(&id));
+                        ScanStatsResponse {
+                            card_id: stats.card_id,
+                            track_id: stats.track_id,
+                            title: meta.as_ref().map(|m| m.title.clone()),
+                            artist: meta.map(|m| m.artist),
+                            scans: stats.scans,
+                            local: stats.local,
+                            remote: stats.remote,
+                            not_found: stats.not_found,
+                            failed: stats.failed,
+                            last_scan: stats.last_scan.to_rfc3339(),
+                        }
+                    })
+                    .collect::<Vec<_>>())
+            })
+        };
+        match stats {
+            Ok(stats) => Response::json(&stats),
+            Err(e) => ApiError::from(e).into_response(),
+        }
+    }
+}
+
+/// A track's stream, with where it is streamed from
+struct TrackStream {
+    track_id: TrackId,
+    /// false if proxied from another instance or a bucket
+    local: bool,
+    response: Response,
+}
+
+fn random_instance_id() -> String {
+    // std seeds every `RandomState` with fresh random keys
+    format!("{:016x}", RandomState::new().hash_one(std::process::id()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArtistResponse {
+    name: String,
+    track_count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct YearsResponse {
+    /// the earliest first, years without tracks are left out
+    years: Vec<YearResponse>,
+    decades: Vec<DecadeResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct YearResponse {
+    year: u32,
+    track_count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DecadeResponse {
+    /// first year of the decade, e.g. `1990`
+    decade: u32,
+    track_count: usize,
+}
+
+/// Album as listed by `/albums`
+#[derive(Serialize, Deserialize)]
+struct AlbumInfoResponse {
+    /// the lowest id of its tracks
+    id: TrackId,
+    /// of the folder
+    name: String,
+    /// `None` if the tracks name different artists or none
+    artist: Option<String>,
+    /// earliest year of its tracks
+    year: Option<u32>,
+    /// of the first track having artwork
+    artwork: Option<String>,
+    track_count: usize,
+}
+
+impl AlbumInfoResponse {
+    fn new(album: &Album, metadata: &HashMap<TrackId, TrackMetadata>) -> Self {
+        let metadata: Vec<_> = album
+            .tracks
+            .iter()
+            .filter_map(|track| metadata.get(track))
+            .collect();
+        let artist = metadata.first().map(|m| &m.artist);
+        Self {
+            id: album.id,
+            name: album.name.clone(),
+            artist: artist
+                .filter(|&artist| metadata.iter().all(|m| &m.artist == artist))
+                .cloned(),
+            year: metadata.iter().filter_map(|m| m.year).min(),
+            artwork: metadata
+                .iter()
+                .find_map(|m| m.artwork.as_ref().map(|a| a.0.clone())),
+            track_count: album.tracks.len(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AlbumResponse {
+    #[serde(flatten)]
+    info: AlbumInfoResponse,
+    /// in playing order
+    tracks: Vec<PlaylistTrackResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NextTrackResponse {
+    track_id: TrackId,
+    /// relative to the server, e.g. `/api/v1/tracks/12/stream`
+    stream_url: String,
+}
+
+/// Settings taken over by `POST /admin/reload`
+#[derive(Serialize, Deserialize)]
+struct ReloadResponse {
+    basic_auth: bool,
+    tokens: usize,
+    ip_rules: usize,
+    log_level: String,
+    ignored_dirs: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrackResponse {
+    track_id: TrackId,
+    location: Location,
+    metadata: Option<TrackMetadataResponse>,
+    /// free-form notes, see `localdeck meta add --notes`
+    notes: Option<String>,
+    /// credited artists in the order they are shown, empty without metadata
+    artists: Vec<ArtistCreditResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArtistCreditResponse {
+    name: String,
+    /// `main`, `featuring` or `remixer`
+    role: String,
+}
+
+/// Data of the playlist page
+#[derive(Serialize, Deserialize)]
+struct PlaylistPageResponse {
+    name: String,
+    tracks: Vec<PlaylistTrackResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LibraryPageResponse {
+    /// search the page starts with
+    query: Option<String>,
+    tracks: Vec<PlaylistTrackResponse>,
+    /// Latin letters of Cyrillic ones if the search matches them, see [`localdeck_storage::search`]
+    transliteration: Option<HashMap<char, String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlaylistTrackResponse {
+    track_id: TrackId,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueueResponse {
+    /// in playing order, the first one is playing
+    entries: Vec<QueueEntryResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueueEntryResponse {
+    id: EntryId,
+    track_id: TrackId,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueuedResponse {
+    id: EntryId,
+    track_id: TrackId,
+    /// 0 if nothing else is queued, i.e. the track plays right away
+    position: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZoneResponse {
+    name: String,
+    /// false if a browser at `/queue/player` plays the queue
+    jukebox: bool,
+    queued: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NowPlayingResponse {
+    /// `None` while the queue is empty
+    entry: Option<QueueEntryResponse>,
+    paused: bool,
+    position_secs: f64,
+    /// `None` if the file's format does not tell
+    duration_secs: Option<f64>,
+    /// 0 to 1
+    volume: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FavouriteResponse {
+    track_id: TrackId,
+    /// state after the toggle
+    favourite: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LyricsResponse {
+    track_id: TrackId,
+    plain: Option<String>,
+    /// LRC text as stored
+    synced: Option<String>,
+    /// `synced` split into lines, empty without synced lyrics
+    lines: Vec<LyricLineResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LyricLineResponse {
+    /// seconds from the start of the track
+    time: f64,
+    text: String,
+}
+
+/// Scans of one card, see [`card_scans::CardScanStats`]
+#[derive(Serialize, Deserialize)]
+struct ScanStatsResponse {
+    card_id: String,
+    track_id: Option<TrackId>,
+    title: Option<String>,
+    artist: Option<String>,
+    scans: usize,
+    local: usize,
+    remote: usize,
+    not_found: usize,
+    failed: usize,
+    /// RFC 3339
+    last_scan: String,
+}
+
+#[derive(Serialize)]
+struct HealthResponse<'a> {
+    status: &'static str,
+    instance: &'a str,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrackListResponse {
+    track_id: TrackId,
+    /// RFC 3339, unknown for tracks added by older versions
+    added: Option<String>,
+    locations: Vec<Location>,
+    /// the recorded files, in the order of `locations`
+    files: Vec<FileResponse>,
+}
+
+impl TrackListResponse {
+    fn new(track: TrackListEntry) -> Self {
+        TrackListResponse {
+            track_id: track.id,
+            added: track.added.map(|time| time.to_rfc3339()),
+            locations: track.locations().cloned().collect(),
+            files: track
+                .files
+                .into_iter()
+                .map(|file| FileResponse {
+                    location: file.loc,
+                    size: file.size,
+                    modified: file.modified.map(|time| time.to_rfc3339()),
+                    format: file.format,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileResponse {
+    location: Location,
+    /// bytes
+    size: i64,
+    /// RFC 3339, unknown for files streamed over the network
+    modified: Option<String>,
+    /// lowercase extension, e.g. `flac`
+    format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrackMetadataResponse {
+    pub artist: String,
+    pub title: String,
+    pub year: Option<u32>,
+    pub label: Option<String>,
+    pub artwork: Option<String>,
+}
+
+/// Entry of `GET /search`, ordered by track id
+#[derive(Serialize, Deserialize)]
+struct SearchResultResponse {
+    track_id: TrackId,
+    /// matching files of the track
+    locations: Vec<Location>,
+}
+
+/// Entry of `POST /tracks/batch`, in the order of the requested ids
+#[derive(Serialize, Deserialize)]
+struct BatchTrackResponse {
+    /// as requested, a track id or a card alias
+    id: String,
+    /// unknown if the id names no track
+    track_id: Option<TrackId>,
+    /// whether a file of the track can be streamed now
+    available: bool,
+    /// of the file that would be streamed
+    location: Option<Location>,
+    metadata: Option<TrackMetadataResponse>,
+    notes: Option<String>,
+}
+
+impl TrackMetadataResponse {
+    fn from_domain(metadata: TrackMetadata) -> Self {
+        Self {
+            artist: metadata.artist,
+            title: metadata.title,
+            year: metadata.year,
+            label: metadata.label,
+            artwork: metadata.artwork.map(|a| a.0),
+        }
+    }
+}
+
+impl TrackResponse {
+    fn from_domain(
+        track: &TrackId,
+        location: Location,
+        meta: Option<TrackMetadata>,
+        notes: Option<String>,
+        credits: Vec<ArtistCredit>,
+    ) -> Self {
+        Self {
+            track_id: *track,
+            location,
+            metadata: meta.map(TrackMetadataResponse::from_domain),
+            notes,
+            artists: credits
+                .into_iter()
+                .map(|credit| ArtistCreditResponse {
+                    name: credit.name,
+                    role: credit.role.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn parse_json_response<T: serde::de::DeserializeOwned>(
+    response: rouille::Response,
+) -> anyhow::Result<T> {
+    Ok(serde_json::from_reader(
+        response.data.into_reader_and_size().0,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{ApiToken, BasicAuth, Role},
+        ip_filter::{IpRule, Subnet},
+        schedule::PlayTarget,
+        stream_limit::StreamLimits,
+        theme::ThemeConfig,
+    };
+    use localdeck_storage::{
+        config::{Config, Database, LibrarySource},
+        file_hash::{FileHash, HashKind},
+        lyrics::TrackLyrics,
+        operations::{HashedFile, MetadataUpdate, Storage},
+        remote::{SyncFile, SyncLibrary, SyncTrack},
+        track::ArtworkRef,
+    };
+
+    use rouille::Request;
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::Path,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicBool, Ordering},
+        },
+    };
+    use tempfile::tempdir;
+
+    pub fn parse_text_response(response: rouille::Response) -> String {
+        let mut buf = String::new();
+        let mut reader = response.data.into_reader_and_size().0;
+        reader.read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    fn create_server(db: &Arc<Mutex<Storage>>) -> HttpServer {
+        HttpServer {
+            storage: db.clone(),
+            instance_id: random_instance_id(),
+            zones: vec![Zone::new(DEFAULT_ZONE, None)],
+            streams: StreamCounter::default(),
+            reload: None,
+            reloaded: RwLock::new(None),
+            config: HttpConfig {
+                bind_addr: "0.0.0.0".to_string(),
+                port: 8080,
+                language: None,
+                theme: Default::default(),
+                basic_auth: None,
+                tokens: Vec::new(),
+                public_card_routes: true,
+                ip_rules: Vec::new(),
+                party_queue: false,
+                schedule: Vec::new(),
+                static_dir: None,
+                streams: Default::default(),
+            },
+        }
+    }
+
+    fn create_server_with_tracks<S: AsRef<Path>>(
+        lib_root: S,
+    ) -> (HttpServer, HashMap<TrackId, HashSet<HashedFile>>) {
+        let storage = setup_storage(Some(Location::from_path(lib_root))).unwrap();
+        let files = {
+            let mut locked = storage.lock().unwrap();
+            locked.update_db_with_new_files().unwrap()
+        };
+        (create_server(&storage), files)
+    }
+
+    fn create_empty_server() -> HttpServer {
+        let storage = setup_storage(None).unwrap();
+        create_server(&storage)
+    }
+
+    fn setup_storage(root: Option<Location>) -> anyhow::Result<Arc<Mutex<Storage>>> {
+        Ok(Arc::new(Mutex::new(Storage::new(Config {
+            database: Database::InMemory,
+            library_source: root
+                .map(|root| LibrarySource {
+                    roots: vec![root.into()],
+                    follow_symlinks: false,
+                    ignored_dirs: vec![],
+                    ..Default::default()
+                })
+                .unwrap_or_default(),
+        })?)))
+    }
+
+    #[test]
+    fn test_http_recent_tracks() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http("GET", "/recent?days=1", vec![], vec![]);
+        let body: Vec<TrackListResponse> = parse_json_response(server.handle_request(&request))?;
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0].track_id, id);
+
+        let request = Request::fake_http("GET", "/recent?days=week", vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 400);
+        Ok(())
+    }
+
+    // --------------------------------------------------
+    // ✅ SUCCESS
+    // --------------------------------------------------
+
+    #[test]
+    fn test_http_get_track_success() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"x")?;
+
+        let (server, files) = create_server_with_tracks(dir.path());
+
+        let (id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http("GET", format!("/tracks/{}", id), vec![], vec![]);
+
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+
+        let body: TrackResponse = parse_json_response(response)?;
+
+        assert_eq!(body.track_id, id);
+        assert_eq!(body.location, Location::from_path(file_path));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_healthz() {
+        let server = create_empty_server();
+
+        let request = Request::fake_http("GET", "/healthz", vec![], vec![]);
+        let response = server.handle_request(&request);
+
+        assert_eq!(response.status_code, 200);
+        let body: serde_json::Value = serde_json::from_str(&parse_text_response(response)).unwrap();
+        assert_eq!(body["instance"], server.instance_id());
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let mut server = create_empty_server();
+        server.config.basic_auth = Some(BasicAuth {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        });
+        let get = |server: &HttpServer, url: &str, authorization: Option<&str>| {
+            let headers = authorization
+                .map(|value| vec![("Authorization".to_string(), value.to_string())])
+                .unwrap_or_default();
+            server
+                .handle_request(&Request::fake_http("GET", url, headers, vec![]))
+                .status_code
+        };
+
+        assert_eq!(get(&server, "/healthz", None), 401);
+        // base64 of user:wrong
+        assert_eq!(
+            get(&server, "/healthz", Some("Basic dXNlcjp3cm9uZw==")),
+            401
+        );
+        // base64 of user:pass
+        assert_eq!(get(&server, "/healthz", Some("Basic dXNlcjpwYXNz")), 200);
+        // reaches the handler, which rejects the missing hash
+        assert_eq!(get(&server, "/play", None), 400);
+
+        server.config.public_card_routes = false;
+        assert_eq!(get(&server, "/play", None), 401);
+    }
+
+    #[test]
+    fn test_api_token_roles() {
+        let mut server = create_empty_server();
+        let token = |name: &str, role| ApiToken {
+            name: name.to_string(),
+            token: format!("{name}-secret"),
+            role,
+        };
+        server.config.tokens = vec![token("family", Role::Listener), token("me", Role::Admin)];
+        let status = |method: &'static str, url: &str, bearer: Option<&str>| {
+            let headers = bearer
+                .map(|token| vec![("Authorization".to_string(), format!("Bearer {token}"))])
+                .unwrap_or_default();
+            server
+                .handle_request(&Request::fake_http(method, url, headers, vec![]))
+                .status_code
+        };
+
+        assert_eq!(status("GET", "/healthz", None), 401);
+        assert_eq!(status("GET", "/healthz", Some("guessed")), 401);
+        assert_eq!(status("GET", "/healthz", Some("family-secret")), 200);
+        assert_eq!(
+            status("GET", "/healthz?access_token=family-secret", None),
+            200
+        );
+        assert_eq!(status("GET", "/play", None), 400);
+
+        // mutations need an admin, who then gets past authorization
+        assert_eq!(
+            status("DELETE", "/playlists/mix", Some("family-secret")),
+            403
+        );
+        assert_eq!(status("DELETE", "/playlists/mix", Some("me-secret")), 404);
+    }
+
+    #[test]
+    fn test_protected_card_routes_with_tokens_only() {
+        let mut server = create_empty_server();
+        server.config.tokens = vec![ApiToken {
+            name: "family".to_string(),
+            token: "family-secret".to_string(),
+            role: Role::Listener,
+        }];
+        server.config.public_card_routes = false;
+        let status = |url: &str, bearer: Option<&str>| {
+            let headers = bearer
+                .map(|token| vec![("Authorization".to_string(), format!("Bearer {token}"))])
+                .unwrap_or_default();
+            server
+                .handle_request(&Request::fake_http("GET", url, headers, vec![]))
+                .status_code
+        };
+
+        for url in ["/play", "/play-list", "/random", "/s/abcde"] {
+            assert_eq!(status(url, None), 401, "{url}");
+        }
+        // reaches the handler, which rejects the missing hash
+        assert_eq!(status("/play", Some("family-secret")), 400);
+    }
+
+    #[test]
+    fn test_reload_access_settings() {
+        let admin = |name: &str| ApiToken {
+            name: name.to_string(),
+            token: format!("{name}-secret"),
+            role: Role::Admin,
+        };
+        let mut server = create_empty_server().with_reload(move || {
+            let mut config = create_empty_server().config;
+            config.tokens = vec![admin("me")];
+            Ok(ReloadedConfig {
+                http: config,
+                log_level: log::LevelFilter::Debug,
+                ignored_dirs: Vec::new(),
+            })
+        });
+        let status =
+            |server: &HttpServer, method: &'static str, url: &str, bearer: Option<&str>| {
+                let headers = bearer
+                    .map(|token| vec![("Authorization".to_string(), format!("Bearer {token}"))])
+                    .unwrap_or_default();
+                server
+                    .handle_request(&Request::fake_http(method, url, headers, vec![]))
+                    .status_code
+            };
+
+        // without credentials nobody may reload
+        assert_eq!(status(&server, "POST", "/admin/reload", None), 403);
+        assert_eq!(status(&server, "GET", "/healthz", None), 200);
+
+        server.config.tokens = vec![
+            admin("old"),
+            ApiToken {
+                name: "family".to_string(),
+                token: "family-secret".to_string(),
+                role: Role::Listener,
+            },
+        ];
+        assert_eq!(status(&server, "POST", "/admin/reload", None), 401);
+        assert_eq!(
+            status(&server, "POST", "/admin/reload", Some("family-secret")),
+            403
+        );
+        assert_eq!(
+            status(&server, "POST", "/admin/reload", Some("old-secret")),
+            200
+        );
+        assert_eq!(log::max_level(), log::LevelFilter::Debug);
+        assert_eq!(status(&server, "GET", "/healthz", Some("old-secret")), 401);
+        assert_eq!(status(&server, "GET", "/healthz", Some("me-secret")), 200);
+        assert_eq!(
+            status(&server, "POST", "/admin/reload", Some("me-secret")),
+            200
+        );
+
+        let without_reload = create_empty_server();
+        let request = Request::fake_http("POST", "/admin/reload", vec![], vec![]);
+        assert_eq!(without_reload.handle_request(&request).status_code, 404);
+    }
+
+    #[test]
+    fn test_reload_without_credentials_by_signal() {
+        let server = create_empty_server().with_reload(|| {
+            Ok(ReloadedConfig {
+                http: create_empty_server().config,
+                log_level: log::max_level(),
+                ignored_dirs: vec![PathBuf::from("/music/samples")],
+            })
+        });
+        let request = Request::fake_http("POST", "/admin/reload", vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 403);
+
+        // what SIGHUP runs
+        let response = server.reload_config().unwrap();
+        assert_eq!(response.ignored_dirs, 1);
+        assert!(!response.basic_auth);
+        assert!(create_empty_server().reload_config().is_err());
+    }
+
+    #[test]
+    fn test_reload_ignored_dirs() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let skipped = dir.path().join("samples");
+        fs::create_dir(&skipped)?;
+        let ignored = skipped.clone();
+        let admin = || ApiToken {
+            name: "me".to_string(),
+            token: "me-secret".to_string(),
+            role: Role::Admin,
+        };
+        let (mut server, _) = create_server_with_tracks(dir.path());
+        server.config.tokens = vec![admin()];
+        let server = server.with_reload(move || {
+            let mut config = create_empty_server().config;
+            config.tokens = vec![admin()];
+            Ok(ReloadedConfig {
+                http: config,
+                log_level: log::max_level(),
+                ignored_dirs: vec![ignored.clone()],
+            })
+        });
+        let request = Request::fake_http(
+            "POST",
+            "/admin/reload",
+            vec![("Authorization".to_string(), "Bearer me-secret".to_string())],
+            vec![],
+        );
+        let response: ReloadResponse = parse_json_response(server.handle_request(&request))?;
+        assert_eq!(response.ignored_dirs, 1);
+
+        fs::write(skipped.join("kick.mp3"), b"kick")?;
+        fs::write(dir.path().join("new.mp3"), b"new")?;
+        let added = server
+            .storage()
+            .lock()
+            .unwrap()
+            .update_db_with_new_files()?;
+        let paths: Vec<_> = added
+            .values()
+            .flatten()
+            .map(|f| f.file.loc.clone())
+            .collect();
+        assert_eq!(paths, vec![Location::from_path(dir.path().join("new.mp3"))]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_rules() {
+        let mut server = create_empty_server();
+        let lan: Subnet = "192.168.1.0/24".parse().unwrap();
+        server.config.ip_rules = vec![
+            IpRule {
+                path: "/play".to_string(),
+                methods: vec![],
+                allow: vec![],
+                deny: vec![],
+            },
+            IpRule {
+                path: "/".to_string(),
+                methods: vec!["POST".to_string(), "DELETE".to_string()],
+                allow: vec![lan],
+                deny: vec!["192.168.1.13".parse().unwrap()],
+            },
+        ];
+        let status = |from: &str, method: &'static str, url: &str| {
+            let request =
+                Request::fake_http_from(from.parse().unwrap(), method, url, vec![], vec![]);
+            server.handle_request(&request).status_code
+        };
+
+        assert_eq!(status("203.0.113.9:5000", "GET", "/play"), 400);
+        assert_eq!(status("203.0.113.9:5000", "GET", "/healthz"), 200);
+        assert_eq!(status("203.0.113.9:5000", "DELETE", "/playlists/mix"), 403);
+        assert_eq!(status("192.168.1.13:5000", "DELETE", "/playlists/mix"), 403);
+        assert_eq!(status("192.168.1.20:5000", "DELETE", "/playlists/mix"), 404);
+        // IPv4 clients of a dual-stack socket
+        assert_eq!(
+            status("[::ffff:192.168.1.20]:5000", "DELETE", "/playlists/mix"),
+            404
+        );
+    }
+
+    #[test]
+    fn test_api_v1_routes() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+
+        let tracks: Vec<TrackListResponse> = parse_json_response(get("/api/v1/tracks"))?;
+        let aliased: Vec<TrackListResponse> = parse_json_response(get("/tracks"))?;
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].track_id, aliased[0].track_id);
+        assert_eq!(get(&format!("/api/v1/tracks/{id}/stream")).status_code, 200);
+        assert_eq!(get("/api/v1/healthz").status_code, 200);
+        let request = Request::fake_http(
+            "POST",
+            format!("/api/v1/tracks/{id}/favourite"),
+            vec![],
+            vec![],
+        );
+        assert_eq!(server.handle_request(&request).status_code, 200);
+
+        // pages and cards keep their unversioned addresses only
+        for url in [
+            format!("/api/v1/play?h={id}"),
+            "/api/v1/scan_qr".to_string(),
+            "/api/v1/sw.js".to_string(),
+            "/api/v1".to_string(),
+            "/api/v1tracks".to_string(),
+        ] {
+            assert_eq!(get(&url).status_code, 404, "{url}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracks_batch() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<TrackId> = files.into_keys().collect();
+        ids.sort();
+        let batch = |body: &str| {
+            let request = Request::fake_http(
+                "POST",
+                "/api/v1/tracks/batch",
+                vec![("Content-Type".to_string(), "application/json".to_string())],
+                body.as_bytes().to_vec(),
+            );
+            server.handle_request(&request)
+        };
+
+        let body = format!("[\"{}\", \"missing-card\", \"{}\"]", ids[1], ids[0]);
+        let tracks: Vec<BatchTrackResponse> = parse_json_response(batch(&body))?;
+        let found: Vec<_> = tracks
+            .iter()
+            .map(|track| (track.track_id, track.available))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(Some(ids[1]), true), (None, false), (Some(ids[0]), true)]
+        );
+        assert_eq!(tracks[1].id, "missing-card");
+        assert!(tracks[0].location.is_some());
+
+        assert_eq!(batch("[]").status_code, 200);
+        assert_eq!(batch("{\"ids\": []}").status_code, 400);
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_list_tracks() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"x")?;
+
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http("GET", "/tracks", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+
+        let body: Vec<TrackListResponse> = parse_json_response(response)?;
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0].track_id, id);
+        assert_eq!(body[0].locations, vec![Location::from_path(file_path)]);
+        assert!(body[0].added.is_some());
+        let file = &body[0].files[0];
+        assert_eq!(file.size, 1);
+        assert_eq!(file.format.as_deref(), Some("mp3"));
+        assert!(file.modified.is_some());
+
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+        let flac: Vec<TrackListResponse> = parse_json_response(get("/tracks?format=flac"))?;
+        assert!(flac.is_empty());
+        let mp3: Vec<TrackListResponse> = parse_json_response(get("/tracks?format=mp3&artist="))?;
+        assert_eq!(mp3.len(), 1);
+        assert_eq!(get("/tracks?year_from=nineties").status_code, 400);
+        let by_name: Vec<TrackListResponse> = parse_json_response(get("/tracks?sort=name"))?;
+        assert_eq!(by_name.len(), 1);
+        assert_eq!(get("/tracks?sort=title").status_code, 400);
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_search() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("Café.mp3");
+        fs::write(&file_path, b"x")?;
+        fs::write(dir.path().join("other.flac"), b"y")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let id = files
+            .into_iter()
+            .find(|(_, files)| {
+                files
+                    .iter()
+                    .any(|f| f.file.loc == Location::from_path(&file_path))
+            })
+            .unwrap()
+            .0;
+
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+        let found: Vec<SearchResultResponse> =
+            parse_json_response(get("/search?q=cafe%20-format%3Aflac"))?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].track_id, id);
+        assert_eq!(found[0].locations, vec![Location::from_path(file_path)]);
+        let versioned: Vec<SearchResultResponse> =
+            parse_json_response(get("/api/v1/search?q=cafe"))?;
+        assert_eq!(versioned.len(), 1);
+        assert_eq!(versioned[0].track_id, id);
+        let all: Vec<SearchResultResponse> = parse_json_response(get("/search"))?;
+        assert_eq!(all.len(), 2);
+        assert_eq!(get("/search?q=tag%3Aparty").status_code, 400);
+        Ok(())
+    }
+
+    // --------------------------------------------------
+    // ❌ TRACK NOT IN DB
+    // --------------------------------------------------
+
+    #[test]
+    fn test_http_get_track_not_found() -> anyhow::Result<()> {
+        let storage = setup_storage(None)?;
+
+        let track_id = "3";
+
+        let request = Request::fake_http("GET", format!("/tracks/{}", track_id), vec![], vec![]);
+
+        let response = create_server(&storage).handle_request(&request);
+
+        assert_eq!(response.status_code, 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_get_track_stream_success() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"x")?;
+
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http("GET", format!("/tracks/{}/stream", id), vec![], vec![]);
+
+        let response = server.handle_request(&request);
+
+        assert_eq!(response.status_code, 200);
+
+        // Read the response body bytes to check content
+        let mut body = Vec::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_end(&mut body)?;
+
+        assert_eq!(body, b"x");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_only_missing_files_are_quarantined() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let looping = dir.path().join("loop.mp3");
+        let missing = dir.path().join("missing.mp3");
+        fs::write(&looping, b"a")?;
+        fs::write(&missing, b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+
+        // opening fails with another error than a missing file
+        fs::remove_file(&looping)?;
+        std::os::unix::fs::symlink(&looping, &looping)?;
+        fs::remove_file(&missing)?;
+        for track in files.keys() {
+            let request =
+                Request::fake_http("GET", format!("/tracks/{track}/stream"), vec![], vec![]);
+            assert!(server.handle_request(&request).status_code >= 400);
+        }
+
+        let quarantined = server.storage().lock().unwrap().list_quarantined()?;
+        let paths: Vec<_> = quarantined
+            .iter()
+            .map(|file| file.loc.to_string())
+            .collect();
+        assert_eq!(paths, vec![Location::from_path(&missing).to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_get_track_stream_not_found() -> anyhow::Result<()> {
+        let storage = setup_storage(None)?;
+        let track_id = FileHash::from_bytes(&[0, 1, 3]);
+
+        let request = Request::fake_http(
+            "GET",
+            format!("/tracks/{}/stream", track_id.to_hex()),
+            vec![],
+            vec![],
+        );
+
+        let response = create_server(&storage).handle_request(&request);
+
+        assert_eq!(response.status_code, 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_play_missing_hash() {
+        let server = create_empty_server();
+
+        let request = Request::fake_http("GET", "/play", vec![], vec![]);
+
+        let response = server.handle_request(&request);
+        let status = response.status_code;
+
+        assert_eq!(
+            status,
+            400,
+            "expected 400 for missing hash, got {}. response: {}",
+            status,
+            parse_text_response(response)
+        );
+
+        let body = parse_text_response(response);
+
+        assert!(
+            body.contains("missing media hash"),
+            "expected missing-hash error, got: {}",
+            body
+        );
+    }
+
+    #[test]
+    fn test_stream_headers() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"x").unwrap();
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+
+        let request =
+            Request::fake_http("GET", format!("/tracks/{track_id}/stream"), vec![], vec![]);
+        let response = server
+            .get_track_stream(track_id.to_string(), &request)
+            .expect("streaming should succeed")
+            .response;
+
+        // Check that Accept-Ranges header is present
+        assert_eq!(
+            response
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("Accept-Ranges")),
+            true
+        );
+
+        // Check status code
+        assert!(
+            response.status_code == 200 || response.status_code == 206,
+            "expected 200 or 206, got {}",
+            response.status_code
+        );
+    }
+
+    #[test]
+    fn test_stream_partial_range() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"asdfghjkas").unwrap();
+
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+
+        // Request a partial range
+        let request = Request::fake_http(
+            "GET",
+            format!("/tracks/{track_id}/stream"),
+            vec![("Range".into(), "bytes=2-5".into())],
+            vec![],
+        );
+
+        let response = server
+            .get_track_stream(track_id.to_string(), &request)
+            .expect("partial streaming should succeed")
+            .response;
+
+        assert_eq!(response.status_code, 206);
+
+        let content_range = response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Range"))
+            .expect("Content-Range header should be present")
+            .1
+            .to_string();
+
+        assert_eq!(content_range, "bytes 2-5/10");
+
+        let mut body = Vec::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body, b"dfgh");
+    }
+
+    #[test]
+    fn test_stream_if_range() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"asdfghjkas")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+        let stream = |if_range: Option<&str>| {
+            let mut headers = vec![("Range".to_string(), "bytes=2-5".to_string())];
+            if let Some(tag) = if_range {
+                headers.push(("If-Range".to_string(), tag.to_string()));
+            }
+            let request =
+                Request::fake_http("GET", format!("/tracks/{track_id}/stream"), headers, vec![]);
+            server.handle_request(&request)
+        };
+        let etag = |response: &Response| {
+            response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("ETag"))
+                .map(|(_, v)| v.to_string())
+                .expect("local streams carry an ETag")
+        };
+
+        let first = stream(None);
+        assert_eq!(first.status_code, 206);
+        let tag = etag(&first);
+        assert_eq!(stream(Some(&tag)).status_code, 206);
+
+        // the client resumes a copy of another version of the file
+        let changed = stream(Some("\"0-0\""));
+        assert_eq!(changed.status_code, 200);
+        assert_eq!(changed.data.into_reader_and_size().1, Some(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_streams_are_capped() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"song")?;
+        let (mut server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+        server.streams = StreamCounter::new(StreamLimits {
+            max: Some(2),
+            max_per_ip: Some(1),
+            max_bytes_per_sec: None,
+        });
+        let stream = |from: &str| {
+            let request = Request::fake_http_from(
+                from.parse().unwrap(),
+                "GET",
+                format!("/tracks/{track_id}/stream"),
+                vec![],
+                vec![],
+            );
+            server.handle_request(&request)
+        };
+
+        let first = stream("192.168.1.10:5000");
+        assert_eq!(first.status_code, 200);
+        let refused = stream("192.168.1.10:5001");
+        assert_eq!(refused.status_code, 503);
+        assert!(
+            refused
+                .headers
+                .iter()
+                .any(|(k, v)| k.eq_ignore_ascii_case("Retry-After") && v == "5")
+        );
+        let second = stream("192.168.1.11:5000");
+        assert_eq!(second.status_code, 200);
+        assert_eq!(stream("192.168.1.12:5000").status_code, 503);
+
+        // a slot is given back once the body is sent
+        drop(first);
+        assert_eq!(server.streams.active(), 1);
+        assert_eq!(stream("192.168.1.12:5000").status_code, 200);
+        drop(second);
+        assert_eq!(server.streams.active(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_head_has_headers_without_body() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"asdfghjkas")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+        let header = |response: &Response, name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.to_string())
+        };
+
+        for url in [
+            format!("/tracks/{track_id}/stream"),
+            format!("/play?h={track_id}"),
+        ] {
+            let request = Request::fake_http("HEAD", url.clone(), vec![], vec![]);
+            let response = server.handle_request(&request);
+            assert_eq!(response.status_code, 200, "{url}");
+            assert_eq!(
+                header(&response, "Content-Type").as_deref(),
+                Some("audio/mpeg")
+            );
+            assert_eq!(header(&response, "Accept-Ranges").as_deref(), Some("bytes"));
+            let (mut body, size) = response.data.into_reader_and_size();
+            assert_eq!(size, Some(10), "{url}");
+            let mut read = Vec::new();
+            body.read_to_end(&mut read)?;
+            assert!(read.is_empty(), "{url}");
+        }
+
+        let request = Request::fake_http(
+            "HEAD",
+            format!("/tracks/{track_id}/stream"),
+            vec![("Range".into(), "bytes=2-5".into())],
+            vec![],
+        );
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.data.into_reader_and_size().1, Some(4));
+
+        // probing is not scanning
+        let scans = server.storage.lock().unwrap().list_card_scans(None)?;
+        assert!(scans.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_invalid_range_returns_416() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("song.mp3");
+
+        fs::write(&file_path, b"x").unwrap();
+
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track_id, _) = files.into_iter().next().unwrap();
+
+        // Request a range beyond file size
+        let request = Request::fake_http(
+            "GET",
+            "/tracks/{track_id}/stream",
+            vec![("Range".into(), "bytes=20-30".into())],
+            vec![],
+        );
+
+        let response = server.get_track_stream(track_id.to_string(), &request);
+
+        assert!(matches!(response, Err(ApiError::InvalidRange(1))));
+
+        let request = Request::fake_http(
+            "GET",
+            format!("/tracks/{track_id}/stream"),
+            vec![("Range".into(), "bytes=20-30".into())],
+            vec![],
+        );
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 416);
+        assert!(
+            response
+                .headers
+                .iter()
+                .any(|(k, v)| k.eq_ignore_ascii_case("Content-Range") && v == "bytes */1")
+        );
+    }
+
+    #[test]
+    fn test_parse_http_range() {
+        let parse = |range| HttpServer::parse_http_range(range, 10);
+        assert_eq!(parse("bytes=2-5").unwrap(), Some((2, 5)));
+        assert_eq!(parse("bytes=2-").unwrap(), Some((2, 9)));
+        assert_eq!(parse("bytes=-3").unwrap(), Some((7, 9)));
+        assert_eq!(parse("bytes=-30").unwrap(), Some((0, 9)));
+        // ends past the file are cut to it
+        assert_eq!(parse("bytes=8-30").unwrap(), Some((8, 9)));
+        assert_eq!(parse("items=2-5").unwrap(), None);
+        assert_eq!(parse("bytes=0-1,4-5").unwrap(), None);
+
+        for range in [
+            "bytes=x-5",
+            "bytes=2-y",
+            "bytes=5-2",
+            "bytes=-",
+            "bytes=-0",
+            "bytes=10-",
+        ] {
+            assert!(
+                matches!(parse(range), Err(ApiError::InvalidRange(10))),
+                "{range}"
+            );
+        }
+        assert!(HttpServer::parse_http_range("bytes=0-", 0).is_err());
+    }
+
+    #[test]
+    fn test_http_get_track_with_metadata() -> anyhow::Result<()> {
+        use std::fs;
+        use tempfile::tempdir;
+
+        // ---------- Setup temp directory and music file ----------
+        let dir = tempdir()?;
+        let file_path = dir.path().join("song.mp3");
+        fs::write(&file_path, b"x")?;
+
+        // ---------- Setup server with track and metadata ----------
+        // `create_server_with_tracks` should accept metadata if we extend it
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+
+        server.storage.lock().unwrap().update_track_metadata(
+            id,
+            MetadataUpdate {
+                title: Some("Test Song".to_string()),
+                artist: Some("Test Artist".to_string()),
+                year: Some(2026),
+                label: Some("Test Label".to_string()),
+                artwork: Some(ArtworkRef("cover.jpg".to_string())),
+            },
+            false,
+        )?;
+        server
+            .storage
+            .lock()
+            .unwrap()
+            .set_track_notes(id, Some("use for first dance"))?;
+
+        // ---------- Make the HTTP request ----------
+        let request = Request::fake_http("GET", format!("/tracks/{}", id), vec![], vec![]);
+        let response = server.handle_request(&request);
+
+        assert_eq!(response.status_code, 200);
+
+        // ---------- Parse JSON response ----------
+        let body: TrackResponse = parse_json_response(response)?;
+
+        // ---------- Assertions ----------
+        assert_eq!(body.track_id, id);
+        assert_eq!(body.location, Location::from_path(file_path));
+
+        // Metadata assertions
+        let metadata = body.metadata.expect("Metadata should be present");
+        assert_eq!(metadata.title, "Test Song");
+        assert_eq!(metadata.artist, "Test Artist");
+        assert_eq!(metadata.year, Some(2026));
+        assert_eq!(metadata.label.as_deref(), Some("Test Label"));
+        assert_eq!(
+            metadata.artwork.as_ref().map(|a| a.as_str()),
+            Some("cover.jpg")
+        );
+        assert_eq!(body.notes.as_deref(), Some("use for first dance"));
+        let artists: Vec<_> = body
+            .artists
+            .iter()
+            .map(|artist| (artist.name.as_str(), artist.role.as_str()))
+            .collect();
+        assert_eq!(artists, [("Test Artist", "main")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_artwork_in_accepted_format() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let covers = tempdir()?;
+        let cover = covers.path().join("1.jpg");
+        fs::write(&cover, b"jpeg")?;
+        fs::write(covers.path().join("1.webp"), b"webp")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+        server.storage.lock().unwrap().update_track_metadata(
+            id,
+            MetadataUpdate {
+                title: Some("Song".to_string()),
+                artist: Some("Artist".to_string()),
+                year: None,
+                label: None,
+                artwork: Some(ArtworkRef(cover.to_string_lossy().into_owned())),
+            },
+            false,
+        )?;
+
+        let get = |accept: &str| {
+            let headers = vec![("Accept".to_string(), accept.to_string())];
+            let request =
+                Request::fake_http("GET", format!("/tracks/{id}/artwork"), headers, vec![]);
+            let response = server.handle_request(&request);
+            assert_eq!(response.status_code, 200);
+            let content_type = response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+                .map(|(_, v)| v.to_string());
+            let mut body = String::new();
+            response
+                .data
+                .into_reader_and_size()
+                .0
+                .read_to_string(&mut body)
+                .unwrap();
+            (content_type, body)
+        };
+        // there is no AVIF copy, the WebP one is next
+        assert_eq!(
+            get("image/avif,image/webp,*/*;q=0.8"),
+            (Some("image/webp".to_string()), "webp".to_string())
+        );
+        assert_eq!(
+            get("image/*,*/*;q=0.8"),
+            (Some("image/jpeg".to_string()), "jpeg".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_get_lyrics() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http("GET", format!("/tracks/{id}/lyrics"), vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 404);
+
+        server.storage.lock().unwrap().set_lyrics(
+            id,
+            &TrackLyrics {
+                plain: Some("one\ntwo".to_string()),
+                synced: Some("[00:01.00]one\n[00:02.50]two".to_string()),
+            },
+        )?;
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+
+        let body: LyricsResponse = parse_json_response(response)?;
+        assert_eq!(body.track_id, id);
+        assert_eq!(body.plain.as_deref(), Some("one\ntwo"));
+        let lines: Vec<_> = body
+            .lines
+            .iter()
+            .map(|l| (l.time, l.text.as_str()))
+            .collect();
+        assert_eq!(lines, vec![(1.0, "one"), (2.5, "two")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_guest_pages_are_translated() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (mut server, _) = create_server_with_tracks(dir.path());
+        let russian = vec![(
+            "Accept-Language".to_string(),
+            "fr;q=0.9, ru;q=0.8".to_string(),
+        )];
+
+        let request = Request::fake_http("GET", "/scan_qr", russian.clone(), vec![]);
+        let page = parse_text_response(server.handle_request(&request));
+        assert!(page.contains("Сканер QR"));
+        assert!(!page.contains("/*TEXTS*/"));
+
+        let request = Request::fake_http("GET", "/play?h=12345", russian, vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 404);
+        assert_eq!(
+            parse_text_response(response),
+            Lang::Ru.texts().track_not_found
+        );
+
+        // the configured language is used when the browser accepts no supported one
+        server.config.language = Some("de".to_string());
+        let request = Request::fake_http("GET", "/play", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 400);
+        assert_eq!(parse_text_response(response), Lang::De.texts().missing_hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scanner_page_is_themed() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (mut server, _) = create_server_with_tracks(dir.path());
+        let theme_dir = tempdir()?;
+        fs::write(theme_dir.path().join("style.css"), "body { color: red; }")?;
+        server.config.theme = ThemeConfig {
+            title: Some("Sasha's Deck </script>".to_string()),
+            logo: None,
+            accent_color: Some("#e91e63".to_string()),
+            dir: Some(theme_dir.path().to_path_buf()),
+        };
+
+        let request = Request::fake_http("GET", "/scan_qr", vec![], vec![]);
+        let page = parse_text_response(server.handle_request(&request));
+        assert!(page.contains("Sasha's Deck \\u003c/script>"));
+        assert!(page.contains("#e91e63"));
+        assert!(page.contains("/theme/style.css"));
+
+        let request = Request::fake_http("GET", "/theme/style.css", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(parse_text_response(response), "body { color: red; }");
+
+        let request = Request::fake_http("GET", "/theme/logo", vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 404);
+
+        // pages of the theme directory replace the built-in ones
+        fs::write(
+            theme_dir.path().join("scan_qr.html"),
+            "<h1>/*TEXTS*/null</h1>",
+        )?;
+        let page = parse_text_response(server.handle_request(&Request::fake_http(
+            "GET",
+            "/scan_qr",
+            vec![],
+            vec![],
+        )));
+        assert!(page.starts_with("<h1>{\"lang\":\"en\""));
+
+        // placeholders in the inserted data are left as they are
+        server.config.theme.title = Some("/*QUEUE*/false".to_string());
+        fs::remove_file(theme_dir.path().join("scan_qr.html"))?;
+        let page = parse_text_response(server.handle_request(&Request::fake_http(
+            "GET",
+            "/scan_qr",
+            vec![],
+            vec![],
+        )));
+        assert!(page.contains("\"/*QUEUE*/false\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pwa_files() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (mut server, _) = create_server_with_tracks(dir.path());
+        server.config.theme.title = Some("Sasha's Deck".to_string());
+
+        let request = Request::fake_http("GET", "/manifest.webmanifest", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+        let manifest: serde_json::Value = parse_json_response(response)?;
+        assert_eq!(manifest["name"], "Sasha's Deck");
+        assert_eq!(manifest["start_url"], "/scan_qr");
+
+        for url in ["/sw.js", "/icon.svg"] {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            assert_eq!(server.handle_request(&request).status_code, 200, "{url}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_dir() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (mut server, _) = create_server_with_tracks(dir.path());
+        let get = |server: &HttpServer, url: &str| {
+            server.handle_request(&Request::fake_http("GET", url, vec![], vec![]))
+        };
+        assert_eq!(get(&server, "/static/player.js").status_code, 404);
+
+        let static_dir = tempdir()?;
+        fs::create_dir(static_dir.path().join("fonts"))?;
+        fs::write(static_dir.path().join("player.js"), "play();")?;
+        fs::write(static_dir.path().join("fonts").join("deck.woff2"), "font")?;
+        fs::write(dir.path().join("secret.txt"), "secret")?;
+        server.config.static_dir = Some(static_dir.path().to_path_buf());
+
+        let response = get(&server, "/static/player.js");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(parse_text_response(response), "play();");
+        let response = get(&server, "/static/fonts/deck.woff2");
+        assert_eq!(parse_text_response(response), "font");
+        assert_eq!(get(&server, "/static/missing.css").status_code, 404);
+        assert_eq!(get(&server, "/staticplayer.js").status_code, 404);
+        assert_eq!(get(&server, "/static/fonts").status_code, 404);
+        let outside = format!(
+            "/static/../{}/secret.txt",
+            dir.path().file_name().unwrap().to_string_lossy()
+        );
+        assert_eq!(get(&server, &outside).status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cast_media_and_cors() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (id, _) = files.into_iter().next().unwrap();
+        let host = vec![("Host".to_string(), "deck.local:8080".to_string())];
+
+        let request = Request::fake_http("GET", format!("/tracks/{id}/cast"), host, vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+        assert!(
+            response
+                .headers
+                .iter()
+                .any(|(k, v)| k == "Access-Control-Allow-Origin" && v == "*")
+        );
+        let media: CastMedia = parse_json_response(response)?;
+        assert_eq!(
+            media.content_id,
+            format!("http://deck.local:8080/tracks/{id}/stream")
+        );
+        assert_eq!(media.content_type, "audio/mpeg");
+        assert_eq!(media.metadata.title, format!("Track {id}"));
+
+        let request = Request::fake_http("OPTIONS", format!("/tracks/{id}/stream"), vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 204);
+        assert!(
+            response
+                .headers
+                .iter()
+                .any(|(k, v)| k == "Access-Control-Allow-Headers" && v.contains("Range"))
+        );
+
+        // pages are not opened to other origins
+        let request = Request::fake_http("GET", "/scan_qr", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert!(
+            !response
+                .headers
+                .iter()
+                .any(|(k, _)| k == "Access-Control-Allow-Origin")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_playlist_page() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"x")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track, _) = files.into_iter().next().unwrap();
+        let playlist = {
+            let mut storage = server.storage.lock().unwrap();
+            let playlist = storage.create_playlist("party")?;
+            storage.set_playlist_tracks(playlist.id, &[track])?;
+            storage.set_playlist_token(playlist.id, Some("secret"))?;
+            playlist
+        };
+
+        let url = format!("/playlists/{}?t=secret", playlist.id);
+        let request = Request::fake_http("GET", url, vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+        let page = parse_text_response(response);
+        assert!(page.contains(&format!(
+            r#"{{"name":"party","tracks":[{{"track_id":{track}"#
+        )));
+
+        for url in [
+            format!("/playlists/{}", playlist.id),
+            format!("/playlists/{}?t=wrong", playlist.id),
+            format!("/playlists/{}?t=secret", playlist.id + 1),
+            "/playlists/party".to_string(),
+        ] {
+            let request = Request::fake_http("GET", url.clone(), vec![], vec![]);
+            assert_eq!(server.handle_request(&request).status_code, 404, "{url}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_track() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.flac"), b"b")?;
+        let (mut server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let playlist = {
+            let mut storage = server.storage.lock().unwrap();
+            let playlist = storage.create_playlist("surprise")?;
+            storage.set_playlist_tracks(playlist.id, &[ids[1]])?;
+            playlist
+        };
+        server.config.basic_auth = Some(BasicAuth {
+            user: "host".to_string(),
+            password: "secret".to_string(),
+        });
+        let random = |query: &str| {
+            let request = Request::fake_http("GET", format!("/random{query}"), vec![], vec![]);
+            server.handle_request(&request)
+        };
+        let location = |response: Response| {
+            assert_eq!(response.status_code, 302);
+            response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("Location"))
+                .map(|(_, v)| v.to_string())
+                .unwrap()
+        };
+
+        let target = location(random(""));
+        assert!(ids.iter().any(|id| target == format!("/play?h={id}")));
+        assert_eq!(
+            location(random(&format!("?playlist={}", playlist.id))),
+            format!("/play?h={}", ids[1])
+        );
+        let flac = location(random("?format=flac"));
+        let flac_track = ids.iter().find(|id| flac == format!("/play?h={id}"));
+        assert!(flac_track.is_some());
+        assert_eq!(random("?format=wav").status_code, 404);
+        assert_eq!(random("?playlist=999").status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_track() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("album"))?;
+        fs::create_dir_all(dir.path().join("other"))?;
+        fs::write(dir.path().join("album/01.mp3"), b"first")?;
+        fs::write(dir.path().join("album/02.mp3"), b"second")?;
+        fs::write(dir.path().join("other/x.mp3"), b"other")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let track_of = |name: &str| {
+            *files
+                .iter()
+                .find(|(_, files)| files.iter().any(|f| f.file.loc.to_string().ends_with(name)))
+                .unwrap()
+                .0
+        };
+        let (first, second, other) = (track_of("01.mp3"), track_of("02.mp3"), track_of("x.mp3"));
+        let playlist = {
+            let mut storage = server.storage.lock().unwrap();
+            let playlist = storage.create_playlist("mix")?;
+            storage.set_playlist_tracks(playlist.id, &[other, first])?;
+            playlist
+        };
+        let next = |track: TrackId, query: &str| {
+            let url = format!("/api/v1/tracks/{track}/next{query}");
+            server.handle_request(&Request::fake_http("GET", url, vec![], vec![]))
+        };
+
+        let response: NextTrackResponse = parse_json_response(next(first, ""))?;
+        assert_eq!(response.track_id, second);
+        assert_eq!(
+            response.stream_url,
+            format!("/api/v1/tracks/{second}/stream")
+        );
+        // the album ends
+        assert_eq!(next(second, "?order=album").status_code, 404);
+
+        let response: NextTrackResponse = parse_json_response(next(first, "?order=shuffle"))?;
+        assert!(response.track_id == second || response.track_id == other);
+
+        let in_playlist = format!("?order=playlist:{}", playlist.id);
+        let response: NextTrackResponse = parse_json_response(next(other, &in_playlist))?;
+        assert_eq!(response.track_id, first);
+        assert_eq!(next(first, &in_playlist).status_code, 404);
+        assert_eq!(next(first, "?order=playlist:999").status_code, 404);
+        assert_eq!(next(first, "?order=backwards").status_code, 400);
+        Ok(())
+    }
+
+    #[test]
+    fn test_artists() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.flac"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        for (track, artist) in [(ids[0], "Burial"), (ids[1], "burial")] {
+            server.storage.lock().unwrap().update_track_metadata(
+                track,
+                MetadataUpdate {
+                    title: Some("Title".to_string()),
+                    artist: Some(artist.to_string()),
+                    year: None,
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+
+        let artists: Vec<ArtistResponse> = parse_json_response(get("/api/v1/artists"))?;
+        assert_eq!(artists.len(), 1);
+        assert_eq!(artists[0].name, "Burial");
+        assert_eq!(artists[0].track_count, 2);
+
+        let tracks: Vec<TrackListResponse> = parse_json_response(get("/artists/BURIAL/tracks"))?;
+        let tracks: Vec<_> = tracks.iter().map(|track| track.track_id).collect();
+        assert_eq!(tracks, ids);
+        let flac: Vec<TrackListResponse> =
+            parse_json_response(get("/artists/burial/tracks?format=flac"))?;
+        assert_eq!(flac.len(), 1);
+        assert_eq!(get("/artists/Nobody/tracks").status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_years() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        fs::write(dir.path().join("c.mp3"), b"c")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        for (track, year) in [(ids[0], 1994), (ids[1], 1997), (ids[2], 2001)] {
+            server.storage.lock().unwrap().update_track_metadata(
+                track,
+                MetadataUpdate {
+                    title: Some("Title".to_string()),
+                    artist: Some("Artist".to_string()),
+                    year: Some(year),
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+
+        let years: YearsResponse = parse_json_response(get("/api/v1/years"))?;
+        let counts: Vec<_> = years
+            .years
+            .iter()
+            .map(|y| (y.year, y.track_count))
+            .collect();
+        assert_eq!(counts, [(1994, 1), (1997, 1), (2001, 1)]);
+        let decades: Vec<_> = years
+            .decades
+            .iter()
+            .map(|d| (d.decade, d.track_count))
+            .collect();
+        assert_eq!(decades, [(1990, 2), (2000, 1)]);
+
+        let tracks: Vec<TrackListResponse> = parse_json_response(get("/years/1990s/tracks"))?;
+        let tracks: Vec<_> = tracks.iter().map(|track| track.track_id).collect();
+        assert_eq!(tracks, [ids[0], ids[1]]);
+        let tracks: Vec<TrackListResponse> = parse_json_response(get("/years/2001/tracks"))?;
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(get("/years/1980s/tracks").status_code, 404);
+        assert_eq!(get("/years/90s-ish/tracks").status_code, 400);
+        Ok(())
+    }
+
+    #[test]
+    fn test_albums() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("Live"))?;
+        fs::create_dir_all(dir.path().join("Demos"))?;
+        fs::write(dir.path().join("Live/02.mp3"), b"second")?;
+        fs::write(dir.path().join("Live/01.mp3"), b"first")?;
+        fs::write(dir.path().join("Demos/a.mp3"), b"demo")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let track_of = |name: &str| {
+            *files
+                .iter()
+                .find(|(_, files)| files.iter().any(|f| f.file.loc.to_string().ends_with(name)))
+                .unwrap()
+                .0
+        };
+        let (first, second) = (track_of("01.mp3"), track_of("02.mp3"));
+        for (track, title, year) in [(first, "Intro", 2019), (second, "Outro", 2020)] {
+            server.storage.lock().unwrap().update_track_metadata(
+                track,
+                MetadataUpdate {
+                    title: Some(title.to_string()),
+                    artist: Some("Band".to_string()),
+                    year: Some(year),
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
+        let get =
+            |url: &str| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+
+        let albums: Vec<AlbumInfoResponse> = parse_json_response(get("/api/v1/albums"))?;
+        let names: Vec<_> = albums.iter().map(|album| album.name.as_str()).collect();
+        assert_eq!(names, ["Demos", "Live"]);
+        assert_eq!(albums[0].artist, None);
+        assert_eq!(albums[1].artist.as_deref(), Some("Band"));
+        assert_eq!(albums[1].year, Some(2019));
+        assert_eq!(albums[1].track_count, 2);
+
+        let album: AlbumResponse = parse_json_response(get(&format!("/albums/{}", albums[1].id)))?;
+        let tracks: Vec<_> = album.tracks.iter().map(|track| track.track_id).collect();
+        assert_eq!(tracks, [first, second]);
+        assert_eq!(album.tracks[0].title.as_deref(), Some("Intro"));
+        assert_eq!(get("/albums/999").status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_links() -> anyhow::Result<()> {
+        let mut server = create_empty_server();
+        let code = server.storage.lock().unwrap().short_link("/play?h=12")?;
+        server.config.basic_auth = Some(BasicAuth {
+            user: "host".to_string(),
+            password: "secret".to_string(),
+        });
+        let get =
+            |url: String| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+
+        let response = get(format!("/s/{code}"));
+        assert_eq!(response.status_code, 302);
+        let location = response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Location"))
+            .map(|(_, v)| v.to_string());
+        assert_eq!(location.as_deref(), Some("/play?h=12"));
+        assert_eq!(get("/s/0000o".to_string()).status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_play_list() -> anyhow::Result<()> {
+        let mut server = create_empty_server();
+        let (open, shared) = {
+            let mut storage = server.storage.lock().unwrap();
+            let open = storage.create_playlist("open")?;
+            let shared = storage.create_playlist("shared")?;
+            storage.set_playlist_token(shared.id, Some("abc123"))?;
+            (open, shared)
+        };
+        server.config.basic_auth = Some(BasicAuth {
+            user: "host".to_string(),
+            password: "secret".to_string(),
+        });
+        let get =
+            |url: String| server.handle_request(&Request::fake_http("GET", url, vec![], vec![]));
+        let location = |response: Response| {
+            assert_eq!(response.status_code, 302);
+            response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("Location"))
+                .map(|(_, v)| v.to_string())
+                .unwrap()
+        };
+
+        assert_eq!(
+            location(get(format!("/play-list?p={}", open.id))),
+            format!("/playlists/{}?play=1", open.id)
+        );
+        assert_eq!(
+            location(get(format!("/play-list?p={}&t=abc123", shared.id))),
+            format!("/playlists/{}?play=1&t=abc123", shared.id)
+        );
+        assert_eq!(get(format!("/play-list?p={}", shared.id)).status_code, 404);
+        assert_eq!(get("/play-list?p=999".to_string()).status_code, 404);
+        assert_eq!(get("/play-list".to_string()).status_code, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_library_page() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        server.storage.lock().unwrap().update_track_metadata(
+            ids[1],
+            MetadataUpdate {
+                title: Some("Song".to_string()),
+                artist: Some("Band".to_string()),
+                year: None,
+                label: None,
+                artwork: None,
+            },
+            false,
+        )?;
+
+        let request = Request::fake_http("GET", "/library?q=band", vec![], vec![]);
+        let response = server.handle_request(&request);
+        assert_eq!(response.status_code, 200);
+        let page = parse_text_response(response);
+        assert!(page.contains(&format!(
+            r#"{{"query":"band","tracks":[{{"track_id":{},"title":"Song","artist":"Band"}},{{"track_id":{},"title":null,"artist":null}}],"transliteration":null}}"#,
+            ids[1], ids[0]
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_party_queue() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (mut server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let request = |method: &'static str, url: &str| {
+            server.handle_request(&Request::fake_http(method, url, vec![], vec![]))
+        };
+
+        assert_eq!(request("GET", "/queue/player").status_code, 404);
+        assert_eq!(
+            request("POST", &format!("/queue?h={}", ids[0])).status_code,
+            404
+        );
+
+        server.config.party_queue = true;
+        let request = |method: &'static str, url: &str| {
+            server.handle_request(&Request::fake_http(method, url, vec![], vec![]))
+        };
+        let queued = |server: &HttpServer| -> anyhow::Result<Vec<TrackId>> {
+            let request = Request::fake_http("GET", "/queue", vec![], vec![]);
+            let queue: QueueResponse = parse_json_response(server.handle_request(&request))?;
+            Ok(queue.entries.into_iter().map(|e| e.track_id).collect())
+        };
+
+        let first: QueuedResponse =
+            parse_json_response(request("POST", &format!("/queue?h={}", ids[0])))?;
+        assert_eq!(first.position, 0);
+        let second: QueuedResponse =
+            parse_json_response(request("POST", &format!("/queue?h={}", ids[1])))?;
+        assert_eq!(second.position, 1);
+        assert_eq!(request("POST", "/queue?h=unknown").status_code, 404);
+        assert_eq!(queued(&server)?, vec![ids[0], ids[1]]);
+
+        let moved = format!("/queue/{}/move?position=0", second.id);
+        assert_eq!(request("POST", &moved).status_code, 204);
+        assert_eq!(queued(&server)?, vec![ids[1], ids[0]]);
+
+        assert_eq!(request("POST", "/queue/skip").status_code, 204);
+        let skip_first = format!("/queue/skip?entry={}", first.id);
+        assert_eq!(request("POST", &skip_first).status_code, 204);
+        assert_eq!(request("POST", &skip_first).status_code, 404);
+        assert!(queued(&server)?.is_empty());
+
+        assert_eq!(request("GET", "/queue/player").status_code, 200);
+        let scanner = parse_text_response(request("GET", "/scan_qr"));
+        assert!(scanner.contains("const QUEUE = true;"));
+
+        // listeners may add to the queue, but only the host changes it
+        server.config.tokens = vec![ApiToken {
+            name: "guest".to_string(),
+            token: "guest-secret".to_string(),
+            role: Role::Listener,
+        }];
+        let as_guest = |url: &str| {
+            let auth = vec![(
+                "Authorization".to_string(),
+                "Bearer guest-secret".to_string(),
+            )];
+            server
+                .handle_request(&Request::fake_http("POST", url, auth, vec![]))
+                .status_code
+        };
+        assert_eq!(as_guest(&format!("/queue?h={}", ids[0])), 200);
+        assert_eq!(as_guest("/queue/skip"), 403);
+        Ok(())
     }
 
-    // --------------------------------------------------
-    // ✅ SUCCESS
-    // --------------------------------------------------
-
-    #[test]
-    fn test_http_get_track_success() -> anyhow::Result<()> {
-        let dir = tempdir()?;
-        let file_path = dir.path().join("song.mp3");
-        fs::write(&file_path, b"x")?;
+    /// Records what it was told to play, by file content
+    struct FakeJukebox {
+        events: Arc<Mutex<Vec<String>>>,
+        finished: Arc<AtomicBool>,
+        /// set to make the prepared file take over
+        advanced: Arc<AtomicBool>,
+        paused: bool,
+        position: Duration,
+        volume: f32,
+    }
 
-        let (server, files) = create_server_with_tracks(dir.path());
+    impl Jukebox for FakeJukebox {
+        fn play(&mut self, path: &Path) -> anyhow::Result<()> {
+            let content = fs::read_to_string(path)?;
+            self.events.lock().unwrap().push(format!("play {content}"));
+            self.finished.store(false, Ordering::SeqCst);
+            Ok(())
+        }
 
-        let (id, _) = files.into_iter().next().unwrap();
+        fn stop(&mut self) {
+            self.events.lock().unwrap().push("stop".to_string());
+        }
 
-        let request = Request::fake_http("GET", format!("/tracks/{}", id), vec![], vec![]);
+        fn is_finished(&self) -> bool {
+            self.finished.load(Ordering::SeqCst)
+        }
 
-        let response = server.handle_request(&request);
-        assert_eq!(response.status_code, 200);
+        fn pause(&mut self) {
+            self.paused = true;
+        }
 
-        let body: TrackResponse = parse_json_response(response)?;
+        fn resume(&mut self) {
+            self.paused = false;
+        }
 
-        assert_eq!(body.track_id, id);
-        assert_eq!(body.location, Location::from_path(file_path));
+        fn is_paused(&self) -> bool {
+            self.paused
+        }
 
-        Ok(())
-    }
+        fn seek(&mut self, position: Duration) -> anyhow::Result<()> {
+            if position > Duration::from_secs(60) {
+                anyhow::bail!("past the end");
+            }
+            self.position = position;
+            Ok(())
+        }
 
-    // --------------------------------------------------
-    // ❌ TRACK NOT IN DB
-    // --------------------------------------------------
+        fn position(&self) -> Duration {
+            self.position
+        }
 
-    #[test]
-    fn test_http_get_track_not_found() -> anyhow::Result<()> {
-        let storage = setup_storage(None)?;
+        fn duration(&self) -> Option<Duration> {
+            Some(Duration::from_secs(60))
+        }
 
-        let track_id = "3";
+        fn volume(&self) -> f32 {
+            self.volume
+        }
 
-        let request = Request::fake_http("GET", format!("/tracks/{}", track_id), vec![], vec![]);
+        fn set_volume(&mut self, volume: f32) {
+            self.volume = volume;
+        }
 
-        let response = create_server(&storage).handle_request(&request);
+        fn set_next(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+            let content = match path {
+                Some(path) => fs::read_to_string(path)?,
+                None => "nothing".to_string(),
+            };
+            self.events.lock().unwrap().push(format!("next {content}"));
+            Ok(())
+        }
 
-        assert_eq!(response.status_code, 404);
+        fn take_advanced(&mut self) -> bool {
+            self.advanced.swap(false, Ordering::SeqCst)
+        }
+    }
 
-        Ok(())
+    impl FakeJukebox {
+        fn new(events: &Arc<Mutex<Vec<String>>>, finished: &Arc<AtomicBool>) -> Self {
+            Self {
+                events: Arc::clone(events),
+                finished: Arc::clone(finished),
+                advanced: Arc::default(),
+                paused: false,
+                position: Duration::ZERO,
+                volume: 1.0,
+            }
+        }
     }
 
     #[test]
-    fn test_http_get_track_stream_success() -> anyhow::Result<()> {
+    fn test_jukebox_plays_the_queue() -> anyhow::Result<()> {
         let dir = tempdir()?;
-        let file_path = dir.path().join("song.mp3");
-        fs::write(&file_path, b"x")?;
-
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
         let (server, files) = create_server_with_tracks(dir.path());
-        let (id, _) = files.into_iter().next().unwrap();
-
-        let request = Request::fake_http("GET", format!("/tracks/{}/stream", id), vec![], vec![]);
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let events = Arc::new(Mutex::new(vec![]));
+        let finished = Arc::new(AtomicBool::new(true));
+        let server = server.with_jukebox(FakeJukebox::new(&events, &finished));
+        let request = |method: &'static str, url: &str| {
+            server.handle_request(&Request::fake_http(method, url, vec![], vec![]))
+        };
+        let mut driver = JukeboxDriver::new(Arc::clone(server.zones[0].jukebox.as_ref().unwrap()));
+        let mut step = || driver.step(&server.zones[0].queue, &server.storage);
+        let events = || events.lock().unwrap().clone();
 
-        let response = server.handle_request(&request);
+        // party_queue is off in the config, the jukebox turns it on
+        let player = parse_text_response(request("GET", "/queue/player"));
+        assert!(player.contains("const JUKEBOX = true;"));
+        step();
+        assert!(events().is_empty());
 
-        assert_eq!(response.status_code, 200);
+        for id in &ids {
+            assert_eq!(request("POST", &format!("/queue?h={id}")).status_code, 200);
+        }
 
-        // Read the response body bytes to check content
-        let mut body = Vec::new();
-        response
-            .data
-            .into_reader_and_size()
-            .0
-            .read_to_end(&mut body)?;
+        step();
+        step();
+        let [first, next] = &events()[..] else {
+            panic!("expected the first entry to play and the second to be prepared");
+        };
+        let (first, next) = (first.replace("play ", ""), next.replace("next ", ""));
+        assert_ne!(first, next);
 
-        assert_eq!(body, b"x");
+        // the first entry is removed once it finished, and the next one starts
+        finished.store(true, Ordering::SeqCst);
+        step();
+        step();
+        assert_eq!(events()[2], format!("play {next}"));
+        assert_eq!(server.zones[0].queue.lock().unwrap().entries().len(), 1);
 
+        assert_eq!(request("POST", "/queue/skip").status_code, 204);
+        step();
+        assert_eq!(events().last().map(String::as_str), Some("stop"));
+        step();
+        assert_eq!(events().len(), 4);
         Ok(())
     }
 
     #[test]
-    fn test_http_get_track_stream_not_found() -> anyhow::Result<()> {
-        let storage = setup_storage(None)?;
-        let track_id = FileHash::from_bytes(&[0, 1, 3]);
+    fn test_jukebox_moves_on_to_the_prepared_entry() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let events = Arc::new(Mutex::new(vec![]));
+        let fake = FakeJukebox::new(&events, &Arc::default());
+        let advanced = Arc::clone(&fake.advanced);
+        let server = server.with_jukebox(fake);
+        let mut driver = JukeboxDriver::new(Arc::clone(server.zones[0].jukebox.as_ref().unwrap()));
+        let mut step = || driver.step(&server.zones[0].queue, &server.storage);
+        let events = || events.lock().unwrap().clone();
+        let last = {
+            let mut queue = server.zones[0].queue.lock().unwrap();
+            queue.push(ids[0]);
+            queue.push(ids[1]);
+            queue.push(ids[0]).0
+        };
 
-        let request = Request::fake_http(
-            "GET",
-            format!("/tracks/{}/stream", track_id.to_hex()),
-            vec![],
-            vec![],
+        step();
+        step();
+        let [first, next] = &events()[..] else {
+            panic!("expected the first entry to play and the second to be prepared");
+        };
+        let first = first.replace("play ", "");
+        assert_eq!(
+            next,
+            &format!("next {}", if first == "a" { "b" } else { "a" })
         );
 
-        let response = create_server(&storage).handle_request(&request);
-
-        assert_eq!(response.status_code, 404);
+        // the prepared entry took over without being started again
+        advanced.store(true, Ordering::SeqCst);
+        step();
+        assert_eq!(server.zones[0].queue.lock().unwrap().entries().len(), 2);
+        step();
+        assert_eq!(events().len(), 3);
+        assert_eq!(events()[2], format!("next {first}"));
 
+        server.zones[0].queue.lock().unwrap().skip(Some(last.id));
+        step();
+        assert_eq!(events()[3], "next nothing");
+        assert_eq!(events().len(), 4);
         Ok(())
     }
 
     #[test]
-    fn test_play_missing_hash() {
-        let server = create_empty_server();
+    fn test_jukebox_remote() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        assert_eq!(
+            server
+                .handle_request(&Request::fake_http("POST", "/player/pause", vec![], vec![]))
+                .status_code,
+            404
+        );
 
-        let request = Request::fake_http("GET", "/play", vec![], vec![]);
+        let finished = Arc::new(AtomicBool::new(true));
+        let server = server.with_jukebox(FakeJukebox::new(&Arc::default(), &finished));
+        let request = |method: &'static str, url: &str| {
+            server.handle_request(&Request::fake_http(method, url, vec![], vec![]))
+        };
+        let now_playing = || -> anyhow::Result<NowPlayingResponse> {
+            parse_json_response(request("GET", "/player/now-playing"))
+        };
 
-        let response = server.handle_request(&request);
-        let status = response.status_code;
+        let idle = now_playing()?;
+        assert!(idle.entry.is_none());
+        assert_eq!(idle.duration_secs, None);
+        assert_eq!(request("POST", "/player/next").status_code, 404);
 
+        for id in &ids {
+            request("POST", &format!("/queue?h={id}"));
+        }
+        assert_eq!(request("POST", "/player/pause").status_code, 204);
         assert_eq!(
-            status,
-            400,
-            "expected 400 for missing hash, got {}. response: {}",
-            status,
-            parse_text_response(response)
+            request("POST", "/player/seek?position=12.5").status_code,
+            204
         );
+        assert_eq!(request("POST", "/player/seek?position=-1").status_code, 400);
+        assert_eq!(request("POST", "/player/seek?position=90").status_code, 400);
+        assert_eq!(request("PUT", "/player/volume?level=0.25").status_code, 204);
+        assert_eq!(request("PUT", "/player/volume?level=2").status_code, 400);
 
-        let body = parse_text_response(response);
+        let playing = now_playing()?;
+        assert_eq!(playing.entry.map(|e| e.track_id), Some(ids[0]));
+        assert!(playing.paused);
+        assert_eq!(playing.position_secs, 12.5);
+        assert_eq!(playing.duration_secs, Some(60.0));
+        assert_eq!(playing.volume, 0.25);
 
-        assert!(
-            body.contains("missing media hash"),
-            "expected missing-hash error, got: {}",
-            body
-        );
+        assert_eq!(request("POST", "/player/play").status_code, 204);
+        assert_eq!(request("POST", "/player/next").status_code, 204);
+        let next = now_playing()?;
+        assert!(!next.paused);
+        assert_eq!(next.entry.map(|e| e.track_id), Some(ids[1]));
+        assert_eq!(request("GET", "/player/unknown").status_code, 404);
+        Ok(())
     }
 
     #[test]
-    fn test_stream_headers() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("song.mp3");
-        fs::write(&file_path, b"x").unwrap();
+    fn test_jukebox_zones() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
         let (server, files) = create_server_with_tracks(dir.path());
-        let (track_id, _) = files.into_iter().next().unwrap();
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let zone = || FakeJukebox::new(&Arc::default(), &Arc::default());
+        let server = server
+            .with_zone("living-room", zone())
+            .with_zone("kitchen", zone());
+        let request = |method: &'static str, url: &str| {
+            server.handle_request(&Request::fake_http(method, url, vec![], vec![]))
+        };
+        let queued = |url: &str| -> anyhow::Result<Vec<TrackId>> {
+            let queue: QueueResponse = parse_json_response(request("GET", url))?;
+            Ok(queue.entries.into_iter().map(|e| e.track_id).collect())
+        };
 
-        let request =
-            Request::fake_http("GET", format!("/tracks/{track_id}/stream"), vec![], vec![]);
-        let response = server
-            .get_track_stream(track_id.to_string(), &request)
-            .expect("streaming should succeed");
+        // the first zone took the place of the default one
+        let zones: Vec<ZoneResponse> = parse_json_response(request("GET", "/zones"))?;
+        let names: Vec<_> = zones.iter().map(|zone| zone.name.as_str()).collect();
+        assert_eq!(names, vec!["living-room", "kitchen"]);
 
-        // Check that Accept-Ranges header is present
-        assert_eq!(
-            response
-                .headers
-                .iter()
-                .any(|(k, _)| k.eq_ignore_ascii_case("Accept-Ranges")),
-            true
-        );
+        request("POST", &format!("/queue?h={}", ids[0]));
+        request("POST", &format!("/queue?h={}&zone=kitchen", ids[1]));
+        assert_eq!(queued("/queue")?, vec![ids[0]]);
+        assert_eq!(queued("/queue?zone=living-room")?, vec![ids[0]]);
+        assert_eq!(queued("/queue?zone=kitchen")?, vec![ids[1]]);
+        assert_eq!(request("POST", "/queue?h=1&zone=attic").status_code, 404);
 
-        // Check status code
-        assert!(
-            response.status_code == 200 || response.status_code == 206,
-            "expected 200 or 206, got {}",
-            response.status_code
-        );
+        let volume = "/player/volume?level=0.5&zone=kitchen";
+        assert_eq!(request("PUT", volume).status_code, 204);
+        let volume_of = |url: &str| -> anyhow::Result<f32> {
+            let now_playing: NowPlayingResponse = parse_json_response(request("GET", url))?;
+            Ok(now_playing.volume)
+        };
+        assert_eq!(volume_of("/player/now-playing?zone=kitchen")?, 0.5);
+        assert_eq!(volume_of("/player/now-playing")?, 1.0);
+        Ok(())
     }
 
     #[test]
-    fn test_stream_partial_range() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("song.mp3");
-        fs::write(&file_path, b"asdfghjkas").unwrap();
-
+    fn test_scheduled_playlist_replaces_the_queue() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
         let (server, files) = create_server_with_tracks(dir.path());
-        let (track_id, _) = files.into_iter().next().unwrap();
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let mut fake = FakeJukebox::new(&Arc::default(), &Arc::default());
+        fake.paused = true;
+        let server = server.with_jukebox(fake);
+        let jukebox = server.zones[0].jukebox.as_ref().unwrap();
+        let playlist = {
+            let mut storage = server.storage.lock().unwrap();
+            let playlist = storage.create_playlist("Wake up")?;
+            storage.set_playlist_tracks(playlist.id, &[ids[1], ids[0]])?;
+            storage.create_playlist("Empty")?;
+            playlist
+        };
+        server.zones[0].queue.lock().unwrap().push(ids[0]);
+        let start =
+            |target: PlayTarget| schedule::start(&target, &server.zones[0], &server.storage);
+        let queued = || -> Vec<TrackId> {
+            let queue = server.zones[0].queue.lock().unwrap();
+            queue.entries().iter().map(|e| e.track_id).collect()
+        };
 
-        // Request a partial range
-        let request = Request::fake_http(
-            "GET",
-            format!("/tracks/{track_id}/stream"),
-            vec![("Range".into(), "bytes=2-5".into())],
-            vec![],
-        );
+        start(PlayTarget::Playlist("Wake up".to_string()))?;
+        assert_eq!(queued(), vec![ids[1], ids[0]]);
+        assert!(!jukebox.lock().unwrap().is_paused());
 
-        let response = server
-            .get_track_stream(track_id.to_string(), &request)
-            .expect("partial streaming should succeed");
+        start(PlayTarget::Playlist(playlist.id.to_string()))?;
+        start(PlayTarget::Track(ids[0]))?;
+        assert_eq!(queued(), vec![ids[0]]);
 
-        assert_eq!(response.status_code, 206);
+        assert!(start(PlayTarget::Playlist("Empty".to_string())).is_err());
+        assert!(start(PlayTarget::Playlist("Missing".to_string())).is_err());
+        assert_eq!(queued(), vec![ids[0]]);
+        Ok(())
+    }
 
-        let content_range = response
-            .headers
-            .iter()
-            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Range"))
-            .expect("Content-Range header should be present")
-            .1
-            .to_string();
+    #[test]
+    fn test_favourites() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.mp3"), b"a")?;
+        fs::write(dir.path().join("b.mp3"), b"b")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let mut ids: Vec<_> = files.into_keys().collect();
+        ids.sort();
+        let toggle = |id: TrackId| -> anyhow::Result<FavouriteResponse> {
+            let request =
+                Request::fake_http("POST", format!("/tracks/{id}/favourite"), vec![], vec![]);
+            parse_json_response(server.handle_request(&request))
+        };
+        let favourites = || -> anyhow::Result<Vec<TrackId>> {
+            let request = Request::fake_http("GET", "/favourites", vec![], vec![]);
+            let tracks: Vec<TrackListResponse> =
+                parse_json_response(server.handle_request(&request))?;
+            assert!(tracks.iter().all(|track| track.locations.len() == 1));
+            Ok(tracks.into_iter().map(|track| track.track_id).collect())
+        };
 
-        assert_eq!(content_range, "bytes 2-5/10");
+        assert!(toggle(ids[0])?.favourite);
+        assert!(toggle(ids[1])?.favourite);
+        assert_eq!(favourites()?.len(), 2);
+        assert!(!toggle(ids[0])?.favourite);
+        assert_eq!(favourites()?, vec![ids[1]]);
+
+        let request = Request::fake_http("POST", "/tracks/999/favourite", vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 404);
+        Ok(())
     }
 
     #[test]
-    fn test_stream_invalid_range_returns_416() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("song.mp3");
+    fn test_scans_are_logged_and_reported() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("song.mp3"), b"song")?;
+        let (server, files) = create_server_with_tracks(dir.path());
+        let (track, _) = files.into_iter().next().unwrap();
+        let play = |card: &str, headers: Vec<(String, String)>| {
+            let request = Request::fake_http("GET", format!("/play?h={card}"), headers, vec![]);
+            server.handle_request(&request).status_code
+        };
 
-        fs::write(&file_path, b"x").unwrap();
+        let phone = ("User-Agent".to_string(), "Mozilla/5.0 (iPhone)".to_string());
+        assert_eq!(play(&track.to_string(), vec![phone.clone()]), 200);
+        // the rest of the same playback
+        let rest = ("Range".to_string(), "bytes=2-".to_string());
+        assert_eq!(play(&track.to_string(), vec![phone, rest]), 206);
+        assert_eq!(play("missing-card", vec![]), 404);
 
-        let (server, files) = create_server_with_tracks(dir.path());
-        let (track_id, _) = files.into_iter().next().unwrap();
+        let scans = server.storage.lock().unwrap().list_card_scans(None)?;
+        assert_eq!(scans.len(), 2);
+        let played = scans.iter().find(|s| s.track_id == Some(track)).unwrap();
+        assert_eq!(played.outcome, ScanOutcome::Local);
+        assert_eq!(played.user_agent.as_deref(), Some("Mozilla/5.0 (iPhone)"));
 
-        // Request a range beyond file size
-        let request = Request::fake_http(
-            "GET",
-            "/tracks/{track_id}/stream",
-            vec![("Range".into(), "bytes=20-30".into())],
-            vec![],
+        let request = Request::fake_http("GET", "/stats/scans?days=7", vec![], vec![]);
+        let report: Vec<ScanStatsResponse> = parse_json_response(server.handle_request(&request))?;
+        let cards: Vec<_> = report
+            .iter()
+            .map(|stats| (stats.card_id.as_str(), stats.local, stats.not_found))
+            .collect();
+        // equally often scanned cards are ordered by id
+        assert_eq!(
+            cards,
+            vec![(track.to_string().as_str(), 1, 0), ("missing-card", 0, 1)]
         );
 
-        let response = server.get_track_stream(track_id.to_string(), &request);
-
-        assert!(matches!(response, Err(ApiError::InvalidRange)));
+        let request = Request::fake_http("GET", "/stats/scans?days=week", vec![], vec![]);
+        assert_eq!(server.handle_request(&request).status_code, 400);
+        Ok(())
     }
 
     #[test]
-    fn test_http_get_track_with_metadata() -> anyhow::Result<()> {
-        use std::fs;
-        use tempfile::tempdir;
-
-        // ---------- Setup temp directory and music file ----------
+    fn test_sync_library() -> anyhow::Result<()> {
         let dir = tempdir()?;
-        let file_path = dir.path().join("song.mp3");
-        fs::write(&file_path, b"x")?;
-
-        // ---------- Setup server with track and metadata ----------
-        // `create_server_with_tracks` should accept metadata if we extend it
+        fs::write(dir.path().join("song.mp3"), b"x")?;
         let (server, files) = create_server_with_tracks(dir.path());
-        let (id, _) = files.into_iter().next().unwrap();
-
-        server.storage.lock().unwrap().update_track_metadata(
-            id,
-            MetadataUpdate {
-                title: Some("Test Song".to_string()),
-                artist: Some("Test Artist".to_string()),
-                year: Some(2026),
-                label: Some("Test Label".to_string()),
-                artwork: Some(ArtworkRef("cover.jpg".to_string())),
-            },
-            false,
-        )?;
+        let (track, hashed) = files.into_iter().next().unwrap();
+        let hash = hashed.into_iter().next().unwrap().hash;
+        {
+            let mut storage = server.storage.lock().unwrap();
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    title: Some("Song".to_string()),
+                    artist: Some("Artist".to_string()),
+                    year: None,
+                    label: None,
+                    artwork: Some(ArtworkRef("/home/me/cover.jpg".to_string())),
+                },
+                false,
+            )?;
+            let public = storage.create_playlist("public")?;
+            storage.set_playlist_tracks(public.id, &[track])?;
+            let private = storage.create_playlist("private")?;
+            storage.set_playlist_token(private.id, Some("secret"))?;
+        }
 
-        // ---------- Make the HTTP request ----------
-        let request = Request::fake_http("GET", format!("/tracks/{}", id), vec![], vec![]);
+        let request = Request::fake_http("GET", "/sync", vec![], vec![]);
         let response = server.handle_request(&request);
-
         assert_eq!(response.status_code, 200);
+        let library: SyncLibrary = parse_json_response(response)?;
 
-        // ---------- Parse JSON response ----------
-        let body: TrackResponse = parse_json_response(response)?;
+        assert_eq!(library.tracks.len(), 1);
+        let synced = &library.tracks[0];
+        assert_eq!(synced.track_id, track);
+        assert_eq!(synced.files[0].hash, hash.to_hex());
+        assert_eq!(synced.files[0].name.as_deref(), Some("song.mp3"));
+        let metadata = synced.metadata.as_ref().unwrap();
+        assert_eq!(metadata.title, "Song");
+        // the cover is a file of this machine
+        assert_eq!(metadata.artwork, None);
 
-        // ---------- Assertions ----------
-        assert_eq!(body.track_id, id);
-        assert_eq!(body.location, Location::from_path(file_path));
+        let playlists: Vec<_> = library.playlists.iter().map(|p| &p.name).collect();
+        assert_eq!(playlists, vec!["public"]);
+        assert_eq!(library.playlists[0].tracks, vec![track]);
+        Ok(())
+    }
 
-        // Metadata assertions
-        let metadata = body.metadata.expect("Metadata should be present");
-        assert_eq!(metadata.title, "Test Song");
-        assert_eq!(metadata.artist, "Test Artist");
-        assert_eq!(metadata.year, Some(2026));
-        assert_eq!(metadata.label.as_deref(), Some("Test Label"));
-        assert_eq!(
-            metadata.artwork.as_ref().map(|a| a.as_str()),
-            Some("cover.jpg")
+    /// Another localdeck instance listing one track, remote id 8, and streaming `song` for it.
+    /// Returns its base URL and the request heads it received
+    fn serve_remote_deck(song: &'static [u8]) -> (String, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let library = SyncLibrary {
+            tracks: vec![SyncTrack {
+                track_id: 8,
+                files: vec![SyncFile {
+                    hash: FileHash::from_bytes(song).to_hex(),
+                    kind: HashKind::Full,
+                    file_size: song.len() as i64,
+                    name: Some("song.mp3".to_string()),
+                }],
+                metadata: None,
+            }],
+            playlists: vec![],
+        };
+        let library = serde_json::to_string(&library).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    head.push_str(&line.to_lowercase());
+                }
+                let response = if head.starts_with("get /sync ") {
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", library.len())
+                        + &format!("Connection: close\r\n\r\n{library}")
+                } else if head.starts_with("get /tracks/8/stream ") {
+                    // only the range the proxy tests ask for
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Type: audio/mpeg\r\nContent-Range: bytes 2-5/{}\r\nContent-Length: 4\r\nConnection: close\r\n\r\n{}",
+                        song.len(),
+                        String::from_utf8_lossy(&song[2..6])
+                    )
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                        .to_string()
+                };
+                seen.lock().unwrap().push(head);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[test]
+    fn test_remote_track_stream_is_proxied() -> anyhow::Result<()> {
+        let (base_url, requests) = serve_remote_deck(b"asdfghjkas");
+        let storage = setup_storage(Some(Location::Remote {
+            base_url,
+            token: Some("secret".to_string()),
+            path: Default::default(),
+        }))?;
+        let files = storage.lock().unwrap().update_db_with_new_files()?;
+        let server = create_server(&storage);
+        let (track_id, _) = files.into_iter().next().unwrap();
+
+        let request = Request::fake_http(
+            "GET",
+            format!("/tracks/{track_id}/stream"),
+            vec![("Range".into(), "bytes=2-5".into())],
+            vec![],
         );
+        let response = server.handle_request(&request);
+
+        assert_eq!(response.status_code, 206);
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.to_string())
+        };
+        assert_eq!(header("Content-Range").as_deref(), Some("bytes 2-5/10"));
+        assert_eq!(header("Content-Type").as_deref(), Some("audio/mpeg"));
+        assert_eq!(parse_text_response(response), "dfgh");
 
+        let requests = requests.lock().unwrap();
+        let stream = requests
+            .iter()
+            .find(|head| head.starts_with("get /tracks/8/stream "))
+            .expect("the stream should be requested from the other instance");
+        assert!(stream.contains("range: bytes=2-5"));
+        assert!(stream.contains("authorization: bearer secret"));
         Ok(())
     }
 }
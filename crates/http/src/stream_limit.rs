@@ -0,0 +1,188 @@
+//! Caps on the number of tracks streamed at once and on how fast each is sent.
+//!
+//! A slot is taken when a stream response is built and given back once its body has been
+//! sent or the client went away, so a guest pausing a track holds none. Requests beyond
+//! a cap get a `503` telling the player when to try again. Players buffer ahead, so a
+//! throttled stream plays on as long as the rate stays above the track's bitrate.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rouille::{Response, ResponseBody};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct StreamLimits {
+    /// streams sent at once to all clients, unlimited if unset
+    #[serde(default)]
+    pub max: Option<usize>,
+    /// streams sent at once to a single address, unlimited if unset
+    #[serde(default)]
+    pub max_per_ip: Option<usize>,
+    /// rate each stream is sent at at most, e.g. `1000000` leaves room for several FLACs
+    /// on a slow uplink. Unlimited if unset
+    #[serde(default)]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Active {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Streams being sent, shared by all request handlers
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StreamCounter {
+    limits: StreamLimits,
+    active: Arc<Mutex<Active>>,
+}
+
+impl StreamCounter {
+    pub(crate) fn new(limits: StreamLimits) -> Self {
+        Self {
+            limits,
+            active: Arc::default(),
+        }
+    }
+
+    /// Takes a slot for a stream to `ip`, or refuses if a cap is reached
+    pub(crate) fn acquire(&self, ip: IpAddr) -> Result<StreamSlot, ApiError> {
+        let mut active = self.active.lock().unwrap();
+        if self.limits.max.is_some_and(|max| active.total >= max) {
+            return Err(ApiError::Busy(format!(
+                "{} tracks are streaming already",
+                active.total
+            )));
+        }
+        let of_ip = active.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.limits.max_per_ip.is_some_and(|max| of_ip >= max) {
+            return Err(ApiError::Busy(format!(
+                "{of_ip} tracks are streaming to {ip}"
+            )));
+        }
+        active.total += 1;
+        *active.per_ip.entry(ip).or_default() += 1;
+        Ok(StreamSlot {
+            active: Arc::clone(&self.active),
+            ip,
+            rate: self.limits.max_bytes_per_sec.filter(|&rate| rate > 0),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn active(&self) -> usize {
+        self.active.lock().unwrap().total
+    }
+}
+
+/// A stream counted against the caps until dropped
+#[derive(Debug)]
+pub(crate) struct StreamSlot {
+    active: Arc<Mutex<Active>>,
+    ip: IpAddr,
+    rate: Option<u64>,
+}
+
+impl StreamSlot {
+    /// Keeps the slot taken while the body of `response` is being sent, no faster
+    /// than the configured rate
+    pub(crate) fn hold_during(self, mut response: Response) -> Response {
+        let (body, size) = response.data.into_reader_and_size();
+        let body: Box<dyn Read + Send> = match self.rate {
+            Some(rate) => Box::new(Throttled::new(body, rate)),
+            None => body,
+        };
+        let body = SlotReader { body, _slot: self };
+        response.data = match size {
+            Some(size) => ResponseBody::from_reader_and_size(body, size),
+            None => ResponseBody::from_reader(body),
+        };
+        response
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        active.total = active.total.saturating_sub(1);
+        if let Some(of_ip) = active.per_ip.get_mut(&self.ip) {
+            *of_ip -= 1;
+            if *of_ip == 0 {
+                active.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+struct SlotReader {
+    body: Box<dyn Read + Send>,
+    _slot: StreamSlot,
+}
+
+impl Read for SlotReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// Reads no more than `rate` bytes per second on average
+struct Throttled<R> {
+    inner: R,
+    rate: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl<R: Read> Throttled<R> {
+    fn new(inner: R, rate: u64) -> Self {
+        Self {
+            inner,
+            rate,
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let due = Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+        if let Some(early) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(early);
+        }
+        // small chunks keep the rate even instead of sending a second's worth at once
+        let chunk = buf.len().min((self.rate / 20).max(1) as usize);
+        let read = self.inner.read(&mut buf[..chunk])?;
+        self.sent += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read},
+        time::{Duration, Instant},
+    };
+
+    use super::Throttled;
+
+    #[test]
+    fn throttled_reads_keep_to_the_rate() -> io::Result<()> {
+        let started = Instant::now();
+        let mut body = Vec::new();
+        Throttled::new(&[7u8; 5000][..], 10_000).read_to_end(&mut body)?;
+        assert_eq!(body.len(), 5000);
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Library description served at `/sync` for `localdeck sync` on other machines.
+//!
+//! Track ids differ between libraries, so tracks are described by the content hashes
+//! of their files, which the pulling side matches against its own files.
+//! Playlists protected by a share token are left out, their tracks are not public.
+
+use std::collections::HashMap;
+
+use localdeck_storage::{
+    error::StorageError,
+    location::Location,
+    remote::{SyncFile, SyncLibrary, SyncPlaylist, SyncTrack},
+    store::LibraryStore,
+    track::{TrackId, TrackMetadata},
+};
+
+pub(crate) fn load_library(storage: &mut dyn LibraryStore) -> Result<SyncLibrary, StorageError> {
+    let mut metadata: HashMap<TrackId, TrackMetadata> = storage
+        .scan_metadata()?
+        .into_iter()
+        .map(|track| (track.id, track.metadata))
+        .collect();
+
+    let mut tracks: Vec<SyncTrack> = vec![];
+    for (track_id, file) in storage.list_hashed_files()? {
+        if tracks.last().map(|t| t.track_id) != Some(track_id) {
+            tracks.push(SyncTrack {
+                track_id,
+                files: vec![],
+                metadata: metadata.remove(&track_id).map(without_local_artwork),
+            });
+        }
+        // streams of remote roots have no file name
+        let path = match &file.file.loc {
+            Location::File { path }
+            | Location::Usb { path, .. }
+            | Location::NetworkShare { path, .. }
+            | Location::S3 { path, .. } => Some(path),
+            Location::Remote { .. } => None,
+        };
+        if let Some(track) = tracks.last_mut() {
+            track.files.push(SyncFile {
+                hash: file.hash.to_hex(),
+                kind: file.kind,
+                file_size: file.file.file_size,
+                name: path
+                    .and_then(|path| path.file_name())
+                    .map(|n| n.to_string_lossy().to_string()),
+            });
+        }
+    }
+
+    let playlists = storage
+        .list_playlists()?
+        .into_iter()
+        .filter(|playlist| playlist.share_token.is_none())
+        .map(|playlist| SyncPlaylist {
+            name: playlist.name,
+            tracks: playlist.tracks,
+        })
+        .collect();
+    Ok(SyncLibrary { tracks, playlists })
+}
+
+/// Artwork files of this machine mean nothing elsewhere, links are kept
+fn without_local_artwork(mut metadata: TrackMetadata) -> TrackMetadata {
+    metadata.artwork = metadata
+        .artwork
+        .filter(|artwork| artwork.0.starts_with("http://") || artwork.0.starts_with("https://"));
+    metadata
+}
@@ -0,0 +1,89 @@
+//! Readiness and watchdog notifications for running under systemd (`Type=notify`).
+//!
+//! All functions are no-ops when the process was not started by systemd
+//! (no `NOTIFY_SOCKET`) or on non-unix platforms.
+
+use std::{
+    sync::{Arc, Mutex, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Tells systemd that the server is bound and accepting connections
+pub fn notify_ready() {
+    #[cfg(unix)]
+    if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]) {
+        log::warn!("failed to send readiness notification to systemd: {e}");
+    }
+}
+
+/// Tells systemd that the server is shutting down
+pub fn notify_stopping() {
+    #[cfg(unix)]
+    if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Stopping]) {
+        log::warn!("failed to send stopping notification to systemd: {e}");
+    }
+}
+
+/// Returns the interval systemd expects watchdog pings at, if `WatchdogSec=` is configured for the unit
+fn watchdog_interval() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) {
+            // ping twice per period, as recommended by sd_watchdog_enabled(3)
+            return Some(Duration::from_micros(usec / 2));
+        }
+    }
+    None
+}
+
+/// How long the shared state may stay locked before watchdog pings stop. Long operations,
+/// e.g. exporting a backup bundle, hold it for minutes and must not get the service restarted
+const STUCK_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// How often the watchdog thread checks whether `alive` is free
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Spawns a thread that pings the systemd watchdog while `alive` was free at some point
+/// of the last [`STUCK_AFTER`].
+///
+/// If the shared state stays locked for longer (for example a request handler deadlocked),
+/// pings stop and systemd restarts the service. The thread never waits for the lock itself.
+pub fn spawn_watchdog<T: ?Sized + Send + 'static>(alive: Arc<Mutex<T>>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    log::info!("systemd watchdog enabled, pinging every {interval:?}");
+
+    thread::spawn(move || {
+        let mut last_free = Instant::now();
+        let mut last_ping = Instant::now();
+        loop {
+            thread::sleep(CHECK_INTERVAL.min(interval));
+            match alive.try_lock() {
+                Ok(_) => last_free = Instant::now(),
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Poisoned(_)) => {
+                    log::error!("storage lock is poisoned, stopping systemd watchdog pings");
+                    return;
+                }
+            }
+            if last_ping.elapsed() < interval {
+                continue;
+            }
+            if last_free.elapsed() >= STUCK_AFTER {
+                log::error!(
+                    "storage has been locked for {:?}, stopping systemd watchdog pings",
+                    last_free.elapsed()
+                );
+                return;
+            }
+            last_ping = Instant::now();
+            #[cfg(unix)]
+            if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]) {
+                log::warn!("failed to ping systemd watchdog: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,53 @@
+//! Data of the guest pages.
+//!
+//! Pages are plain HTML whose scripts hold placeholders like `/*LIBRARY*/null`, so a page
+//! opened from disk still runs with the value after the comment. The server swaps every
+//! placeholder for JSON of its value in a single pass, text of an inserted value is never
+//! taken for a placeholder, e.g. a track titled `/*THEME*/null`.
+
+use serde::Serialize;
+
+/// JSON which can be placed inside a `<script>` element
+pub(crate) fn script_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value)
+        .expect("page data is serializable")
+        .replace('<', "\\u003c")
+}
+
+/// Replaces the placeholders of `values` in `page`, left to right
+pub(crate) fn fill(page: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(page.len());
+    let mut rest = page;
+    while let Some((at, placeholder, value)) = values
+        .iter()
+        .filter_map(|(placeholder, value)| {
+            rest.find(placeholder).map(|at| (at, placeholder, value))
+        })
+        .min_by_key(|(at, ..)| *at)
+    {
+        filled.push_str(&rest[..at]);
+        filled.push_str(value);
+        rest = &rest[at + placeholder.len()..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_values_are_not_filled_again() {
+        let page = "const texts = /*TEXTS*/null;\nconst theme = /*THEME*/null;\n/*TEXTS*/null";
+        let values = [
+            ("/*TEXTS*/null", script_json(&"/*THEME*/null")),
+            ("/*THEME*/null", script_json(&"</script>")),
+        ];
+        assert_eq!(
+            fill(page, &values),
+            "const texts = \"/*THEME*/null\";\nconst theme = \"\\u003c/script>\";\n\"/*THEME*/null\""
+        );
+        assert_eq!(fill("no placeholders", &values), "no placeholders");
+    }
+}
@@ -0,0 +1,104 @@
+//! Branding of the pages guests see, configured in the `[http.theme]` section
+
+use std::{fs, fs::File, path::PathBuf};
+
+use rouille::Response;
+use serde::{Deserialize, Serialize};
+
+const STYLESHEET_NAME: &str = "style.css";
+/// routes of the server
+const LOGO_URL: &str = "/theme/logo";
+const STYLESHEET_URL: &str = "/theme/style.css";
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ThemeConfig {
+    /// shown as the page heading and title instead of "QR Scanner", e.g. "Sasha's Deck"
+    pub title: Option<String>,
+    /// image shown above the scanner
+    pub logo: Option<PathBuf>,
+    /// CSS color of the heading, borders and the current lyrics line, e.g. `#e91e63`
+    pub accent_color: Option<String>,
+    /// directory with a `style.css` applied after the built-in styles,
+    /// and pages replacing the built-in ones, e.g. `scan_qr.html`
+    pub dir: Option<PathBuf>,
+}
+
+/// Theme as the pages receive it, as JSON
+#[derive(Debug, Serialize)]
+pub(crate) struct PageTheme<'a> {
+    title: Option<&'a str>,
+    logo: Option<&'static str>,
+    accent_color: Option<&'a str>,
+    stylesheet: Option<&'static str>,
+}
+
+impl ThemeConfig {
+    /// Page `name` from the theme directory, `builtin` if the directory has none
+    pub(crate) fn page(&self, name: &str, builtin: &str) -> String {
+        self.dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(name)).ok())
+            .unwrap_or_else(|| builtin.to_string())
+    }
+
+    pub(crate) fn page_theme(&self) -> PageTheme<'_> {
+        PageTheme {
+            title: self.title.as_deref(),
+            logo: self.logo.as_ref().map(|_| LOGO_URL),
+            accent_color: self.accent_color.as_deref(),
+            stylesheet: self.stylesheet_path().map(|_| STYLESHEET_URL),
+        }
+    }
+
+    fn stylesheet_path(&self) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(STYLESHEET_NAME))
+            .filter(|path| path.is_file())
+    }
+
+    /// Response to `/theme/<name>`, the logo or the stylesheet
+    pub(crate) fn file_response(&self, name: &str) -> Response {
+        let path = match name {
+            "logo" => self.logo.clone(),
+            STYLESHEET_NAME => self.stylesheet_path(),
+            _ => None,
+        };
+        match path {
+            Some(path) => serve_file(path),
+            None => Response::empty_404(),
+        }
+    }
+}
+
+fn serve_file(path: PathBuf) -> Response {
+    match File::open(&path) {
+        Ok(file) => {
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            Response::from_file(mime.to_string(), file)
+        }
+        Err(e) => {
+            log::warn!("cannot open theme file {}: {e}", path.display());
+            Response::empty_404()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_is_parsed() {
+        let theme: ThemeConfig = toml::from_str(
+            r#"
+title = "Sasha's Deck"
+accent_color = "crimson"
+"#,
+        )
+        .unwrap();
+        assert_eq!(theme.title.as_deref(), Some("Sasha's Deck"));
+        assert_eq!(theme.accent_color.as_deref(), Some("crimson"));
+        assert_eq!(theme.logo, None);
+    }
+}
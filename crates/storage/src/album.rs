@@ -0,0 +1,111 @@
+//! Albums as the folders holding the tracks' files.
+//!
+//! Libraries are usually laid out one release per folder, which tells more reliably what
+//! belongs together than tags do. A track belongs to the folder of its first recorded
+//! file, and tracks of an album are ordered by file name, as a file manager shows them.
+
+use std::collections::BTreeMap;
+
+use crate::{location::Location, operations::TrackListEntry, track::TrackId};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Album {
+    /// the lowest id of its tracks, so it stays the same while that track is kept
+    pub id: TrackId,
+    /// name of the folder, e.g. `[2019] Live at Home`
+    pub name: String,
+    pub folder: Location,
+    /// in file name order
+    pub tracks: Vec<TrackId>,
+}
+
+/// Albums of the tracks with files, ordered by folder
+pub fn group_albums(tracks: &[TrackListEntry]) -> Vec<Album> {
+    let mut folders: BTreeMap<String, (Location, Vec<(String, TrackId)>)> = BTreeMap::new();
+    for track in tracks {
+        let Some(file) = track.files.first() else {
+            continue;
+        };
+        let Some(folder) = file.loc.parent() else {
+            continue;
+        };
+        folders
+            .entry(folder.to_string())
+            .or_insert_with(|| (folder, Vec::new()))
+            .1
+            .push((file.loc.to_string(), track.id));
+    }
+    folders
+        .into_values()
+        .map(|(folder, mut files)| {
+            files.sort();
+            let tracks: Vec<TrackId> = files.into_iter().map(|(_, track)| track).collect();
+            Album {
+                id: tracks.iter().copied().min().unwrap_or_default(),
+                name: folder.file_name().unwrap_or_else(|| folder.to_string()),
+                folder,
+                tracks,
+            }
+        })
+        .collect()
+}
+
+/// Album holding `track`, `None` for tracks without files
+pub fn album_of(tracks: &[TrackListEntry], track: TrackId) -> Option<Album> {
+    group_albums(tracks)
+        .into_iter()
+        .find(|album| album.tracks.contains(&track))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        location::Location,
+        operations::{ListedFile, TrackListEntry},
+    };
+
+    use super::{album_of, group_albums};
+
+    fn track(id: i64, path: &str) -> TrackListEntry {
+        TrackListEntry {
+            id,
+            added: None,
+            files: vec![ListedFile {
+                loc: Location::Usb {
+                    label: "deck".to_string(),
+                    path: Path::new(path).to_path_buf(),
+                },
+                size: 1,
+                modified: None,
+                format: None,
+            }],
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn tracks_are_grouped_by_folder() {
+        let tracks = [
+            track(3, "music/Live/02.mp3"),
+            track(5, "music/Live/01.mp3"),
+            track(4, "music/Demos/a.mp3"),
+            TrackListEntry {
+                id: 9,
+                added: None,
+                files: vec![],
+                meta: None,
+            },
+        ];
+        let albums = group_albums(&tracks);
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums[0].name, "Demos");
+        assert_eq!(albums[0].tracks, vec![4]);
+        assert_eq!(albums[1].name, "Live");
+        assert_eq!(albums[1].id, 3);
+        assert_eq!(albums[1].tracks, vec![5, 3]);
+        assert_eq!(album_of(&tracks, 5), Some(albums[1].clone()));
+        assert_eq!(album_of(&tracks, 9), None);
+    }
+}
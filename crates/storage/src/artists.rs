@@ -0,0 +1,395 @@
+//! Artists credited on tracks, so "A feat. B" is listed under both A and B.
+//!
+//! The `artist` of the metadata stays the string shown for a track. Whenever it or the
+//! title changes, the credits are read from them again: the artist before "feat.", "ft."
+//! or "featuring" is the main artist, the names after it and after a "(feat. B)" in the
+//! title are featured, and "Song (B Remix)" credits B as remixer. Main artists are not
+//! split, "Earth, Wind & Fire" is one artist.
+//!
+//! Credits set by hand with `localdeck meta artists` replace the artist string with
+//! one made from them, and are kept until the artist or title is edited.
+
+use std::{fmt, str::FromStr};
+
+use rusqlite::{Connection, params};
+use serde::Serialize;
+
+use crate::{
+    Storage,
+    audit::{self, AuditOperation},
+    error::StorageError,
+    provenance::{self, MetaField, MetadataOrigin},
+    schema::{columns::*, tables::*},
+    sort_names,
+    track::TrackId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtistRole {
+    Main,
+    Featuring,
+    Remixer,
+}
+
+impl ArtistRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtistRole::Main => "main",
+            ArtistRole::Featuring => "featuring",
+            ArtistRole::Remixer => "remixer",
+        }
+    }
+}
+
+impl fmt::Display for ArtistRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for ArtistRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" => Ok(ArtistRole::Main),
+            "featuring" => Ok(ArtistRole::Featuring),
+            "remixer" => Ok(ArtistRole::Remixer),
+            other => Err(format!("Unknown artist role: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtistCredit {
+    pub name: String,
+    pub role: ArtistRole,
+}
+
+impl ArtistCredit {
+    pub fn new(name: impl Into<String>, role: ArtistRole) -> Self {
+        Self {
+            name: name.into(),
+            role,
+        }
+    }
+}
+
+/// Words introducing featured artists, matched ignoring case at the start of a word
+const FEATURING: &[&str] = &["featuring ", "feat. ", "feat ", "ft. ", "ft "];
+
+/// Remixes named after what they are rather than who made them, like "Extended Remix"
+const NOT_REMIXERS: &[&str] = &[
+    "extended", "radio", "club", "original", "official", "vip", "dub",
+];
+
+/// Featured artists and remixers are listed as "B, C & D"
+fn split_names(names: &str) -> impl Iterator<Item = &str> {
+    names
+        .split([',', '&'])
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// Start and length of the first featuring word
+fn find_featuring(text: &str) -> Option<(usize, usize)> {
+    let lower = text.to_ascii_lowercase();
+    // the byte before is ASCII, so `i` is a char boundary
+    (1..lower.len())
+        .filter(|&i| matches!(lower.as_bytes()[i - 1], b' ' | b'(' | b'['))
+        .find_map(|i| {
+            FEATURING
+                .iter()
+                .find(|word| lower[i..].starts_with(*word))
+                .map(|word| (i, word.len()))
+        })
+}
+
+/// Splits "A feat. B" and "Song (feat. B) [Live]" into the text without the featured
+/// artists, "A" and "Song [Live]", and their names
+pub(crate) fn split_featuring(text: &str) -> (String, Vec<&str>) {
+    let Some((start, len)) = find_featuring(text) else {
+        return (text.trim().to_string(), vec![]);
+    };
+    let before = text[..start].trim_end();
+    let after = &text[start + len..];
+    let closing = match before.chars().last() {
+        Some('(') => ')',
+        Some('[') => ']',
+        _ => return (before.to_string(), split_names(after).collect()),
+    };
+    let (names, rest) = after.split_once(closing).unwrap_or((after, ""));
+    let before = before[..before.len() - 1].trim_end();
+    (
+        format!("{before}{rest}").trim().to_string(),
+        split_names(names).collect(),
+    )
+}
+
+/// Remixers of "Song (B Remix)", "Song [B & C Remix]" or "Song - B Remix"
+fn remixers(title: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = title
+        .split(['(', '['])
+        .skip(1)
+        .map(|part| part.split([')', ']']).next().unwrap_or(part))
+        .collect();
+    if let Some((_, suffix)) = title.rsplit_once(" - ") {
+        parts.push(suffix);
+    }
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| part.to_ascii_lowercase().ends_with(" remix"))
+        .map(|part| part[..part.len() - " remix".len()].trim())
+        .filter(|name| !NOT_REMIXERS.contains(&name.to_ascii_lowercase().as_str()))
+        .flat_map(split_names)
+        .collect()
+}
+
+/// Credits of a track with this artist and title, the main artist first
+pub fn parse_credits(artist: &str, title: &str) -> Vec<ArtistCredit> {
+    let (main, featuring) = split_featuring(artist);
+    let (title, title_featuring) = split_featuring(title);
+    let mut credits: Vec<ArtistCredit> = vec![];
+    let names = std::iter::once((main.as_str(), ArtistRole::Main))
+        .chain(
+            featuring
+                .into_iter()
+                .map(|name| (name, ArtistRole::Featuring)),
+        )
+        .chain(
+            title_featuring
+                .into_iter()
+                .map(|name| (name, ArtistRole::Featuring)),
+        )
+        .chain(
+            remixers(&title)
+                .into_iter()
+                .map(|name| (name, ArtistRole::Remixer)),
+        );
+    for (name, role) in names {
+        let known = credits
+            .iter()
+            .any(|credit| credit.role == role && credit.name.to_lowercase() == name.to_lowercase());
+        if !name.is_empty() && !known {
+            credits.push(ArtistCredit::new(name, role));
+        }
+    }
+    credits
+}
+
+/// Artist string shown for the credits, like "A & B feat. C". `None` without a main artist
+pub fn display_artist(credits: &[ArtistCredit]) -> Option<String> {
+    let names = |role| {
+        credits
+            .iter()
+            .filter(|credit| credit.role == role)
+            .map(|credit| credit.name.as_str())
+            .collect::<Vec<_>>()
+    };
+    let main = names(ArtistRole::Main);
+    if main.is_empty() {
+        return None;
+    }
+    let featuring = names(ArtistRole::Featuring);
+    let mut display = main.join(" & ");
+    if !featuring.is_empty() {
+        display.push_str(" feat. ");
+        display.push_str(&featuring.join(", "));
+    }
+    Some(display)
+}
+
+/// Replaces the credits of the track
+pub(crate) fn link_credits(
+    db: &Connection,
+    track_id: TrackId,
+    credits: &[ArtistCredit],
+) -> Result<(), rusqlite::Error> {
+    db.execute(
+        &format!("DELETE FROM {TRACK_ARTISTS} WHERE {TRACK_ID} = ?1"),
+        params![track_id],
+    )?;
+    for (position, credit) in credits.iter().enumerate() {
+        // spellings differing in case are one artist, named by the one sorting first
+        db.execute(
+            &format!(
+                "INSERT INTO {ARTISTS} ({NAME}) VALUES (?1)
+                ON CONFLICT ({NAME}) DO UPDATE SET {NAME} = excluded.{NAME}
+                WHERE excluded.{NAME} < {NAME} COLLATE BINARY"
+            ),
+            params![credit.name],
+        )?;
+        let artist_id: i64 = db.query_row(
+            &format!("SELECT {ARTIST_ID} FROM {ARTISTS} WHERE {NAME} = ?1"),
+            params![credit.name],
+            |row| row.get(0),
+        )?;
+        db.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {TRACK_ARTISTS} ({TRACK_ID}, {ARTIST_ID}, {ROLE}, {POSITION})
+                VALUES (?1, ?2, ?3, ?4)"
+            ),
+            params![track_id, artist_id, credit.role.as_str(), position as i64],
+        )?;
+    }
+    Ok(())
+}
+
+/// Credits tracks whose metadata was stored before artists were
+pub(crate) fn link_missing(db: &Connection) -> Result<(), rusqlite::Error> {
+    let tracks = db
+        .prepare(&format!(
+            "SELECT m.{TRACK_ID}, m.{ARTIST}, m.{TITLE} FROM {TRACK_METADATA} m
+            WHERE NOT EXISTS (SELECT 1 FROM {TRACK_ARTISTS} ta WHERE ta.{TRACK_ID} = m.{TRACK_ID})"
+        ))?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, TrackId>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (track_id, artist, title) in tracks {
+        link_credits(db, track_id, &parse_credits(&artist, &title))?;
+    }
+    Ok(())
+}
+
+impl Storage {
+    /// Credits of the track in the order they are shown, empty without metadata
+    pub fn artist_credits(&mut self, track_id: TrackId) -> Result<Vec<ArtistCredit>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT a.{NAME}, ta.{ROLE} FROM {TRACK_ARTISTS} ta
+            JOIN {ARTISTS} a ON a.{ARTIST_ID} = ta.{ARTIST_ID}
+            WHERE ta.{TRACK_ID} = ?1
+            ORDER BY ta.{POSITION}"
+        ))?;
+        let rows = stmt
+            .query_map(params![track_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(name, role)| {
+                let role = role
+                    .parse()
+                    .map_err(|e: String| StorageError::Internal(anyhow::anyhow!(e)))?;
+                Ok(ArtistCredit { name, role })
+            })
+            .collect()
+    }
+
+    /// Credits the artists on the track, which needs metadata. The artist string becomes
+    /// one made from the credits, so they need a main artist.
+    ///
+    /// Without credits they are read from the artist and title again
+    pub fn set_artist_credits(
+        &mut self,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError> {
+        let tx = self.db.transaction()?;
+        let meta = Self::load_metadata(&tx, track_id)?
+            .ok_or(StorageError::RequiredMetaMissing(track_id))?;
+        if credits.is_empty() {
+            link_credits(&tx, track_id, &parse_credits(&meta.artist, &meta.title))?;
+        } else {
+            let display =
+                display_artist(credits).ok_or(StorageError::RequiredMetaMissing(track_id))?;
+            link_credits(&tx, track_id, credits)?;
+            if display != meta.artist {
+                tx.execute(
+                    &format!(
+                        "UPDATE {TRACK_METADATA} SET {ARTIST} = ?1, {ARTIST_SORT} = ?2
+                        WHERE {TRACK_ID} = ?3"
+                    ),
+                    params![display, sort_names::artist_sort_name(&display), track_id],
+                )?;
+                provenance::record_sources(
+                    &tx,
+                    track_id,
+                    &[MetaField::Artist],
+                    MetadataOrigin::Manual,
+                )?;
+            }
+        }
+        Self::insert_update_time(&tx)?;
+        audit::record(&tx, AuditOperation::Metadata, "artists", [track_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArtistCredit, ArtistRole::*, display_artist, parse_credits};
+
+    fn credits(artist: &str, title: &str) -> Vec<(String, &'static str)> {
+        parse_credits(artist, title)
+            .into_iter()
+            .map(|credit| (credit.name, credit.role.as_str()))
+            .collect()
+    }
+
+    fn named(names: &[(&str, &'static str)]) -> Vec<(String, &'static str)> {
+        names
+            .iter()
+            .map(|(name, role)| (name.to_string(), *role))
+            .collect()
+    }
+
+    #[test]
+    fn featured_artists_and_remixers_are_credited() {
+        assert_eq!(credits("Burial", "Archangel"), named(&[("Burial", "main")]));
+        assert_eq!(
+            credits(
+                "Daft Punk feat. Pharrell Williams & Nile Rodgers",
+                "Get Lucky"
+            ),
+            named(&[
+                ("Daft Punk", "main"),
+                ("Pharrell Williams", "featuring"),
+                ("Nile Rodgers", "featuring"),
+            ])
+        );
+        assert_eq!(
+            credits(
+                "Kanye West",
+                "All Of The Lights (Ft. Rihanna) [Hudson Mohawke Remix]"
+            ),
+            named(&[
+                ("Kanye West", "main"),
+                ("Rihanna", "featuring"),
+                ("Hudson Mohawke", "remixer"),
+            ])
+        );
+        assert_eq!(
+            credits("Disclosure", "Latch - Extended Remix"),
+            named(&[("Disclosure", "main")])
+        );
+        // main artists are not split, and "ft" inside a word is no featuring
+        assert_eq!(
+            credits("Earth, Wind & Fire", "Boogie Wonderland"),
+            named(&[("Earth, Wind & Fire", "main")])
+        );
+        assert_eq!(
+            credits("Daft Punk", "Aftershock"),
+            named(&[("Daft Punk", "main")])
+        );
+    }
+
+    #[test]
+    fn display_names_main_and_featured_artists() {
+        let credits = [
+            ArtistCredit::new("A", Main),
+            ArtistCredit::new("B", Main),
+            ArtistCredit::new("C", Featuring),
+            ArtistCredit::new("D", Remixer),
+        ];
+        assert_eq!(display_artist(&credits).as_deref(), Some("A & B feat. C"));
+        assert_eq!(display_artist(&credits[2..]), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Locates the audio data of music files, leaving out embedded tags,
+//! so editing the artist or artwork of a file keeps its [`HashKind::Audio`](crate::file_hash::HashKind) hash.
+//!
+//! Known containers are MP3 (ID3v2, ID3v1 and APEv2 tags), FLAC (metadata blocks)
+//! and MP4/M4A (everything except `mdat` atoms). Other files are used as a whole.
+
+const ID3V2_HEADER: usize = 10;
+const ID3V1_SIZE: usize = 128;
+const APE_FOOTER: usize = 32;
+
+/// Parts of the file holding audio frames, in file order
+pub(crate) fn audio_frames(contents: &[u8]) -> Vec<&[u8]> {
+    let audio = skip_id3v2(contents);
+    if let Some(frames) = audio.strip_prefix(b"fLaC") {
+        return vec![skip_flac_metadata(frames)];
+    }
+    if let Some(mdat) = mp4_mdat(contents) {
+        return mdat;
+    }
+    vec![strip_trailing_tags(audio)]
+}
+
+/// Skips ID3v2 tags at the start, there may be several of them
+fn skip_id3v2(mut contents: &[u8]) -> &[u8] {
+    while contents.len() >= ID3V2_HEADER && contents.starts_with(b"ID3") {
+        let flags = contents[5];
+        // sizes are "syncsafe", the highest bit of every byte is zero
+        let size = contents[6..10]
+            .iter()
+            .fold(0usize, |size, byte| (size << 7) | (*byte & 0x7f) as usize);
+        let footer = if flags & 0x10 != 0 { ID3V2_HEADER } else { 0 };
+        let end = ID3V2_HEADER + size + footer;
+        if end > contents.len() {
+            break;
+        }
+        contents = &contents[end..];
+    }
+    contents
+}
+
+/// Strips an ID3v1 tag and an APEv2 tag, which are appended to MP3 files
+fn strip_trailing_tags(mut contents: &[u8]) -> &[u8] {
+    if contents.len() >= ID3V1_SIZE && contents[contents.len() - ID3V1_SIZE..].starts_with(b"TAG") {
+        contents = &contents[..contents.len() - ID3V1_SIZE];
+    }
+    if contents.len() >= APE_FOOTER {
+        let footer = &contents[contents.len() - APE_FOOTER..];
+        if footer.starts_with(b"APETAGEX") {
+            // the size covers items and footer, the header is present if the highest flag bit is set
+            let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as usize;
+            let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+            let header = if flags & (1 << 31) != 0 {
+                APE_FOOTER
+            } else {
+                0
+            };
+            if let Some(start) = contents.len().checked_sub(size + header) {
+                contents = &contents[..start];
+            }
+        }
+    }
+    contents
+}
+
+/// Skips FLAC metadata blocks following the `fLaC` marker, including Vorbis comments and pictures
+fn skip_flac_metadata(mut contents: &[u8]) -> &[u8] {
+    while contents.len() >= 4 {
+        let is_last = contents[0] & 0x80 != 0;
+        let size = u32::from_be_bytes([0, contents[1], contents[2], contents[3]]) as usize;
+        let end = (4 + size).min(contents.len());
+        contents = &contents[end..];
+        if is_last {
+            break;
+        }
+    }
+    contents
+}
+
+/// Contents of the top level `mdat` atoms, `None` if the file does not look like MP4
+fn mp4_mdat(contents: &[u8]) -> Option<Vec<&[u8]>> {
+    if contents.get(4..8) != Some(b"ftyp") {
+        return None;
+    }
+    let mut mdat = vec![];
+    let mut rest = contents;
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let (header, size) = match size {
+            // the atom extends to the end of the file
+            0 => (8, rest.len()),
+            // 64 bit size follows the type
+            1 if rest.len() >= 16 => (
+                16,
+                u64::from_be_bytes(rest[8..16].try_into().unwrap()) as usize,
+            ),
+            size if size >= 8 => (8, size),
+            _ => return None,
+        };
+        if size < header || size > rest.len() {
+            return None;
+        }
+        if kind == b"mdat" {
+            mdat.push(&rest[header..size]);
+        }
+        rest = &rest[size..];
+    }
+    (!mdat.is_empty()).then_some(mdat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::audio_frames;
+
+    fn id3v2(tag: &[u8]) -> Vec<u8> {
+        let size = tag.len();
+        let mut bytes = b"ID3\x04\x00\x00".to_vec();
+        bytes.extend([
+            (size >> 21) as u8 & 0x7f,
+            (size >> 14) as u8 & 0x7f,
+            (size >> 7) as u8 & 0x7f,
+            size as u8 & 0x7f,
+        ]);
+        bytes.extend(tag);
+        bytes
+    }
+
+    fn id3v1(title: &str) -> Vec<u8> {
+        let mut bytes = b"TAG".to_vec();
+        bytes.extend(title.as_bytes());
+        bytes.resize(128, 0);
+        bytes
+    }
+
+    #[test]
+    fn mp3_tags_are_skipped() {
+        let frames = b"\xff\xfbframes".to_vec();
+        let tagged = [id3v2(b"TIT2 some title"), frames.clone(), id3v1("title")].concat();
+        let retagged = [id3v2(&[7; 300]), frames.clone(), id3v1("other title")].concat();
+
+        assert_eq!(audio_frames(&tagged).concat(), frames);
+        assert_eq!(audio_frames(&retagged).concat(), frames);
+    }
+
+    #[test]
+    fn flac_metadata_is_skipped() {
+        let flac = |comment: &[u8]| {
+            let mut bytes = b"fLaC".to_vec();
+            // streaminfo
+            bytes.extend([0, 0, 0, 2, 1, 2]);
+            // last block: vorbis comment
+            bytes.extend([0x84, 0, 0, comment.len() as u8]);
+            bytes.extend(comment);
+            bytes.extend(b"\xff\xf8frames");
+            bytes
+        };
+
+        assert_eq!(audio_frames(&flac(b"ARTIST=a")).concat(), b"\xff\xf8frames");
+        assert_eq!(
+            audio_frames(&flac(b"ARTIST=another")).concat(),
+            b"\xff\xf8frames"
+        );
+    }
+
+    #[test]
+    fn only_mp4_mdat_is_used() {
+        let atom = |kind: &[u8], body: &[u8]| {
+            let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            bytes.extend(kind);
+            bytes.extend(body);
+            bytes
+        };
+        let m4a = |meta: &[u8]| {
+            [
+                atom(b"ftyp", b"M4A "),
+                atom(b"moov", meta),
+                atom(b"mdat", b"frames"),
+            ]
+            .concat()
+        };
+
+        assert_eq!(audio_frames(&m4a(b"title")).concat(), b"frames");
+        assert_eq!(audio_frames(&m4a(b"other title")).concat(), b"frames");
+    }
+
+    #[test]
+    fn unknown_files_are_used_whole() {
+        assert_eq!(audio_frames(b"RIFF wave data").concat(), b"RIFF wave data");
+    }
+}
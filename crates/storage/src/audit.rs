@@ -0,0 +1,361 @@
+//! Log of library mutations, to find out when and how tracks were changed
+
+use std::{fmt::Display, str::FromStr, time::SystemTime};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use rusqlite::{OptionalExtension, Transaction, params};
+
+use crate::{
+    Storage,
+    db::{i64_seconds_to_local_time, system_time_to_i64},
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::{TrackId, TrackMetadata},
+};
+
+pub type AuditId = i64;
+
+/// Kind of a logged mutation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOperation {
+    /// new files were added by `update`
+    Update,
+    /// a file was linked to an existing track
+    AddFile,
+    /// files were removed from the database
+    Forget,
+    /// a file found at another path kept its row, see `update`
+    Move,
+    /// track metadata was inserted or changed
+    Metadata,
+    /// one track was merged into another
+    Merge,
+    /// lyrics of a track were stored
+    Lyrics,
+    /// dangling tracks were removed
+    Clean,
+    /// an `update`, `forget` or move was reverted
+    Undo,
+}
+
+impl AuditOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOperation::Update => "update",
+            AuditOperation::AddFile => "add",
+            AuditOperation::Forget => "forget",
+            AuditOperation::Move => "move",
+            AuditOperation::Metadata => "metadata",
+            AuditOperation::Merge => "merge",
+            AuditOperation::Lyrics => "lyrics",
+            AuditOperation::Clean => "clean",
+            AuditOperation::Undo => "undo",
+        }
+    }
+}
+
+impl Display for AuditOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AuditOperation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            AuditOperation::Update,
+            AuditOperation::AddFile,
+            AuditOperation::Forget,
+            AuditOperation::Move,
+            AuditOperation::Metadata,
+            AuditOperation::Merge,
+            AuditOperation::Lyrics,
+            AuditOperation::Clean,
+            AuditOperation::Undo,
+        ]
+        .into_iter()
+        .find(|op| op.as_str() == s)
+        .ok_or(anyhow!("unknown audit operation '{s}'"))
+    }
+}
+
+/// How a mutation changed a file row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileChange {
+    Added,
+    Removed,
+}
+
+impl FileChange {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FileChange::Added => "added",
+            FileChange::Removed => "removed",
+        }
+    }
+}
+
+/// One logged mutation
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub id: AuditId,
+    pub logged_at: DateTime<Local>,
+    pub operation: AuditOperation,
+    /// human readable description, e.g. the new metadata values
+    pub details: String,
+    pub tracks: Vec<TrackId>,
+}
+
+/// Adds an entry to the audit log as part of the mutating transaction
+pub(crate) fn record(
+    tx: &Transaction,
+    operation: AuditOperation,
+    details: &str,
+    tracks: impl IntoIterator<Item = TrackId>,
+) -> Result<AuditId, StorageError> {
+    let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+    tx.execute(
+        &format!(
+            "INSERT INTO {AUDIT_LOG} ({LOGGED_AT}, {OPERATION}, {DETAILS}) VALUES (?1, ?2, ?3)"
+        ),
+        params![now, operation.as_str(), details],
+    )?;
+    let id = tx.last_insert_rowid();
+    add_tracks(tx, id, tracks)?;
+    Ok(id)
+}
+
+/// Attaches more affected tracks to an existing entry
+pub(crate) fn add_tracks(
+    tx: &Transaction,
+    id: AuditId,
+    tracks: impl IntoIterator<Item = TrackId>,
+) -> Result<(), StorageError> {
+    let mut stmt = tx.prepare_cached(&format!(
+        "INSERT INTO {AUDIT_TRACKS} ({AUDIT_ID}, {TRACK_ID}) VALUES (?1, ?2)"
+    ))?;
+    for track in tracks {
+        stmt.execute(params![id, track])?;
+    }
+    Ok(())
+}
+
+/// Keeps a copy of the file rows matching `condition` (SQL over the files table),
+/// must be called before removing and after adding them
+pub(crate) fn record_files(
+    tx: &Transaction,
+    id: AuditId,
+    change: FileChange,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> Result<(), StorageError> {
+    tx.execute(
+        &format!(
+            "INSERT INTO {AUDIT_FILES}
+                ({AUDIT_ID}, {CHANGE}, {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT})
+            SELECT {id}, '{}', {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT}
+            FROM {FILES} WHERE {condition}",
+            change.as_str()
+        ),
+        params,
+    )?;
+    Ok(())
+}
+
+/// Short description of metadata values for the log
+pub(crate) fn describe_metadata(meta: &TrackMetadata) -> String {
+    let mut details = format!("{} - {}", meta.artist, meta.title);
+    if let Some(year) = meta.year {
+        details.push_str(&format!(", year: {year}"));
+    }
+    if let Some(label) = &meta.label {
+        details.push_str(&format!(", label: {label}"));
+    }
+    if let Some(artwork) = &meta.artwork {
+        details.push_str(&format!(", artwork: {}", artwork.0));
+    }
+    details
+}
+
+/// Result of [`Storage::undo_last`]
+#[derive(Debug, Clone)]
+pub struct UndoReport {
+    /// entry that was reverted
+    pub undone: AuditEntry,
+    /// file rows removed again, for undone updates and moves
+    pub removed_files: usize,
+    /// file rows put back, for undone forgets and moves
+    pub restored_files: usize,
+    /// tracks created by the undone update which were left without files and metadata
+    pub removed_tracks: usize,
+}
+
+impl Storage {
+    /// Returns the latest `limit` logged mutations, newest first.
+    ///
+    /// If `track` is set, returns only mutations affecting that track.
+    pub fn audit_log(
+        &mut self,
+        track: Option<TrackId>,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, StorageError> {
+        self.audit_entries(
+            &format!(
+                "?1 IS NULL OR {AUDIT_ID} IN
+                    (SELECT {AUDIT_ID} FROM {AUDIT_TRACKS} WHERE {TRACK_ID} = ?1)
+                ORDER BY {AUDIT_ID} DESC LIMIT ?2"
+            ),
+            params![track, limit as i64],
+        )
+    }
+
+    /// Loads entries matching the SQL `condition` over the audit log table
+    fn audit_entries(
+        &self,
+        condition: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<AuditEntry>, StorageError> {
+        let rows = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {AUDIT_ID}, {LOGGED_AT}, {OPERATION}, {DETAILS} FROM {AUDIT_LOG}
+                WHERE {condition}"
+            ))?;
+            stmt.query_map(params, |row| {
+                Ok((
+                    row.get::<_, AuditId>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut tracks_stmt = self.db.prepare(&format!(
+            "SELECT {TRACK_ID} FROM {AUDIT_TRACKS} WHERE {AUDIT_ID} = ?1 ORDER BY rowid"
+        ))?;
+        rows.into_iter()
+            .map(|(id, logged_at, operation, details)| {
+                Ok(AuditEntry {
+                    id,
+                    logged_at: i64_seconds_to_local_time(logged_at)
+                        .map_err(StorageError::Internal)?,
+                    operation: operation.parse().map_err(StorageError::Internal)?,
+                    details,
+                    tracks: tracks_stmt
+                        .query_map(params![id], |row| row.get(0))?
+                        .collect::<Result<Vec<_>, _>>()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Reverts the file changes of the most recent `update`, `forget` or move in a single transaction.
+    ///
+    /// Returns `None` if there is nothing to undo. Every operation can be undone once.
+    pub fn undo_last(&mut self) -> Result<Option<UndoReport>, StorageError> {
+        let target: Option<AuditId> = self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT {AUDIT_ID} FROM {AUDIT_LOG}
+                    WHERE {OPERATION} IN (?1, ?2, ?3)
+                      AND {AUDIT_ID} IN (SELECT {AUDIT_ID} FROM {AUDIT_FILES})
+                    ORDER BY {AUDIT_ID} DESC LIMIT 1"
+                ),
+                params![
+                    AuditOperation::Update.as_str(),
+                    AuditOperation::Forget.as_str(),
+                    AuditOperation::Move.as_str()
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(id) = target else {
+            return Ok(None);
+        };
+        let undone = self
+            .audit_entries(&format!("{AUDIT_ID} = ?1"), params![id])?
+            .pop()
+            .ok_or(StorageError::Internal(anyhow!(
+                "audit entry #{id} not found"
+            )))?;
+        let operation = undone.operation;
+        let tx = self.db.transaction()?;
+
+        let tracks: Vec<TrackId> = tx
+            .prepare(&format!(
+                "SELECT DISTINCT {TRACK_ID} FROM {AUDIT_FILES} WHERE {AUDIT_ID} = ?1"
+            ))?
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        // rows added by the update are removed again
+        let removed_files = tx.execute(
+            &format!(
+                "DELETE FROM {FILES} WHERE EXISTS (
+                    SELECT 1 FROM {AUDIT_FILES} a
+                    WHERE a.{AUDIT_ID} = ?1 AND a.{CHANGE} = ?2
+                      AND a.{USB_LABEL} = {FILES}.{USB_LABEL} AND a.{PATH} = {FILES}.{PATH}
+                      AND a.{TRACK_ID} = {FILES}.{TRACK_ID}
+                )"
+            ),
+            params![id, FileChange::Added.as_str()],
+        )?;
+
+        // rows removed by forget are put back, recreating their tracks if they were cleaned since
+        tx.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {TRACKS} ({TRACK_ID})
+                SELECT DISTINCT {TRACK_ID} FROM {AUDIT_FILES} WHERE {AUDIT_ID} = ?1 AND {CHANGE} = ?2"
+            ),
+            params![id, FileChange::Removed.as_str()],
+        )?;
+        let restored_files = tx.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {FILES} ({USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT})
+                SELECT {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT}
+                FROM {AUDIT_FILES} WHERE {AUDIT_ID} = ?1 AND {CHANGE} = ?2"
+            ),
+            params![id, FileChange::Removed.as_str()],
+        )?;
+
+        // tracks which the update created are removed if nothing refers to them anymore
+        let mut removed_tracks = 0;
+        if operation == AuditOperation::Update {
+            let mut stmt = tx.prepare(&format!(
+                "DELETE FROM {TRACKS} WHERE {TRACK_ID} = ?1
+                  AND NOT EXISTS (SELECT 1 FROM {FILES} WHERE {TRACK_ID} = ?1)
+                  AND NOT EXISTS (SELECT 1 FROM {TRACK_METADATA} WHERE {TRACK_ID} = ?1)
+                  AND NOT EXISTS (SELECT 1 FROM {CARD_MAPPINGS} WHERE {TRACK_ID} = ?1)"
+            ))?;
+            for track in &tracks {
+                removed_tracks += stmt.execute(params![track])?;
+            }
+        }
+
+        // the file copies are dropped, so the same operation is not undone twice
+        tx.execute(
+            &format!("DELETE FROM {AUDIT_FILES} WHERE {AUDIT_ID} = ?1"),
+            params![id],
+        )?;
+        Storage::insert_update_time(&tx)?;
+        record(
+            &tx,
+            AuditOperation::Undo,
+            &format!("undid #{id} ({operation})"),
+            tracks,
+        )?;
+        tx.commit()?;
+
+        Ok(Some(UndoReport {
+            undone,
+            removed_files,
+            restored_files,
+            removed_tracks,
+        }))
+    }
+}
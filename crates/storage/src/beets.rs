@@ -0,0 +1,199 @@
+//! Metadata from a [beets](https://beets.io) library, with `localdeck import beets`.
+//!
+//! Beets keeps its items in an SQLite database, with the path of each file and the tags
+//! it fixed up from MusicBrainz. An item belongs to the track with a file at its path, or,
+//! for files recorded at another path, to the track with a file of the same content.
+//!
+//! Artist, title, year and label go into the track's metadata, the cover of the item's
+//! album becomes its artwork. Album and genre have no field here, albums are the folders
+//! holding the files, see [`crate::album`].
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params, types::ValueRef};
+
+use crate::{
+    Storage,
+    audit::{self, AuditOperation},
+    error::StorageError,
+    file_hash::FileHash,
+    operations::{LocationRow, MetadataUpdate},
+    provenance::{self, MetadataOrigin},
+    schema::{columns::*, tables::*},
+    track::{ArtworkRef, TrackId},
+};
+
+/// Result of [`Storage::import_beets`]
+#[derive(Debug, Default)]
+pub struct BeetsImportReport {
+    /// items in the beets library
+    pub items: usize,
+    /// items without a track here, by their path
+    pub unmatched: Vec<PathBuf>,
+    /// tracks whose metadata changed
+    pub updated: Vec<TrackId>,
+    /// tracks without metadata yet, whose item has no title or artist
+    pub missing_required: Vec<TrackId>,
+}
+
+#[derive(Debug)]
+struct BeetsItem {
+    path: PathBuf,
+    artist: String,
+    title: String,
+    year: i64,
+    label: String,
+    artpath: Option<PathBuf>,
+}
+
+/// Paths are stored as bytes by beets, and as text by older versions
+fn path_column(value: ValueRef<'_>) -> Option<PathBuf> {
+    let bytes = match value {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes,
+        _ => return None,
+    };
+    Some(PathBuf::from(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// Beets leaves unknown fields empty or zero
+fn known(value: String) -> Option<String> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+fn read_items(library: &Path) -> Result<Vec<BeetsItem>, StorageError> {
+    let beets = Connection::open_with_flags(library, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = beets.prepare(
+        "SELECT items.path, items.artist, items.title, items.year, items.label, albums.artpath
+        FROM items LEFT JOIN albums ON albums.id = items.album_id
+        ORDER BY items.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(BeetsItem {
+            path: path_column(row.get_ref(0)?).unwrap_or_default(),
+            artist: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+            title: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            year: row.get::<_, Option<i64>>(3)?.unwrap_or_default(),
+            label: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+            artpath: path_column(row.get_ref(5)?),
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+impl Storage {
+    /// Track of a beets item: the one with a file at its path, else the one with a file of
+    /// the same content, if the item's file is there
+    fn beets_item_track(&mut self, path: &Path) -> Result<Option<TrackId>, StorageError> {
+        if let Ok(loc) = self.fs.reverse_resolve(path) {
+            let row = LocationRow::from_location(loc)?;
+            let track = self
+                .db
+                .query_row(
+                    &format!(
+                        "SELECT {TRACK_ID} FROM {FILES} WHERE {USB_LABEL} = ?1 AND {PATH} = ?2"
+                    ),
+                    params![row.usb_label, row.path],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if track.is_some() {
+                return Ok(track);
+            }
+        }
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let kind = self.fs.hash_kind();
+        let hash = FileHash::from_file_with(path, kind)?;
+        Ok(self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT {TRACK_ID} FROM {FILES} WHERE {FILE_HASH} = ?1 AND {HASH_KIND} = ?2
+                    LIMIT 1"
+                ),
+                params![hash.to_string(), kind.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Imports the metadata of the items of the beets library at `library`.
+    ///
+    /// Without `allow_overwrite` only fields the tracks don't have yet are set, with it the
+    /// values of beets replace them. All tracks are updated in one transaction
+    pub fn import_beets(
+        &mut self,
+        library: &Path,
+        allow_overwrite: bool,
+    ) -> Result<BeetsImportReport, StorageError> {
+        let items = read_items(library)?;
+        let mut report = BeetsImportReport {
+            items: items.len(),
+            ..Default::default()
+        };
+        let mut matched = vec![];
+        for item in items {
+            match self.beets_item_track(&item.path)? {
+                Some(track) => matched.push((track, item)),
+                None => report.unmatched.push(item.path),
+            }
+        }
+
+        let tx = self.db.transaction()?;
+        for (track_id, item) in matched {
+            let current_meta = Self::load_metadata(&tx, track_id)?;
+            let year = u32::try_from(item.year).ok().filter(|year| *year > 0);
+            let label = known(item.label);
+            let artwork = item
+                .artpath
+                .map(|path| ArtworkRef(path.to_string_lossy().to_string()));
+            let update = match &current_meta {
+                Some(current) if !allow_overwrite => MetadataUpdate {
+                    artist: None,
+                    title: None,
+                    year: year.filter(|_| current.year.is_none()),
+                    label: label.filter(|_| current.label.is_none()),
+                    artwork: artwork.filter(|_| current.artwork.is_none()),
+                },
+                _ => MetadataUpdate {
+                    artist: known(item.artist),
+                    title: known(item.title),
+                    year,
+                    label,
+                    artwork,
+                },
+            };
+            let merged =
+                match Self::update_meta(track_id, current_meta.clone(), update, allow_overwrite) {
+                    Ok(merged) => merged,
+                    Err(StorageError::RequiredMetaMissing(_)) => {
+                        report.missing_required.push(track_id);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+            if current_meta.as_ref() != Some(&merged) {
+                let changed = provenance::changed_fields(current_meta.as_ref(), &merged);
+                Self::store_metadata(&tx, track_id, merged)?;
+                provenance::record_sources(&tx, track_id, &changed, MetadataOrigin::Manual)?;
+                if !report.updated.contains(&track_id) {
+                    report.updated.push(track_id);
+                }
+            }
+        }
+
+        if !report.updated.is_empty() {
+            Self::insert_update_time(&tx)?;
+            audit::record(
+                &tx,
+                AuditOperation::Metadata,
+                &format!("beets {}", library.to_string_lossy()),
+                report.updated.iter().copied(),
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(report)
+    }
+}
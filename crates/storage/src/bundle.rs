@@ -0,0 +1,297 @@
+//! Moving a whole library to another machine with `localdeck bundle`.
+//!
+//! A bundle is a copy of the database with a manifest listing the library roots and
+//! the recorded files relative to them, plus the local artwork files.
+//! On import, files of each old root are moved below the matching root of the new machine.
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Storage,
+    db::{self, system_time_to_i64},
+    error::StorageError,
+    location::{LOCATION_PATH_SEP, Location, replace_windows_slashes},
+    schema::{self, columns::*, tables::*},
+    track::TrackId,
+};
+
+/// Version of the bundle layout, increased on incompatible changes
+pub const BUNDLE_FORMAT: u32 = 1;
+/// Directory of the bundle holding artwork files
+pub const ARTWORK_DIR: &str = "artwork";
+
+/// Contents of a bundle, stored next to the database copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: u32,
+    /// seconds since the unix epoch
+    pub created_at: i64,
+    /// library roots on the exporting machine, in config order, only plain directories are included
+    pub roots: Vec<String>,
+    /// recorded files below `roots`
+    pub files: Vec<BundleFile>,
+    /// local artwork copied into the bundle
+    pub artwork: Vec<BundleArtwork>,
+    pub playlists: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleFile {
+    /// index in [`BundleManifest::roots`]
+    pub root: usize,
+    /// path relative to the root, `/` separated
+    pub path: String,
+    pub track_id: TrackId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleArtwork {
+    pub track_id: TrackId,
+    /// path inside the bundle, e.g. `artwork/12.jpg`
+    pub path: String,
+    /// file on the exporting machine
+    #[serde(skip)]
+    pub source: PathBuf,
+}
+
+/// Result of [`Storage::import_bundle`]
+#[derive(Debug, Default)]
+pub struct BundleImportReport {
+    pub tracks: usize,
+    /// files moved below the roots of this machine
+    pub relocated_files: usize,
+    /// relocated files which do not exist (yet) on this machine
+    pub missing_files: Vec<Location>,
+}
+
+/// Absolute path of a file root as stored in the files table
+fn root_prefix(root: &Path) -> String {
+    replace_windows_slashes(root)
+        .trim_end_matches(LOCATION_PATH_SEP)
+        .to_string()
+}
+
+/// Path relative to `root`, `None` if the path is not below it
+fn strip_root<'a>(path: &'a str, root: &str) -> Option<&'a str> {
+    path.strip_prefix(root)?.strip_prefix(LOCATION_PATH_SEP)
+}
+
+impl Storage {
+    /// File of the database, `None` for in-memory databases
+    pub fn database_path(&self) -> Option<PathBuf> {
+        self.db
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Writes a copy of the database to `dest`, which must not exist, with local artwork
+    /// pointing into the bundle. The returned manifest lists what goes into the bundle
+    pub fn export_bundle(&mut self, dest: &Path) -> Result<BundleManifest, StorageError> {
+        db::copy_to(&self.db, dest)?;
+        let copy = Connection::open(dest)?;
+
+        let roots: Vec<String> = self
+            .fs
+            .roots()
+            .iter()
+            .filter_map(|root| match &root.location {
+                Location::File { path } => Some(root_prefix(path)),
+                Location::Usb { .. }
+                | Location::NetworkShare { .. }
+                | Location::S3 { .. }
+                | Location::Remote { .. } => None,
+            })
+            .collect();
+
+        let mut files = vec![];
+        let mut stmt = copy.prepare(&format!(
+            "SELECT {PATH}, {TRACK_ID} FROM {FILES} WHERE {USB_LABEL} = '' ORDER BY {PATH}"
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (path, track_id) = row?;
+            let found = roots
+                .iter()
+                .enumerate()
+                .find_map(|(i, root)| Some((i, strip_root(&path, root)?)));
+            if let Some((root, relative)) = found {
+                files.push(BundleFile {
+                    root,
+                    path: relative.to_string(),
+                    track_id,
+                });
+            }
+        }
+        drop(stmt);
+
+        let mut artwork = vec![];
+        let mut stmt = copy.prepare(&format!(
+            "SELECT {TRACK_ID}, {ARTWORK_URL} FROM {TRACK_METADATA} WHERE {ARTWORK_URL} IS NOT NULL"
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, TrackId>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (track_id, url) = row?;
+            let source = PathBuf::from(&url);
+            // remote artwork stays a link
+            if url.contains("://") || !source.is_file() {
+                continue;
+            }
+            let path = match source.extension() {
+                Some(ext) => format!("{ARTWORK_DIR}/{track_id}.{}", ext.to_string_lossy()),
+                None => format!("{ARTWORK_DIR}/{track_id}"),
+            };
+            artwork.push(BundleArtwork {
+                track_id,
+                path,
+                source,
+            });
+        }
+        drop(stmt);
+        for art in &artwork {
+            copy.execute(
+                &format!("UPDATE {TRACK_METADATA} SET {ARTWORK_URL} = ?1 WHERE {TRACK_ID} = ?2"),
+                params![art.path, art.track_id],
+            )?;
+        }
+
+        let playlists = {
+            let mut stmt =
+                copy.prepare(&format!("SELECT {NAME} FROM {PLAYLISTS} ORDER BY {NAME}"))?;
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?
+        };
+
+        Ok(BundleManifest {
+            format: BUNDLE_FORMAT,
+            created_at: system_time_to_i64(std::time::SystemTime::now())
+                .map_err(StorageError::Internal)?,
+            roots,
+            files,
+            artwork,
+            playlists,
+        })
+    }
+
+    /// Fills this library, which must be empty, from the database of a bundle.
+    ///
+    /// `roots` gives the new path of each root of [`BundleManifest::roots`].
+    /// Artwork paths are made absolute below `artwork_dir`, where the artwork of the bundle
+    /// was extracted to
+    pub fn import_bundle(
+        &mut self,
+        bundle_db: &Path,
+        manifest: &BundleManifest,
+        roots: &[PathBuf],
+        artwork_dir: &Path,
+    ) -> Result<BundleImportReport, StorageError> {
+        if roots.len() != manifest.roots.len() {
+            return Err(StorageError::Internal(anyhow::anyhow!(
+                "bundle has {} library roots, {} new roots given",
+                manifest.roots.len(),
+                roots.len()
+            )));
+        }
+        let tracks: i64 =
+            self.db
+                .query_row(&format!("SELECT COUNT(*) FROM {TRACKS}"), [], |row| {
+                    row.get(0)
+                })?;
+        if tracks > 0 {
+            return Err(StorageError::Internal(anyhow::anyhow!(
+                "the library already has {tracks} tracks, bundles are imported into empty libraries only"
+            )));
+        }
+        // bundles of older versions miss newer tables and columns
+        schema::init(&Connection::open(bundle_db)?)?;
+
+        self.db.execute(
+            "ATTACH DATABASE ?1 AS bundle",
+            params![bundle_db.to_string_lossy()],
+        )?;
+        let result = self.copy_bundle(manifest, roots, artwork_dir);
+        self.db.execute("DETACH DATABASE bundle", [])?;
+        result
+    }
+
+    fn copy_bundle(
+        &mut self,
+        manifest: &BundleManifest,
+        roots: &[PathBuf],
+        artwork_dir: &Path,
+    ) -> Result<BundleImportReport, StorageError> {
+        let tx = self.db.transaction()?;
+        // parents are listed before the tables referencing them
+        for table in ALL_TABLES {
+            let columns = {
+                let mut stmt = tx.prepare(&format!("PRAGMA bundle.table_info({table})"))?;
+                stmt.query_map([], |row| row.get::<_, String>(1))?
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(", ")
+            };
+            tx.execute(
+                &format!(
+                    "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM bundle.{table}"
+                ),
+                [],
+            )?;
+        }
+
+        let mut report = BundleImportReport {
+            tracks: tx.query_row(&format!("SELECT COUNT(*) FROM {TRACKS}"), [], |row| {
+                row.get::<_, i64>(0)
+            })? as usize,
+            ..Default::default()
+        };
+        let new_roots: Vec<String> = roots.iter().map(|root| root_prefix(root)).collect();
+        let relocate = |path: &str| {
+            manifest
+                .roots
+                .iter()
+                .zip(&new_roots)
+                .find_map(|(old, new)| Some(format!("{new}/{}", strip_root(path, old)?)))
+        };
+        for table in [FILES, SNAPSHOT_FILES, AUDIT_FILES] {
+            let paths = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT DISTINCT {PATH} FROM {table} WHERE {USB_LABEL} = ''"
+                ))?;
+                stmt.query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            let mut update = tx.prepare(&format!(
+                "UPDATE {table} SET {PATH} = ?1 WHERE {USB_LABEL} = '' AND {PATH} = ?2"
+            ))?;
+            for path in paths {
+                let Some(new_path) = relocate(&path) else {
+                    continue;
+                };
+                update.execute(params![new_path, path])?;
+                if table == FILES {
+                    report.relocated_files += 1;
+                    if !Path::new(&new_path).exists() {
+                        report.missing_files.push(Location::from_path(&new_path));
+                    }
+                }
+            }
+        }
+
+        for art in &manifest.artwork {
+            let Some(relative) = art.path.strip_prefix(&format!("{ARTWORK_DIR}/")) else {
+                continue;
+            };
+            tx.execute(
+                &format!("UPDATE {TRACK_METADATA} SET {ARTWORK_URL} = ?1 WHERE {TRACK_ID} = ?2"),
+                params![artwork_dir.join(relative).to_string_lossy(), art.track_id],
+            )?;
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(report)
+    }
+}
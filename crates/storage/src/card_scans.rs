@@ -0,0 +1,219 @@
+//! Log of `/play` requests, i.e. of printed cards being scanned.
+//!
+//! Each scan keeps the card id, the track it resolved to and how the track was served,
+//! so it shows which cards get played and which ones stopped working.
+
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use rusqlite::params;
+
+use crate::{
+    CardId, Storage,
+    db::i64_seconds_to_local_time,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+/// How a scan was answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOutcome {
+    /// streamed from a file of this machine
+    Local,
+    /// proxied from another localdeck instance or a bucket
+    Remote,
+    /// the card is not mapped to a track, or the track has no available file
+    NotFound,
+    /// streaming failed for another reason
+    Failed,
+}
+
+impl ScanOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanOutcome::Local => "local",
+            ScanOutcome::Remote => "remote",
+            ScanOutcome::NotFound => "not_found",
+            ScanOutcome::Failed => "failed",
+        }
+    }
+}
+
+impl Display for ScanOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ScanOutcome {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ScanOutcome::Local,
+            ScanOutcome::Remote,
+            ScanOutcome::NotFound,
+            ScanOutcome::Failed,
+        ]
+        .into_iter()
+        .find(|outcome| outcome.as_str() == s)
+        .ok_or(anyhow!("unknown scan outcome '{s}'"))
+    }
+}
+
+/// One `/play` request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardScan {
+    pub scanned_at: DateTime<Local>,
+    /// id printed on the card, a card alias or a plain track id
+    pub card_id: CardId,
+    /// `None` if the card id did not resolve
+    pub track_id: Option<TrackId>,
+    pub outcome: ScanOutcome,
+    pub user_agent: Option<String>,
+}
+
+/// Scans of one card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardScanStats {
+    pub card_id: CardId,
+    /// track of the latest scan
+    pub track_id: Option<TrackId>,
+    pub scans: usize,
+    pub local: usize,
+    pub remote: usize,
+    pub not_found: usize,
+    pub failed: usize,
+    pub last_scan: DateTime<Local>,
+}
+
+/// Scan counts per card, most scanned cards first
+pub fn summarize(scans: &[CardScan]) -> Vec<CardScanStats> {
+    let mut by_card: HashMap<&str, CardScanStats> = HashMap::new();
+    for scan in scans {
+        let stats = by_card
+            .entry(&scan.card_id)
+            .or_insert_with(|| CardScanStats {
+                card_id: scan.card_id.clone(),
+                track_id: scan.track_id,
+                scans: 0,
+                local: 0,
+                remote: 0,
+                not_found: 0,
+                failed: 0,
+                last_scan: scan.scanned_at,
+            });
+        stats.scans += 1;
+        match scan.outcome {
+            ScanOutcome::Local => stats.local += 1,
+            ScanOutcome::Remote => stats.remote += 1,
+            ScanOutcome::NotFound => stats.not_found += 1,
+            ScanOutcome::Failed => stats.failed += 1,
+        }
+        if scan.scanned_at >= stats.last_scan {
+            stats.last_scan = scan.scanned_at;
+            stats.track_id = scan.track_id;
+        }
+    }
+    let mut stats: Vec<_> = by_card.into_values().collect();
+    stats.sort_by(|a, b| {
+        b.scans
+            .cmp(&a.scans)
+            .then_with(|| a.card_id.cmp(&b.card_id))
+    });
+    stats
+}
+
+impl Storage {
+    /// Appends a scan to the log. Scans are not library changes, `updated_at` stays the same
+    pub fn record_card_scan(&mut self, scan: &CardScan) -> Result<(), StorageError> {
+        self.log_changes += self.db.execute(
+            &format!(
+                "INSERT INTO {CARD_SCANS} ({SCANNED_AT}, {CARD_ID}, {TRACK_ID}, {OUTCOME}, {USER_AGENT})
+                VALUES (?1, ?2, ?3, ?4, ?5)"
+            ),
+            params![
+                scan.scanned_at.timestamp(),
+                scan.card_id,
+                scan.track_id,
+                scan.outcome.as_str(),
+                scan.user_agent
+            ],
+        )? as u64;
+        Ok(())
+    }
+
+    /// Logged scans, optionally only those made since a time, newest first
+    pub fn list_card_scans(
+        &mut self,
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<CardScan>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT {SCANNED_AT}, {CARD_ID}, {TRACK_ID}, {OUTCOME}, {USER_AGENT} FROM {CARD_SCANS}
+            WHERE {SCANNED_AT} >= ?1 ORDER BY {SCANNED_AT} DESC, rowid DESC"
+        ))?;
+        let since = since.map_or(i64::MIN, |since| since.timestamp());
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(scanned_at, card_id, track_id, outcome, user_agent)| {
+                Ok(CardScan {
+                    scanned_at: i64_seconds_to_local_time(scanned_at)
+                        .map_err(StorageError::Internal)?,
+                    card_id,
+                    track_id,
+                    outcome: outcome.parse().map_err(StorageError::Internal)?,
+                    user_agent,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn scan(card_id: &str, secs: i64, outcome: ScanOutcome) -> CardScan {
+        CardScan {
+            scanned_at: Local.timestamp_opt(secs, 0).unwrap(),
+            card_id: card_id.to_string(),
+            track_id: Some(1),
+            outcome,
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn scans_are_counted_per_card() {
+        let mut moved = scan("b", 30, ScanOutcome::Remote);
+        moved.track_id = Some(2);
+        let stats = summarize(&[
+            scan("a", 10, ScanOutcome::Local),
+            scan("b", 20, ScanOutcome::Local),
+            moved,
+            scan("b", 5, ScanOutcome::NotFound),
+        ]);
+
+        assert_eq!(stats.len(), 2);
+        let b = &stats[0];
+        assert_eq!(b.card_id, "b");
+        assert_eq!((b.scans, b.local, b.remote, b.not_found), (3, 1, 1, 1));
+        assert_eq!(b.track_id, Some(2));
+        assert_eq!(b.last_scan.timestamp(), 30);
+        assert_eq!(stats[1].card_id, "a");
+    }
+}
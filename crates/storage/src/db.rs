@@ -11,7 +11,10 @@ use crate::{error::StorageError, schema};
 
 pub enum DBConfig {
     InMemory,
-    OnDisk { location: PathBuf },
+    OnDisk {
+        location: PathBuf,
+        encryption_key: Option<String>,
+    },
 }
 
 pub type SecondsSinceUnix = i64;
@@ -24,16 +27,63 @@ fn open_from_file(path: &Path) -> Result<rusqlite::Connection, rusqlite::Error>
     Connection::open(path)
 }
 
+/// Unlocks an SQLCipher database, the key must be set before anything else is read
+#[cfg(feature = "sqlcipher")]
+fn apply_key(db: &Connection, key: &str) -> Result<(), StorageError> {
+    db.pragma_update(None, "key", key)?;
+    // a wrong key only shows on the first read
+    db.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .map_err(|e| {
+            StorageError::Internal(anyhow!(
+                "can't open the database with the configured encryption_key, \
+                 either the key is wrong or the database is not encrypted: {e}"
+            ))
+        })
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+fn apply_key(_db: &Connection, _key: &str) -> Result<(), StorageError> {
+    Err(StorageError::Internal(anyhow!(
+        "encryption_key is set, but localdeck was built without the sqlcipher feature"
+    )))
+}
+
 pub fn open(config: DBConfig) -> Result<rusqlite::Connection, StorageError> {
     let db = match config {
         DBConfig::InMemory => open_in_memory()?,
-        DBConfig::OnDisk { location } => open_from_file(&location)?,
+        DBConfig::OnDisk {
+            location,
+            encryption_key,
+        } => {
+            let db = open_from_file(&location)?;
+            if let Some(key) = encryption_key {
+                apply_key(&db, &key)?;
+            }
+            db
+        }
     };
     db.pragma_update(None, "foreign_keys", true)?;
     schema::init(&db)?;
     Ok(db)
 }
 
+/// Writes an unencrypted copy of the database to `dest`, which must not exist
+pub(crate) fn copy_to(db: &Connection, dest: &Path) -> Result<(), StorageError> {
+    #[cfg(feature = "sqlcipher")]
+    {
+        // VACUUM INTO would keep the encryption of the database
+        db.execute(
+            "ATTACH DATABASE ?1 AS plain KEY ''",
+            rusqlite::params![dest.to_string_lossy()],
+        )?;
+        db.query_row("SELECT sqlcipher_export('plain')", [], |_| Ok(()))?;
+        db.execute("DETACH DATABASE plain", [])?;
+    }
+    #[cfg(not(feature = "sqlcipher"))]
+    db.execute("VACUUM INTO ?1", rusqlite::params![dest.to_string_lossy()])?;
+    Ok(())
+}
+
 /// converts time to number of seconds since unix_epoch
 pub fn system_time_to_i64(time: SystemTime) -> anyhow::Result<SecondsSinceUnix> {
     i64::try_from(
@@ -78,4 +128,69 @@ mod tests {
             assert!(tables.contains(&table.to_string()));
         }
     }
+
+    #[test]
+    fn init_adds_new_columns_to_old_db() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE files (
+                usb_label TEXT NOT NULL,
+                path TEXT NOT NULL,
+                track_id INTEGER NOT NULL,
+                file_size INTEGER NOT NULL,
+                file_hash TEXT NOT NULL,
+                PRIMARY KEY (usb_label, path)
+            );",
+        )
+        .unwrap();
+
+        schema::init(&db).unwrap();
+        // running it again on an up to date database is a no-op
+        schema::init(&db).unwrap();
+
+        for column in ["last_seen", "hash_kind"] {
+            let exists = db
+                .prepare("SELECT 1 FROM pragma_table_info('files') WHERE name = ?1")
+                .unwrap()
+                .exists([column])
+                .unwrap();
+            assert!(exists, "{column} was not added");
+        }
+    }
+
+    fn on_disk(location: &std::path::Path, key: Option<&str>) -> DBConfig {
+        DBConfig::OnDisk {
+            location: location.to_path_buf(),
+            encryption_key: key.map(str::to_string),
+        }
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn encryption_key_needs_sqlcipher() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("library.db");
+        assert!(open(on_disk(&db, Some("secret"))).is_err());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypted_db_opens_with_its_key_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("library.db");
+        let db = open(on_disk(&path, Some("secret"))).unwrap();
+
+        assert!(open(on_disk(&path, Some("wrong"))).is_err());
+        assert!(open(on_disk(&path, None)).is_err());
+        open(on_disk(&path, Some("secret"))).unwrap();
+
+        // copies for bundles are readable anywhere
+        let copy = dir.path().join("copy.db");
+        crate::db::copy_to(&db, &copy).unwrap();
+        let tables: i64 = rusqlite::Connection::open(&copy)
+            .unwrap()
+            .query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get(0))
+            .unwrap();
+        assert!(tables > 0);
+    }
 }
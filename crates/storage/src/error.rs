@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::{location::Location, track::TrackId};
+use crate::{location::Location, search::QueryError, track::TrackId};
 
 #[derive(Debug, Error)]
 pub enum StorageError {
@@ -31,4 +31,16 @@ pub enum StorageError {
 
     #[error("The path '{0}' is outside of all configured library directories and USB roots.")]
     PathOutsideLibrary(std::path::PathBuf),
+
+    #[error("snapshot {0} not found")]
+    SnapshotNotFound(i64),
+
+    #[error("playlist {0} not found")]
+    PlaylistNotFound(String),
+
+    #[error("playlist {0} already exists")]
+    PlaylistExists(String),
+
+    #[error(transparent)]
+    InvalidQuery(#[from] QueryError),
 }
@@ -0,0 +1,73 @@
+//! Tracks marked with a heart, a quicker way to keep loved tracks than a playlist.
+
+use std::time::SystemTime;
+
+use rusqlite::{OptionalExtension, params};
+
+use crate::{
+    Storage,
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+impl Storage {
+    pub fn is_favourite(&mut self, track_id: TrackId) -> Result<bool, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT 1 FROM {FAVOURITES} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Marks or unmarks the track, marking it again keeps the time it was marked first
+    pub fn set_favourite(
+        &mut self,
+        track_id: TrackId,
+        favourite: bool,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        let exists = tx
+            .query_row(
+                &format!("SELECT 1 FROM {TRACKS} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if exists.is_none() {
+            return Err(StorageError::TrackNotFound(track_id.to_string()));
+        }
+        if favourite {
+            tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO {FAVOURITES} ({TRACK_ID}, {CREATED_AT}) VALUES (?1, ?2)"
+                ),
+                params![track_id, now],
+            )?;
+        } else {
+            tx.execute(
+                &format!("DELETE FROM {FAVOURITES} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+            )?;
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Favourite tracks, the most recently marked first
+    pub fn list_favourites(&mut self) -> Result<Vec<TrackId>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT {TRACK_ID} FROM {FAVOURITES} ORDER BY {CREATED_AT} DESC, rowid DESC"
+        ))?;
+        Ok(stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?)
+    }
+}
@@ -1,6 +1,14 @@
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
 
 use blake3::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::audio_frames::audio_frames;
 
 /// Represents file hash.
 ///
@@ -9,6 +17,49 @@ use blake3::Hash;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileHash(pub Hash);
 
+/// Bytes read from each end of a file by [`FileHash::quick_from_file`]
+pub const QUICK_HASH_CHUNK: u64 = 1024 * 1024;
+
+/// How a recorded file hash was computed, selected by `hash_kind` in the config.
+///
+/// Hashes of different kinds never match, `verify` rehashes files recorded
+/// with another kind than the configured one, keeping their track ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashKind {
+    /// the whole file, see [`FileHash::from_file`]
+    #[default]
+    Full,
+    /// the size and both ends of a file, see [`FileHash::quick_from_file`].
+    /// `verify` replaces them with full hashes
+    Quick,
+    /// audio data without embedded tags, see [`FileHash::audio_from_file`]
+    Audio,
+}
+
+impl HashKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashKind::Full => "full",
+            HashKind::Quick => "quick",
+            HashKind::Audio => "audio",
+        }
+    }
+}
+
+impl FromStr for HashKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(HashKind::Full),
+            "quick" => Ok(HashKind::Quick),
+            "audio" => Ok(HashKind::Audio),
+            other => Err(format!("Unknown hash kind: {other}")),
+        }
+    }
+}
+
 impl std::fmt::Display for FileHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_hex())
@@ -35,13 +86,61 @@ impl FileHash {
         let contents = std::fs::read(path)?;
         Ok(Self::from_bytes(&contents))
     }
+
+    /// hashes the file size and the first and last [`QUICK_HASH_CHUNK`] bytes of the file.
+    ///
+    /// Only reads a few megabytes of large files, but misses changes in the middle of them.
+    /// Never equal to the full hash of the same file.
+    pub fn quick_from_file(path: &Path) -> Result<Self, io::Error> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"localdeck quick hash");
+        hasher.update(&size.to_le_bytes());
+        if size <= 2 * QUICK_HASH_CHUNK {
+            io::copy(&mut file, &mut hasher)?;
+        } else {
+            let mut chunk = vec![0; QUICK_HASH_CHUNK as usize];
+            file.read_exact(&mut chunk)?;
+            hasher.update(&chunk);
+            file.seek(SeekFrom::End(-(QUICK_HASH_CHUNK as i64)))?;
+            file.read_exact(&mut chunk)?;
+            hasher.update(&chunk);
+        }
+        Ok(Self(hasher.finalize()))
+    }
+
+    /// hashes the audio data of the file, leaving out tags, so files differing only
+    /// in their artist, title or artwork get the same hash.
+    ///
+    /// Files in formats without known tag layout are hashed whole.
+    /// Never equal to the full hash of the same file.
+    pub fn audio_from_file(path: &Path) -> Result<Self, io::Error> {
+        let contents = std::fs::read(path)?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"localdeck audio hash");
+        for frames in audio_frames(&contents) {
+            hasher.update(frames);
+        }
+        Ok(Self(hasher.finalize()))
+    }
+
+    /// hashes the file the way selected by `kind`
+    pub fn from_file_with(path: &Path, kind: HashKind) -> Result<Self, io::Error> {
+        match kind {
+            HashKind::Full => Self::from_file(path),
+            HashKind::Quick => Self::quick_from_file(path),
+            HashKind::Audio => Self::audio_from_file(path),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use tempfile::TempDir;
 
-    use crate::file_hash::FileHash;
+    use crate::file_hash::{FileHash, QUICK_HASH_CHUNK};
 
     #[test]
     fn same_contents_same_hash() {
@@ -72,4 +171,29 @@ mod tests {
 
         assert_ne!(ha, hb);
     }
+
+    #[test]
+    fn quick_hash_ignores_middle_of_large_files() {
+        let tmp = TempDir::new().unwrap();
+        let a = tmp.path().join("a.mp3");
+        let b = tmp.path().join("b.mp3");
+
+        let mut contents = vec![0u8; 3 * QUICK_HASH_CHUNK as usize];
+        std::fs::write(&a, &contents).unwrap();
+        contents[QUICK_HASH_CHUNK as usize + 10] = 1;
+        std::fs::write(&b, &contents).unwrap();
+
+        assert_eq!(
+            FileHash::quick_from_file(&a).unwrap(),
+            FileHash::quick_from_file(&b).unwrap()
+        );
+        assert_ne!(
+            FileHash::from_file(&a).unwrap(),
+            FileHash::from_file(&b).unwrap()
+        );
+        assert_ne!(
+            FileHash::quick_from_file(&a).unwrap(),
+            FileHash::from_file(&a).unwrap()
+        );
+    }
 }
@@ -4,44 +4,99 @@ use anyhow::anyhow;
 use walkdir::WalkDir;
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
     path::{Path, PathBuf},
 };
 
 use crate::{
-    config::{self, LibrarySource},
+    config::{self, LibraryRoot, LibrarySource},
+    db::system_time_to_i64,
     error::StorageError,
-    file_hash::FileHash,
+    file_hash::{FileHash, HashKind},
     location::Location,
-    usb::LocationResolver,
+    progress::{NoProgress, Progress},
+    remote::RemoteRoot,
+    s3::{self, S3Root},
+    track::TrackId,
+    usb::{LocationResolver, ResolveError},
 };
 
-const MUSIC_EXTENSIONS: &[&str] = &["mp3", "flac", "wav", "m4a", "ogg", "aac"];
-
-pub fn is_music_file(path: &Path) -> bool {
+/// Checks the file extension against the allowlist, see [`LibrarySource::extensions`]
+pub fn is_music_file(path: &Path, extensions: &[String]) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
-        .map(|ext| MUSIC_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .map(|ext| {
+            extensions
+                .iter()
+                .any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        })
         .unwrap_or(false)
 }
 
+/// Lowercase extension of a recorded path, e.g. `flac`
+pub(crate) fn file_format(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// Modification time of the file in seconds since the epoch
+pub(crate) fn modified_secs(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    system_time_to_i64(modified).ok()
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
 #[derive(Debug)]
 pub struct FileStorage {
     pub loc_resolver: LocationResolver,
+    pub progress: Box<dyn Progress>,
     config: LibrarySource,
+    /// files listed by remote roots during the last scan, with the hashes reported for them
+    remote_files: HashMap<Location, HashedFile>,
 }
 
 impl FileStorage {
     pub fn new(config: LibrarySource) -> Self {
         Self {
             loc_resolver: LocationResolver::default(),
+            progress: Box::new(NoProgress),
             config,
+            remote_files: HashMap::new(),
         }
     }
 
+    /// Configured library, with the ignored directories of [`FileStorage::set_ignored_dirs`]
+    pub fn source(&self) -> &LibrarySource {
+        &self.config
+    }
+
+    /// How new files are hashed
+    pub fn hash_kind(&self) -> HashKind {
+        self.config.hash_kind
+    }
+
     /// Recursively scans all music files in given directories. Retrieves their paths and metadata
+    ///
+    /// Disabled roots are ignored, roots that are currently unavailable are skipped with a warning.
     pub fn scan(&mut self) -> Result<FsSnapshot, StorageError> {
-        let roots: Vec<Location> = self.config.roots.clone();
+        let unavailable = self.unavailable_roots();
+        let roots: Vec<Location> = self
+            .enabled_roots()
+            .map(|r| r.location.clone())
+            .filter(|loc| !unavailable.iter().any(|u| &u.location == loc))
+            .collect();
+        for root in &unavailable {
+            println!(
+                "Skipping unavailable root {}: {}",
+                root.location, root.reason
+            );
+        }
         let scanned_dirs = roots
             .iter()
             .map(|root| {
@@ -49,11 +104,89 @@ impl FileStorage {
                 self.scan_dir(root)
             })
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(scanned_dirs.into_iter().flatten().collect())
+        let snapshot: FsSnapshot = scanned_dirs.into_iter().flatten().collect();
+        self.progress.scan_finished(snapshot.len());
+        Ok(snapshot)
+    }
+
+    /// Whether searches match Cyrillic text by its transliteration, see [`crate::search`]
+    pub fn transliterate_search(&self) -> bool {
+        self.config.transliterate_search
+    }
+
+    /// Replaces [`LibrarySource::ignored_dirs`], later scans skip the new ones
+    pub fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.config.ignored_dirs = dirs;
+    }
+
+    /// Extensions of files treated as music
+    pub fn extensions(&self) -> &[String] {
+        &self.config.extensions
+    }
+
+    /// All configured roots, disabled ones included
+    pub fn roots(&self) -> &[LibraryRoot] {
+        &self.config.roots
+    }
+
+    pub fn enabled_roots(&self) -> impl Iterator<Item = &LibraryRoot> {
+        self.config.roots.iter().filter(|r| r.enabled)
+    }
+
+    /// Checks whether the location belongs only to disabled roots
+    pub fn is_disabled(&self, loc: &Location) -> bool {
+        let mut containing = self
+            .config
+            .roots
+            .iter()
+            .filter(|root| loc.starts_with(&root.location))
+            .peekable();
+        containing.peek().is_some() && containing.all(|root| !root.enabled)
+    }
+
+    /// Returns enabled roots which do not resolve to an existing directory right now,
+    /// for example because a USB drive is not plugged in or another instance is offline.
+    pub fn unavailable_roots(&mut self) -> Vec<UnavailableRoot> {
+        let roots: Vec<Location> = self.enabled_roots().map(|r| r.location.clone()).collect();
+        roots
+            .into_iter()
+            .filter_map(|location| {
+                if let Some(remote) = RemoteRoot::new(&location) {
+                    let reason = remote.unavailable_reason()?;
+                    return Some(UnavailableRoot { location, reason });
+                }
+                if let Some(bucket) = S3Root::new(&location) {
+                    let reason = bucket.unavailable_reason()?;
+                    return Some(UnavailableRoot { location, reason });
+                }
+                let reason = match self.loc_resolver.resolve(&location) {
+                    Ok(path) if path.is_dir() => return None,
+                    Ok(path) => format!("{} is not a directory", path.to_string_lossy()),
+                    Err(e) => e.to_string(),
+                };
+                Some(UnavailableRoot { location, reason })
+            })
+            .collect()
     }
 
     /// Recursively scans all music files in the given directory. Retrieves their paths and metadata
+    ///
+    /// Remote roots list the tracks of the other instance instead, S3 roots the objects
+    /// below their prefix.
     pub fn scan_dir(&mut self, root: &Location) -> Result<Vec<FileWithMeta>, StorageError> {
+        if let Some(remote) = RemoteRoot::new(root) {
+            return self.scan_remote(&remote);
+        }
+        if let Some(bucket) = S3Root::new(root) {
+            let objects = bucket
+                .files()?
+                .into_iter()
+                .filter(|f| {
+                    matches!(&f.loc, Location::S3 { path, .. } if is_music_file(path, &self.config.extensions))
+                })
+                .collect();
+            return Ok(self.keep_music_files(objects));
+        }
         let root_path = self.loc_resolver.resolve(root).map_err(|e| {
             StorageError::Internal(anyhow!("failed to resolve library source root: {e}"))
         })?;
@@ -61,10 +194,14 @@ impl FileStorage {
 
         let walker = WalkDir::new(&root_path).follow_links(self.config.follow_symlinks);
 
-        walker
-            // filter out ignored directories
+        let files = walker
+            // filter out ignored directories and, if configured, hidden entries
             .into_iter()
             .filter_entry(|entry| {
+                // the root itself is always scanned, even if its name starts with a dot
+                if self.config.skip_hidden && entry.depth() > 0 && is_hidden(entry.file_name()) {
+                    return false;
+                }
                 let entry_path = entry.path();
                 // keep the entry if it's not inside any ignored directory
                 !self
@@ -84,7 +221,7 @@ impl FileStorage {
                 let pathbuf = e.path().to_path_buf();
                 (e, pathbuf)
             })
-            .filter(|(_, p)| is_music_file(p))
+            .filter(|(_, p)| is_music_file(p, &self.config.extensions))
             .map(|(e, p)| -> Result<_, StorageError> {
                 let metadata = e.metadata().map_err(|e| {
                     StorageError::Internal(anyhow!(
@@ -104,7 +241,85 @@ impl FileStorage {
                 let loc = root.join(rel);
                 Ok(FileWithMeta { loc, file_size })
             })
-            .collect::<Result<Vec<_>, _>>()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.keep_music_files(files))
+    }
+
+    /// Drops files above the size limit and reports the others as found
+    fn keep_music_files(&mut self, files: Vec<FileWithMeta>) -> Vec<FileWithMeta> {
+        let mut found = Vec::with_capacity(files.len());
+        for f in files {
+            if self.exceeds_max_size(&f) {
+                println!(
+                    "Skipping {}: {:.1} MB is above the max_file_size limit",
+                    f.loc,
+                    f.size_mb()
+                );
+                continue;
+            }
+            self.progress.file_found(&f.loc);
+            found.push(f);
+        }
+        found
+    }
+
+    fn scan_remote(&mut self, remote: &RemoteRoot) -> Result<Vec<FileWithMeta>, StorageError> {
+        let files = remote.files(self.hash_kind())?;
+        let mut found = Vec::with_capacity(files.len());
+        for file in files {
+            self.progress.file_found(&file.file.loc);
+            found.push(file.file.clone());
+            self.remote_files.insert(file.file.loc.clone(), file);
+        }
+        Ok(found)
+    }
+
+    /// Hashes a new file found by the last scan.
+    ///
+    /// Files of remote roots are not read, they get the hash the other instance reported.
+    /// Objects of S3 roots get the checksum stored with them if there is one.
+    pub(crate) fn hash_file(
+        &mut self,
+        file: &FileWithMeta,
+        kind: HashKind,
+    ) -> Result<HashedFile, StorageError> {
+        let hashed = match self.remote_files.get(&file.loc) {
+            Some(remote) => remote.clone(),
+            None if !file.loc.is_local() => {
+                let bucket = self.containing_root(&file.loc).and_then(S3Root::new);
+                let Some(bucket) = bucket else {
+                    return Err(StorageError::Internal(anyhow!(
+                        "{} is outside of the configured roots",
+                        file.loc
+                    )));
+                };
+                bucket.hash(file, kind)?
+            }
+            None => {
+                let path = self.loc_resolver.resolve(&file.loc).map_err(|e| {
+                    StorageError::Internal(anyhow!("Failed to resolve a file location. Possibly a drive got removed during the operation: {e}"))
+                })?;
+                HashedFile::with_kind(FileHash::from_file_with(&path, kind)?, kind, file.clone())
+            }
+        };
+        self.progress.file_hashed(&file.loc, file.file_size as u64);
+        Ok(hashed)
+    }
+
+    /// Modification time of a file on a local drive, `None` for files streamed over the
+    /// network and files that can not be reached
+    pub(crate) fn modified_time(&mut self, loc: &Location) -> Option<i64> {
+        if !loc.is_local() {
+            return None;
+        }
+        modified_secs(&self.loc_resolver.resolve(loc).ok()?)
+    }
+
+    /// Checks whether the file is above the configured `max_file_size`
+    pub fn exceeds_max_size(&self, file: &FileWithMeta) -> bool {
+        self.config
+            .max_file_size
+            .is_some_and(|max| file.file_size as u64 > max)
     }
 
     /// Takes a physical system path and maps it back to a logical library Location
@@ -114,6 +329,7 @@ impl FileStorage {
 
         // Iterate through all roots defined in your config
         for root in &self.config.roots {
+            let root = &root.location;
             // Resolve the physical base path of this specific root configuration
             if let Ok(base_path) = self.loc_resolver.resolve(root) {
                 if let Ok(canonical_base) = base_path.canonicalize() {
@@ -126,11 +342,143 @@ impl FileStorage {
         }
         Err(StorageError::PathOutsideLibrary(target))
     }
+
+    /// Picks the first playable file among the recorded locations of a track.
+    ///
+    /// Files in disabled roots are ignored, files from roots with a higher priority are preferred.
+    /// Remote files and objects in S3 roots are not checked, their path is the URL and the
+    /// location carries the token or credentials of their root.
+    pub(crate) fn pick_track_file(
+        &mut self,
+        track_id: TrackId,
+        mut paths: Vec<Location>,
+    ) -> Result<(PathBuf, Location), StorageError> {
+        let total = paths.len();
+        paths.retain(|loc| !self.is_disabled(loc));
+        let disabled = total - paths.len();
+        paths.sort_by_key(|loc| std::cmp::Reverse(self.root_priority(loc)));
+
+        let mut unmounted_locations = vec![];
+        let mut unmounted_shares = vec![];
+
+        for loc in paths {
+            if loc.is_remote() {
+                let url = PathBuf::from(loc.url().unwrap_or_default());
+                return Ok((url, self.with_root_settings(loc)));
+            }
+            if matches!(loc, Location::S3 { .. }) {
+                let loc = self.with_root_settings(loc);
+                let url = PathBuf::from(s3::object_url(&loc, "GET").unwrap_or_default());
+                return Ok((url, loc));
+            }
+            let path = self.loc_resolver.resolve(&loc);
+            match path {
+                Ok(p) => {
+                    if is_valid_music_path(&p, self.extensions()) {
+                        return Ok((p, loc));
+                    }
+                }
+                Err(e) => match e {
+                    ResolveError::UsbNotFound { label } => unmounted_locations.push(label),
+                    ResolveError::ShareNotMounted { mount, .. } => unmounted_shares.push(mount),
+                    ResolveError::SystemQueryFail(..) => {
+                        return Err(StorageError::Internal(anyhow!(
+                            "Error while resolving location {loc}: {e}"
+                        )));
+                    }
+                    ResolveError::Remote { .. }
+                    | ResolveError::ObjectStorage { .. }
+                    | ResolveError::WindowsError(..) => {
+                        return Err(StorageError::Internal(anyhow!(
+                            "Error while resolving location {loc}: {e}"
+                        )));
+                    }
+                },
+            }
+        }
+        Err(StorageError::InvalidTrackFile {
+            track: track_id,
+            extra: if !unmounted_locations.is_empty() {
+                format!("following drive labels are unmounted: {unmounted_locations:?}")
+            } else if !unmounted_shares.is_empty() {
+                format!("following network shares are not mounted: {unmounted_shares:?}")
+            } else if disabled > 0 {
+                format!("{disabled} file(s) are in disabled library roots")
+            } else {
+                "".to_string()
+            },
+        })
+    }
+
+    /// First configured root containing the location
+    fn containing_root(&self, loc: &Location) -> Option<&Location> {
+        self.config
+            .roots
+            .iter()
+            .map(|root| &root.location)
+            .find(|root| loc.starts_with(root))
+    }
+
+    /// Location with the settings only kept in the configured root containing it,
+    /// the token of a remote instance or the credentials of a bucket
+    fn with_root_settings(&self, loc: Location) -> Location {
+        match (self.containing_root(&loc), loc) {
+            (Some(Location::Remote { token, .. }), Location::Remote { base_url, path, .. }) => {
+                Location::Remote {
+                    base_url,
+                    token: token.clone(),
+                    path,
+                }
+            }
+            (
+                Some(Location::S3 {
+                    region,
+                    credentials,
+                    ..
+                }),
+                Location::S3 {
+                    endpoint,
+                    bucket,
+                    path,
+                    ..
+                },
+            ) => Location::S3 {
+                endpoint,
+                bucket,
+                region: region.clone(),
+                credentials: credentials.clone(),
+                path,
+            },
+            (_, loc) => loc,
+        }
+    }
+
+    /// Priority of the configured root containing the given file location.
+    ///
+    /// Files outside of all roots get the lowest possible priority.
+    pub fn root_priority(&self, loc: &Location) -> i32 {
+        self.config
+            .roots
+            .iter()
+            .filter(|root| loc.starts_with(&root.location))
+            .map(|root| root.priority)
+            .max()
+            .unwrap_or(i32::MIN)
+    }
+}
+
+/// Configured library root that can not be accessed
+#[derive(Debug, Clone)]
+pub struct UnavailableRoot {
+    pub location: Location,
+    /// Human readable reason, e.g. the USB drive is not mounted
+    pub reason: String,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct HashedFile {
     pub hash: FileHash,
+    pub kind: HashKind,
     pub file: FileWithMeta,
 }
 
@@ -151,7 +499,15 @@ pub type FsSnapshot = HashSet<FileWithMeta>;
 
 impl HashedFile {
     pub fn new(id: FileHash, file: FileWithMeta) -> Self {
-        Self { hash: id, file }
+        Self::with_kind(id, HashKind::Full, file)
+    }
+
+    pub fn with_kind(id: FileHash, kind: HashKind, file: FileWithMeta) -> Self {
+        Self {
+            hash: id,
+            kind,
+            file,
+        }
     }
 }
 
@@ -160,10 +516,10 @@ impl HashedFile {
 /// This does NOT decode audio, but rules out:
 /// - missing paths
 /// - directories / special files
-/// - wrong extensions
+/// - extensions outside of the allowlist
 /// - empty files
 /// - unreadable files
-pub fn is_valid_music_path(path: &Path) -> bool {
+pub fn is_valid_music_path(path: &Path, extensions: &[String]) -> bool {
     // Must exist and be a file
     let meta = match std::fs::metadata(path) {
         Ok(m) => m,
@@ -175,7 +531,7 @@ pub fn is_valid_music_path(path: &Path) -> bool {
     }
 
     // Must look like music by extension
-    if !is_music_file(path) {
+    if !is_music_file(path, extensions) {
         return false;
     }
 
@@ -192,7 +548,12 @@ pub fn is_valid_music_path(path: &Path) -> bool {
 mod tests {
     use tempfile::TempDir;
 
-    use crate::{config::LibrarySource, error::StorageError, fs::FileStorage, location::Location};
+    use crate::{
+        config::LibrarySource,
+        error::StorageError,
+        fs::{FileStorage, FileWithMeta},
+        location::Location,
+    };
 
     #[test]
     fn scan_finds_music_files() {
@@ -212,9 +573,10 @@ mod tests {
         std::fs::write(&not_music, b"ccc").unwrap();
 
         let files = FileStorage::new(LibrarySource {
-            roots: vec![root.clone()],
+            roots: vec![root.clone().into()],
             follow_symlinks: false,
             ignored_dirs: vec![],
+            ..Default::default()
         })
         .scan_dir(&root)
         .unwrap();
@@ -249,10 +611,11 @@ mod tests {
         let config = LibrarySource {
             follow_symlinks: false,
             roots: vec![
-                Location::from_path(dir1.path()),
-                Location::from_path(dir2.path()),
+                Location::from_path(dir1.path()).into(),
+                Location::from_path(dir2.path()).into(),
             ],
             ignored_dirs: vec![],
+            ..Default::default()
         };
 
         let snapshot = FileStorage::new(config).scan().unwrap();
@@ -287,9 +650,10 @@ mod tests {
         std::fs::write(&ignored_song, b"ccc").unwrap();
 
         let files = FileStorage::new(LibrarySource {
-            roots: vec![Location::from_path(root)],
+            roots: vec![Location::from_path(root).into()],
             follow_symlinks: false,
             ignored_dirs: vec![ignored_dir.clone()],
+            ..Default::default()
         })
         .scan_dir(&Location::from_path(root))
         .unwrap();
@@ -307,6 +671,86 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn scan_skips_hidden_entries() -> anyhow::Result<()> {
+        let tmp = TempDir::new()?;
+        // hidden root itself is still scanned
+        let root = tmp.path().join(".music");
+        let trash = root.join(".Trash-1000");
+        std::fs::create_dir_all(&trash)?;
+
+        let song = root.join("song.mp3");
+        std::fs::write(&song, b"aaa")?;
+        std::fs::write(root.join("._song.mp3"), b"bbb")?;
+        std::fs::write(trash.join("deleted.mp3"), b"ccc")?;
+
+        let config = |skip_hidden| LibrarySource {
+            roots: vec![Location::from_path(&root).into()],
+            follow_symlinks: false,
+            ignored_dirs: vec![],
+            skip_hidden,
+            ..Default::default()
+        };
+        let files = FileStorage::new(config(true)).scan()?;
+        assert_eq!(files.len(), 1);
+        assert!(files.contains(&FileWithMeta {
+            loc: Location::from_path(&song),
+            file_size: 3,
+        }));
+
+        let files = FileStorage::new(config(false)).scan()?;
+        assert_eq!(files.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn scan_uses_configured_extensions() -> anyhow::Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        std::fs::write(root.join("song.mp3"), b"aaa")?;
+        std::fs::write(root.join("song.OPUS"), b"bbb")?;
+
+        let files = FileStorage::new(LibrarySource {
+            roots: vec![Location::from_path(root).into()],
+            follow_symlinks: false,
+            ignored_dirs: vec![],
+            extensions: vec!["opus".to_string()],
+            ..Default::default()
+        })
+        .scan()?;
+
+        assert_eq!(files.len(), 1);
+        assert!(files.contains(&FileWithMeta {
+            loc: Location::from_path(root.join("song.OPUS")),
+            file_size: 3,
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn scan_skips_files_above_max_size() -> anyhow::Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        std::fs::write(root.join("small.mp3"), b"aaa")?;
+        std::fs::write(root.join("dj_set.wav"), b"aaaaaaaaaa")?;
+
+        let files = FileStorage::new(LibrarySource {
+            roots: vec![Location::from_path(root).into()],
+            follow_symlinks: false,
+            ignored_dirs: vec![],
+            max_file_size: Some(5),
+            ..Default::default()
+        })
+        .scan()?;
+
+        assert_eq!(files.len(), 1);
+        assert!(files.contains(&FileWithMeta {
+            loc: Location::from_path(root.join("small.mp3")),
+            file_size: 3,
+        }));
+        Ok(())
+    }
+
     #[test]
     fn test_reverse_resolve_success() {
         use tempfile::TempDir;
@@ -321,9 +765,10 @@ mod tests {
         std::fs::write(&song, b"aaa").unwrap();
 
         let mut fs_storage = FileStorage::new(LibrarySource {
-            roots: vec![root.clone()],
+            roots: vec![root.clone().into()],
             follow_symlinks: false,
             ignored_dirs: vec![],
+            ..Default::default()
         });
 
         // Act: Map the absolute physical path back to a structured Location
@@ -353,9 +798,10 @@ mod tests {
         std::fs::write(&outside_file, b"bbb").unwrap();
 
         let mut fs_storage = FileStorage::new(LibrarySource {
-            roots: vec![Location::from_path(&library_path)],
+            roots: vec![Location::from_path(&library_path).into()],
             follow_symlinks: false,
             ignored_dirs: vec![],
+            ..Default::default()
         });
 
         // Act
@@ -0,0 +1,127 @@
+//! Shell-style patterns over stored file paths, for `forget 'backup/**/*.wav'`.
+//!
+//! `*` matches any part of a file or directory name, `?` a single character and a `**`
+//! component any number of directories. Everything else, brackets included, is taken
+//! literally, so names like `[2019] Album` need no escaping.
+
+use std::{fmt::Display, path::Path};
+
+use crate::location::{LOCATION_PATH_SEP, replace_windows_slashes};
+
+const RECURSIVE: &str = "**";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathGlob {
+    pattern: String,
+    components: Vec<String>,
+}
+
+impl PathGlob {
+    pub fn new(pattern: &str) -> Self {
+        let pattern = replace_windows_slashes(Path::new(pattern));
+        Self {
+            components: pattern
+                .split(LOCATION_PATH_SEP)
+                .map(str::to_string)
+                .collect(),
+            pattern,
+        }
+    }
+
+    /// Whether `pattern` has wildcards, other paths are better handled as a directory or file
+    pub fn is_pattern(pattern: &str) -> bool {
+        pattern.contains(['*', '?'])
+    }
+
+    /// Leading components without wildcards, every matching path is at or under it.
+    /// Empty if the pattern starts with a wildcard
+    pub fn literal_prefix(&self) -> String {
+        let literal = self
+            .components
+            .iter()
+            .take_while(|c| !Self::is_pattern(c))
+            .count();
+        // the last component names files, only directories before it bound the search
+        let literal = literal.min(self.components.len().saturating_sub(1));
+        self.components[..literal].join(LOCATION_PATH_SEP)
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<&str> = path.split(LOCATION_PATH_SEP).collect();
+        match_components(&self.components, &path)
+    }
+}
+
+impl Display for PathGlob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+fn match_components(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == RECURSIVE => {
+            (0..=path.len()).any(|skipped| match_components(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                let pattern: Vec<char> = first.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_name(&pattern, &name) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|taken| match_name(rest, &name[taken..])),
+        Some(('?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_name(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathGlob;
+
+    #[test]
+    fn wildcards_stay_within_names() {
+        let glob = PathGlob::new("/backup/*.wav");
+        assert!(glob.matches("/backup/a.wav"));
+        assert!(!glob.matches("/backup/set/a.wav"));
+        assert!(!glob.matches("/backup/a.flac"));
+        assert!(PathGlob::new("/backup/track?.mp3").matches("/backup/track1.mp3"));
+        assert!(!PathGlob::new("/backup/track?.mp3").matches("/backup/track10.mp3"));
+    }
+
+    #[test]
+    fn double_star_spans_directories() {
+        let glob = PathGlob::new("usb-backup/**/*.wav");
+        assert!(glob.matches("usb-backup/a.wav"));
+        assert!(glob.matches("usb-backup/2019/live/a.wav"));
+        assert!(!glob.matches("other/usb-backup/a.wav"));
+        assert!(PathGlob::new("**/*.wav").matches("/music/a.wav"));
+    }
+
+    #[test]
+    fn brackets_are_literal() {
+        let glob = PathGlob::new("/music/[2019] Album/*");
+        assert!(glob.matches("/music/[2019] Album/a.mp3"));
+        assert!(!glob.matches("/music/2 Album/a.mp3"));
+    }
+
+    #[test]
+    fn prefix_stops_at_first_wildcard() {
+        assert_eq!(
+            PathGlob::new("/music/backup/**/*.wav").literal_prefix(),
+            "/music/backup"
+        );
+        assert_eq!(PathGlob::new("/music/*/a.wav").literal_prefix(), "/music");
+        assert_eq!(PathGlob::new("/music/a?.wav").literal_prefix(), "/music");
+        assert_eq!(PathGlob::new("**/*.wav").literal_prefix(), "");
+    }
+}
@@ -1,13 +1,43 @@
+pub mod album;
+pub mod artists;
+mod audio_frames;
+pub mod audit;
+pub mod beets;
+pub mod bundle;
+pub mod card_scans;
 pub mod config;
 mod db;
 pub mod error;
+pub mod favourites;
 pub mod file_hash;
 mod fs;
+pub mod glob;
 pub mod location;
+pub mod lyrics;
+pub mod notes;
 pub mod operations;
+pub mod playlist;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod progress;
+pub mod provenance;
+pub mod quarantine;
+pub mod remote;
+pub mod s3;
 mod schema;
+pub mod search;
+pub mod short_links;
+pub mod snapshot;
+pub mod sort_names;
+pub mod store;
+pub mod tags;
 pub mod track;
+mod track_index;
+pub mod update;
 mod usb;
+pub mod usb_library;
+pub mod verify;
+pub mod youtube;
 
 pub use operations::Storage;
 
@@ -9,8 +9,60 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Hash)]
 #[serde(tag = "type")]
 pub enum Location {
-    File { path: PathBuf },
-    Usb { label: String, path: PathBuf },
+    File {
+        path: PathBuf,
+    },
+    Usb {
+        label: String,
+        path: PathBuf,
+    },
+    /// SMB or NFS share mounted at `mount`, `path` is relative to it.
+    ///
+    /// Unlike a plain directory, an unmounted share is recognized as such, so its files are
+    /// not taken for deleted. If `source` is set, e.g. `//nas/music` or `nas:/export/music`,
+    /// the share must be mounted from there. Like the token of remote roots it is only
+    /// kept in the configured root.
+    NetworkShare {
+        mount: PathBuf,
+        #[serde(default, skip_serializing)]
+        source: Option<String>,
+        #[serde(default)]
+        path: PathBuf,
+    },
+    /// Objects in a bucket of an S3-compatible object storage, `path` is the object key
+    /// (a key prefix for roots). Objects are addressed path style, `{endpoint}/{bucket}/{key}`.
+    ///
+    /// `region` (default `us-east-1`) and `credentials` are only kept in the configured root.
+    /// Public buckets need no credentials.
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default, skip_serializing)]
+        region: Option<String>,
+        #[serde(default, skip_serializing)]
+        credentials: Option<S3Credentials>,
+        #[serde(default)]
+        path: PathBuf,
+    },
+    /// Another localdeck instance, its tracks are streamed through this one.
+    ///
+    /// `path` is relative to `base_url`, e.g. `tracks/12/stream`, and empty for roots.
+    /// The token is sent to the other instance as a bearer token. It is only kept
+    /// in the configured root, recorded files carry none, and it is never serialized,
+    /// so API responses don't leak it.
+    Remote {
+        base_url: String,
+        #[serde(default, skip_serializing)]
+        token: Option<String>,
+        #[serde(default)]
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone, Hash)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
 }
 
 impl Location {
@@ -26,8 +78,78 @@ impl Location {
             Location::Usb { .. } => Err(anyhow!(
                 "Location includes usb label, can't unpack as simple path"
             )),
+            Location::NetworkShare { mount, path, .. } => Ok(mount.join(path)),
+            Location::S3 { .. } => Err(anyhow!(
+                "Location is in object storage, can't unpack as simple path"
+            )),
+            Location::Remote { .. } => Err(anyhow!(
+                "Location is on another localdeck instance, can't unpack as simple path"
+            )),
         }
     }
+
+    pub fn is_remote(&self) -> bool {
+        matches!(self, Location::Remote { .. })
+    }
+
+    /// Whether the file can be read from the file system, once its drive or share is mounted.
+    /// Other files are streamed over the network
+    pub fn is_local(&self) -> bool {
+        match self {
+            Location::File { .. } | Location::Usb { .. } | Location::NetworkShare { .. } => true,
+            Location::S3 { .. } | Location::Remote { .. } => false,
+        }
+    }
+
+    /// Checks whether this location is inside `base` (or equal to it)
+    pub fn starts_with(&self, base: &Location) -> bool {
+        match (self, base) {
+            (Location::File { path }, Location::File { path: base }) => path.starts_with(base),
+            (
+                Location::Usb { label, path },
+                Location::Usb {
+                    label: base_label,
+                    path: base,
+                },
+            ) => label == base_label && path.starts_with(base),
+            (
+                Location::NetworkShare { mount, path, .. },
+                Location::NetworkShare {
+                    mount: base_mount,
+                    path: base,
+                    ..
+                },
+            ) => mount == base_mount && path.starts_with(base),
+            (
+                Location::S3 {
+                    endpoint,
+                    bucket,
+                    path,
+                    ..
+                },
+                Location::S3 {
+                    endpoint: base_endpoint,
+                    bucket: base_bucket,
+                    path: base,
+                    ..
+                },
+            ) => {
+                same_base_url(endpoint, base_endpoint)
+                    && bucket == base_bucket
+                    && path.starts_with(base)
+            }
+            (
+                Location::Remote { base_url, path, .. },
+                Location::Remote {
+                    base_url: base_url_of_base,
+                    path: base,
+                    ..
+                },
+            ) => same_base_url(base_url, base_url_of_base) && path.starts_with(base),
+            _ => false,
+        }
+    }
+
     pub fn join(&self, rel: &Path) -> Self {
         match self {
             Location::Usb { label, path } => Location::Usb {
@@ -37,10 +159,86 @@ impl Location {
             Location::File { path } => Location::File {
                 path: path.join(rel),
             },
+            Location::NetworkShare {
+                mount,
+                source,
+                path,
+            } => Location::NetworkShare {
+                mount: mount.clone(),
+                source: source.clone(),
+                path: path.join(rel),
+            },
+            Location::S3 {
+                endpoint,
+                bucket,
+                region,
+                credentials,
+                path,
+            } => Location::S3 {
+                endpoint: endpoint.clone(),
+                bucket: bucket.clone(),
+                region: region.clone(),
+                credentials: credentials.clone(),
+                path: path.join(rel),
+            },
+            Location::Remote {
+                base_url,
+                token,
+                path,
+            } => Location::Remote {
+                base_url: base_url.clone(),
+                token: token.clone(),
+                path: path.join(rel),
+            },
+        }
+    }
+
+    /// Directory holding the file, e.g. its album folder. `None` if the path has no parent
+    pub fn parent(&self) -> Option<Self> {
+        let mut parent = self.clone();
+        let (Location::File { path }
+        | Location::Usb { path, .. }
+        | Location::NetworkShare { path, .. }
+        | Location::S3 { path, .. }
+        | Location::Remote { path, .. }) = &mut parent;
+        path.pop().then_some(parent)
+    }
+
+    /// Last component of the path, e.g. the name of a file or folder
+    pub fn file_name(&self) -> Option<String> {
+        let (Location::File { path }
+        | Location::Usb { path, .. }
+        | Location::NetworkShare { path, .. }
+        | Location::S3 { path, .. }
+        | Location::Remote { path, .. }) = self;
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    /// Full URL of a remote location, `None` for local ones
+    pub fn url(&self) -> Option<String> {
+        match self {
+            Location::Remote { base_url, path, .. } => {
+                let base_url = base_url.trim_end_matches('/');
+                Some(if path.as_os_str().is_empty() {
+                    base_url.to_string()
+                } else {
+                    format!("{base_url}/{}", replace_windows_slashes(path))
+                })
+            }
+            Location::File { .. }
+            | Location::Usb { .. }
+            | Location::NetworkShare { .. }
+            | Location::S3 { .. } => None,
         }
     }
 }
 
+/// Base URLs differing in a trailing slash only point to the same instance
+fn same_base_url(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
 pub const LOCATION_PATH_SEP: &str = "/";
 
 pub fn replace_windows_slashes(s: &Path) -> String {
@@ -54,6 +252,16 @@ impl Display for Location {
             Location::Usb { label, path } => {
                 write!(f, "USB({})/{}", label, replace_windows_slashes(path))
             }
+            Location::NetworkShare { mount, path, .. } => write!(
+                f,
+                "SHARE({})/{}",
+                replace_windows_slashes(mount),
+                replace_windows_slashes(path)
+            ),
+            Location::S3 { bucket, path, .. } => {
+                write!(f, "S3({bucket})/{}", replace_windows_slashes(path))
+            }
+            Location::Remote { .. } => write!(f, "{}", self.url().unwrap_or_default()),
         }
     }
 }
@@ -0,0 +1,176 @@
+//! Lyrics of tracks, fetched from LRCLIB by the `lyrics fetch` command
+
+use std::time::{Duration, SystemTime};
+
+use rusqlite::{ErrorCode, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::{
+    Storage,
+    audit::{self, AuditOperation},
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackLyrics {
+    pub plain: Option<String>,
+    /// lyrics with timestamps, in LRC format: `[mm:ss.xx] line`
+    pub synced: Option<String>,
+}
+
+/// Line of synced lyrics, sung from `time` on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricLine {
+    pub time: Duration,
+    pub text: String,
+}
+
+impl TrackLyrics {
+    /// Timed lines of the synced lyrics in playback order, empty without synced lyrics.
+    ///
+    /// Lines with several timestamps (`[00:12.00][01:40.00]chorus`) are repeated,
+    /// the `[offset:+/-ms]` tag is applied, other tags like `[ar:artist]` are skipped.
+    pub fn synced_lines(&self) -> Vec<LyricLine> {
+        let Some(synced) = &self.synced else {
+            return vec![];
+        };
+        let mut offset_ms = 0i64;
+        let mut lines = vec![];
+        for line in synced.lines() {
+            let mut rest = line.trim();
+            let mut times = vec![];
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                if let Some(time) = parse_timestamp(tag) {
+                    times.push(time);
+                } else if let Some(offset) = tag.strip_prefix("offset:") {
+                    offset_ms = offset.trim().parse().unwrap_or(0);
+                }
+                rest = after;
+            }
+            for time in times {
+                // a positive offset makes lines appear sooner
+                let ms = (time.as_millis() as i64 - offset_ms).max(0);
+                lines.push(LyricLine {
+                    time: Duration::from_millis(ms as u64),
+                    text: rest.trim().to_string(),
+                });
+            }
+        }
+        lines.sort_by_key(|line| line.time);
+        lines
+    }
+}
+
+/// `mm:ss`, `mm:ss.xx` or `mm:ss.xxx`
+fn parse_timestamp(tag: &str) -> Option<Duration> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds: f64 = seconds.parse().ok()?;
+    if !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(Duration::from_secs(minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+impl Storage {
+    pub fn get_lyrics(&mut self, track_id: TrackId) -> Result<Option<TrackLyrics>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT {PLAIN_LYRICS}, {SYNCED_LYRICS} FROM {TRACK_LYRICS} WHERE {TRACK_ID} = ?1"
+                ),
+                params![track_id],
+                |row| {
+                    Ok(TrackLyrics {
+                        plain: row.get(0)?,
+                        synced: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// Stores lyrics of the track, replacing lyrics stored before
+    pub fn set_lyrics(
+        &mut self,
+        track_id: TrackId,
+        lyrics: &TrackLyrics,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {TRACK_LYRICS} ({TRACK_ID}, {PLAIN_LYRICS}, {SYNCED_LYRICS}, {FETCHED_AT})
+                VALUES (?1, ?2, ?3, ?4)"
+            ),
+            params![track_id, lyrics.plain, lyrics.synced, now],
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(error, _)
+                if error.code == ErrorCode::ConstraintViolation =>
+            {
+                StorageError::TrackNotFound(track_id.to_string())
+            }
+            e => StorageError::Database(e),
+        })?;
+        Self::insert_update_time(&tx)?;
+        let details = if lyrics.synced.is_some() {
+            "synced lyrics"
+        } else {
+            "plain lyrics"
+        };
+        audit::record(&tx, AuditOperation::Lyrics, details, [track_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LyricLine, TrackLyrics};
+
+    fn line(ms: u64, text: &str) -> LyricLine {
+        LyricLine {
+            time: Duration::from_millis(ms),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn synced_lines_are_parsed_in_order() {
+        let lyrics = TrackLyrics {
+            plain: None,
+            synced: Some(
+                "[ar:Artist]\n[ti:Title]\n[00:01.50] first\n[00:10.00][01:02.25]chorus\n[00:05.123]second\n"
+                    .to_string(),
+            ),
+        };
+        assert_eq!(
+            lyrics.synced_lines(),
+            vec![
+                line(1500, "first"),
+                line(5123, "second"),
+                line(10000, "chorus"),
+                line(62250, "chorus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn offset_is_applied() {
+        let lyrics = TrackLyrics {
+            plain: None,
+            synced: Some("[offset:+500]\n[00:00.20]start\n[00:02.00]later".to_string()),
+        };
+        assert_eq!(
+            lyrics.synced_lines(),
+            vec![line(0, "start"), line(1500, "later")]
+        );
+    }
+}
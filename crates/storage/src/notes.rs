@@ -0,0 +1,74 @@
+//! Free-form notes on tracks, like "vinyl rip, crackly intro" or "use for first dance".
+//!
+//! Notes are kept apart from the metadata, so tracks without artist and title can have
+//! them too. `find` and the library search match them like artists and titles.
+
+use std::time::SystemTime;
+
+use rusqlite::{ErrorCode, OptionalExtension, params};
+
+use crate::{
+    Storage,
+    audit::{self, AuditOperation},
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+/// Notes worth storing: trimmed, blank notes are none
+pub fn clean_notes(notes: Option<&str>) -> Option<&str> {
+    notes.map(str::trim).filter(|notes| !notes.is_empty())
+}
+
+impl Storage {
+    pub fn track_notes(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT {NOTES} FROM {TRACK_NOTES} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Replaces the notes of the track, blank notes remove them
+    pub fn set_track_notes(
+        &mut self,
+        track_id: TrackId,
+        notes: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        match clean_notes(notes) {
+            Some(notes) => {
+                tx.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO {TRACK_NOTES} ({TRACK_ID}, {NOTES}, {UPDATED_AT})
+                        VALUES (?1, ?2, ?3)"
+                    ),
+                    params![track_id, notes, now],
+                )
+                .map_err(|e| match e {
+                    rusqlite::Error::SqliteFailure(error, _)
+                        if error.code == ErrorCode::ConstraintViolation =>
+                    {
+                        StorageError::TrackNotFound(track_id.to_string())
+                    }
+                    e => StorageError::Database(e),
+                })?;
+            }
+            None => {
+                tx.execute(
+                    &format!("DELETE FROM {TRACK_NOTES} WHERE {TRACK_ID} = ?1"),
+                    params![track_id],
+                )?;
+            }
+        }
+        Self::insert_update_time(&tx)?;
+        audit::record(&tx, AuditOperation::Metadata, "notes", [track_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -1,37 +1,51 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
+    mem,
     path::{Path, PathBuf},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::anyhow;
 use chrono::{DateTime, Local};
 
-#[cfg(test)]
-use crate::config::LibrarySource;
 use crate::{
-    CardId,
-    config::{Config, Database},
+    CardId, artists,
+    audit::{self, AuditId, AuditOperation, FileChange},
+    config::{Config, Database, LibrarySource},
     db::{self, DBConfig, i64_seconds_to_local_time, system_time_to_i64},
     error::StorageError,
-    file_hash::FileHash,
-    fs::{FileStorage, FileWithMeta, FsSnapshot, is_valid_music_path},
+    file_hash::{FileHash, HashKind},
+    fs::{FileStorage, FsSnapshot, file_format, modified_secs},
+    glob::PathGlob,
     location::{LOCATION_PATH_SEP, Location, replace_windows_slashes},
+    progress::Progress,
+    provenance::{self, MetadataOrigin},
     schema::{columns, tables},
+    search::{Condition, SearchQuery, SearchedFile},
+    sort_names,
     track::{ArtworkRef, Track, TrackId, TrackMetadata},
-    usb::ResolveError,
+    track_index::TrackIndex,
+    update::{LibraryScan, PendingUpdate},
 };
 
 use columns::*;
-use rusqlite::{ErrorCode, OptionalExtension, Transaction, params};
+use rusqlite::{ErrorCode, OptionalExtension, Transaction, params, params_from_iter, types::Value};
 use tables::*;
 
-pub use crate::fs::HashedFile;
+pub use crate::fs::{FileWithMeta, HashedFile, UnavailableRoot};
+
+/// Number of hashed files committed at once by [`Storage::update_db_with_new_files`]
+pub(crate) const UPDATE_CHECKPOINT_FILES: usize = 100;
 
 /// Main structure that implements all storage logic
 pub struct Storage {
     pub(crate) db: rusqlite::Connection,
-    fs: FileStorage,
+    pub(crate) fs: FileStorage,
+    /// see [`Storage::enable_track_index`]
+    pub(crate) index_enabled: bool,
+    pub(crate) index: Option<TrackIndex>,
+    /// rows written through `db` which are not library changes, they don't invalidate `index`
+    pub(crate) log_changes: u64,
 }
 
 #[derive(Debug)]
@@ -44,6 +58,19 @@ pub struct ForgetReport {
     pub removed_tracks: usize,
 }
 
+/// Result of [`Storage::apply_metadata_under`]
+#[derive(Debug, Default)]
+pub struct ApplyMetadataReport {
+    /// tracks with files under the path
+    pub matched_tracks: usize,
+    /// tracks whose metadata changed
+    pub updated: Vec<TrackId>,
+    /// tracks with other values set already, left alone without `allow_overwrite`
+    pub kept: Vec<TrackId>,
+    /// tracks without metadata yet, which can only be created with a title and artist
+    pub missing_required: Vec<TrackId>,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct CleanDanglingReport {
     /// Number of dangling track ids removed from TRACKS.
@@ -59,22 +86,178 @@ pub struct StaleTracks {
     pub dangling: Vec<TrackId>,
 }
 
+/// Summary of one `update` run
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    pub id: i64,
+    pub started_at: DateTime<Local>,
+    pub duration: Duration,
+    /// music files found on disk
+    pub files_seen: usize,
+    /// files added to the database
+    pub files_added: usize,
+    /// recorded files that were no longer found on disk
+    pub files_removed: usize,
+    /// breakdown by enabled library root
+    pub roots: Vec<RootScanReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RootScanReport {
+    pub root: String,
+    /// false if the root could not be accessed during the scan
+    pub available: bool,
+    pub files_seen: usize,
+    pub files_added: usize,
+}
+
+/// Track as shown in library listings
+#[derive(Debug)]
+pub struct TrackListEntry {
+    pub id: TrackId,
+    /// when the first file of the track was recorded, unknown for tracks added
+    /// by versions not recording it
+    pub added: Option<DateTime<Local>>,
+    /// All recorded files, empty for tracks without files
+    pub files: Vec<ListedFile>,
+    /// `None` for tracks without metadata
+    pub meta: Option<ListedMetadata>,
+}
+
+/// The part of the metadata shown in listings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedMetadata {
+    pub artist: String,
+    pub title: String,
+    pub year: Option<u32>,
+    /// see [`crate::sort_names`]
+    pub artist_sort: String,
+    pub title_sort: String,
+}
+
+/// Conditions on listed tracks, all of which must hold. The default one lists every track
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackFilter {
+    /// credited artist, main, featured or remixing, ignoring case
+    pub artist: Option<String>,
+    /// first year of release, inclusive
+    pub year_from: Option<u32>,
+    /// last year of release, inclusive
+    pub year_to: Option<u32>,
+    /// format of at least one file, an extension like `flac`
+    pub format: Option<String>,
+}
+
+impl TrackFilter {
+    /// Format as recorded, lowercase and without a leading dot
+    pub(crate) fn normalized_format(&self) -> Option<String> {
+        self.format
+            .as_deref()
+            .map(|format| format.trim().trim_start_matches('.').to_ascii_lowercase())
+    }
+}
+
+/// Artist of the metadata, with how many tracks name them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtistEntry {
+    /// as written in the metadata. Spellings differing in case count as one artist
+    pub name: String,
+    pub tracks: usize,
+}
+
+/// Year of release, with how many tracks came out then
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YearEntry {
+    pub year: u32,
+    pub tracks: usize,
+}
+
+/// Recorded file of a [`TrackListEntry`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedFile {
+    pub loc: Location,
+    /// bytes
+    pub size: i64,
+    /// modification time when the file was hashed. Not known for files streamed over the
+    /// network and files recorded by older versions
+    pub modified: Option<DateTime<Local>>,
+    /// lowercase extension, e.g. `flac`
+    pub format: Option<String>,
+}
+
+impl TrackListEntry {
+    pub fn locations(&self) -> impl Iterator<Item = &Location> {
+        self.files.iter().map(|file| &file.loc)
+    }
+}
+
 impl Storage {
     /// when called, opens a data base connection
     /// and applies migrations
+    ///
+    /// Library roots that can not be resolved are reported with a warning,
+    /// the storage keeps working with the available ones.
     pub fn new(config: Config) -> Result<Self, StorageError> {
         let mut fs = FileStorage::new(config.library_source);
         let db_config = match config.database {
             Database::InMemory => DBConfig::InMemory,
-            Database::OnDisk { location } => DBConfig::OnDisk {
+            Database::OnDisk {
+                location,
+                encryption_key,
+            } => DBConfig::OnDisk {
                 location: fs.loc_resolver.resolve(&location).map_err(|e| {
                     StorageError::Internal(anyhow!("Failed to resolve DB location: {e}"))
                 })?,
+                encryption_key,
             },
+            #[cfg(feature = "postgres")]
+            Database::Postgres { .. } => {
+                return Err(StorageError::Internal(anyhow!(
+                    "this operation is only supported with an SQLite database"
+                )));
+            }
         };
 
         let db: rusqlite::Connection = db::open(db_config)?;
-        Ok(Self { db, fs })
+        for root in fs.unavailable_roots() {
+            log::warn!(
+                "library root {} is unavailable, its tracks will not be served: {}",
+                root.location,
+                root.reason
+            );
+        }
+        Ok(Self {
+            db,
+            fs,
+            index_enabled: false,
+            index: None,
+            log_changes: 0,
+        })
+    }
+
+    /// Sets the receiver of scan and hashing progress, see [`Progress`]
+    pub fn set_progress(&mut self, progress: Box<dyn Progress>) {
+        self.fs.progress = progress;
+    }
+
+    /// Configured library roots which are not accessible at the moment
+    pub fn unavailable_roots(&mut self) -> Vec<UnavailableRoot> {
+        self.fs.unavailable_roots()
+    }
+
+    /// Whether searches match Cyrillic text by its transliteration, see [`crate::search`]
+    pub fn transliterate_search(&self) -> bool {
+        self.fs.transliterate_search()
+    }
+
+    /// Directories skipped by later scans, replacing those of the config
+    pub fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.fs.set_ignored_dirs(dirs)
+    }
+
+    /// Library scanned by updates
+    pub fn library_source(&self) -> LibrarySource {
+        self.fs.source().clone()
     }
 
     #[cfg(test)]
@@ -82,6 +265,9 @@ impl Storage {
         Self {
             db,
             fs: FileStorage::new(lib_config),
+            index_enabled: false,
+            index: None,
+            log_changes: 0,
         }
     }
 
@@ -119,7 +305,7 @@ impl Storage {
         let files = {
             // Query the files table directly filtering by the integer track_id
             let mut stmt = tx.prepare(&format!(
-                "SELECT {USB_LABEL}, {PATH}, {FILE_SIZE}, {FILE_HASH}
+                "SELECT {USB_LABEL}, {PATH}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}
              FROM {FILES}
              WHERE {TRACK_ID} = ?"
             ))?;
@@ -129,19 +315,21 @@ impl Storage {
                 let path: String = row.get(1)?;
                 let file_size: i64 = row.get(2)?;
                 let hash: String = row.get(3)?;
+                let kind: String = row.get(4)?;
 
-                Ok((LocationRow { usb_label, path }, file_size, hash))
+                Ok((LocationRow { usb_label, path }, file_size, hash, kind))
             })?
             .collect::<Result<Vec<_>, _>>()?
         };
 
         let files = files
             .into_iter()
-            .map(|(lr, file_size, hash)| {
+            .map(|(lr, file_size, hash, kind)| {
                 Ok(HashedFile {
                     hash: FileHash::from_hex(hash).map_err(|e| {
                         StorageError::Internal(anyhow!("Database contains invalid file hash {e}"))
                     })?,
+                    kind: parse_hash_kind(&kind)?,
                     file: FileWithMeta {
                         loc: lr.into(),
                         file_size,
@@ -187,7 +375,7 @@ impl Storage {
         Ok(metadata_list)
     }
 
-    fn insert_update_time(tx: &Transaction) -> Result<(), StorageError> {
+    pub(crate) fn insert_update_time(tx: &Transaction) -> Result<(), StorageError> {
         let time_secs = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
         // ---------- Record update timestamp ----------
         tx.execute(
@@ -206,10 +394,7 @@ impl Storage {
     }
 
     /// Helper to look up an existing track ID by file hash, or provision a new track row if missing.
-    fn get_or_create_track_id(
-        tx: &Transaction,
-        hash: &FileHash,
-    ) -> Result<TrackId, rusqlite::Error> {
+    fn get_or_create_track_id(tx: &Transaction, hash: &FileHash) -> Result<TrackId, StorageError> {
         let hash = hash.to_string();
         // Query to find existing track by file hash
         let query = format!("SELECT {TRACK_ID} FROM {FILES} WHERE {FILE_HASH} = ?1 LIMIT 1");
@@ -222,35 +407,45 @@ impl Storage {
         if let Some(id) = existing_track_id {
             Ok(id)
         } else {
-            // Insert a new default row into tracks to auto-increment a new ID
-            let insert_query = format!("INSERT INTO {TRACKS} DEFAULT VALUES");
+            // Insert a new row into tracks to auto-increment a new ID
+            let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+            let insert_query = format!("INSERT INTO {TRACKS} ({CREATED_AT}) VALUES (?1)");
             let mut insert_track_stmt = tx.prepare_cached(&insert_query)?;
-            insert_track_stmt.execute([])?;
+            insert_track_stmt.execute([now])?;
 
             Ok(tx.last_insert_rowid())
         }
     }
 
-    /// Inserts a single file entry bound to a specific TrackId.
+    /// Inserts a single file entry bound to a specific TrackId, with the modification time
+    /// of the file if it is known.
     /// Returns `Ok(true)` if inserted, or `Ok(false)` if ignored due to a location conflict.
     fn insert_file(
         tx: &rusqlite::Transaction,
         track_id: TrackId,
         hashed_file: &HashedFile,
+        modified: Option<i64>,
     ) -> Result<bool, StorageError> {
         let insert_file_query = format!(
-            "INSERT OR IGNORE INTO {FILES} ({USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}) \
-             VALUES (?1, ?2, ?3, ?4, ?5)"
+            "INSERT OR IGNORE INTO {FILES} ({USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {LAST_SEEN}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT}) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
         );
         let mut stmt = tx.prepare_cached(&insert_file_query)?;
 
+        // the file was just hashed, so it is on disk
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
         let loc_row = LocationRow::from_location(hashed_file.file.loc.clone())?;
+        let format = file_format(&loc_row.path);
         let rows_changed = stmt.execute(rusqlite::params![
             loc_row.usb_label,
             loc_row.path,
             track_id,
             hashed_file.file.file_size,
-            hashed_file.hash.to_string()
+            hashed_file.hash.to_string(),
+            now,
+            hashed_file.kind.as_str(),
+            modified,
+            format
         ])?;
 
         Ok(rows_changed > 0)
@@ -259,9 +454,12 @@ impl Storage {
     /// Inserts track files, grouping by hash. Reuses track IDs on hash matches.
     ///
     /// Ignores location conflicts. Returns only newly inserted items.
+    ///
+    /// Inserted tracks are logged to the `audit_id` entry, which is created on the first insertion.
     fn insert_files(
         &mut self,
         files: impl IntoIterator<Item = HashedFile>,
+        audit_id: &mut Option<AuditId>,
     ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
         let mut grouped_by_hash: HashMap<FileHash, Vec<HashedFile>> = HashMap::new();
         for hashed_file in files {
@@ -279,8 +477,9 @@ impl Storage {
             let track_id = Self::get_or_create_track_id(&tx, &hash)?;
 
             for hashed_file in hashed_files {
+                let modified = self.fs.modified_time(&hashed_file.file.loc);
                 // Call the granular single insert helper
-                if Self::insert_file(&tx, track_id, &hashed_file)? {
+                if Self::insert_file(&tx, track_id, &hashed_file, modified)? {
                     inserted_tracks
                         .entry(track_id)
                         .or_default()
@@ -291,6 +490,29 @@ impl Storage {
 
         if !inserted_tracks.is_empty() {
             Self::insert_update_time(&tx)?;
+            let tracks = inserted_tracks.keys().copied();
+            let id = match audit_id {
+                Some(id) => {
+                    audit::add_tracks(&tx, *id, tracks)?;
+                    *id
+                }
+                None => *audit_id.insert(audit::record(
+                    &tx,
+                    AuditOperation::Update,
+                    "new files added",
+                    tracks,
+                )?),
+            };
+            for file in inserted_tracks.values().flatten() {
+                let row = LocationRow::from_location(file.file.loc.clone())?;
+                audit::record_files(
+                    &tx,
+                    id,
+                    FileChange::Added,
+                    &format!("{USB_LABEL} = ?1 AND {PATH} = ?2"),
+                    params![row.usb_label, row.path],
+                )?;
+            }
         }
 
         tx.commit()?;
@@ -306,11 +528,60 @@ impl Storage {
 
     /// checks for new music files not present in database
     pub fn check_new(&mut self) -> Result<HashSet<FileWithMeta>, StorageError> {
+        let snapshot = Self::scan_fs(&mut self.fs)?;
+        self.mark_seen(&snapshot)?;
+        self.new_files_in(&snapshot)
+    }
+
+    /// Sets the last seen time of all recorded files found by a scan to now,
+    /// and the format of files recorded before it was stored
+    fn mark_seen(&mut self, snapshot: &FsSnapshot) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(&format!(
+                "UPDATE {FILES} SET {LAST_SEEN} = ?1, {FORMAT} = COALESCE({FORMAT}, ?4)
+                WHERE {USB_LABEL} = ?2 AND {PATH} = ?3"
+            ))?;
+            for file in snapshot {
+                let row = LocationRow::from_location(file.loc.clone())?;
+                let format = file_format(&row.path);
+                stmt.execute(params![now, row.usb_label, row.path, format])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// When a scan found the recorded file at `loc` last time.
+    ///
+    /// Returns `None` if no scan has seen it yet or the location is not recorded.
+    pub fn last_seen(&mut self, loc: &Location) -> Result<Option<DateTime<Local>>, StorageError> {
+        let row = LocationRow::from_location(loc.clone())?;
+        let last_seen: Option<i64> = self
+            .db
+            .query_row(
+                &format!("SELECT {LAST_SEEN} FROM {FILES} WHERE {USB_LABEL} = ?1 AND {PATH} = ?2"),
+                params![row.usb_label, row.path],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        last_seen
+            .map(|secs| i64_seconds_to_local_time(secs).map_err(StorageError::Internal))
+            .transpose()
+    }
+
+    /// files of the snapshot not present in database
+    fn new_files_in(
+        &mut self,
+        snapshot: &FsSnapshot,
+    ) -> Result<HashSet<FileWithMeta>, StorageError> {
         let mut fs = HashSet::new();
         let mut tx = self.db.transaction()?;
-        for file in Self::scan_fs(&mut self.fs)? {
-            if Self::_find_track_by_file(&mut tx, &file)?.is_none() {
-                fs.insert(file);
+        for file in snapshot {
+            if Self::_find_track_by_file(&mut tx, file)?.is_none() {
+                fs.insert(file.clone());
             }
         }
         tx.commit()?;
@@ -365,31 +636,311 @@ impl Storage {
     }
 
     /// Scans for untracked files, hashes them, and commits them to the database.
+    ///
+    /// Files are committed in batches of [`UPDATE_CHECKPOINT_FILES`], so an interrupted update
+    /// keeps already hashed files and the next run continues with the rest.
+    ///
+    /// A summary of every completed run is stored, see [`Storage::list_scans`].
     pub fn update_db_with_new_files(
         &mut self,
     ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
-        let new_files = self.check_new()?;
-        if !new_files.is_empty() {
-            println!("Hashing {} new files", new_files.len());
-        }
-        let with_hash = new_files.into_iter().map(|f| {
-            let path = self.fs.loc_resolver.resolve(&f.loc);
-            let path = match path {
-                Ok(path) => path,
-                Err(e) => return Err(StorageError::Internal(anyhow!("Failed to resolve a file location. Possibly a drive got removed during the operation: {e}"))),
+        let scan = LibraryScan::run(&mut self.fs)?;
+        let mut update = self.start_update(scan)?;
+        let new_files = mem::take(&mut update.new_files);
+        self.hash_and_insert_files(new_files, UPDATE_CHECKPOINT_FILES, &mut update)?;
+        self.finish_update(update)
+    }
+
+    /// Records which files of `scan` are still there and lists the new ones
+    pub fn start_update(&mut self, scan: LibraryScan) -> Result<PendingUpdate, StorageError> {
+        self.mark_seen(&scan.snapshot)?;
+        let gone = self
+            .missing_files(&scan.snapshot, &scan.unavailable)?
+            .into_values()
+            .flatten()
+            .map(|file| LocationRow::from_location(file.loc))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let new_files = self.new_files_in(&scan.snapshot)?;
+        Ok(PendingUpdate::new(scan, new_files, gone))
+    }
+
+    /// Commits a batch of hashed new files of `update`. A file with the content of a gone
+    /// file takes over its row instead, see [`Storage::move_files`]
+    pub fn insert_new_files(
+        &mut self,
+        update: &mut PendingUpdate,
+        files: Vec<HashedFile>,
+    ) -> Result<(), StorageError> {
+        let files = self.move_files(files, &mut update.gone)?;
+        for (track, files) in self.insert_files(files, &mut update.audit_id)? {
+            update.inserted.entry(track).or_default().extend(files);
+        }
+        Ok(())
+    }
+
+    /// Stores the summary of `update`, returns the inserted files
+    pub fn finish_update(
+        &mut self,
+        update: PendingUpdate,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        let PendingUpdate {
+            scan,
+            gone,
+            inserted,
+            ..
+        } = update;
+        let added: Vec<&FileWithMeta> = inserted.values().flatten().map(|f| &f.file).collect();
+        let roots = self
+            .fs
+            .enabled_roots()
+            .map(|root| RootScanReport {
+                root: root.location.to_string(),
+                available: !scan.unavailable.iter().any(|u| u.location == root.location),
+                files_seen: scan
+                    .snapshot
+                    .iter()
+                    .filter(|f| f.loc.starts_with(&root.location))
+                    .count(),
+                files_added: added
+                    .iter()
+                    .filter(|f| f.loc.starts_with(&root.location))
+                    .count(),
+            })
+            .collect();
+        self.record_scan(&ScanReport {
+            id: 0,
+            started_at: DateTime::from(scan.started_at),
+            duration: scan.started_at.elapsed().unwrap_or_default(),
+            files_seen: scan.snapshot.len(),
+            files_added: added.len(),
+            // files found elsewhere were moved, not removed
+            files_removed: gone.len(),
+            roots,
+        })?;
+
+        Ok(inserted)
+    }
+
+    /// Stores the scan summary, the id of `report` is ignored
+    fn record_scan(&mut self, report: &ScanReport) -> Result<(), StorageError> {
+        let started_at =
+            system_time_to_i64(report.started_at.into()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        tx.execute(
+            &format!(
+                "INSERT INTO {SCANS} ({STARTED_AT}, {DURATION_MS}, {FILES_SEEN}, {FILES_ADDED}, {FILES_REMOVED}) VALUES (?1, ?2, ?3, ?4, ?5)"
+            ),
+            params![
+                started_at,
+                report.duration.as_millis() as i64,
+                report.files_seen as i64,
+                report.files_added as i64,
+                report.files_removed as i64
+            ],
+        )?;
+        let scan_id = tx.last_insert_rowid();
+        for root in &report.roots {
+            tx.execute(
+                &format!(
+                    "INSERT INTO {SCAN_ROOTS} ({SCAN_ID}, {ROOT}, {AVAILABLE}, {FILES_SEEN}, {FILES_ADDED}) VALUES (?1, ?2, ?3, ?4, ?5)"
+                ),
+                params![
+                    scan_id,
+                    root.root,
+                    root.available,
+                    root.files_seen as i64,
+                    root.files_added as i64
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns summaries of the latest `limit` update runs, newest first
+    pub fn list_scans(&mut self, limit: usize) -> Result<Vec<ScanReport>, StorageError> {
+        let mut scans = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {SCAN_ID}, {STARTED_AT}, {DURATION_MS}, {FILES_SEEN}, {FILES_ADDED}, {FILES_REMOVED}
+                FROM {SCANS} ORDER BY {SCAN_ID} DESC LIMIT ?1"
+            ))?;
+            stmt.query_map(params![limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(id, started_at, duration_ms, seen, added, removed)| {
+                Ok(ScanReport {
+                    id,
+                    started_at: i64_seconds_to_local_time(started_at)
+                        .map_err(StorageError::Internal)?,
+                    duration: Duration::from_millis(duration_ms as u64),
+                    files_seen: seen as usize,
+                    files_added: added as usize,
+                    files_removed: removed as usize,
+                    roots: vec![],
+                })
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?
+        };
+
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT {ROOT}, {AVAILABLE}, {FILES_SEEN}, {FILES_ADDED} FROM {SCAN_ROOTS} WHERE {SCAN_ID} = ?1 ORDER BY rowid"
+        ))?;
+        for scan in &mut scans {
+            scan.roots = stmt
+                .query_map(params![scan.id], |row| {
+                    Ok(RootScanReport {
+                        root: row.get(0)?,
+                        available: row.get(1)?,
+                        files_seen: row.get::<_, i64>(2)? as usize,
+                        files_added: row.get::<_, i64>(3)? as usize,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        Ok(scans)
+    }
+
+    /// Points the rows of files in `gone` at the new locations of their content, so moved
+    /// files keep their row instead of getting a second one. Every move is logged separately
+    /// and can be undone.
+    ///
+    /// Returns the files that are not moves, a row in `gone` is taken by one file only.
+    fn move_files(
+        &mut self,
+        files: Vec<HashedFile>,
+        gone: &mut HashSet<LocationRow>,
+    ) -> Result<Vec<HashedFile>, StorageError> {
+        if gone.is_empty() {
+            return Ok(files);
+        }
+        let tx = self.db.transaction()?;
+        let mut not_moved = Vec::new();
+        let mut moved = false;
+        for file in files {
+            let candidates = tx
+                .prepare_cached(&format!(
+                    "SELECT {USB_LABEL}, {PATH}, {TRACK_ID} FROM {FILES} WHERE {FILE_HASH} = ?1"
+                ))?
+                .query_map(params![file.hash.to_string()], |row| {
+                    Ok((
+                        LocationRow {
+                            usb_label: row.get(0)?,
+                            path: row.get(1)?,
+                        },
+                        row.get::<_, TrackId>(2)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let Some((old, track_id)) = candidates.into_iter().find(|(row, _)| gone.contains(row))
+            else {
+                not_moved.push(file);
+                continue;
             };
-            let hash = FileHash::from_file(&path)?;
-            Ok(HashedFile::new(hash, f))
-        }).collect::<Result<Vec<_>, _>>()?;
-        self.insert_files(with_hash.clone())
+            gone.remove(&old);
+
+            let new = LocationRow::from_location(file.file.loc.clone())?;
+            let from: Location = old.clone().into();
+            let audit_id = audit::record(
+                &tx,
+                AuditOperation::Move,
+                &format!("{from} -> {}", file.file.loc),
+                [track_id],
+            )?;
+            let at = format!("{USB_LABEL} = ?1 AND {PATH} = ?2");
+            audit::record_files(
+                &tx,
+                audit_id,
+                FileChange::Removed,
+                &at,
+                params![old.usb_label, old.path],
+            )?;
+            let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+            tx.execute(
+                &format!(
+                    "UPDATE {FILES} SET {USB_LABEL} = ?1, {PATH} = ?2, {FILE_SIZE} = ?3,
+                        {LAST_SEEN} = ?4, {MODIFIED_AT} = ?5, {FORMAT} = ?6
+                    WHERE {USB_LABEL} = ?7 AND {PATH} = ?8"
+                ),
+                params![
+                    new.usb_label,
+                    new.path,
+                    file.file.file_size,
+                    now,
+                    self.fs.modified_time(&file.file.loc),
+                    file_format(&new.path),
+                    old.usb_label,
+                    old.path
+                ],
+            )?;
+            audit::record_files(
+                &tx,
+                audit_id,
+                FileChange::Added,
+                &at,
+                params![new.usb_label, new.path],
+            )?;
+            println!("Moved {from} to {}", file.file.loc);
+            moved = true;
+        }
+        if moved {
+            Self::insert_update_time(&tx)?;
+        }
+        tx.commit()?;
+        Ok(not_moved)
+    }
+
+    /// Hashes the files and inserts them in batches, see [`Storage::insert_new_files`]
+    fn hash_and_insert_files(
+        &mut self,
+        files: Vec<FileWithMeta>,
+        batch_size: usize,
+        update: &mut PendingUpdate,
+    ) -> Result<(), StorageError> {
+        let kind = self.fs.hash_kind();
+        let total_bytes = files.iter().map(|f| f.file_size as u64).sum();
+        self.fs.progress.hashing_started(files.len(), total_bytes);
+        let result = (|| {
+            for batch in files.chunks(batch_size) {
+                let with_hash = batch
+                    .iter()
+                    .map(|f| self.fs.hash_file(f, kind))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.insert_new_files(update, with_hash)?;
+            }
+            Ok(())
+        })();
+        self.fs.progress.hashing_finished();
+        result
     }
 
     /// checks for tracks without available files.
+    ///
+    /// Files under currently unavailable roots and files skipped because of their size
+    /// are not reported as missing.
     pub fn check_missing(
         &mut self,
     ) -> Result<HashMap<TrackId, HashSet<FileWithMeta>>, StorageError> {
+        let unavailable = self.fs.unavailable_roots();
         let fs = self.fs.scan()?;
+        self.mark_seen(&fs)?;
+        self.missing_files(&fs, &unavailable)
+    }
 
+    /// recorded files which are not in the snapshot, grouped by track
+    fn missing_files(
+        &mut self,
+        fs: &FsSnapshot,
+        unavailable: &[UnavailableRoot],
+    ) -> Result<HashMap<TrackId, HashSet<FileWithMeta>>, StorageError> {
         let mut track_db_locs: HashMap<TrackId, HashSet<FileWithMeta>> = Default::default();
 
         let tracks = self.get_tracks()?;
@@ -398,7 +949,14 @@ impl Storage {
         for track in tracks {
             let track_files = Self::_get_track_files(&mut tx, track)?;
             for db_file in track_files {
-                if !fs.contains(&db_file.file) {
+                let under_unavailable_root = unavailable
+                    .iter()
+                    .any(|root| db_file.file.loc.starts_with(&root.location));
+                if !under_unavailable_root
+                    && !self.fs.is_disabled(&db_file.file.loc)
+                    && !self.fs.exceeds_max_size(&db_file.file)
+                    && !fs.contains(&db_file.file)
+                {
                     track_db_locs
                         .entry(track)
                         .or_insert(Default::default())
@@ -454,15 +1012,42 @@ impl Storage {
         tx.prepare_cached(&update_cards_query)?
             .execute(rusqlite::params![master_id, slave_id])?;
 
-        // 4. Delete the slave track from the tracks ledger.
+        // 3a. Keep the slave track's places in playlists
+        let update_playlists_query =
+            format!("UPDATE {PLAYLIST_TRACKS} SET {TRACK_ID} = ?1 WHERE {TRACK_ID} = ?2");
+        tx.prepare_cached(&update_playlists_query)?
+            .execute(rusqlite::params![master_id, slave_id])?;
+
+        // 3b. A favourite slave makes the master a favourite
+        let favourite_query = format!(
+            "INSERT OR IGNORE INTO {FAVOURITES} ({TRACK_ID}, {CREATED_AT})
+            SELECT ?1, {CREATED_AT} FROM {FAVOURITES} WHERE {TRACK_ID} = ?2"
+        );
+        tx.prepare_cached(&favourite_query)?
+            .execute(rusqlite::params![master_id, slave_id])?;
+
+        // 4. Keep the slave id working as a card alias, it may be printed on cards already
+        let alias_query = format!(
+            "INSERT OR IGNORE INTO {CARD_MAPPINGS} ({CARD_ID}, {TRACK_ID}) VALUES (?1, ?2)"
+        );
+        tx.prepare_cached(&alias_query)?
+            .execute(rusqlite::params![slave_id.to_string(), master_id])?;
+
+        // 5. Delete the slave track from the tracks ledger.
         // Due to FOREIGN KEY (... ) ON DELETE CASCADE, this automatically deletes
         // the slave track's metadata entry from the track_metadata table.
         let delete_track_query = format!("DELETE FROM {TRACKS} WHERE {TRACK_ID} = ?1");
         tx.prepare_cached(&delete_track_query)?
             .execute(rusqlite::params![slave_id])?;
 
-        // 5. Update ledger tracking time since the library structures changed
+        // 6. Update ledger tracking time since the library structures changed
         Self::insert_update_time(&tx)?;
+        audit::record(
+            &tx,
+            AuditOperation::Merge,
+            &format!("track {slave_id} merged into {master_id}"),
+            [master_id, slave_id],
+        )?;
 
         tx.commit()?;
         Ok(())
@@ -479,6 +1064,7 @@ impl Storage {
         let location = self.fs.reverse_resolve(physical_path)?;
         // 2. Compute the file properties needed for insertion
         let file_size = std::fs::metadata(physical_path)?.len() as i64;
+        let modified = modified_secs(physical_path);
         let hash = FileHash::from_file(physical_path)?;
 
         let hashed_file = HashedFile::new(
@@ -491,9 +1077,15 @@ impl Storage {
         let mut tx = self.db.transaction()?;
         // Make sure master track exists
         let _ = Self::_resolve_track(&mut tx, master_id.to_string())?;
-        let inserted = Self::insert_file(&tx, master_id, &hashed_file)?;
+        let inserted = Self::insert_file(&tx, master_id, &hashed_file, modified)?;
         if inserted {
             Self::insert_update_time(&tx)?;
+            audit::record(
+                &tx,
+                AuditOperation::AddFile,
+                &hashed_file.file.loc.to_string(),
+                [master_id],
+            )?;
         }
         tx.commit()?;
         Ok(())
@@ -535,7 +1127,7 @@ impl Storage {
 
         let result = {
             let mut stmt = tx.prepare(&format!(
-                "SELECT {TRACK_ID}, {FILE_HASH}
+                "SELECT {TRACK_ID}, {FILE_HASH}, {HASH_KIND}
              FROM {FILES}
              WHERE {USB_LABEL} = ?1 AND {PATH} = ?2
              LIMIT 1"
@@ -547,8 +1139,9 @@ impl Storage {
             if let Some(row) = rows.next()? {
                 let track_id_raw: i64 = row.get(0)?;
                 let hash_str: String = row.get(1)?;
+                let kind: String = row.get(2)?;
 
-                Some((track_id_raw, hash_str))
+                Some((track_id_raw, hash_str, kind))
             } else {
                 None
             }
@@ -556,13 +1149,14 @@ impl Storage {
 
         // Map the database string hash and integer ID into the strongly-typed structures
         match result {
-            Some((track_id, hash_str)) => {
+            Some((track_id, hash_str, kind)) => {
                 let hash = FileHash::from_hex(&hash_str).map_err(|e| {
                     StorageError::Internal(anyhow!("Database contains invalid file hash {e}"))
                 })?;
 
                 let hashed_file = HashedFile {
                     hash,
+                    kind: parse_hash_kind(&kind)?,
                     file: file.clone(),
                 };
 
@@ -574,14 +1168,24 @@ impl Storage {
 
     /// retrieves file of the track, checking that it is a valid music file in the file system
     ///
-    /// If multiple paths point to the same track, chooses one of them.
+    /// If multiple paths point to the same track, prefers files from the root with the highest priority.
+    /// Quarantined files are never picked.
     pub fn find_track_file(
         &mut self,
         track_id: TrackId,
     ) -> Result<(TrackId, PathBuf, Location), StorageError> {
-        let paths = (|| {
+        if let Some(index) = self.track_index()? {
+            let paths = index.locations(track_id).unwrap_or_default().to_vec();
+            if paths.is_empty() {
+                return Err(StorageError::TrackNotFound(track_id.to_string()));
+            }
+            let (path, loc) = self.fs.pick_track_file(track_id, paths)?;
+            return Ok((track_id, path, loc));
+        }
+
+        let paths: Vec<Location> = (|| {
             let mut stmt = self.db.prepare(&format!(
-                "SELECT {USB_LABEL}, {PATH} FROM files WHERE {TRACK_ID} = ?1"
+                "SELECT {USB_LABEL}, {PATH} FROM files WHERE {TRACK_ID} = ?1 AND {QUARANTINED_AT} IS NULL"
             ))?;
 
             Ok(stmt
@@ -598,39 +1202,8 @@ impl Storage {
             return Err(StorageError::TrackNotFound(track_id.to_string()));
         }
 
-        let mut unmounted_locations = vec![];
-
-        for loc in paths {
-            let path = self.fs.loc_resolver.resolve(&loc);
-            match path {
-                Ok(p) => {
-                    if is_valid_music_path(&p) {
-                        return Ok((track_id, p, loc));
-                    }
-                }
-                Err(e) => match e {
-                    ResolveError::UsbNotFound { label } => unmounted_locations.push(label),
-                    ResolveError::SystemQueryFail(..) => {
-                        return Err(StorageError::Internal(anyhow!(
-                            "Error while resolving location {loc}: {e}"
-                        )));
-                    }
-                    ResolveError::WindowsError(..) => {
-                        return Err(StorageError::Internal(anyhow!(
-                            "Error while resolving location {loc}: {e}"
-                        )));
-                    }
-                },
-            }
-        }
-        Err(StorageError::InvalidTrackFile {
-            track: track_id,
-            extra: if !unmounted_locations.is_empty() {
-                format!("following drive labels are unmounted: {unmounted_locations:?}")
-            } else {
-                "".to_string()
-            },
-        })
+        let (path, loc) = self.fs.pick_track_file(track_id, paths)?;
+        Ok((track_id, path, loc))
     }
 
     fn _resolve_track(tx: &mut Transaction, card_id: CardId) -> Result<TrackId, StorageError> {
@@ -664,22 +1237,214 @@ impl Storage {
     ///
     /// If given id is a valid track id, tries it as it is as well
     pub fn resolve_track(&mut self, card_id: CardId) -> Result<TrackId, StorageError> {
+        if let Some(index) = self.track_index()? {
+            return index
+                .resolve(&card_id)
+                .ok_or(StorageError::TrackNotFound(card_id));
+        }
         let mut tx = self.db.transaction()?;
         let res = Self::_resolve_track(&mut tx, card_id)?;
         tx.commit()?;
         Ok(res)
     }
 
+    /// Lists all tracks with their recorded files, ordered by track id
+    pub fn list_tracks(&mut self) -> Result<Vec<TrackListEntry>, StorageError> {
+        self.list_tracks_matching(&TrackFilter::default())
+    }
+
+    /// Credited artists ordered by their sort name, "The Beatles" under B. Spellings
+    /// differing in case are one artist like for [`TrackFilter::artist`]. Tracks count for
+    /// each of their artists, featured or remixing ones too
+    pub fn list_artists(&mut self) -> Result<Vec<ArtistEntry>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT a.{NAME}, COUNT(DISTINCT ta.{TRACK_ID}) FROM {ARTISTS} a
+             JOIN {TRACK_ARTISTS} ta ON ta.{ARTIST_ID} = a.{ARTIST_ID}
+             GROUP BY a.{ARTIST_ID}"
+        ))?;
+        let mut artists: Vec<ArtistEntry> = stmt
+            .query_map([], |row| {
+                Ok(ArtistEntry {
+                    name: row.get(0)?,
+                    tracks: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        sort_names::sort_artists(&mut artists);
+        Ok(artists)
+    }
+
+    /// Years of release of the metadata, the earliest first. Tracks of unknown year are left out
+    pub fn list_years(&mut self) -> Result<Vec<YearEntry>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT {YEAR}, COUNT(*) FROM {TRACK_METADATA}
+             WHERE {YEAR} IS NOT NULL
+             GROUP BY {YEAR}
+             ORDER BY {YEAR}"
+        ))?;
+        Ok(stmt
+            .query_map([], |row| {
+                Ok(YearEntry {
+                    year: row.get(0)?,
+                    tracks: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Lists the tracks passing `filter` with all their recorded files, ordered by track id
+    pub fn list_tracks_matching(
+        &mut self,
+        filter: &TrackFilter,
+    ) -> Result<Vec<TrackListEntry>, StorageError> {
+        let mut conditions = String::new();
+        let mut values: Vec<Value> = vec![];
+        let metadata = |condition: &str| {
+            format!(
+                " AND EXISTS (SELECT 1 FROM {TRACK_METADATA} m
+                WHERE m.{TRACK_ID} = t.{TRACK_ID} AND {condition})"
+            )
+        };
+        if let Some(artist) = &filter.artist {
+            conditions.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM {TRACK_ARTISTS} ta
+                JOIN {ARTISTS} a ON a.{ARTIST_ID} = ta.{ARTIST_ID}
+                WHERE ta.{TRACK_ID} = t.{TRACK_ID} AND a.{NAME} = ?)"
+            ));
+            values.push(Value::Text(artist.trim().to_string()));
+        }
+        if let Some(year) = filter.year_from {
+            conditions.push_str(&metadata(&format!("m.{YEAR} >= ?")));
+            values.push(Value::Integer(year.into()));
+        }
+        if let Some(year) = filter.year_to {
+            conditions.push_str(&metadata(&format!("m.{YEAR} <= ?")));
+            values.push(Value::Integer(year.into()));
+        }
+        if let Some(format) = filter.normalized_format() {
+            conditions.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM {FILES} ff
+                WHERE ff.{TRACK_ID} = t.{TRACK_ID} AND ff.{FORMAT} = ?)"
+            ));
+            values.push(Value::Text(format));
+        }
+
+        let tx = self.db.transaction()?;
+        let rows = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT t.{TRACK_ID}, t.{CREATED_AT}, f.{USB_LABEL}, f.{PATH}, f.{FILE_SIZE}, f.{MODIFIED_AT}, f.{FORMAT},
+                md.{ARTIST}, md.{TITLE}, md.{YEAR}, md.{ARTIST_SORT}, md.{TITLE_SORT}
+             FROM {TRACKS} t
+             LEFT JOIN {FILES} f ON t.{TRACK_ID} = f.{TRACK_ID}
+             LEFT JOIN {TRACK_METADATA} md ON t.{TRACK_ID} = md.{TRACK_ID}
+             WHERE 1=1{conditions}
+             ORDER BY t.{TRACK_ID}, f.{USB_LABEL}, f.{PATH}"
+            ))?;
+
+            stmt.query_map(params_from_iter(values), |row| {
+                let track: (TrackId, Option<i64>) = (row.get(0)?, row.get(1)?);
+                let usb_label: Option<String> = row.get(2)?;
+                let path: Option<String> = row.get(3)?;
+                let details: (Option<i64>, Option<i64>, Option<String>) =
+                    (row.get(4)?, row.get(5)?, row.get(6)?);
+                let meta: (Option<String>, Option<String>, Option<u32>) =
+                    (row.get(7)?, row.get(8)?, row.get(9)?);
+                let sort: (Option<String>, Option<String>) = (row.get(10)?, row.get(11)?);
+                Ok((track, usb_label.zip(path), details, meta, sort))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        tx.commit()?;
+
+        let mut entries: Vec<TrackListEntry> = Vec::new();
+        for ((track_id, added), file, (size, modified, format), (artist, title, year), sort) in rows
+        {
+            if entries.last().map(|e| e.id) != Some(track_id) {
+                entries.push(TrackListEntry {
+                    id: track_id,
+                    added: added
+                        .map(i64_seconds_to_local_time)
+                        .transpose()
+                        .map_err(StorageError::Internal)?,
+                    files: vec![],
+                    meta: artist.zip(title).map(|(artist, title)| ListedMetadata {
+                        artist_sort: sort
+                            .0
+                            .unwrap_or_else(|| sort_names::artist_sort_name(&artist)),
+                        title_sort: sort.1.unwrap_or_else(|| sort_names::sort_name(&title)),
+                        artist,
+                        title,
+                        year,
+                    }),
+                });
+            }
+            if let (Some(entry), Some((usb_label, path))) = (entries.last_mut(), file) {
+                entry.files.push(ListedFile {
+                    loc: LocationRow { usb_label, path }.into(),
+                    size: size.unwrap_or_default(),
+                    modified: modified
+                        .map(i64_seconds_to_local_time)
+                        .transpose()
+                        .map_err(StorageError::Internal)?,
+                    format,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Every recorded file with its track and content hash, ordered by track id
+    pub fn list_hashed_files(&mut self) -> Result<Vec<(TrackId, HashedFile)>, StorageError> {
+        let rows = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {TRACK_ID}, {USB_LABEL}, {PATH}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}
+             FROM {FILES}
+             ORDER BY {TRACK_ID}, {USB_LABEL}, {PATH}"
+            ))?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, TrackId>(0)?,
+                    LocationRow {
+                        usb_label: row.get(1)?,
+                        path: row.get(2)?,
+                    },
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        rows.into_iter()
+            .map(|(track_id, loc, file_size, hash, kind)| {
+                Ok((
+                    track_id,
+                    HashedFile::with_kind(
+                        parse_file_hash(&hash)?,
+                        parse_hash_kind(&kind)?,
+                        FileWithMeta {
+                            loc: loc.into(),
+                            file_size,
+                        },
+                    ),
+                ))
+            })
+            .collect()
+    }
+
     pub fn find_track_file_with_meta(
         &mut self,
         track: TrackId,
     ) -> Result<(PathBuf, Location, Option<TrackMetadata>), StorageError> {
         let (_, path, loc) = self.find_track_file(track)?;
-        let meta = self.get_track_metadata(track)?;
+        let meta = match self.track_index()? {
+            Some(index) => index.metadata(track).cloned(),
+            None => self.get_track_metadata(track)?,
+        };
         Ok((path, loc, meta))
     }
 
-    /// searches for a file where path, track_id, hash, card_id, artist or title matches the query
+    /// searches for files matching the query, see [`crate::search`] for what it can ask
     ///
     /// conditionally selects only tracks without meta data
     pub fn find_files(
@@ -687,65 +1452,96 @@ impl Storage {
         query: &str,
         no_meta: bool,
     ) -> Result<HashMap<TrackId, HashSet<Location>>, StorageError> {
-        let tx = self.db.transaction()?;
+        let query = SearchQuery::parse(query, self.fs.transliterate_search())?;
 
-        let cleaned_query = query.trim().to_lowercase();
-        let like_query = format!("%{}%", cleaned_query);
+        let tx = self.db.transaction()?;
 
         // 1. Build base query with all required table joins using constants
         let mut sql = format!(
-            "SELECT DISTINCT f.{TRACK_ID}, f.{USB_LABEL}, f.{PATH}
+            "SELECT f.{TRACK_ID}, f.{USB_LABEL}, f.{PATH}, f.{FILE_HASH}, cm.{CARD_ID},
+                tm.{ARTIST}, tm.{TITLE}, tm.{LABEL}, tn.{NOTES}
              FROM {FILES} f
              LEFT JOIN {TRACK_METADATA} tm ON f.{TRACK_ID} = tm.{TRACK_ID}
              LEFT JOIN {CARD_MAPPINGS} cm ON f.{TRACK_ID} = cm.{TRACK_ID}
+             LEFT JOIN {TRACK_NOTES} tn ON f.{TRACK_ID} = tn.{TRACK_ID}
              WHERE 1=1"
         );
 
         // 2. Append conditional filters
-        if !cleaned_query.is_empty() {
-            sql.push_str(&format!(
-                " AND (
-                    LOWER(f.{PATH}) LIKE ?1 OR
-                    LOWER(f.{TRACK_ID}) LIKE ?1 OR
-                    LOWER(f.{FILE_HASH}) LIKE ?1 OR
-                    LOWER(cm.{CARD_ID}) LIKE ?1 OR
-                    LOWER(tm.{ARTIST}) LIKE ?1 OR
-                    LOWER(tm.{TITLE}) LIKE ?1
-                )"
-            ));
-        }
-
         if no_meta {
             sql.push_str(&format!(" AND tm.{TRACK_ID} IS NULL"));
         }
+        let mut values: Vec<Value> = vec![];
+        for term in query.filters() {
+            let condition = match &term.condition {
+                Condition::Year { from, to } => {
+                    let mut years = vec![];
+                    if let Some(from) = from {
+                        years.push(format!("m.{YEAR} >= ?"));
+                        values.push(Value::Integer((*from).into()));
+                    }
+                    if let Some(to) = to {
+                        years.push(format!("m.{YEAR} <= ?"));
+                        values.push(Value::Integer((*to).into()));
+                    }
+                    format!(
+                        "EXISTS (SELECT 1 FROM {TRACK_METADATA} m
+                        WHERE m.{TRACK_ID} = f.{TRACK_ID} AND {})",
+                        years.join(" AND ")
+                    )
+                }
+                Condition::Format(format) => {
+                    values.push(Value::Text(format.clone()));
+                    format!("COALESCE(f.{FORMAT}, '') = ?")
+                }
+                Condition::Playlist(name) => {
+                    values.push(Value::Text(name.clone()));
+                    format!(
+                        "EXISTS (SELECT 1 FROM {PLAYLIST_TRACKS} pt
+                        JOIN {PLAYLISTS} p ON p.{PLAYLIST_ID} = pt.{PLAYLIST_ID}
+                        WHERE pt.{TRACK_ID} = f.{TRACK_ID} AND LOWER(p.{NAME}) = LOWER(?))"
+                    )
+                }
+                Condition::Text { .. } => continue,
+            };
+            let not = if term.negated { "NOT " } else { "" };
+            sql.push_str(&format!(" AND {not}{condition}"));
+        }
 
-        // 3. Prepare statement and run execution cleanly via a single branch
+        // 3. Prepare statement and gather the cards of each file
         let mut stmt = tx.prepare(&sql)?;
 
-        let params = if !cleaned_query.is_empty() {
-            rusqlite::params![like_query]
-        } else {
-            rusqlite::params![]
-        };
-
-        let rows = stmt
-            .query_map(params, |row| {
-                let track_id: i64 = row.get(0)?;
-                let usb_label: String = row.get(1)?;
-                let path: String = row.get(2)?;
-
-                let loc: Location = LocationRow { usb_label, path }.into();
-                Ok((track_id, loc))
-            })?
-            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        let mut files: HashMap<(String, String), SearchedFile> = HashMap::new();
+        let mut rows = stmt.query(params_from_iter(values))?;
+        while let Some(row) = rows.next()? {
+            let file = files
+                .entry((row.get(1)?, row.get(2)?))
+                .or_insert(SearchedFile {
+                    track_id: row.get(0)?,
+                    path: row.get(2)?,
+                    hash: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                    cards: vec![],
+                    artist: row.get(5)?,
+                    title: row.get(6)?,
+                    label: row.get(7)?,
+                    notes: row.get(8)?,
+                });
+            if let Some(card) = row.get(4)? {
+                file.cards.push(card);
+            }
+        }
 
+        drop(rows);
         drop(stmt);
         tx.commit()?;
 
         // 4. Construct response hash map grouping locations by track ID
         let mut map: HashMap<TrackId, HashSet<Location>> = HashMap::new();
-        for (track_id, loc) in rows {
-            map.entry(track_id).or_default().insert(loc);
+        for ((usb_label, path), file) in files {
+            if query.matches(&file) {
+                let loc: Location = LocationRow { usb_label, path }.into();
+                map.entry(file.track_id).or_default().insert(loc);
+            }
         }
 
         Ok(map)
@@ -806,6 +1602,7 @@ impl Storage {
 
         if removed_tracks > 0 {
             Self::insert_update_time(&tx)?;
+            audit::record(&tx, AuditOperation::Clean, "", dangling_track_ids)?;
         }
 
         tx.commit()?;
@@ -818,24 +1615,18 @@ impl Storage {
     pub fn forget_path(&mut self, path: &Path) -> Result<ForgetReport, StorageError> {
         let tx = self.db.transaction()?;
 
-        let path_prefix = replace_windows_slashes(path);
-
-        let dir_prefix = if path_prefix.ends_with(LOCATION_PATH_SEP) {
-            path_prefix.clone()
-        } else {
-            format!("{}{}%", path_prefix, LOCATION_PATH_SEP)
-        };
+        let (path_prefix, dir_start, dir_end) = path_range(path);
         // --------------------------------------------------
         // Collect affected track ids BEFORE deletion
         // --------------------------------------------------
 
         let mut stmt = tx.prepare(&format!(
             "SELECT DISTINCT {TRACK_ID} FROM {FILES}
-         WHERE {PATH} = ?1 OR {PATH} LIKE ?2"
+         WHERE {PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)"
         ))?;
 
         let affected_track_ids = stmt
-            .query_map(params![path_prefix, dir_prefix], |row| {
+            .query_map(params![path_prefix, dir_start, dir_end], |row| {
                 row.get::<_, TrackId>(0)
             })?
             .collect::<Result<Vec<_>, _>>()?;
@@ -844,6 +1635,24 @@ impl Storage {
 
         let affected_tracks = affected_track_ids.len();
 
+        // --------------------------------------------------
+        // Keep copies of the entries in the audit log, so forget can be undone
+        // --------------------------------------------------
+
+        let audit_id = audit::record(
+            &tx,
+            AuditOperation::Forget,
+            &path_prefix,
+            affected_track_ids.iter().copied(),
+        )?;
+        audit::record_files(
+            &tx,
+            audit_id,
+            FileChange::Removed,
+            &format!("{PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)"),
+            params![path_prefix, dir_start, dir_end],
+        )?;
+
         // --------------------------------------------------
         // Delete entries
         // --------------------------------------------------
@@ -851,9 +1660,9 @@ impl Storage {
         let removed_files = tx.execute(
             &format!(
                 "DELETE FROM {FILES}
-             WHERE {PATH} = ?1 OR {PATH} LIKE ?2"
+             WHERE {PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)"
             ),
-            params![path_prefix, dir_prefix],
+            params![path_prefix, dir_start, dir_end],
         )?;
 
         // --------------------------------------------------
@@ -891,6 +1700,93 @@ impl Storage {
         })
     }
 
+    /// Removes the files whose path matches the glob, e.g. every WAV copy in a backup directory.
+    /// Undone like [`Storage::forget_path`]
+    pub fn forget_matching(&mut self, glob: &PathGlob) -> Result<ForgetReport, StorageError> {
+        let tx = self.db.transaction()?;
+
+        let matched = Self::paths_matching(&tx, glob)?;
+        let affected_track_ids: Vec<TrackId> = matched
+            .iter()
+            .map(|(_, track_id)| *track_id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let audit_id = audit::record(
+            &tx,
+            AuditOperation::Forget,
+            &glob.to_string(),
+            affected_track_ids.iter().copied(),
+        )?;
+        let mut removed_files = 0;
+        // a path can be stored for several USB drives
+        let paths: BTreeSet<&String> = matched.iter().map(|(path, _)| path).collect();
+        for path in paths {
+            audit::record_files(
+                &tx,
+                audit_id,
+                FileChange::Removed,
+                &format!("{PATH} = ?1"),
+                params![path],
+            )?;
+            removed_files += tx.execute(
+                &format!("DELETE FROM {FILES} WHERE {PATH} = ?1"),
+                params![path],
+            )?;
+        }
+
+        let mut removed_tracks = 0;
+        for track_id in &affected_track_ids {
+            let remaining: isize = tx.query_row(
+                &format!("SELECT COUNT(*) FROM {FILES} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |row| row.get(0),
+            )?;
+            if remaining == 0 {
+                removed_tracks += 1;
+            }
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+
+        Ok(ForgetReport {
+            removed_tracks,
+            affected_tracks: affected_track_ids.len(),
+            removed_files,
+        })
+    }
+
+    /// Distinct stored paths matching the glob with their tracks, only the paths under
+    /// the literal start of the pattern are read
+    fn paths_matching(
+        tx: &Transaction,
+        glob: &PathGlob,
+    ) -> Result<Vec<(String, TrackId)>, StorageError> {
+        let prefix = glob.literal_prefix();
+        let mut rows = Vec::new();
+        if prefix.is_empty() {
+            let mut stmt =
+                tx.prepare(&format!("SELECT DISTINCT {PATH}, {TRACK_ID} FROM {FILES}"))?;
+            for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+                rows.push(row?);
+            }
+        } else {
+            let (path, start, end) = path_range(Path::new(&prefix));
+            let mut stmt = tx.prepare(&format!(
+                "SELECT DISTINCT {PATH}, {TRACK_ID} FROM {FILES}
+                WHERE {PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)"
+            ))?;
+            for row in stmt.query_map(params![path, start, end], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })? {
+                rows.push(row?);
+            }
+        }
+        rows.retain(|(path, _): &(String, TrackId)| glob.matches(path));
+        Ok(rows)
+    }
+
     pub fn update_track_metadata(
         &mut self,
         track_id: TrackId,
@@ -899,71 +1795,175 @@ impl Storage {
     ) -> Result<(), StorageError> {
         let tx = self.db.transaction()?;
 
-        // ---------- load current metadata ----------
-        let current_meta: Option<TrackMetadata> = (|| {
-            let mut stmt = tx.prepare(&format!(
-                "SELECT {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL}
-             FROM {TRACK_METADATA}
-             WHERE {TRACK_ID} = ?1"
-            ))?;
+        let current_meta = Self::load_metadata(&tx, track_id)?;
+        let merged = Self::update_meta(track_id, current_meta.clone(), new_meta, allow_overwrite)?;
+        let details = audit::describe_metadata(&merged);
+        let changed = provenance::changed_fields(current_meta.as_ref(), &merged);
 
-            let mut rows = stmt.query(params![track_id.to_string()])?;
+        Self::store_metadata(&tx, track_id, merged)?;
+        provenance::record_sources(&tx, track_id, &changed, MetadataOrigin::Manual)?;
+        Self::insert_update_time(&tx)?;
+        audit::record(&tx, AuditOperation::Metadata, &details, [track_id])?;
 
-            if let Some(row) = rows.next()? {
-                Ok::<_, rusqlite::Error>(Some(TrackMetadata {
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Sets the given fields on every track with a file under the path, e.g. the label of
+    /// an album folder. All tracks are updated in one transaction.
+    ///
+    /// Fields are merged as in [`Storage::update_track_metadata`]. Tracks that would need
+    /// an overwrite or have no metadata to add to are skipped and listed in the report
+    pub fn apply_metadata_under(
+        &mut self,
+        path: &Path,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<ApplyMetadataReport, StorageError> {
+        let tx = self.db.transaction()?;
+        let (path_prefix, dir_start, dir_end) = path_range(path);
+
+        let track_ids = tx
+            .prepare(&format!(
+                "SELECT DISTINCT {TRACK_ID} FROM {FILES}
+             WHERE {PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)
+             ORDER BY {TRACK_ID}"
+            ))?
+            .query_map(params![path_prefix, dir_start, dir_end], |row| {
+                row.get::<_, TrackId>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut report = ApplyMetadataReport {
+            matched_tracks: track_ids.len(),
+            ..Default::default()
+        };
+        for track_id in track_ids {
+            let current_meta = Self::load_metadata(&tx, track_id)?;
+            let merged = match Self::update_meta(
+                track_id,
+                current_meta.clone(),
+                new_meta.clone(),
+                allow_overwrite,
+            ) {
+                Ok(merged) => merged,
+                Err(StorageError::MetadataOverwriteDenied(_)) => {
+                    report.kept.push(track_id);
+                    continue;
+                }
+                Err(StorageError::RequiredMetaMissing(_)) => {
+                    report.missing_required.push(track_id);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if current_meta.as_ref() != Some(&merged) {
+                let changed = provenance::changed_fields(current_meta.as_ref(), &merged);
+                Self::store_metadata(&tx, track_id, merged)?;
+                provenance::record_sources(&tx, track_id, &changed, MetadataOrigin::Manual)?;
+                report.updated.push(track_id);
+            }
+        }
+
+        if !report.updated.is_empty() {
+            Self::insert_update_time(&tx)?;
+            audit::record(
+                &tx,
+                AuditOperation::Metadata,
+                &path_prefix,
+                report.updated.iter().copied(),
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(report)
+    }
+
+    pub(crate) fn load_metadata(
+        tx: &Transaction,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError> {
+        let mut stmt = tx.prepare_cached(&format!(
+            "SELECT {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL}
+             FROM {TRACK_METADATA}
+             WHERE {TRACK_ID} = ?1"
+        ))?;
+        let meta = stmt
+            .query_row(params![track_id.to_string()], |row| {
+                Ok(TrackMetadata {
                     title: row.get(0)?,
                     artist: row.get(1)?,
                     year: row.get(2)?,
                     label: row.get(3)?,
                     artwork: row.get::<_, Option<String>>(4)?.map(ArtworkRef),
-                }))
-            } else {
-                Ok(None)
-            }
-        })()?;
-
-        let merged = Self::update_meta(track_id, current_meta, new_meta, allow_overwrite)?;
+                })
+            })
+            .optional()?;
+        Ok(meta)
+    }
 
-        // ---------- upsert ----------
-        let _ = tx
-            .execute(
-                &format!(
-                    "INSERT INTO {TRACK_METADATA}
-            ({TRACK_ID}, {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL})
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    /// Inserts or replaces the metadata row of the track, crediting its artists again if
+    /// the artist or title changed
+    pub(crate) fn store_metadata(
+        tx: &Transaction,
+        track_id: TrackId,
+        meta: TrackMetadata,
+    ) -> Result<(), StorageError> {
+        let previous: Option<(String, String)> = tx
+            .query_row(
+                &format!("SELECT {ARTIST}, {TITLE} FROM {TRACK_METADATA} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let credits = (previous.as_ref() != Some(&(meta.artist.clone(), meta.title.clone())))
+            .then(|| artists::parse_credits(&meta.artist, &meta.title));
+        tx.execute(
+            &format!(
+                "INSERT INTO {TRACK_METADATA}
+            ({TRACK_ID}, {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL}, {ARTIST_SORT}, {TITLE_SORT})
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
             ON CONFLICT({TRACK_ID}) DO UPDATE SET
                 {TITLE} = excluded.{TITLE},
                 {ARTIST} = excluded.{ARTIST},
                 {YEAR} = excluded.{YEAR},
                 {LABEL} = excluded.{LABEL},
-                {ARTWORK_URL} = excluded.{ARTWORK_URL}
+                {ARTWORK_URL} = excluded.{ARTWORK_URL},
+                -- sort names set by hand are kept while the name is
+                {ARTIST_SORT} = CASE WHEN {ARTIST} = excluded.{ARTIST}
+                    THEN COALESCE({ARTIST_SORT}, excluded.{ARTIST_SORT}) ELSE excluded.{ARTIST_SORT} END,
+                {TITLE_SORT} = CASE WHEN {TITLE} = excluded.{TITLE}
+                    THEN COALESCE({TITLE_SORT}, excluded.{TITLE_SORT}) ELSE excluded.{TITLE_SORT} END
             "
-                ),
-                params![
-                    track_id.to_string(),
-                    merged.title,
-                    merged.artist,
-                    merged.year,
-                    merged.label,
-                    merged.artwork.map(|a| a.0),
-                ],
-            )
-            .map_err(|e| match e {
-                rusqlite::Error::SqliteFailure(error, _)
-                    if error.code == ErrorCode::ConstraintViolation =>
-                {
-                    StorageError::TrackNotFound(track_id.to_string())
-                }
-                e => StorageError::Database(e),
-            })?;
-        Self::insert_update_time(&tx)?;
-
-        tx.commit()?;
-
+            ),
+            params![
+                track_id.to_string(),
+                meta.title,
+                meta.artist,
+                meta.year,
+                meta.label,
+                meta.artwork.map(|a| a.0),
+                sort_names::artist_sort_name(&meta.artist),
+                sort_names::sort_name(&meta.title),
+            ],
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(error, _)
+                if error.code == ErrorCode::ConstraintViolation =>
+            {
+                StorageError::TrackNotFound(track_id.to_string())
+            }
+            e => StorageError::Database(e),
+        })?;
+        if let Some(credits) = credits {
+            artists::link_credits(tx, track_id, &credits)?;
+        }
         Ok(())
     }
 
-    fn update_meta(
+    /// Merges new metadata into the current one, see [`Storage::update_track_metadata`]
+    pub(crate) fn update_meta(
         track: TrackId,
         old: Option<TrackMetadata>,
         new: MetadataUpdate,
@@ -1021,18 +2021,72 @@ impl Storage {
     }
 }
 
+/// Stored paths at `path` and under it: the path itself, and the bounds of the half-open
+/// range of paths inside the directory, e.g. `/music/` to `/music0`.
+///
+/// Unlike a `LIKE` pattern, the range uses the index on paths and treats `%` and `_`
+/// in names literally
+pub(crate) fn path_range(path: &Path) -> (String, String, String) {
+    let path = replace_windows_slashes(path);
+    let path = match path.strip_suffix(LOCATION_PATH_SEP) {
+        Some(dir) => dir.to_string(),
+        None => path,
+    };
+    let start = format!("{path}{LOCATION_PATH_SEP}");
+    // the character following the separator, so every path starting with `start` is below it
+    let end = format!("{path}{}", char::from(LOCATION_PATH_SEP.as_bytes()[0] + 1));
+    (path, start, end)
+}
+
 /// DB format of storing file location
-#[derive(Debug)]
-struct LocationRow {
-    /// present if file is stored on usb, empty otherwise
-    usb_label: String,
-    /// relative path if stored on usb, absolute otherwise
-    path: String,
+pub(crate) fn parse_file_hash(hash: &str) -> Result<FileHash, StorageError> {
+    FileHash::from_hex(hash)
+        .map_err(|e| StorageError::Internal(anyhow!("Database contains invalid file hash {e}")))
+}
+
+pub(crate) fn parse_hash_kind(kind: &str) -> Result<HashKind, StorageError> {
+    kind.parse()
+        .map_err(|e| StorageError::Internal(anyhow!("Database contains invalid file row: {e}")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct LocationRow {
+    /// present if file is stored on usb, the base URL for remote files,
+    /// [`SHARE_LABEL_PREFIX`] and the mount point for network shares,
+    /// [`S3_LABEL_PREFIX`] and `bucket@endpoint` for object storage, empty otherwise
+    pub(crate) usb_label: String,
+    /// relative path if stored on usb, a share or remote, the object key in object storage,
+    /// absolute otherwise
+    pub(crate) path: String,
 }
 
+/// Marks network share mount points in the usb label column
+const SHARE_LABEL_PREFIX: &str = "share:";
+/// Marks buckets in the usb label column, bucket names can't contain `@`
+const S3_LABEL_PREFIX: &str = "s3:";
+
 impl LocationRow {
     pub fn is_usb(&self) -> bool {
         !self.usb_label.is_empty()
+            && !self.is_remote()
+            && self.share_mount().is_none()
+            && self.s3_bucket().is_none()
+    }
+
+    /// USB labels can't contain a URL scheme separator
+    pub fn is_remote(&self) -> bool {
+        self.usb_label.contains("://") && self.s3_bucket().is_none()
+    }
+
+    /// Bucket and endpoint
+    fn s3_bucket(&self) -> Option<(&str, &str)> {
+        self.usb_label
+            .strip_prefix(S3_LABEL_PREFIX)?
+            .split_once('@')
+    }
+
+    fn share_mount(&self) -> Option<&str> {
+        self.usb_label.strip_prefix(SHARE_LABEL_PREFIX)
     }
 }
 
@@ -1055,6 +2109,26 @@ impl LocationRow {
                     }
                 }
             }
+            Location::NetworkShare { mount, path, .. } => LocationRow {
+                usb_label: format!("{SHARE_LABEL_PREFIX}{}", replace_windows_slashes(&mount)),
+                path: replace_windows_slashes(&path),
+            },
+            Location::S3 {
+                endpoint,
+                bucket,
+                path,
+                ..
+            } => LocationRow {
+                usb_label: format!(
+                    "{S3_LABEL_PREFIX}{bucket}@{}",
+                    endpoint.trim_end_matches('/')
+                ),
+                path: replace_windows_slashes(&path),
+            },
+            Location::Remote { base_url, path, .. } => LocationRow {
+                usb_label: base_url.trim_end_matches('/').to_string(),
+                path: replace_windows_slashes(&path),
+            },
         })
     }
 }
@@ -1062,8 +2136,33 @@ impl LocationRow {
 impl Into<Location> for LocationRow {
     fn into(self) -> Location {
         let is_usb = self.is_usb();
+        let is_remote = self.is_remote();
+        let share_mount = self.share_mount().map(PathBuf::from);
+        let s3_bucket = self
+            .s3_bucket()
+            .map(|(bucket, endpoint)| (bucket.to_string(), endpoint.to_string()));
         let path = PathBuf::from(self.path);
-        if is_usb {
+        if let Some((bucket, endpoint)) = s3_bucket {
+            Location::S3 {
+                endpoint,
+                bucket,
+                region: None,
+                credentials: None,
+                path,
+            }
+        } else if let Some(mount) = share_mount {
+            Location::NetworkShare {
+                mount,
+                source: None,
+                path,
+            }
+        } else if is_remote {
+            Location::Remote {
+                base_url: self.usb_label,
+                token: None,
+                path,
+            }
+        } else if is_usb {
             Location::Usb {
                 label: self.usb_label,
                 path,
@@ -1074,7 +2173,7 @@ impl Into<Location> for LocationRow {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MetadataUpdate {
     pub artist: Option<String>,
     pub title: Option<String>,
@@ -1088,21 +2187,39 @@ mod tests {
     use std::{
         collections::{HashMap, HashSet},
         fs::{self},
+        mem,
         path::{Path, PathBuf},
+        sync::{Arc, Mutex},
     };
 
     use rusqlite::{Connection, params};
     use tempfile::tempdir;
 
+    use chrono::Local;
+
     use crate::{
-        config::LibrarySource,
+        artists::{ArtistCredit, ArtistRole},
+        audit::AuditOperation,
+        card_scans::{CardScan, ScanOutcome},
+        config::{Config, Database, LibraryRoot, LibrarySource},
         error::StorageError,
-        file_hash::FileHash,
-        fs::{FileWithMeta, HashedFile},
-        location::Location,
-        operations::{MetadataUpdate, Storage, replace_windows_slashes},
+        file_hash::{FileHash, HashKind},
+        fs::{FileStorage, FileWithMeta, HashedFile},
+        glob::PathGlob,
+        location::{Location, S3Credentials},
+        lyrics::TrackLyrics,
+        operations::{
+            ArtistEntry, ListedMetadata, MetadataUpdate, Storage, TrackFilter, YearEntry,
+            replace_windows_slashes,
+        },
+        progress::Progress,
+        provenance::{MetaField, MetadataOrigin, MetadataPolicy},
         schema::{self, *},
-        track::TrackId,
+        short_links::SHORT_CODE_LEN,
+        snapshot::LibraryState,
+        sort_names::{self, SortNames},
+        track::{ArtworkRef, TrackId, TrackMetadata},
+        update::LibraryScan,
         usb::LocationResolver,
     };
 
@@ -1128,11 +2245,15 @@ mod tests {
         Ok(Storage::from_existing_conn(
             conn,
             LibrarySource {
-                roots: vec![Location::File {
-                    path: tmp_dir.to_path_buf(),
-                }],
+                roots: vec![
+                    Location::File {
+                        path: tmp_dir.to_path_buf(),
+                    }
+                    .into(),
+                ],
                 follow_symlinks: false,
                 ignored_dirs: vec![],
+                ..Default::default()
             },
         ))
     }
@@ -1147,6 +2268,7 @@ mod tests {
                 roots: vec![],
                 follow_symlinks: false,
                 ignored_dirs: vec![],
+                ..Default::default()
             },
         ))
     }
@@ -1353,6 +2475,10 @@ mod tests {
         )?;
         assert_eq!(slave_meta_exists, 0);
 
+        // Assert 4: The slave id still resolves, as an alias of the master
+        drop(stmt);
+        assert_eq!(storage.resolve_track(slave.to_string())?, master);
+
         Ok(())
     }
 
@@ -1402,6 +2528,128 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_interrupted_update_keeps_committed_batches() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path1 = dir.path().join("a.mp3");
+        let path2 = dir.path().join("b.mp3");
+        std::fs::write(&path1, b"audio_a")?;
+        std::fs::write(&path2, b"audio_b")?;
+
+        let mut storage = setup_storage(dir.path())?;
+        let scan = LibraryScan::run(&mut storage.fs)?;
+        let mut update = storage.start_update(scan)?;
+        let files = mem::take(&mut update.new_files);
+        // second file disappears before it gets hashed
+        std::fs::remove_file(&path2)?;
+
+        assert!(
+            storage
+                .hash_and_insert_files(files, 1, &mut update)
+                .is_err()
+        );
+
+        let remaining = storage.check_new()?;
+        assert!(remaining.is_empty());
+        let first = FileWithMeta {
+            loc: Location::from_path(&path1),
+            file_size: 7,
+        };
+        assert!(Storage::_find_track_by_file(&mut storage.db.transaction()?, &first)?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_reports_progress() -> anyhow::Result<()> {
+        #[derive(Debug, Default)]
+        struct Events {
+            found: usize,
+            hashing: Option<(usize, u64)>,
+            hashed_bytes: u64,
+            finished: bool,
+        }
+
+        #[derive(Debug)]
+        struct Recorder(Arc<Mutex<Events>>);
+
+        impl Progress for Recorder {
+            fn file_found(&mut self, _loc: &Location) {
+                self.0.lock().unwrap().found += 1;
+            }
+            fn hashing_started(&mut self, files: usize, bytes: u64) {
+                self.0.lock().unwrap().hashing = Some((files, bytes));
+            }
+            fn file_hashed(&mut self, _loc: &Location, bytes: u64) {
+                self.0.lock().unwrap().hashed_bytes += bytes;
+            }
+            fn hashing_finished(&mut self) {
+                self.0.lock().unwrap().finished = true;
+            }
+        }
+
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("a.mp3"), b"audio_a")?;
+        std::fs::write(dir.path().join("b.mp3"), b"audio_bb")?;
+
+        let mut storage = setup_storage(dir.path())?;
+        let events = Arc::new(Mutex::new(Events::default()));
+        storage.set_progress(Box::new(Recorder(events.clone())));
+        storage.update_db_with_new_files()?;
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.found, 2);
+        assert_eq!(events.hashing, Some((2, 15)));
+        assert_eq!(events.hashed_bytes, 15);
+        assert!(events.finished);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_records_scan_report() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path1 = dir.path().join("a.mp3");
+        let path2 = dir.path().join("b.mp3");
+        std::fs::write(&path1, b"audio_a")?;
+        std::fs::write(&path2, b"audio_b")?;
+
+        let mut storage = setup_storage(dir.path())?;
+        storage.update_db_with_new_files()?;
+        std::fs::remove_file(&path2)?;
+        storage.update_db_with_new_files()?;
+
+        let scans = storage.list_scans(10)?;
+        assert_eq!(scans.len(), 2);
+
+        let (latest, first) = (&scans[0], &scans[1]);
+        assert!(latest.id > first.id);
+        assert_eq!(
+            (first.files_seen, first.files_added, first.files_removed),
+            (2, 2, 0)
+        );
+        assert_eq!(
+            (latest.files_seen, latest.files_added, latest.files_removed),
+            (1, 0, 1)
+        );
+
+        assert_eq!(first.roots.len(), 1);
+        assert_eq!(
+            first.roots[0].root,
+            Location::from_path(dir.path()).to_string()
+        );
+        assert!(first.roots[0].available);
+        assert_eq!(first.roots[0].files_seen, 2);
+        assert_eq!(first.roots[0].files_added, 2);
+
+        assert_eq!(storage.list_scans(1)?.len(), 1);
+
+        let log = storage.audit_log(None, 10)?;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].operation, AuditOperation::Update);
+        assert_eq!(log[0].tracks.len(), 2);
+        Ok(())
+    }
+
     #[test]
     fn test_update_db_with_new_files() -> anyhow::Result<()> {
         let dir = tempdir()?;
@@ -1459,44 +2707,337 @@ mod tests {
     }
 
     #[test]
-    fn test_insert_files_fresh_tracks() -> anyhow::Result<()> {
-        let mut storage = setup_clean_storage()?;
-
-        let file_a = HashedFile::new(
-            mock_hash(1),
-            FileWithMeta {
-                loc: Location::from_path("a.mp3"),
-                file_size: 100,
-            },
-        );
-        let file_b = HashedFile::new(
-            mock_hash(2),
-            FileWithMeta {
-                loc: Location::from_path("b.mp3"),
-                file_size: 200,
+    fn test_unmounted_share_files_are_kept() -> anyhow::Result<()> {
+        // an empty mount point directory, nothing is mounted there
+        let mount = tempdir()?;
+        let share = Location::NetworkShare {
+            mount: mount.path().to_path_buf(),
+            source: Some("//nas/music".to_string()),
+            path: PathBuf::new(),
+        };
+        let conn = rusqlite::Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(
+            conn,
+            LibrarySource {
+                roots: vec![share.clone().into()],
+                ..Default::default()
             },
         );
+        // one mount check for the whole test, each waits for the share to come up
+        storage.fs.loc_resolver = LocationResolver::test_resolver([]);
+        let inserted = storage.insert_files(
+            [HashedFile::new(
+                mock_hash(1),
+                FileWithMeta {
+                    loc: share.join(Path::new("song.mp3")),
+                    file_size: 1,
+                },
+            )],
+            &mut None,
+        )?;
+        let track = *inserted.keys().next().unwrap();
 
-        // Path 1: Insert completely brand new files
-        let result = storage.insert_files([file_a.clone(), file_b.clone()])?;
-
-        // Should return both items under 2 distinct generated track IDs
-        assert_eq!(result.len(), 2);
-
-        // Verify update time was bumped because rows were inserted
-        let count: i64 =
-            storage
-                .db
-                .query_row(&format!("SELECT COUNT(*) FROM {UPDATES}"), [], |r| r.get(0))?;
-        assert_eq!(count, 1);
+        let unavailable = storage.unavailable_roots();
+        assert_eq!(unavailable.len(), 1);
+        assert!(
+            unavailable[0]
+                .reason
+                .contains("not mounted from //nas/music"),
+            "{}",
+            unavailable[0].reason
+        );
 
+        storage.update_db_with_new_files()?;
+        assert_eq!(storage.list_scans(1)?[0].files_removed, 0);
+        assert!(storage.check_missing()?.is_empty());
+        match storage.find_track_file(track) {
+            Err(StorageError::InvalidTrackFile { extra, .. }) => {
+                assert!(extra.contains("network shares are not mounted"), "{extra}")
+            }
+            other => panic!("expected an unmounted share, got {other:?}"),
+        }
         Ok(())
     }
 
-    #[test]
-    fn test_insert_files_reuses_track_id_for_matching_hashes() -> anyhow::Result<()> {
-        let mut storage = setup_clean_storage()?;
-        let shared_hash = mock_hash(1);
+    /// Minimal localdeck instance answering `/sync` with the given libraries, one per request.
+    /// Returns its base URL and the `Authorization` headers it received
+    fn serve_remote_libraries(libraries: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let auth = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&auth);
+        std::thread::spawn(move || {
+            let mut libraries = libraries.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':')
+                        && name.eq_ignore_ascii_case("authorization")
+                    {
+                        seen.lock().unwrap().push(value.trim().to_string());
+                    }
+                }
+                let body = if request_line.starts_with("GET /sync ") {
+                    libraries.next().unwrap_or_default()
+                } else {
+                    "ok".to_string()
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        (base_url, auth)
+    }
+
+    fn remote_track_json(track_id: TrackId, hash: &FileHash) -> String {
+        format!(
+            r#"{{"track_id": {track_id}, "files": [{{"hash": "{}", "kind": "full", "file_size": 7, "name": "song.mp3"}}], "metadata": null}}"#,
+            hash.to_hex()
+        )
+    }
+
+    #[test]
+    fn test_remote_root_tracks() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let local = dir.path().join("a.mp3");
+        std::fs::write(&local, b"audio_a")?;
+        let shared = FileHash::from_file(&local)?;
+        let remote_only = mock_hash(8);
+
+        let without_8 = format!(
+            r#"{{"tracks": [{}], "playlists": []}}"#,
+            remote_track_json(7, &shared)
+        );
+        let (base_url, auth) = serve_remote_libraries(vec![
+            format!(
+                r#"{{"tracks": [{}, {}], "playlists": []}}"#,
+                remote_track_json(7, &shared),
+                remote_track_json(8, &remote_only)
+            ),
+            without_8.clone(),
+            without_8,
+        ]);
+        let conn = rusqlite::Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(
+            conn,
+            LibrarySource {
+                roots: vec![
+                    Location::from_path(dir.path()).into(),
+                    Location::Remote {
+                        base_url: base_url.clone(),
+                        token: Some("secret".to_string()),
+                        path: PathBuf::new(),
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            },
+        );
+
+        let inserted = storage.update_db_with_new_files()?;
+        // the local copy and the remote one are the same track
+        assert_eq!(inserted.len(), 2);
+        let track_of = |hash: &FileHash| {
+            inserted
+                .iter()
+                .find(|(_, files)| files.iter().any(|f| &f.hash == hash))
+                .map(|(track, files)| (*track, files.len()))
+                .unwrap()
+        };
+        assert_eq!(track_of(&shared).1, 2);
+        let (remote_track, _) = track_of(&remote_only);
+
+        let (_, path, loc) = storage.find_track_file(remote_track)?;
+        assert_eq!(path, PathBuf::from(format!("{base_url}/tracks/8/stream")));
+        assert_eq!(
+            loc,
+            Location::Remote {
+                base_url: base_url.clone(),
+                token: Some("secret".to_string()),
+                path: PathBuf::from("tracks/8/stream"),
+            }
+        );
+        let auth = auth.lock().unwrap().clone();
+        assert!(!auth.is_empty() && auth.iter().all(|a| a == "Bearer secret"));
+
+        // track 8 is gone from the other instance, its file is missing like a deleted one
+        storage.update_db_with_new_files()?;
+        assert_eq!(storage.list_scans(1)?[0].files_removed, 1);
+        assert_eq!(
+            storage.check_missing()?.into_keys().collect::<Vec<_>>(),
+            vec![remote_track]
+        );
+        Ok(())
+    }
+
+    /// Bucket `deck` with `mirror/a.mp3` carrying the checksum `a_hash`, `mirror/b.mp3`
+    /// without one and a cover image. Returns the endpoint and the requested paths
+    fn serve_bucket(a_hash: &FileHash) -> (String, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&requests);
+        let a_hash = a_hash.to_hex();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut parts = request_line.split_whitespace();
+                let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+                seen.lock().unwrap().push(format!("{method} {target}"));
+                let path = target.split('?').next().unwrap();
+                let (headers, body) = match (method, path) {
+                    ("GET", "/deck") => (
+                        String::new(),
+                        "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                         <Contents><Key>mirror/a.mp3</Key><Size>7</Size></Contents>\
+                         <Contents><Key>mirror/b.mp3</Key><Size>7</Size></Contents>\
+                         <Contents><Key>mirror/cover.jpg</Key><Size>3</Size></Contents>\
+                         </ListBucketResult>"
+                            .to_string(),
+                    ),
+                    ("HEAD", "/deck/mirror/a.mp3") => (
+                        format!("x-amz-meta-localdeck-hash: full:{a_hash}\r\n"),
+                        String::new(),
+                    ),
+                    ("GET", "/deck/mirror/b.mp3") => (String::new(), "audio_b".to_string()),
+                    _ => (String::new(), String::new()),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    if method == "HEAD" { "" } else { &body }
+                )
+                .unwrap();
+            }
+        });
+        (endpoint, requests)
+    }
+
+    #[test]
+    fn test_s3_root_objects() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let local = dir.path().join("a.mp3");
+        std::fs::write(&local, b"audio_a")?;
+        let shared = FileHash::from_file(&local)?;
+        let (endpoint, requests) = serve_bucket(&shared);
+
+        let bucket = |path: &str, credentials| Location::S3 {
+            endpoint: endpoint.clone(),
+            bucket: "deck".to_string(),
+            region: None,
+            credentials,
+            path: PathBuf::from(path),
+        };
+        let credentials = Some(S3Credentials {
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "secret".to_string(),
+        });
+        let conn = rusqlite::Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(
+            conn,
+            LibrarySource {
+                roots: vec![
+                    Location::from_path(dir.path()).into(),
+                    bucket("mirror", credentials.clone()).into(),
+                ],
+                ..Default::default()
+            },
+        );
+
+        let inserted = storage.update_db_with_new_files()?;
+        // the stored checksum joins the object to the local copy, the other one was downloaded
+        assert_eq!(inserted.len(), 2);
+        let track_of = |hash: &FileHash| {
+            inserted
+                .iter()
+                .find(|(_, files)| files.iter().any(|f| &f.hash == hash))
+                .map(|(track, files)| (*track, files.len()))
+                .unwrap()
+        };
+        assert_eq!(track_of(&shared).1, 2);
+        let (mirror_only, _) = track_of(&FileHash::from_bytes(b"audio_b"));
+
+        let (_, path, loc) = storage.find_track_file(mirror_only)?;
+        let url = path.to_string_lossy().to_string();
+        assert!(url.starts_with(&format!("{endpoint}/deck/mirror/b.mp3?")));
+        assert!(url.contains("X-Amz-Signature="));
+        assert_eq!(loc, bucket("mirror/b.mp3", credentials));
+
+        let requests = requests.lock().unwrap().clone();
+        assert!(requests.iter().all(|r| r.contains("X-Amz-Signature=")));
+        assert!(!requests.iter().any(|r| r.contains("cover.jpg")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_files_fresh_tracks() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+
+        let file_a = HashedFile::new(
+            mock_hash(1),
+            FileWithMeta {
+                loc: Location::from_path("a.mp3"),
+                file_size: 100,
+            },
+        );
+        let file_b = HashedFile::new(
+            mock_hash(2),
+            FileWithMeta {
+                loc: Location::from_path("b.mp3"),
+                file_size: 200,
+            },
+        );
+
+        // Path 1: Insert completely brand new files
+        let result = storage.insert_files([file_a.clone(), file_b.clone()], &mut None)?;
+
+        // Should return both items under 2 distinct generated track IDs
+        assert_eq!(result.len(), 2);
+
+        // Verify update time was bumped because rows were inserted
+        let count: i64 =
+            storage
+                .db
+                .query_row(&format!("SELECT COUNT(*) FROM {UPDATES}"), [], |r| r.get(0))?;
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_files_reuses_track_id_for_matching_hashes() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let shared_hash = mock_hash(1);
 
         let file_a = HashedFile::new(
             shared_hash.clone(),
@@ -1514,7 +3055,7 @@ mod tests {
         );
 
         // Path 2: Distinct locations, but identical file content hashes
-        let result = storage.insert_files([file_a, file_b])?;
+        let result = storage.insert_files([file_a, file_b], &mut None)?;
 
         // Should group both files under exactly ONE TrackId entry
         assert_eq!(result.len(), 1);
@@ -1545,10 +3086,10 @@ mod tests {
         );
 
         // Seed the first file safely
-        storage.insert_files([file_original])?;
+        storage.insert_files([file_original], &mut None)?;
 
         // Path 3: Attempt to insert to a primary key location that already exists
-        let result = storage.insert_files([file_conflict])?;
+        let result = storage.insert_files([file_conflict], &mut None)?;
 
         // Should be completely ignored by `INSERT OR IGNORE` and excluded from return map
         assert!(
@@ -1614,10 +3155,13 @@ mod tests {
         let track2 = mock_hash(2);
 
         // 1. Run the insert and capture the generated Track IDs from the returned map
-        let result = storage.insert_files([
-            HashedFile::new(track1.clone(), file1.clone()),
-            HashedFile::new(track2.clone(), file2.clone()),
-        ])?;
+        let result = storage.insert_files(
+            [
+                HashedFile::new(track1.clone(), file1.clone()),
+                HashedFile::new(track2.clone(), file2.clone()),
+            ],
+            &mut None,
+        )?;
 
         // Find which track ID belongs to which hash dynamically
         let id1 = result
@@ -1794,6 +3338,100 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_track_prefers_root_with_higher_priority() -> anyhow::Result<()> {
+        let slow_root = tempdir()?;
+        let fast_root = tempdir()?;
+        let slow_path = slow_root.path().join("song.mp3");
+        let fast_path = fast_root.path().join("song.mp3");
+        fs::write(&slow_path, b"abc")?;
+        fs::write(&fast_path, b"abc")?;
+
+        let conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(
+            conn,
+            LibrarySource {
+                roots: vec![
+                    Location::from_path(slow_root.path()).into(),
+                    LibraryRoot {
+                        location: Location::from_path(fast_root.path()),
+                        priority: 1,
+                        enabled: true,
+                    },
+                ],
+                follow_symlinks: false,
+                ignored_dirs: vec![],
+                ..Default::default()
+            },
+        );
+
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        insert_real_files(
+            &storage.db,
+            [
+                (track, replace_windows_slashes(&slow_path)),
+                (track, replace_windows_slashes(&fast_path)),
+            ],
+            None,
+        );
+
+        let (_, path, _) = storage.find_track_file(track)?;
+        assert_eq!(path, PathBuf::from(replace_windows_slashes(&fast_path)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_track_skips_disabled_root() -> anyhow::Result<()> {
+        let enabled_root = tempdir()?;
+        let disabled_root = tempdir()?;
+        let enabled_path = enabled_root.path().join("song.mp3");
+        let disabled_path = disabled_root.path().join("song.mp3");
+        fs::write(&enabled_path, b"abc")?;
+        fs::write(&disabled_path, b"abc")?;
+        let disabled_only_path = disabled_root.path().join("other.mp3");
+        fs::write(&disabled_only_path, b"def")?;
+
+        let conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(
+            conn,
+            LibrarySource {
+                roots: vec![
+                    Location::from_path(enabled_root.path()).into(),
+                    LibraryRoot {
+                        location: Location::from_path(disabled_root.path()),
+                        priority: 1,
+                        enabled: false,
+                    },
+                ],
+                follow_symlinks: false,
+                ignored_dirs: vec![],
+                ..Default::default()
+            },
+        );
+
+        let tracks = insert_tracks(&mut storage.db, 2);
+        insert_real_files(
+            &storage.db,
+            [
+                (tracks[0], replace_windows_slashes(&enabled_path)),
+                (tracks[0], replace_windows_slashes(&disabled_path)),
+                (tracks[1], replace_windows_slashes(&disabled_only_path)),
+            ],
+            None,
+        );
+
+        let (_, path, _) = storage.find_track_file(tracks[0])?;
+        assert_eq!(path, PathBuf::from(replace_windows_slashes(&enabled_path)));
+
+        let err = storage.find_track_file(tracks[1]).unwrap_err();
+        assert!(matches!(err, StorageError::InvalidTrackFile { .. }));
+
+        Ok(())
+    }
+
     #[test]
     fn test_get_track_not_in_db() -> anyhow::Result<()> {
         let conn = rusqlite::Connection::open_in_memory()?;
@@ -1971,6 +3609,128 @@ mod tests {
         assert!(results.is_empty());
     }
 
+    #[test]
+    fn test_find_files_ignores_accents() -> anyhow::Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let tracks = insert_tracks(&mut conn, 2);
+        insert_fake_files(
+            &conn,
+            vec![
+                (tracks[0], "Björk/Army of Me.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "cafe.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        conn.execute(
+            "INSERT INTO track_metadata (track_id, title, artist, year, label, artwork_url)
+         VALUES (?1, ?2, ?3, NULL, NULL, NULL)",
+            rusqlite::params![tracks[1], "Café del Mar", "Energy 52"],
+        )?;
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+
+        let results = storage.find_files("bjork", false)?;
+        assert_files(&results, [(tracks[0], vec!["Björk/Army of Me.mp3"])]);
+        let results = storage.find_files("CAFÉ", false)?;
+        assert_files(&results, [(tracks[1], vec!["cafe.mp3"])]);
+        let results = storage.find_files("cafe del", false)?;
+        assert_files(&results, [(tracks[1], vec!["cafe.mp3"])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_files_structured_query() -> anyhow::Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let tracks = insert_tracks(&mut conn, 3);
+        insert_fake_files(
+            &conn,
+            vec![
+                (tracks[0], "one more time.flac", MOCKED_FILE_SIZE),
+                (tracks[0], "one more time.wav", MOCKED_FILE_SIZE),
+                (tracks[1], "around the world.mp3", MOCKED_FILE_SIZE),
+                (tracks[2], "get lucky.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        conn.execute(
+            &format!("UPDATE {FILES} SET {FORMAT} = substr({PATH}, instr({PATH}, '.') + 1)"),
+            [],
+        )?;
+        for (track, title, year) in [
+            (tracks[0], "One More Time", 2000),
+            (tracks[1], "Around the World", 1997),
+            (tracks[2], "Get Lucky", 2013),
+        ] {
+            conn.execute(
+                "INSERT INTO track_metadata (track_id, title, artist, year, label, artwork_url)
+             VALUES (?1, ?2, 'Daft Punk', ?3, NULL, NULL)",
+                rusqlite::params![track, title, year],
+            )?;
+        }
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+        let party = storage.create_playlist("Party")?;
+        storage.set_playlist_tracks(party.id, &[tracks[0], tracks[2]])?;
+
+        let results = storage.find_files(r#"artist:"daft punk" year:>=2000"#, false)?;
+        assert_files(
+            &results,
+            [
+                (tracks[0], vec!["one more time.flac", "one more time.wav"]),
+                (tracks[2], vec!["get lucky.mp3"]),
+            ],
+        );
+        assert_eq!(results.len(), 2);
+        let results = storage.find_files("playlist:party -format:wav year:<2010", false)?;
+        assert_files(&results, [(tracks[0], vec!["one more time.flac"])]);
+        assert_eq!(results.len(), 1);
+        let results = storage.find_files("-title:time year:1990..2020 -playlist:party", false)?;
+        assert_files(&results, [(tracks[1], vec!["around the world.mp3"])]);
+        assert_eq!(results.len(), 1);
+        assert!(storage.find_files("title:daft", false)?.is_empty());
+        assert!(matches!(
+            storage.find_files("tag:party", false),
+            Err(StorageError::InvalidQuery(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_notes() -> anyhow::Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let tracks = insert_tracks(&mut conn, 2);
+        insert_fake_files(
+            &conn,
+            vec![
+                (tracks[0], "foo.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "bar.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+
+        assert_eq!(storage.track_notes(tracks[0])?, None);
+        storage.set_track_notes(tracks[0], Some("  Vinyl rip, crackly intro "))?;
+        assert_eq!(
+            storage.track_notes(tracks[0])?.as_deref(),
+            Some("Vinyl rip, crackly intro")
+        );
+
+        let results = storage.find_files("crackly", false)?;
+        assert_files(&results, [(tracks[0], vec!["foo.mp3"])]);
+
+        storage.set_track_notes(tracks[0], Some(" "))?;
+        assert_eq!(storage.track_notes(tracks[0])?, None);
+        assert!(storage.find_files("crackly", false)?.is_empty());
+
+        assert!(matches!(
+            storage.set_track_notes(TrackId::MAX, Some("first dance")),
+            Err(StorageError::TrackNotFound(_))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn test_find_files_by_card_id() -> anyhow::Result<()> {
         let mut conn = Connection::open_in_memory().unwrap();
@@ -2071,73 +3831,1353 @@ mod tests {
         let conn = Connection::open_in_memory().unwrap();
         schema::init(&conn).unwrap();
 
-        let storage = Storage::from_existing_conn(conn, LibrarySource::default());
-        let mut storage = storage;
+        let storage = Storage::from_existing_conn(conn, LibrarySource::default());
+        let mut storage = storage;
+
+        let tracks = insert_tracks(&mut storage.db, 3);
+        let track_files = [
+            (tracks[0], "/music/track_a1.mp3", MOCKED_FILE_SIZE),
+            (tracks[0], "/music/subdir/track_a2.mp3", MOCKED_FILE_SIZE),
+            (tracks[1], "/music/track_b.mp3", MOCKED_FILE_SIZE),
+            (tracks[2], "/hello/track_c.mp3", MOCKED_FILE_SIZE), // outside deleted path
+            (tracks[0], "/hello/track_a3.mp3", MOCKED_FILE_SIZE), // outside deleted path
+        ];
+        insert_fake_files(&storage.db, track_files, None);
+
+        // Forget top-level directory
+        let path_to_forget = Path::new("/music");
+        let report = storage.forget_path(path_to_forget).unwrap();
+
+        assert_eq!(report.removed_files, 3); // a1 + a2 + b
+        assert_eq!(report.affected_tracks, 2); // a + b
+        assert_eq!(report.removed_tracks, 1); // b
+
+        // Remaining DB entries
+        let remaining: Vec<TrackId> = storage
+            .db
+            .prepare("SELECT track_id FROM files")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert!(remaining.len() == 2);
+    }
+
+    #[test]
+    fn test_undo_forget_restores_files() -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+
+        let tracks = insert_tracks(&mut storage.db, 2);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/music/track_a.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "/hello/track_b.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        storage.forget_path(Path::new("/music"))?;
+        // the forgotten track is cleaned up before undo
+        storage.clean_dangling()?;
+
+        let report = storage.undo_last()?.expect("forget can be undone");
+        assert_eq!(report.undone.operation, AuditOperation::Forget);
+        assert_eq!(report.restored_files, 1);
+        assert_eq!(report.removed_files, 0);
+
+        let list = storage.list_tracks()?;
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, tracks[0]);
+        assert_eq!(
+            list[0].locations().collect::<Vec<_>>(),
+            vec![&Location::from_path("/music/track_a.mp3")]
+        );
+
+        // every operation is undone only once
+        assert!(storage.undo_last()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_update_removes_added_files() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("a.mp3"), b"audio_a")?;
+        let mut storage = setup_storage(dir.path())?;
+        storage.update_db_with_new_files()?;
+        assert_eq!(storage.list_tracks()?.len(), 1);
+
+        let report = storage.undo_last()?.expect("update can be undone");
+        assert_eq!(report.undone.operation, AuditOperation::Update);
+        assert_eq!(report.removed_files, 1);
+        assert_eq!(report.removed_tracks, 1);
+        assert!(storage.list_tracks()?.is_empty());
+
+        let log = storage.audit_log(None, 1)?;
+        assert_eq!(log[0].operation, AuditOperation::Undo);
+
+        // the file is picked up again by the next update
+        assert_eq!(storage.check_new()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_moves_rows_of_moved_files() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let old = dir.path().join("a.mp3");
+        std::fs::write(&old, b"audio_a")?;
+        let mut storage = setup_storage(dir.path())?;
+        storage.update_db_with_new_files()?;
+        let track = storage.list_tracks()?[0].id;
+
+        std::fs::create_dir(dir.path().join("sorted"))?;
+        let new = dir.path().join("sorted").join("a.mp3");
+        std::fs::rename(&old, &new)?;
+        let inserted = storage.update_db_with_new_files()?;
+        assert!(inserted.is_empty());
+
+        let list = storage.list_tracks()?;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, track);
+        assert_eq!(
+            list[0].locations().collect::<Vec<_>>(),
+            vec![&Location::from_path(&new)]
+        );
+        assert!(storage.check_missing()?.is_empty());
+        assert_eq!(storage.list_scans(1)?[0].files_removed, 0);
+
+        let log = storage.audit_log(Some(track), 1)?;
+        assert_eq!(log[0].operation, AuditOperation::Move);
+
+        let report = storage.undo_last()?.expect("move can be undone");
+        assert_eq!(report.undone.operation, AuditOperation::Move);
+        assert_eq!((report.removed_files, report.restored_files), (1, 1));
+        assert_eq!(
+            storage.list_tracks()?[0].locations().collect::<Vec<_>>(),
+            vec![&Location::from_path(&old)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_quarantined_files_are_not_played() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        insert_fake_files(
+            &storage.db,
+            [(track, "/music/broken.mp3", MOCKED_FILE_SIZE)],
+            None,
+        );
+        let loc = Location::from_path("/music/broken.mp3");
+
+        assert!(storage.quarantine(&loc, "unreadable")?);
+        assert!(!storage.quarantine(&loc, "again")?);
+        let quarantined = storage.list_quarantined()?;
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].track, track);
+        assert_eq!(quarantined[0].reason, "unreadable");
+        assert!(matches!(
+            storage.find_track_file(track),
+            Err(StorageError::TrackNotFound(_))
+        ));
+
+        assert_eq!(storage.clear_quarantine(Some(Path::new("/other")))?, 0);
+        assert_eq!(storage.clear_quarantine(Some(Path::new("/music")))?, 1);
+        assert!(storage.list_quarantined()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_seen_updated_by_scans() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("a.mp3");
+        std::fs::write(&path, b"audio_a")?;
+        let mut storage = setup_storage(dir.path())?;
+        let loc = Location::File { path: path.clone() };
+        assert_eq!(storage.last_seen(&loc)?, None);
+
+        storage.update_db_with_new_files()?;
+        assert!(storage.last_seen(&loc)?.is_some());
+
+        // a file recorded without a scan has not been seen
+        let gone = Location::File {
+            path: dir.path().join("gone.mp3"),
+        };
+        let track = storage.list_tracks()?[0].id;
+        insert_fake_files(
+            &storage.db,
+            [(track, gone.to_string(), MOCKED_FILE_SIZE)],
+            None,
+        );
+        storage.check_missing()?;
+        assert_eq!(storage.last_seen(&gone)?, None);
+
+        // a file removed from disk keeps the time it was seen last
+        storage
+            .db
+            .execute(&format!("UPDATE {FILES} SET {LAST_SEEN} = 0"), [])?;
+        std::fs::remove_file(&path)?;
+        storage.check_missing()?;
+        assert_eq!(storage.last_seen(&loc)?.map(|t| t.timestamp()), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_index_follows_changes() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let lib = dir.path().join("lib");
+        fs::create_dir(&lib)?;
+        fs::write(lib.join("a.mp3"), b"audio_a")?;
+        let open = || {
+            Storage::new(Config {
+                database: Database::OnDisk {
+                    location: Location::from_path(dir.path().join("db.sqlite")),
+                    encryption_key: None,
+                },
+                library_source: LibrarySource {
+                    roots: vec![Location::from_path(&lib).into()],
+                    ..Default::default()
+                },
+            })
+        };
+        let mut server = open()?;
+        server.enable_track_index();
+        let mut cli = open()?;
+
+        assert!(server.resolve_track("1".to_string()).is_err());
+
+        // changes of another connection are picked up
+        cli.update_db_with_new_files()?;
+        let track = server.resolve_track("1".to_string())?;
+        assert!(server.find_track_file_with_meta(track)?.2.is_none());
+        cli.update_track_metadata(
+            track,
+            MetadataUpdate {
+                artist: Some("Artist".to_string()),
+                title: Some("Title".to_string()),
+                year: None,
+                label: None,
+                artwork: None,
+            },
+            false,
+        )?;
+        let (_, _, meta) = server.find_track_file_with_meta(track)?;
+        assert_eq!(meta.unwrap().title, "Title");
+
+        // logged scans are not library changes, the index is kept
+        let version = server.track_index()?.unwrap().version;
+        server.record_card_scan(&CardScan {
+            scanned_at: Local::now(),
+            card_id: track.to_string(),
+            track_id: Some(track),
+            outcome: ScanOutcome::Local,
+            user_agent: None,
+        })?;
+        assert_eq!(server.track_index()?.unwrap().version, version);
+
+        // and so are changes of the same connection
+        server.forget_path(&lib)?;
+        assert!(matches!(
+            server.find_track_file_with_meta(track),
+            Err(StorageError::TrackNotFound(_))
+        ));
+        Ok(())
+    }
+    #[test]
+    fn test_verify_replaces_quick_hashes() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let source = |hash_kind| LibrarySource {
+            roots: vec![Location::from_path(dir.path()).into()],
+            hash_kind,
+            ..Default::default()
+        };
+        let track_of = |inserted: &HashMap<TrackId, HashSet<HashedFile>>, name: &str| {
+            let loc = Location::from_path(dir.path().join(name));
+            inserted
+                .iter()
+                .find(|(_, files)| files.iter().any(|f| f.file.loc == loc))
+                .map(|(track, _)| *track)
+                .unwrap()
+        };
+        std::fs::write(dir.path().join("a.mp3"), b"audio_a")?;
+        std::fs::write(dir.path().join("b.mp3"), b"audio_b")?;
+
+        let mut storage = setup_storage(dir.path())?;
+        storage.fs = FileStorage::new(source(HashKind::Quick));
+        let quick = storage.update_db_with_new_files()?;
+        assert!(
+            quick
+                .values()
+                .flatten()
+                .all(|file| file.kind == HashKind::Quick)
+        );
+
+        // a copy of a.mp3 hashed fully does not match its quick hash
+        std::fs::write(dir.path().join("c.mp3"), b"audio_a")?;
+        std::fs::write(dir.path().join("d.mp3"), b"audio_d")?;
+        storage.fs = FileStorage::new(source(HashKind::Full));
+        let full = storage.update_db_with_new_files()?;
+        let (track_a, track_c) = (track_of(&quick, "a.mp3"), track_of(&full, "c.mp3"));
+        assert_ne!(track_a, track_c);
+
+        std::fs::write(dir.path().join("d.mp3"), b"changed")?;
+        let report = storage.verify(false)?;
+        assert_eq!(report.verified, 4);
+        assert_eq!(report.rehashed, 2);
+        assert_eq!(
+            report.changed,
+            vec![Location::from_path(dir.path().join("d.mp3"))]
+        );
+        assert_eq!(report.quarantined, 1);
+        assert_eq!(
+            storage.list_quarantined()?[0].loc,
+            Location::from_path(dir.path().join("d.mp3"))
+        );
+        assert!(report.unavailable.is_empty());
+        assert_eq!(
+            report.duplicates,
+            vec![(track_a.min(track_c), track_a.max(track_c))]
+        );
+
+        let files = storage.get_track_files(track_a)?;
+        assert_eq!(files[0].kind, HashKind::Full);
+        assert_eq!(
+            files[0].hash,
+            FileHash::from_file(&dir.path().join("a.mp3"))?
+        );
+
+        // nothing is left to upgrade
+        let report = storage.verify(true)?;
+        assert_eq!(report.verified, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_hash_ignores_tags() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let mp3 = |title: &str| {
+            let mut bytes = b"\xff\xfbaudio frames".to_vec();
+            let mut tag = b"TAG".to_vec();
+            tag.extend(title.as_bytes());
+            tag.resize(128, 0);
+            bytes.extend(tag);
+            bytes
+        };
+        std::fs::write(dir.path().join("a.mp3"), mp3("title"))?;
+
+        // library hashed fully before switching to audio hashes
+        let mut storage = setup_storage(dir.path())?;
+        let old = storage.update_db_with_new_files()?;
+        let old_track = *old.keys().next().unwrap();
+
+        storage.fs = FileStorage::new(LibrarySource {
+            roots: vec![Location::from_path(dir.path()).into()],
+            hash_kind: HashKind::Audio,
+            ..Default::default()
+        });
+        std::fs::write(dir.path().join("b.mp3"), mp3("fixed title"))?;
+        std::fs::write(dir.path().join("c.mp3"), mp3("another title"))?;
+        let new = storage.update_db_with_new_files()?;
+        // retagged copies are one track
+        assert_eq!(new.len(), 1);
+        let new_track = *new.keys().next().unwrap();
+        assert_ne!(old_track, new_track);
+
+        // migrating the old file keeps its track and reveals the duplicate
+        let report = storage.verify(true)?;
+        assert_eq!(report.rehashed, 1);
+        assert!(report.changed.is_empty());
+        assert_eq!(report.duplicates, vec![(old_track, new_track)]);
+        assert_eq!(storage.get_track_files(old_track)?[0].kind, HashKind::Audio);
+
+        // old ids keep working after merging the duplicates
+        storage.merge_tracks(old_track, new_track, false)?;
+        assert_eq!(storage.resolve_track(new_track.to_string())?, old_track);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lyrics_are_stored_and_replaced() -> anyhow::Result<()> {
+        let mut storage = Storage::new(Config {
+            database: Database::InMemory,
+            library_source: LibrarySource::default(),
+        })?;
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        assert_eq!(storage.get_lyrics(track)?, None);
+
+        let plain = TrackLyrics {
+            plain: Some("first line".to_string()),
+            synced: None,
+        };
+        storage.set_lyrics(track, &plain)?;
+        assert_eq!(storage.get_lyrics(track)?, Some(plain));
+
+        let synced = TrackLyrics {
+            plain: Some("first line".to_string()),
+            synced: Some("[00:01.50] first line".to_string()),
+        };
+        storage.set_lyrics(track, &synced)?;
+        assert_eq!(storage.get_lyrics(track)?, Some(synced.clone()));
+
+        assert!(matches!(
+            storage.set_lyrics(track + 1, &synced),
+            Err(StorageError::TrackNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_youtube_ids_are_stored_and_replaced() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        assert_eq!(storage.youtube_id(track)?, None);
+
+        storage.set_youtube_id(track, "dQw4w9WgXcQ")?;
+        assert_eq!(storage.youtube_id(track)?.as_deref(), Some("dQw4w9WgXcQ"));
+        storage.set_youtube_id(track, "9bZkp7q19f0")?;
+        assert_eq!(storage.youtube_id(track)?.as_deref(), Some("9bZkp7q19f0"));
+
+        assert!(matches!(
+            storage.set_youtube_id(track + 1, "dQw4w9WgXcQ"),
+            Err(StorageError::TrackNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_playlists() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+
+        let party = storage.create_playlist("party")?;
+        assert!(matches!(
+            storage.create_playlist("party"),
+            Err(StorageError::PlaylistExists(_))
+        ));
+        storage.create_playlist("calm")?;
+
+        storage.set_playlist_tracks(party.id, &[tracks[2], tracks[0], tracks[2]])?;
+        assert_eq!(
+            storage.get_playlist(party.id)?.tracks,
+            vec![tracks[2], tracks[0], tracks[2]]
+        );
+        assert!(matches!(
+            storage.set_playlist_tracks(party.id, &[tracks[2] + 1]),
+            Err(StorageError::TrackNotFound(_))
+        ));
+
+        // merged tracks keep their places
+        storage.merge_tracks(tracks[1], tracks[2], false)?;
+        assert_eq!(
+            storage.get_playlist(party.id)?.tracks,
+            vec![tracks[1], tracks[0], tracks[1]]
+        );
+
+        storage.set_playlist_token(party.id, Some("secret"))?;
+        let party = storage.get_playlist(party.id)?;
+        assert!(party.is_shared_with(Some("secret")));
+        assert!(!party.is_shared_with(None));
+
+        let names: Vec<_> = storage
+            .list_playlists()?
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["calm", "party"]);
+
+        storage.delete_playlist(party.id)?;
+        assert!(matches!(
+            storage.get_playlist(party.id),
+            Err(StorageError::PlaylistNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_favourites() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        assert!(storage.list_favourites()?.is_empty());
+
+        storage.set_favourite(tracks[0], true)?;
+        storage.set_favourite(tracks[2], true)?;
+        storage.set_favourite(tracks[0], true)?;
+        assert!(storage.is_favourite(tracks[0])?);
+        assert!(!storage.is_favourite(tracks[1])?);
+        assert_eq!(storage.list_favourites()?, vec![tracks[2], tracks[0]]);
+
+        storage.set_favourite(tracks[0], false)?;
+        assert_eq!(storage.list_favourites()?, vec![tracks[2]]);
+        assert!(matches!(
+            storage.set_favourite(tracks[2] + 1, true),
+            Err(StorageError::TrackNotFound(_))
+        ));
+
+        // the master of a merged favourite becomes one
+        storage.merge_tracks(tracks[1], tracks[2], false)?;
+        assert_eq!(storage.list_favourites()?, vec![tracks[1]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_links() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let code = storage.short_link("/play?h=1")?;
+        assert_eq!(code.len(), SHORT_CODE_LEN);
+        assert_eq!(storage.short_link("/play?h=1")?, code);
+        assert_ne!(storage.short_link("/play?h=2")?, code);
+        assert_eq!(
+            storage.resolve_short_link(&code)?.as_deref(),
+            Some("/play?h=1")
+        );
+        assert_eq!(storage.resolve_short_link("0000o")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_scans_are_logged() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        let updated_at = storage.updated_at()?;
+        let now = Local::now();
+        let scan = |card_id: &str, ago: i64, outcome| CardScan {
+            scanned_at: now - chrono::Duration::hours(ago),
+            card_id: card_id.to_string(),
+            track_id: (outcome != ScanOutcome::NotFound).then_some(track),
+            outcome,
+            user_agent: Some("Mozilla/5.0 (iPhone)".to_string()),
+        };
+
+        storage.record_card_scan(&scan(&track.to_string(), 48, ScanOutcome::Local))?;
+        storage.record_card_scan(&scan("lost-card", 1, ScanOutcome::NotFound))?;
+        assert_eq!(storage.updated_at()?, updated_at);
+
+        let scans = storage.list_card_scans(None)?;
+        assert_eq!(
+            scans.iter().map(|s| s.card_id.as_str()).collect::<Vec<_>>(),
+            vec!["lost-card", &track.to_string()]
+        );
+        assert_eq!(scans[1].outcome, ScanOutcome::Local);
+        assert_eq!(scans[1].track_id, Some(track));
+        assert_eq!(scans[1].user_agent.as_deref(), Some("Mozilla/5.0 (iPhone)"));
+
+        let recent = storage.list_card_scans(Some(now - chrono::Duration::days(1)))?;
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].track_id, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_round_trip() -> anyhow::Result<()> {
+        let old_machine = tempdir()?;
+        let mut storage = setup_storage(old_machine.path())?;
+        let tracks = insert_tracks(&mut storage.db, 2);
+        let old_root = replace_windows_slashes(old_machine.path());
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], format!("{old_root}/a.mp3"), MOCKED_FILE_SIZE),
+                (tracks[1], format!("{old_root}/sub/b.mp3"), MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        let cover = old_machine.path().join("cover.png");
+        fs::write(&cover, b"png")?;
+        for (track, artwork) in [
+            (tracks[0], cover.to_string_lossy().to_string()),
+            (tracks[1], "https://example.com/b.jpg".to_string()),
+        ] {
+            storage.db.execute(
+                &format!(
+                    "INSERT INTO {TRACK_METADATA} ({TRACK_ID}, {TITLE}, {ARTIST}, {ARTWORK_URL}) VALUES (?1, 'T', 'A', ?2)"
+                ),
+                params![track, artwork],
+            )?;
+        }
+        let party = storage.create_playlist("party")?;
+        storage.set_playlist_tracks(party.id, &[tracks[1], tracks[0]])?;
+
+        let bundle_db = old_machine.path().join("bundle.db");
+        let manifest = storage.export_bundle(&bundle_db)?;
+        assert_eq!(manifest.roots, vec![old_root.clone()]);
+        let files: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, vec!["a.mp3", "sub/b.mp3"]);
+        assert_eq!(manifest.artwork.len(), 1);
+        assert_eq!(
+            manifest.artwork[0].path,
+            format!("artwork/{}.png", tracks[0])
+        );
+        assert_eq!(manifest.artwork[0].source, cover);
+        assert_eq!(manifest.playlists, vec!["party"]);
+
+        let new_machine = tempdir()?;
+        let mut imported = setup_storage(new_machine.path())?;
+        let artwork_dir = new_machine.path().join("artwork");
+        let report = imported.import_bundle(
+            &bundle_db,
+            &manifest,
+            &[new_machine.path().to_path_buf()],
+            &artwork_dir,
+        )?;
+        assert_eq!(report.tracks, 2);
+        assert_eq!(report.relocated_files, 2);
+        assert_eq!(report.missing_files.len(), 2);
+
+        let new_root = replace_windows_slashes(new_machine.path());
+        let mut stmt = imported
+            .db
+            .prepare(&format!("SELECT {PATH} FROM {FILES} ORDER BY {PATH}"))?;
+        let paths: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        drop(stmt);
+        assert_eq!(
+            paths,
+            vec![format!("{new_root}/a.mp3"), format!("{new_root}/sub/b.mp3")]
+        );
+
+        let artwork = |storage: &mut Storage, track| {
+            storage
+                .get_track_metadata(track)
+                .unwrap()
+                .and_then(|meta| meta.artwork)
+                .map(|artwork| artwork.0)
+        };
+        assert_eq!(
+            artwork(&mut imported, tracks[0]),
+            Some(
+                artwork_dir
+                    .join(format!("{}.png", tracks[0]))
+                    .to_string_lossy()
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            artwork(&mut imported, tracks[1]).as_deref(),
+            Some("https://example.com/b.jpg")
+        );
+        assert_eq!(
+            imported.get_playlist(party.id)?.tracks,
+            vec![tracks[1], tracks[0]]
+        );
+
+        // the library has tracks now
+        assert!(
+            imported
+                .import_bundle(
+                    &bundle_db,
+                    &manifest,
+                    &[new_machine.path().to_path_buf()],
+                    &artwork_dir
+                )
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_usb_database() -> anyhow::Result<()> {
+        let stick = tempdir()?;
+        let mut storage = setup_clean_storage()?;
+        storage.fs.loc_resolver =
+            LocationResolver::test_resolver([("MUSIC".to_string(), stick.path().to_path_buf())]);
+        let tracks = insert_tracks(&mut storage.db, 3);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "a.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "sub/b.mp3", MOCKED_FILE_SIZE),
+            ],
+            Some("MUSIC".to_string()),
+        );
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[1], "/home/me/b.mp3", MOCKED_FILE_SIZE),
+                (tracks[2], "/home/me/c.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        for (track, artwork) in [(tracks[0], "/home/me/a.png"), (tracks[2], "/home/me/c.png")] {
+            storage.db.execute(
+                &format!(
+                    "INSERT INTO {TRACK_METADATA} ({TRACK_ID}, {TITLE}, {ARTIST}, {ARTWORK_URL}) VALUES (?1, 'T', 'A', ?2)"
+                ),
+                params![track, artwork],
+            )?;
+        }
+        let party = storage.create_playlist("party")?;
+        storage.set_playlist_tracks(party.id, &[tracks[2], tracks[1], tracks[0]])?;
+
+        let report = storage.export_usb_database("MUSIC")?;
+        assert_eq!(report.path, stick.path().join("localdeck.db"));
+        assert_eq!((report.tracks, report.files, report.playlists), (2, 2, 1));
+
+        let mut usb =
+            Storage::from_existing_conn(Connection::open(&report.path)?, Default::default());
+        let mut stmt = usb.db.prepare(&format!(
+            "SELECT {USB_LABEL}, {PATH} FROM {FILES} ORDER BY {PATH}"
+        ))?;
+        let files: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        drop(stmt);
+        assert_eq!(
+            files,
+            vec![
+                ("MUSIC".to_string(), "a.mp3".to_string()),
+                ("MUSIC".to_string(), "sub/b.mp3".to_string())
+            ]
+        );
+        assert_eq!(
+            usb.get_playlist(party.id)?.tracks,
+            vec![tracks[1], tracks[0]]
+        );
+        let meta = usb.get_track_metadata(tracks[0])?.unwrap();
+        assert_eq!(meta.artwork, None);
+
+        // exporting again replaces the database
+        storage.export_usb_database("MUSIC")?;
+        assert!(storage.export_usb_database("OTHER").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_beets() -> anyhow::Result<()> {
+        let library = tempdir()?;
+        let elsewhere = tempdir()?;
+        let mut storage = setup_storage(library.path())?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        let root = replace_windows_slashes(library.path());
+        for name in ["a.mp3", "c.mp3"] {
+            fs::write(library.path().join(name), name)?;
+        }
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], format!("{root}/a.mp3"), MOCKED_FILE_SIZE),
+                (tracks[1], "/old/disk/b.mp3".to_string(), MOCKED_FILE_SIZE),
+                (tracks[2], format!("{root}/c.mp3"), MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        // beets knows the second file at another path
+        let moved = elsewhere.path().join("b.mp3");
+        fs::write(&moved, "b")?;
+        storage.db.execute(
+            &format!("UPDATE {FILES} SET {FILE_HASH} = ?1 WHERE {TRACK_ID} = ?2"),
+            params![FileHash::from_file(&moved)?.to_string(), tracks[1]],
+        )?;
+        storage.update_track_metadata(
+            tracks[1],
+            MetadataUpdate {
+                artist: Some("Mine".to_string()),
+                title: Some("Edited".to_string()),
+                year: None,
+                label: None,
+                artwork: None,
+            },
+            false,
+        )?;
+
+        let beets_db = elsewhere.path().join("library.db");
+        let beets = Connection::open(&beets_db)?;
+        beets.execute_batch(
+            "CREATE TABLE albums (id INTEGER PRIMARY KEY, artpath BLOB);
+            CREATE TABLE items (id INTEGER PRIMARY KEY, path BLOB, album_id INTEGER,
+                artist TEXT, title TEXT, year INTEGER, label TEXT, album TEXT, genre TEXT);
+            INSERT INTO albums VALUES (1, CAST('/covers/one.jpg' AS BLOB));",
+        )?;
+        for (path, title, year) in [
+            (library.path().join("a.mp3"), "Song A", 1999),
+            (moved.clone(), "Song B", 2004),
+            (library.path().join("c.mp3"), "", 0),
+            (PathBuf::from("/nowhere/d.mp3"), "Song D", 2010),
+        ] {
+            beets.execute(
+                "INSERT INTO items (path, album_id, artist, title, year, label, album, genre)
+                VALUES (?1, 1, 'Band', ?2, ?3, 'Indie', 'One', 'Rock')",
+                params![path.to_string_lossy().as_bytes(), title, year],
+            )?;
+        }
+        drop(beets);
+
+        let report = storage.import_beets(&beets_db, false)?;
+        assert_eq!(report.items, 4);
+        assert_eq!(report.unmatched, vec![PathBuf::from("/nowhere/d.mp3")]);
+        assert_eq!(report.updated, vec![tracks[0], tracks[1]]);
+        assert_eq!(report.missing_required, vec![tracks[2]]);
+        assert_eq!(
+            storage.get_track_metadata(tracks[0])?,
+            Some(TrackMetadata {
+                artist: "Band".to_string(),
+                title: "Song A".to_string(),
+                year: Some(1999),
+                label: Some("Indie".to_string()),
+                artwork: Some(ArtworkRef("/covers/one.jpg".to_string())),
+            })
+        );
+        let edited = storage.get_track_metadata(tracks[1])?.unwrap();
+        assert_eq!((edited.title.as_str(), edited.year), ("Edited", Some(2004)));
+
+        let report = storage.import_beets(&beets_db, true)?;
+        assert_eq!(report.updated, vec![tracks[1]]);
+        let replaced = storage.get_track_metadata(tracks[1])?.unwrap();
+        assert_eq!(
+            (replaced.artist.as_str(), replaced.title.as_str()),
+            ("Band", "Song B")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_diff() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/music/a.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "/music/b.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        let first = storage.create_snapshot(Some("before"))?;
+        assert_eq!(first.files, 2);
+
+        storage.forget_path(Path::new("/music/a.mp3"))?;
+        insert_fake_files(
+            &storage.db,
+            [(tracks[2], "/music/c.mp3", MOCKED_FILE_SIZE)],
+            None,
+        );
+        let second = storage.create_snapshot(None)?;
+
+        let snapshots = storage.list_snapshots()?;
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].name.as_deref(), Some("before"));
+        assert_eq!(snapshots[1].files, 2);
+
+        let diff = storage.diff_snapshots(
+            LibraryState::Snapshot(first.id),
+            LibraryState::Snapshot(second.id),
+        )?;
+        assert_eq!(
+            diff.added.iter().map(|f| &f.location).collect::<Vec<_>>(),
+            vec![&Location::from_path("/music/c.mp3")]
+        );
+        assert_eq!(diff.added[0].track_id, tracks[2]);
+        assert_eq!(
+            diff.removed.iter().map(|f| &f.location).collect::<Vec<_>>(),
+            vec![&Location::from_path("/music/a.mp3")]
+        );
+
+        // snapshots are independent of later changes
+        storage.forget_path(Path::new("/music"))?;
+        let diff =
+            storage.diff_snapshots(LibraryState::Snapshot(first.id), LibraryState::Current)?;
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed.len(), 2);
+
+        assert_eq!(storage.snapshot_at(Local::now())?, Some(second.id));
+        assert!(matches!(
+            storage.diff_snapshots(LibraryState::Snapshot(42), LibraryState::Current),
+            Err(StorageError::SnapshotNotFound(42))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_forget_windows() {
+        let conn = Connection::open_in_memory().unwrap();
+        schema::init(&conn).unwrap();
+
+        let storage = Storage::from_existing_conn(conn, LibrarySource::default());
+        let mut storage = storage;
+
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        let track_files = [
+            (track, "C:/music/track_a1.mp3", MOCKED_FILE_SIZE),
+            (track, "C:/music/subdir/track_a2.mp3", MOCKED_FILE_SIZE),
+        ];
+        insert_fake_files(&storage.db, track_files, None);
+
+        let path_to_forget = Path::new("C:\\music\\subdir");
+        let report = storage.forget_path(path_to_forget).unwrap();
+
+        assert_eq!(report.removed_files, 1);
+        assert_eq!(report.affected_tracks, 1);
+        assert_eq!(report.removed_tracks, 0);
+
+        // Remaining DB entries
+        let remaining: Vec<String> = storage
+            .db
+            .prepare("SELECT path FROM files")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(remaining, vec!["C:/music/track_a1.mp3"]);
+    }
+
+    #[test]
+    fn test_forget_takes_wildcards_literally() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let track = insert_tracks(&mut storage.db, 1)[0];
+        insert_fake_files(
+            &storage.db,
+            [
+                (track, "/music/a_b/1.mp3", MOCKED_FILE_SIZE),
+                (track, "/music/axb/2.mp3", MOCKED_FILE_SIZE),
+                (track, "/music/100%/3.mp3", MOCKED_FILE_SIZE),
+                (track, "/music/100 live/4.mp3", MOCKED_FILE_SIZE),
+                (track, "/music/a_b0/5.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            storage.forget_path(Path::new("/music/a_b"))?.removed_files,
+            1
+        );
+        assert_eq!(
+            storage
+                .forget_path(Path::new("/music/100%/"))?
+                .removed_files,
+            1
+        );
+
+        let mut remaining: Vec<String> = storage
+            .db
+            .prepare("SELECT path FROM files")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "/music/100 live/4.mp3",
+                "/music/a_b0/5.mp3",
+                "/music/axb/2.mp3"
+            ]
+        );
+
+        let plan: Vec<String> = storage
+            .db
+            .prepare(&format!(
+                "EXPLAIN QUERY PLAN SELECT {TRACK_ID} FROM {FILES}
+                WHERE {PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3)"
+            ))?
+            .query_map(params!["/m", "/m/", "/m0"], |row| row.get(3))?
+            .collect::<Result<_, _>>()?;
+        assert!(
+            plan.iter()
+                .all(|step| !step.starts_with("SCAN") || step.contains("idx_files_path")),
+            "{plan:?}"
+        );
+        assert!(plan.iter().any(|step| step.contains("idx_files_path")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_forget_matching_glob() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/backup/a.wav", MOCKED_FILE_SIZE),
+                (tracks[0], "/music/a.flac", MOCKED_FILE_SIZE),
+                (tracks[1], "/backup/2019/live/b.wav", MOCKED_FILE_SIZE),
+                (tracks[2], "/backup/c.mp3", MOCKED_FILE_SIZE),
+                (tracks[2], "/music/backup/c.wav", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+
+        let report = storage.forget_matching(&PathGlob::new("/backup/**/*.wav"))?;
+        assert_eq!(report.removed_files, 2);
+        assert_eq!(report.affected_tracks, 2);
+        assert_eq!(report.removed_tracks, 1);
+
+        let mut remaining: Vec<String> = storage
+            .db
+            .prepare("SELECT path FROM files")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec!["/backup/c.mp3", "/music/a.flac", "/music/backup/c.wav"]
+        );
+
+        let undone = storage.undo_last()?.expect("forget can be undone");
+        assert_eq!(undone.undone.details, "/backup/**/*.wav");
+        assert_eq!(undone.restored_files, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracks_of_older_databases_dated_from_audit_log() -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(
+            "CREATE TABLE tracks (track_id INTEGER PRIMARY KEY AUTOINCREMENT);
+            CREATE TABLE audit_log (
+                audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                logged_at INTEGER NOT NULL,
+                operation TEXT NOT NULL,
+                details TEXT NOT NULL
+            );
+            CREATE TABLE audit_tracks (audit_id INTEGER NOT NULL, track_id INTEGER NOT NULL);
+            INSERT INTO tracks DEFAULT VALUES;
+            INSERT INTO tracks DEFAULT VALUES;
+            INSERT INTO audit_log VALUES (1, 1700000000, 'update', 'new files added');
+            INSERT INTO audit_log VALUES (2, 1800000000, 'update', 'new files added');
+            INSERT INTO audit_tracks VALUES (1, 1), (2, 1);",
+        )?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+
+        let list = storage.list_tracks()?;
+        assert_eq!(list[0].added.map(|t| t.timestamp()), Some(1700000000));
+        // added before anything was logged
+        assert_eq!(list[1].added, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_tracks() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+
+        let tracks = insert_tracks(&mut storage.db, 2);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/music/b.mp3", MOCKED_FILE_SIZE),
+                (tracks[0], "/music/a.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+
+        storage.update_track_metadata(
+            tracks[0],
+            MetadataUpdate {
+                title: Some("Archangel".to_string()),
+                artist: Some("Burial".to_string()),
+                year: Some(2007),
+                label: None,
+                artwork: None,
+            },
+            false,
+        )?;
+
+        let list = storage.list_tracks()?;
 
-        let tracks = insert_tracks(&mut storage.db, 3);
-        let track_files = [
-            (tracks[0], "/music/track_a1.mp3", MOCKED_FILE_SIZE),
-            (tracks[0], "/music/subdir/track_a2.mp3", MOCKED_FILE_SIZE),
-            (tracks[1], "/music/track_b.mp3", MOCKED_FILE_SIZE),
-            (tracks[2], "/hello/track_c.mp3", MOCKED_FILE_SIZE), // outside deleted path
-            (tracks[0], "/hello/track_a3.mp3", MOCKED_FILE_SIZE), // outside deleted path
-        ];
-        insert_fake_files(&storage.db, track_files, None);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, tracks[0]);
+        assert_eq!(
+            list[0].locations().collect::<Vec<_>>(),
+            vec![
+                &Location::from_path("/music/a.mp3"),
+                &Location::from_path("/music/b.mp3")
+            ]
+        );
+        assert_eq!(
+            list[0].meta,
+            Some(ListedMetadata {
+                artist: "Burial".to_string(),
+                title: "Archangel".to_string(),
+                year: Some(2007),
+                artist_sort: "Burial".to_string(),
+                title_sort: "Archangel".to_string(),
+            })
+        );
+        assert_eq!(list[1].id, tracks[1]);
+        assert!(list[1].files.is_empty());
+        assert_eq!(list[1].meta, None);
+        Ok(())
+    }
 
-        // Forget top-level directory
-        let path_to_forget = Path::new("/music");
-        let report = storage.forget_path(path_to_forget).unwrap();
+    #[test]
+    fn test_list_artists() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 4);
+        for (track, artist) in [
+            (tracks[0], "burial"),
+            (tracks[1], "Aphex Twin"),
+            (tracks[2], "Burial"),
+        ] {
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    artist: Some(artist.to_string()),
+                    title: Some("Title".to_string()),
+                    year: None,
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
 
-        assert_eq!(report.removed_files, 3); // a1 + a2 + b
-        assert_eq!(report.affected_tracks, 2); // a + b
-        assert_eq!(report.removed_tracks, 1); // b
+        assert_eq!(
+            storage.list_artists()?,
+            vec![
+                ArtistEntry {
+                    name: "Aphex Twin".to_string(),
+                    tracks: 1
+                },
+                ArtistEntry {
+                    name: "Burial".to_string(),
+                    tracks: 2
+                },
+            ]
+        );
+        Ok(())
+    }
 
-        // Remaining DB entries
-        let remaining: Vec<TrackId> = storage
-            .db
-            .prepare("SELECT track_id FROM files")
-            .unwrap()
-            .query_map([], |row| row.get(0))
-            .unwrap()
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+    #[test]
+    fn test_artist_credits() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 2);
+        for (track, artist, title) in [
+            (tracks[0], "Burial", "Archangel"),
+            (tracks[1], "Four Tet feat. Burial", "Moth (Jamie xx Remix)"),
+        ] {
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    artist: Some(artist.to_string()),
+                    title: Some(title.to_string()),
+                    year: None,
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
 
-        assert!(remaining.len() == 2);
+        assert_eq!(
+            storage.artist_credits(tracks[1])?,
+            vec![
+                ArtistCredit::new("Four Tet", ArtistRole::Main),
+                ArtistCredit::new("Burial", ArtistRole::Featuring),
+                ArtistCredit::new("Jamie xx", ArtistRole::Remixer),
+            ]
+        );
+        let names: Vec<_> = storage
+            .list_artists()?
+            .into_iter()
+            .map(|artist| (artist.name, artist.tracks))
+            .collect();
+        assert_eq!(
+            names,
+            [("Burial", 2), ("Four Tet", 1), ("Jamie xx", 1)]
+                .map(|(name, tracks)| (name.to_string(), tracks))
+        );
+        let burial = TrackFilter {
+            artist: Some("burial".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.list_tracks_matching(&burial)?.len(), 2);
+
+        // credits set by hand name the track
+        storage.set_artist_credits(
+            tracks[1],
+            &[
+                ArtistCredit::new("Four Tet", ArtistRole::Main),
+                ArtistCredit::new("Burial", ArtistRole::Main),
+            ],
+        )?;
+        let meta = storage.get_track_metadata(tracks[1])?.unwrap();
+        assert_eq!(meta.artist, "Four Tet & Burial");
+        assert_eq!(storage.artist_credits(tracks[1])?.len(), 2);
+        assert!(matches!(
+            storage.set_artist_credits(
+                tracks[1],
+                &[ArtistCredit::new("Burial", ArtistRole::Featuring)]
+            ),
+            Err(StorageError::RequiredMetaMissing(_))
+        ));
+        Ok(())
     }
 
     #[test]
-    fn test_forget_windows() {
-        let conn = Connection::open_in_memory().unwrap();
-        schema::init(&conn).unwrap();
-
-        let storage = Storage::from_existing_conn(conn, LibrarySource::default());
-        let mut storage = storage;
+    fn test_sort_names() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        for (track, artist, title) in [
+            (tracks[0], "The Beatles", "Help!"),
+            (tracks[1], "Björk", "Army of Me"),
+            (tracks[2], "Air", "La femme d'argent"),
+        ] {
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    artist: Some(artist.to_string()),
+                    title: Some(title.to_string()),
+                    year: None,
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
+        let sorted_artists = |storage: &mut Storage| -> anyhow::Result<Vec<String>> {
+            let mut listed = storage.list_tracks()?;
+            sort_names::sort_by_name(&mut listed);
+            Ok(listed.into_iter().map(|t| t.meta.unwrap().artist).collect())
+        };
+        assert_eq!(
+            storage.sort_names(tracks[0])?,
+            Some(SortNames {
+                artist: "Beatles, The".to_string(),
+                title: "Help!".to_string(),
+            })
+        );
+        assert_eq!(
+            sorted_artists(&mut storage)?,
+            ["Air", "The Beatles", "Björk"]
+        );
 
-        let track = insert_tracks(&mut storage.db, 1)[0];
-        let track_files = [
-            (track, "C:/music/track_a1.mp3", MOCKED_FILE_SIZE),
-            (track, "C:/music/subdir/track_a2.mp3", MOCKED_FILE_SIZE),
-        ];
-        insert_fake_files(&storage.db, track_files, None);
+        // set by hand, kept while the artist is
+        storage.set_sort_names(tracks[1], Some("Bjork"), None)?;
+        assert_eq!(
+            sorted_artists(&mut storage)?,
+            ["Air", "The Beatles", "Björk"]
+        );
+        storage.set_sort_names(tracks[2], Some("Zair"), None)?;
+        let update = |title: &str| MetadataUpdate {
+            artist: None,
+            title: Some(title.to_string()),
+            year: None,
+            label: None,
+            artwork: None,
+        };
+        storage.update_track_metadata(tracks[2], update("Sexy Boy"), true)?;
+        assert_eq!(storage.sort_names(tracks[2])?.unwrap().artist, "Zair");
+        assert_eq!(
+            sorted_artists(&mut storage)?,
+            ["The Beatles", "Björk", "Air"]
+        );
+        storage.set_sort_names(tracks[2], Some(""), None)?;
+        assert_eq!(storage.sort_names(tracks[2])?.unwrap().artist, "Air");
+        let bare = insert_tracks(&mut storage.db, 1)[0];
+        assert!(matches!(
+            storage.set_sort_names(bare, Some("X"), None),
+            Err(StorageError::RequiredMetaMissing(_))
+        ));
+        Ok(())
+    }
 
-        let path_to_forget = Path::new("C:\\music\\subdir");
-        let report = storage.forget_path(path_to_forget).unwrap();
+    #[test]
+    fn test_list_years() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 4);
+        for (track, year) in [
+            (tracks[0], Some(1997)),
+            (tracks[1], Some(1994)),
+            (tracks[2], Some(1997)),
+            (tracks[3], None),
+        ] {
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    artist: Some("Artist".to_string()),
+                    title: Some("Title".to_string()),
+                    year,
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
 
-        assert_eq!(report.removed_files, 1);
-        assert_eq!(report.affected_tracks, 1);
-        assert_eq!(report.removed_tracks, 0);
+        assert_eq!(
+            storage.list_years()?,
+            vec![
+                YearEntry {
+                    year: 1994,
+                    tracks: 1
+                },
+                YearEntry {
+                    year: 1997,
+                    tracks: 2
+                },
+            ]
+        );
+        Ok(())
+    }
 
-        // Remaining DB entries
-        let remaining: Vec<String> = storage
-            .db
-            .prepare("SELECT path FROM files")
-            .unwrap()
-            .query_map([], |row| row.get(0))
-            .unwrap()
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+    #[test]
+    fn test_list_tracks_matching() -> anyhow::Result<()> {
+        let mut storage = setup_clean_storage()?;
+        let tracks = insert_tracks(&mut storage.db, 3);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/music/a.flac", MOCKED_FILE_SIZE),
+                (tracks[0], "/music/a.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "/music/b.mp3", MOCKED_FILE_SIZE),
+                (tracks[2], "/music/c.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        storage.db.execute(
+            &format!("UPDATE {FILES} SET {FORMAT} = substr({PATH}, instr({PATH}, '.') + 1)"),
+            [],
+        )?;
+        for (track, artist, year) in [(tracks[0], "Burial", 2007), (tracks[1], "burial", 2012)] {
+            storage.update_track_metadata(
+                track,
+                MetadataUpdate {
+                    artist: Some(artist.to_string()),
+                    title: Some("Title".to_string()),
+                    year: Some(year),
+                    label: None,
+                    artwork: None,
+                },
+                false,
+            )?;
+        }
+        let mut ids = |filter: TrackFilter| -> anyhow::Result<Vec<TrackId>> {
+            Ok(storage
+                .list_tracks_matching(&filter)?
+                .into_iter()
+                .map(|track| track.id)
+                .collect())
+        };
 
-        assert_eq!(remaining, vec!["C:/music/track_a1.mp3"]);
+        assert_eq!(ids(TrackFilter::default())?, tracks);
+        let burial = TrackFilter {
+            artist: Some("BURIAL".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(burial.clone())?, vec![tracks[0], tracks[1]]);
+        let late_burial = TrackFilter {
+            year_from: Some(2010),
+            ..burial
+        };
+        assert_eq!(ids(late_burial)?, vec![tracks[1]]);
+        let until = TrackFilter {
+            year_to: Some(2007),
+            ..Default::default()
+        };
+        assert_eq!(ids(until)?, vec![tracks[0]]);
+        let flac = TrackFilter {
+            format: Some(".FLAC".to_string()),
+            ..Default::default()
+        };
+        let list = storage.list_tracks_matching(&flac)?;
+        assert_eq!(list.len(), 1);
+        // tracks are listed with all their files
+        assert_eq!(list[0].files.len(), 2);
+        Ok(())
     }
 
     #[test]
@@ -2381,6 +5421,151 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_import_tags_keeps_edits() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mp3 = |title: &str, artist: &str| {
+            let mut tag = b"TAG".to_vec();
+            for field in [title, artist] {
+                let mut field = field.as_bytes().to_vec();
+                field.resize(30, 0);
+                tag.extend(field);
+            }
+            tag.resize(128, 0);
+            [b"\xff\xfbframes".to_vec(), tag].concat()
+        };
+        let file = dir.path().join("a.mp3");
+        std::fs::write(&file, mp3("Title", "Artist"))?;
+        let mut storage = setup_storage(dir.path())?;
+        let tracks: Vec<_> = storage.update_db_with_new_files()?.into_keys().collect();
+
+        let report = storage.import_tags(&tracks, MetadataPolicy::DbWins)?;
+        assert_eq!(report.updated, tracks);
+        let track = tracks[0];
+        assert_eq!(storage.get_track_metadata(track)?.unwrap().artist, "Artist");
+        let sources = storage.metadata_sources(track)?;
+        assert_eq!(sources.len(), 2);
+        assert!(sources.iter().all(|s| s.origin == MetadataOrigin::Tags));
+
+        let edit = MetadataUpdate {
+            title: None,
+            artist: Some("Edited Artist".into()),
+            year: None,
+            label: None,
+            artwork: None,
+        };
+        storage.update_track_metadata(track, edit, true)?;
+        std::fs::write(&file, mp3("Retagged Title", "Retagged Artist"))?;
+
+        let report = storage.import_tags(&tracks, MetadataPolicy::Ask)?;
+        assert_eq!(report.updated, tracks);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].field, MetaField::Artist);
+        let meta = storage.get_track_metadata(track)?.unwrap();
+        // the title only came from tags, the artist was edited
+        assert_eq!(meta.title, "Retagged Title");
+        assert_eq!(meta.artist, "Edited Artist");
+
+        storage.import_tags(&tracks, MetadataPolicy::TagsWin)?;
+        let meta = storage.get_track_metadata(track)?.unwrap();
+        assert_eq!(meta.artist, "Retagged Artist");
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_metadata_under_directory() -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory()?;
+        schema::init(&conn)?;
+        let mut storage = Storage::from_existing_conn(conn, LibrarySource::default());
+
+        let tracks = insert_tracks(&mut storage.db, 4);
+        insert_fake_files(
+            &storage.db,
+            [
+                (tracks[0], "/music/album/01.mp3", MOCKED_FILE_SIZE),
+                (tracks[1], "/music/album/cd2/02.mp3", MOCKED_FILE_SIZE),
+                (tracks[2], "/music/album/03.mp3", MOCKED_FILE_SIZE),
+                (tracks[3], "/music/album-live/01.mp3", MOCKED_FILE_SIZE),
+            ],
+            None,
+        );
+        let meta = |title: &str, label: Option<&str>| MetadataUpdate {
+            title: Some(title.into()),
+            artist: Some("Artist".into()),
+            year: None,
+            label: label.map(Into::into),
+            artwork: None,
+        };
+        storage.update_track_metadata(tracks[0], meta("One", None), false)?;
+        storage.update_track_metadata(tracks[1], meta("Two", Some("Old Label")), false)?;
+        storage.update_track_metadata(tracks[3], meta("Live", None), false)?;
+
+        let label = MetadataUpdate {
+            title: None,
+            artist: None,
+            year: Some(2001),
+            label: Some("Label".into()),
+            artwork: None,
+        };
+        let report =
+            storage.apply_metadata_under(Path::new("/music/album"), label.clone(), false)?;
+        assert_eq!(report.matched_tracks, 3);
+        assert_eq!(report.updated, vec![tracks[0]]);
+        assert_eq!(report.kept, vec![tracks[1]]);
+        assert_eq!(report.missing_required, vec![tracks[2]]);
+
+        let first = storage.get_track_metadata(tracks[0])?.unwrap();
+        assert_eq!(first.label.as_deref(), Some("Label"));
+        assert_eq!(first.year, Some(2001));
+        // a sibling directory sharing the name prefix is not under the path
+        assert_eq!(storage.get_track_metadata(tracks[3])?.unwrap().label, None);
+
+        let report = storage.apply_metadata_under(Path::new("/music/album"), label, true)?;
+        assert_eq!(report.updated, vec![tracks[1]]);
+        assert!(report.kept.is_empty());
+        let second = storage.get_track_metadata(tracks[1])?.unwrap();
+        assert_eq!(second.label.as_deref(), Some("Label"));
+        assert_eq!(second.title, "Two");
+
+        let log = storage.audit_log(None, 1)?;
+        assert_eq!(log[0].operation, AuditOperation::Metadata);
+        assert_eq!(log[0].tracks, vec![tracks[1]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutations_are_audited() -> anyhow::Result<()> {
+        let mut conn = rusqlite::Connection::open_in_memory()?;
+        schema::init(&conn)?;
+
+        let tracks = insert_tracks(&mut conn, 2);
+        let mut storage = Storage::from_existing_conn(conn, Default::default());
+
+        let update = MetadataUpdate {
+            title: Some("Song A".into()),
+            artist: Some("Artist A".into()),
+            year: Some(1999),
+            label: None,
+            artwork: None,
+        };
+        storage.update_track_metadata(tracks[0], update, false)?;
+        storage.merge_tracks(tracks[0], tracks[1], false)?;
+
+        let log = storage.audit_log(None, 10)?;
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].operation, AuditOperation::Merge);
+        assert_eq!(log[0].tracks, vec![tracks[0], tracks[1]]);
+        assert_eq!(log[1].operation, AuditOperation::Metadata);
+        assert_eq!(log[1].details, "Artist A - Song A, year: 1999");
+
+        let log = storage.audit_log(Some(tracks[1]), 10)?;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].operation, AuditOperation::Merge);
+
+        assert_eq!(storage.audit_log(None, 1)?.len(), 1);
+        Ok(())
+    }
+
     #[test]
     fn test_update_track_metadata_reject_overwrite() -> anyhow::Result<()> {
         let mut conn = rusqlite::Connection::open_in_memory()?;
@@ -2464,14 +5649,18 @@ mod tests {
     }
 
     mod check_tests {
+        use rusqlite::Connection;
         use tempfile::tempdir;
 
         use crate::{
+            Storage,
+            config::LibrarySource,
             location::{Location, replace_windows_slashes},
             operations::tests::{
                 MOCKED_FILE_SIZE, insert_fake_files, insert_real_files, insert_tracks, mock_hash,
                 setup_storage,
             },
+            schema,
         };
 
         #[test]
@@ -2638,6 +5827,44 @@ mod tests {
             Ok(())
         }
 
+        #[test]
+        fn test_check_missing_ignores_unavailable_root() -> anyhow::Result<()> {
+            let dir = tempdir()?;
+            let gone_root = dir.path().join("unplugged");
+
+            let conn = Connection::open_in_memory()?;
+            schema::init(&conn)?;
+            let mut storage = Storage::from_existing_conn(
+                conn,
+                LibrarySource {
+                    roots: vec![
+                        Location::from_path(dir.path()).into(),
+                        Location::from_path(&gone_root).into(),
+                    ],
+                    follow_symlinks: false,
+                    ignored_dirs: vec![],
+                    ..Default::default()
+                },
+            );
+
+            let unavailable = storage.unavailable_roots();
+            assert_eq!(unavailable.len(), 1);
+            assert_eq!(unavailable[0].location, Location::from_path(&gone_root));
+
+            let path = gone_root.join("song.mp3");
+            let track_id = insert_tracks(&mut storage.db, 1)[0];
+            insert_fake_files(
+                &storage.db,
+                [(track_id, replace_windows_slashes(&path), MOCKED_FILE_SIZE)],
+                None,
+            );
+
+            let diff = storage.check_missing()?;
+            assert!(diff.is_empty());
+
+            Ok(())
+        }
+
         #[test]
         fn test_check_stale_no_stale_tracks() -> anyhow::Result<()> {
             let dir = tempdir()?;
@@ -2820,7 +6047,11 @@ mod tests {
     mod usb_conversion {
         use std::path::PathBuf;
 
-        use crate::{error::StorageError, location::Location, operations::LocationRow};
+        use crate::{
+            error::StorageError,
+            location::{Location, S3Credentials},
+            operations::LocationRow,
+        };
 
         #[test]
         fn empty_usb_label_error() {
@@ -2848,7 +6079,7 @@ mod tests {
                 Location::File { path } => {
                     assert_eq!(path, PathBuf::from("/home/user/music/song.mp3"));
                 }
-                Location::Usb { .. } => panic!("expected File variant, got Usb"),
+                other => panic!("expected File variant, got {other:?}"),
             }
         }
 
@@ -2867,8 +6098,95 @@ mod tests {
                     assert_eq!(label, "DJ_USB");
                     assert_eq!(path, PathBuf::from("music/song.mp3"));
                 }
-                Location::File { .. } => panic!("expected Usb variant, got File"),
+                other => panic!("expected Usb variant, got {other:?}"),
             }
         }
+
+        #[test]
+        fn test_location_share_roundtrip() {
+            let original = Location::NetworkShare {
+                mount: PathBuf::from("/mnt/nas"),
+                source: Some("//nas/music".to_string()),
+                path: PathBuf::from("albums/song.mp3"),
+            };
+
+            let row: LocationRow = LocationRow::from_location(original.clone()).unwrap();
+            assert!(!row.is_usb());
+            let restored: Location = row.into();
+
+            assert_eq!(
+                restored,
+                Location::NetworkShare {
+                    mount: PathBuf::from("/mnt/nas"),
+                    source: None,
+                    path: PathBuf::from("albums/song.mp3"),
+                }
+            );
+            assert!(restored.starts_with(&original));
+            assert_eq!(restored.to_string(), "SHARE(/mnt/nas)/albums/song.mp3");
+        }
+
+        #[test]
+        fn test_location_s3_roundtrip() {
+            let original = Location::S3 {
+                endpoint: "https://s3.eu-central-1.amazonaws.com/".to_string(),
+                bucket: "deck-mirror".to_string(),
+                region: Some("eu-central-1".to_string()),
+                credentials: Some(S3Credentials {
+                    access_key_id: "AKID".to_string(),
+                    secret_access_key: "secret".to_string(),
+                }),
+                path: PathBuf::from("music/album/song.flac"),
+            };
+
+            let row: LocationRow = LocationRow::from_location(original.clone()).unwrap();
+            assert!(!row.is_usb());
+            assert!(!row.is_remote());
+            let restored: Location = row.into();
+
+            assert_eq!(
+                restored,
+                Location::S3 {
+                    endpoint: "https://s3.eu-central-1.amazonaws.com".to_string(),
+                    bucket: "deck-mirror".to_string(),
+                    region: None,
+                    credentials: None,
+                    path: PathBuf::from("music/album/song.flac"),
+                }
+            );
+            assert!(restored.starts_with(&original));
+            assert_eq!(
+                restored.to_string(),
+                "S3(deck-mirror)/music/album/song.flac"
+            );
+        }
+
+        #[test]
+        fn test_location_remote_roundtrip() {
+            let original = Location::Remote {
+                base_url: "http://main-deck:8080/".to_string(),
+                token: Some("secret".to_string()),
+                path: PathBuf::from("tracks/12/stream"),
+            };
+
+            let row: LocationRow = LocationRow::from_location(original.clone()).unwrap();
+            assert!(!row.is_usb());
+            let restored: Location = row.into();
+
+            // the token stays in the config
+            assert_eq!(
+                restored,
+                Location::Remote {
+                    base_url: "http://main-deck:8080".to_string(),
+                    token: None,
+                    path: PathBuf::from("tracks/12/stream"),
+                }
+            );
+            assert!(restored.starts_with(&original));
+            assert_eq!(
+                restored.to_string(),
+                "http://main-deck:8080/tracks/12/stream"
+            );
+        }
     }
 }
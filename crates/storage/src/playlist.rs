@@ -0,0 +1,197 @@
+//! Named, ordered lists of tracks, shared as a web page with one link.
+//!
+//! The page of a playlist is found by its id, so the link keeps working after renames.
+//! A playlist with a share token only opens with the token in the link.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+use rusqlite::{ErrorCode, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::{
+    Storage,
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+pub type PlaylistId = i64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Playlist {
+    pub id: PlaylistId,
+    pub name: String,
+    /// secret part of the shared link, `None` if anyone knowing the id may open it
+    pub share_token: Option<String>,
+    /// in playing order, a track may appear several times
+    pub tracks: Vec<TrackId>,
+}
+
+impl Playlist {
+    /// Whether a visitor presenting `token` may open the playlist page
+    pub fn is_shared_with(&self, token: Option<&str>) -> bool {
+        match &self.share_token {
+            Some(expected) => token == Some(expected.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// Random token for a shared playlist link
+pub fn new_share_token() -> String {
+    // RandomState is seeded randomly for every process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    let seed = hasher.finish().to_le_bytes();
+    blake3::hash(&seed).to_hex()[..16].to_string()
+}
+
+impl Storage {
+    pub fn create_playlist(&mut self, name: &str) -> Result<Playlist, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        tx.execute(
+            &format!("INSERT INTO {PLAYLISTS} ({NAME}, {CREATED_AT}) VALUES (?1, ?2)"),
+            params![name, now],
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(error, _)
+                if error.code == ErrorCode::ConstraintViolation =>
+            {
+                StorageError::PlaylistExists(name.to_string())
+            }
+            e => StorageError::Database(e),
+        })?;
+        let id = tx.last_insert_rowid();
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(Playlist {
+            id,
+            name: name.to_string(),
+            share_token: None,
+            tracks: vec![],
+        })
+    }
+
+    pub fn delete_playlist(&mut self, id: PlaylistId) -> Result<(), StorageError> {
+        let tx = self.db.transaction()?;
+        let deleted = tx.execute(
+            &format!("DELETE FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = ?1"),
+            params![id],
+        )?;
+        if deleted == 0 {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// All playlists ordered by name
+    pub fn list_playlists(&mut self) -> Result<Vec<Playlist>, StorageError> {
+        let ids = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {PLAYLIST_ID} FROM {PLAYLISTS} ORDER BY {NAME}"
+            ))?;
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<PlaylistId>, _>>()?
+        };
+        ids.into_iter().map(|id| self.get_playlist(id)).collect()
+    }
+
+    pub fn get_playlist(&mut self, id: PlaylistId) -> Result<Playlist, StorageError> {
+        let (name, share_token) = self
+            .db
+            .query_row(
+                &format!("SELECT {NAME}, {SHARE_TOKEN} FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = ?1"),
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| StorageError::PlaylistNotFound(id.to_string()))?;
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT {TRACK_ID} FROM {PLAYLIST_TRACKS} WHERE {PLAYLIST_ID} = ?1 ORDER BY {POSITION}"
+        ))?;
+        let tracks = stmt
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(Playlist {
+            id,
+            name,
+            share_token,
+            tracks,
+        })
+    }
+
+    /// Replaces the tracks of the playlist, keeping their order
+    pub fn set_playlist_tracks(
+        &mut self,
+        id: PlaylistId,
+        tracks: &[TrackId],
+    ) -> Result<(), StorageError> {
+        let tx = self.db.transaction()?;
+        let exists = tx
+            .query_row(
+                &format!("SELECT 1 FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = ?1"),
+                params![id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if exists.is_none() {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        tx.execute(
+            &format!("DELETE FROM {PLAYLIST_TRACKS} WHERE {PLAYLIST_ID} = ?1"),
+            params![id],
+        )?;
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT INTO {PLAYLIST_TRACKS} ({PLAYLIST_ID}, {POSITION}, {TRACK_ID}) VALUES (?1, ?2, ?3)"
+            ))?;
+            for (position, track) in tracks.iter().enumerate() {
+                insert
+                    .execute(params![id, position as i64, track])
+                    .map_err(|e| match e {
+                        rusqlite::Error::SqliteFailure(error, _)
+                            if error.code == ErrorCode::ConstraintViolation =>
+                        {
+                            StorageError::TrackNotFound(track.to_string())
+                        }
+                        e => StorageError::Database(e),
+                    })?;
+            }
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Sets or removes the token needed to open the shared playlist page
+    pub fn set_playlist_token(
+        &mut self,
+        id: PlaylistId,
+        token: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let tx = self.db.transaction()?;
+        let updated = tx.execute(
+            &format!("UPDATE {PLAYLISTS} SET {SHARE_TOKEN} = ?1 WHERE {PLAYLIST_ID} = ?2"),
+            params![token, id],
+        )?;
+        if updated == 0 {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
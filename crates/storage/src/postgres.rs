@@ -0,0 +1,2083 @@
+//! PostgreSQL implementation of [`LibraryStore`], for libraries shared by several machines.
+//!
+//! Uses the same tables as the SQLite database. The audit log, scan reports and snapshots
+//! are only kept by the SQLite [`Storage`](crate::Storage).
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    mem,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use postgres::{Client, GenericClient, NoTls, Transaction, error::SqlState, types::ToSql};
+
+use crate::{
+    CardId,
+    artists::{self, ArtistCredit},
+    bundle::BundleManifest,
+    card_scans::CardScan,
+    config::LibrarySource,
+    db::{i64_seconds_to_local_time, system_time_to_i64},
+    error::StorageError,
+    file_hash::FileHash,
+    fs::{FileStorage, FileWithMeta, HashedFile, UnavailableRoot, file_format, modified_secs},
+    glob::PathGlob,
+    location::Location,
+    lyrics::TrackLyrics,
+    notes::clean_notes,
+    operations::{
+        ApplyMetadataReport, ArtistEntry, CleanDanglingReport, ForgetReport, ListedFile,
+        ListedMetadata, LocationRow, MetadataUpdate, StaleTracks, Storage, TrackFilter,
+        TrackListEntry, YearEntry, parse_file_hash, parse_hash_kind, path_range,
+    },
+    playlist::{Playlist, PlaylistId},
+    progress::Progress,
+    provenance::{self, FieldSource, MetaField, MetadataOrigin, MetadataPolicy, TagImportReport},
+    quarantine::QuarantinedFile,
+    schema::{columns::*, tables::*},
+    search::{Condition, SearchQuery, SearchedFile},
+    short_links,
+    sort_names::{self, SortNames},
+    store::LibraryStore,
+    track::{ArtworkRef, Track, TrackId, TrackMetadata},
+    update::{LibraryScan, PendingUpdate},
+};
+
+/// Number of hashed files committed at once by [`PgStorage::update_db_with_new_files`]
+const UPDATE_CHECKPOINT_FILES: usize = 100;
+
+/// Condition on the path column for the bounds of [`path_range`] as `$1` to `$3`.
+/// Paths are compared byte-wise, as in `idx_files_path`
+fn under_path() -> String {
+    format!(r#"{PATH} = $1 OR ({PATH} COLLATE "C" >= $2 AND {PATH} COLLATE "C" < $3)"#)
+}
+
+/// Postgres version of `schema::SCHEMA`, keep both in sync
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS tracks (
+    track_id BIGSERIAL PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS card_mappings (
+    card_id TEXT PRIMARY KEY,
+    track_id BIGINT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS files (
+    usb_label TEXT NOT NULL,
+    path TEXT NOT NULL,
+    track_id BIGINT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE,
+    file_size BIGINT NOT NULL,
+    file_hash TEXT NOT NULL,
+    PRIMARY KEY (usb_label, path)
+);
+
+CREATE TABLE IF NOT EXISTS updates (
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS track_metadata (
+    track_id BIGINT PRIMARY KEY REFERENCES tracks(track_id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    year INTEGER,
+    label TEXT,
+    artwork_url TEXT
+);
+
+CREATE TABLE IF NOT EXISTS track_lyrics (
+    track_id BIGINT PRIMARY KEY REFERENCES tracks(track_id) ON DELETE CASCADE,
+    plain_lyrics TEXT,
+    synced_lyrics TEXT,
+    fetched_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS playlists (
+    playlist_id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    share_token TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS playlist_tracks (
+    playlist_id BIGINT NOT NULL REFERENCES playlists(playlist_id) ON DELETE CASCADE,
+    position BIGINT NOT NULL,
+    track_id BIGINT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE,
+    PRIMARY KEY (playlist_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS favourites (
+    track_id BIGINT PRIMARY KEY REFERENCES tracks(track_id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS card_scans (
+    scanned_at BIGINT NOT NULL,
+    card_id TEXT NOT NULL,
+    track_id BIGINT,
+    outcome TEXT NOT NULL,
+    user_agent TEXT
+);
+
+CREATE TABLE IF NOT EXISTS short_links (
+    code TEXT PRIMARY KEY,
+    target TEXT NOT NULL UNIQUE,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS youtube_ids (
+    track_id BIGINT PRIMARY KEY REFERENCES tracks(track_id) ON DELETE CASCADE,
+    video_id TEXT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS track_notes (
+    track_id BIGINT PRIMARY KEY REFERENCES tracks(track_id) ON DELETE CASCADE,
+    notes TEXT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS artists (
+    artist_id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_artists_name ON artists (LOWER(name));
+
+CREATE TABLE IF NOT EXISTS track_artists (
+    track_id BIGINT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE,
+    artist_id BIGINT NOT NULL REFERENCES artists(artist_id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, artist_id, role)
+);
+
+CREATE INDEX IF NOT EXISTS idx_track_artists_artist ON track_artists (artist_id);
+
+CREATE TABLE IF NOT EXISTS metadata_sources (
+    track_id BIGINT NOT NULL REFERENCES tracks(track_id) ON DELETE CASCADE,
+    field TEXT NOT NULL,
+    origin TEXT NOT NULL,
+    changed_at BIGINT NOT NULL,
+    PRIMARY KEY (track_id, field)
+);
+
+-- columns added after a table was created are missing in older databases
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS created_at BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS last_seen BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS hash_kind TEXT NOT NULL DEFAULT 'full';
+ALTER TABLE files ADD COLUMN IF NOT EXISTS modified_at BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS format TEXT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS quarantined_at BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS quarantine_reason TEXT;
+ALTER TABLE track_metadata ADD COLUMN IF NOT EXISTS artist_sort TEXT;
+ALTER TABLE track_metadata ADD COLUMN IF NOT EXISTS title_sort TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_files_hash ON files(file_hash);
+CREATE INDEX IF NOT EXISTS idx_files_track_id ON files(track_id);
+CREATE INDEX IF NOT EXISTS idx_files_path ON files(path COLLATE "C");
+CREATE INDEX IF NOT EXISTS idx_track_metadata_artist ON track_metadata(artist);
+CREATE INDEX IF NOT EXISTS idx_track_metadata_artist_lower ON track_metadata(LOWER(artist));
+CREATE INDEX IF NOT EXISTS idx_track_metadata_year ON track_metadata(year);
+CREATE INDEX IF NOT EXISTS idx_playlist_tracks_track_id ON playlist_tracks(track_id);
+CREATE INDEX IF NOT EXISTS idx_card_scans_scanned_at ON card_scans(scanned_at);
+"#;
+
+impl From<postgres::Error> for StorageError {
+    fn from(e: postgres::Error) -> Self {
+        StorageError::Internal(anyhow!("postgres error: {e}"))
+    }
+}
+
+/// Library stored in a PostgreSQL database
+pub struct PgStorage {
+    db: Client,
+    fs: FileStorage,
+}
+
+impl PgStorage {
+    /// Connects to the database at `url` and applies migrations
+    pub fn new(url: &str, library_source: LibrarySource) -> Result<Self, StorageError> {
+        let mut db = Client::connect(url, NoTls)?;
+        db.batch_execute(SCHEMA)?;
+        Self::fill_missing_sort_names(&mut db)?;
+        Self::link_missing_credits(&mut db)?;
+        let mut fs = FileStorage::new(library_source);
+        for root in fs.unavailable_roots() {
+            log::warn!(
+                "library root {} is unavailable, its tracks will not be served: {}",
+                root.location,
+                root.reason
+            );
+        }
+        Ok(Self { db, fs })
+    }
+
+    fn insert_update_time(tx: &mut Transaction) -> Result<(), StorageError> {
+        let time_secs = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        tx.execute(
+            &format!("INSERT INTO {UPDATES} ({UPDATED_AT}) VALUES ($1)"),
+            &[&time_secs],
+        )?;
+        Ok(())
+    }
+
+    fn load_metadata(
+        db: &mut impl GenericClient,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError> {
+        let row = db.query_opt(
+            &format!(
+                "SELECT {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL}
+                FROM {TRACK_METADATA} WHERE {TRACK_ID} = $1"
+            ),
+            &[&track_id],
+        )?;
+        Ok(row.map(|row| TrackMetadata {
+            title: row.get(0),
+            artist: row.get(1),
+            year: row.get::<_, Option<i32>>(2).map(|y| y as u32),
+            label: row.get(3),
+            artwork: row.get::<_, Option<String>>(4).map(ArtworkRef),
+        }))
+    }
+
+    fn store_metadata(
+        tx: &mut Transaction,
+        track_id: TrackId,
+        meta: TrackMetadata,
+    ) -> Result<(), StorageError> {
+        let previous = tx
+            .query_opt(
+                &format!("SELECT {ARTIST}, {TITLE} FROM {TRACK_METADATA} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?
+            .map(|row| (row.get::<_, String>(0), row.get::<_, String>(1)));
+        let credits = (previous.as_ref() != Some(&(meta.artist.clone(), meta.title.clone())))
+            .then(|| artists::parse_credits(&meta.artist, &meta.title));
+        tx.execute(
+            &format!(
+                "INSERT INTO {TRACK_METADATA}
+                    ({TRACK_ID}, {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL},
+                    {ARTIST_SORT}, {TITLE_SORT})
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT ({TRACK_ID}) DO UPDATE SET
+                    {TITLE} = excluded.{TITLE},
+                    {ARTIST} = excluded.{ARTIST},
+                    {YEAR} = excluded.{YEAR},
+                    {LABEL} = excluded.{LABEL},
+                    {ARTWORK_URL} = excluded.{ARTWORK_URL},
+                    {ARTIST_SORT} = CASE WHEN {TRACK_METADATA}.{ARTIST} = excluded.{ARTIST}
+                        THEN COALESCE({TRACK_METADATA}.{ARTIST_SORT}, excluded.{ARTIST_SORT})
+                        ELSE excluded.{ARTIST_SORT} END,
+                    {TITLE_SORT} = CASE WHEN {TRACK_METADATA}.{TITLE} = excluded.{TITLE}
+                        THEN COALESCE({TRACK_METADATA}.{TITLE_SORT}, excluded.{TITLE_SORT})
+                        ELSE excluded.{TITLE_SORT} END"
+            ),
+            &[
+                &track_id,
+                &meta.title,
+                &meta.artist,
+                &meta.year.map(|y| y as i32),
+                &meta.label,
+                &meta.artwork.map(|a| a.0),
+                &sort_names::artist_sort_name(&meta.artist),
+                &sort_names::sort_name(&meta.title),
+            ],
+        )
+        .map_err(|e| {
+            if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                StorageError::TrackNotFound(track_id.to_string())
+            } else {
+                e.into()
+            }
+        })?;
+        if let Some(credits) = credits {
+            Self::link_credits(tx, track_id, &credits)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the credits of the track, see [`artists::link_credits`]
+    fn link_credits(
+        tx: &mut Transaction,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError> {
+        tx.execute(
+            &format!("DELETE FROM {TRACK_ARTISTS} WHERE {TRACK_ID} = $1"),
+            &[&track_id],
+        )?;
+        for (position, credit) in credits.iter().enumerate() {
+            let existing = tx.query_opt(
+                &format!(
+                    "SELECT {ARTIST_ID}, {NAME} FROM {ARTISTS} WHERE LOWER({NAME}) = LOWER($1)"
+                ),
+                &[&credit.name],
+            )?;
+            let artist_id: i64 = match existing {
+                Some(row) => {
+                    // named by the spelling sorting first, like in SQLite
+                    if credit.name.as_str() < row.get::<_, &str>(1) {
+                        tx.execute(
+                            &format!("UPDATE {ARTISTS} SET {NAME} = $1 WHERE {ARTIST_ID} = $2"),
+                            &[&credit.name, &row.get::<_, i64>(0)],
+                        )?;
+                    }
+                    row.get(0)
+                }
+                None => tx
+                    .query_one(
+                        &format!(
+                            "INSERT INTO {ARTISTS} ({NAME}) VALUES ($1) RETURNING {ARTIST_ID}"
+                        ),
+                        &[&credit.name],
+                    )?
+                    .get(0),
+            };
+            tx.execute(
+                &format!(
+                    "INSERT INTO {TRACK_ARTISTS} ({TRACK_ID}, {ARTIST_ID}, {ROLE}, {POSITION})
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT DO NOTHING"
+                ),
+                &[
+                    &track_id,
+                    &artist_id,
+                    &credit.role.as_str(),
+                    &(position as i32),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sort names for tracks whose metadata was stored before sort names were
+    fn fill_missing_sort_names(db: &mut Client) -> Result<(), StorageError> {
+        let mut tx = db.transaction()?;
+        let rows = tx.query(
+            &format!(
+                "SELECT {TRACK_ID}, {ARTIST}, {TITLE} FROM {TRACK_METADATA}
+                WHERE {ARTIST_SORT} IS NULL OR {TITLE_SORT} IS NULL"
+            ),
+            &[],
+        )?;
+        for row in rows {
+            tx.execute(
+                &format!(
+                    "UPDATE {TRACK_METADATA} SET
+                        {ARTIST_SORT} = COALESCE({ARTIST_SORT}, $1),
+                        {TITLE_SORT} = COALESCE({TITLE_SORT}, $2)
+                    WHERE {TRACK_ID} = $3"
+                ),
+                &[
+                    &sort_names::artist_sort_name(row.get(1)),
+                    &sort_names::sort_name(row.get(2)),
+                    &row.get::<_, TrackId>(0),
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Credits tracks whose metadata was stored before artists were
+    fn link_missing_credits(db: &mut Client) -> Result<(), StorageError> {
+        let mut tx = db.transaction()?;
+        let rows = tx.query(
+            &format!(
+                "SELECT m.{TRACK_ID}, m.{ARTIST}, m.{TITLE} FROM {TRACK_METADATA} m
+                WHERE NOT EXISTS (SELECT 1 FROM {TRACK_ARTISTS} ta WHERE ta.{TRACK_ID} = m.{TRACK_ID})"
+            ),
+            &[],
+        )?;
+        for row in rows {
+            let credits = artists::parse_credits(row.get(1), row.get(2));
+            Self::link_credits(&mut tx, row.get(0), &credits)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_sources(
+        db: &mut impl GenericClient,
+        track_id: TrackId,
+    ) -> Result<Vec<FieldSource>, StorageError> {
+        db.query(
+            &format!(
+                "SELECT {FIELD}, {ORIGIN}, {CHANGED_AT} FROM {METADATA_SOURCES} WHERE {TRACK_ID} = $1"
+            ),
+            &[&track_id],
+        )?
+        .iter()
+        .map(|row| {
+            Ok(FieldSource {
+                field: row.get::<_, &str>(0).parse().map_err(StorageError::Internal)?,
+                origin: row.get::<_, &str>(1).parse().map_err(StorageError::Internal)?,
+                changed_at: i64_seconds_to_local_time(row.get(2)).map_err(StorageError::Internal)?,
+            })
+        })
+        .collect()
+    }
+
+    fn record_sources(
+        tx: &mut Transaction,
+        track_id: TrackId,
+        fields: &[MetaField],
+        origin: MetadataOrigin,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        for field in fields {
+            tx.execute(
+                &format!(
+                    "INSERT INTO {METADATA_SOURCES} ({TRACK_ID}, {FIELD}, {ORIGIN}, {CHANGED_AT})
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT ({TRACK_ID}, {FIELD}) DO UPDATE SET
+                        {ORIGIN} = excluded.{ORIGIN},
+                        {CHANGED_AT} = excluded.{CHANGED_AT}"
+                ),
+                &[&track_id, &field.as_str(), &origin.as_str(), &now],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn track_exists(tx: &mut Transaction, track_id: TrackId) -> Result<bool, StorageError> {
+        Ok(tx
+            .query_opt(
+                &format!("SELECT 1 FROM {TRACKS} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?
+            .is_some())
+    }
+
+    /// Recorded files the scan marked seen at `seen_at` did not find, leaving out files
+    /// under unavailable roots and files which are not scanned
+    fn gone_files(
+        &mut self,
+        seen_at: i64,
+        unavailable: &[UnavailableRoot],
+    ) -> Result<HashSet<LocationRow>, StorageError> {
+        let rows = self.db.query(
+            &format!(
+                "SELECT {USB_LABEL}, {PATH}, {FILE_SIZE} FROM {FILES}
+                WHERE {LAST_SEEN} IS NULL OR {LAST_SEEN} < $1"
+            ),
+            &[&seen_at],
+        )?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|r| {
+                let row = LocationRow {
+                    usb_label: r.get(0),
+                    path: r.get(1),
+                };
+                let file = FileWithMeta {
+                    loc: row.clone().into(),
+                    file_size: r.get(2),
+                };
+                let kept = unavailable
+                    .iter()
+                    .any(|root| file.loc.starts_with(&root.location))
+                    || self.fs.is_disabled(&file.loc)
+                    || self.fs.exceeds_max_size(&file);
+                (!kept).then_some(row)
+            })
+            .collect())
+    }
+
+    /// Inserts the files, reusing track ids of already known hashes.
+    /// A file with the content of a row in `gone` takes over that row instead.
+    ///
+    /// Returns only inserted files, location conflicts are ignored.
+    fn insert_files(
+        &mut self,
+        files: Vec<HashedFile>,
+        gone: &mut HashSet<LocationRow>,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        let mut inserted: HashMap<TrackId, HashSet<HashedFile>> = HashMap::new();
+        let mut moved = false;
+        for file in files {
+            let hash = file.hash.to_string();
+            let row = LocationRow::from_location(file.file.loc.clone())?;
+            let modified = self.fs.modified_time(&file.file.loc);
+            let old = tx
+                .query(
+                    &format!("SELECT {USB_LABEL}, {PATH} FROM {FILES} WHERE {FILE_HASH} = $1"),
+                    &[&hash],
+                )?
+                .into_iter()
+                .map(|r| LocationRow {
+                    usb_label: r.get(0),
+                    path: r.get(1),
+                })
+                .find(|old| gone.contains(old));
+            if let Some(old) = old {
+                gone.remove(&old);
+                tx.execute(
+                    &format!(
+                        "UPDATE {FILES} SET {USB_LABEL} = $1, {PATH} = $2, {FILE_SIZE} = $3,
+                            {LAST_SEEN} = $4, {MODIFIED_AT} = $5, {FORMAT} = $6
+                        WHERE {USB_LABEL} = $7 AND {PATH} = $8"
+                    ),
+                    &[
+                        &row.usb_label,
+                        &row.path,
+                        &file.file.file_size,
+                        &now,
+                        &modified,
+                        &file_format(&row.path),
+                        &old.usb_label,
+                        &old.path,
+                    ],
+                )?;
+                let from: Location = old.into();
+                println!("Moved {from} to {}", file.file.loc);
+                moved = true;
+                continue;
+            }
+            let existing = tx.query_opt(
+                &format!("SELECT {TRACK_ID} FROM {FILES} WHERE {FILE_HASH} = $1 LIMIT 1"),
+                &[&hash],
+            )?;
+            let track_id: TrackId = match existing {
+                Some(row) => row.get(0),
+                None => tx
+                    .query_one(
+                        &format!(
+                            "INSERT INTO {TRACKS} ({CREATED_AT}) VALUES ($1) RETURNING {TRACK_ID}"
+                        ),
+                        &[&now],
+                    )?
+                    .get(0),
+            };
+            let changed = tx.execute(
+                &format!(
+                    "INSERT INTO {FILES} ({USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {LAST_SEEN}, {HASH_KIND}, {MODIFIED_AT}, {FORMAT})
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO NOTHING"
+                ),
+                &[
+                    &row.usb_label,
+                    &row.path,
+                    &track_id,
+                    &file.file.file_size,
+                    &hash,
+                    &now,
+                    &file.kind.as_str(),
+                    &modified,
+                    &file_format(&row.path),
+                ],
+            )?;
+            if changed > 0 {
+                inserted.entry(track_id).or_default().insert(file);
+            }
+        }
+        if moved || !inserted.is_empty() {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+}
+
+impl LibraryStore for PgStorage {
+    fn set_progress(&mut self, progress: Box<dyn Progress>) {
+        self.fs.progress = progress;
+    }
+
+    fn unavailable_roots(&mut self) -> Vec<UnavailableRoot> {
+        self.fs.unavailable_roots()
+    }
+
+    fn transliterate_search(&self) -> bool {
+        self.fs.transliterate_search()
+    }
+
+    fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.fs.set_ignored_dirs(dirs)
+    }
+
+    fn update_db_with_new_files(
+        &mut self,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        let scan = LibraryScan::run(&mut self.fs)?;
+        let mut update = self.start_update(scan)?;
+        let new_files = mem::take(&mut update.new_files);
+
+        let kind = self.fs.hash_kind();
+        let total_bytes = new_files.iter().map(|f| f.file_size as u64).sum();
+        self.fs
+            .progress
+            .hashing_started(new_files.len(), total_bytes);
+        let result: Result<(), StorageError> = (|| {
+            for batch in new_files.chunks(UPDATE_CHECKPOINT_FILES) {
+                let with_hash = batch
+                    .iter()
+                    .map(|f| self.fs.hash_file(f, kind))
+                    .collect::<Result<Vec<_>, StorageError>>()?;
+                self.insert_new_files(&mut update, with_hash)?;
+            }
+            Ok(())
+        })();
+        self.fs.progress.hashing_finished();
+        result?;
+        self.finish_update(update)
+    }
+
+    fn library_source(&self) -> LibrarySource {
+        self.fs.source().clone()
+    }
+
+    fn start_update(&mut self, scan: LibraryScan) -> Result<PendingUpdate, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut new_files: Vec<FileWithMeta> = vec![];
+        {
+            let mut tx = self.db.transaction()?;
+            let seen = tx.prepare(&format!(
+                "UPDATE {FILES} SET {LAST_SEEN} = $1, {FORMAT} = COALESCE({FORMAT}, $4)
+                WHERE {USB_LABEL} = $2 AND {PATH} = $3"
+            ))?;
+            for file in &scan.snapshot {
+                let row = LocationRow::from_location(file.loc.clone())?;
+                let format = file_format(&row.path);
+                if tx.execute(&seen, &[&now, &row.usb_label, &row.path, &format])? == 0 {
+                    new_files.push(file.clone());
+                }
+            }
+            tx.commit()?;
+        }
+        let gone = self.gone_files(now, &scan.unavailable)?;
+        Ok(PendingUpdate::new(scan, new_files, gone))
+    }
+
+    fn insert_new_files(
+        &mut self,
+        update: &mut PendingUpdate,
+        files: Vec<HashedFile>,
+    ) -> Result<(), StorageError> {
+        for (track, files) in self.insert_files(files, &mut update.gone)? {
+            update.inserted.entry(track).or_default().extend(files);
+        }
+        Ok(())
+    }
+
+    /// Scan reports are not kept, see the module docs
+    fn finish_update(
+        &mut self,
+        update: PendingUpdate,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        Ok(update.inserted)
+    }
+
+    fn scan_metadata(&mut self) -> Result<Vec<Track>, StorageError> {
+        let rows = self.db.query(
+            &format!(
+                "SELECT {TRACK_ID}, {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL} FROM {TRACK_METADATA}"
+            ),
+            &[],
+        )?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Track {
+                id: row.get(0),
+                metadata: TrackMetadata {
+                    title: row.get(1),
+                    artist: row.get(2),
+                    year: row.get::<_, Option<i32>>(3).map(|y| y as u32),
+                    label: row.get(4),
+                    artwork: row.get::<_, Option<String>>(5).map(ArtworkRef),
+                },
+            })
+            .collect())
+    }
+
+    fn updated_at(&mut self) -> Result<DateTime<Local>, StorageError> {
+        let latest: i64 = self
+            .db
+            .query_one(
+                &format!("SELECT COALESCE(MAX({UPDATED_AT}), 0) FROM {UPDATES}"),
+                &[],
+            )?
+            .get(0);
+        i64_seconds_to_local_time(latest).map_err(StorageError::Internal)
+    }
+
+    fn list_tracks(&mut self) -> Result<Vec<TrackListEntry>, StorageError> {
+        self.list_tracks_matching(&TrackFilter::default())
+    }
+
+    fn list_tracks_matching(
+        &mut self,
+        filter: &TrackFilter,
+    ) -> Result<Vec<TrackListEntry>, StorageError> {
+        let artist = filter.artist.as_deref().map(str::trim);
+        let year_from = filter.year_from.map(|year| year as i32);
+        let year_to = filter.year_to.map(|year| year as i32);
+        let format = filter.normalized_format();
+        let mut conditions = String::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![];
+        let metadata = |condition: String| {
+            format!(
+                " AND EXISTS (SELECT 1 FROM {TRACK_METADATA} m
+                WHERE m.{TRACK_ID} = t.{TRACK_ID} AND {condition})"
+            )
+        };
+        if let Some(artist) = &artist {
+            params.push(artist);
+            conditions.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM {TRACK_ARTISTS} ta
+                JOIN {ARTISTS} a ON a.{ARTIST_ID} = ta.{ARTIST_ID}
+                WHERE ta.{TRACK_ID} = t.{TRACK_ID} AND LOWER(a.{NAME}) = LOWER(${}))",
+                params.len()
+            ));
+        }
+        if let Some(year) = &year_from {
+            params.push(year);
+            conditions.push_str(&metadata(format!("m.{YEAR} >= ${}", params.len())));
+        }
+        if let Some(year) = &year_to {
+            params.push(year);
+            conditions.push_str(&metadata(format!("m.{YEAR} <= ${}", params.len())));
+        }
+        if let Some(format) = &format {
+            params.push(format);
+            conditions.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM {FILES} ff
+                WHERE ff.{TRACK_ID} = t.{TRACK_ID} AND ff.{FORMAT} = ${})",
+                params.len()
+            ));
+        }
+
+        let rows = self.db.query(
+            &format!(
+                "SELECT t.{TRACK_ID}, t.{CREATED_AT}, f.{USB_LABEL}, f.{PATH}, f.{FILE_SIZE}, f.{MODIFIED_AT}, f.{FORMAT},
+                    md.{ARTIST}, md.{TITLE}, md.{YEAR}, md.{ARTIST_SORT}, md.{TITLE_SORT}
+                FROM {TRACKS} t
+                LEFT JOIN {FILES} f ON t.{TRACK_ID} = f.{TRACK_ID}
+                LEFT JOIN {TRACK_METADATA} md ON t.{TRACK_ID} = md.{TRACK_ID}
+                WHERE 1=1{conditions}
+                ORDER BY t.{TRACK_ID}, f.{USB_LABEL}, f.{PATH}"
+            ),
+            &params,
+        )?;
+
+        let mut entries: Vec<TrackListEntry> = Vec::new();
+        for row in rows {
+            let track_id: TrackId = row.get(0);
+            if entries.last().map(|e| e.id) != Some(track_id) {
+                let added: Option<i64> = row.get(1);
+                let artist: Option<String> = row.get(7);
+                let title: Option<String> = row.get(8);
+                entries.push(TrackListEntry {
+                    id: track_id,
+                    added: added
+                        .map(i64_seconds_to_local_time)
+                        .transpose()
+                        .map_err(StorageError::Internal)?,
+                    files: vec![],
+                    meta: artist.zip(title).map(|(artist, title)| ListedMetadata {
+                        artist_sort: row
+                            .get::<_, Option<String>>(10)
+                            .unwrap_or_else(|| sort_names::artist_sort_name(&artist)),
+                        title_sort: row
+                            .get::<_, Option<String>>(11)
+                            .unwrap_or_else(|| sort_names::sort_name(&title)),
+                        artist,
+                        title,
+                        year: row.get::<_, Option<i32>>(9).map(|y| y as u32),
+                    }),
+                });
+            }
+            let usb_label: Option<String> = row.get(2);
+            let path: Option<String> = row.get(3);
+            if let (Some(entry), Some((usb_label, path))) =
+                (entries.last_mut(), usb_label.zip(path))
+            {
+                let modified: Option<i64> = row.get(5);
+                entry.files.push(ListedFile {
+                    loc: LocationRow { usb_label, path }.into(),
+                    size: row.get(4),
+                    modified: modified
+                        .map(i64_seconds_to_local_time)
+                        .transpose()
+                        .map_err(StorageError::Internal)?,
+                    format: row.get(6),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn list_artists(&mut self) -> Result<Vec<ArtistEntry>, StorageError> {
+        let mut artists: Vec<ArtistEntry> = self
+            .db
+            .query(
+                &format!(
+                    "SELECT a.{NAME}, COUNT(DISTINCT ta.{TRACK_ID}) FROM {ARTISTS} a
+                     JOIN {TRACK_ARTISTS} ta ON ta.{ARTIST_ID} = a.{ARTIST_ID}
+                     GROUP BY a.{ARTIST_ID}, a.{NAME}"
+                ),
+                &[],
+            )?
+            .iter()
+            .map(|row| ArtistEntry {
+                name: row.get(0),
+                tracks: row.get::<_, i64>(1) as usize,
+            })
+            .collect();
+        sort_names::sort_artists(&mut artists);
+        Ok(artists)
+    }
+
+    fn list_years(&mut self) -> Result<Vec<YearEntry>, StorageError> {
+        Ok(self
+            .db
+            .query(
+                &format!(
+                    "SELECT {YEAR}, COUNT(*) FROM {TRACK_METADATA}
+                     WHERE {YEAR} IS NOT NULL
+                     GROUP BY {YEAR}
+                     ORDER BY {YEAR}"
+                ),
+                &[],
+            )?
+            .iter()
+            .map(|row| YearEntry {
+                year: row.get::<_, i32>(0) as u32,
+                tracks: row.get::<_, i64>(1) as usize,
+            })
+            .collect())
+    }
+
+    fn list_hashed_files(&mut self) -> Result<Vec<(TrackId, HashedFile)>, StorageError> {
+        let rows = self.db.query(
+            &format!(
+                "SELECT {TRACK_ID}, {USB_LABEL}, {PATH}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}
+                FROM {FILES}
+                ORDER BY {TRACK_ID}, {USB_LABEL}, {PATH}"
+            ),
+            &[],
+        )?;
+        rows.into_iter()
+            .map(|row| {
+                let loc = LocationRow {
+                    usb_label: row.get(1),
+                    path: row.get(2),
+                };
+                let file = FileWithMeta {
+                    loc: loc.into(),
+                    file_size: row.get(3),
+                };
+                let hash = parse_file_hash(row.get(4))?;
+                let kind = parse_hash_kind(row.get(5))?;
+                Ok((row.get(0), HashedFile::with_kind(hash, kind, file)))
+            })
+            .collect()
+    }
+
+    fn resolve_track(&mut self, card_id: CardId) -> Result<TrackId, StorageError> {
+        let parsed_id = card_id.parse::<i64>().unwrap_or(-1);
+        let row = self.db.query_opt(
+            &format!(
+                "SELECT t.{TRACK_ID}
+                FROM {TRACKS} t
+                LEFT JOIN {CARD_MAPPINGS} cm ON t.{TRACK_ID} = cm.{TRACK_ID}
+                WHERE cm.{CARD_ID} = $1 OR t.{TRACK_ID} = $2
+                LIMIT 1"
+            ),
+            &[&card_id, &parsed_id],
+        )?;
+        match row {
+            Some(row) => Ok(row.get(0)),
+            None => Err(StorageError::TrackNotFound(card_id)),
+        }
+    }
+
+    fn find_track_file_with_meta(
+        &mut self,
+        track: TrackId,
+    ) -> Result<(PathBuf, Location, Option<TrackMetadata>), StorageError> {
+        let paths: Vec<Location> = self
+            .db
+            .query(
+                &format!(
+                    "SELECT {USB_LABEL}, {PATH} FROM {FILES}
+                    WHERE {TRACK_ID} = $1 AND {QUARANTINED_AT} IS NULL"
+                ),
+                &[&track],
+            )?
+            .into_iter()
+            .map(|row| {
+                LocationRow {
+                    usb_label: row.get(0),
+                    path: row.get(1),
+                }
+                .into()
+            })
+            .collect();
+        if paths.is_empty() {
+            return Err(StorageError::TrackNotFound(track.to_string()));
+        }
+        let (path, loc) = self.fs.pick_track_file(track, paths)?;
+        let meta = Self::load_metadata(&mut self.db, track)?;
+        Ok((path, loc, meta))
+    }
+
+    fn get_track_metadata(
+        &mut self,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError> {
+        Self::load_metadata(&mut self.db, track_id)
+    }
+
+    fn find_files(
+        &mut self,
+        query: &str,
+        no_meta: bool,
+    ) -> Result<HashMap<TrackId, HashSet<Location>>, StorageError> {
+        let query = SearchQuery::parse(query, self.fs.transliterate_search())?;
+
+        let mut sql = format!(
+            "SELECT f.{TRACK_ID}, f.{USB_LABEL}, f.{PATH}, f.{FILE_HASH}, cm.{CARD_ID},
+                tm.{ARTIST}, tm.{TITLE}, tm.{LABEL}, tn.{NOTES}
+            FROM {FILES} f
+            LEFT JOIN {TRACK_METADATA} tm ON f.{TRACK_ID} = tm.{TRACK_ID}
+            LEFT JOIN {CARD_MAPPINGS} cm ON f.{TRACK_ID} = cm.{TRACK_ID}
+            LEFT JOIN {TRACK_NOTES} tn ON f.{TRACK_ID} = tn.{TRACK_ID}
+            WHERE 1=1"
+        );
+        if no_meta {
+            sql.push_str(&format!(" AND tm.{TRACK_ID} IS NULL"));
+        }
+        let mut years: Vec<i32> = vec![];
+        for term in query.filters() {
+            if let Condition::Year { from, to } = &term.condition {
+                years.extend(from.iter().chain(to).map(|year| *year as i32));
+            }
+        }
+        let mut years_left = years.iter();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![];
+        for term in query.filters() {
+            let condition = match &term.condition {
+                Condition::Year { from, to } => {
+                    let mut bounds = vec![];
+                    for (bound, op) in [(from, ">="), (to, "<=")] {
+                        if bound.is_some() {
+                            params.push(years_left.next().unwrap());
+                            bounds.push(format!("m.{YEAR} {op} ${}", params.len()));
+                        }
+                    }
+                    format!(
+                        "EXISTS (SELECT 1 FROM {TRACK_METADATA} m
+                        WHERE m.{TRACK_ID} = f.{TRACK_ID} AND {})",
+                        bounds.join(" AND ")
+                    )
+                }
+                Condition::Format(format) => {
+                    params.push(format);
+                    format!("COALESCE(f.{FORMAT}, '') = ${}", params.len())
+                }
+                Condition::Playlist(name) => {
+                    params.push(name);
+                    format!(
+                        "EXISTS (SELECT 1 FROM {PLAYLIST_TRACKS} pt
+                        JOIN {PLAYLISTS} p ON p.{PLAYLIST_ID} = pt.{PLAYLIST_ID}
+                        WHERE pt.{TRACK_ID} = f.{TRACK_ID} AND LOWER(p.{NAME}) = LOWER(${}))",
+                        params.len()
+                    )
+                }
+                Condition::Text { .. } => continue,
+            };
+            let not = if term.negated { "NOT " } else { "" };
+            sql.push_str(&format!(" AND {not}{condition}"));
+        }
+
+        let mut files: HashMap<(String, String), SearchedFile> = HashMap::new();
+        for row in self.db.query(&sql, &params)? {
+            let file = files
+                .entry((row.get(1), row.get(2)))
+                .or_insert_with(|| SearchedFile {
+                    track_id: row.get(0),
+                    path: row.get(2),
+                    hash: row.get::<_, Option<String>>(3).unwrap_or_default(),
+                    cards: vec![],
+                    artist: row.get(5),
+                    title: row.get(6),
+                    label: row.get(7),
+                    notes: row.get(8),
+                });
+            if let Some(card) = row.get(4) {
+                file.cards.push(card);
+            }
+        }
+
+        let mut map: HashMap<TrackId, HashSet<Location>> = HashMap::new();
+        for ((usb_label, path), file) in files {
+            if query.matches(&file) {
+                let loc: Location = LocationRow { usb_label, path }.into();
+                map.entry(file.track_id).or_default().insert(loc);
+            }
+        }
+        Ok(map)
+    }
+
+    fn check_stale(&mut self) -> Result<StaleTracks, StorageError> {
+        let rows = self.db.query(
+            &format!(
+                "SELECT t.{TRACK_ID}, m.{TRACK_ID} IS NOT NULL
+                FROM {TRACKS} t
+                LEFT JOIN {FILES} f ON t.{TRACK_ID} = f.{TRACK_ID}
+                LEFT JOIN {TRACK_METADATA} m ON t.{TRACK_ID} = m.{TRACK_ID}
+                WHERE f.{TRACK_ID} IS NULL"
+            ),
+            &[],
+        )?;
+        let mut result = StaleTracks::default();
+        for row in rows {
+            if row.get(1) {
+                result.metadata_only.push(row.get(0));
+            } else {
+                result.dangling.push(row.get(0));
+            }
+        }
+        Ok(result)
+    }
+
+    fn update_track_metadata(
+        &mut self,
+        track_id: TrackId,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let current_meta = Self::load_metadata(&mut tx, track_id)?;
+        let merged =
+            Storage::update_meta(track_id, current_meta.clone(), new_meta, allow_overwrite)?;
+        let changed = provenance::changed_fields(current_meta.as_ref(), &merged);
+
+        Self::store_metadata(&mut tx, track_id, merged)?;
+        Self::record_sources(&mut tx, track_id, &changed, MetadataOrigin::Manual)?;
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn apply_metadata_under(
+        &mut self,
+        path: &Path,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<ApplyMetadataReport, StorageError> {
+        let (path_prefix, dir_start, dir_end) = path_range(path);
+
+        let mut tx = self.db.transaction()?;
+        let track_ids: Vec<TrackId> = tx
+            .query(
+                &format!(
+                    "SELECT DISTINCT {TRACK_ID} FROM {FILES}
+                    WHERE {} ORDER BY {TRACK_ID}",
+                    under_path()
+                ),
+                &[&path_prefix, &dir_start, &dir_end],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let mut report = ApplyMetadataReport {
+            matched_tracks: track_ids.len(),
+            ..Default::default()
+        };
+        for track_id in track_ids {
+            let current_meta = Self::load_metadata(&mut tx, track_id)?;
+            let merged = match Storage::update_meta(
+                track_id,
+                current_meta.clone(),
+                new_meta.clone(),
+                allow_overwrite,
+            ) {
+                Ok(merged) => merged,
+                Err(StorageError::MetadataOverwriteDenied(_)) => {
+                    report.kept.push(track_id);
+                    continue;
+                }
+                Err(StorageError::RequiredMetaMissing(_)) => {
+                    report.missing_required.push(track_id);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if current_meta.as_ref() != Some(&merged) {
+                let changed = provenance::changed_fields(current_meta.as_ref(), &merged);
+                Self::store_metadata(&mut tx, track_id, merged)?;
+                Self::record_sources(&mut tx, track_id, &changed, MetadataOrigin::Manual)?;
+                report.updated.push(track_id);
+            }
+        }
+        if !report.updated.is_empty() {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+
+    fn import_tags(
+        &mut self,
+        tracks: &[TrackId],
+        policy: MetadataPolicy,
+    ) -> Result<TagImportReport, StorageError> {
+        let mut report = TagImportReport::default();
+        let tagged = provenance::read_track_tags(
+            tracks,
+            |track| {
+                self.find_track_file_with_meta(track)
+                    .map(|(path, location, _)| (path, location))
+            },
+            &mut report.skipped,
+        );
+
+        let mut tx = self.db.transaction()?;
+        for track in &tagged {
+            let current = Self::load_metadata(&mut tx, track.track_id)?;
+            let sources = Self::load_sources(&mut tx, track.track_id)?;
+            let had_metadata = current.is_some();
+            let merge = provenance::merge_tags(track, current, &sources, policy);
+            report.conflicts.extend(merge.conflicts);
+            let Some(meta) = merge.meta else {
+                if !had_metadata {
+                    report.skipped.push(track.track_id);
+                }
+                continue;
+            };
+            Self::store_metadata(&mut tx, track.track_id, meta)?;
+            Self::record_sources(
+                &mut tx,
+                track.track_id,
+                &merge.from_tags,
+                MetadataOrigin::Tags,
+            )?;
+            report.updated.push(track.track_id);
+        }
+        if !report.updated.is_empty() {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+
+    fn metadata_sources(&mut self, track_id: TrackId) -> Result<Vec<FieldSource>, StorageError> {
+        Self::load_sources(&mut self.db, track_id)
+    }
+
+    fn get_lyrics(&mut self, track_id: TrackId) -> Result<Option<TrackLyrics>, StorageError> {
+        let row = self.db.query_opt(
+            &format!(
+                "SELECT {PLAIN_LYRICS}, {SYNCED_LYRICS} FROM {TRACK_LYRICS} WHERE {TRACK_ID} = $1"
+            ),
+            &[&track_id],
+        )?;
+        Ok(row.map(|row| TrackLyrics {
+            plain: row.get(0),
+            synced: row.get(1),
+        }))
+    }
+
+    fn set_lyrics(&mut self, track_id: TrackId, lyrics: &TrackLyrics) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        tx.execute(
+            &format!(
+                "INSERT INTO {TRACK_LYRICS} ({TRACK_ID}, {PLAIN_LYRICS}, {SYNCED_LYRICS}, {FETCHED_AT})
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT ({TRACK_ID}) DO UPDATE SET
+                    {PLAIN_LYRICS} = excluded.{PLAIN_LYRICS},
+                    {SYNCED_LYRICS} = excluded.{SYNCED_LYRICS},
+                    {FETCHED_AT} = excluded.{FETCHED_AT}"
+            ),
+            &[&track_id, &lyrics.plain, &lyrics.synced, &now],
+        )
+        .map_err(|e| {
+            if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                StorageError::TrackNotFound(track_id.to_string())
+            } else {
+                e.into()
+            }
+        })?;
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn create_playlist(&mut self, name: &str) -> Result<Playlist, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        let row = tx
+            .query_one(
+                &format!(
+                    "INSERT INTO {PLAYLISTS} ({NAME}, {CREATED_AT}) VALUES ($1, $2) RETURNING {PLAYLIST_ID}"
+                ),
+                &[&name, &now],
+            )
+            .map_err(|e| {
+                if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    StorageError::PlaylistExists(name.to_string())
+                } else {
+                    e.into()
+                }
+            })?;
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(Playlist {
+            id: row.get(0),
+            name: name.to_string(),
+            share_token: None,
+            tracks: vec![],
+        })
+    }
+
+    fn delete_playlist(&mut self, id: PlaylistId) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let deleted = tx.execute(
+            &format!("DELETE FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = $1"),
+            &[&id],
+        )?;
+        if deleted == 0 {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list_playlists(&mut self) -> Result<Vec<Playlist>, StorageError> {
+        let ids: Vec<PlaylistId> = self
+            .db
+            .query(
+                &format!("SELECT {PLAYLIST_ID} FROM {PLAYLISTS} ORDER BY {NAME}"),
+                &[],
+            )?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        ids.into_iter().map(|id| self.get_playlist(id)).collect()
+    }
+
+    fn get_playlist(&mut self, id: PlaylistId) -> Result<Playlist, StorageError> {
+        let row = self
+            .db
+            .query_opt(
+                &format!("SELECT {NAME}, {SHARE_TOKEN} FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = $1"),
+                &[&id],
+            )?
+            .ok_or_else(|| StorageError::PlaylistNotFound(id.to_string()))?;
+        let tracks = self
+            .db
+            .query(
+                &format!(
+                    "SELECT {TRACK_ID} FROM {PLAYLIST_TRACKS} WHERE {PLAYLIST_ID} = $1 ORDER BY {POSITION}"
+                ),
+                &[&id],
+            )?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        Ok(Playlist {
+            id,
+            name: row.get(0),
+            share_token: row.get(1),
+            tracks,
+        })
+    }
+
+    fn set_playlist_tracks(
+        &mut self,
+        id: PlaylistId,
+        tracks: &[TrackId],
+    ) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let exists = tx.query_opt(
+            &format!("SELECT 1 FROM {PLAYLISTS} WHERE {PLAYLIST_ID} = $1"),
+            &[&id],
+        )?;
+        if exists.is_none() {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        tx.execute(
+            &format!("DELETE FROM {PLAYLIST_TRACKS} WHERE {PLAYLIST_ID} = $1"),
+            &[&id],
+        )?;
+        let insert = tx.prepare(&format!(
+            "INSERT INTO {PLAYLIST_TRACKS} ({PLAYLIST_ID}, {POSITION}, {TRACK_ID}) VALUES ($1, $2, $3)"
+        ))?;
+        for (position, track) in tracks.iter().enumerate() {
+            tx.execute(&insert, &[&id, &(position as i64), track])
+                .map_err(|e| {
+                    if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                        StorageError::TrackNotFound(track.to_string())
+                    } else {
+                        e.into()
+                    }
+                })?;
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_playlist_token(
+        &mut self,
+        id: PlaylistId,
+        token: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let updated = tx.execute(
+            &format!("UPDATE {PLAYLISTS} SET {SHARE_TOKEN} = $1 WHERE {PLAYLIST_ID} = $2"),
+            &[&token, &id],
+        )?;
+        if updated == 0 {
+            return Err(StorageError::PlaylistNotFound(id.to_string()));
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn is_favourite(&mut self, track_id: TrackId) -> Result<bool, StorageError> {
+        Ok(self
+            .db
+            .query_opt(
+                &format!("SELECT 1 FROM {FAVOURITES} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?
+            .is_some())
+    }
+
+    fn set_favourite(&mut self, track_id: TrackId, favourite: bool) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        if !Self::track_exists(&mut tx, track_id)? {
+            return Err(StorageError::TrackNotFound(track_id.to_string()));
+        }
+        if favourite {
+            tx.execute(
+                &format!(
+                    "INSERT INTO {FAVOURITES} ({TRACK_ID}, {CREATED_AT}) VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING"
+                ),
+                &[&track_id, &now],
+            )?;
+        } else {
+            tx.execute(
+                &format!("DELETE FROM {FAVOURITES} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?;
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list_favourites(&mut self) -> Result<Vec<TrackId>, StorageError> {
+        Ok(self
+            .db
+            .query(
+                &format!("SELECT {TRACK_ID} FROM {FAVOURITES} ORDER BY {CREATED_AT} DESC"),
+                &[],
+            )?
+            .iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    fn short_link(&mut self, target: &str) -> Result<String, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        let existing = tx.query_opt(
+            &format!("SELECT {CODE} FROM {SHORT_LINKS} WHERE {TARGET} = $1"),
+            &[&target],
+        )?;
+        let code = match existing {
+            Some(row) => row.get(0),
+            None => {
+                let mut inserted = None;
+                for _ in 0..short_links::ATTEMPTS {
+                    let code = short_links::new_code();
+                    let added = tx.execute(
+                        &format!(
+                            "INSERT INTO {SHORT_LINKS} ({CODE}, {TARGET}, {CREATED_AT})
+                            VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"
+                        ),
+                        &[&code, &target, &now],
+                    )?;
+                    if added == 1 {
+                        inserted = Some(code);
+                        break;
+                    }
+                }
+                inserted.ok_or_else(|| {
+                    StorageError::Internal(anyhow!("no free short code for {target}"))
+                })?
+            }
+        };
+        tx.commit()?;
+        Ok(code)
+    }
+
+    fn resolve_short_link(&mut self, code: &str) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_opt(
+                &format!("SELECT {TARGET} FROM {SHORT_LINKS} WHERE {CODE} = $1"),
+                &[&code],
+            )?
+            .map(|row| row.get(0)))
+    }
+
+    fn youtube_id(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_opt(
+                &format!("SELECT {VIDEO_ID} FROM {YOUTUBE_IDS} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?
+            .map(|row| row.get(0)))
+    }
+
+    fn set_youtube_id(&mut self, track_id: TrackId, video_id: &str) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        self.db
+            .execute(
+                &format!(
+                    "INSERT INTO {YOUTUBE_IDS} ({TRACK_ID}, {VIDEO_ID}, {UPDATED_AT})
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT ({TRACK_ID}) DO UPDATE SET
+                        {VIDEO_ID} = excluded.{VIDEO_ID},
+                        {UPDATED_AT} = excluded.{UPDATED_AT}"
+                ),
+                &[&track_id, &video_id, &now],
+            )
+            .map_err(|e| {
+                if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                    StorageError::TrackNotFound(track_id.to_string())
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    fn track_notes(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_opt(
+                &format!("SELECT {NOTES} FROM {TRACK_NOTES} WHERE {TRACK_ID} = $1"),
+                &[&track_id],
+            )?
+            .map(|row| row.get(0)))
+    }
+
+    fn set_track_notes(
+        &mut self,
+        track_id: TrackId,
+        notes: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        match clean_notes(notes) {
+            Some(notes) => {
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {TRACK_NOTES} ({TRACK_ID}, {NOTES}, {UPDATED_AT})
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT ({TRACK_ID}) DO UPDATE SET
+                            {NOTES} = excluded.{NOTES},
+                            {UPDATED_AT} = excluded.{UPDATED_AT}"
+                    ),
+                    &[&track_id, &notes, &now],
+                )
+                .map_err(|e| {
+                    if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                        StorageError::TrackNotFound(track_id.to_string())
+                    } else {
+                        e.into()
+                    }
+                })?;
+            }
+            None => {
+                tx.execute(
+                    &format!("DELETE FROM {TRACK_NOTES} WHERE {TRACK_ID} = $1"),
+                    &[&track_id],
+                )?;
+            }
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn artist_credits(&mut self, track_id: TrackId) -> Result<Vec<ArtistCredit>, StorageError> {
+        self.db
+            .query(
+                &format!(
+                    "SELECT a.{NAME}, ta.{ROLE} FROM {TRACK_ARTISTS} ta
+                    JOIN {ARTISTS} a ON a.{ARTIST_ID} = ta.{ARTIST_ID}
+                    WHERE ta.{TRACK_ID} = $1
+                    ORDER BY ta.{POSITION}"
+                ),
+                &[&track_id],
+            )?
+            .iter()
+            .map(|row| {
+                let role = row
+                    .get::<_, &str>(1)
+                    .parse()
+                    .map_err(|e: String| StorageError::Internal(anyhow!(e)))?;
+                Ok(ArtistCredit::new(row.get::<_, String>(0), role))
+            })
+            .collect()
+    }
+
+    fn set_artist_credits(
+        &mut self,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let meta = Self::load_metadata(&mut tx, track_id)?
+            .ok_or(StorageError::RequiredMetaMissing(track_id))?;
+        if credits.is_empty() {
+            let credits = artists::parse_credits(&meta.artist, &meta.title);
+            Self::link_credits(&mut tx, track_id, &credits)?;
+        } else {
+            let display = artists::display_artist(credits)
+                .ok_or(StorageError::RequiredMetaMissing(track_id))?;
+            Self::link_credits(&mut tx, track_id, credits)?;
+            if display != meta.artist {
+                tx.execute(
+                    &format!(
+                        "UPDATE {TRACK_METADATA} SET {ARTIST} = $1, {ARTIST_SORT} = $2
+                        WHERE {TRACK_ID} = $3"
+                    ),
+                    &[&display, &sort_names::artist_sort_name(&display), &track_id],
+                )?;
+                Self::record_sources(
+                    &mut tx,
+                    track_id,
+                    &[MetaField::Artist],
+                    MetadataOrigin::Manual,
+                )?;
+            }
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn sort_names(&mut self, track_id: TrackId) -> Result<Option<SortNames>, StorageError> {
+        Ok(self
+            .db
+            .query_opt(
+                &format!(
+                    "SELECT {ARTIST}, {TITLE}, {ARTIST_SORT}, {TITLE_SORT}
+                    FROM {TRACK_METADATA} WHERE {TRACK_ID} = $1"
+                ),
+                &[&track_id],
+            )?
+            .map(|row| SortNames {
+                artist: row
+                    .get::<_, Option<String>>(2)
+                    .unwrap_or_else(|| sort_names::artist_sort_name(row.get(0))),
+                title: row
+                    .get::<_, Option<String>>(3)
+                    .unwrap_or_else(|| sort_names::sort_name(row.get(1))),
+            }))
+    }
+
+    fn set_sort_names(
+        &mut self,
+        track_id: TrackId,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let mut tx = self.db.transaction()?;
+        let meta = Self::load_metadata(&mut tx, track_id)?
+            .ok_or(StorageError::RequiredMetaMissing(track_id))?;
+        let artist = artist.map(|artist| match artist.trim() {
+            "" => sort_names::artist_sort_name(&meta.artist),
+            artist => artist.to_string(),
+        });
+        let title = title.map(|title| match title.trim() {
+            "" => sort_names::sort_name(&meta.title),
+            title => title.to_string(),
+        });
+        tx.execute(
+            &format!(
+                "UPDATE {TRACK_METADATA} SET
+                    {ARTIST_SORT} = COALESCE($1, {ARTIST_SORT}),
+                    {TITLE_SORT} = COALESCE($2, {TITLE_SORT})
+                WHERE {TRACK_ID} = $3"
+            ),
+            &[&artist, &title, &track_id],
+        )?;
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn record_card_scan(&mut self, scan: &CardScan) -> Result<(), StorageError> {
+        self.db.execute(
+            &format!(
+                "INSERT INTO {CARD_SCANS} ({SCANNED_AT}, {CARD_ID}, {TRACK_ID}, {OUTCOME}, {USER_AGENT})
+                VALUES ($1, $2, $3, $4, $5)"
+            ),
+            &[
+                &scan.scanned_at.timestamp(),
+                &scan.card_id,
+                &scan.track_id,
+                &scan.outcome.as_str(),
+                &scan.user_agent,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn list_card_scans(
+        &mut self,
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<CardScan>, StorageError> {
+        let since = since.map_or(i64::MIN, |since| since.timestamp());
+        self.db
+            .query(
+                &format!(
+                    "SELECT {SCANNED_AT}, {CARD_ID}, {TRACK_ID}, {OUTCOME}, {USER_AGENT} FROM {CARD_SCANS}
+                    WHERE {SCANNED_AT} >= $1 ORDER BY {SCANNED_AT} DESC"
+                ),
+                &[&since],
+            )?
+            .iter()
+            .map(|row| {
+                Ok(CardScan {
+                    scanned_at: i64_seconds_to_local_time(row.get(0))
+                        .map_err(StorageError::Internal)?,
+                    card_id: row.get(1),
+                    track_id: row.get(2),
+                    outcome: row
+                        .get::<_, String>(3)
+                        .parse()
+                        .map_err(StorageError::Internal)?,
+                    user_agent: row.get(4),
+                })
+            })
+            .collect()
+    }
+
+    fn add_file_to_track(
+        &mut self,
+        master_id: TrackId,
+        physical_path: &Path,
+    ) -> Result<(), StorageError> {
+        let location = self.fs.reverse_resolve(physical_path)?;
+        let file_size = std::fs::metadata(physical_path)?.len() as i64;
+        let modified = modified_secs(physical_path);
+        let hash = FileHash::from_file(physical_path)?;
+        let row = LocationRow::from_location(location)?;
+        let format = file_format(&row.path);
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+
+        let mut tx = self.db.transaction()?;
+        if !Self::track_exists(&mut tx, master_id)? {
+            return Err(StorageError::TrackNotFound(master_id.to_string()));
+        }
+        let inserted = tx.execute(
+            &format!(
+                "INSERT INTO {FILES} ({USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {LAST_SEEN}, {MODIFIED_AT}, {FORMAT})
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING"
+            ),
+            &[
+                &row.usb_label,
+                &row.path,
+                &master_id,
+                &file_size,
+                &hash.to_string(),
+                &now,
+                &modified,
+                &format,
+            ],
+        )?;
+        if inserted > 0 {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn merge_tracks(
+        &mut self,
+        master_id: TrackId,
+        slave_id: TrackId,
+        ignore_slave_meta: bool,
+    ) -> Result<(), StorageError> {
+        if master_id == slave_id {
+            return Ok(());
+        }
+        let mut tx = self.db.transaction()?;
+
+        let has_meta = tx
+            .query_opt(
+                &format!("SELECT 1 FROM {TRACK_METADATA} WHERE {TRACK_ID} = $1"),
+                &[&slave_id],
+            )?
+            .is_some();
+        if has_meta && !ignore_slave_meta {
+            return Err(StorageError::SlaveTrackHasMetadata(slave_id));
+        }
+
+        tx.execute(
+            &format!("UPDATE {FILES} SET {TRACK_ID} = $1 WHERE {TRACK_ID} = $2"),
+            &[&master_id, &slave_id],
+        )?;
+        tx.execute(
+            &format!("UPDATE {CARD_MAPPINGS} SET {TRACK_ID} = $1 WHERE {TRACK_ID} = $2"),
+            &[&master_id, &slave_id],
+        )?;
+        tx.execute(
+            &format!("UPDATE {PLAYLIST_TRACKS} SET {TRACK_ID} = $1 WHERE {TRACK_ID} = $2"),
+            &[&master_id, &slave_id],
+        )?;
+        tx.execute(
+            &format!(
+                "INSERT INTO {FAVOURITES} ({TRACK_ID}, {CREATED_AT})
+                SELECT $1, {CREATED_AT} FROM {FAVOURITES} WHERE {TRACK_ID} = $2
+                ON CONFLICT DO NOTHING"
+            ),
+            &[&master_id, &slave_id],
+        )?;
+        // the slave id stays valid as a card alias
+        tx.execute(
+            &format!(
+                "INSERT INTO {CARD_MAPPINGS} ({CARD_ID}, {TRACK_ID}) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+            ),
+            &[&slave_id.to_string(), &master_id],
+        )?;
+        // metadata of the slave is removed by ON DELETE CASCADE
+        tx.execute(
+            &format!("DELETE FROM {TRACKS} WHERE {TRACK_ID} = $1"),
+            &[&slave_id],
+        )?;
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn forget_path(&mut self, path: &Path) -> Result<ForgetReport, StorageError> {
+        let (path_prefix, dir_start, dir_end) = path_range(path);
+
+        let mut tx = self.db.transaction()?;
+        let affected_track_ids: Vec<TrackId> = tx
+            .query(
+                &format!(
+                    "SELECT DISTINCT {TRACK_ID} FROM {FILES} WHERE {}",
+                    under_path()
+                ),
+                &[&path_prefix, &dir_start, &dir_end],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let removed_files = tx.execute(
+            &format!("DELETE FROM {FILES} WHERE {}", under_path()),
+            &[&path_prefix, &dir_start, &dir_end],
+        )? as usize;
+
+        let mut removed_tracks = 0;
+        for track_id in &affected_track_ids {
+            let remaining: i64 = tx
+                .query_one(
+                    &format!("SELECT COUNT(*) FROM {FILES} WHERE {TRACK_ID} = $1"),
+                    &[track_id],
+                )?
+                .get(0);
+            if remaining == 0 {
+                removed_tracks += 1;
+            }
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+
+        Ok(ForgetReport {
+            removed_tracks,
+            affected_tracks: affected_track_ids.len(),
+            removed_files,
+        })
+    }
+
+    fn forget_matching(&mut self, glob: &PathGlob) -> Result<ForgetReport, StorageError> {
+        let prefix = glob.literal_prefix();
+        let mut tx = self.db.transaction()?;
+        let rows = if prefix.is_empty() {
+            tx.query(
+                &format!("SELECT DISTINCT {PATH}, {TRACK_ID} FROM {FILES}"),
+                &[],
+            )?
+        } else {
+            let (path, start, end) = path_range(Path::new(&prefix));
+            tx.query(
+                &format!(
+                    "SELECT DISTINCT {PATH}, {TRACK_ID} FROM {FILES} WHERE {}",
+                    under_path()
+                ),
+                &[&path, &start, &end],
+            )?
+        };
+        let matched: Vec<(String, TrackId)> = rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .filter(|(path, _): &(String, TrackId)| glob.matches(path))
+            .collect();
+        let affected_track_ids: BTreeSet<TrackId> =
+            matched.iter().map(|(_, track_id)| *track_id).collect();
+
+        let mut removed_files = 0;
+        // a path can be stored for several USB drives
+        let paths: BTreeSet<&String> = matched.iter().map(|(path, _)| path).collect();
+        for path in paths {
+            removed_files +=
+                tx.execute(&format!("DELETE FROM {FILES} WHERE {PATH} = $1"), &[path])? as usize;
+        }
+
+        let mut removed_tracks = 0;
+        for track_id in &affected_track_ids {
+            let remaining: i64 = tx
+                .query_one(
+                    &format!("SELECT COUNT(*) FROM {FILES} WHERE {TRACK_ID} = $1"),
+                    &[track_id],
+                )?
+                .get(0);
+            if remaining == 0 {
+                removed_tracks += 1;
+            }
+        }
+        Self::insert_update_time(&mut tx)?;
+        tx.commit()?;
+
+        Ok(ForgetReport {
+            removed_tracks,
+            affected_tracks: affected_track_ids.len(),
+            removed_files,
+        })
+    }
+
+    fn quarantine(&mut self, loc: &Location, reason: &str) -> Result<bool, StorageError> {
+        let row = LocationRow::from_location(loc.clone())?;
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let mut tx = self.db.transaction()?;
+        let changed = tx.execute(
+            &format!(
+                "UPDATE {FILES} SET {QUARANTINED_AT} = $1, {QUARANTINE_REASON} = $2
+                WHERE {USB_LABEL} = $3 AND {PATH} = $4 AND {QUARANTINED_AT} IS NULL"
+            ),
+            &[&now, &reason, &row.usb_label, &row.path],
+        )?;
+        if changed > 0 {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(changed > 0)
+    }
+
+    fn list_quarantined(&mut self) -> Result<Vec<QuarantinedFile>, StorageError> {
+        let rows = self.db.query(
+            &format!(
+                "SELECT {TRACK_ID}, {USB_LABEL}, {PATH}, {QUARANTINE_REASON}, {QUARANTINED_AT}
+                FROM {FILES} WHERE {QUARANTINED_AT} IS NOT NULL
+                ORDER BY {QUARANTINED_AT} DESC, {USB_LABEL}, {PATH}"
+            ),
+            &[],
+        )?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(QuarantinedFile {
+                    track: row.get(0),
+                    loc: LocationRow {
+                        usb_label: row.get(1),
+                        path: row.get(2),
+                    }
+                    .into(),
+                    reason: row.get::<_, Option<String>>(3).unwrap_or_default(),
+                    since: i64_seconds_to_local_time(row.get(4)).map_err(StorageError::Internal)?,
+                })
+            })
+            .collect()
+    }
+
+    fn clear_quarantine(&mut self, path: Option<&Path>) -> Result<usize, StorageError> {
+        let mut tx = self.db.transaction()?;
+        let release = format!(
+            "UPDATE {FILES} SET {QUARANTINED_AT} = NULL, {QUARANTINE_REASON} = NULL
+            WHERE {QUARANTINED_AT} IS NOT NULL"
+        );
+        let released = match path {
+            Some(path) => {
+                let (path, start, end) = path_range(path);
+                tx.execute(
+                    &format!("{release} AND ({})", under_path()),
+                    &[&path, &start, &end],
+                )?
+            }
+            None => tx.execute(&release, &[])?,
+        } as usize;
+        if released > 0 {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(released)
+    }
+
+    fn clean_dangling(&mut self) -> Result<CleanDanglingReport, StorageError> {
+        let mut tx = self.db.transaction()?;
+        let removed_tracks = tx.execute(
+            &format!(
+                "DELETE FROM {TRACKS} t
+                WHERE NOT EXISTS (SELECT 1 FROM {FILES} f WHERE f.{TRACK_ID} = t.{TRACK_ID})
+                  AND NOT EXISTS (SELECT 1 FROM {TRACK_METADATA} m WHERE m.{TRACK_ID} = t.{TRACK_ID})"
+            ),
+            &[],
+        )? as usize;
+        if removed_tracks > 0 {
+            Self::insert_update_time(&mut tx)?;
+        }
+        tx.commit()?;
+        Ok(CleanDanglingReport { removed_tracks })
+    }
+
+    fn export_bundle(&mut self, _dest: &Path) -> Result<BundleManifest, StorageError> {
+        Err(StorageError::Internal(anyhow!(
+            "bundles and backups need the SQLite backend, back up PostgreSQL with pg_dump"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{artists::ArtistRole, location::Location};
+
+    /// Tests share one database, they must not drop tables while another one runs
+    static DATABASE: Mutex<()> = Mutex::new(());
+
+    /// Runs against the database in `LOCALDECK_TEST_POSTGRES_URL`, skipped when it is not set.
+    /// The tables of that database are dropped. Keep the guard until the test ends
+    fn setup_storage(root: &Path) -> Option<(PgStorage, MutexGuard<'static, ()>)> {
+        let url = std::env::var("LOCALDECK_TEST_POSTGRES_URL").ok()?;
+        let guard = DATABASE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut db = Client::connect(&url, NoTls).unwrap();
+        db.batch_execute(&format!(
+            "DROP TABLE IF EXISTS {TRACK_ARTISTS}, {ARTISTS}, {TRACK_NOTES}, {YOUTUBE_IDS}, {SHORT_LINKS}, {METADATA_SOURCES}, {CARD_SCANS}, {FAVOURITES}, {PLAYLIST_TRACKS}, {PLAYLISTS}, {TRACK_LYRICS}, {FILES}, {CARD_MAPPINGS}, {TRACK_METADATA}, {UPDATES}, {TRACKS}"
+        ))
+        .unwrap();
+        let source = LibrarySource {
+            roots: vec![
+                Location::File {
+                    path: root.to_path_buf(),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        };
+        Some((PgStorage::new(&url, source).unwrap(), guard))
+    }
+
+    #[test]
+    fn test_postgres_update_and_serve() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("a.mp3"), b"audio_a")?;
+        std::fs::write(dir.path().join("copy_of_a.mp3"), b"audio_a")?;
+        std::fs::write(dir.path().join("b.mp3"), b"audio_b")?;
+        let Some((mut storage, _guard)) = setup_storage(dir.path()) else {
+            return Ok(());
+        };
+
+        let inserted = storage.update_db_with_new_files()?;
+        assert_eq!(inserted.len(), 2);
+        assert!(storage.update_db_with_new_files()?.is_empty());
+
+        let tracks = storage.list_tracks()?;
+        let track = tracks.iter().find(|t| t.files.len() == 2).unwrap().id;
+        storage.update_track_metadata(
+            track,
+            MetadataUpdate {
+                artist: Some("Artist".to_string()),
+                title: Some("Title".to_string()),
+                year: Some(1999),
+                label: None,
+                artwork: None,
+            },
+            false,
+        )?;
+        let resolved = storage.resolve_track(track.to_string())?;
+        let (_, _, meta) = storage.find_track_file_with_meta(resolved)?;
+        assert_eq!(meta.unwrap().year, Some(1999));
+        assert_eq!(storage.find_files("artist", false)?.len(), 1);
+        storage.set_artist_credits(
+            track,
+            &[
+                ArtistCredit::new("Artist", ArtistRole::Main),
+                ArtistCredit::new("Guest", ArtistRole::Featuring),
+            ],
+        )?;
+        assert_eq!(
+            storage.get_track_metadata(track)?.unwrap().artist,
+            "Artist feat. Guest"
+        );
+        assert_eq!(storage.artist_credits(track)?.len(), 2);
+        let guest = TrackFilter {
+            artist: Some("GUEST".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.list_tracks_matching(&guest)?.len(), 1);
+        assert_eq!(storage.list_artists()?.len(), 2);
+
+        let report = storage.forget_path(&dir.path().join("b.mp3"))?;
+        assert_eq!(report.removed_tracks, 1);
+        assert_eq!(storage.clean_dangling()?.removed_tracks, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_postgres_playlists() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("a.mp3"), b"playlist_a")?;
+        std::fs::write(dir.path().join("b.mp3"), b"playlist_b")?;
+        let Some((mut storage, _guard)) = setup_storage(dir.path()) else {
+            return Ok(());
+        };
+        let tracks: Vec<TrackId> = storage.update_db_with_new_files()?.into_keys().collect();
+
+        let name = format!("playlist of {}", dir.path().display());
+        let playlist = storage.create_playlist(&name)?;
+        assert!(matches!(
+            storage.create_playlist(&name),
+            Err(StorageError::PlaylistExists(_))
+        ));
+        storage.set_playlist_tracks(playlist.id, &[tracks[1], tracks[0]])?;
+        storage.set_playlist_token(playlist.id, Some("secret"))?;
+
+        let stored = storage.get_playlist(playlist.id)?;
+        assert_eq!(stored.tracks, vec![tracks[1], tracks[0]]);
+        assert_eq!(stored.share_token.as_deref(), Some("secret"));
+
+        storage.delete_playlist(playlist.id)?;
+        assert!(storage.get_playlist(playlist.id).is_err());
+        Ok(())
+    }
+}
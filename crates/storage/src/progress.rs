@@ -0,0 +1,29 @@
+//! Hooks to observe long running library operations, like scanning and hashing
+
+use std::fmt::Debug;
+
+use crate::location::Location;
+
+/// Receives progress of scans and updates. All methods do nothing by default
+pub trait Progress: Debug + Send {
+    /// a music file was found while walking the library roots
+    fn file_found(&mut self, _loc: &Location) {}
+
+    /// walking the library roots is done
+    fn scan_finished(&mut self, _files: usize) {}
+
+    /// hashing of `files` new files with `bytes` in total is about to start
+    fn hashing_started(&mut self, _files: usize, _bytes: u64) {}
+
+    /// one more file was hashed
+    fn file_hashed(&mut self, _loc: &Location, _bytes: u64) {}
+
+    /// hashing is done or was interrupted by an error
+    fn hashing_finished(&mut self) {}
+}
+
+/// Ignores all progress events
+#[derive(Debug, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
@@ -0,0 +1,583 @@
+//! Where each metadata field of a track came from, and how file tags are merged with
+//! values stored before.
+//!
+//! Fields read from tags follow the tags when a track is scanned again, they only cache the
+//! file. Fields edited by hand are kept or replaced as the [`MetadataPolicy`] says, so
+//! a re-scan does not silently undo an edit. Fields set before sources were recorded count
+//! as edited by hand, at an unknown time.
+
+use std::{fmt, path::PathBuf, str::FromStr, time::SystemTime};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, params};
+use serde::Deserialize;
+
+use crate::{
+    Storage,
+    audit::{self, AuditOperation},
+    db::{i64_seconds_to_local_time, system_time_to_i64},
+    error::StorageError,
+    location::Location,
+    schema::{columns::*, tables::*},
+    tags::{self, FileTags},
+    track::{TrackId, TrackMetadata},
+};
+
+/// What happens to a value edited by hand when the file's tags say otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataPolicy {
+    /// keep the database value, tags only fill in missing fields
+    #[default]
+    DbWins,
+    /// replace it with the tag
+    TagsWin,
+    /// replace it if the file was modified after the edit
+    NewestWins,
+    /// keep it and report a [`TagConflict`], to ask the user
+    Ask,
+}
+
+impl FromStr for MetadataPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "db-wins" => Ok(MetadataPolicy::DbWins),
+            "tags-win" => Ok(MetadataPolicy::TagsWin),
+            "newest-wins" => Ok(MetadataPolicy::NewestWins),
+            "ask" => Ok(MetadataPolicy::Ask),
+            other => Err(format!("Unknown metadata policy: {other}")),
+        }
+    }
+}
+
+/// Metadata field that tags can set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetaField {
+    Title,
+    Artist,
+    Year,
+    Label,
+}
+
+impl MetaField {
+    pub const ALL: [MetaField; 4] = [
+        MetaField::Title,
+        MetaField::Artist,
+        MetaField::Year,
+        MetaField::Label,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetaField::Title => "title",
+            MetaField::Artist => "artist",
+            MetaField::Year => "year",
+            MetaField::Label => "label",
+        }
+    }
+
+    pub fn stored(&self, meta: &TrackMetadata) -> Option<String> {
+        match self {
+            MetaField::Title => Some(meta.title.clone()),
+            MetaField::Artist => Some(meta.artist.clone()),
+            MetaField::Year => meta.year.map(|year| year.to_string()),
+            MetaField::Label => meta.label.clone(),
+        }
+    }
+
+    pub fn tagged(&self, tags: &FileTags) -> Option<String> {
+        match self {
+            MetaField::Title => tags.title.clone(),
+            MetaField::Artist => tags.artist.clone(),
+            MetaField::Year => tags.year.map(|year| year.to_string()),
+            MetaField::Label => tags.label.clone(),
+        }
+    }
+
+    /// Copies the field from the tags, if they have it
+    fn take(&self, meta: &mut TrackMetadata, tags: &FileTags) {
+        match self {
+            MetaField::Title => meta.title = tags.title.clone().unwrap_or_default(),
+            MetaField::Artist => meta.artist = tags.artist.clone().unwrap_or_default(),
+            MetaField::Year => meta.year = tags.year,
+            MetaField::Label => meta.label = tags.label.clone(),
+        }
+    }
+}
+
+impl fmt::Display for MetaField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MetaField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MetaField::ALL
+            .into_iter()
+            .find(|field| field.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown metadata field '{s}'"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataOrigin {
+    /// read from the tags of a file
+    Tags,
+    /// set with `meta add`, `meta apply` or another edit
+    Manual,
+}
+
+impl MetadataOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataOrigin::Tags => "tags",
+            MetadataOrigin::Manual => "manual",
+        }
+    }
+}
+
+impl FromStr for MetadataOrigin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tags" => Ok(MetadataOrigin::Tags),
+            "manual" => Ok(MetadataOrigin::Manual),
+            _ => Err(anyhow!("unknown metadata origin '{s}'")),
+        }
+    }
+}
+
+/// Recorded source of one field of a track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSource {
+    pub field: MetaField,
+    pub origin: MetadataOrigin,
+    pub changed_at: DateTime<Local>,
+}
+
+/// A value edited by hand which the tags of the track's file contradict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagConflict {
+    pub track_id: TrackId,
+    pub field: MetaField,
+    pub stored: String,
+    pub tagged: String,
+}
+
+/// A field the database and the file's tags disagree on, `None` where one side has no value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: MetaField,
+    pub stored: Option<String>,
+    pub tagged: Option<String>,
+}
+
+/// Fields that differ between the stored metadata and the tags of a file
+pub fn diff_tags(meta: Option<&TrackMetadata>, tags: &FileTags) -> Vec<FieldDiff> {
+    MetaField::ALL
+        .into_iter()
+        .map(|field| FieldDiff {
+            field,
+            stored: meta.and_then(|meta| field.stored(meta)),
+            tagged: field.tagged(tags),
+        })
+        .filter(|diff| diff.stored != diff.tagged)
+        .collect()
+}
+
+/// Result of [`Storage::import_tags`](crate::Storage::import_tags)
+#[derive(Debug, Default)]
+pub struct TagImportReport {
+    /// tracks with fields taken from tags
+    pub updated: Vec<TrackId>,
+    /// edited values kept for now, see [`MetadataPolicy::Ask`]
+    pub conflicts: Vec<TagConflict>,
+    /// tracks without a local file with tags, or without metadata and
+    /// without a title and artist in their tags
+    pub skipped: Vec<TrackId>,
+}
+
+/// Tags of a track's file, read before the database is locked for the update
+pub(crate) struct TaggedTrack {
+    pub track_id: TrackId,
+    pub tags: FileTags,
+    pub modified: Option<DateTime<Local>>,
+}
+
+impl Storage {
+    /// Reads the tags of the tracks' files and merges them into their metadata, e.g. for the
+    /// tracks an `update` added. Values edited by hand are treated as `policy` says
+    pub fn import_tags(
+        &mut self,
+        tracks: &[TrackId],
+        policy: MetadataPolicy,
+    ) -> Result<TagImportReport, StorageError> {
+        let mut report = TagImportReport::default();
+        let tagged = read_track_tags(
+            tracks,
+            |track| {
+                self.find_track_file_with_meta(track)
+                    .map(|(path, location, _)| (path, location))
+            },
+            &mut report.skipped,
+        );
+
+        let tx = self.db.transaction()?;
+        for track in &tagged {
+            let current = Self::load_metadata(&tx, track.track_id)?;
+            let sources = load_sources(&tx, track.track_id)?;
+            let had_metadata = current.is_some();
+            let merge = merge_tags(track, current, &sources, policy);
+            report.conflicts.extend(merge.conflicts);
+            let Some(meta) = merge.meta else {
+                if !had_metadata {
+                    report.skipped.push(track.track_id);
+                }
+                continue;
+            };
+            let details = format!("from tags: {}", audit::describe_metadata(&meta));
+            Self::store_metadata(&tx, track.track_id, meta)?;
+            record_sources(&tx, track.track_id, &merge.from_tags, MetadataOrigin::Tags)?;
+            audit::record(&tx, AuditOperation::Metadata, &details, [track.track_id])?;
+            report.updated.push(track.track_id);
+        }
+        if !report.updated.is_empty() {
+            Self::insert_update_time(&tx)?;
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+
+    /// Recorded sources of the track's fields, fields set before sources were kept are missing
+    pub fn metadata_sources(
+        &mut self,
+        track_id: TrackId,
+    ) -> Result<Vec<FieldSource>, StorageError> {
+        load_sources(&self.db, track_id)
+    }
+}
+
+pub(crate) fn load_sources(
+    db: &Connection,
+    track_id: TrackId,
+) -> Result<Vec<FieldSource>, StorageError> {
+    let mut stmt = db.prepare_cached(&format!(
+        "SELECT {FIELD}, {ORIGIN}, {CHANGED_AT} FROM {METADATA_SOURCES} WHERE {TRACK_ID} = ?1"
+    ))?;
+    let rows = stmt
+        .query_map(params![track_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|(field, origin, changed_at)| {
+            Ok(FieldSource {
+                field: field.parse().map_err(StorageError::Internal)?,
+                origin: origin.parse().map_err(StorageError::Internal)?,
+                changed_at: i64_seconds_to_local_time(changed_at)
+                    .map_err(StorageError::Internal)?,
+            })
+        })
+        .collect()
+}
+
+/// Marks the fields as set from `origin` now
+pub(crate) fn record_sources(
+    db: &Connection,
+    track_id: TrackId,
+    fields: &[MetaField],
+    origin: MetadataOrigin,
+) -> Result<(), StorageError> {
+    let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+    let mut stmt = db.prepare_cached(&format!(
+        "INSERT INTO {METADATA_SOURCES} ({TRACK_ID}, {FIELD}, {ORIGIN}, {CHANGED_AT})
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT({TRACK_ID}, {FIELD}) DO UPDATE SET
+            {ORIGIN} = excluded.{ORIGIN},
+            {CHANGED_AT} = excluded.{CHANGED_AT}"
+    ))?;
+    for field in fields {
+        stmt.execute(params![track_id, field.as_str(), origin.as_str(), now])?;
+    }
+    Ok(())
+}
+
+/// Reads the tags of the best file of every track, tracks without readable tags are added to `skipped`
+pub(crate) fn read_track_tags(
+    tracks: &[TrackId],
+    mut find_file: impl FnMut(TrackId) -> Result<(PathBuf, Location), StorageError>,
+    skipped: &mut Vec<TrackId>,
+) -> Vec<TaggedTrack> {
+    let mut tagged = Vec::new();
+    for &track_id in tracks {
+        let file = match find_file(track_id) {
+            Ok((path, location)) if location.is_local() => path,
+            Ok(_) => {
+                skipped.push(track_id);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("no file of track {track_id} to read tags from: {e}");
+                skipped.push(track_id);
+                continue;
+            }
+        };
+        match tags::read_tags(&file) {
+            Ok(tags) if !tags.is_empty() => tagged.push(TaggedTrack {
+                track_id,
+                tags,
+                modified: std::fs::metadata(&file)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+                    .map(DateTime::from),
+            }),
+            Ok(_) => skipped.push(track_id),
+            Err(e) => {
+                log::warn!("could not read tags of {}: {e}", file.display());
+                skipped.push(track_id);
+            }
+        }
+    }
+    tagged
+}
+
+/// Outcome of merging the tags of one track
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct TagMerge {
+    /// metadata to store, `None` if nothing changes
+    pub meta: Option<TrackMetadata>,
+    /// fields now set from tags
+    pub from_tags: Vec<MetaField>,
+    pub conflicts: Vec<TagConflict>,
+}
+
+pub(crate) fn merge_tags(
+    track: &TaggedTrack,
+    current: Option<TrackMetadata>,
+    sources: &[FieldSource],
+    policy: MetadataPolicy,
+) -> TagMerge {
+    let tags = &track.tags;
+    let Some(current) = current else {
+        // a new row needs the required fields
+        let (Some(title), Some(artist)) = (tags.title.clone(), tags.artist.clone()) else {
+            return TagMerge::default();
+        };
+        return TagMerge {
+            meta: Some(TrackMetadata {
+                title,
+                artist,
+                year: tags.year,
+                label: tags.label.clone(),
+                artwork: None,
+            }),
+            from_tags: MetaField::ALL
+                .into_iter()
+                .filter(|field| field.tagged(tags).is_some())
+                .collect(),
+            conflicts: vec![],
+        };
+    };
+
+    let mut merged = current.clone();
+    let mut merge = TagMerge::default();
+    for field in MetaField::ALL {
+        let Some(tagged) = field.tagged(tags) else {
+            continue;
+        };
+        let stored = field.stored(&current);
+        if stored.as_ref() == Some(&tagged) {
+            continue;
+        }
+        let source = sources.iter().find(|source| source.field == field);
+        let take = match (&stored, source) {
+            (None, _) => true,
+            (_, Some(source)) if source.origin == MetadataOrigin::Tags => true,
+            _ => match policy {
+                MetadataPolicy::DbWins => false,
+                MetadataPolicy::TagsWin => true,
+                // an edit of unknown time is kept
+                MetadataPolicy::NewestWins => {
+                    matches!((track.modified, source), (Some(modified), Some(source)) if modified > source.changed_at)
+                }
+                MetadataPolicy::Ask => {
+                    merge.conflicts.push(TagConflict {
+                        track_id: track.track_id,
+                        field,
+                        stored: stored.clone().unwrap_or_default(),
+                        tagged: tagged.clone(),
+                    });
+                    false
+                }
+            },
+        };
+        if take {
+            field.take(&mut merged, tags);
+            merge.from_tags.push(field);
+        }
+    }
+    if merged != current {
+        merge.meta = Some(merged);
+    }
+    merge
+}
+
+/// Fields with a different value in `new`, all of them if there was no metadata
+pub(crate) fn changed_fields(old: Option<&TrackMetadata>, new: &TrackMetadata) -> Vec<MetaField> {
+    MetaField::ALL
+        .into_iter()
+        .filter(|field| old.is_none_or(|old| field.stored(old) != field.stored(new)))
+        .filter(|field| field.stored(new).is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 10, day, 12, 0, 0).unwrap()
+    }
+
+    fn stored() -> TrackMetadata {
+        TrackMetadata {
+            title: "Title".into(),
+            artist: "Edited Artist".into(),
+            year: None,
+            label: Some("Label".into()),
+            artwork: None,
+        }
+    }
+
+    fn tagged(modified: u32) -> TaggedTrack {
+        TaggedTrack {
+            track_id: 1,
+            tags: FileTags {
+                title: Some("Tagged Title".into()),
+                artist: Some("Artist".into()),
+                year: Some(1999),
+                label: Some("Label".into()),
+            },
+            modified: Some(at(modified)),
+        }
+    }
+
+    fn source(field: MetaField, origin: MetadataOrigin, day: u32) -> FieldSource {
+        FieldSource {
+            field,
+            origin,
+            changed_at: at(day),
+        }
+    }
+
+    #[test]
+    fn tags_fill_in_and_refresh_but_keep_edits() {
+        let sources = [
+            source(MetaField::Title, MetadataOrigin::Tags, 1),
+            source(MetaField::Artist, MetadataOrigin::Manual, 10),
+        ];
+        let merge = merge_tags(&tagged(5), Some(stored()), &sources, MetadataPolicy::DbWins);
+        let meta = merge.meta.unwrap();
+        assert_eq!(meta.title, "Tagged Title");
+        assert_eq!(meta.artist, "Edited Artist");
+        assert_eq!(meta.year, Some(1999));
+        assert_eq!(merge.from_tags, vec![MetaField::Title, MetaField::Year]);
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn policies_decide_about_edits() {
+        let sources = [source(MetaField::Artist, MetadataOrigin::Manual, 10)];
+        let artist = |track: &TaggedTrack, policy| {
+            merge_tags(track, Some(stored()), &sources, policy)
+                .meta
+                .unwrap()
+                .artist
+        };
+        assert_eq!(artist(&tagged(5), MetadataPolicy::TagsWin), "Artist");
+        assert_eq!(
+            artist(&tagged(5), MetadataPolicy::NewestWins),
+            "Edited Artist"
+        );
+        assert_eq!(artist(&tagged(15), MetadataPolicy::NewestWins), "Artist");
+
+        let merge = merge_tags(&tagged(15), Some(stored()), &sources, MetadataPolicy::Ask);
+        assert_eq!(merge.meta.unwrap().artist, "Edited Artist");
+        // the title has no recorded source, it was set before sources were kept
+        assert_eq!(
+            merge.conflicts,
+            vec![
+                TagConflict {
+                    track_id: 1,
+                    field: MetaField::Title,
+                    stored: "Title".into(),
+                    tagged: "Tagged Title".into(),
+                },
+                TagConflict {
+                    track_id: 1,
+                    field: MetaField::Artist,
+                    stored: "Edited Artist".into(),
+                    tagged: "Artist".into(),
+                },
+            ]
+        );
+
+        // edits of unknown time are newer than any file
+        let merge = merge_tags(&tagged(15), Some(stored()), &[], MetadataPolicy::NewestWins);
+        assert_eq!(merge.meta.unwrap().title, "Title");
+    }
+
+    #[test]
+    fn differing_fields_are_listed() {
+        let tags = tagged(1).tags;
+        assert_eq!(
+            diff_tags(Some(&stored()), &tags),
+            vec![
+                FieldDiff {
+                    field: MetaField::Title,
+                    stored: Some("Title".into()),
+                    tagged: Some("Tagged Title".into()),
+                },
+                FieldDiff {
+                    field: MetaField::Artist,
+                    stored: Some("Edited Artist".into()),
+                    tagged: Some("Artist".into()),
+                },
+                FieldDiff {
+                    field: MetaField::Year,
+                    stored: None,
+                    tagged: Some("1999".into()),
+                },
+            ]
+        );
+        assert_eq!(diff_tags(None, &tags).len(), 4);
+        assert!(diff_tags(None, &FileTags::default()).is_empty());
+    }
+
+    #[test]
+    fn new_metadata_needs_title_and_artist() {
+        let merge = merge_tags(&tagged(1), None, &[], MetadataPolicy::DbWins);
+        assert_eq!(merge.from_tags, MetaField::ALL.to_vec());
+        assert_eq!(merge.meta.unwrap().title, "Tagged Title");
+
+        let mut untitled = tagged(1);
+        untitled.tags.title = None;
+        assert_eq!(
+            merge_tags(&untitled, None, &[], MetadataPolicy::DbWins),
+            TagMerge::default()
+        );
+    }
+}
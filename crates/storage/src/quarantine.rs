@@ -0,0 +1,111 @@
+//! Files held back from playback because their content changed or they could not be read.
+//!
+//! A quarantined file keeps its row, so `update` does not record it again, but it is never
+//! picked to play or stream its track. `verify` and the stream handler quarantine files,
+//! `quarantine clear` releases them once they are fixed.
+
+use std::{path::Path, time::SystemTime};
+
+use chrono::{DateTime, Local};
+use rusqlite::params;
+
+use crate::{
+    Storage,
+    db::{i64_seconds_to_local_time, system_time_to_i64},
+    error::StorageError,
+    location::Location,
+    operations::{LocationRow, path_range},
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+#[derive(Debug, Clone)]
+pub struct QuarantinedFile {
+    pub track: TrackId,
+    pub loc: Location,
+    /// why the file was held back, e.g. the error of opening it
+    pub reason: String,
+    pub since: DateTime<Local>,
+}
+
+impl Storage {
+    /// Holds back the recorded file at `loc`, a file quarantined already keeps its reason.
+    ///
+    /// Returns whether a file was quarantined
+    pub fn quarantine(&mut self, loc: &Location, reason: &str) -> Result<bool, StorageError> {
+        let row = LocationRow::from_location(loc.clone())?;
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        let changed = tx.execute(
+            &format!(
+                "UPDATE {FILES} SET {QUARANTINED_AT} = ?1, {QUARANTINE_REASON} = ?2
+                WHERE {USB_LABEL} = ?3 AND {PATH} = ?4 AND {QUARANTINED_AT} IS NULL"
+            ),
+            params![now, reason, row.usb_label, row.path],
+        )?;
+        if changed > 0 {
+            Self::insert_update_time(&tx)?;
+        }
+        tx.commit()?;
+        Ok(changed > 0)
+    }
+
+    /// Quarantined files, the most recently held back first
+    pub fn list_quarantined(&mut self) -> Result<Vec<QuarantinedFile>, StorageError> {
+        let rows = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {TRACK_ID}, {USB_LABEL}, {PATH}, {QUARANTINE_REASON}, {QUARANTINED_AT}
+                FROM {FILES} WHERE {QUARANTINED_AT} IS NOT NULL
+                ORDER BY {QUARANTINED_AT} DESC, {USB_LABEL}, {PATH}"
+            ))?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, TrackId>(0)?,
+                    LocationRow {
+                        usb_label: row.get(1)?,
+                        path: row.get(2)?,
+                    },
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        rows.into_iter()
+            .map(|(track, row, reason, since)| {
+                Ok(QuarantinedFile {
+                    track,
+                    loc: row.into(),
+                    reason: reason.unwrap_or_default(),
+                    since: i64_seconds_to_local_time(since).map_err(StorageError::Internal)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Releases quarantined files at or under `path`, or all of them.
+    ///
+    /// Returns the number of released files
+    pub fn clear_quarantine(&mut self, path: Option<&Path>) -> Result<usize, StorageError> {
+        let tx = self.db.transaction()?;
+        let release = format!(
+            "UPDATE {FILES} SET {QUARANTINED_AT} = NULL, {QUARANTINE_REASON} = NULL
+            WHERE {QUARANTINED_AT} IS NOT NULL"
+        );
+        let released = match path {
+            Some(path) => {
+                let (path, start, end) = path_range(path);
+                tx.execute(
+                    &format!("{release} AND ({PATH} = ?1 OR ({PATH} >= ?2 AND {PATH} < ?3))"),
+                    params![path, start, end],
+                )?
+            }
+            None => tx.execute(&release, [])?,
+        };
+        if released > 0 {
+            Self::insert_update_time(&tx)?;
+        }
+        tx.commit()?;
+        Ok(released)
+    }
+}
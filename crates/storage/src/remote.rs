@@ -0,0 +1,158 @@
+//! Libraries of other localdeck instances, as described by their `/sync` endpoint.
+//!
+//! `localdeck sync` pulls from such a description, and library roots pointing to another
+//! instance ([`Location::Remote`]) list their tracks from it. Tracks of a remote root are
+//! recorded with the content hashes the other instance reported, nothing is downloaded.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+
+use crate::{
+    error::StorageError,
+    file_hash::{FileHash, HashKind},
+    fs::{FileWithMeta, HashedFile},
+    location::Location,
+    track::{TrackId, TrackMetadata},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Roots are checked before every scan, an unreachable instance should not hold it up long
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncLibrary {
+    /// tracks having files, ordered by id
+    pub tracks: Vec<SyncTrack>,
+    pub playlists: Vec<SyncPlaylist>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncTrack {
+    /// id on the serving machine, e.g. for `/tracks/<id>/stream`
+    pub track_id: TrackId,
+    pub files: Vec<SyncFile>,
+    pub metadata: Option<TrackMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncFile {
+    /// hex encoded
+    pub hash: String,
+    pub kind: HashKind,
+    pub file_size: i64,
+    /// file name without directories, e.g. `song.flac`
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncPlaylist {
+    pub name: String,
+    /// ids on the serving machine, in playing order
+    pub tracks: Vec<TrackId>,
+}
+
+/// Client for the instance of a remote root
+pub(crate) struct RemoteRoot {
+    agent: Agent,
+    root: Location,
+    url: String,
+    token: Option<String>,
+}
+
+impl RemoteRoot {
+    /// `None` for local roots
+    pub(crate) fn new(root: &Location) -> Option<Self> {
+        let Location::Remote { token, .. } = root else {
+            return None;
+        };
+        Some(Self {
+            agent: Agent::config_builder()
+                .timeout_connect(Some(CONNECT_TIMEOUT))
+                .build()
+                .into(),
+            root: root.clone(),
+            url: root.url()?,
+            token: token.clone(),
+        })
+    }
+
+    fn get(&self, endpoint: &str) -> ureq::RequestBuilder<ureq::typestate::WithoutBody> {
+        let request = self.agent.get(format!("{}/{endpoint}", self.url));
+        match &self.token {
+            Some(token) => request.header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    /// Reason why the instance can't be used right now, `None` if it answers
+    pub(crate) fn unavailable_reason(&self) -> Option<String> {
+        self.get("healthz")
+            .config()
+            .timeout_global(Some(HEALTH_TIMEOUT))
+            .build()
+            .call()
+            .err()
+            .map(|e| format!("{} is not reachable: {e}", self.url))
+    }
+
+    pub(crate) fn library(&self) -> Result<SyncLibrary, StorageError> {
+        self.get("sync")
+            .call()
+            .and_then(|mut response| {
+                response
+                    .body_mut()
+                    .with_config()
+                    .limit(u64::MAX)
+                    .read_json()
+            })
+            .map_err(|e| {
+                StorageError::Internal(anyhow!("failed to list tracks of {}: {e}", self.url))
+            })
+    }
+
+    /// One file per remote track, the stream of the track.
+    ///
+    /// The hash of the remote file hashed like local files is used if there is one,
+    /// so the track matches local copies of it
+    pub(crate) fn files(&self, kind: HashKind) -> Result<Vec<HashedFile>, StorageError> {
+        let Location::Remote { base_url, path, .. } = &self.root else {
+            return Ok(vec![]);
+        };
+        let mut files = vec![];
+        for track in self.library()?.tracks {
+            let Some(file) = track
+                .files
+                .iter()
+                .find(|f| f.kind == kind)
+                .or(track.files.first())
+            else {
+                continue;
+            };
+            let hash = FileHash::from_hex(&file.hash).map_err(|e| {
+                StorageError::Internal(anyhow!("{} sent an invalid file hash: {e}", self.url))
+            })?;
+            files.push(HashedFile::with_kind(
+                hash,
+                file.kind,
+                FileWithMeta {
+                    // recorded files carry no token, see `Location::Remote`
+                    loc: Location::Remote {
+                        base_url: base_url.clone(),
+                        token: None,
+                        path: path.join(stream_path(track.track_id)),
+                    },
+                    file_size: file.file_size,
+                },
+            ));
+        }
+        Ok(files)
+    }
+}
+
+/// Stream of a track relative to the instance serving it
+pub(crate) fn stream_path(track_id: TrackId) -> PathBuf {
+    PathBuf::from(format!("tracks/{track_id}/stream"))
+}
@@ -1,13 +1,57 @@
 use rusqlite::Connection;
 
+use crate::{artists, sort_names};
+
 pub mod tables {
     pub const FILES: &str = "files";
     pub const UPDATES: &str = "updates";
     pub const TRACK_METADATA: &str = "track_metadata";
     pub const TRACKS: &str = "tracks";
     pub const CARD_MAPPINGS: &str = "card_mappings";
+    pub const SCANS: &str = "scans";
+    pub const SCAN_ROOTS: &str = "scan_roots";
+    pub const AUDIT_LOG: &str = "audit_log";
+    pub const AUDIT_TRACKS: &str = "audit_tracks";
+    pub const AUDIT_FILES: &str = "audit_files";
+    pub const SNAPSHOTS: &str = "snapshots";
+    pub const SNAPSHOT_FILES: &str = "snapshot_files";
+    pub const TRACK_LYRICS: &str = "track_lyrics";
+    pub const PLAYLISTS: &str = "playlists";
+    pub const PLAYLIST_TRACKS: &str = "playlist_tracks";
+    pub const CARD_SCANS: &str = "card_scans";
+    pub const FAVOURITES: &str = "favourites";
+    pub const METADATA_SOURCES: &str = "metadata_sources";
+    pub const SHORT_LINKS: &str = "short_links";
+    pub const YOUTUBE_IDS: &str = "youtube_ids";
+    pub const TRACK_NOTES: &str = "track_notes";
+    pub const ARTISTS: &str = "artists";
+    pub const TRACK_ARTISTS: &str = "track_artists";
 
-    pub const ALL_TABLES: &[&str] = &[TRACKS, FILES, UPDATES, TRACK_METADATA, CARD_MAPPINGS];
+    pub const ALL_TABLES: &[&str] = &[
+        TRACKS,
+        FILES,
+        UPDATES,
+        TRACK_METADATA,
+        CARD_MAPPINGS,
+        SCANS,
+        SCAN_ROOTS,
+        AUDIT_LOG,
+        AUDIT_TRACKS,
+        AUDIT_FILES,
+        SNAPSHOTS,
+        SNAPSHOT_FILES,
+        TRACK_LYRICS,
+        PLAYLISTS,
+        PLAYLIST_TRACKS,
+        CARD_SCANS,
+        FAVOURITES,
+        METADATA_SOURCES,
+        SHORT_LINKS,
+        YOUTUBE_IDS,
+        TRACK_NOTES,
+        ARTISTS,
+        TRACK_ARTISTS,
+    ];
 }
 
 pub mod columns {
@@ -23,6 +67,48 @@ pub mod columns {
     pub const FILE_SIZE: &str = "file_size";
     pub const FILE_HASH: &str = "file_hash";
     pub const CARD_ID: &str = "card_id";
+    pub const SCAN_ID: &str = "scan_id";
+    pub const STARTED_AT: &str = "started_at";
+    pub const DURATION_MS: &str = "duration_ms";
+    pub const FILES_SEEN: &str = "files_seen";
+    pub const FILES_ADDED: &str = "files_added";
+    pub const FILES_REMOVED: &str = "files_removed";
+    pub const ROOT: &str = "root";
+    pub const AVAILABLE: &str = "available";
+    pub const AUDIT_ID: &str = "audit_id";
+    pub const LOGGED_AT: &str = "logged_at";
+    pub const OPERATION: &str = "operation";
+    pub const DETAILS: &str = "details";
+    pub const CHANGE: &str = "change";
+    pub const SNAPSHOT_ID: &str = "snapshot_id";
+    pub const CREATED_AT: &str = "created_at";
+    pub const NAME: &str = "name";
+    pub const LAST_SEEN: &str = "last_seen";
+    pub const HASH_KIND: &str = "hash_kind";
+    pub const PLAIN_LYRICS: &str = "plain_lyrics";
+    pub const SYNCED_LYRICS: &str = "synced_lyrics";
+    pub const FETCHED_AT: &str = "fetched_at";
+    pub const PLAYLIST_ID: &str = "playlist_id";
+    pub const SHARE_TOKEN: &str = "share_token";
+    pub const POSITION: &str = "position";
+    pub const SCANNED_AT: &str = "scanned_at";
+    pub const OUTCOME: &str = "outcome";
+    pub const USER_AGENT: &str = "user_agent";
+    pub const FIELD: &str = "field";
+    pub const ORIGIN: &str = "origin";
+    pub const CHANGED_AT: &str = "changed_at";
+    pub const MODIFIED_AT: &str = "modified_at";
+    pub const FORMAT: &str = "format";
+    pub const QUARANTINED_AT: &str = "quarantined_at";
+    pub const QUARANTINE_REASON: &str = "quarantine_reason";
+    pub const CODE: &str = "code";
+    pub const TARGET: &str = "target";
+    pub const VIDEO_ID: &str = "video_id";
+    pub const NOTES: &str = "notes";
+    pub const ARTIST_ID: &str = "artist_id";
+    pub const ROLE: &str = "role";
+    pub const ARTIST_SORT: &str = "artist_sort";
+    pub const TITLE_SORT: &str = "title_sort";
 }
 
 pub use columns::*;
@@ -30,7 +116,9 @@ pub use tables::*;
 
 const SCHEMA: &str = r#"
 CREATE TABLE IF NOT EXISTS tracks (
-    track_id INTEGER PRIMARY KEY AUTOINCREMENT
+    track_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- when the first file of the track was recorded, NULL if that is not known
+    created_at INTEGER
 );
 
 -- 2. Card Mappings: Translation layer matching a physical card's printed id
@@ -47,6 +135,17 @@ CREATE TABLE IF NOT EXISTS files (
     track_id INTEGER NOT NULL,
     file_size INTEGER NOT NULL,
     file_hash TEXT NOT NULL,
+    -- when a scan found the file on disk last time, NULL if no scan has seen it yet
+    last_seen INTEGER,
+    -- 'full' or 'quick', see file_hash.rs
+    hash_kind TEXT NOT NULL DEFAULT 'full',
+    -- modification time of the file when it was hashed, NULL if it is not on a local drive
+    modified_at INTEGER,
+    -- lowercase extension, e.g. 'flac'
+    format TEXT,
+    -- set while the file is held back from playback, see quarantine.rs
+    quarantined_at INTEGER,
+    quarantine_reason TEXT,
     PRIMARY KEY (usb_label, path),
     FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
 );
@@ -65,16 +164,251 @@ CREATE TABLE IF NOT EXISTS track_metadata (
     FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
 );
 
+-- One row per `update` run
+CREATE TABLE IF NOT EXISTS scans (
+    scan_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    files_seen INTEGER NOT NULL,
+    files_added INTEGER NOT NULL,
+    -- recorded files that were no longer found on disk
+    files_removed INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS scan_roots (
+    scan_id INTEGER NOT NULL,
+    root TEXT NOT NULL,
+    available INTEGER NOT NULL,
+    files_seen INTEGER NOT NULL,
+    files_added INTEGER NOT NULL,
+    FOREIGN KEY (scan_id) REFERENCES scans(scan_id) ON DELETE CASCADE
+);
+
+-- Every mutation of the library, see audit.rs
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    logged_at INTEGER NOT NULL,
+    operation TEXT NOT NULL,
+    details TEXT NOT NULL
+);
+
+-- Tracks affected by a mutation. No foreign key on track_id, as tracks may be deleted later
+CREATE TABLE IF NOT EXISTS audit_tracks (
+    audit_id INTEGER NOT NULL,
+    track_id INTEGER NOT NULL,
+    FOREIGN KEY (audit_id) REFERENCES audit_log(audit_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_tracks_track_id ON audit_tracks(track_id);
+
+-- Copies of file rows added or removed by a mutation, used to undo it
+CREATE TABLE IF NOT EXISTS audit_files (
+    audit_id INTEGER NOT NULL,
+    change TEXT NOT NULL,
+    usb_label TEXT NOT NULL,
+    path TEXT NOT NULL,
+    track_id INTEGER NOT NULL,
+    file_size INTEGER NOT NULL,
+    file_hash TEXT NOT NULL,
+    hash_kind TEXT NOT NULL DEFAULT 'full',
+    modified_at INTEGER,
+    format TEXT,
+    FOREIGN KEY (audit_id) REFERENCES audit_log(audit_id) ON DELETE CASCADE
+);
+
+-- Frozen copies of the files table, see snapshot.rs
+CREATE TABLE IF NOT EXISTS snapshots (
+    snapshot_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,
+    name TEXT
+);
+
+CREATE TABLE IF NOT EXISTS snapshot_files (
+    snapshot_id INTEGER NOT NULL,
+    usb_label TEXT NOT NULL,
+    path TEXT NOT NULL,
+    track_id INTEGER NOT NULL,
+    file_size INTEGER NOT NULL,
+    file_hash TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, usb_label, path),
+    FOREIGN KEY (snapshot_id) REFERENCES snapshots(snapshot_id) ON DELETE CASCADE
+);
+
+-- Lyrics of a track, see lyrics.rs. Synced lyrics are in LRC format
+CREATE TABLE IF NOT EXISTS track_lyrics (
+    track_id INTEGER PRIMARY KEY,
+    plain_lyrics TEXT,
+    synced_lyrics TEXT,
+    fetched_at INTEGER NOT NULL,
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+-- Ordered lists of tracks, see playlist.rs
+CREATE TABLE IF NOT EXISTS playlists (
+    playlist_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    share_token TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS playlist_tracks (
+    playlist_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    track_id INTEGER NOT NULL,
+    PRIMARY KEY (playlist_id, position),
+    FOREIGN KEY (playlist_id) REFERENCES playlists(playlist_id) ON DELETE CASCADE,
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_playlist_tracks_track_id ON playlist_tracks(track_id);
+
+-- Every `/play` request, see card_scans.rs. No foreign key on track_id, as tracks may be deleted later
+CREATE TABLE IF NOT EXISTS card_scans (
+    scanned_at INTEGER NOT NULL,
+    card_id TEXT NOT NULL,
+    -- NULL if the card id did not resolve
+    track_id INTEGER,
+    -- 'local', 'remote', 'not_found' or 'failed'
+    outcome TEXT NOT NULL,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_card_scans_scanned_at ON card_scans(scanned_at);
+
+-- Tracks marked as favourite, see favourites.rs
+CREATE TABLE IF NOT EXISTS favourites (
+    track_id INTEGER PRIMARY KEY,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+-- Where metadata fields were last set from, see provenance.rs.
+-- Fields without a row were set before sources were recorded
+CREATE TABLE IF NOT EXISTS metadata_sources (
+    track_id INTEGER NOT NULL,
+    -- 'title', 'artist', 'year' or 'label'
+    field TEXT NOT NULL,
+    -- 'tags' or 'manual'
+    origin TEXT NOT NULL,
+    changed_at INTEGER NOT NULL,
+    PRIMARY KEY (track_id, field),
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+-- Short codes of server paths, see short_links.rs
+CREATE TABLE IF NOT EXISTS short_links (
+    code TEXT PRIMARY KEY,
+    target TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL
+);
+
+-- YouTube video of a track, see youtube.rs
+CREATE TABLE IF NOT EXISTS youtube_ids (
+    track_id INTEGER PRIMARY KEY,
+    video_id TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+-- Free-form notes on a track, see notes.rs
+CREATE TABLE IF NOT EXISTS track_notes (
+    track_id INTEGER PRIMARY KEY,
+    notes TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE
+);
+
+-- Artists credited on tracks, see artists.rs
+CREATE TABLE IF NOT EXISTS artists (
+    artist_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+
+-- Credits of a track in the order they are shown, as main, featuring or remixer
+CREATE TABLE IF NOT EXISTS track_artists (
+    track_id INTEGER NOT NULL,
+    artist_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, artist_id, role),
+    FOREIGN KEY (track_id) REFERENCES tracks(track_id) ON DELETE CASCADE,
+    FOREIGN KEY (artist_id) REFERENCES artists(artist_id) ON DELETE CASCADE
+);
+
 -- Fast lookup when checking if a file's hash already exists in the library
 CREATE INDEX IF NOT EXISTS idx_files_hash
     ON files(file_hash);
 
 CREATE INDEX IF NOT EXISTS idx_files_track_id ON files(track_id);
 
+-- `forget` and `meta apply` select the paths under a directory as a range
+CREATE INDEX IF NOT EXISTS idx_files_path ON files(path);
+
 CREATE INDEX IF NOT EXISTS idx_track_metadata_artist
     ON track_metadata(artist);
+
+-- artists are grouped and filtered ignoring case
+CREATE INDEX IF NOT EXISTS idx_track_metadata_artist_nocase
+    ON track_metadata(artist COLLATE NOCASE);
+
+-- tracks of an artist, for `/artists/<name>/tracks`
+CREATE INDEX IF NOT EXISTS idx_track_artists_artist
+    ON track_artists(artist_id);
+
+-- listings filtered by the year of release
+CREATE INDEX IF NOT EXISTS idx_track_metadata_year
+    ON track_metadata(year);
 "#;
 
 pub fn init(conn: &Connection) -> Result<(), rusqlite::Error> {
-    conn.execute_batch(SCHEMA)
+    conn.execute_batch(SCHEMA)?;
+    // columns added after a table was created are missing in older databases
+    if add_column_if_missing(conn, TRACKS, CREATED_AT, "INTEGER")? {
+        // the audit log remembers when tracks were added by `update`
+        conn.execute(
+            &format!(
+                "UPDATE {TRACKS} SET {CREATED_AT} = (
+                    SELECT MIN(l.{LOGGED_AT}) FROM {AUDIT_TRACKS} a
+                    JOIN {AUDIT_LOG} l ON a.{AUDIT_ID} = l.{AUDIT_ID}
+                    WHERE a.{TRACK_ID} = {TRACKS}.{TRACK_ID}
+                )"
+            ),
+            [],
+        )?;
+    }
+    add_column_if_missing(conn, FILES, LAST_SEEN, "INTEGER")?;
+    add_column_if_missing(conn, FILES, HASH_KIND, "TEXT NOT NULL DEFAULT 'full'")?;
+    add_column_if_missing(conn, FILES, MODIFIED_AT, "INTEGER")?;
+    add_column_if_missing(conn, FILES, FORMAT, "TEXT")?;
+    add_column_if_missing(conn, FILES, QUARANTINED_AT, "INTEGER")?;
+    add_column_if_missing(conn, FILES, QUARANTINE_REASON, "TEXT")?;
+    add_column_if_missing(conn, AUDIT_FILES, HASH_KIND, "TEXT NOT NULL DEFAULT 'full'")?;
+    add_column_if_missing(conn, AUDIT_FILES, MODIFIED_AT, "INTEGER")?;
+    add_column_if_missing(conn, AUDIT_FILES, FORMAT, "TEXT")?;
+    add_column_if_missing(conn, TRACK_METADATA, ARTIST_SORT, "TEXT")?;
+    add_column_if_missing(conn, TRACK_METADATA, TITLE_SORT, "TEXT")?;
+    sort_names::fill_missing(conn)?;
+    artists::link_missing(conn)?;
+    Ok(())
+}
+
+/// Returns whether the column was added
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<bool, rusqlite::Error> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+        ))?
+        .exists([column])?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(!exists)
 }
@@ -0,0 +1,492 @@
+//! Matching of `find` queries against paths, artists, titles and the like.
+//!
+//! Queries are words matching files whose track id, path, hash, card, artist, title or notes
+//! contain each of them. `"daft punk"` keeps words together and `-live` leaves out what
+//! matches. Terms naming a field look only there:
+//!
+//! - `artist:`, `title:`, `label:`, `notes:`, `path:` and `card:` match text like words do
+//! - `year:1999`, `year:>2000`, `year:<=1990` or `year:1990..1999` match the year of release,
+//!   tracks of unknown year never do
+//! - `format:flac` matches files of this format
+//! - `playlist:party` matches tracks of the playlist, its whole name ignoring case
+//!
+//! So `artist:"daft punk" year:>2000 playlist:party -format:wav` are the tracks of Daft Punk
+//! since 2001 in the playlist "Party", without their wav files.
+//!
+//! Text is compared normalized: decomposed with NFKD, without diacritics and lowercase. So
+//! "bjork" finds "Björk", "cafe" finds "Café" and "ﬁre" finds "fire". The library page
+//! normalizes its search the same way.
+//!
+//! With `transliterate_search` in the config, Cyrillic letters are also written as Latin
+//! ones before comparing, so "kino" finds "Кино" and "Кино" finds "Kino". The scheme is the
+//! common one without diacritics: "ж" is "zh", "х" is "kh" and "й" is "i" like "и".
+//!
+//! Years, formats and playlists are conditions of the SQL query of the backend. SQL `LIKE`
+//! can't compare normalized text, so text terms are matched here after reading the rows.
+
+use thiserror::Error;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::track::TrackId;
+
+/// Letters without a decomposition, written as their closest ASCII letters
+const FOLDED: &[(char, &str)] = &[
+    ('ø', "o"),
+    ('đ', "d"),
+    ('ł', "l"),
+    ('ß', "ss"),
+    ('æ', "ae"),
+    ('œ', "oe"),
+];
+
+/// Lowercase Cyrillic letters written in Latin ones. Letters with diacritics like "ё" and
+/// "й" lose them when normalized, so they are written like "е" and "и"
+pub const CYRILLIC_TO_LATIN: &[(char, &str)] = &[
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('ґ', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('є', "ye"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('і', "i"),
+    ('к', "k"),
+    ('л', "l"),
+    ('м', "m"),
+    ('н', "n"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('у', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ы', "y"),
+    ('ь', ""),
+    ('э', "e"),
+    ('ю', "yu"),
+    ('я', "ya"),
+];
+
+/// `text` decomposed, without diacritics and lowercase
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)) {
+        for c in c.to_lowercase() {
+            match FOLDED.iter().find(|(letter, _)| *letter == c) {
+                Some((_, folded)) => normalized.push_str(folded),
+                None => normalized.push(c),
+            }
+        }
+    }
+    normalized
+}
+
+/// Normalized `text` with Cyrillic letters written in Latin ones
+pub fn transliterate(normalized: &str) -> String {
+    let mut latin = String::with_capacity(normalized.len());
+    for c in normalized.chars() {
+        match CYRILLIC_TO_LATIN.iter().find(|(letter, _)| *letter == c) {
+            Some((_, written)) => latin.push_str(written),
+            None => latin.push(c),
+        }
+    }
+    latin
+}
+
+/// Why a query could not be parsed
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid search: {0}")]
+pub struct QueryError(String);
+
+/// Fields matched as text, like `artist:"daft punk"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Artist,
+    Title,
+    Label,
+    Notes,
+    Path,
+    Card,
+}
+
+/// What a term of a query asks of a file and its track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// normalized text in the field, or in any field plain words match if `None`
+    Text {
+        field: Option<TextField>,
+        text: String,
+    },
+    /// year of release within these, inclusive
+    Year { from: Option<u32>, to: Option<u32> },
+    /// format of the file, lowercase and without a leading dot
+    Format(String),
+    /// track in the playlist of this name, ignoring case
+    Playlist(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchTerm {
+    /// whether the term leaves out what matches, `-format:wav`
+    pub negated: bool,
+    pub condition: Condition,
+}
+
+/// A file and its track as text terms see them
+#[derive(Debug, Default)]
+pub struct SearchedFile {
+    pub track_id: TrackId,
+    pub path: String,
+    pub hash: String,
+    pub cards: Vec<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub label: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// A parsed query of `find` or `GET /search`, all of whose terms must hold
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    terms: Vec<SearchTerm>,
+    transliterate: bool,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str, transliterate: bool) -> Result<Self, QueryError> {
+        let mut parsed = Self {
+            terms: vec![],
+            transliterate,
+        };
+        for (negated, field, value) in split_terms(query)? {
+            let condition = match field.as_deref() {
+                None => Condition::Text {
+                    field: None,
+                    text: parsed.prepare(&value),
+                },
+                Some(_) if value.trim().is_empty() => {
+                    return Err(QueryError(format!("`{}:` needs a value", field.unwrap())));
+                }
+                Some(name) => match text_field(name) {
+                    Some(field) => Condition::Text {
+                        field: Some(field),
+                        text: parsed.prepare(&value),
+                    },
+                    None => match name {
+                        "year" => parse_years(&value)?,
+                        "format" => Condition::Format(
+                            value.trim().trim_start_matches('.').to_ascii_lowercase(),
+                        ),
+                        "playlist" => Condition::Playlist(value.trim().to_string()),
+                        _ => {
+                            return Err(QueryError(format!(
+                                "unknown field `{name}`, fields are artist, title, label, notes, \
+                                path, card, year, format and playlist"
+                            )));
+                        }
+                    },
+                },
+            };
+            // `""` asks for nothing
+            if !matches!(&condition, Condition::Text { text, .. } if text.is_empty()) {
+                parsed.terms.push(SearchTerm { negated, condition });
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The empty query matches everything
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Terms the backend checks in SQL: years, formats and playlists
+    pub fn filters(&self) -> impl Iterator<Item = &SearchTerm> {
+        self.terms
+            .iter()
+            .filter(|term| !matches!(term.condition, Condition::Text { .. }))
+    }
+
+    /// Whether the text terms hold for the file, [`SearchQuery::filters`] are not checked
+    pub fn matches(&self, file: &SearchedFile) -> bool {
+        let mut text_terms = self.terms.iter().filter_map(|term| match &term.condition {
+            Condition::Text { field, text } => Some((term.negated, *field, text)),
+            _ => None,
+        });
+        let Some(first) = text_terms.next() else {
+            return true;
+        };
+        let track_id = file.track_id.to_string();
+        // fields only plain words match have no name
+        let texts: Vec<(Option<TextField>, String)> = [
+            (None, Some(track_id.as_str())),
+            (None, Some(file.hash.as_str())),
+            (Some(TextField::Path), Some(file.path.as_str())),
+            (Some(TextField::Artist), file.artist.as_deref()),
+            (Some(TextField::Title), file.title.as_deref()),
+            (Some(TextField::Notes), file.notes.as_deref()),
+            (Some(TextField::Label), file.label.as_deref()),
+        ]
+        .into_iter()
+        .chain(
+            file.cards
+                .iter()
+                .map(|card| (Some(TextField::Card), Some(card.as_str()))),
+        )
+        .filter_map(|(field, text)| text.map(|text| (field, self.prepare(text))))
+        .collect();
+        std::iter::once(first)
+            .chain(text_terms)
+            .all(|(negated, field, text)| {
+                let found = texts.iter().any(|(named, searched)| {
+                    let in_field = match field {
+                        Some(field) => *named == Some(field),
+                        None => *named != Some(TextField::Label),
+                    };
+                    in_field && searched.contains(text.as_str())
+                });
+                found != negated
+            })
+    }
+
+    /// Text as the query compares it
+    fn prepare(&self, text: &str) -> String {
+        let normalized = normalize(text.trim());
+        if self.transliterate {
+            transliterate(&normalized)
+        } else {
+            normalized
+        }
+    }
+}
+
+fn text_field(name: &str) -> Option<TextField> {
+    Some(match name {
+        "artist" => TextField::Artist,
+        "title" => TextField::Title,
+        "label" => TextField::Label,
+        "notes" => TextField::Notes,
+        "path" => TextField::Path,
+        "card" => TextField::Card,
+        _ => return None,
+    })
+}
+
+/// Terms of the query with whether they are negated and the field they name
+fn split_terms(query: &str) -> Result<Vec<(bool, Option<String>, String)>, QueryError> {
+    let mut terms = vec![];
+    let mut chars = query.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(terms);
+        };
+        let negated = first == '-' && chars.peek().is_some_and(|c| !c.is_whitespace());
+        let mut field = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut next = (!negated).then_some(first);
+        while let Some(c) = next
+            .take()
+            .or_else(|| chars.next_if(|c| !c.is_whitespace()))
+        {
+            match c {
+                '"' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => value.push(c),
+                            None => return Err(QueryError("unclosed quote".to_string())),
+                        }
+                    }
+                }
+                // `C:` of a Windows path is no field
+                ':' if field.is_none()
+                    && !quoted
+                    && value.len() > 1
+                    && value.chars().all(|c| c.is_ascii_alphabetic()) =>
+                {
+                    field = Some(std::mem::take(&mut value).to_ascii_lowercase());
+                }
+                c => value.push(c),
+            }
+        }
+        terms.push((negated, field, value));
+    }
+}
+
+/// `1999`, `>2000`, `>=2000`, `<1990`, `<=1990` or `1990..1999`
+fn parse_years(value: &str) -> Result<Condition, QueryError> {
+    let invalid = || {
+        QueryError(format!(
+            "`year:{value}` is no year like 1999, >2000, <=1990 or 1990..1999"
+        ))
+    };
+    let year = |text: &str| text.trim().parse::<u32>().map_err(|_| invalid());
+    let value = value.trim();
+    let (from, to) = if let Some(year_) = value.strip_prefix(">=") {
+        (Some(year(year_)?), None)
+    } else if let Some(year_) = value.strip_prefix("<=") {
+        (None, Some(year(year_)?))
+    } else if let Some(year_) = value.strip_prefix('>') {
+        (Some(year(year_)?.checked_add(1).ok_or_else(invalid)?), None)
+    } else if let Some(year_) = value.strip_prefix('<') {
+        (None, Some(year(year_)?.checked_sub(1).ok_or_else(invalid)?))
+    } else if let Some((from, to)) = value.split_once("..") {
+        (Some(year(from)?), Some(year(to)?))
+    } else {
+        let year = year(value)?;
+        (Some(year), Some(year))
+    };
+    Ok(Condition::Year { from, to })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Condition, QueryError, SearchQuery, SearchTerm, SearchedFile, TextField, normalize,
+        transliterate,
+    };
+
+    fn file(path: &str, artist: &str, title: &str) -> SearchedFile {
+        SearchedFile {
+            track_id: 7,
+            path: path.to_string(),
+            artist: Some(artist.to_string()),
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accents_case_and_compatibility_forms_are_ignored() {
+        assert_eq!(normalize("Björk"), "bjork");
+        assert_eq!(normalize("Café"), "cafe");
+        assert_eq!(normalize("ﬁre"), "fire");
+        assert_eq!(normalize("Røyksopp"), "royksopp");
+        assert_eq!(normalize("Straße"), "strasse");
+
+        let query = SearchQuery::parse(" Cafe ", false).unwrap();
+        assert!(query.matches(&file("a.mp3", "Energy 52", "Café del Mar")));
+        assert!(!query.matches(&file("a.mp3", "Energy 52", "Caff")));
+        let query = SearchQuery::parse("BJÖRK", false).unwrap();
+        assert!(query.matches(&file("bjork - army of me.mp3", "", "")));
+        assert!(SearchQuery::parse("", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cyrillic_and_latin_match_when_transliterating() {
+        assert_eq!(transliterate(&normalize("Кино")), "kino");
+        assert_eq!(transliterate(&normalize("Звёзды")), "zvezdy");
+        assert_eq!(
+            transliterate(&normalize("Щедрый вечер")),
+            "shchedryi vecher"
+        );
+
+        let kino = file("a.mp3", "Кино", "Группа крови");
+        let latin = file("b.mp3", "Kino", "Gruppa krovi");
+        let matches = |query: &str, transliterate, file| {
+            SearchQuery::parse(query, transliterate)
+                .unwrap()
+                .matches(file)
+        };
+        assert!(matches("kino", true, &kino));
+        assert!(matches("Кино", true, &latin));
+        assert!(matches("\"gruppa krovi\"", true, &kino));
+        assert!(!matches("kino", false, &kino));
+        assert!(matches("Кино", false, &kino));
+    }
+
+    #[test]
+    fn terms_are_parsed() {
+        let query = SearchQuery::parse(r#"artist:"daft punk" year:>2000 Live -format:.WAV"#, false)
+            .unwrap();
+        let term = |negated, condition| SearchTerm { negated, condition };
+        assert_eq!(
+            query.terms,
+            [
+                term(
+                    false,
+                    Condition::Text {
+                        field: Some(TextField::Artist),
+                        text: "daft punk".to_string(),
+                    }
+                ),
+                term(
+                    false,
+                    Condition::Year {
+                        from: Some(2001),
+                        to: None,
+                    }
+                ),
+                term(
+                    false,
+                    Condition::Text {
+                        field: None,
+                        text: "live".to_string(),
+                    }
+                ),
+                term(true, Condition::Format("wav".to_string())),
+            ]
+        );
+        assert_eq!(query.filters().count(), 2);
+
+        let years = |value| match SearchQuery::parse(&format!("year:{value}"), false)
+            .unwrap()
+            .terms[0]
+            .condition
+        {
+            Condition::Year { from, to } => (from, to),
+            _ => unreachable!(),
+        };
+        assert_eq!(years("1999"), (Some(1999), Some(1999)));
+        assert_eq!(years("<=1990"), (None, Some(1990)));
+        assert_eq!(years("<1990"), (None, Some(1989)));
+        assert_eq!(years("1990..1999"), (Some(1990), Some(1999)));
+
+        let error = |query| SearchQuery::parse(query, false).unwrap_err();
+        assert!(matches!(error("tag:party"), QueryError(e) if e.contains("unknown field `tag`")));
+        assert!(matches!(error("year:nineties"), QueryError(_)));
+        assert!(matches!(error("artist:"), QueryError(_)));
+        assert!(matches!(error("\"daft punk"), QueryError(_)));
+        // no fields
+        assert_eq!(
+            SearchQuery::parse(r"C:\music - x", false)
+                .unwrap()
+                .terms
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn text_terms_match_their_fields() {
+        let track = SearchedFile {
+            label: Some("Virgin".to_string()),
+            cards: vec!["CARD_1".to_string(), "CARD_2".to_string()],
+            ..file("daft punk/one more time.mp3", "Daft Punk", "One More Time")
+        };
+        let matches = |query| SearchQuery::parse(query, false).unwrap().matches(&track);
+        assert!(matches("punk time"));
+        assert!(!matches("punk alive"));
+        assert!(matches("title:time -title:alive"));
+        assert!(!matches("artist:time"));
+        assert!(matches("label:virgin"));
+        assert!(!matches("virgin"));
+        assert!(matches("card:card_2"));
+        assert!(!matches("-card:card_1"));
+        assert!(matches("7 year:<2000"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Short codes for paths of the server, so `/s/k3f9q` stands in for `/play?h=1234`.
+//!
+//! Fewer characters make a QR code with fewer, larger modules, which a phone reads
+//! from further away and off worn cards. A path keeps the code it got first, so
+//! printing a card again gives the same link.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use rusqlite::{OptionalExtension, params};
+
+use crate::{
+    Storage,
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+};
+
+pub const SHORT_CODE_LEN: usize = 5;
+/// Lowercase letters and digits without the look-alikes `0`, `o`, `1` and `l`
+const ALPHABET: &[u8; 32] = b"abcdefghijkmnpqrstuvwxyz23456789";
+/// Codes drawn before giving up, each clashing with a taken one is very unlikely
+pub(crate) const ATTEMPTS: usize = 10;
+
+/// A random code, so the links of one card don't tell those of others
+pub(crate) fn new_code() -> String {
+    // RandomState is seeded randomly for every process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    let mut bits = hasher.finish();
+    (0..SHORT_CODE_LEN)
+        .map(|_| {
+            let c = ALPHABET[(bits % 32) as usize];
+            bits /= 32;
+            c as char
+        })
+        .collect()
+}
+
+impl Storage {
+    /// Code of the short link to `target`, made on the first call for a target
+    pub fn short_link(&mut self, target: &str) -> Result<String, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        let existing: Option<String> = tx
+            .query_row(
+                &format!("SELECT {CODE} FROM {SHORT_LINKS} WHERE {TARGET} = ?1"),
+                params![target],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let code = match existing {
+            Some(code) => code,
+            None => {
+                let mut inserted = None;
+                for _ in 0..ATTEMPTS {
+                    let code = new_code();
+                    let added = tx.execute(
+                        &format!(
+                            "INSERT OR IGNORE INTO {SHORT_LINKS} ({CODE}, {TARGET}, {CREATED_AT})
+                            VALUES (?1, ?2, ?3)"
+                        ),
+                        params![code, target, now],
+                    )?;
+                    if added == 1 {
+                        inserted = Some(code);
+                        break;
+                    }
+                }
+                inserted.ok_or_else(|| {
+                    StorageError::Internal(anyhow!("no free short code for {target}"))
+                })?
+            }
+        };
+        tx.commit()?;
+        Ok(code)
+    }
+
+    /// Target of a short link, `None` for unknown codes
+    pub fn resolve_short_link(&mut self, code: &str) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT {TARGET} FROM {SHORT_LINKS} WHERE {CODE} = ?1"),
+                params![code],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+}
@@ -0,0 +1,196 @@
+//! Point-in-time copies of the files table, to compare the library between dates
+//! independently of what is on disk right now
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use rusqlite::{OptionalExtension, params};
+
+use crate::{
+    Storage,
+    db::{i64_seconds_to_local_time, system_time_to_i64},
+    error::StorageError,
+    location::Location,
+    operations::LocationRow,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+pub type SnapshotId = i64;
+
+#[derive(Debug, Clone)]
+pub struct SnapshotInfo {
+    pub id: SnapshotId,
+    pub created_at: DateTime<Local>,
+    pub name: Option<String>,
+    /// number of file rows in the snapshot
+    pub files: usize,
+}
+
+/// One side of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryState {
+    Snapshot(SnapshotId),
+    /// the database as it is now
+    Current,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotFile {
+    pub track_id: TrackId,
+    pub location: Location,
+    pub file_size: i64,
+}
+
+/// Files which differ between two library states.
+///
+/// A file with the same location but different content is reported as removed and added.
+#[derive(Debug, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<SnapshotFile>,
+    pub removed: Vec<SnapshotFile>,
+}
+
+impl LibraryState {
+    /// SQL selecting the file rows of this state
+    fn files_query(&self) -> String {
+        match self {
+            LibraryState::Snapshot(id) => format!(
+                "SELECT {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}
+                FROM {SNAPSHOT_FILES} WHERE {SNAPSHOT_ID} = {id}"
+            ),
+            LibraryState::Current => format!(
+                "SELECT {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH} FROM {FILES}"
+            ),
+        }
+    }
+}
+
+impl Storage {
+    /// Stores a copy of all current file rows
+    pub fn create_snapshot(&mut self, name: Option<&str>) -> Result<SnapshotInfo, StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        let tx = self.db.transaction()?;
+        tx.execute(
+            &format!("INSERT INTO {SNAPSHOTS} ({CREATED_AT}, {NAME}) VALUES (?1, ?2)"),
+            params![now, name],
+        )?;
+        let id = tx.last_insert_rowid();
+        let files = tx.execute(
+            &format!(
+                "INSERT INTO {SNAPSHOT_FILES}
+                    ({SNAPSHOT_ID}, {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH})
+                SELECT ?1, {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH} FROM {FILES}"
+            ),
+            params![id],
+        )?;
+        tx.commit()?;
+
+        Ok(SnapshotInfo {
+            id,
+            created_at: i64_seconds_to_local_time(now).map_err(StorageError::Internal)?,
+            name: name.map(str::to_string),
+            files,
+        })
+    }
+
+    /// All snapshots, oldest first
+    pub fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT s.{SNAPSHOT_ID}, s.{CREATED_AT}, s.{NAME}, COUNT(f.{PATH})
+            FROM {SNAPSHOTS} s
+            LEFT JOIN {SNAPSHOT_FILES} f ON s.{SNAPSHOT_ID} = f.{SNAPSHOT_ID}
+            GROUP BY s.{SNAPSHOT_ID}
+            ORDER BY s.{SNAPSHOT_ID}"
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, SnapshotId>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(id, created_at, name, files)| {
+                Ok(SnapshotInfo {
+                    id,
+                    created_at: i64_seconds_to_local_time(created_at)
+                        .map_err(StorageError::Internal)?,
+                    name,
+                    files: files as usize,
+                })
+            })
+            .collect()
+    }
+
+    /// Latest snapshot created not later than `time`
+    pub fn snapshot_at(
+        &mut self,
+        time: DateTime<Local>,
+    ) -> Result<Option<SnapshotId>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT {SNAPSHOT_ID} FROM {SNAPSHOTS} WHERE {CREATED_AT} <= ?1
+                    ORDER BY {CREATED_AT} DESC, {SNAPSHOT_ID} DESC LIMIT 1"
+                ),
+                params![time.timestamp()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Compares files of two library states
+    pub fn diff_snapshots(
+        &mut self,
+        from: LibraryState,
+        to: LibraryState,
+    ) -> Result<SnapshotDiff, StorageError> {
+        for state in [from, to] {
+            if let LibraryState::Snapshot(id) = state {
+                let exists = self
+                    .db
+                    .query_row(
+                        &format!("SELECT 1 FROM {SNAPSHOTS} WHERE {SNAPSHOT_ID} = ?1"),
+                        params![id],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                if exists.is_none() {
+                    return Err(StorageError::SnapshotNotFound(id));
+                }
+            }
+        }
+
+        let (from, to) = (from.files_query(), to.files_query());
+        Ok(SnapshotDiff {
+            added: self.files_except(&to, &from)?,
+            removed: self.files_except(&from, &to)?,
+        })
+    }
+
+    /// rows of query `a` which are not in query `b`
+    fn files_except(&self, a: &str, b: &str) -> Result<Vec<SnapshotFile>, StorageError> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT * FROM ({a} EXCEPT {b}) ORDER BY {USB_LABEL}, {PATH}"
+        ))?;
+        Ok(stmt
+            .query_map([], |row| {
+                Ok(SnapshotFile {
+                    location: LocationRow {
+                        usb_label: row.get(0)?,
+                        path: row.get(1)?,
+                    }
+                    .into(),
+                    track_id: row.get(2)?,
+                    file_size: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+}
@@ -0,0 +1,178 @@
+//! Names tracks are ordered by, so "The Beatles" is listed under B as "Beatles, The".
+//!
+//! Every track with metadata has an `artist_sort` and a `title_sort`. They are made from
+//! the artist and title when those are stored, and made again when those change. A sort
+//! name set with `localdeck meta add --artist-sort` is kept until then.
+//!
+//! Leading articles go to the end and leading punctuation is skipped, so
+//! "...And Justice for All" sorts under A. Featured artists don't count, "A feat. B"
+//! sorts with the other tracks of A. Names of people are left as they are, nothing tells
+//! "Daft Punk" from a first and a last name.
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{
+    Storage,
+    artists::split_featuring,
+    audit::{self, AuditOperation},
+    error::StorageError,
+    operations::{ArtistEntry, TrackListEntry},
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+/// Articles moved to the end of sort names, compared ignoring case
+const ARTICLES: &[&str] = &["the", "a", "an"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortNames {
+    pub artist: String,
+    pub title: String,
+}
+
+/// `text` with a leading article moved to the end and leading punctuation skipped
+pub fn sort_name(text: &str) -> String {
+    let trimmed = text.trim();
+    let name = trimmed.trim_start_matches(|c: char| !c.is_alphanumeric());
+    if name.is_empty() {
+        // "!!!" is a band too
+        return trimmed.to_string();
+    }
+    match name.split_once(' ') {
+        Some((first, rest))
+            if ARTICLES.iter().any(|a| first.eq_ignore_ascii_case(a))
+                && !rest.trim().is_empty() =>
+        {
+            format!("{}, {first}", rest.trim())
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Sort name of an artist string, by its main artist
+pub fn artist_sort_name(artist: &str) -> String {
+    sort_name(&split_featuring(artist).0)
+}
+
+/// Orders tracks by artist and title sort names ignoring case, tracks without metadata last
+pub fn sort_by_name(tracks: &mut [TrackListEntry]) {
+    tracks.sort_by_cached_key(|track| {
+        let names = track.meta.as_ref().map(|meta| {
+            (
+                meta.artist_sort.to_lowercase(),
+                meta.title_sort.to_lowercase(),
+            )
+        });
+        (names.is_none(), names, track.id)
+    });
+}
+
+/// Orders artists by sort name ignoring case
+pub fn sort_artists(artists: &mut [ArtistEntry]) {
+    artists.sort_by_cached_key(|artist| sort_name(&artist.name).to_lowercase());
+}
+
+/// Sort names for tracks whose metadata was stored before sort names were
+pub(crate) fn fill_missing(db: &Connection) -> Result<(), rusqlite::Error> {
+    let tracks = db
+        .prepare(&format!(
+            "SELECT {TRACK_ID}, {ARTIST}, {TITLE} FROM {TRACK_METADATA}
+            WHERE {ARTIST_SORT} IS NULL OR {TITLE_SORT} IS NULL"
+        ))?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, TrackId>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (track_id, artist, title) in tracks {
+        db.execute(
+            &format!(
+                "UPDATE {TRACK_METADATA} SET
+                    {ARTIST_SORT} = COALESCE({ARTIST_SORT}, ?1),
+                    {TITLE_SORT} = COALESCE({TITLE_SORT}, ?2)
+                WHERE {TRACK_ID} = ?3"
+            ),
+            params![artist_sort_name(&artist), sort_name(&title), track_id],
+        )?;
+    }
+    Ok(())
+}
+
+impl Storage {
+    /// Sort names of the track, `None` without metadata
+    pub fn sort_names(&mut self, track_id: TrackId) -> Result<Option<SortNames>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT {ARTIST_SORT}, {TITLE_SORT} FROM {TRACK_METADATA} WHERE {TRACK_ID} = ?1"
+                ),
+                params![track_id],
+                |row| {
+                    Ok(SortNames {
+                        artist: row.get(0)?,
+                        title: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// Sets the given sort names of the track, which needs metadata. Empty ones are made
+    /// from the artist or title again
+    pub fn set_sort_names(
+        &mut self,
+        track_id: TrackId,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let tx = self.db.transaction()?;
+        let meta = Self::load_metadata(&tx, track_id)?
+            .ok_or(StorageError::RequiredMetaMissing(track_id))?;
+        let artist = artist.map(|artist| match artist.trim() {
+            "" => artist_sort_name(&meta.artist),
+            artist => artist.to_string(),
+        });
+        let title = title.map(|title| match title.trim() {
+            "" => sort_name(&meta.title),
+            title => title.to_string(),
+        });
+        tx.execute(
+            &format!(
+                "UPDATE {TRACK_METADATA} SET
+                    {ARTIST_SORT} = COALESCE(?1, {ARTIST_SORT}),
+                    {TITLE_SORT} = COALESCE(?2, {TITLE_SORT})
+                WHERE {TRACK_ID} = ?3"
+            ),
+            params![artist, title, track_id],
+        )?;
+        Self::insert_update_time(&tx)?;
+        audit::record(&tx, AuditOperation::Metadata, "sort names", [track_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{artist_sort_name, sort_name};
+
+    #[test]
+    fn articles_and_punctuation_are_moved_out_of_the_way() {
+        assert_eq!(sort_name("The Beatles"), "Beatles, The");
+        assert_eq!(sort_name("a tribe called quest"), "tribe called quest, a");
+        assert_eq!(sort_name("...And Justice for All"), "And Justice for All");
+        assert_eq!(sort_name("'Til Tuesday"), "Til Tuesday");
+        assert_eq!(sort_name("Theory of a Deadman"), "Theory of a Deadman");
+        assert_eq!(sort_name("The"), "The");
+        assert_eq!(sort_name("!!!"), "!!!");
+        assert_eq!(
+            artist_sort_name("The Weeknd feat. Daft Punk"),
+            "Weeknd, The"
+        );
+        assert_eq!(artist_sort_name("A feat. B"), "A");
+    }
+}
@@ -0,0 +1,895 @@
+//! Backend independent interface of the track library.
+//!
+//! The HTTP server, the card player and most commands only use [`LibraryStore`],
+//! so another backend or a test double can serve them instead of the SQLite [`Storage`].
+//! Checks, history and snapshots are still specific to [`Storage`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    CardId,
+    artists::ArtistCredit,
+    bundle::BundleManifest,
+    card_scans::CardScan,
+    config::{Config, Database, LibrarySource},
+    error::StorageError,
+    glob::PathGlob,
+    location::Location,
+    lyrics::TrackLyrics,
+    operations::{
+        ApplyMetadataReport, ArtistEntry, CleanDanglingReport, ForgetReport, HashedFile,
+        MetadataUpdate, StaleTracks, Storage, TrackFilter, TrackListEntry, UnavailableRoot,
+        YearEntry,
+    },
+    playlist::{Playlist, PlaylistId},
+    progress::Progress,
+    provenance::{FieldSource, MetadataPolicy, TagImportReport},
+    quarantine::QuarantinedFile,
+    sort_names::SortNames,
+    track::{Track, TrackId, TrackMetadata},
+    update::{LibraryScan, PendingUpdate},
+};
+
+/// Opens the backend selected by the `database` config
+pub fn open_store(config: Config) -> Result<Box<dyn LibraryStore>, StorageError> {
+    match &config.database {
+        #[cfg(feature = "postgres")]
+        Database::Postgres { url } => Ok(Box::new(crate::postgres::PgStorage::new(
+            url,
+            config.library_source,
+        )?)),
+        Database::InMemory | Database::OnDisk { .. } => Ok(Box::new(Storage::new(config)?)),
+    }
+}
+
+/// Tracks, their files, metadata and card aliases.
+///
+/// See the methods of [`Storage`] for the detailed behavior each implementation must follow.
+pub trait LibraryStore: Send {
+    /// Sets the receiver of scan and hashing progress
+    fn set_progress(&mut self, progress: Box<dyn Progress>);
+
+    /// Configured library roots which are not accessible at the moment
+    fn unavailable_roots(&mut self) -> Vec<UnavailableRoot>;
+
+    /// Whether searches match Cyrillic text by its transliteration, see [`crate::search`]
+    fn transliterate_search(&self) -> bool;
+
+    /// Directories skipped by later scans, replacing those of the config
+    fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>);
+
+    /// Lets the backend keep lookups of [`LibraryStore::resolve_track`] and
+    /// [`LibraryStore::find_track_file_with_meta`] in memory, for long running servers
+    fn enable_track_index(&mut self) {}
+
+    /// Scans the library roots and adds new files, returns the inserted files by track
+    fn update_db_with_new_files(
+        &mut self,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError>;
+
+    /// Library scanned by updates
+    fn library_source(&self) -> LibrarySource;
+
+    /// First step of an update done in steps, see [`crate::update::update_shared`]:
+    /// records which files of `scan` are still there and lists the new ones
+    fn start_update(&mut self, scan: LibraryScan) -> Result<PendingUpdate, StorageError>;
+
+    /// Commits a batch of hashed new files of `update`
+    fn insert_new_files(
+        &mut self,
+        update: &mut PendingUpdate,
+        files: Vec<HashedFile>,
+    ) -> Result<(), StorageError>;
+
+    /// Completes `update`, returns the inserted files by track
+    fn finish_update(
+        &mut self,
+        update: PendingUpdate,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError>;
+
+    /// All tracks having metadata
+    fn scan_metadata(&mut self) -> Result<Vec<Track>, StorageError>;
+
+    /// Time of the latest library mutation
+    fn updated_at(&mut self) -> Result<DateTime<Local>, StorageError>;
+
+    /// All tracks with their recorded file locations, ordered by track id
+    fn list_tracks(&mut self) -> Result<Vec<TrackListEntry>, StorageError>;
+
+    /// Tracks passing the filter, like [`LibraryStore::list_tracks`]
+    fn list_tracks_matching(
+        &mut self,
+        filter: &TrackFilter,
+    ) -> Result<Vec<TrackListEntry>, StorageError>;
+
+    /// Artists of the metadata with their track counts, ordered by name
+    fn list_artists(&mut self) -> Result<Vec<ArtistEntry>, StorageError>;
+
+    /// Years of release of the metadata with their track counts, the earliest first
+    fn list_years(&mut self) -> Result<Vec<YearEntry>, StorageError>;
+
+    /// Recorded files with their content hashes, ordered by track id
+    fn list_hashed_files(&mut self) -> Result<Vec<(TrackId, HashedFile)>, StorageError>;
+
+    /// Track id of a card alias, or of a plain track id
+    fn resolve_track(&mut self, card_id: CardId) -> Result<TrackId, StorageError>;
+
+    /// Best available file of the track with its metadata
+    fn find_track_file_with_meta(
+        &mut self,
+        track: TrackId,
+    ) -> Result<(PathBuf, Location, Option<TrackMetadata>), StorageError>;
+
+    fn get_track_metadata(
+        &mut self,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError>;
+
+    /// Files of tracks matching the query, optionally only of tracks without metadata
+    fn find_files(
+        &mut self,
+        query: &str,
+        no_meta: bool,
+    ) -> Result<HashMap<TrackId, HashSet<Location>>, StorageError>;
+
+    /// Tracks without recorded files
+    fn check_stale(&mut self) -> Result<StaleTracks, StorageError>;
+
+    fn update_track_metadata(
+        &mut self,
+        track_id: TrackId,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<(), StorageError>;
+
+    /// Updates the metadata of all tracks with files under the path at once
+    fn apply_metadata_under(
+        &mut self,
+        path: &Path,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<ApplyMetadataReport, StorageError>;
+
+    /// Merges the tags of the tracks' files into their metadata
+    fn import_tags(
+        &mut self,
+        tracks: &[TrackId],
+        policy: MetadataPolicy,
+    ) -> Result<TagImportReport, StorageError>;
+
+    /// Where the track's fields were set from, as far as recorded
+    fn metadata_sources(&mut self, track_id: TrackId) -> Result<Vec<FieldSource>, StorageError>;
+
+    fn get_lyrics(&mut self, track_id: TrackId) -> Result<Option<TrackLyrics>, StorageError>;
+
+    /// Stores lyrics of the track, replacing lyrics stored before
+    fn set_lyrics(&mut self, track_id: TrackId, lyrics: &TrackLyrics) -> Result<(), StorageError>;
+
+    fn create_playlist(&mut self, name: &str) -> Result<Playlist, StorageError>;
+
+    fn delete_playlist(&mut self, id: PlaylistId) -> Result<(), StorageError>;
+
+    /// All playlists ordered by name
+    fn list_playlists(&mut self) -> Result<Vec<Playlist>, StorageError>;
+
+    fn get_playlist(&mut self, id: PlaylistId) -> Result<Playlist, StorageError>;
+
+    /// Replaces the tracks of the playlist, keeping their order
+    fn set_playlist_tracks(
+        &mut self,
+        id: PlaylistId,
+        tracks: &[TrackId],
+    ) -> Result<(), StorageError>;
+
+    /// Sets or removes the token needed to open the shared playlist page
+    fn set_playlist_token(
+        &mut self,
+        id: PlaylistId,
+        token: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    fn is_favourite(&mut self, track_id: TrackId) -> Result<bool, StorageError>;
+
+    /// Marks or unmarks the track, marking it again keeps the time it was marked first
+    fn set_favourite(&mut self, track_id: TrackId, favourite: bool) -> Result<(), StorageError>;
+
+    /// Favourite tracks, the most recently marked first
+    fn list_favourites(&mut self) -> Result<Vec<TrackId>, StorageError>;
+
+    /// Code of the short link to `target`, see [`crate::short_links`]
+    fn short_link(&mut self, target: &str) -> Result<String, StorageError>;
+
+    /// Target of a short link, `None` for unknown codes
+    fn resolve_short_link(&mut self, code: &str) -> Result<Option<String>, StorageError>;
+
+    /// YouTube video of the track, see [`crate::youtube`]
+    fn youtube_id(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError>;
+
+    /// Stores the video of the track, replacing the one stored before
+    fn set_youtube_id(&mut self, track_id: TrackId, video_id: &str) -> Result<(), StorageError>;
+
+    /// Free-form notes on the track, see [`crate::notes`]
+    fn track_notes(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError>;
+
+    /// Replaces the notes of the track, blank notes remove them
+    fn set_track_notes(
+        &mut self,
+        track_id: TrackId,
+        notes: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    /// Artists credited on the track in the order they are shown, see [`crate::artists`]
+    fn artist_credits(&mut self, track_id: TrackId) -> Result<Vec<ArtistCredit>, StorageError>;
+
+    /// Credits the artists on the track and makes its artist string from them. Without
+    /// credits they are read from the artist and title again
+    fn set_artist_credits(
+        &mut self,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError>;
+
+    /// Names the track is ordered by, `None` without metadata, see [`crate::sort_names`]
+    fn sort_names(&mut self, track_id: TrackId) -> Result<Option<SortNames>, StorageError>;
+
+    /// Overrides the given sort names of the track, empty ones are made automatically again
+    fn set_sort_names(
+        &mut self,
+        track_id: TrackId,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    /// Logs a `/play` request, see [`crate::card_scans`]
+    fn record_card_scan(&mut self, scan: &CardScan) -> Result<(), StorageError>;
+
+    /// Logged scans, optionally only those made since a time, newest first
+    fn list_card_scans(
+        &mut self,
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<CardScan>, StorageError>;
+
+    fn add_file_to_track(
+        &mut self,
+        master_id: TrackId,
+        physical_path: &Path,
+    ) -> Result<(), StorageError>;
+
+    fn merge_tracks(
+        &mut self,
+        master_id: TrackId,
+        slave_id: TrackId,
+        ignore_slave_meta: bool,
+    ) -> Result<(), StorageError>;
+
+    /// Removes all files under the path
+    fn forget_path(&mut self, path: &Path) -> Result<ForgetReport, StorageError>;
+
+    /// Removes the files whose path matches the glob
+    fn forget_matching(&mut self, glob: &PathGlob) -> Result<ForgetReport, StorageError>;
+
+    /// Holds back a file from playback, see [`crate::quarantine`]
+    fn quarantine(&mut self, loc: &Location, reason: &str) -> Result<bool, StorageError>;
+
+    fn list_quarantined(&mut self) -> Result<Vec<QuarantinedFile>, StorageError>;
+
+    /// Releases quarantined files at or under the path, or all of them
+    fn clear_quarantine(&mut self, path: Option<&Path>) -> Result<usize, StorageError>;
+
+    /// Removes tracks without files and metadata
+    fn clean_dangling(&mut self) -> Result<CleanDanglingReport, StorageError>;
+
+    /// Writes a copy of the database to `dest` for a bundle, see [`Storage::export_bundle`]
+    fn export_bundle(&mut self, dest: &Path) -> Result<BundleManifest, StorageError>;
+}
+
+impl LibraryStore for Storage {
+    fn set_progress(&mut self, progress: Box<dyn Progress>) {
+        Storage::set_progress(self, progress)
+    }
+
+    fn unavailable_roots(&mut self) -> Vec<UnavailableRoot> {
+        Storage::unavailable_roots(self)
+    }
+
+    fn transliterate_search(&self) -> bool {
+        Storage::transliterate_search(self)
+    }
+
+    fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>) {
+        Storage::set_ignored_dirs(self, dirs)
+    }
+
+    fn enable_track_index(&mut self) {
+        Storage::enable_track_index(self)
+    }
+
+    fn update_db_with_new_files(
+        &mut self,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        Storage::update_db_with_new_files(self)
+    }
+
+    fn library_source(&self) -> LibrarySource {
+        Storage::library_source(self)
+    }
+
+    fn start_update(&mut self, scan: LibraryScan) -> Result<PendingUpdate, StorageError> {
+        Storage::start_update(self, scan)
+    }
+
+    fn insert_new_files(
+        &mut self,
+        update: &mut PendingUpdate,
+        files: Vec<HashedFile>,
+    ) -> Result<(), StorageError> {
+        Storage::insert_new_files(self, update, files)
+    }
+
+    fn finish_update(
+        &mut self,
+        update: PendingUpdate,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        Storage::finish_update(self, update)
+    }
+
+    fn scan_metadata(&mut self) -> Result<Vec<Track>, StorageError> {
+        Storage::scan_metadata(self)
+    }
+
+    fn updated_at(&mut self) -> Result<DateTime<Local>, StorageError> {
+        Storage::updated_at(self)
+    }
+
+    fn list_tracks(&mut self) -> Result<Vec<TrackListEntry>, StorageError> {
+        Storage::list_tracks(self)
+    }
+
+    fn list_tracks_matching(
+        &mut self,
+        filter: &TrackFilter,
+    ) -> Result<Vec<TrackListEntry>, StorageError> {
+        Storage::list_tracks_matching(self, filter)
+    }
+
+    fn list_artists(&mut self) -> Result<Vec<ArtistEntry>, StorageError> {
+        Storage::list_artists(self)
+    }
+
+    fn list_years(&mut self) -> Result<Vec<YearEntry>, StorageError> {
+        Storage::list_years(self)
+    }
+
+    fn list_hashed_files(&mut self) -> Result<Vec<(TrackId, HashedFile)>, StorageError> {
+        Storage::list_hashed_files(self)
+    }
+
+    fn resolve_track(&mut self, card_id: CardId) -> Result<TrackId, StorageError> {
+        Storage::resolve_track(self, card_id)
+    }
+
+    fn find_track_file_with_meta(
+        &mut self,
+        track: TrackId,
+    ) -> Result<(PathBuf, Location, Option<TrackMetadata>), StorageError> {
+        Storage::find_track_file_with_meta(self, track)
+    }
+
+    fn get_track_metadata(
+        &mut self,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError> {
+        Storage::get_track_metadata(self, track_id)
+    }
+
+    fn find_files(
+        &mut self,
+        query: &str,
+        no_meta: bool,
+    ) -> Result<HashMap<TrackId, HashSet<Location>>, StorageError> {
+        Storage::find_files(self, query, no_meta)
+    }
+
+    fn check_stale(&mut self) -> Result<StaleTracks, StorageError> {
+        Storage::check_stale(self)
+    }
+
+    fn update_track_metadata(
+        &mut self,
+        track_id: TrackId,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<(), StorageError> {
+        Storage::update_track_metadata(self, track_id, new_meta, allow_overwrite)
+    }
+
+    fn apply_metadata_under(
+        &mut self,
+        path: &Path,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<ApplyMetadataReport, StorageError> {
+        Storage::apply_metadata_under(self, path, new_meta, allow_overwrite)
+    }
+
+    fn import_tags(
+        &mut self,
+        tracks: &[TrackId],
+        policy: MetadataPolicy,
+    ) -> Result<TagImportReport, StorageError> {
+        Storage::import_tags(self, tracks, policy)
+    }
+
+    fn metadata_sources(&mut self, track_id: TrackId) -> Result<Vec<FieldSource>, StorageError> {
+        Storage::metadata_sources(self, track_id)
+    }
+
+    fn get_lyrics(&mut self, track_id: TrackId) -> Result<Option<TrackLyrics>, StorageError> {
+        Storage::get_lyrics(self, track_id)
+    }
+
+    fn set_lyrics(&mut self, track_id: TrackId, lyrics: &TrackLyrics) -> Result<(), StorageError> {
+        Storage::set_lyrics(self, track_id, lyrics)
+    }
+
+    fn create_playlist(&mut self, name: &str) -> Result<Playlist, StorageError> {
+        Storage::create_playlist(self, name)
+    }
+
+    fn delete_playlist(&mut self, id: PlaylistId) -> Result<(), StorageError> {
+        Storage::delete_playlist(self, id)
+    }
+
+    fn list_playlists(&mut self) -> Result<Vec<Playlist>, StorageError> {
+        Storage::list_playlists(self)
+    }
+
+    fn get_playlist(&mut self, id: PlaylistId) -> Result<Playlist, StorageError> {
+        Storage::get_playlist(self, id)
+    }
+
+    fn set_playlist_tracks(
+        &mut self,
+        id: PlaylistId,
+        tracks: &[TrackId],
+    ) -> Result<(), StorageError> {
+        Storage::set_playlist_tracks(self, id, tracks)
+    }
+
+    fn set_playlist_token(
+        &mut self,
+        id: PlaylistId,
+        token: Option<&str>,
+    ) -> Result<(), StorageError> {
+        Storage::set_playlist_token(self, id, token)
+    }
+
+    fn is_favourite(&mut self, track_id: TrackId) -> Result<bool, StorageError> {
+        Storage::is_favourite(self, track_id)
+    }
+
+    fn set_favourite(&mut self, track_id: TrackId, favourite: bool) -> Result<(), StorageError> {
+        Storage::set_favourite(self, track_id, favourite)
+    }
+
+    fn list_favourites(&mut self) -> Result<Vec<TrackId>, StorageError> {
+        Storage::list_favourites(self)
+    }
+
+    fn short_link(&mut self, target: &str) -> Result<String, StorageError> {
+        Storage::short_link(self, target)
+    }
+
+    fn resolve_short_link(&mut self, code: &str) -> Result<Option<String>, StorageError> {
+        Storage::resolve_short_link(self, code)
+    }
+
+    fn youtube_id(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Storage::youtube_id(self, track_id)
+    }
+
+    fn set_youtube_id(&mut self, track_id: TrackId, video_id: &str) -> Result<(), StorageError> {
+        Storage::set_youtube_id(self, track_id, video_id)
+    }
+
+    fn track_notes(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Storage::track_notes(self, track_id)
+    }
+
+    fn set_track_notes(
+        &mut self,
+        track_id: TrackId,
+        notes: Option<&str>,
+    ) -> Result<(), StorageError> {
+        Storage::set_track_notes(self, track_id, notes)
+    }
+
+    fn artist_credits(&mut self, track_id: TrackId) -> Result<Vec<ArtistCredit>, StorageError> {
+        Storage::artist_credits(self, track_id)
+    }
+
+    fn set_artist_credits(
+        &mut self,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError> {
+        Storage::set_artist_credits(self, track_id, credits)
+    }
+
+    fn sort_names(&mut self, track_id: TrackId) -> Result<Option<SortNames>, StorageError> {
+        Storage::sort_names(self, track_id)
+    }
+
+    fn set_sort_names(
+        &mut self,
+        track_id: TrackId,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), StorageError> {
+        Storage::set_sort_names(self, track_id, artist, title)
+    }
+
+    fn record_card_scan(&mut self, scan: &CardScan) -> Result<(), StorageError> {
+        Storage::record_card_scan(self, scan)
+    }
+
+    fn list_card_scans(
+        &mut self,
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<CardScan>, StorageError> {
+        Storage::list_card_scans(self, since)
+    }
+
+    fn add_file_to_track(
+        &mut self,
+        master_id: TrackId,
+        physical_path: &Path,
+    ) -> Result<(), StorageError> {
+        Storage::add_file_to_track(self, master_id, physical_path)
+    }
+
+    fn merge_tracks(
+        &mut self,
+        master_id: TrackId,
+        slave_id: TrackId,
+        ignore_slave_meta: bool,
+    ) -> Result<(), StorageError> {
+        Storage::merge_tracks(self, master_id, slave_id, ignore_slave_meta)
+    }
+
+    fn forget_path(&mut self, path: &Path) -> Result<ForgetReport, StorageError> {
+        Storage::forget_path(self, path)
+    }
+
+    fn forget_matching(&mut self, glob: &PathGlob) -> Result<ForgetReport, StorageError> {
+        Storage::forget_matching(self, glob)
+    }
+
+    fn quarantine(&mut self, loc: &Location, reason: &str) -> Result<bool, StorageError> {
+        Storage::quarantine(self, loc, reason)
+    }
+
+    fn list_quarantined(&mut self) -> Result<Vec<QuarantinedFile>, StorageError> {
+        Storage::list_quarantined(self)
+    }
+
+    fn clear_quarantine(&mut self, path: Option<&Path>) -> Result<usize, StorageError> {
+        Storage::clear_quarantine(self, path)
+    }
+
+    fn clean_dangling(&mut self) -> Result<CleanDanglingReport, StorageError> {
+        Storage::clean_dangling(self)
+    }
+
+    fn export_bundle(&mut self, dest: &Path) -> Result<BundleManifest, StorageError> {
+        Storage::export_bundle(self, dest)
+    }
+}
+
+impl<T: LibraryStore + ?Sized> LibraryStore for Box<T> {
+    fn set_progress(&mut self, progress: Box<dyn Progress>) {
+        (**self).set_progress(progress)
+    }
+
+    fn unavailable_roots(&mut self) -> Vec<UnavailableRoot> {
+        (**self).unavailable_roots()
+    }
+
+    fn transliterate_search(&self) -> bool {
+        (**self).transliterate_search()
+    }
+
+    fn set_ignored_dirs(&mut self, dirs: Vec<PathBuf>) {
+        (**self).set_ignored_dirs(dirs)
+    }
+
+    fn enable_track_index(&mut self) {
+        (**self).enable_track_index()
+    }
+
+    fn update_db_with_new_files(
+        &mut self,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        (**self).update_db_with_new_files()
+    }
+
+    fn library_source(&self) -> LibrarySource {
+        (**self).library_source()
+    }
+
+    fn start_update(&mut self, scan: LibraryScan) -> Result<PendingUpdate, StorageError> {
+        (**self).start_update(scan)
+    }
+
+    fn insert_new_files(
+        &mut self,
+        update: &mut PendingUpdate,
+        files: Vec<HashedFile>,
+    ) -> Result<(), StorageError> {
+        (**self).insert_new_files(update, files)
+    }
+
+    fn finish_update(
+        &mut self,
+        update: PendingUpdate,
+    ) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+        (**self).finish_update(update)
+    }
+
+    fn scan_metadata(&mut self) -> Result<Vec<Track>, StorageError> {
+        (**self).scan_metadata()
+    }
+
+    fn updated_at(&mut self) -> Result<DateTime<Local>, StorageError> {
+        (**self).updated_at()
+    }
+
+    fn list_tracks(&mut self) -> Result<Vec<TrackListEntry>, StorageError> {
+        (**self).list_tracks()
+    }
+
+    fn list_tracks_matching(
+        &mut self,
+        filter: &TrackFilter,
+    ) -> Result<Vec<TrackListEntry>, StorageError> {
+        (**self).list_tracks_matching(filter)
+    }
+
+    fn list_artists(&mut self) -> Result<Vec<ArtistEntry>, StorageError> {
+        (**self).list_artists()
+    }
+
+    fn list_years(&mut self) -> Result<Vec<YearEntry>, StorageError> {
+        (**self).list_years()
+    }
+
+    fn list_hashed_files(&mut self) -> Result<Vec<(TrackId, HashedFile)>, StorageError> {
+        (**self).list_hashed_files()
+    }
+
+    fn resolve_track(&mut self, card_id: CardId) -> Result<TrackId, StorageError> {
+        (**self).resolve_track(card_id)
+    }
+
+    fn find_track_file_with_meta(
+        &mut self,
+        track: TrackId,
+    ) -> Result<(PathBuf, Location, Option<TrackMetadata>), StorageError> {
+        (**self).find_track_file_with_meta(track)
+    }
+
+    fn get_track_metadata(
+        &mut self,
+        track_id: TrackId,
+    ) -> Result<Option<TrackMetadata>, StorageError> {
+        (**self).get_track_metadata(track_id)
+    }
+
+    fn find_files(
+        &mut self,
+        query: &str,
+        no_meta: bool,
+    ) -> Result<HashMap<TrackId, HashSet<Location>>, StorageError> {
+        (**self).find_files(query, no_meta)
+    }
+
+    fn check_stale(&mut self) -> Result<StaleTracks, StorageError> {
+        (**self).check_stale()
+    }
+
+    fn update_track_metadata(
+        &mut self,
+        track_id: TrackId,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<(), StorageError> {
+        (**self).update_track_metadata(track_id, new_meta, allow_overwrite)
+    }
+
+    fn apply_metadata_under(
+        &mut self,
+        path: &Path,
+        new_meta: MetadataUpdate,
+        allow_overwrite: bool,
+    ) -> Result<ApplyMetadataReport, StorageError> {
+        (**self).apply_metadata_under(path, new_meta, allow_overwrite)
+    }
+
+    fn import_tags(
+        &mut self,
+        tracks: &[TrackId],
+        policy: MetadataPolicy,
+    ) -> Result<TagImportReport, StorageError> {
+        (**self).import_tags(tracks, policy)
+    }
+
+    fn metadata_sources(&mut self, track_id: TrackId) -> Result<Vec<FieldSource>, StorageError> {
+        (**self).metadata_sources(track_id)
+    }
+
+    fn get_lyrics(&mut self, track_id: TrackId) -> Result<Option<TrackLyrics>, StorageError> {
+        (**self).get_lyrics(track_id)
+    }
+
+    fn set_lyrics(&mut self, track_id: TrackId, lyrics: &TrackLyrics) -> Result<(), StorageError> {
+        (**self).set_lyrics(track_id, lyrics)
+    }
+
+    fn create_playlist(&mut self, name: &str) -> Result<Playlist, StorageError> {
+        (**self).create_playlist(name)
+    }
+
+    fn delete_playlist(&mut self, id: PlaylistId) -> Result<(), StorageError> {
+        (**self).delete_playlist(id)
+    }
+
+    fn list_playlists(&mut self) -> Result<Vec<Playlist>, StorageError> {
+        (**self).list_playlists()
+    }
+
+    fn get_playlist(&mut self, id: PlaylistId) -> Result<Playlist, StorageError> {
+        (**self).get_playlist(id)
+    }
+
+    fn set_playlist_tracks(
+        &mut self,
+        id: PlaylistId,
+        tracks: &[TrackId],
+    ) -> Result<(), StorageError> {
+        (**self).set_playlist_tracks(id, tracks)
+    }
+
+    fn set_playlist_token(
+        &mut self,
+        id: PlaylistId,
+        token: Option<&str>,
+    ) -> Result<(), StorageError> {
+        (**self).set_playlist_token(id, token)
+    }
+
+    fn is_favourite(&mut self, track_id: TrackId) -> Result<bool, StorageError> {
+        (**self).is_favourite(track_id)
+    }
+
+    fn set_favourite(&mut self, track_id: TrackId, favourite: bool) -> Result<(), StorageError> {
+        (**self).set_favourite(track_id, favourite)
+    }
+
+    fn list_favourites(&mut self) -> Result<Vec<TrackId>, StorageError> {
+        (**self).list_favourites()
+    }
+
+    fn short_link(&mut self, target: &str) -> Result<String, StorageError> {
+        (**self).short_link(target)
+    }
+
+    fn resolve_short_link(&mut self, code: &str) -> Result<Option<String>, StorageError> {
+        (**self).resolve_short_link(code)
+    }
+
+    fn youtube_id(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        (**self).youtube_id(track_id)
+    }
+
+    fn set_youtube_id(&mut self, track_id: TrackId, video_id: &str) -> Result<(), StorageError> {
+        (**self).set_youtube_id(track_id, video_id)
+    }
+
+    fn track_notes(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        (**self).track_notes(track_id)
+    }
+
+    fn set_track_notes(
+        &mut self,
+        track_id: TrackId,
+        notes: Option<&str>,
+    ) -> Result<(), StorageError> {
+        (**self).set_track_notes(track_id, notes)
+    }
+
+    fn artist_credits(&mut self, track_id: TrackId) -> Result<Vec<ArtistCredit>, StorageError> {
+        (**self).artist_credits(track_id)
+    }
+
+    fn set_artist_credits(
+        &mut self,
+        track_id: TrackId,
+        credits: &[ArtistCredit],
+    ) -> Result<(), StorageError> {
+        (**self).set_artist_credits(track_id, credits)
+    }
+
+    fn sort_names(&mut self, track_id: TrackId) -> Result<Option<SortNames>, StorageError> {
+        (**self).sort_names(track_id)
+    }
+
+    fn set_sort_names(
+        &mut self,
+        track_id: TrackId,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), StorageError> {
+        (**self).set_sort_names(track_id, artist, title)
+    }
+
+    fn record_card_scan(&mut self, scan: &CardScan) -> Result<(), StorageError> {
+        (**self).record_card_scan(scan)
+    }
+
+    fn list_card_scans(
+        &mut self,
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<CardScan>, StorageError> {
+        (**self).list_card_scans(since)
+    }
+
+    fn add_file_to_track(
+        &mut self,
+        master_id: TrackId,
+        physical_path: &Path,
+    ) -> Result<(), StorageError> {
+        (**self).add_file_to_track(master_id, physical_path)
+    }
+
+    fn merge_tracks(
+        &mut self,
+        master_id: TrackId,
+        slave_id: TrackId,
+        ignore_slave_meta: bool,
+    ) -> Result<(), StorageError> {
+        (**self).merge_tracks(master_id, slave_id, ignore_slave_meta)
+    }
+
+    fn forget_path(&mut self, path: &Path) -> Result<ForgetReport, StorageError> {
+        (**self).forget_path(path)
+    }
+
+    fn forget_matching(&mut self, glob: &PathGlob) -> Result<ForgetReport, StorageError> {
+        (**self).forget_matching(glob)
+    }
+
+    fn quarantine(&mut self, loc: &Location, reason: &str) -> Result<bool, StorageError> {
+        (**self).quarantine(loc, reason)
+    }
+
+    fn list_quarantined(&mut self) -> Result<Vec<QuarantinedFile>, StorageError> {
+        (**self).list_quarantined()
+    }
+
+    fn clear_quarantine(&mut self, path: Option<&Path>) -> Result<usize, StorageError> {
+        (**self).clear_quarantine(path)
+    }
+
+    fn clean_dangling(&mut self) -> Result<CleanDanglingReport, StorageError> {
+        (**self).clean_dangling()
+    }
+
+    fn export_bundle(&mut self, dest: &Path) -> Result<BundleManifest, StorageError> {
+        (**self).export_bundle(dest)
+    }
+}
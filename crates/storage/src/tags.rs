@@ -0,0 +1,453 @@
+//! Reads the title, artist, year and label embedded in music files.
+//!
+//! Known tags are ID3v2 (versions 2.2 to 2.4) and ID3v1 in MP3 files, and Vorbis comments
+//! in FLAC files. Values of an ID3v2 tag are preferred over those of an ID3v1 tag.
+//! Other files are read as having no tags.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const ID3V2_HEADER: usize = 10;
+const ID3V1_SIZE: i64 = 128;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+/// Values found in the tags of a file, `None` for missing or empty ones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub year: Option<u32>,
+    pub label: Option<String>,
+}
+
+impl FileTags {
+    pub fn is_empty(&self) -> bool {
+        *self == FileTags::default()
+    }
+
+    /// Fills in fields missing here from `other`
+    fn or(self, other: FileTags) -> FileTags {
+        FileTags {
+            title: self.title.or(other.title),
+            artist: self.artist.or(other.artist),
+            year: self.year.or(other.year),
+            label: self.label.or(other.label),
+        }
+    }
+}
+
+pub fn read_tags(path: &Path) -> io::Result<FileTags> {
+    read_tags_from(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_tags_from(file: &mut (impl Read + Seek)) -> io::Result<FileTags> {
+    let mut tags = FileTags::default();
+    let mut header = [0; ID3V2_HEADER];
+    let mut start = 0;
+    // there may be several ID3v2 tags, the first one wins
+    while read_fully(file, &mut header)? && header.starts_with(b"ID3") {
+        let mut body = vec![0; syncsafe(&header[6..10])];
+        if !read_fully(file, &mut body)? {
+            return Ok(tags);
+        }
+        tags = tags.or(parse_id3v2(header[3], header[5], body));
+        if header[5] & 0x10 != 0 {
+            // footer
+            file.seek(SeekFrom::Current(ID3V2_HEADER as i64))?;
+        }
+        start = file.stream_position()?;
+    }
+
+    if header.starts_with(b"fLaC") {
+        file.seek(SeekFrom::Start(start + 4))?;
+        return Ok(tags.or(read_flac_comments(file)?));
+    }
+
+    let length = file.seek(SeekFrom::End(0))?;
+    if length >= start + ID3V1_SIZE as u64 {
+        let mut id3v1 = [0; ID3V1_SIZE as usize];
+        file.seek(SeekFrom::End(-ID3V1_SIZE))?;
+        file.read_exact(&mut id3v1)?;
+        if id3v1.starts_with(b"TAG") {
+            tags = tags.or(parse_id3v1(&id3v1));
+        }
+    }
+    Ok(tags)
+}
+
+/// Like `read_exact`, but returns false if the file is too short
+fn read_fully(file: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sizes in ID3v2 headers use 7 bits of every byte
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as usize)
+}
+
+/// Removes the zero bytes inserted after 0xFF, so the tag is not mistaken for audio frames
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut after_ff = false;
+    for &byte in data {
+        if !(after_ff && byte == 0) {
+            result.push(byte);
+        }
+        after_ff = byte == 0xff;
+    }
+    result
+}
+
+fn parse_id3v2(version: u8, flags: u8, mut body: Vec<u8>) -> FileTags {
+    let mut tags = FileTags::default();
+    if !(2..=4).contains(&version) {
+        return tags;
+    }
+    if flags & 0x80 != 0 {
+        body = remove_unsynchronisation(&body);
+    }
+    let mut rest = body.as_slice();
+    if flags & 0x40 != 0 && version >= 3 && rest.len() >= 4 {
+        // extended header, its size counts itself only in version 2.4
+        let skip = match version {
+            3 => 4 + u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize,
+            _ => syncsafe(&rest[..4]),
+        };
+        rest = rest.get(skip..).unwrap_or_default();
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    while rest.len() >= header_len && rest[0] != 0 {
+        let id = &rest[..id_len];
+        let size = match version {
+            2 => u32::from_be_bytes([0, rest[3], rest[4], rest[5]]) as usize,
+            3 => u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize,
+            _ => syncsafe(&rest[4..8]),
+        };
+        let format_flags = if version == 2 { 0 } else { rest[9] };
+        let Some(data) = rest.get(header_len..header_len + size) else {
+            break;
+        };
+        rest = &rest[header_len + size..];
+
+        let mut data = data.to_vec();
+        let compressed_or_encrypted = match version {
+            3 => format_flags & 0xc0 != 0,
+            _ => format_flags & 0x0c != 0,
+        };
+        if compressed_or_encrypted {
+            continue;
+        }
+        if version == 4 {
+            if format_flags & 0x02 != 0 {
+                data = remove_unsynchronisation(&data);
+            }
+            if format_flags & 0x01 != 0 {
+                // data length indicator
+                data.drain(..4.min(data.len()));
+            }
+        }
+
+        let field = match id {
+            b"TIT2" | b"TT2" => &mut tags.title,
+            b"TPE1" | b"TP1" => &mut tags.artist,
+            b"TPUB" | b"TPB" => &mut tags.label,
+            b"TDRC" | b"TYER" | b"TYE" => {
+                tags.year = tags
+                    .year
+                    .or(decode_text_frame(&data).and_then(|y| parse_year(&y)));
+                continue;
+            }
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = decode_text_frame(&data);
+        }
+    }
+    tags
+}
+
+/// First value of an ID3v2 text frame, which starts with its encoding
+fn decode_text_frame(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&byte| byte as char).collect(),
+        1 | 2 => {
+            let mut units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| [pair[0], pair[1]])
+                .map(if encoding == 2 || text.starts_with(&[0xfe, 0xff]) {
+                    u16::from_be_bytes
+                } else {
+                    u16::from_le_bytes
+                })
+                .collect();
+            if units.first() == Some(&0xfeff) {
+                units.remove(0);
+            }
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    // version 2.4 separates several values with zeros
+    non_empty(text.split('\0').next().unwrap_or_default())
+}
+
+fn parse_id3v1(tag: &[u8]) -> FileTags {
+    let text = |range: std::ops::Range<usize>| {
+        let field: String = tag[range]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| byte as char)
+            .collect();
+        non_empty(&field)
+    };
+    FileTags {
+        title: text(3..33),
+        artist: text(33..63),
+        year: text(93..97).and_then(|year| parse_year(&year)),
+        label: None,
+    }
+}
+
+/// Walks the FLAC metadata blocks following the `fLaC` marker
+fn read_flac_comments(file: &mut (impl Read + Seek)) -> io::Result<FileTags> {
+    let mut header = [0; 4];
+    while read_fully(file, &mut header)? {
+        let is_last = header[0] & 0x80 != 0;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        if header[0] & 0x7f == FLAC_VORBIS_COMMENT {
+            let mut block = vec![0; size as usize];
+            file.read_exact(&mut block)?;
+            return Ok(parse_vorbis_comments(&block));
+        }
+        if is_last {
+            break;
+        }
+        file.seek(SeekFrom::Current(size.into()))?;
+    }
+    Ok(FileTags::default())
+}
+
+/// `KEY=value` pairs after a vendor string, all lengths are little endian
+fn parse_vorbis_comments(block: &[u8]) -> FileTags {
+    let mut tags = FileTags::default();
+    let Some((vendor_len, rest)) = block.split_first_chunk::<4>() else {
+        return tags;
+    };
+    let vendor_len = u32::from_le_bytes(*vendor_len) as usize;
+    let Some((count, mut rest)) = rest
+        .get(vendor_len..)
+        .and_then(|rest| rest.split_first_chunk::<4>())
+    else {
+        return tags;
+    };
+
+    for _ in 0..u32::from_le_bytes(*count) {
+        let Some((len, after)) = rest.split_first_chunk::<4>() else {
+            break;
+        };
+        let len = u32::from_le_bytes(*len) as usize;
+        let Some(comment) = after.get(..len) else {
+            break;
+        };
+        rest = &after[len..];
+
+        let comment = String::from_utf8_lossy(comment);
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        let field = match key.to_ascii_uppercase().as_str() {
+            "TITLE" => &mut tags.title,
+            "ARTIST" => &mut tags.artist,
+            "LABEL" | "ORGANIZATION" | "PUBLISHER" => &mut tags.label,
+            "DATE" | "YEAR" => {
+                tags.year = tags.year.or(parse_year(value));
+                continue;
+            }
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = non_empty(value);
+        }
+    }
+    tags
+}
+
+/// Year of a date like `1999`, `1999-05-01` or `1999/05`
+fn parse_year(date: &str) -> Option<u32> {
+    let digits = date.trim().get(..4)?;
+    digits.parse().ok()
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn id3v2(version: u8, frames: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, data) in frames {
+            let size = data.len() as u32;
+            body.extend(*id);
+            if version == 4 {
+                body.extend([
+                    (size >> 21) as u8 & 0x7f,
+                    (size >> 14) as u8 & 0x7f,
+                    (size >> 7) as u8 & 0x7f,
+                    size as u8 & 0x7f,
+                ]);
+            } else {
+                body.extend(size.to_be_bytes());
+            }
+            body.extend([0, 0]);
+            body.extend(data);
+        }
+        // padding
+        body.extend([0; 16]);
+        let size = body.len();
+        let mut bytes = vec![b'I', b'D', b'3', version, 0, 0];
+        bytes.extend([
+            (size >> 21) as u8 & 0x7f,
+            (size >> 14) as u8 & 0x7f,
+            (size >> 7) as u8 & 0x7f,
+            size as u8 & 0x7f,
+        ]);
+        bytes.extend(body);
+        bytes
+    }
+
+    fn utf8(text: &str) -> Vec<u8> {
+        [&[3], text.as_bytes()].concat()
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        let mut data = vec![1, 0xff, 0xfe];
+        data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        data
+    }
+
+    fn id3v1(title: &str, artist: &str, year: &str) -> Vec<u8> {
+        let mut bytes = b"TAG".to_vec();
+        for (field, len) in [(title, 30), (artist, 30), ("", 30), (year, 4)] {
+            let mut field = field.as_bytes().to_vec();
+            field.resize(len, 0);
+            bytes.extend(field);
+        }
+        bytes.resize(128, 0);
+        bytes
+    }
+
+    fn read(bytes: Vec<u8>) -> FileTags {
+        read_tags_from(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn id3_tags_are_read() {
+        let tag = id3v2(
+            3,
+            &[
+                (b"TIT2", utf16("Título")),
+                (b"TYER", utf8("1999")),
+                (b"COMM", utf8("skipped")),
+            ],
+        );
+        let file = [
+            tag,
+            b"\xff\xfbframes".to_vec(),
+            id3v1("v1 title", "v1 artist", "1980"),
+        ]
+        .concat();
+        assert_eq!(
+            read(file),
+            FileTags {
+                title: Some("Título".into()),
+                // filled in from ID3v1
+                artist: Some("v1 artist".into()),
+                year: Some(1999),
+                label: None,
+            }
+        );
+
+        let tag = id3v2(
+            4,
+            &[
+                (b"TPE1", utf8("Artist\0Featured")),
+                (b"TIT2", utf8("Title")),
+                (b"TDRC", utf8("2004-05-01")),
+                (b"TPUB", utf8(" Label ")),
+            ],
+        );
+        assert_eq!(
+            read([tag, b"\xff\xfbframes".to_vec()].concat()),
+            FileTags {
+                title: Some("Title".into()),
+                artist: Some("Artist".into()),
+                year: Some(2004),
+                label: Some("Label".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn flac_comments_are_read() {
+        let mut comments = Vec::new();
+        let vendor = b"reference libFLAC";
+        comments.extend((vendor.len() as u32).to_le_bytes());
+        comments.extend(vendor);
+        let items = [
+            "title=Title",
+            "ARTIST=Artist",
+            "DATE=2011",
+            "ORGANIZATION=Label",
+        ];
+        comments.extend((items.len() as u32).to_le_bytes());
+        for item in items {
+            comments.extend((item.len() as u32).to_le_bytes());
+            comments.extend(item.as_bytes());
+        }
+
+        let mut file = b"fLaC".to_vec();
+        // stream info block, then the comments as the last block
+        file.extend([0, 0, 0, 34]);
+        file.extend([0; 34]);
+        file.extend([0x80 | FLAC_VORBIS_COMMENT]);
+        file.extend(&(comments.len() as u32).to_be_bytes()[1..]);
+        file.extend(comments);
+        file.extend(b"frames");
+
+        assert_eq!(
+            read(file),
+            FileTags {
+                title: Some("Title".into()),
+                artist: Some("Artist".into()),
+                year: Some(2011),
+                label: Some("Label".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn untagged_files_have_no_tags() {
+        assert!(read(b"\xff\xfbframes".to_vec()).is_empty());
+        assert!(read(Vec::new()).is_empty());
+        // a tag cut short is not mistaken for values
+        assert!(read(id3v2(3, &[(b"TIT2", utf8("Title"))])[..12].to_vec()).is_empty());
+    }
+}
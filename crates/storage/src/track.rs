@@ -1,6 +1,9 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Track id. Represents track entity
+///
+/// Assigned by the database, not derived from file hashes, so printed cards stay valid
+/// when the way files are hashed changes, see [`HashKind`](crate::file_hash::HashKind)
 pub type TrackId = i64;
 
 /// Represent a music track
@@ -10,7 +13,7 @@ pub struct Track {
     pub metadata: TrackMetadata,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TrackMetadata {
     pub artist: String,
     pub title: String,
@@ -19,6 +22,71 @@ pub struct TrackMetadata {
     pub artwork: Option<ArtworkRef>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct ArtworkRef(pub String);
+
+/// What taggers and rippers write when they know nothing better, compared ignoring case
+const PLACEHOLDERS: &[&str] = &[
+    "unknown",
+    "unknown artist",
+    "unknown title",
+    "untitled",
+    "no title",
+];
+
+/// Whether a title or artist says nothing, e.g. is empty, `Unknown Artist` or `Track 07`
+pub fn is_placeholder(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+    let numbered = value
+        .strip_prefix("track")
+        .is_some_and(|rest| rest.trim().chars().all(|c| c.is_ascii_digit()));
+    value.is_empty() || numbered || PLACEHOLDERS.contains(&value.as_str())
+}
+
+impl TrackMetadata {
+    /// Names of the fields still to fill in: an artist or title that is a placeholder,
+    /// and the artwork
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        let mut missing = vec![];
+        if is_placeholder(&self.artist) {
+            missing.push("artist");
+        }
+        if is_placeholder(&self.title) {
+            missing.push("title");
+        }
+        if self.artwork.is_none() {
+            missing.push("artwork");
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_missing_fields() {
+        let meta = |artist: &str, title: &str, artwork: Option<&str>| TrackMetadata {
+            artist: artist.to_string(),
+            title: title.to_string(),
+            year: None,
+            label: None,
+            artwork: artwork.map(|url| ArtworkRef(url.to_string())),
+        };
+        assert!(
+            meta("Nirvana", "Lithium", Some("cover.jpg"))
+                .missing_fields()
+                .is_empty()
+        );
+        assert_eq!(
+            meta("Unknown Artist", "Track 07", None).missing_fields(),
+            vec!["artist", "title", "artwork"]
+        );
+        assert_eq!(
+            meta(" ", "Trackmania", Some("cover.jpg")).missing_fields(),
+            vec!["artist"]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! In-memory copy of track files, metadata and card aliases,
+//! so the server answers lookups of every scanned card without querying the database
+
+use std::collections::HashMap;
+
+use crate::{
+    CardId, Storage,
+    error::StorageError,
+    location::Location,
+    operations::LocationRow,
+    schema::{columns::*, tables::*},
+    track::{ArtworkRef, TrackId, TrackMetadata},
+};
+
+/// State of the database an index was loaded from.
+///
+/// SQLite's `data_version` changes on commits of other connections,
+/// the second part on library changes made through this connection:
+/// `total_changes` less the rows written to logs the index does not cover, like card scans.
+pub(crate) type DataVersion = (i64, u64);
+
+#[derive(Debug, Default)]
+struct IndexedTrack {
+    locations: Vec<Location>,
+    metadata: Option<TrackMetadata>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TrackIndex {
+    pub(crate) version: DataVersion,
+    tracks: HashMap<TrackId, IndexedTrack>,
+    cards: HashMap<CardId, TrackId>,
+}
+
+impl TrackIndex {
+    fn load(db: &rusqlite::Connection, version: DataVersion) -> Result<Self, StorageError> {
+        let mut tracks: HashMap<TrackId, IndexedTrack> = HashMap::new();
+
+        let mut stmt = db.prepare(&format!("SELECT {TRACK_ID} FROM {TRACKS}"))?;
+        for id in stmt.query_map([], |row| row.get(0))? {
+            tracks.insert(id?, IndexedTrack::default());
+        }
+
+        let mut stmt = db.prepare(&format!(
+            "SELECT {TRACK_ID}, {USB_LABEL}, {PATH} FROM {FILES} WHERE {QUARANTINED_AT} IS NULL"
+        ))?;
+        let files = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, TrackId>(0)?,
+                LocationRow {
+                    usb_label: row.get(1)?,
+                    path: row.get(2)?,
+                },
+            ))
+        })?;
+        for file in files {
+            let (track, row) = file?;
+            tracks.entry(track).or_default().locations.push(row.into());
+        }
+
+        let mut stmt = db.prepare(&format!(
+            "SELECT {TRACK_ID}, {TITLE}, {ARTIST}, {YEAR}, {LABEL}, {ARTWORK_URL} FROM {TRACK_METADATA}"
+        ))?;
+        let metadata = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, TrackId>(0)?,
+                TrackMetadata {
+                    title: row.get(1)?,
+                    artist: row.get(2)?,
+                    year: row.get(3)?,
+                    label: row.get(4)?,
+                    artwork: row.get::<_, Option<String>>(5)?.map(ArtworkRef),
+                },
+            ))
+        })?;
+        for meta in metadata {
+            let (track, meta) = meta?;
+            tracks.entry(track).or_default().metadata = Some(meta);
+        }
+
+        let mut stmt = db.prepare(&format!(
+            "SELECT {CARD_ID}, {TRACK_ID} FROM {CARD_MAPPINGS}"
+        ))?;
+        let cards = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            version,
+            tracks,
+            cards,
+        })
+    }
+
+    /// Same as [`Storage::resolve_track`], card aliases take precedence over track ids
+    pub(crate) fn resolve(&self, card_id: &str) -> Option<TrackId> {
+        self.cards.get(card_id).copied().or_else(|| {
+            card_id
+                .parse()
+                .ok()
+                .filter(|id| self.tracks.contains_key(id))
+        })
+    }
+
+    /// Playable recorded locations of a track, quarantined files are left out.
+    /// `None` for unknown tracks
+    pub(crate) fn locations(&self, track: TrackId) -> Option<&[Location]> {
+        self.tracks.get(&track).map(|t| t.locations.as_slice())
+    }
+
+    pub(crate) fn metadata(&self, track: TrackId) -> Option<&TrackMetadata> {
+        self.tracks.get(&track).and_then(|t| t.metadata.as_ref())
+    }
+}
+
+impl Storage {
+    /// Keeps track files, metadata and card aliases in memory for
+    /// [`Storage::resolve_track`] and [`Storage::find_track_file_with_meta`].
+    ///
+    /// The copy is reloaded on the next lookup after any change of the database,
+    /// including changes by other processes, like the CLI updating the library.
+    pub fn enable_track_index(&mut self) {
+        self.index_enabled = true;
+    }
+
+    fn data_version(&self) -> Result<DataVersion, StorageError> {
+        let data_version = self
+            .db
+            .pragma_query_value(None, "data_version", |row| row.get(0))?;
+        Ok((data_version, self.db.total_changes() - self.log_changes))
+    }
+
+    /// Index matching the current database state, `None` if it is not enabled
+    pub(crate) fn track_index(&mut self) -> Result<Option<&TrackIndex>, StorageError> {
+        if !self.index_enabled {
+            return Ok(None);
+        }
+        let version = self.data_version()?;
+        if self
+            .index
+            .as_ref()
+            .is_none_or(|index| index.version != version)
+        {
+            log::debug!("loading track index");
+            self.index = Some(TrackIndex::load(&self.db, version)?);
+        }
+        Ok(self.index.as_ref())
+    }
+}
@@ -0,0 +1,116 @@
+//! `update` of a store shared with a running server.
+//!
+//! Walking the library roots and hashing new files take long on large libraries. Here they
+//! happen without the store: it is only locked to compare the walk with the database and
+//! to record each batch of hashed files, so requests are answered while a scan runs.
+
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    audit::AuditId,
+    error::StorageError,
+    fs::{FileStorage, FileWithMeta, FsSnapshot, HashedFile, UnavailableRoot},
+    operations::{LocationRow, UPDATE_CHECKPOINT_FILES},
+    progress::Progress,
+    store::LibraryStore,
+    track::TrackId,
+};
+
+/// Music files found by a walk of the library roots
+#[derive(Debug)]
+pub struct LibraryScan {
+    pub(crate) started_at: SystemTime,
+    pub(crate) unavailable: Vec<UnavailableRoot>,
+    pub(crate) snapshot: FsSnapshot,
+}
+
+impl LibraryScan {
+    pub(crate) fn run(fs: &mut FileStorage) -> Result<Self, StorageError> {
+        let started_at = SystemTime::now();
+        println!("Scanning music on file system...");
+        let unavailable = fs.unavailable_roots();
+        let snapshot = fs.scan()?;
+        Ok(Self {
+            started_at,
+            unavailable,
+            snapshot,
+        })
+    }
+}
+
+/// An update between [`LibraryStore::start_update`] and [`LibraryStore::finish_update`]
+#[derive(Debug)]
+pub struct PendingUpdate {
+    pub(crate) scan: LibraryScan,
+    /// files to hash and insert, in a stable order so consecutive runs make progress
+    /// through the same files
+    pub(crate) new_files: Vec<FileWithMeta>,
+    /// recorded files the scan did not find, new files with their content are moves
+    pub(crate) gone: HashSet<LocationRow>,
+    pub(crate) audit_id: Option<AuditId>,
+    pub(crate) inserted: HashMap<TrackId, HashSet<HashedFile>>,
+}
+
+impl PendingUpdate {
+    pub(crate) fn new(
+        scan: LibraryScan,
+        new_files: impl IntoIterator<Item = FileWithMeta>,
+        gone: HashSet<LocationRow>,
+    ) -> Self {
+        let mut new_files: Vec<_> = new_files.into_iter().collect();
+        new_files.sort_by_cached_key(|f| f.loc.to_string());
+        if !new_files.is_empty() {
+            println!("Hashing {} new files", new_files.len());
+        }
+        Self {
+            scan,
+            new_files,
+            gone,
+            audit_id: None,
+            inserted: HashMap::new(),
+        }
+    }
+}
+
+/// Same as [`LibraryStore::update_db_with_new_files`], but `store` is only locked while the
+/// database is read or written, not while the roots are walked and new files are hashed.
+/// `progress` receives the events of the walk and the hashing
+pub fn update_shared(
+    store: &Mutex<dyn LibraryStore>,
+    progress: Box<dyn Progress>,
+) -> Result<HashMap<TrackId, HashSet<HashedFile>>, StorageError> {
+    let lock = || {
+        store
+            .lock()
+            .map_err(|e| StorageError::Internal(anyhow!("could not lock the library: {e}")))
+    };
+    let mut fs = FileStorage::new(lock()?.library_source());
+    fs.progress = progress;
+    let scan = LibraryScan::run(&mut fs)?;
+    let mut update = lock()?.start_update(scan)?;
+
+    let files = mem::take(&mut update.new_files);
+    let kind = fs.hash_kind();
+    let total_bytes = files.iter().map(|f| f.file_size as u64).sum();
+    fs.progress.hashing_started(files.len(), total_bytes);
+    let result: Result<(), StorageError> = (|| {
+        for batch in files.chunks(UPDATE_CHECKPOINT_FILES) {
+            let with_hash = batch
+                .iter()
+                .map(|f| fs.hash_file(f, kind))
+                .collect::<Result<Vec<_>, _>>()?;
+            lock()?.insert_new_files(&mut update, with_hash)?;
+        }
+        Ok(())
+    })();
+    fs.progress.hashing_finished();
+    result?;
+    lock()?.finish_update(update)
+}
@@ -1,7 +1,8 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::{
     collections::HashMap,
+    thread,
     time::{Duration, Instant},
 };
 
@@ -15,6 +16,19 @@ pub enum ResolveError {
     #[error("failed to query system mounts")]
     SystemQueryFail(#[from] std::io::Error),
 
+    #[error("network share {mount} is not mounted{}", expected_source(.expected))]
+    ShareNotMounted {
+        mount: String,
+        /// share which should be mounted there
+        expected: Option<String>,
+    },
+
+    #[error("{url} is on another localdeck instance, it has no local path")]
+    Remote { url: String },
+
+    #[error("{key} is in object storage, it has no local path")]
+    ObjectStorage { key: String },
+
     #[error("usb resolve failed, windows-specific error: {0}")]
     WindowsError(String), // #[error("failed to parse mounts")]
                           // Parse, // optional, if you want to distinguish further
@@ -58,16 +72,82 @@ impl UsbResolver {
     }
 }
 
+fn expected_source(source: &Option<String>) -> String {
+    source
+        .as_ref()
+        .map(|source| format!(" from {source}"))
+        .unwrap_or_default()
+}
+
+/// Checks of share mounts before giving up, automounters mount shares on first access
+const SHARE_MOUNT_ATTEMPTS: u32 = 3;
+const SHARE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct ShareResolver {
+    /// maps mount point -> whether a share is mounted there, and when that was checked
+    mounted: HashMap<PathBuf, (bool, Instant)>,
+    ttl: Duration,
+}
+
+impl ShareResolver {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            mounted: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Cached check that a share is mounted at `mount`, retried a few times before
+    /// reporting it unmounted. Both outcomes are kept until the cache expires, so a
+    /// share which comes back is picked up without a restart
+    fn check_mounted(&mut self, mount: &Path, source: Option<&str>) -> Result<(), ResolveError> {
+        let cached = self
+            .mounted
+            .get(mount)
+            .filter(|(_, checked_at)| checked_at.elapsed() <= self.ttl)
+            .map(|(mounted, _)| *mounted);
+        let mounted = match cached {
+            // the expected source is only known for roots, recheck those
+            Some(true) if source.is_none() => true,
+            Some(false) => false,
+            _ => {
+                let mounted = (0..SHARE_MOUNT_ATTEMPTS).any(|attempt| {
+                    if attempt > 0 {
+                        // touching the mount point lets an automounter mount the share
+                        let _ = std::fs::read_dir(mount);
+                        thread::sleep(SHARE_RETRY_DELAY);
+                    }
+                    is_share_mounted(mount, source).unwrap_or(false)
+                });
+                self.mounted
+                    .insert(mount.to_path_buf(), (mounted, Instant::now()));
+                mounted
+            }
+        };
+        if mounted {
+            Ok(())
+        } else {
+            Err(ResolveError::ShareNotMounted {
+                mount: mount.to_string_lossy().to_string(),
+                expected: source.map(str::to_string),
+            })
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Struct to resolve paths of locations
 pub struct LocationResolver {
     usb_resolver: UsbResolver,
+    share_resolver: ShareResolver,
 }
 
 impl LocationResolver {
     pub fn new(ttl: Duration) -> Self {
         LocationResolver {
             usb_resolver: UsbResolver::new(ttl),
+            share_resolver: ShareResolver::new(ttl),
         }
     }
 
@@ -79,6 +159,7 @@ impl LocationResolver {
                 last_refresh: Instant::now(),
                 ttl: Duration::from_secs(999),
             },
+            share_resolver: ShareResolver::new(Duration::from_secs(999)),
         }
     }
 
@@ -91,6 +172,21 @@ impl LocationResolver {
                 let mount = self.usb_resolver.resolve_label(label)?;
                 Ok(mount.join(path))
             }
+            Location::NetworkShare {
+                mount,
+                source,
+                path,
+            } => {
+                self.share_resolver
+                    .check_mounted(mount, source.as_deref())?;
+                Ok(mount.join(path))
+            }
+            Location::S3 { .. } => Err(ResolveError::ObjectStorage {
+                key: loc.to_string(),
+            }),
+            Location::Remote { .. } => Err(ResolveError::Remote {
+                url: loc.url().unwrap_or_default(),
+            }),
         }
     }
 }
@@ -122,6 +218,39 @@ pub fn find_mount_by_label(label: &str) -> Result<PathBuf, ResolveError> {
     for_windows::find_mount_by_label(label)
 }
 
+/// Whether a filesystem is mounted at `mount`, from `source` if given
+#[cfg(not(target_os = "windows"))]
+fn is_share_mounted(mount: &Path, source: Option<&str>) -> Result<bool, ResolveError> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts")?;
+    Ok(mounts_contain(&mounts, mount, source))
+}
+
+/// Shares are UNC paths or mapped drives on windows, which are gone when disconnected
+#[cfg(target_os = "windows")]
+fn is_share_mounted(mount: &Path, _source: Option<&str>) -> Result<bool, ResolveError> {
+    Ok(mount.is_dir())
+}
+
+/// Looks for a mount point in the format of `/proc/self/mounts`
+#[cfg(not(target_os = "windows"))]
+fn mounts_contain(mounts: &str, mount: &Path, source: Option<&str>) -> bool {
+    // spaces and other special characters are escaped as octal
+    let unescape = |field: &str| {
+        field
+            .replace("\\040", " ")
+            .replace("\\011", "\t")
+            .replace("\\134", "\\")
+    };
+    mounts.lines().any(|line| {
+        let parts: Vec<_> = line.split_whitespace().collect();
+        parts.len() >= 2
+            && Path::new(&unescape(parts[1])) == mount
+            && source.is_none_or(|source| {
+                unescape(parts[0]).trim_end_matches('/') == source.trim_end_matches('/')
+            })
+    })
+}
+
 #[cfg(target_os = "windows")]
 mod for_windows {
     use std::{
@@ -224,3 +353,39 @@ mod for_windows {
         }
     }
 }
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+    use std::path::Path;
+
+    use super::mounts_contain;
+
+    const MOUNTS: &str = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+//nas/music /mnt/nas cifs rw,vers=3.0 0 0
+nas:/export/old\\040music /mnt/old\\040music nfs4 rw 0 0
+";
+
+    #[test]
+    fn share_mounts_are_found() {
+        assert!(mounts_contain(MOUNTS, Path::new("/mnt/nas"), None));
+        assert!(mounts_contain(
+            MOUNTS,
+            Path::new("/mnt/nas/"),
+            Some("//nas/music")
+        ));
+        assert!(mounts_contain(
+            MOUNTS,
+            Path::new("/mnt/old music"),
+            Some("nas:/export/old music")
+        ));
+        // something else is mounted there
+        assert!(!mounts_contain(
+            MOUNTS,
+            Path::new("/mnt/nas"),
+            Some("//backup/music")
+        ));
+        // the bare mount point directory of an unmounted share
+        assert!(!mounts_contain(MOUNTS, Path::new("/mnt/usb"), None));
+    }
+}
@@ -0,0 +1,108 @@
+//! A library kept on a USB stick, with `localdeck export usb-db`.
+//!
+//! Files on a stick are recorded by its label and their path below the stick root, so a
+//! database trimmed to the tracks of one stick works on any machine the stick is plugged
+//! into. The other machine points its database and its only root at the stick:
+//!
+//! ```toml
+//! [storage.database]
+//! type = "OnDisk"
+//! location = { type = "Usb", label = "MUSIC", path = "localdeck.db" }
+//!
+//! [storage.library_source]
+//! roots = [{ type = "Usb", label = "MUSIC", path = "" }]
+//! ```
+
+use std::path::PathBuf;
+
+use rusqlite::{Connection, params};
+
+use crate::{
+    Storage, db,
+    error::StorageError,
+    location::Location,
+    schema::{columns::*, tables::*},
+};
+
+/// Name of the database written to the stick root
+pub const USB_DATABASE_FILE: &str = "localdeck.db";
+
+/// Result of [`Storage::export_usb_database`]
+#[derive(Debug)]
+pub struct UsbDatabaseReport {
+    /// where the database was written
+    pub path: PathBuf,
+    pub tracks: usize,
+    pub files: usize,
+    pub playlists: usize,
+}
+
+impl Storage {
+    /// Writes the tracks with files on the stick `label` to [`USB_DATABASE_FILE`] in its
+    /// root, replacing the database exported before.
+    ///
+    /// Files elsewhere are left out, and so is the history of this machine: scans, the
+    /// audit log, snapshots and card scans. Artwork stored on this machine is unlinked,
+    /// covers linked from the web are kept
+    pub fn export_usb_database(&mut self, label: &str) -> Result<UsbDatabaseReport, StorageError> {
+        let root = Location::Usb {
+            label: label.to_string(),
+            path: PathBuf::new(),
+        };
+        let mount = self
+            .fs
+            .loc_resolver
+            .resolve(&root)
+            .map_err(|e| StorageError::Internal(e.into()))?;
+        let dest = mount.join(USB_DATABASE_FILE);
+        // written next to the old database first, which is kept if the export fails
+        let partial = mount.join(format!("{USB_DATABASE_FILE}.part"));
+        if partial.exists() {
+            std::fs::remove_file(&partial)?;
+        }
+        db::copy_to(&self.db, &partial)?;
+
+        let report = {
+            let copy = Connection::open(&partial)?;
+            copy.pragma_update(None, "foreign_keys", true)?;
+            copy.execute(
+                &format!("DELETE FROM {FILES} WHERE {USB_LABEL} != ?1"),
+                params![label],
+            )?;
+            // the rest of the tracks goes with them through the foreign keys
+            copy.execute(
+                &format!(
+                    "DELETE FROM {TRACKS} WHERE {TRACK_ID} NOT IN (SELECT {TRACK_ID} FROM {FILES})"
+                ),
+                [],
+            )?;
+            for table in [SCANS, AUDIT_LOG, SNAPSHOTS, CARD_SCANS, UPDATES] {
+                copy.execute(&format!("DELETE FROM {table}"), [])?;
+            }
+            copy.execute(
+                &format!(
+                    "UPDATE {TRACK_METADATA} SET {ARTWORK_URL} = NULL
+                    WHERE {ARTWORK_URL} NOT LIKE '%://%'"
+                ),
+                [],
+            )?;
+            let count = |table: &str| -> Result<usize, StorageError> {
+                let count: i64 =
+                    copy.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                        row.get(0)
+                    })?;
+                Ok(count as usize)
+            };
+            let report = UsbDatabaseReport {
+                path: dest.clone(),
+                tracks: count(TRACKS)?,
+                files: count(FILES)?,
+                playlists: count(PLAYLISTS)?,
+            };
+            copy.execute("VACUUM", [])?;
+            report
+        };
+        std::fs::rename(&partial, &dest)?;
+        Ok(report)
+    }
+}
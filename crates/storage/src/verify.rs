@@ -0,0 +1,173 @@
+//! Full hashing of recorded files, to check them and to replace hashes
+//! of other kinds than the configured one, see [`HashKind`]
+
+use rusqlite::params;
+
+use crate::{
+    Storage,
+    error::StorageError,
+    file_hash::{FileHash, HashKind},
+    location::Location,
+    operations::{LocationRow, parse_hash_kind},
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+/// Result of [`Storage::verify`]
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// files which were hashed
+    pub verified: usize,
+    /// hashes replaced by hashes of the configured kind
+    pub rehashed: usize,
+    /// files whose content no longer matches their recorded full hash, they are quarantined
+    pub changed: Vec<Location>,
+    /// files which are there but could not be read, they are quarantined as well
+    pub unreadable: Vec<Location>,
+    /// recorded files which could not be found, e.g. because their drive is not mounted
+    pub unavailable: Vec<Location>,
+    /// files newly held back from playback, see [`crate::quarantine`]
+    pub quarantined: usize,
+    /// pairs of different tracks with identical files, found after rehashing.
+    /// They can be joined with `merge`
+    pub duplicates: Vec<(TrackId, TrackId)>,
+}
+
+struct RecordedFile {
+    row: LocationRow,
+    track: TrackId,
+    size: i64,
+    hash: String,
+    kind: HashKind,
+}
+
+impl Storage {
+    /// Hashes whole recorded files.
+    ///
+    /// Hashes of another kind than the configured one are replaced, keeping the track ids,
+    /// quick hashes are replaced by full ones. This way existing libraries switch to
+    /// [`HashKind::Audio`]. Changed and unreadable files keep their rows but are quarantined.
+    /// With `only_outdated`, files which already have a hash of the target kind are skipped.
+    pub fn verify(&mut self, only_outdated: bool) -> Result<VerifyReport, StorageError> {
+        let target = match self.fs.hash_kind() {
+            HashKind::Quick => HashKind::Full,
+            kind => kind,
+        };
+        let files = {
+            let mut stmt = self.db.prepare(&format!(
+                "SELECT {USB_LABEL}, {PATH}, {TRACK_ID}, {FILE_SIZE}, {FILE_HASH}, {HASH_KIND}
+                FROM {FILES} ORDER BY {USB_LABEL}, {PATH}"
+            ))?;
+            stmt.query_map([], |row| {
+                Ok((
+                    LocationRow {
+                        usb_label: row.get(0)?,
+                        path: row.get(1)?,
+                    },
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        let mut files = files
+            .into_iter()
+            .map(|(row, track, size, hash, kind)| {
+                Ok(RecordedFile {
+                    row,
+                    track,
+                    size,
+                    hash,
+                    kind: parse_hash_kind(&kind)?,
+                })
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        if only_outdated {
+            files.retain(|file| file.kind != target);
+        }
+
+        let mut report = VerifyReport::default();
+        let mut rehashed = vec![];
+        let mut broken = vec![];
+        let total_bytes = files.iter().map(|f| f.size as u64).sum();
+        self.fs.progress.hashing_started(files.len(), total_bytes);
+        for file in files {
+            let loc: Location = file.row.clone().into();
+            let hash = match self.fs.loc_resolver.resolve(&loc) {
+                Ok(path) => FileHash::from_file_with(&path, target)
+                    .map_err(|e| path.is_file().then(|| e.to_string())),
+                Err(_) => Err(None),
+            };
+            self.fs.progress.file_hashed(&loc, file.size as u64);
+            let hash = match hash {
+                Ok(hash) => hash,
+                // the file is there, reading it fails
+                Err(Some(error)) => {
+                    report.unreadable.push(loc.clone());
+                    broken.push((loc, error));
+                    continue;
+                }
+                Err(None) => {
+                    report.unavailable.push(loc);
+                    continue;
+                }
+            };
+            report.verified += 1;
+            if file.kind != target {
+                rehashed.push((file, hash));
+            } else if file.hash != hash.to_string() {
+                report.changed.push(loc.clone());
+                broken.push((
+                    loc,
+                    "content changed since the file was recorded".to_string(),
+                ));
+            }
+        }
+        self.fs.progress.hashing_finished();
+
+        for (loc, reason) in &broken {
+            if self.quarantine(loc, reason)? {
+                report.quarantined += 1;
+            }
+        }
+
+        if rehashed.is_empty() {
+            return Ok(report);
+        }
+        let tx = self.db.transaction()?;
+        {
+            let mut update = tx.prepare(&format!(
+                "UPDATE {FILES} SET {FILE_HASH} = ?1, {HASH_KIND} = ?2
+                WHERE {USB_LABEL} = ?3 AND {PATH} = ?4"
+            ))?;
+            for (file, hash) in &rehashed {
+                report.rehashed += update.execute(params![
+                    hash.to_string(),
+                    target.as_str(),
+                    file.row.usb_label,
+                    file.row.path
+                ])?;
+            }
+
+            let mut same_content = tx.prepare(&format!(
+                "SELECT DISTINCT {TRACK_ID} FROM {FILES} WHERE {FILE_HASH} = ?1 AND {TRACK_ID} != ?2"
+            ))?;
+            for (file, hash) in &rehashed {
+                let others = same_content
+                    .query_map(params![hash.to_string(), file.track], |row| row.get(0))?
+                    .collect::<Result<Vec<TrackId>, _>>()?;
+                for other in others {
+                    let pair = (file.track.min(other), file.track.max(other));
+                    if !report.duplicates.contains(&pair) {
+                        report.duplicates.push(pair);
+                    }
+                }
+            }
+        }
+        Self::insert_update_time(&tx)?;
+        tx.commit()?;
+        Ok(report)
+    }
+}
@@ -0,0 +1,78 @@
+//! YouTube videos of tracks, the `y` part of `/play` URLs on printed cards.
+//!
+//! A card keeps pointing to the video when the track is not in the library it is
+//! scanned at, so `localdeck url` remembers the video given once for a track.
+
+use std::time::SystemTime;
+
+use rusqlite::{ErrorCode, OptionalExtension, params};
+
+use crate::{
+    Storage,
+    db::system_time_to_i64,
+    error::StorageError,
+    schema::{columns::*, tables::*},
+    track::TrackId,
+};
+
+/// Whether `id` looks like the id of a video, e.g. `dQw4w9WgXcQ` of
+/// `https://www.youtube.com/watch?v=dQw4w9WgXcQ`. Such ids need no escaping in URLs
+pub fn is_video_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+}
+
+impl Storage {
+    pub fn youtube_id(&mut self, track_id: TrackId) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT {VIDEO_ID} FROM {YOUTUBE_IDS} WHERE {TRACK_ID} = ?1"),
+                params![track_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Stores the video of the track, replacing the one stored before
+    pub fn set_youtube_id(
+        &mut self,
+        track_id: TrackId,
+        video_id: &str,
+    ) -> Result<(), StorageError> {
+        let now = system_time_to_i64(SystemTime::now()).map_err(StorageError::Internal)?;
+        self.db
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {YOUTUBE_IDS} ({TRACK_ID}, {VIDEO_ID}, {UPDATED_AT})
+                    VALUES (?1, ?2, ?3)"
+                ),
+                params![track_id, video_id, now],
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::SqliteFailure(error, _)
+                    if error.code == ErrorCode::ConstraintViolation =>
+                {
+                    StorageError::TrackNotFound(track_id.to_string())
+                }
+                e => StorageError::Database(e),
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_video_id;
+
+    #[test]
+    fn video_ids_are_recognized() {
+        assert!(is_video_id("dQw4w9WgXcQ"));
+        assert!(is_video_id("a-b_c"));
+        assert!(!is_video_id(""));
+        assert!(!is_video_id("watch?v=dQw4w9WgXcQ"));
+        assert!(!is_video_id("https://youtu.be/dQw4w9WgXcQ"));
+    }
+}